@@ -1,15 +1,23 @@
 //! API endpoint performance benchmarks
+//!
+//! Requests go through the whole router to an in-process mock upstream, so the
+//! numbers cover request conversion, the upstream round trip, response
+//! conversion and, for streams, SSE decoding and re-encoding.
 
-use criterion::{black_box, criterion_group, criterion_main, Criterion, BenchmarkId};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
 use aiapiproxy::config::settings::Settings;
+use aiapiproxy::config::{AppConfig, ModelConfig, ProviderConfig};
 use aiapiproxy::handlers::create_router;
 use aiapiproxy::models::claude::*;
 use axum::{
     body::Body,
     http::{Request, StatusCode},
+    Router,
 };
-use serde_json;
+use httpmock::MockServer;
+use std::collections::HashMap;
 use std::env;
+use tokio::runtime::Runtime;
 use tower::ServiceExt;
 
 /// Setup test environment
@@ -29,10 +37,94 @@ fn create_test_settings() -> Settings {
     Settings::new().expect("Failed to create test settings")
 }
 
+/// Create test app config with the provider pointing at `base_url`
+fn create_test_app_config(base_url: &str) -> AppConfig {
+    let mut models = HashMap::new();
+    models.insert("gpt-4o".to_string(), ModelConfig {
+        name: "gpt-4o".to_string(),
+        enabled: true,
+        alias: None,
+        max_tokens: Some(8192),
+        context_window: None,
+        temperature: None,
+        options: Default::default(),
+    });
+    
+    let mut providers = HashMap::new();
+    providers.insert("openai".to_string(), ProviderConfig {
+        provider_type: "openai".to_string(),
+        enabled: true,
+        base_url: base_url.to_string(),
+        api_key: "test_key".to_string(),
+        api_key_file: None,
+        options: Default::default(),
+        models,
+    });
+    
+    AppConfig {
+        providers,
+        ..Default::default()
+    }
+}
+
+/// Mock OpenAI upstream
+///
+/// Non-streaming requests get a short completion; streaming requests get
+/// `chunks` text deltas followed by a finish chunk and `[DONE]`.
+fn start_upstream(chunks: usize) -> MockServer {
+    let server = MockServer::start();
+    
+    let chunk = |delta: serde_json::Value, finish_reason: Option<&str>| serde_json::json!({
+        "id": "chatcmpl-bench",
+        "object": "chat.completion.chunk",
+        "created": 0,
+        "model": "gpt-4o",
+        "choices": [{"index": 0, "delta": delta, "finish_reason": finish_reason}]
+    });
+    let mut stream_body = format!("data: {}\n\n", chunk(serde_json::json!({"role": "assistant", "content": ""}), None));
+    for i in 0..chunks {
+        stream_body.push_str(&format!("data: {}\n\n", chunk(serde_json::json!({"content": format!("token {} ", i)}), None)));
+    }
+    stream_body.push_str(&format!("data: {}\n\n", chunk(serde_json::json!({}), Some("stop"))));
+    stream_body.push_str("data: [DONE]\n\n");
+    
+    server.mock(|when, then| {
+        when.method(httpmock::Method::POST)
+            .path("/chat/completions")
+            .json_body_partial(r#"{"stream": true}"#);
+        then.status(200)
+            .header("Content-Type", "text/event-stream")
+            .body(stream_body);
+    });
+    server.mock(|when, then| {
+        when.method(httpmock::Method::POST).path("/chat/completions");
+        then.status(200).json_body(serde_json::json!({
+            "id": "chatcmpl-bench",
+            "object": "chat.completion",
+            "created": 0,
+            "model": "gpt-4o",
+            "choices": [{
+                "index": 0,
+                "message": {"role": "assistant", "content": "Machine learning is a branch of artificial intelligence."},
+                "finish_reason": "stop"
+            }],
+            "usage": {"prompt_tokens": 20, "completion_tokens": 9, "total_tokens": 29}
+        }));
+    });
+    
+    server
+}
+
+/// Create the router once, outside of the measured loop
+fn create_app(rt: &Runtime, upstream: &MockServer) -> Router {
+    rt.block_on(create_router(create_test_settings(), create_test_app_config(&upstream.base_url())))
+        .expect("Failed to create router")
+}
+
 /// Create simple Claude request
 fn create_simple_claude_request() -> ClaudeRequest {
     ClaudeRequest {
-        model: "claude-3-sonnet".to_string(),
+        model: "openai/gpt-4o".to_string(),
         max_tokens: 100,
         messages: vec![ClaudeMessage {
             role: "user".to_string(),
@@ -45,7 +137,7 @@ fn create_simple_claude_request() -> ClaudeRequest {
 /// Create complex Claude request
 fn create_complex_claude_request() -> ClaudeRequest {
     ClaudeRequest {
-        model: "claude-3-opus".to_string(),
+        model: "openai/gpt-4o".to_string(),
         max_tokens: 1000,
         messages: vec![
             ClaudeMessage {
@@ -68,46 +160,40 @@ fn create_complex_claude_request() -> ClaudeRequest {
     }
 }
 
+/// POST /v1/messages with a JSON body
+fn messages_request(body: String) -> Request<Body> {
+    Request::builder()
+        .method("POST")
+        .uri("/v1/messages")
+        .header("content-type", "application/json")
+        .body(Body::from(body))
+        .unwrap()
+}
+
+/// Send a request and read the whole response body
+async fn send(app: &Router, request: Request<Body>) -> (StatusCode, axum::body::Bytes) {
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, body)
+}
+
 /// Benchmark: Health check endpoint
 fn bench_health_endpoint(c: &mut Criterion) {
-    let rt = tokio::runtime::Runtime::new().unwrap();
+    let rt = Runtime::new().unwrap();
+    let upstream = start_upstream(0);
+    let app = create_app(&rt, &upstream);
     
     c.bench_function("health_endpoint", |b| {
         b.iter(|| {
             rt.block_on(async {
-                let settings = create_test_settings();
-                let app = create_router(settings).await.expect("Failed to create router");
-                
                 let request = Request::builder()
                     .uri("/health")
                     .body(Body::empty())
                     .unwrap();
                 
-                let response = black_box(app.oneshot(request).await.unwrap());
-                assert_eq!(response.status(), StatusCode::OK);
-            })
-        })
-    });
-}
-
-/// Benchmark: Ready check endpoint
-fn bench_readiness_endpoint(c: &mut Criterion) {
-    let rt = tokio::runtime::Runtime::new().unwrap();
-    
-    c.bench_function("readiness_endpoint", |b| {
-        b.iter(|| {
-            rt.block_on(async {
-                let settings = create_test_settings();
-                let app = create_router(settings).await.expect("Failed to create router");
-                
-                let request = Request::builder()
-                    .uri("/health/ready")
-                    .body(Body::empty())
-                    .unwrap();
-                
-                let response = black_box(app.oneshot(request).await.unwrap());
-                // Expect 503 in test environment because OpenAI connection will fail
-                assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+                let (status, _) = black_box(send(&app, request).await);
+                assert_eq!(status, StatusCode::OK);
             })
         })
     });
@@ -115,120 +201,104 @@ fn bench_readiness_endpoint(c: &mut Criterion) {
 
 /// Benchmark: Live check endpoint
 fn bench_liveness_endpoint(c: &mut Criterion) {
-    let rt = tokio::runtime::Runtime::new().unwrap();
+    let rt = Runtime::new().unwrap();
+    let upstream = start_upstream(0);
+    let app = create_app(&rt, &upstream);
     
     c.bench_function("liveness_endpoint", |b| {
         b.iter(|| {
             rt.block_on(async {
-                let settings = create_test_settings();
-                let app = create_router(settings).await.expect("Failed to create router");
-                
                 let request = Request::builder()
                     .uri("/health/live")
                     .body(Body::empty())
                     .unwrap();
                 
-                let response = black_box(app.oneshot(request).await.unwrap());
-                assert_eq!(response.status(), StatusCode::OK);
+                let (status, _) = black_box(send(&app, request).await);
+                assert_eq!(status, StatusCode::OK);
             })
         })
     });
 }
 
-/// Benchmark: Message endpoint request validation
-fn bench_messages_validation(c: &mut Criterion) {
-    let rt = tokio::runtime::Runtime::new().unwrap();
+/// Benchmark: Non-streaming messages, end to end
+fn bench_messages(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let upstream = start_upstream(0);
+    let app = create_app(&rt, &upstream);
+    let mut group = c.benchmark_group("messages");
     
-    c.bench_function("messages_validation", |b| {
-        b.iter(|| {
-            rt.block_on(async {
-                let settings = create_test_settings();
-                let app = create_router(settings).await.expect("Failed to create router");
-                
-                let claude_request = create_simple_claude_request();
-                let request_body = serde_json::to_string(&claude_request).unwrap();
-                
-                let request = Request::builder()
-                    .method("POST")
-                    .uri("/v1/messages")
-                    .header("content-type", "application/json")
-                    .header("authorization", "Bearer sk-ant-REDACTED")
-                    .body(Body::from(request_body))
-                    .unwrap();
-                
-                let response = black_box(app.oneshot(request).await.unwrap());
-                // Expect 502 because OpenAI request will fail, but validation should pass
-                assert!(response.status() == StatusCode::BAD_GATEWAY || response.status() == StatusCode::INTERNAL_SERVER_ERROR);
+    let requests = [
+        ("simple", create_simple_claude_request()),
+        ("complex", create_complex_claude_request()),
+    ];
+    for (name, claude_request) in requests {
+        let request_body = serde_json::to_string(&claude_request).unwrap();
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                rt.block_on(async {
+                    let (status, body) = black_box(send(&app, messages_request(request_body.clone())).await);
+                    assert_eq!(status, StatusCode::OK, "{}", String::from_utf8_lossy(&body));
+                })
             })
-        })
-    });
+        });
+    }
+    
+    group.finish();
 }
 
-/// Benchmark: Authentication failure handling
-fn bench_auth_failure(c: &mut Criterion) {
-    let rt = tokio::runtime::Runtime::new().unwrap();
+/// Benchmark: Streaming messages, end to end, by number of upstream chunks
+fn bench_streaming(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("streaming");
     
-    c.bench_function("auth_failure", |b| {
-        b.iter(|| {
-            rt.block_on(async {
-                let settings = create_test_settings();
-                let app = create_router(settings).await.expect("Failed to create router");
-                
-                let claude_request = create_simple_claude_request();
-                let request_body = serde_json::to_string(&claude_request).unwrap();
-                
-                let request = Request::builder()
-                    .method("POST")
-                    .uri("/v1/messages")
-                    .header("content-type", "application/json")
-                    // Intentionally not providing authentication header
-                    .body(Body::from(request_body))
-                    .unwrap();
-                
-                let response = black_box(app.oneshot(request).await.unwrap());
-                assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let mut claude_request = create_simple_claude_request();
+    claude_request.stream = Some(true);
+    let request_body = serde_json::to_string(&claude_request).unwrap();
+    
+    for chunks in [10, 100, 1000] {
+        let upstream = start_upstream(chunks);
+        let app = create_app(&rt, &upstream);
+        
+        group.throughput(Throughput::Elements(chunks as u64));
+        group.bench_with_input(BenchmarkId::new("chunks", chunks), &chunks, |b, _| {
+            b.iter(|| {
+                rt.block_on(async {
+                    let (status, body) = black_box(send(&app, messages_request(request_body.clone())).await);
+                    assert_eq!(status, StatusCode::OK);
+                    assert!(body.ends_with(b"data: {\"type\":\"message_stop\"}\n\n"));
+                })
             })
-        })
-    });
+        });
+    }
+    
+    group.finish();
 }
 
 /// Benchmark: Different request size handling
 fn bench_request_sizes(c: &mut Criterion) {
-    let rt = tokio::runtime::Runtime::new().unwrap();
+    let rt = Runtime::new().unwrap();
+    let upstream = start_upstream(0);
+    let app = create_app(&rt, &upstream);
     let mut group = c.benchmark_group("request_sizes");
     
-    for size in [100, 1000, 10000].iter() {
-        let content = "x".repeat(*size);
+    for size in [100, 1000, 10000] {
+        let claude_request = ClaudeRequest {
+            model: "openai/gpt-4o".to_string(),
+            max_tokens: 100,
+            messages: vec![ClaudeMessage {
+                role: "user".to_string(),
+                content: ClaudeContent::Text("x".repeat(size)),
+            }],
+            ..Default::default()
+        };
+        let request_body = serde_json::to_string(&claude_request).unwrap();
         
-        group.bench_with_input(BenchmarkId::new("process_request", size), size, |b, _| {
+        group.throughput(Throughput::Bytes(request_body.len() as u64));
+        group.bench_with_input(BenchmarkId::new("process_request", size), &size, |b, _| {
             b.iter(|| {
                 rt.block_on(async {
-                    let settings = create_test_settings();
-                    let app = create_router(settings).await.expect("Failed to create router");
-                    
-                    let claude_request = ClaudeRequest {
-                        model: "claude-3-sonnet".to_string(),
-                        max_tokens: 100,
-                        messages: vec![ClaudeMessage {
-                            role: "user".to_string(),
-                            content: ClaudeContent::Text(content.clone()),
-                        }],
-                        ..Default::default()
-                    };
-                    
-                    let request_body = serde_json::to_string(&claude_request).unwrap();
-                    
-                    let request = Request::builder()
-                        .method("POST")
-                        .uri("/v1/messages")
-                        .header("content-type", "application/json")
-                        .header("authorization", "Bearer sk-ant-REDACTED")
-                        .body(Body::from(request_body))
-                        .unwrap();
-                    
-                    let response = black_box(app.oneshot(request).await.unwrap());
-                    // Validation should pass, but OpenAI request will fail
-                    assert!(response.status() == StatusCode::BAD_GATEWAY || response.status() == StatusCode::INTERNAL_SERVER_ERROR);
+                    let (status, _) = black_box(send(&app, messages_request(request_body.clone())).await);
+                    assert_eq!(status, StatusCode::OK);
                 })
             })
         });
@@ -239,34 +309,28 @@ fn bench_request_sizes(c: &mut Criterion) {
 
 /// Benchmark: Concurrent request handling
 fn bench_concurrent_requests(c: &mut Criterion) {
-    let rt = tokio::runtime::Runtime::new().unwrap();
+    let rt = Runtime::new().unwrap();
+    let upstream = start_upstream(0);
+    let app = create_app(&rt, &upstream);
+    let request_body = serde_json::to_string(&create_simple_claude_request()).unwrap();
     let mut group = c.benchmark_group("concurrent_requests");
     
-    for concurrency in [1, 5, 10].iter() {
-        group.bench_with_input(BenchmarkId::new("health_check", concurrency), concurrency, |b, &concurrency| {
+    for concurrency in [1, 5, 10] {
+        group.throughput(Throughput::Elements(concurrency as u64));
+        group.bench_with_input(BenchmarkId::new("messages", concurrency), &concurrency, |b, &concurrency| {
             b.iter(|| {
-                rt.block_on(async move {
-                    let settings = create_test_settings();
-                    let app = create_router(settings).await.expect("Failed to create router");
-                    
+                rt.block_on(async {
                     let mut handles = vec![];
                     
                     for _ in 0..concurrency {
-                        let app_clone = app.clone();
-                        let handle = tokio::spawn(async move {
-                            let request = Request::builder()
-                                .uri("/health")
-                                .body(Body::empty())
-                                .unwrap();
-                            
-                            app_clone.oneshot(request).await.unwrap()
-                        });
-                        handles.push(handle);
+                        let app = app.clone();
+                        let request = messages_request(request_body.clone());
+                        handles.push(tokio::spawn(async move { send(&app, request).await }));
                     }
                     
                     for handle in handles {
-                        let response = black_box(handle.await.unwrap());
-                        assert_eq!(response.status(), StatusCode::OK);
+                        let (status, _) = black_box(handle.await.unwrap());
+                        assert_eq!(status, StatusCode::OK);
                     }
                 })
             })
@@ -276,165 +340,24 @@ fn bench_concurrent_requests(c: &mut Criterion) {
     group.finish();
 }
 
-/// Benchmark: JSON serialization/deserialization overhead
-fn bench_json_processing(c: &mut Criterion) {
-    let rt = tokio::runtime::Runtime::new().unwrap();
-    let mut group = c.benchmark_group("json_processing");
-    
-    let simple_request = create_simple_claude_request();
-    let complex_request = create_complex_claude_request();
-    
-    group.bench_function("simple_request_json", |b| {
-        b.iter(|| {
-            rt.block_on(async {
-                let settings = create_test_settings();
-                let app = create_router(settings).await.expect("Failed to create router");
-                
-                let request_body = black_box(serde_json::to_string(&simple_request).unwrap());
-                
-                let request = Request::builder()
-                    .method("POST")
-                    .uri("/v1/messages")
-                    .header("content-type", "application/json")
-                    .header("authorization", "Bearer sk-ant-REDACTED")
-                    .body(Body::from(request_body))
-                    .unwrap();
-                
-                let response = black_box(app.oneshot(request).await.unwrap());
-                assert!(response.status().is_client_error() || response.status().is_server_error());
-            })
-        })
-    });
-    
-    group.bench_function("complex_request_json", |b| {
-        b.iter(|| {
-            rt.block_on(async {
-                let settings = create_test_settings();
-                let app = create_router(settings).await.expect("Failed to create router");
-                
-                let request_body = black_box(serde_json::to_string(&complex_request).unwrap());
-                
-                let request = Request::builder()
-                    .method("POST")
-                    .uri("/v1/messages")
-                    .header("content-type", "application/json")
-                    .header("authorization", "Bearer sk-ant-REDACTED")
-                    .body(Body::from(request_body))
-                    .unwrap();
-                
-                let response = black_box(app.oneshot(request).await.unwrap());
-                assert!(response.status().is_client_error() || response.status().is_server_error());
-            })
-        })
-    });
-    
-    group.finish();
-}
-
 /// Benchmark: Error handling performance
 fn bench_error_handling(c: &mut Criterion) {
-    let rt = tokio::runtime::Runtime::new().unwrap();
+    let rt = Runtime::new().unwrap();
+    let upstream = start_upstream(0);
+    let app = create_app(&rt, &upstream);
     let mut group = c.benchmark_group("error_handling");
     
-    // Test performance of various error scenario handling
-    group.bench_function("missing_auth", |b| {
-        b.iter(|| {
-            rt.block_on(async {
-                let settings = create_test_settings();
-                let app = create_router(settings).await.expect("Failed to create router");
-                
-                let request = Request::builder()
-                    .method("POST")
-                    .uri("/v1/messages")
-                    .header("content-type", "application/json")
-                    .body(Body::from(r#"{"model":"claude-3-sonnet","max_tokens":100,"messages":[{"role":"user","content":"test"}]}"#))
-                    .unwrap();
-                
-                let response = black_box(app.oneshot(request).await.unwrap());
-                assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
-            })
-        })
-    });
-    
-    group.bench_function("invalid_json", |b| {
-        b.iter(|| {
-            rt.block_on(async {
-                let settings = create_test_settings();
-                let app = create_router(settings).await.expect("Failed to create router");
-                
-                let request = Request::builder()
-                    .method("POST")
-                    .uri("/v1/messages")
-                    .header("content-type", "application/json")
-                    .header("authorization", "Bearer sk-ant-REDACTED")
-                    .body(Body::from(r#"{"model":"claude-3-sonnet","max_tokens":}"#)) // Invalid JSON
-                    .unwrap();
-                
-                let response = black_box(app.oneshot(request).await.unwrap());
-                assert_eq!(response.status(), StatusCode::BAD_REQUEST);
-            })
-        })
-    });
-    
-    group.bench_function("validation_error", |b| {
-        b.iter(|| {
-            rt.block_on(async {
-                let settings = create_test_settings();
-                let app = create_router(settings).await.expect("Failed to create router");
-                
-                let request = Request::builder()
-                    .method("POST")
-                    .uri("/v1/messages")
-                    .header("content-type", "application/json")
-                    .header("authorization", "Bearer sk-ant-REDACTED")
-                    .body(Body::from(r#"{"model":"claude-3-sonnet","max_tokens":0,"messages":[]}"#)) // Validation error
-                    .unwrap();
-                
-                let response = black_box(app.oneshot(request).await.unwrap());
-                assert_eq!(response.status(), StatusCode::BAD_REQUEST);
-            })
-        })
-    });
-    
-    group.finish();
-}
-
-/// Benchmark: Routing performance
-fn bench_routing(c: &mut Criterion) {
-    let rt = tokio::runtime::Runtime::new().unwrap();
-    let mut group = c.benchmark_group("routing");
-    
-    let endpoints = vec![
-        ("/health", "GET"),
-        ("/health/ready", "GET"),
-        ("/health/live", "GET"),
-        ("/", "GET"),
-        ("/v1/messages", "POST"),
+    let cases = [
+        ("invalid_json", r#"{"model":"openai/gpt-4o","max_tokens":}"#, StatusCode::BAD_REQUEST),
+        ("validation_error", r#"{"model":"openai/gpt-4o","max_tokens":0,"messages":[]}"#, StatusCode::BAD_REQUEST),
+        ("unknown_model", r#"{"model":"claude-3-sonnet","max_tokens":100,"messages":[{"role":"user","content":"test"}]}"#, StatusCode::NOT_FOUND),
     ];
-    
-    for (path, method) in endpoints {
-        group.bench_function(&format!("{}_{}", method.to_lowercase(), path.replace('/', "_")), |b| {
+    for (name, request_body, expected) in cases {
+        group.bench_function(name, |b| {
             b.iter(|| {
                 rt.block_on(async {
-                    let settings = create_test_settings();
-                    let app = create_router(settings).await.expect("Failed to create router");
-                    
-                    let mut request_builder = Request::builder().method(method).uri(path);
-                    
-                    let body = if method == "POST" {
-                        request_builder = request_builder
-                            .header("content-type", "application/json")
-                            .header("authorization", "Bearer sk-ant-REDACTED");
-                        Body::from(r#"{"model":"claude-3-sonnet","max_tokens":100,"messages":[{"role":"user","content":"test"}]}"#)
-                    } else {
-                        Body::empty()
-                    };
-                    
-                    let request = request_builder.body(body).unwrap();
-                    let response = black_box(app.oneshot(request).await.unwrap());
-                    
-                    // Verify response status codes are within reasonable range
-                    assert!(response.status().as_u16() >= 200 && response.status().as_u16() < 600);
+                    let (status, _) = black_box(send(&app, messages_request(request_body.to_string())).await);
+                    assert_eq!(status, expected);
                 })
             })
         });
@@ -446,15 +369,12 @@ fn bench_routing(c: &mut Criterion) {
 criterion_group!(
     benches,
     bench_health_endpoint,
-    bench_readiness_endpoint,
     bench_liveness_endpoint,
-    bench_messages_validation,
-    bench_auth_failure,
+    bench_messages,
+    bench_streaming,
     bench_request_sizes,
     bench_concurrent_requests,
-    bench_json_processing,
-    bench_error_handling,
-    bench_routing
+    bench_error_handling
 );
 
-criterion_main!(benches);
\ No newline at end of file
+criterion_main!(benches);
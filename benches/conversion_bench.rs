@@ -29,6 +29,7 @@ fn create_test_settings() -> Settings {
         },
         request: RequestConfig {
             max_request_size: 1024,
+            max_image_request_size: 4096,
             max_concurrent_requests: 10,
             timeout: 30,
         },
@@ -100,6 +101,7 @@ fn create_multimodal_claude_request() -> ClaudeRequest {
                         source_type: "base64".to_string(),
                         media_type: "image/jpeg".to_string(),
                         data: "iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAYAAAAfFcSJAAAADUlEQVR42mP8/5+hHgAHggJ/PchI7wAAAABJRU5ErkJggg==".to_string(),
+                        url: None,
                     },
                 },
             ]),
@@ -131,8 +133,11 @@ fn create_openai_response() -> OpenAIResponse {
             prompt_tokens: 15,
             completion_tokens: 10,
             total_tokens: 25,
+            prompt_tokens_details: None,
         }),
         system_fingerprint: None,
+        search_results: None,
+        search_queries: None,
     }
 }
 
@@ -144,6 +149,8 @@ fn create_openai_stream_response() -> OpenAIStreamResponse {
         created: Utc::now().timestamp() as u64,
         model: "gpt-4o".to_string(),
         system_fingerprint: None,
+        search_results: None,
+        search_queries: None,
         choices: vec![OpenAIStreamChoice {
             index: 0,
             delta: OpenAIStreamDelta {
@@ -314,13 +321,13 @@ fn bench_serialization(c: &mut Criterion) {
     
     group.bench_function("claude_request_deserialize", |b| {
         b.iter(|| {
-            black_box(serde_json::from_str::<ClaudeRequest>(&black_box(&claude_json)).unwrap())
+            black_box(serde_json::from_str::<ClaudeRequest>(black_box(&claude_json)).unwrap())
         })
     });
     
     group.bench_function("openai_response_deserialize", |b| {
         b.iter(|| {
-            black_box(serde_json::from_str::<OpenAIResponse>(&black_box(&openai_json)).unwrap())
+            black_box(serde_json::from_str::<OpenAIResponse>(black_box(&openai_json)).unwrap())
         })
     });
     
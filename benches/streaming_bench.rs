@@ -0,0 +1,245 @@
+//! Streaming conversion benchmarks
+//!
+//! Each benchmark replays a recorded-style trace of 1000 upstream chunks: a
+//! long text answer followed by a tool call whose arguments arrive in small
+//! pieces, split into TCP-sized reads. Throughput is reported per chunk.
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use aiapiproxy::config::settings::*;
+use aiapiproxy::models::claude::ClaudeStreamEvent;
+use aiapiproxy::models::openai::OpenAIStreamResponse;
+use aiapiproxy::providers::ResponsesStreamParser;
+use aiapiproxy::services::{ApiConverter, ContentBlockTracker};
+use aiapiproxy::utils::sse::{parse_openai_chunk, SseDecoder};
+use serde_json::json;
+use std::collections::HashMap;
+
+/// Chunks in a trace
+const TRACE_CHUNKS: usize = 1000;
+
+/// Text deltas before the tool call starts
+const TEXT_CHUNKS: usize = 900;
+
+/// Bytes per upstream read, about one TCP segment
+const READ_SIZE: usize = 1460;
+
+/// Create test settings
+fn create_test_settings() -> Settings {
+    Settings {
+        server: ServerConfig {
+            host: "localhost".to_string(),
+            port: 8080,
+        },
+        openai: OpenAIConfig {
+            api_key: "test_key".to_string(),
+            base_url: "https://api.openai.com/v1".to_string(),
+            timeout: 30,
+            stream_timeout: 300,
+        },
+        model_mapping: ModelMapping {
+            haiku: "gpt-4o-mini".to_string(),
+            sonnet: "gpt-4o".to_string(),
+            opus: "gpt-4".to_string(),
+            custom: HashMap::new(),
+        },
+        request: RequestConfig {
+            max_request_size: 1024,
+            max_image_request_size: 4096,
+            max_concurrent_requests: 10,
+            timeout: 30,
+        },
+        security: SecurityConfig {
+            allowed_origins: vec!["*".to_string()],
+            api_key_header: "Authorization".to_string(),
+            cors_enabled: true,
+        },
+        logging: LoggingConfig {
+            level: "info".to_string(),
+            format: "text".to_string(),
+        },
+    }
+}
+
+/// Text of the `i`th delta, a word or two like real token deltas
+fn text_delta(i: usize) -> String {
+    const WORDS: [&str; 8] = [" the", " stream", "ing", " response", " is", " converted", " chunk", " by chunk."];
+    WORDS[i % WORDS.len()].to_string()
+}
+
+/// Arguments of the `i`th tool call delta
+fn arguments_delta(i: usize) -> String {
+    match i {
+        0 => r#"{"path": "src/"#.to_string(),
+        _ => format!("dir{}/", i),
+    }
+}
+
+/// An OpenAI chat completion chunk trace
+fn openai_trace() -> Vec<serde_json::Value> {
+    let chunk = |delta: serde_json::Value, finish_reason: Option<&str>| json!({
+        "id": "chatcmpl-bench",
+        "object": "chat.completion.chunk",
+        "created": 1700000000,
+        "model": "gpt-4o",
+        "choices": [{"index": 0, "delta": delta, "finish_reason": finish_reason}]
+    });
+    
+    let mut trace = vec![chunk(json!({"role": "assistant", "content": ""}), None)];
+    for i in 1..TEXT_CHUNKS {
+        trace.push(chunk(json!({"content": text_delta(i)}), None));
+    }
+    trace.push(chunk(json!({"tool_calls": [{
+        "index": 0,
+        "id": "call_bench",
+        "type": "function",
+        "function": {"name": "list_files", "arguments": ""}
+    }]}), None));
+    for i in 0..TRACE_CHUNKS - TEXT_CHUNKS - 2 {
+        trace.push(chunk(json!({"tool_calls": [{"index": 0, "function": {"arguments": arguments_delta(i)}}]}), None));
+    }
+    trace.push(chunk(json!({}), Some("tool_calls")));
+    trace
+}
+
+/// A Responses API event trace
+fn responses_trace() -> Vec<serde_json::Value> {
+    let mut trace = vec![json!({"type": "response.created", "response": {"id": "resp_bench", "status": "in_progress"}})];
+    for i in 1..TEXT_CHUNKS {
+        trace.push(json!({"type": "response.output_text.delta", "output_index": 0, "content_index": 0, "delta": text_delta(i)}));
+    }
+    trace.push(json!({
+        "type": "response.output_item.added",
+        "output_index": 1,
+        "item": {"type": "function_call", "call_id": "call_bench", "name": "list_files", "arguments": ""}
+    }));
+    for i in 0..TRACE_CHUNKS - TEXT_CHUNKS - 2 {
+        trace.push(json!({"type": "response.function_call_arguments.delta", "output_index": 1, "delta": arguments_delta(i)}));
+    }
+    trace.push(json!({"type": "response.completed", "response": {"id": "resp_bench", "status": "completed"}}));
+    trace
+}
+
+/// Encode a trace as an SSE body split into upstream reads
+fn sse_reads(trace: &[serde_json::Value]) -> Vec<Vec<u8>> {
+    let mut body = String::new();
+    for event in trace {
+        body.push_str(&format!("data: {}\n\n", event));
+    }
+    body.push_str("data: [DONE]\n\n");
+    body.as_bytes().chunks(READ_SIZE).map(<[u8]>::to_vec).collect()
+}
+
+/// Parsed OpenAI chunks of a trace
+fn openai_chunks(trace: &[serde_json::Value]) -> Vec<OpenAIStreamResponse> {
+    trace.iter().map(|chunk| serde_json::from_value(chunk.clone()).unwrap()).collect()
+}
+
+/// Convert chunks to the Claude events sent to the client
+fn convert(converter: &ApiConverter, chunks: Vec<OpenAIStreamResponse>) -> Vec<ClaudeStreamEvent> {
+    let mut tracker = ContentBlockTracker::new();
+    let mut events = Vec::new();
+    for chunk in chunks {
+        for event in converter.convert_stream_chunk(chunk, "claude-3-5-sonnet").unwrap() {
+            events.extend(tracker.process(event));
+        }
+    }
+    events
+}
+
+/// Benchmark: SSE decoding of OpenAI chunks
+fn bench_sse_parsing(c: &mut Criterion) {
+    let reads = sse_reads(&openai_trace());
+    let mut group = c.benchmark_group("sse_parsing");
+    group.throughput(Throughput::Elements(TRACE_CHUNKS as u64));
+    
+    group.bench_function("decode", |b| {
+        b.iter(|| {
+            let mut decoder = SseDecoder::new();
+            let payloads: usize = reads.iter().map(|read| decoder.push(black_box(read)).len()).sum();
+            assert_eq!(payloads, TRACE_CHUNKS + 1);
+        })
+    });
+    
+    group.bench_function("decode_and_parse", |b| {
+        b.iter(|| {
+            let mut decoder = SseDecoder::new();
+            let chunks = reads.iter()
+                .flat_map(|read| decoder.push(black_box(read)))
+                .filter_map(|data| parse_openai_chunk(&data))
+                .count();
+            assert_eq!(chunks, TRACE_CHUNKS);
+        })
+    });
+    
+    group.finish();
+}
+
+/// Benchmark: `convert_stream_chunk` and block indexing of parsed chunks
+fn bench_stream_chunk_conversion(c: &mut Criterion) {
+    let converter = ApiConverter::new(create_test_settings());
+    let chunks = openai_chunks(&openai_trace());
+    let mut group = c.benchmark_group("stream_chunk_conversion");
+    group.throughput(Throughput::Elements(TRACE_CHUNKS as u64));
+    
+    group.bench_function("convert_stream_chunk", |b| {
+        b.iter_batched(
+            || chunks.clone(),
+            |chunks| black_box(convert(&converter, chunks)),
+            BatchSize::LargeInput,
+        )
+    });
+    
+    // Upstream bytes to the SSE body sent to the client
+    let reads = sse_reads(&openai_trace());
+    group.bench_function("sse_to_sse", |b| {
+        b.iter(|| {
+            let mut decoder = SseDecoder::new();
+            let chunks = reads.iter()
+                .flat_map(|read| decoder.push(black_box(read)))
+                .filter_map(|data| parse_openai_chunk(&data))
+                .collect();
+            let mut body = String::new();
+            for event in convert(&converter, chunks) {
+                body.push_str(&format!("event: {}\ndata: {}\n\n", event.event_type(), serde_json::to_string(&event).unwrap()));
+            }
+            black_box(body)
+        })
+    });
+    
+    group.finish();
+}
+
+/// Benchmark: Responses API events to OpenAI chunks
+fn bench_responses_events(c: &mut Criterion) {
+    let reads = sse_reads(&responses_trace());
+    let mut group = c.benchmark_group("responses_events");
+    group.throughput(Throughput::Elements(TRACE_CHUNKS as u64));
+    
+    group.bench_function("parse", |b| {
+        b.iter(|| {
+            let mut parser = ResponsesStreamParser::default();
+            let chunks: usize = reads.iter().map(|read| parser.push(black_box(read)).len()).sum();
+            assert_eq!(chunks, TRACE_CHUNKS);
+        })
+    });
+    
+    let converter = ApiConverter::new(create_test_settings());
+    group.bench_function("parse_and_convert", |b| {
+        b.iter(|| {
+            let mut parser = ResponsesStreamParser::default();
+            let chunks = reads.iter().flat_map(|read| parser.push(black_box(read))).collect();
+            black_box(convert(&converter, chunks))
+        })
+    });
+    
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_sse_parsing,
+    bench_stream_chunk_conversion,
+    bench_responses_events
+);
+
+criterion_main!(benches);
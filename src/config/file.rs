@@ -2,12 +2,22 @@
 //!
 //! Loads provider and model configuration from JSON file
 
+use super::secrets;
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::path::Path;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use tracing::{debug, info};
 
+/// Headers set by the proxy itself or by the HTTP client, never forwarded
+/// from the inbound request
+const PROTECTED_HEADERS: &[&str] = &[
+    "authorization", "proxy-authorization", "x-api-key", "cookie", "host",
+    "content-length", "content-type", "content-encoding", "accept-encoding",
+    "connection", "keep-alive", "te", "trailer", "transfer-encoding", "upgrade",
+];
+
 /// Server configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServerConfig {
@@ -18,6 +28,23 @@ pub struct ServerConfig {
     /// Listen port (default: 8082)
     #[serde(default = "default_port")]
     pub port: u16,
+    
+    /// Cross-origin resource sharing (default: any origin)
+    #[serde(default)]
+    pub cors: CorsConfig,
+    
+    /// Response compression (default: gzip/br for JSON responses)
+    #[serde(default)]
+    pub compression: CompressionConfig,
+    
+    /// Serve HTTPS with these certificate files (default: plain HTTP)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<TlsConfig>,
+    
+    /// Check the configuration file for changes at this interval and reload
+    /// it (default: only on SIGHUP; a remote configuration is polled every 60)
+    #[serde(rename = "configReloadIntervalSecs", default, skip_serializing_if = "Option::is_none")]
+    pub config_reload_interval_secs: Option<u64>,
 }
 
 fn default_host() -> String {
@@ -33,425 +60,3704 @@ impl Default for ServerConfig {
         Self {
             host: default_host(),
             port: default_port(),
+            cors: CorsConfig::default(),
+            compression: CompressionConfig::default(),
+            tls: None,
+            config_reload_interval_secs: None,
         }
     }
 }
 
-/// Application configuration loaded from JSON file
+/// CORS policy for browser clients
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct AppConfig {
-    /// Server configuration (optional, defaults to localhost:8082)
+pub struct CorsConfig {
+    /// Send CORS headers at all (default: true)
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    
+    /// Origins allowed to call the proxy, e.g. "https://app.example.com";
+    /// "*" allows any (default: ["*"])
+    #[serde(rename = "allowedOrigins", default = "default_allowed_origins")]
+    pub allowed_origins: Vec<String>,
+    
+    /// Let browsers send credentials (cookies, `Authorization`); requires
+    /// explicit origins (default: false)
+    #[serde(rename = "allowCredentials", default)]
+    pub allow_credentials: bool,
+    
+    /// Seconds browsers may cache preflight responses
+    #[serde(rename = "maxAgeSecs", skip_serializing_if = "Option::is_none")]
+    pub max_age_secs: Option<u64>,
+}
+
+fn default_allowed_origins() -> Vec<String> {
+    vec!["*".to_string()]
+}
+
+impl CorsConfig {
+    /// Whether any origin is allowed
+    pub fn allows_any_origin(&self) -> bool {
+        self.allowed_origins.iter().any(|origin| origin == "*")
+    }
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            allowed_origins: default_allowed_origins(),
+            allow_credentials: false,
+            max_age_secs: None,
+        }
+    }
+}
+
+/// Response compression for clients sending `Accept-Encoding`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompressionConfig {
+    /// Compress responses at all (default: true)
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    
+    /// Smallest response body compressed, in bytes (default: 1024)
+    #[serde(rename = "minSizeBytes", default = "default_min_compress_size")]
+    pub min_size_bytes: u16,
+    
+    /// Also compress SSE streams; each event is flushed as it is sent (default: false)
     #[serde(default)]
-    pub server: ServerConfig,
+    pub streaming: bool,
+}
+
+fn default_min_compress_size() -> u16 {
+    1024
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            min_size_bytes: default_min_compress_size(),
+            streaming: false,
+        }
+    }
+}
+
+/// HTTPS certificate and private key
+///
+/// Both files are PEM encoded; the certificate file holds the full chain.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TlsConfig {
+    /// Certificate chain, e.g. "/etc/letsencrypt/live/proxy.example.com/fullchain.pem"
+    #[serde(rename = "certPath")]
+    pub cert_path: PathBuf,
     
-    /// Provider configurations
-    pub providers: HashMap<String, ProviderConfig>,
+    /// Private key, e.g. "/etc/letsencrypt/live/proxy.example.com/privkey.pem"
+    #[serde(rename = "keyPath")]
+    pub key_path: PathBuf,
     
-    /// Claude model to provider/model mapping
-    /// Maps Claude model names (e.g., "claude-3-sonnet-20240620") to provider/model paths
-    #[serde(rename = "modelMapping", default)]
-    pub model_mapping: HashMap<String, String>,
+    /// Re-read both files at this interval so renewed certificates are
+    /// picked up without a restart (default: never)
+    #[serde(rename = "reloadIntervalSecs", default, skip_serializing_if = "Option::is_none")]
+    pub reload_interval_secs: Option<u64>,
 }
 
-/// Provider configuration
+/// Adaptive routing thresholds
+///
+/// Each provider/model path keeps a rolling window of its latest requests.
+/// Once the window holds `minRequests` results and the error rate or the
+/// average latency exceeds its threshold, the path is taken out of rotation
+/// for `cooldownSecs`; then a single probe request decides whether it returns.
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ProviderConfig {
-    /// Provider type (e.g., "openai", "modelhub")
-    #[serde(rename = "type")]
-    pub provider_type: String,
+pub struct AdaptiveRoutingConfig {
+    /// Number of latest requests per path considered (default: 20)
+    #[serde(rename = "windowSize", default = "default_window_size")]
+    pub window_size: usize,
     
-    /// Base URL for the provider API
-    #[serde(rename = "baseUrl")]
-    pub base_url: String,
+    /// Results needed before a path can be marked degraded (default: 5)
+    #[serde(rename = "minRequests", default = "default_min_requests")]
+    pub min_requests: usize,
     
-    /// API key (can be empty if using env var)
-    #[serde(rename = "apiKey", default)]
-    pub api_key: String,
+    /// Error rate above which a path is degraded (default: 0.5)
+    #[serde(rename = "maxErrorRate", default = "default_max_error_rate")]
+    pub max_error_rate: f64,
     
-    /// Provider-specific options
-    #[serde(default)]
-    pub options: ProviderOptions,
+    /// Average latency in milliseconds above which a path is degraded (default: none)
+    #[serde(rename = "maxLatencyMs", skip_serializing_if = "Option::is_none")]
+    pub max_latency_ms: Option<u64>,
     
-    /// Model configurations for this provider
-    pub models: HashMap<String, ModelConfig>,
+    /// Seconds a degraded path is avoided before it is probed (default: 30)
+    #[serde(rename = "cooldownSecs", default = "default_cooldown_secs")]
+    pub cooldown_secs: u64,
 }
 
-/// Provider-specific options
-#[derive(Debug, Clone, Default, Serialize, Deserialize)]
-pub struct ProviderOptions {
-    /// API key parameter name (for URL query parameter auth)
-    #[serde(rename = "apiKeyParam", skip_serializing_if = "Option::is_none")]
-    pub api_key_param: Option<String>,
+fn default_window_size() -> usize {
+    20
+}
+
+fn default_min_requests() -> usize {
+    5
+}
+
+fn default_max_error_rate() -> f64 {
+    0.5
+}
+
+fn default_cooldown_secs() -> u64 {
+    30
+}
+
+impl Default for AdaptiveRoutingConfig {
+    fn default() -> Self {
+        Self {
+            window_size: default_window_size(),
+            min_requests: default_min_requests(),
+            max_error_rate: default_max_error_rate(),
+            max_latency_ms: None,
+            cooldown_secs: default_cooldown_secs(),
+        }
+    }
+}
+
+/// Limit on the requests sent upstream at a time
+///
+/// Requests beyond `maxConcurrent` wait in a queue, interactive ones ahead of
+/// batch ones, and are rejected when the queue is full or the wait times out.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConcurrencyConfig {
+    /// Requests in flight at a time
+    #[serde(rename = "maxConcurrent")]
+    pub max_concurrent: usize,
     
-    /// Mode for modelhub provider ("responses" or "gemini")
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub mode: Option<String>,
+    /// Requests allowed to wait for a slot (default: unbounded)
+    #[serde(rename = "maxQueued", skip_serializing_if = "Option::is_none")]
+    pub max_queued: Option<usize>,
     
-    /// Custom headers to add to requests
+    /// Longest wait for a slot in milliseconds (default: 60000)
+    #[serde(rename = "queueTimeoutMs", default = "default_queue_timeout_ms")]
+    pub queue_timeout_ms: u64,
+}
+
+fn default_queue_timeout_ms() -> u64 {
+    60000
+}
+
+/// End-to-end time budgets of requests, in milliseconds
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct TimeoutBudget {
+    /// Time allowed for a non-streaming response
+    #[serde(rename = "requestMs", skip_serializing_if = "Option::is_none")]
+    pub request_ms: Option<u64>,
+    
+    /// Time allowed until the first streamed event from upstream
+    #[serde(rename = "streamFirstTokenMs", skip_serializing_if = "Option::is_none")]
+    pub stream_first_token_ms: Option<u64>,
+}
+
+/// Request timeouts: default budgets and overrides by route path
+///
+/// ```json
+/// "timeouts": {
+///   "requestMs": 120000,
+///   "streamFirstTokenMs": 60000,
+///   "routes": { "/v1/messages/count_tokens": { "requestMs": 5000 } }
+/// }
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TimeoutConfig {
+    /// Budgets of routes without an override
+    #[serde(flatten)]
+    pub default: TimeoutBudget,
+    
+    /// Budgets by route path; unset fields fall back to the defaults
     #[serde(default, skip_serializing_if = "HashMap::is_empty")]
-    pub headers: HashMap<String, String>,
+    pub routes: HashMap<String, TimeoutBudget>,
 }
 
-/// Model configuration
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ModelConfig {
-    /// Model name to use with the upstream provider
-    pub name: String,
+impl TimeoutConfig {
+    /// Budgets of a route path
+    pub fn budget(&self, path: &str) -> TimeoutBudget {
+        let route = self.routes.get(path).copied().unwrap_or_default();
+        TimeoutBudget {
+            request_ms: route.request_ms.or(self.default.request_ms),
+            stream_first_token_ms: route.stream_first_token_ms.or(self.default.stream_first_token_ms),
+        }
+    }
+}
+
+/// Default seconds without an event before a keep-alive is sent
+pub const DEFAULT_KEEP_ALIVE_SECS: u64 = 15;
+
+/// Form of SSE keep-alives
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeepAliveFormat {
+    /// `ping` events, which Claude clients expect
+    #[default]
+    Ping,
+    /// `: <text>` comment lines, which OpenAI clients expect
+    Comment,
+}
+
+/// Keep-alives of a streaming route
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct KeepAlive {
+    /// Seconds without an event before a keep-alive is sent
+    #[serde(rename = "intervalSecs", skip_serializing_if = "Option::is_none")]
+    pub interval_secs: Option<u64>,
     
-    /// Optional alias for the model (used in request routing)
+    /// `ping` or `comment`
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub alias: Option<String>,
-    
-    /// Maximum tokens limit for this model
-    #[serde(rename = "maxTokens", skip_serializing_if = "Option::is_none")]
-    pub max_tokens: Option<u32>,
+    pub format: Option<KeepAliveFormat>,
     
-    /// Default temperature for this model
+    /// Text of comment keep-alives
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub temperature: Option<f32>,
+    pub comment: Option<String>,
+}
+
+impl KeepAlive {
+    /// Time without an event before a keep-alive is sent (default: 15 seconds)
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_secs.unwrap_or(DEFAULT_KEEP_ALIVE_SECS))
+    }
     
-    /// Model-specific options
-    #[serde(default)]
-    pub options: ModelOptions,
+    /// Form of the keep-alives (default: `ping` events)
+    pub fn format(&self) -> KeepAliveFormat {
+        self.format.unwrap_or_default()
+    }
+    
+    /// Text of comment keep-alives (default: "keep-alive")
+    pub fn comment(&self) -> &str {
+        self.comment.as_deref().unwrap_or("keep-alive")
+    }
 }
 
-/// Model-specific options
+/// Keep-alives sent while a streaming upstream is idle: defaults and
+/// overrides by route path
+///
+/// ```json
+/// "keepAlive": {
+///   "intervalSecs": 10,
+///   "routes": { "/v1/messages": { "format": "comment", "comment": "waiting" } }
+/// }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct KeepAliveConfig {
+    /// Keep-alives of routes without an override
+    #[serde(flatten)]
+    pub default: KeepAlive,
+    
+    /// Keep-alives by route path; unset fields fall back to the defaults
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub routes: HashMap<String, KeepAlive>,
+}
+
+impl KeepAliveConfig {
+    /// Keep-alives of a route path
+    pub fn route(&self, path: &str) -> KeepAlive {
+        let route = self.routes.get(path).cloned().unwrap_or_default();
+        KeepAlive {
+            interval_secs: route.interval_secs.or(self.default.interval_secs),
+            format: route.format.or(self.default.format),
+            comment: route.comment.or_else(|| self.default.comment.clone()),
+        }
+    }
+}
+
+/// Scheduling class of a request waiting for a concurrency slot
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    /// Someone is waiting for the answer (e.g. an editor session); served first
+    #[default]
+    Interactive,
+    /// Background work that can wait
+    Batch,
+}
+
+/// Application configuration loaded from JSON file
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
-pub struct ModelOptions {
-    /// Mode for this model (e.g., "responses", "gemini", "chat")
+pub struct AppConfig {
+    /// Server configuration (optional, defaults to localhost:8082)
+    #[serde(default)]
+    pub server: ServerConfig,
+    
+    /// Provider configurations
+    pub providers: HashMap<String, ProviderConfig>,
+    
+    /// Claude model to provider/model mapping
+    /// Maps Claude model names (e.g., "claude-3-sonnet-20240620") to provider/model paths
+    /// or to failover chains of paths
+    #[serde(rename = "modelMapping", default)]
+    pub model_mapping: HashMap<String, ModelTarget>,
+    
+    /// Allow clients to request any upstream model of a configured provider
+    /// with a raw "provider/model" path, even if the model is not listed in
+    /// the provider's models (default: false)
+    #[serde(rename = "allowModelOverride", default)]
+    pub allow_model_override: bool,
+    
+    /// How requests for multiple choices (`n > 1`) are handled (default: "reject")
+    #[serde(rename = "multipleChoices", default)]
+    pub multiple_choices: MultipleChoicesMode,
+    
+    /// Rules routing requests by their Claude `metadata`, checked in order
+    /// before the model mapping
+    #[serde(rename = "routingRules", default, skip_serializing_if = "Vec::is_empty")]
+    pub routing_rules: Vec<RoutingRule>,
+    
+    /// A/B experiments splitting the sessions of a Claude model between two
+    /// provider/model paths, checked after the routing rules
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub experiments: Vec<ExperimentConfig>,
+    
+    /// System prompt rewrites, applied in order to requests of matching
+    /// Claude models
+    #[serde(rename = "promptRules", default, skip_serializing_if = "Vec::is_empty")]
+    pub prompt_rules: Vec<PromptRule>,
+    
+    /// Replace personal data and secrets in outgoing messages with
+    /// placeholders (disabled when absent)
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub mode: Option<String>,
+    pub redaction: Option<RedactionConfig>,
     
-    /// Whether this model supports streaming
-    #[serde(rename = "supportsStreaming", default = "default_true")]
-    pub supports_streaming: bool,
+    /// Block, redact or annotate prompts and completions matching content
+    /// rules (disabled when absent)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub guardrails: Option<GuardrailsConfig>,
     
-    /// Whether this model supports tool use
-    #[serde(rename = "supportsTools", default = "default_true")]
-    pub supports_tools: bool,
+    /// How request and response payloads appear in debug logs (default:
+    /// summaries with secrets masked)
+    #[serde(rename = "payloadLogging", default)]
+    pub payload_logging: PayloadLoggingConfig,
     
-    /// Whether this model supports vision/images
-    #[serde(rename = "supportsVision", default)]
-    pub supports_vision: bool,
+    /// Shift traffic away from failing or slow upstreams (disabled when absent)
+    #[serde(rename = "adaptiveRouting", skip_serializing_if = "Option::is_none")]
+    pub adaptive_routing: Option<AdaptiveRoutingConfig>,
     
-    /// Whether this model supports temperature parameter
-    /// Set to false for reasoning models (o1, o3, etc.) that don't support temperature
-    #[serde(rename = "supportsTemperature", default = "default_true")]
-    pub supports_temperature: bool,
+    /// Require clients to present a proxy-issued API key (open when absent)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auth: Option<AuthConfig>,
+    
+    /// Prices of upstream models by provider/model path, for cost estimates
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub pricing: HashMap<String, ModelPricing>,
+    
+    /// Limit on concurrent upstream requests (unlimited when absent)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub concurrency: Option<ConcurrencyConfig>,
+    
+    /// End-to-end request timeouts (none when absent)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeouts: Option<TimeoutConfig>,
+    
+    /// Keep-alives of idle streams (default: a `ping` event every 15 seconds)
+    #[serde(rename = "keepAlive", default)]
+    pub keep_alive: KeepAliveConfig,
+    
+    /// Inbound headers forwarded to the upstream, by name or by prefix
+    /// ending in `*`, e.g. `x-stainless-*` (none by default)
+    #[serde(rename = "forwardHeaders", default, skip_serializing_if = "Vec::is_empty")]
+    pub forward_headers: Vec<String>,
+    
+    /// Retry policy for providers without their own `retry` option (no
+    /// retries when absent)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry: Option<RetryConfig>,
+    
+    /// Convert and route requests without calling the upstream, answering
+    /// with the request that would be sent (default: false)
+    #[serde(rename = "dryRun", default)]
+    pub dry_run: bool,
+    
+    /// Reject requests the Anthropic API would reject (temperature above 1,
+    /// roles that don't alternate, orphan tool results, too many or too
+    /// large images) before they reach the upstream (default: false)
+    #[serde(rename = "strictValidation", default)]
+    pub strict_validation: bool,
+    
+    /// Handling of tool calls without a result and tool results without a
+    /// call, before conversion (default: "off")
+    #[serde(rename = "toolPairing", default)]
+    pub tool_pairing: ToolPairing,
+    
+    /// Share one upstream call between identical non-streaming requests of a
+    /// client key that are in flight at the same time (default: false)
+    #[serde(rename = "coalesceRequests", default)]
+    pub coalesce_requests: bool,
+    
+    /// Send all requests of a Claude Code session for a weighted mapping to
+    /// the same target, for upstream prompt caching (default: false)
+    #[serde(rename = "stickySessions", default)]
+    pub sticky_sessions: bool,
+    
+    /// Cache of Gemini thought signatures (default: 1000 entries in memory)
+    #[serde(rename = "thoughtCache", default)]
+    pub thought_cache: ThoughtCacheConfig,
+    
+    /// One JSON line per completed request, written to a rotating file
+    /// (disabled when absent)
+    #[serde(rename = "requestLog", skip_serializing_if = "Option::is_none")]
+    pub request_log: Option<RequestLogConfig>,
+    
+    /// URLs the summary of each completed request is POSTed to
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub webhooks: Vec<WebhookConfig>,
+    
+    /// Mirror a share of requests to a candidate upstream and log both
+    /// responses (disabled when absent)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shadow: Option<ShadowConfig>,
+    
+    /// Warn about slow, short or truncated responses (disabled when absent)
+    #[serde(rename = "anomalyLog", skip_serializing_if = "Option::is_none")]
+    pub anomaly_log: Option<AnomalyLogConfig>,
+    
+    /// Report panics, unparsable upstream responses and bursts of provider
+    /// errors, to Sentry with the `sentry` feature (disabled when absent)
+    #[serde(rename = "errorReports", skip_serializing_if = "Option::is_none")]
+    pub error_reports: Option<ErrorReportsConfig>,
+    
+    /// SQLite database every request's usage is recorded in, restored on
+    /// startup (requires the `sqlite` feature)
+    #[serde(rename = "usageStore", skip_serializing_if = "Option::is_none")]
+    pub usage_store: Option<UsageStoreConfig>,
+    
+    /// Append-only log of configuration changes (disabled when absent)
+    #[serde(rename = "auditLog", skip_serializing_if = "Option::is_none")]
+    pub audit_log: Option<AuditLogConfig>,
+    
+    /// Full conversations of Claude Code sessions, for debugging and review
+    /// (disabled when absent)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transcripts: Option<TranscriptsConfig>,
+    
+    /// Store shared by proxy replicas for session state and thought
+    /// signatures (process memory when absent)
+    #[serde(rename = "stateBackend", skip_serializing_if = "Option::is_none")]
+    pub state_backend: Option<StateBackendConfig>,
+    
+    /// Files the configuration was read from, the loaded file first and then
+    /// its includes (filled in by `load`)
+    #[serde(skip)]
+    pub source_files: Vec<PathBuf>,
 }
 
-fn default_true() -> bool {
-    true
+/// Log of completed requests in JSON Lines
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RequestLogConfig {
+    /// File written to; rotated files get the suffixes `.1`, `.2`, ...
+    pub path: PathBuf,
+    
+    /// Size at which the file is rotated (default: 104857600)
+    #[serde(rename = "maxFileBytes", default = "default_request_log_max_file_bytes")]
+    pub max_file_bytes: u64,
+    
+    /// Rotated files kept besides the current one (default: 5)
+    #[serde(rename = "maxFiles", default = "default_request_log_max_files")]
+    pub max_files: usize,
+    
+    /// Longest request and response body logged; longer bodies are
+    /// truncated (default: 0, bodies are not logged)
+    #[serde(rename = "maxBodyBytes", default)]
+    pub max_body_bytes: usize,
 }
 
-impl AppConfig {
-    /// Load configuration from JSON file
-    pub fn load(path: &Path) -> Result<Self> {
-        info!("Loading configuration from: {:?}", path);
-        
-        let content = std::fs::read_to_string(path)
-            .with_context(|| format!("Failed to read config file: {:?}", path))?;
-        
-        let config: AppConfig = serde_json::from_str(&content)
-            .with_context(|| "Failed to parse config JSON")?;
-        
-        config.validate()?;
-        
-        debug!("Loaded {} providers", config.providers.len());
-        Ok(config)
-    }
+fn default_request_log_max_file_bytes() -> u64 {
+    100 * 1024 * 1024
+}
+
+fn default_request_log_max_files() -> usize {
+    5
+}
+
+/// Mirroring of a share of requests to a candidate upstream
+///
+/// ```json
+/// {"target": "candidate/glm-4.6", "percent": 10, "path": "/var/log/aiapiproxy/shadow.jsonl"}
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ShadowConfig {
+    /// Provider/model path requests are mirrored to
+    pub target: String,
     
-    /// Load configuration from default locations
-    /// Searches in order:
-    /// 1. ~/.config/aiapiproxy/aiapiproxy.json
-    /// 2. ./aiapiproxy.json
-    /// 
-    /// Returns error if no configuration file is found.
-    pub fn load_default() -> Result<Self> {
-        // Try home config directory first
-        if let Some(home) = dirs::home_dir() {
-            let config_path = home.join(".config").join("aiapiproxy").join("aiapiproxy.json");
-            if config_path.exists() {
-                return Self::load(&config_path);
-            }
-        }
-        
-        // Try current directory
-        let local_path = Path::new("aiapiproxy.json");
-        if local_path.exists() {
-            return Self::load(local_path);
+    /// Share of the requests mirrored, in percent
+    pub percent: f64,
+    
+    /// Claude models whose requests are mirrored (default: all)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub models: Vec<String>,
+    
+    /// File the comparisons are written to, rotated like the request log
+    pub path: PathBuf,
+    
+    /// Size at which the file is rotated (default: 104857600)
+    #[serde(rename = "maxFileBytes", default = "default_request_log_max_file_bytes")]
+    pub max_file_bytes: u64,
+    
+    /// Rotated files kept besides the current one (default: 5)
+    #[serde(rename = "maxFiles", default = "default_request_log_max_files")]
+    pub max_files: usize,
+    
+    /// Longest response text logged; longer texts are truncated
+    /// (default: 16384)
+    #[serde(rename = "maxTextBytes", default = "default_shadow_max_text_bytes")]
+    pub max_text_bytes: usize,
+}
+
+fn default_shadow_max_text_bytes() -> usize {
+    16 * 1024
+}
+
+impl ShadowConfig {
+    /// Rotation of the comparison log
+    pub fn log_config(&self) -> RequestLogConfig {
+        RequestLogConfig {
+            path: self.path.clone(),
+            max_file_bytes: self.max_file_bytes,
+            max_files: self.max_files,
+            max_body_bytes: 0,
         }
-        
-        anyhow::bail!(
-            "Configuration file not found. Please create one at:\n\
-             - ~/.config/aiapiproxy/aiapiproxy.json (recommended)\n\
-             - ./aiapiproxy.json (current directory)\n\
-             \n\
-             See aiapiproxy.example.json for reference."
-        )
     }
+}
+
+/// Webhook called after each request
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WebhookConfig {
+    /// http(s) URL the request summary is POSTed to
+    pub url: String,
     
-    /// Validate configuration
-    fn validate(&self) -> Result<()> {
-        if self.providers.is_empty() {
-            anyhow::bail!("At least one provider must be configured");
-        }
-        
-        for (name, provider) in &self.providers {
-            // Validate provider type
-            let valid_types = ["openai", "modelhub", "anthropic", "ark"];
-            if !valid_types.contains(&provider.provider_type.as_str()) {
+    /// Extra headers, e.g. for authentication
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub headers: HashMap<String, String>,
+    
+    /// Timeout of each call in milliseconds (default: 5000)
+    #[serde(rename = "timeoutMs", default = "default_webhook_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+fn default_webhook_timeout_ms() -> u64 {
+    5000
+}
+
+/// Thresholds of requests logged as anomalies
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AnomalyLogConfig {
+    /// Requests (or whole streams) taking at least this long are logged
+    /// (default: 60000)
+    #[serde(rename = "slowRequestMs", default = "default_slow_request_ms")]
+    pub slow_request_ms: u64,
+    
+    /// Completed responses with fewer output tokens are logged (default: 1)
+    #[serde(rename = "minOutputTokens", default = "default_min_output_tokens")]
+    pub min_output_tokens: u32,
+    
+    /// Log responses that stopped at `max_tokens` (default: true)
+    #[serde(rename = "logLengthStops", default = "default_true")]
+    pub log_length_stops: bool,
+}
+
+fn default_slow_request_ms() -> u64 {
+    60_000
+}
+
+fn default_min_output_tokens() -> u32 {
+    1
+}
+
+/// Error reporting
+///
+/// ```json
+/// {"sentryDsn": "https://key@o1.ingest.sentry.io/2", "environment": "production", "burstErrors": 10}
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ErrorReportsConfig {
+    /// Sentry project the reports are sent to (requires the `sentry`
+    /// feature); without it, reports go to a sink set by the embedding
+    /// application
+    #[serde(rename = "sentryDsn", skip_serializing_if = "Option::is_none")]
+    pub sentry_dsn: Option<String>,
+    
+    /// Environment the reports are tagged with, e.g. "production"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub environment: Option<String>,
+    
+    /// Bytes of a raw upstream payload attached to a report; 0 attaches
+    /// none (default: 4096)
+    #[serde(rename = "maxPayloadBytes", default = "default_error_report_payload_bytes")]
+    pub max_payload_bytes: usize,
+    
+    /// Provider errors of one provider/model path within `burstWindowSecs`
+    /// that are reported as a burst (default: 10)
+    #[serde(rename = "burstErrors", default = "default_burst_errors")]
+    pub burst_errors: u32,
+    
+    /// Window in which provider errors are counted (default: 60)
+    #[serde(rename = "burstWindowSecs", default = "default_burst_window_secs")]
+    pub burst_window_secs: u64,
+}
+
+fn default_error_report_payload_bytes() -> usize {
+    4096
+}
+
+fn default_burst_errors() -> u32 {
+    10
+}
+
+fn default_burst_window_secs() -> u64 {
+    60
+}
+
+impl Default for ErrorReportsConfig {
+    fn default() -> Self {
+        Self {
+            sentry_dsn: None,
+            environment: None,
+            max_payload_bytes: default_error_report_payload_bytes(),
+            burst_errors: default_burst_errors(),
+            burst_window_secs: default_burst_window_secs(),
+        }
+    }
+}
+
+/// SQLite usage accounting
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UsageStoreConfig {
+    /// Database file, created if missing
+    pub path: PathBuf,
+}
+
+/// Store of session transcripts: a directory or a SQLite database
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TranscriptsConfig {
+    /// Directory with one JSON Lines file per session, created if missing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub directory: Option<PathBuf>,
+    
+    /// SQLite database file, created if missing (requires the `sqlite` feature)
+    #[serde(rename = "sqlitePath", skip_serializing_if = "Option::is_none")]
+    pub sqlite_path: Option<PathBuf>,
+}
+
+/// Audit log of configuration changes in JSON Lines
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditLogConfig {
+    /// File appended to
+    pub path: PathBuf,
+    
+    /// Entries kept in memory for `GET /admin/audit` (default: 100)
+    #[serde(rename = "recentEntries", default = "default_audit_recent_entries")]
+    pub recent_entries: usize,
+}
+
+fn default_audit_recent_entries() -> usize {
+    100
+}
+
+/// Backend for state shared by proxy replicas
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StateBackendConfig {
+    /// "memory" or "redis" (requires the `redis` feature)
+    #[serde(rename = "type")]
+    pub backend_type: String,
+    
+    /// Connection URL, e.g. "redis://redis:6379/0"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    
+    /// Prefix of all keys, to share one database between deployments (default: "aiapiproxy:")
+    #[serde(rename = "keyPrefix", default = "default_state_key_prefix")]
+    pub key_prefix: String,
+    
+    /// Timeout of connecting and of each command, in milliseconds (default: 500)
+    #[serde(rename = "timeoutMs", default = "default_state_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+fn default_state_key_prefix() -> String {
+    "aiapiproxy:".to_string()
+}
+
+fn default_state_timeout_ms() -> u64 {
+    500
+}
+
+/// Cache of thought signatures by tool call ID
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThoughtCacheConfig {
+    /// Most signatures kept; the least recently used are evicted (default: 1000)
+    #[serde(default = "default_thought_cache_capacity")]
+    pub capacity: usize,
+    
+    /// Drop signatures cached longer ago than this (default: 86400)
+    #[serde(rename = "ttlSecs", default = "default_thought_cache_ttl")]
+    pub ttl_secs: u64,
+    
+    /// Save signatures to this JSON file and load them at startup (default: memory only)
+    #[serde(rename = "persistPath", skip_serializing_if = "Option::is_none")]
+    pub persist_path: Option<PathBuf>,
+    
+    /// How often changes are saved to `persistPath` (default: 30)
+    #[serde(rename = "persistIntervalSecs", default = "default_thought_cache_persist_interval")]
+    pub persist_interval_secs: u64,
+}
+
+fn default_thought_cache_capacity() -> usize {
+    1000
+}
+
+fn default_thought_cache_ttl() -> u64 {
+    86400
+}
+
+fn default_thought_cache_persist_interval() -> u64 {
+    30
+}
+
+impl Default for ThoughtCacheConfig {
+    fn default() -> Self {
+        Self {
+            capacity: default_thought_cache_capacity(),
+            ttl_secs: default_thought_cache_ttl(),
+            persist_path: None,
+            persist_interval_secs: default_thought_cache_persist_interval(),
+        }
+    }
+}
+
+/// Price of an upstream model in USD per million tokens
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct ModelPricing {
+    /// Price of a million input tokens
+    #[serde(rename = "inputPerMTok")]
+    pub input_per_mtok: f64,
+    
+    /// Price of a million output tokens
+    #[serde(rename = "outputPerMTok")]
+    pub output_per_mtok: f64,
+    
+    /// Price of a million input tokens read from the prompt cache
+    /// (default: the input price)
+    #[serde(rename = "cachedInputPerMTok", default, skip_serializing_if = "Option::is_none")]
+    pub cached_input_per_mtok: Option<f64>,
+}
+
+impl ModelPricing {
+    /// Estimated cost of a request in USD; `input_tokens` excludes the
+    /// `cached_tokens` read from the prompt cache
+    pub fn cost(&self, input_tokens: u32, cached_tokens: u32, output_tokens: u32) -> f64 {
+        let cached_price = self.cached_input_per_mtok.unwrap_or(self.input_per_mtok);
+        (f64::from(input_tokens) * self.input_per_mtok
+            + f64::from(cached_tokens) * cached_price
+            + f64::from(output_tokens) * self.output_per_mtok) / 1_000_000.0
+    }
+}
+
+/// Client authentication with proxy-issued API keys
+///
+/// Keys are stored as SHA-256 hashes, so the config file never holds a
+/// usable key. Clients send the key in `x-api-key` or as a Bearer token.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuthConfig {
+    /// Accepted API keys
+    pub keys: Vec<ApiKeyConfig>,
+    
+    /// Budgets shared by all keys of a tenant, by tenant name
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub tenants: HashMap<String, Budget>,
+}
+
+/// Spending limits in USD, estimated with the `pricing` table
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Budget {
+    /// Spend allowed per day (UTC)
+    #[serde(rename = "dailyBudget", skip_serializing_if = "Option::is_none")]
+    pub daily: Option<f64>,
+    
+    /// Spend allowed per calendar month (UTC)
+    #[serde(rename = "monthlyBudget", skip_serializing_if = "Option::is_none")]
+    pub monthly: Option<f64>,
+}
+
+impl Budget {
+    fn validate(&self, owner: &str) -> Result<()> {
+        for (name, limit) in [("dailyBudget", self.daily), ("monthlyBudget", self.monthly)] {
+            if limit.is_some_and(|limit| limit.is_nan() || limit < 0.0) {
+                anyhow::bail!("Invalid {} for {}: must not be negative", name, owner);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Proxy-issued API key
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiKeyConfig {
+    /// Name of the key's holder, used in logs
+    pub name: String,
+    
+    /// Hex-encoded SHA-256 hash of the key
+    #[serde(rename = "keyHash")]
+    pub key_hash: String,
+    
+    /// Tenant the key belongs to, tagged on its usage (default: the key name)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    
+    /// Priority of the key's requests when they have to queue (default: interactive)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub priority: Option<Priority>,
+    
+    /// Whether the key may use the admin API under `/admin` (default: false)
+    #[serde(default)]
+    pub admin: bool,
+    
+    /// Limits of requests made with the key
+    #[serde(flatten)]
+    pub limits: KeyLimits,
+}
+
+/// Limits of a client API key
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct KeyLimits {
+    /// Claude models the key may request; a trailing `*` matches any suffix
+    /// (default: all)
+    #[serde(rename = "allowedModels", default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_models: Vec<String>,
+    
+    /// Largest `max_tokens` a request may ask for
+    #[serde(rename = "maxTokens", skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    
+    /// Requests allowed per minute
+    #[serde(rename = "requestsPerMinute", skip_serializing_if = "Option::is_none")]
+    pub requests_per_minute: Option<u32>,
+    
+    /// Input and output tokens allowed per day (UTC)
+    #[serde(rename = "tokensPerDay", skip_serializing_if = "Option::is_none")]
+    pub tokens_per_day: Option<u64>,
+    
+    /// Spending limits of the key
+    #[serde(flatten)]
+    pub budget: Budget,
+}
+
+/// Target of a `modelMapping` entry
+///
+/// A single provider/model path, an ordered failover chain (when an upstream
+/// fails with a retryable error the next path of the chain is tried), or a
+/// weighted split of requests across equivalent upstream models:
+///
+/// ```json
+/// "claude-sonnet-4": ["ark/glm-4.6", "modelhub-sg1/gpt-5", "openai/gpt-4o"],
+/// "claude-haiku-4": [{"target": "ark/glm-4.6", "weight": 70}, {"target": "openai/gpt-4o-mini", "weight": 30}]
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ModelTarget {
+    /// Single provider/model path
+    Path(String),
+    /// Provider/model paths in failover order
+    Chain(Vec<String>),
+    /// Provider/model paths sharing the requests by weight
+    Weighted(Vec<WeightedTarget>),
+}
+
+/// Provider/model path of a weighted `modelMapping` entry
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WeightedTarget {
+    /// Provider/model path
+    pub target: String,
+    /// Relative share of the requests
+    pub weight: u32,
+}
+
+impl ModelTarget {
+    /// Provider/model paths, in failover order for chains
+    pub fn paths(&self) -> Vec<&str> {
+        match self {
+            Self::Path(path) => vec![path.as_str()],
+            Self::Chain(paths) => paths.iter().map(String::as_str).collect(),
+            Self::Weighted(targets) => targets.iter().map(|t| t.target.as_str()).collect(),
+        }
+    }
+}
+
+/// Route requests whose metadata matches to a provider/model path
+///
+/// ```json
+/// {"metadata": {"team": "search"}, "model": "sonnet", "target": "ark/glm-4.6"}
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoutingRule {
+    /// Metadata values the request must carry (all must match)
+    #[serde(default)]
+    pub metadata: HashMap<String, serde_json::Value>,
+    
+    /// Optional Claude model pattern, matched like `modelMapping` keys
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    
+    /// Target provider/model path
+    pub target: String,
+}
+
+/// A/B experiment splitting the sessions of a Claude model between two
+/// provider/model paths
+///
+/// ```json
+/// {"name": "glm-rollout", "model": "claude-sonnet-4", "control": "openai/gpt-4o", "treatment": "ark/glm-4.6", "treatmentPercent": 20}
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExperimentConfig {
+    /// Name the arms are reported under
+    pub name: String,
+    
+    /// Claude model pattern, matched like `modelMapping` keys
+    pub model: String,
+    
+    /// Provider/model path of the control arm
+    pub control: String,
+    
+    /// Provider/model path of the treatment arm
+    pub treatment: String,
+    
+    /// Share of the sessions in the treatment arm, in percent (default: 50)
+    #[serde(rename = "treatmentPercent", default = "default_treatment_percent")]
+    pub treatment_percent: f64,
+}
+
+fn default_treatment_percent() -> f64 {
+    50.0
+}
+
+impl ExperimentConfig {
+    /// Whether requests for a Claude model take part in this experiment
+    pub fn matches(&self, model: &str) -> bool {
+        model_matches_pattern(model, &self.model)
+    }
+}
+
+impl RoutingRule {
+    /// Whether a request for `model` with `metadata` matches this rule
+    pub fn matches(&self, model: &str, metadata: Option<&HashMap<String, serde_json::Value>>) -> bool {
+        if let Some(pattern) = &self.model {
+            if !model_matches_pattern(model, pattern) {
+                return false;
+            }
+        }
+        
+        self.metadata.iter().all(|(key, expected)| {
+            metadata
+                .and_then(|m| m.get(key))
+                .is_some_and(|value| value == expected)
+        })
+    }
+}
+
+/// Rewrite of the system prompt of requests for matching Claude models
+///
+/// ```json
+/// {"model": "haiku", "stripBoilerplate": true, "prepend": "Answer concisely."}
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PromptRule {
+    /// Optional Claude model pattern, matched like `modelMapping` keys
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    
+    /// Text put before the system prompt
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prepend: Option<String>,
+    
+    /// Text put after the system prompt
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub append: Option<String>,
+    
+    /// Remove Claude Code's Anthropic-specific identity and model lines
+    #[serde(rename = "stripBoilerplate", default)]
+    pub strip_boilerplate: bool,
+    
+    /// Regex replacements, applied in order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub replace: Vec<PromptReplacement>,
+}
+
+/// Regex replacement in the system prompt
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PromptReplacement {
+    /// Regular expression to find
+    pub pattern: String,
+    
+    /// Replacement; `$1` or `${name}` insert capture groups
+    #[serde(default)]
+    pub with: String,
+}
+
+impl PromptRule {
+    /// Whether the rule applies to requests for a Claude model
+    pub fn matches(&self, model: &str) -> bool {
+        self.model.as_deref().is_none_or(|pattern| model_matches_pattern(model, pattern))
+    }
+}
+
+/// Redaction of outgoing message content
+///
+/// ```json
+/// {"builtins": ["email", "secret"], "patterns": [{"name": "employee_id", "pattern": "\\bEMP-\\d{6}\\b"}], "restore": true}
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RedactionConfig {
+    /// Built-in detectors: "email", "phone" and "secret" (default: all)
+    #[serde(default = "default_redaction_builtins")]
+    pub builtins: Vec<String>,
+    
+    /// Additional patterns, checked before the built-in detectors
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub patterns: Vec<RedactionPattern>,
+    
+    /// Put the original values back where responses repeat a placeholder
+    /// (default: false)
+    #[serde(default)]
+    pub restore: bool,
+}
+
+fn default_redaction_builtins() -> Vec<String> {
+    ["email", "phone", "secret"].iter().map(|builtin| builtin.to_string()).collect()
+}
+
+impl Default for RedactionConfig {
+    fn default() -> Self {
+        Self { builtins: default_redaction_builtins(), patterns: Vec::new(), restore: false }
+    }
+}
+
+/// Custom redaction pattern
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RedactionPattern {
+    /// Placeholder label, e.g. "employee_id" for `[REDACTED_EMPLOYEE_ID_1]`
+    pub name: String,
+    
+    /// Regular expression matching the values to replace
+    pub pattern: String,
+}
+
+impl RedactionConfig {
+    fn validate(&self) -> Result<()> {
+        let valid_builtins = default_redaction_builtins();
+        for builtin in &self.builtins {
+            if !valid_builtins.contains(builtin) {
+                anyhow::bail!("Invalid redaction builtin '{}'. Valid values: {:?}", builtin, valid_builtins);
+            }
+        }
+        for pattern in &self.patterns {
+            if pattern.name.is_empty() || !pattern.name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                anyhow::bail!("Invalid redaction pattern name '{}': use letters, digits and underscores", pattern.name);
+            }
+            if let Err(e) = regex::Regex::new(&pattern.pattern) {
+                anyhow::bail!("Invalid redaction pattern '{}': {}", pattern.name, e);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Content guardrails for prompts and completions
+///
+/// ```json
+/// {"rules": [
+///   {"name": "credentials", "pattern": "(?i)password\\s*[:=]\\s*\\S+", "action": "redact"},
+///   {"name": "competitors", "keywords": ["Acme Corp"], "action": "annotate", "scope": "completion"},
+///   {"name": "weapons", "denyTopic": {"terms": ["explosive", "detonator", "blast radius"], "minMatches": 2}}
+/// ]}
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GuardrailsConfig {
+    /// Rules, checked in order
+    #[serde(default)]
+    pub rules: Vec<GuardrailRule>,
+    
+    /// Text of the response to a blocked prompt or completion
+    #[serde(default = "default_guardrail_message")]
+    pub message: String,
+}
+
+fn default_guardrail_message() -> String {
+    "This content was blocked by a content filter.".to_string()
+}
+
+impl Default for GuardrailsConfig {
+    fn default() -> Self {
+        Self { rules: Vec::new(), message: default_guardrail_message() }
+    }
+}
+
+/// Guardrail rule: a pattern, keywords or a deny topic
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GuardrailRule {
+    /// Name reported when the rule matches
+    pub name: String,
+    
+    /// Regular expression
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<String>,
+    
+    /// Words or phrases, matched as whole words ignoring case
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keywords: Vec<String>,
+    
+    /// Topic recognized by its terms
+    #[serde(rename = "denyTopic", default, skip_serializing_if = "Option::is_none")]
+    pub deny_topic: Option<DenyTopic>,
+    
+    /// What happens on a match (default: "block")
+    #[serde(default)]
+    pub action: GuardrailAction,
+    
+    /// Text the rule checks (default: "both")
+    #[serde(default)]
+    pub scope: GuardrailScope,
+    
+    /// Text replacing the matches of a redact rule (default: "[FILTERED]")
+    #[serde(default = "default_guardrail_replacement")]
+    pub replacement: String,
+}
+
+fn default_guardrail_replacement() -> String {
+    "[FILTERED]".to_string()
+}
+
+/// Topic a deny topic rule recognizes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DenyTopic {
+    /// Words or phrases of the topic, matched as whole words ignoring case
+    pub terms: Vec<String>,
+    
+    /// Distinct terms a text needs to contain (default: 1)
+    #[serde(rename = "minMatches", default = "default_min_matches")]
+    pub min_matches: usize,
+}
+
+fn default_min_matches() -> usize {
+    1
+}
+
+/// What happens when a guardrail rule matches
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GuardrailAction {
+    /// Don't send the text; respond with `stop_reason: "content_filter"`
+    #[default]
+    Block,
+    /// Replace the matches
+    Redact,
+    /// Only report the match
+    Annotate,
+}
+
+/// Text a guardrail rule checks
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GuardrailScope {
+    /// Inbound prompts
+    Prompt,
+    /// Responses
+    Completion,
+    #[default]
+    Both,
+}
+
+impl GuardrailsConfig {
+    fn validate(&self) -> Result<()> {
+        let mut names = HashSet::new();
+        for rule in &self.rules {
+            if rule.name.is_empty() {
+                anyhow::bail!("Guardrail rule names must not be empty");
+            }
+            if !names.insert(rule.name.as_str()) {
+                anyhow::bail!("Duplicate guardrail rule '{}'", rule.name);
+            }
+            let kinds = usize::from(rule.pattern.is_some()) + usize::from(!rule.keywords.is_empty()) + usize::from(rule.deny_topic.is_some());
+            if kinds != 1 {
+                anyhow::bail!("Guardrail rule '{}' needs exactly one of pattern, keywords and denyTopic", rule.name);
+            }
+            if let Some(pattern) = &rule.pattern {
+                if let Err(e) = regex::Regex::new(pattern) {
+                    anyhow::bail!("Invalid guardrail pattern '{}': {}", rule.name, e);
+                }
+            }
+            if let Some(topic) = &rule.deny_topic {
+                if topic.min_matches == 0 || topic.min_matches > topic.terms.len() {
+                    anyhow::bail!(
+                        "Invalid minMatches {} for guardrail rule '{}': must be between 1 and the number of terms",
+                        topic.min_matches, rule.name
+                    );
+                }
+            }
+            let terms = rule.keywords.iter().chain(rule.deny_topic.iter().flat_map(|topic| &topic.terms));
+            if terms.clone().any(|term| term.trim().is_empty()) {
+                anyhow::bail!("Guardrail rule '{}' has an empty term", rule.name);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Payloads in debug logs
+///
+/// ```json
+/// {"mode": "full", "redactContent": true, "maskSecrets": true}
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PayloadLoggingConfig {
+    /// How much of each payload is logged (default: "summary")
+    #[serde(default)]
+    pub mode: PayloadLogMode,
+    
+    /// Replace message text, tool inputs and tool results with their length
+    /// (default: false)
+    #[serde(rename = "redactContent", default)]
+    pub redact_content: bool,
+    
+    /// Mask API keys, tokens and private keys (default: true)
+    #[serde(rename = "maskSecrets", default = "default_true")]
+    pub mask_secrets: bool,
+}
+
+impl Default for PayloadLoggingConfig {
+    fn default() -> Self {
+        Self { mode: PayloadLogMode::default(), redact_content: false, mask_secrets: true }
+    }
+}
+
+/// How much of a payload is logged
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PayloadLogMode {
+    /// Payloads aren't logged
+    Off,
+    /// Message previews, with tools and long text cut
+    #[default]
+    Summary,
+    /// Complete payloads
+    Full,
+}
+
+/// Match a Claude model name against a mapping pattern (exact or substring, case-insensitive)
+fn model_matches_pattern(model: &str, pattern: &str) -> bool {
+    let model_lower = model.to_lowercase();
+    let pattern_lower = pattern.to_lowercase();
+    model_lower.contains(&pattern_lower) || pattern_lower.contains(&model_lower)
+}
+
+/// Handling of multiple choices
+///
+/// Claude responses carry a single message, so `n > 1` has no direct equivalent.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MultipleChoicesMode {
+    /// Reject requests with `n > 1`; unexpected extra choices from upstream are dropped
+    #[default]
+    Reject,
+    /// Forward `n` upstream and return the first choice, with the other choices
+    /// exposed in the response `metadata` (non-streaming only)
+    Metadata,
+}
+
+/// Provider configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProviderConfig {
+    /// Provider type (e.g., "openai", "modelhub")
+    #[serde(rename = "type")]
+    pub provider_type: String,
+    
+    /// Base URL for the provider API
+    #[serde(rename = "baseUrl")]
+    pub base_url: String,
+    
+    /// API key (can be empty if using env var); `keychain:<service>/<account>`
+    /// reads it from the OS keychain when the configuration is loaded
+    #[serde(rename = "apiKey", default)]
+    pub api_key: String,
+    
+    /// File the API key is read from when the configuration is loaded, e.g.
+    /// a mounted secret like "/run/secrets/openai"
+    #[serde(rename = "apiKeyFile", skip_serializing_if = "Option::is_none")]
+    pub api_key_file: Option<PathBuf>,
+    
+    /// Whether requests are sent to this provider; `false` takes all its
+    /// models out of service without removing them (default: true)
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    
+    /// Provider-specific options
+    #[serde(default)]
+    pub options: ProviderOptions,
+    
+    /// Model configurations for this provider
+    pub models: HashMap<String, ModelConfig>,
+}
+
+/// Provider-specific options
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ProviderOptions {
+    /// API key parameter name (for URL query parameter auth)
+    #[serde(rename = "apiKeyParam", skip_serializing_if = "Option::is_none")]
+    pub api_key_param: Option<String>,
+    
+    /// Mode for modelhub provider ("responses" or "gemini")
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mode: Option<String>,
+    
+    /// Custom headers to add to requests
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub headers: HashMap<String, String>,
+    
+    /// Retry transient failures on this provider (no retries when absent)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry: Option<RetryConfig>,
+    
+    /// Send requests to this provider through an HTTP proxy, e.g.
+    /// `http://proxy.corp:3128` (default: `HTTP_PROXY`/`HTTPS_PROXY` env vars)
+    #[serde(rename = "httpProxy", skip_serializing_if = "Option::is_none")]
+    pub http_proxy: Option<String>,
+    
+    /// Hosts reached directly despite `httpProxy`, in `NO_PROXY` syntax
+    #[serde(rename = "noProxy", default, skip_serializing_if = "Vec::is_empty")]
+    pub no_proxy: Vec<String>,
+    
+    /// TLS settings for upstreams with a private PKI (default: system roots)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls: Option<UpstreamTlsConfig>,
+    
+    /// Connection pool and keep-alive tuning (default: reqwest's pool settings)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pool: Option<PoolConfig>,
+    
+    /// Requests and tokens per minute the upstream allows; requests are
+    /// held back to stay under them (unlimited when absent)
+    #[serde(rename = "rateLimit", skip_serializing_if = "Option::is_none")]
+    pub rate_limit: Option<RateLimitConfig>,
+    
+    /// Timeout of regular requests to this provider (default: 30)
+    #[serde(rename = "timeoutSecs", skip_serializing_if = "Option::is_none")]
+    pub timeout_secs: Option<u64>,
+    
+    /// Timeout of streaming requests to this provider (default: 300)
+    #[serde(rename = "streamTimeoutSecs", skip_serializing_if = "Option::is_none")]
+    pub stream_timeout_secs: Option<u64>,
+    
+    /// Handling of tool calls without a result in Responses API requests
+    /// (default: "drop")
+    #[serde(rename = "orphanToolCalls", default)]
+    pub orphan_tool_calls: OrphanToolCalls,
+    
+    /// Hosts remote images may be downloaded from for ModelHub Gemini mode,
+    /// whatever they resolve to (default: any host with a public address)
+    #[serde(rename = "imageFetchHosts", default, skip_serializing_if = "Vec::is_empty")]
+    pub image_fetch_hosts: Vec<String>,
+}
+
+/// Per-minute limits of an upstream
+///
+/// Tokens are counted as upstreams do: the estimated prompt tokens plus
+/// `max_tokens`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RateLimitConfig {
+    /// Requests per minute (default: unlimited)
+    #[serde(rename = "requestsPerMinute", skip_serializing_if = "Option::is_none")]
+    pub requests_per_minute: Option<u32>,
+    
+    /// Tokens per minute (default: unlimited)
+    #[serde(rename = "tokensPerMinute", skip_serializing_if = "Option::is_none")]
+    pub tokens_per_minute: Option<u32>,
+    
+    /// Longest a request is held back before failing with a rate limit
+    /// error, in milliseconds (default: 30000)
+    #[serde(rename = "maxWaitMs", default = "default_rate_limit_max_wait_ms")]
+    pub max_wait_ms: u64,
+}
+
+fn default_rate_limit_max_wait_ms() -> u64 {
+    30000
+}
+
+/// Handling of tool calls without a matching result
+///
+/// Claude Code sends them when the user interrupted a tool. Some Responses
+/// API upstreams reject a `function_call` without its `function_call_output`,
+/// others accept it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OrphanToolCalls {
+    /// Leave the call out of the request
+    #[default]
+    Drop,
+    /// Answer the call with an empty output, keeping it in the context
+    SynthesizeEmptyOutput,
+    /// Reject the request with an `invalid_request_error`
+    Error,
+}
+
+/// Handling of `tool_use` and `tool_result` blocks that don't pair up
+///
+/// Claude Code sends a tool call without its result when the user interrupts
+/// a tool; OpenAI-style upstreams reject the request. Pairing runs before
+/// conversion for every provider, so [`OrphanToolCalls`] only handles the
+/// calls it leaves in place.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ToolPairing {
+    /// Answer interrupted calls with an error result, turn results without a
+    /// call into text and drop repeated results
+    Repair,
+    /// Leave interrupted calls, results without a call and repeated results out
+    Drop,
+    /// Reject the request with an `invalid_request_error`
+    Reject,
+    /// Send the messages as they are
+    #[default]
+    Off,
+}
+
+/// TLS settings for connections to one provider
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct UpstreamTlsConfig {
+    /// PEM files with root certificates trusted in addition to the system roots
+    #[serde(rename = "caCertPaths", default, skip_serializing_if = "Vec::is_empty")]
+    pub ca_cert_paths: Vec<PathBuf>,
+    
+    /// Accept any server certificate, including self-signed and expired ones;
+    /// only for testing (default: false)
+    #[serde(rename = "insecureSkipVerify", default)]
+    pub insecure_skip_verify: bool,
+    
+    /// Oldest TLS version accepted: "1.0", "1.1" or "1.2" (default: "1.0")
+    #[serde(rename = "minVersion", skip_serializing_if = "Option::is_none")]
+    pub min_version: Option<String>,
+}
+
+/// Connection pool settings for one provider
+///
+/// Bursts of parallel requests reuse idle connections instead of opening new
+/// ones; keep-alive pings stop idle connections from being dropped silently
+/// by the upstream or a NAT in between.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct PoolConfig {
+    /// Idle connections kept open per host (default: unlimited)
+    #[serde(rename = "maxIdlePerHost", skip_serializing_if = "Option::is_none")]
+    pub max_idle_per_host: Option<usize>,
+    
+    /// Close connections idle for longer than this (default: 90)
+    #[serde(rename = "idleTimeoutSecs", skip_serializing_if = "Option::is_none")]
+    pub idle_timeout_secs: Option<u64>,
+    
+    /// Send HTTP/2 keep-alive pings at this interval, also while idle
+    /// (default: no pings)
+    #[serde(rename = "http2KeepAliveIntervalSecs", skip_serializing_if = "Option::is_none")]
+    pub http2_keep_alive_interval_secs: Option<u64>,
+    
+    /// Close the connection when a keep-alive ping is not acknowledged within
+    /// this time (default: 20)
+    #[serde(rename = "http2KeepAliveTimeoutSecs", skip_serializing_if = "Option::is_none")]
+    pub http2_keep_alive_timeout_secs: Option<u64>,
+    
+    /// Enable TCP keepalive probes at this interval (default: off)
+    #[serde(rename = "tcpKeepaliveSecs", skip_serializing_if = "Option::is_none")]
+    pub tcp_keepalive_secs: Option<u64>,
+}
+
+/// Retry policy for requests to one provider
+///
+/// Transient failures are retried on the same upstream with exponential
+/// backoff before a failover chain moves on. A delay requested by the
+/// upstream (`Retry-After`, `x-ratelimit-reset`) replaces the backoff.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RetryConfig {
+    /// Maximum retry attempts (default: 3)
+    #[serde(rename = "maxRetries", default = "default_max_retries")]
+    pub max_retries: u32,
+    
+    /// Base delay time in milliseconds, doubled on every attempt (default: 1000)
+    #[serde(rename = "baseDelayMs", default = "default_base_delay_ms")]
+    pub base_delay_ms: u64,
+    
+    /// Maximum delay time in milliseconds (default: 10000)
+    #[serde(rename = "maxDelayMs", default = "default_max_delay_ms")]
+    pub max_delay_ms: u64,
+    
+    /// Randomize backoff delays between half and the full delay (default: true)
+    #[serde(default = "default_true")]
+    pub jitter: bool,
+    
+    /// HTTP statuses to retry (default: 408, 429 and 5xx)
+    #[serde(rename = "retryStatuses", skip_serializing_if = "Option::is_none")]
+    pub retry_statuses: Option<Vec<u16>>,
+    
+    /// Longest upstream-requested delay to wait for, in milliseconds; longer
+    /// requests fail immediately (default: 60000)
+    #[serde(rename = "maxRetryAfterMs", default = "default_max_retry_after_ms")]
+    pub max_retry_after_ms: u64,
+}
+
+fn default_max_retries() -> u32 {
+    3
+}
+
+fn default_base_delay_ms() -> u64 {
+    1000
+}
+
+fn default_max_delay_ms() -> u64 {
+    10000
+}
+
+fn default_max_retry_after_ms() -> u64 {
+    60000
+}
+
+impl RetryConfig {
+    /// Check the delays and statuses; `owner` names the policy in errors
+    fn validate(&self, owner: &str) -> Result<()> {
+        if self.base_delay_ms > self.max_delay_ms {
+            anyhow::bail!("retry.baseDelayMs ({}) exceeds retry.maxDelayMs ({}) for {}", self.base_delay_ms, self.max_delay_ms, owner);
+        }
+        if let Some(status) = self.retry_statuses.iter().flatten().find(|s| !(400..=599).contains(*s)) {
+            anyhow::bail!("Invalid retry status {} for {}: expected a 4xx or 5xx status", status, owner);
+        }
+        Ok(())
+    }
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_retries: default_max_retries(),
+            base_delay_ms: default_base_delay_ms(),
+            max_delay_ms: default_max_delay_ms(),
+            jitter: true,
+            retry_statuses: None,
+            max_retry_after_ms: default_max_retry_after_ms(),
+        }
+    }
+}
+
+/// Model configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelConfig {
+    /// Model name to use with the upstream provider
+    pub name: String,
+    
+    /// Whether requests are sent to this model (default: true)
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    
+    /// Optional alias for the model (used in request routing)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alias: Option<String>,
+    
+    /// Maximum tokens limit for this model
+    #[serde(rename = "maxTokens", skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    
+    /// Context window size in tokens; prompts estimated to exceed it are rejected
+    #[serde(rename = "contextWindow", skip_serializing_if = "Option::is_none")]
+    pub context_window: Option<u32>,
+    
+    /// Default temperature for this model
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    
+    /// Model-specific options
+    #[serde(default)]
+    pub options: ModelOptions,
+}
+
+/// Model-specific options
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ModelOptions {
+    /// Mode for this model (e.g., "responses", "gemini", "chat")
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mode: Option<String>,
+    
+    /// Whether this model supports streaming
+    #[serde(rename = "supportsStreaming", default = "default_true")]
+    pub supports_streaming: bool,
+    
+    /// Whether this model supports tool use
+    #[serde(rename = "supportsTools", default = "default_true")]
+    pub supports_tools: bool,
+    
+    /// Whether this model supports vision/images
+    #[serde(rename = "supportsVision", default)]
+    pub supports_vision: bool,
+    
+    /// Whether this model supports temperature parameter
+    /// Set to false for reasoning models (o1, o3, etc.) that don't support temperature
+    #[serde(rename = "supportsTemperature", default = "default_true")]
+    pub supports_temperature: bool,
+    
+    /// Whether this model accepts the top_k sampling parameter
+    /// (e.g., Gemini, Together, Ollama); omitted upstream otherwise
+    #[serde(rename = "supportsTopK", default)]
+    pub supports_top_k: bool,
+    
+    /// Whether this model can run the Claude web search server tool natively
+    /// (OpenAI web search, Perplexity, Gemini google_search)
+    #[serde(rename = "supportsWebSearch", default)]
+    pub supports_web_search: bool,
+    
+    /// Whether the upstream accepts a `metadata` object; Claude request
+    /// metadata is forwarded to it (values as strings)
+    #[serde(rename = "supportsMetadata", default)]
+    pub supports_metadata: bool,
+    
+    /// Whether the upstream accepts Claude `cache_control` prompt caching
+    /// markers on content parts; they are dropped otherwise
+    #[serde(rename = "supportsPromptCaching", default)]
+    pub supports_prompt_caching: bool,
+    
+    /// How structured output (a forced single tool) is sent upstream
+    /// "tool" (default) keeps the forced tool call, "json_schema" maps it to
+    /// `response_format: {"type": "json_schema"}` for providers that support it
+    #[serde(rename = "structuredOutput", skip_serializing_if = "Option::is_none")]
+    pub structured_output: Option<String>,
+    
+    /// What to do when a prompt would overflow `contextWindow`
+    /// "reject" (default) returns an invalid_request_error, "truncate" drops
+    /// the oldest turns while keeping system messages and the latest turn
+    #[serde(rename = "contextOverflow", skip_serializing_if = "Option::is_none")]
+    pub context_overflow: Option<String>,
+    
+    /// Reasoning effort for reasoning models: "low", "medium" or "high"
+    /// Sent as `reasoning_effort` (chat completions) or `reasoning.effort`
+    /// (Responses API); a Claude thinking budget in the request overrides it
+    #[serde(rename = "reasoningEffort", skip_serializing_if = "Option::is_none")]
+    pub reasoning_effort: Option<String>,
+    
+    /// Lower bound for `max_tokens`; smaller requests (e.g. Claude Code's
+    /// `max_tokens: 1` probes) are raised to it
+    #[serde(rename = "minOutputTokens", skip_serializing_if = "Option::is_none")]
+    pub min_output_tokens: Option<u32>,
+    
+    /// Upper bound for `max_tokens` (default 100000)
+    #[serde(rename = "capOutputTokens", skip_serializing_if = "Option::is_none")]
+    pub cap_output_tokens: Option<u32>,
+    
+    /// What to do when `max_tokens` exceeds `capOutputTokens`
+    /// "reject" (default) returns an invalid_request_error, "clamp" lowers it to the cap
+    #[serde(rename = "clampStrategy", skip_serializing_if = "Option::is_none")]
+    pub clamp_strategy: Option<String>,
+    
+    /// How tools are sent to `openai` providers
+    /// "tools" (default) uses `tools`/`tool_calls`, "legacy" uses the deprecated
+    /// `functions`/`function_call` fields for servers that predate tools
+    #[serde(rename = "toolsFormat", skip_serializing_if = "Option::is_none")]
+    pub tools_format: Option<String>,
+    
+    /// Image `detail` for Responses API providers: "low", "high" or "auto"
+    /// Overrides the detail of each image part; unset passes it through
+    #[serde(rename = "imageDetail", skip_serializing_if = "Option::is_none")]
+    pub image_detail: Option<String>,
+    
+    /// Largest decoded image (in bytes) sent to Responses API providers
+    /// Larger images are downscaled when built with the `image-resize`
+    /// feature, and replaced by a text note otherwise
+    #[serde(rename = "maxImageBytes", skip_serializing_if = "Option::is_none")]
+    pub max_image_bytes: Option<usize>,
+    
+    /// What a streaming client sees when the upstream stream fails mid-response
+    /// "error" (default) sends an error event, "stop" ends the message with
+    /// `stop_reason: "pause_turn"`, "resume" retries with the streamed text as
+    /// an assistant prefix (for upstreams that continue one)
+    #[serde(rename = "streamRecovery", skip_serializing_if = "Option::is_none")]
+    pub stream_recovery: Option<String>,
+    
+    /// Continuation requests made when the upstream stops a response at its
+    /// output limit before the client's `max_tokens` (default 0, up to 10);
+    /// the parts are stitched into one response (for upstreams that continue
+    /// an assistant prefix)
+    #[serde(rename = "maxContinuations", skip_serializing_if = "Option::is_none")]
+    pub max_continuations: Option<u32>,
+    
+    /// Sampling parameters used when the request leaves them unset
+    #[serde(rename = "requestDefaults", default, skip_serializing_if = "RequestParams::is_empty")]
+    pub request_defaults: RequestParams,
+    
+    /// Sampling parameters that replace the request's own
+    #[serde(rename = "requestOverrides", default, skip_serializing_if = "RequestParams::is_empty")]
+    pub request_overrides: RequestParams,
+    
+    /// Text put before the system prompt of every request
+    #[serde(rename = "systemPromptPrefix", skip_serializing_if = "Option::is_none")]
+    pub system_prompt_prefix: Option<String>,
+    
+    /// Text put after the system prompt of every request
+    #[serde(rename = "systemPromptSuffix", skip_serializing_if = "Option::is_none")]
+    pub system_prompt_suffix: Option<String>,
+}
+
+/// OpenAI sampling parameters set from a model's configuration
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RequestParams {
+    /// Nucleus sampling mass (`top_p`), between 0 and 1
+    #[serde(rename = "topP", skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    
+    /// `presence_penalty`, between -2 and 2
+    #[serde(rename = "presencePenalty", skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f32>,
+    
+    /// `frequency_penalty`, between -2 and 2
+    #[serde(rename = "frequencyPenalty", skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f32>,
+    
+    /// Sampling seed, for upstreams with reproducible outputs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u32>,
+    
+    /// Stop sequences
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Vec<String>>,
+}
+
+impl RequestParams {
+    /// Whether no parameter is set
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+    
+    /// Check value ranges; `owner` names the model in errors
+    fn validate(&self, owner: &str) -> Result<()> {
+        if self.top_p.is_some_and(|top_p| !(0.0..=1.0).contains(&top_p)) {
+            anyhow::bail!("Invalid topP for {}: expected a value between 0 and 1", owner);
+        }
+        for (name, penalty) in [("presencePenalty", self.presence_penalty), ("frequencyPenalty", self.frequency_penalty)] {
+            if penalty.is_some_and(|penalty| !(-2.0..=2.0).contains(&penalty)) {
+                anyhow::bail!("Invalid {} for {}: expected a value between -2 and 2", name, owner);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl ModelConfig {
+    /// Configuration for an upstream model that is not listed in the config file
+    ///
+    /// Used for per-request model overrides; capabilities use the same defaults
+    /// as an empty `options` object.
+    pub fn passthrough(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            enabled: true,
+            alias: None,
+            max_tokens: None,
+            context_window: None,
+            temperature: None,
+            options: ModelOptions {
+                supports_streaming: true,
+                supports_tools: true,
+                supports_temperature: true,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// Extensions of configuration files, in the order `load_default` looks for them
+const CONFIG_EXTENSIONS: [&str; 4] = ["json", "yaml", "yml", "toml"];
+
+/// Environment variable naming the configuration file
+pub const CONFIG_ENV_VAR: &str = "AIAPIPROXY_CONFIG";
+
+/// Read a configuration file with its includes merged underneath
+///
+/// `include` holds a path or a list of paths, relative to the including file.
+/// Files are merged in the listed order and the including file last, so later
+/// layers override earlier ones; a directory stands for its configuration
+/// files in file name order. Objects are merged key by key, any other value
+/// (including arrays) replaces the earlier one.
+fn load_layered(path: &Path, including: &mut Vec<PathBuf>, source_files: &mut Vec<PathBuf>) -> Result<serde_json::Value> {
+    // Compare resolved paths so `../dir/file` and symlinks can't hide a cycle;
+    // a missing file is reported by the read below
+    let canonical = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    if including.contains(&canonical) {
+        anyhow::bail!("Config file {:?} includes itself", path);
+    }
+    
+    let mut value = parse_config_file(path)?;
+    source_files.push(path.to_path_buf());
+    let includes = match value.as_object_mut().and_then(|object| object.remove("include")) {
+        None => Vec::new(),
+        Some(serde_json::Value::String(include)) => vec![include],
+        Some(serde_json::Value::Array(includes)) => includes.into_iter()
+            .map(|include| match include {
+                serde_json::Value::String(include) => Ok(include),
+                other => anyhow::bail!("Invalid include {} in {:?}", other, path),
+            })
+            .collect::<Result<_>>()?,
+        Some(other) => anyhow::bail!("Invalid include {} in {:?}", other, path),
+    };
+    if includes.is_empty() {
+        return Ok(value);
+    }
+    
+    let base_dir = path.parent().unwrap_or(Path::new(""));
+    let mut merged = serde_json::Value::Object(Default::default());
+    including.push(canonical);
+    for include in includes {
+        let include_path = base_dir.join(&include);
+        let files = if include_path.is_dir() {
+            let mut files: Vec<PathBuf> = std::fs::read_dir(&include_path)
+                .with_context(|| format!("Failed to read config directory: {:?}", include_path))?
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|file| {
+                    file.extension().and_then(|extension| extension.to_str())
+                        .is_some_and(|extension| CONFIG_EXTENSIONS.contains(&extension))
+                })
+                .collect();
+            files.sort();
+            files
+        } else {
+            vec![include_path]
+        };
+        for file in files {
+            debug!("Including configuration from: {:?}", file);
+            merge_config(&mut merged, load_layered(&file, including, source_files)?);
+        }
+    }
+    including.pop();
+    merge_config(&mut merged, value);
+    Ok(merged)
+}
+
+/// Parse one configuration file by its extension
+fn parse_config_file(path: &Path) -> Result<serde_json::Value> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read config file: {:?}", path))?;
+    parse_config(&content, path, &format!("{:?}", path))
+}
+
+/// Parse a configuration document in the format of a path's extension
+///
+/// `origin` names the document in errors.
+pub(crate) fn parse_config(content: &str, path: &Path, origin: &str) -> Result<serde_json::Value> {
+    let extension = path.extension().and_then(|extension| extension.to_str()).unwrap_or_default();
+    match extension.to_ascii_lowercase().as_str() {
+        "yaml" | "yml" => serde_yaml::from_str(content).with_context(|| format!("Failed to parse config YAML: {}", origin)),
+        "toml" => toml::from_str(content).with_context(|| format!("Failed to parse config TOML: {}", origin)),
+        _ => serde_json::from_str(content).with_context(|| format!("Failed to parse config JSON: {}", origin)),
+    }
+}
+
+/// Merge a configuration layer over the layers below it
+fn merge_config(base: &mut serde_json::Value, layer: serde_json::Value) {
+    match (base, layer) {
+        (serde_json::Value::Object(base), serde_json::Value::Object(layer)) => {
+            for (key, value) in layer {
+                match base.get_mut(&key) {
+                    Some(existing) => merge_config(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, layer) => *base = layer,
+    }
+}
+
+impl AppConfig {
+    /// Load configuration from a JSON, YAML (`.yaml`/`.yml`) or TOML (`.toml`) file
+    ///
+    /// Files with other extensions are read as JSON. Files and directories
+    /// listed under `include` are merged underneath the file itself; see
+    /// [`load_layered`].
+    pub fn load(path: &Path) -> Result<Self> {
+        info!("Loading configuration from: {:?}", path);
+        
+        let mut source_files = Vec::new();
+        let value = load_layered(path, &mut Vec::new(), &mut source_files)?;
+        let mut config = Self::from_value(value, &format!("{:?}", path))?;
+        config.source_files = source_files;
+        Ok(config)
+    }
+    
+    /// Build and validate the configuration of a parsed document, reading
+    /// the API keys it refers to
+    pub(crate) fn from_value(value: serde_json::Value, origin: &str) -> Result<Self> {
+        let mut config: AppConfig = serde_json::from_value(value)
+            .with_context(|| format!("Invalid configuration in {}", origin))?;
+        
+        config.validate()?;
+        secrets::resolve_api_keys(&mut config)?;
+        
+        debug!("Loaded {} providers", config.providers.len());
+        Ok(config)
+    }
+    
+    /// Load configuration from default locations
+    /// Searches in order:
+    /// 1. The file named by the `AIAPIPROXY_CONFIG` environment variable
+    /// 2. ~/.config/aiapiproxy/aiapiproxy.{json,yaml,yml,toml}
+    /// 3. ./aiapiproxy.{json,yaml,yml,toml}
+    /// 
+    /// Returns error if no configuration file is found.
+    pub fn load_default() -> Result<Self> {
+        if let Some(config_path) = Self::default_path() {
+            return Self::load(&config_path);
+        }
+        
+        anyhow::bail!(
+            "Configuration file not found. Please create one at:\n\
+             - ~/.config/aiapiproxy/aiapiproxy.json (recommended)\n\
+             - ./aiapiproxy.json (current directory)\n\
+             \n\
+             aiapiproxy.yaml, aiapiproxy.yml and aiapiproxy.toml are read as well.\n\
+             See aiapiproxy.example.json for reference."
+        )
+    }
+    
+    /// Path of the configuration file `load_default` reads, if one exists
+    pub fn default_path() -> Option<PathBuf> {
+        if let Some(path) = std::env::var_os(CONFIG_ENV_VAR).filter(|path| !path.is_empty()) {
+            return Some(PathBuf::from(path));
+        }
+        
+        // Try home config directory first, then the current directory
+        let home_dir = dirs::home_dir().map(|home| home.join(".config").join("aiapiproxy"));
+        home_dir.into_iter()
+            .chain(std::iter::once(PathBuf::new()))
+            .flat_map(|dir| CONFIG_EXTENSIONS.map(|extension| dir.join(format!("aiapiproxy.{}", extension))))
+            .find(|path| path.exists())
+    }
+    
+    /// Validate configuration
+    fn validate(&self) -> Result<()> {
+        if self.providers.is_empty() {
+            anyhow::bail!("At least one provider must be configured");
+        }
+        
+        for (name, provider) in &self.providers {
+            // Validate provider type
+            let valid_types = ["openai", "modelhub", "anthropic", "ark"];
+            if !valid_types.contains(&provider.provider_type.as_str()) {
                 anyhow::bail!("Invalid provider type '{}' for provider '{}'", provider.provider_type, name);
             }
-            
-            // Validate base URL
-            if !provider.base_url.starts_with("http") {
-                anyhow::bail!("Invalid base URL for provider '{}': {}", name, provider.base_url);
+            
+            // Validate base URL
+            if !provider.base_url.starts_with("http") {
+                anyhow::bail!("Invalid base URL for provider '{}': {}", name, provider.base_url);
+            }
+            
+            if let Some(proxy) = &provider.options.http_proxy {
+                if reqwest::Proxy::all(proxy.as_str()).is_err() {
+                    anyhow::bail!("Invalid httpProxy for provider '{}': {}", name, proxy);
+                }
+            }
+            
+            if provider.options.timeout_secs == Some(0) || provider.options.stream_timeout_secs == Some(0) {
+                anyhow::bail!("Timeouts for provider '{}' must be at least 1 second", name);
+            }
+            
+            if let Some(pool) = &provider.options.pool {
+                let intervals = [pool.idle_timeout_secs, pool.http2_keep_alive_interval_secs, pool.http2_keep_alive_timeout_secs, pool.tcp_keepalive_secs];
+                if intervals.contains(&Some(0)) {
+                    anyhow::bail!("Pool intervals for provider '{}' must be at least 1 second", name);
+                }
+            }
+            
+            if let Some(rate_limit) = &provider.options.rate_limit {
+                if rate_limit.requests_per_minute == Some(0) || rate_limit.tokens_per_minute == Some(0) {
+                    anyhow::bail!("Rate limits for provider '{}' must be positive", name);
+                }
+            }
+            
+            if let Some(version) = provider.options.tls.as_ref().and_then(|tls| tls.min_version.as_ref()) {
+                let valid_versions = ["1.0", "1.1", "1.2"];
+                if !valid_versions.contains(&version.as_str()) {
+                    anyhow::bail!("Invalid tls.minVersion '{}' for provider '{}'. Valid values: {:?}", version, name, valid_versions);
+                }
+            }
+            
+            // Validate models
+            if provider.models.is_empty() {
+                anyhow::bail!("Provider '{}' must have at least one model configured", name);
+            }
+            
+            for (model_name, model_config) in &provider.models {
+                if model_config.name.is_empty() {
+                    anyhow::bail!("Model '{}' in provider '{}' must have a name", model_name, name);
+                }
+                
+                if let Some(mode) = &model_config.options.structured_output {
+                    let valid_modes = ["tool", "json_schema"];
+                    if !valid_modes.contains(&mode.as_str()) {
+                        anyhow::bail!("Invalid structuredOutput '{}' for model '{}' in provider '{}'. Valid values: {:?}", mode, model_name, name, valid_modes);
+                    }
+                }
+                
+                if let Some(policy) = &model_config.options.context_overflow {
+                    let valid_policies = ["reject", "truncate"];
+                    if !valid_policies.contains(&policy.as_str()) {
+                        anyhow::bail!("Invalid contextOverflow '{}' for model '{}' in provider '{}'. Valid values: {:?}", policy, model_name, name, valid_policies);
+                    }
+                }
+                
+                if let Some(format) = &model_config.options.tools_format {
+                    let valid_formats = ["tools", "legacy"];
+                    if !valid_formats.contains(&format.as_str()) {
+                        anyhow::bail!("Invalid toolsFormat '{}' for model '{}' in provider '{}'. Valid values: {:?}", format, model_name, name, valid_formats);
+                    }
+                }
+                
+                if let Some(strategy) = &model_config.options.clamp_strategy {
+                    let valid_strategies = ["reject", "clamp"];
+                    if !valid_strategies.contains(&strategy.as_str()) {
+                        anyhow::bail!("Invalid clampStrategy '{}' for model '{}' in provider '{}'. Valid values: {:?}", strategy, model_name, name, valid_strategies);
+                    }
+                }
+                
+                if let (Some(min), Some(cap)) = (model_config.options.min_output_tokens, model_config.options.cap_output_tokens) {
+                    if min > cap {
+                        anyhow::bail!("minOutputTokens ({}) exceeds capOutputTokens ({}) for model '{}' in provider '{}'", min, cap, model_name, name);
+                    }
+                }
+                
+                if let Some(effort) = &model_config.options.reasoning_effort {
+                    let valid_efforts = ["low", "medium", "high"];
+                    if !valid_efforts.contains(&effort.as_str()) {
+                        anyhow::bail!("Invalid reasoningEffort '{}' for model '{}' in provider '{}'. Valid values: {:?}", effort, model_name, name, valid_efforts);
+                    }
+                }
+                
+                if let Some(detail) = &model_config.options.image_detail {
+                    let valid_details = ["low", "high", "auto"];
+                    if !valid_details.contains(&detail.as_str()) {
+                        anyhow::bail!("Invalid imageDetail '{}' for model '{}' in provider '{}'. Valid values: {:?}", detail, model_name, name, valid_details);
+                    }
+                }
+                
+                if let Some(recovery) = &model_config.options.stream_recovery {
+                    let valid_recoveries = ["error", "stop", "resume"];
+                    if !valid_recoveries.contains(&recovery.as_str()) {
+                        anyhow::bail!("Invalid streamRecovery '{}' for model '{}' in provider '{}'. Valid values: {:?}", recovery, model_name, name, valid_recoveries);
+                    }
+                }
+                
+                if model_config.options.max_continuations.is_some_and(|rounds| rounds > 10) {
+                    anyhow::bail!("Invalid maxContinuations for model '{}' in provider '{}': at most 10", model_name, name);
+                }
+                
+                let owner = format!("model '{}' in provider '{}'", model_name, name);
+                model_config.options.request_defaults.validate(&owner)?;
+                model_config.options.request_overrides.validate(&owner)?;
+            }
+            
+            if let Some(retry) = &provider.options.retry {
+                retry.validate(&format!("provider '{}'", name))?;
+            }
+            
+            // Validate modelhub-specific options
+            if provider.provider_type == "modelhub" {
+                if let Some(mode) = &provider.options.mode {
+                    let valid_modes = ["responses", "gemini"];
+                    if !valid_modes.contains(&mode.as_str()) {
+                        anyhow::bail!("Invalid mode '{}' for modelhub provider '{}'. Valid modes: {:?}", mode, name, valid_modes);
+                    }
+                }
+            }
+        }
+        
+        for (claude_model, target) in &self.model_mapping {
+            match target {
+                ModelTarget::Path(_) => continue,
+                ModelTarget::Chain(paths) if paths.is_empty() => {
+                    anyhow::bail!("Empty failover chain for model mapping '{}'", claude_model);
+                }
+                ModelTarget::Weighted(targets) if targets.iter().map(|t| u64::from(t.weight)).sum::<u64>() == 0 => {
+                    anyhow::bail!("Weighted targets for model mapping '{}' must have a positive total weight", claude_model);
+                }
+                _ => {}
+            }
+            for path in target.paths() {
+                let provider = path.split_once('/').map(|(provider, _)| provider);
+                if !provider.is_some_and(|p| self.providers.contains_key(p)) {
+                    anyhow::bail!("Invalid target '{}' for model mapping '{}': expected a configured provider/model path", path, claude_model);
+                }
+            }
+        }
+        
+        if let Some(adaptive) = &self.adaptive_routing {
+            if adaptive.min_requests == 0 || adaptive.min_requests > adaptive.window_size {
+                anyhow::bail!("adaptiveRouting.minRequests must be between 1 and windowSize ({})", adaptive.window_size);
+            }
+            if !(adaptive.max_error_rate > 0.0 && adaptive.max_error_rate <= 1.0) {
+                anyhow::bail!("adaptiveRouting.maxErrorRate must be in (0, 1], got {}", adaptive.max_error_rate);
+            }
+        }
+        
+        if let Some(auth) = &self.auth {
+            if auth.keys.is_empty() {
+                anyhow::bail!("auth.keys must list at least one key");
+            }
+            let mut hashes = HashSet::new();
+            let mut names = HashSet::new();
+            for key in &auth.keys {
+                if !names.insert(key.name.as_str()) {
+                    anyhow::bail!("Duplicate API key name '{}'", key.name);
+                }
+                if key.key_hash.len() != 64 || !key.key_hash.chars().all(|c| c.is_ascii_hexdigit()) {
+                    anyhow::bail!("Invalid keyHash for API key '{}': expected a hex-encoded SHA-256 hash", key.name);
+                }
+                if !hashes.insert(key.key_hash.to_ascii_lowercase()) {
+                    anyhow::bail!("Duplicate keyHash for API key '{}'", key.name);
+                }
+                key.limits.budget.validate(&format!("API key '{}'", key.name))?;
+            }
+            for (tenant, budget) in &auth.tenants {
+                budget.validate(&format!("tenant '{}'", tenant))?;
+            }
+        }
+        
+        let cors = &self.server.cors;
+        for origin in cors.allowed_origins.iter().filter(|origin| *origin != "*") {
+            let valid = (origin.starts_with("http://") || origin.starts_with("https://"))
+                && !origin.ends_with('/')
+                && origin.bytes().all(|byte| byte.is_ascii_graphic());
+            if !valid {
+                anyhow::bail!("Invalid CORS origin '{}': expected scheme://host[:port], e.g. \"https://app.example.com\"", origin);
+            }
+        }
+        if cors.allow_credentials && cors.allows_any_origin() {
+            anyhow::bail!("server.cors.allowCredentials requires explicit allowedOrigins, not \"*\"");
+        }
+        
+        if let Some(tls) = &self.server.tls {
+            if tls.cert_path.as_os_str().is_empty() || tls.key_path.as_os_str().is_empty() {
+                anyhow::bail!("server.tls requires both certPath and keyPath");
+            }
+            if tls.reload_interval_secs == Some(0) {
+                anyhow::bail!("server.tls.reloadIntervalSecs must be at least 1");
+            }
+        }
+        
+        if self.server.config_reload_interval_secs == Some(0) {
+            anyhow::bail!("server.configReloadIntervalSecs must be at least 1");
+        }
+        
+        if self.concurrency.as_ref().is_some_and(|concurrency| concurrency.max_concurrent == 0) {
+            anyhow::bail!("concurrency.maxConcurrent must be at least 1");
+        }
+        
+        let thought_cache = &self.thought_cache;
+        if thought_cache.capacity == 0 || thought_cache.ttl_secs == 0 || thought_cache.persist_interval_secs == 0 {
+            anyhow::bail!("thoughtCache capacity, ttlSecs and persistIntervalSecs must be positive");
+        }
+        
+        if let Some(backend) = &self.state_backend {
+            let valid_types = ["memory", "redis"];
+            if !valid_types.contains(&backend.backend_type.as_str()) {
+                anyhow::bail!("Invalid stateBackend type '{}'. Valid values: {:?}", backend.backend_type, valid_types);
+            }
+            if backend.backend_type == "redis" {
+                if !cfg!(feature = "redis") {
+                    anyhow::bail!("stateBackend type 'redis' requires building with the `redis` feature");
+                }
+                if !backend.url.as_deref().is_some_and(|url| url.starts_with("redis://") || url.starts_with("rediss://")) {
+                    anyhow::bail!("stateBackend type 'redis' requires a redis:// or rediss:// url");
+                }
+            }
+            if backend.timeout_ms == 0 {
+                anyhow::bail!("stateBackend.timeoutMs must be positive");
+            }
+        }
+        
+        if let Some(timeouts) = &self.timeouts {
+            let budgets = std::iter::once(("timeouts", &timeouts.default))
+                .chain(timeouts.routes.iter().map(|(path, budget)| (path.as_str(), budget)));
+            for (owner, budget) in budgets {
+                if budget.request_ms == Some(0) || budget.stream_first_token_ms == Some(0) {
+                    anyhow::bail!("Invalid timeouts for {}: budgets must be positive", owner);
+                }
+            }
+        }
+        
+        let keep_alives = std::iter::once(("keepAlive", &self.keep_alive.default))
+            .chain(self.keep_alive.routes.iter().map(|(path, keep_alive)| (path.as_str(), keep_alive)));
+        for (owner, keep_alive) in keep_alives {
+            if keep_alive.interval_secs == Some(0) {
+                anyhow::bail!("Invalid keep-alive for {}: intervalSecs must be positive", owner);
+            }
+            if keep_alive.comment.as_deref().is_some_and(|comment| comment.contains(['\r', '\n'])) {
+                anyhow::bail!("Invalid keep-alive for {}: the comment must be a single line", owner);
+            }
+        }
+        
+        for pattern in &self.forward_headers {
+            let name = pattern.strip_suffix('*').unwrap_or(pattern);
+            if name.is_empty() || !name.bytes().all(|b| b.is_ascii_alphanumeric() || b"-_.".contains(&b)) {
+                anyhow::bail!("Invalid forwardHeaders entry '{}': expected a header name or a prefix ending in '*'", pattern);
+            }
+            if name.len() == pattern.len() && PROTECTED_HEADERS.contains(&name.to_ascii_lowercase().as_str()) {
+                anyhow::bail!("Invalid forwardHeaders entry '{}': the header is set by the proxy", pattern);
+            }
+        }
+        
+        if let Some(retry) = &self.retry {
+            retry.validate("the top-level retry policy")?;
+        }
+        
+        for (path, pricing) in &self.pricing {
+            let prices = [Some(pricing.input_per_mtok), Some(pricing.output_per_mtok), pricing.cached_input_per_mtok];
+            if prices.iter().flatten().any(|price| price.is_nan() || *price < 0.0) {
+                anyhow::bail!("Invalid pricing for '{}': prices must not be negative", path);
+            }
+        }
+        
+        for rule in &self.routing_rules {
+            let provider = rule.target.split_once('/').map(|(provider, _)| provider);
+            if !provider.is_some_and(|p| self.providers.contains_key(p)) {
+                anyhow::bail!("Invalid routing rule target '{}': expected a configured provider/model path", rule.target);
+            }
+        }
+        
+        for (i, experiment) in self.experiments.iter().enumerate() {
+            if experiment.name.is_empty() || self.experiments[..i].iter().any(|other| other.name == experiment.name) {
+                anyhow::bail!("Experiment names must be non-empty and unique: '{}'", experiment.name);
+            }
+            for path in [&experiment.control, &experiment.treatment] {
+                let provider = path.split_once('/').map(|(provider, _)| provider);
+                if !provider.is_some_and(|p| self.providers.contains_key(p)) {
+                    anyhow::bail!("Invalid target '{}' for experiment '{}': expected a configured provider/model path", path, experiment.name);
+                }
+            }
+            if !(0.0..=100.0).contains(&experiment.treatment_percent) {
+                anyhow::bail!("treatmentPercent of experiment '{}' must be between 0 and 100", experiment.name);
+            }
+        }
+        
+        for replacement in self.prompt_rules.iter().flat_map(|rule| &rule.replace) {
+            if let Err(e) = regex::Regex::new(&replacement.pattern) {
+                anyhow::bail!("Invalid promptRules pattern '{}': {}", replacement.pattern, e);
+            }
+        }
+        
+        if let Some(redaction) = &self.redaction {
+            redaction.validate()?;
+        }
+        if let Some(guardrails) = &self.guardrails {
+            guardrails.validate()?;
+        }
+        
+        if self.request_log.as_ref().is_some_and(|log| log.max_file_bytes == 0) {
+            anyhow::bail!("requestLog.maxFileBytes must be at least 1");
+        }
+        
+        if let Some(shadow) = &self.shadow {
+            let provider = shadow.target.split_once('/').map(|(provider, _)| provider);
+            if !provider.is_some_and(|p| self.providers.contains_key(p)) {
+                anyhow::bail!("Invalid shadow target '{}': expected a configured provider/model path", shadow.target);
+            }
+            if !(shadow.percent > 0.0 && shadow.percent <= 100.0) {
+                anyhow::bail!("shadow.percent must be greater than 0 and at most 100");
+            }
+            if shadow.max_file_bytes == 0 {
+                anyhow::bail!("shadow.maxFileBytes must be at least 1");
+            }
+        }
+        
+        #[cfg(not(feature = "sqlite"))]
+        if self.usage_store.is_some() {
+            anyhow::bail!("usageStore requires the `sqlite` feature");
+        }
+        
+        if let Some(transcripts) = &self.transcripts {
+            if transcripts.directory.is_some() == transcripts.sqlite_path.is_some() {
+                anyhow::bail!("transcripts needs either a directory or a sqlitePath");
+            }
+            if transcripts.sqlite_path.is_some() && !cfg!(feature = "sqlite") {
+                anyhow::bail!("transcripts.sqlitePath requires the `sqlite` feature");
+            }
+        }
+        
+        if let Some(error_reports) = &self.error_reports {
+            if error_reports.sentry_dsn.is_some() && !cfg!(feature = "sentry") {
+                anyhow::bail!("errorReports.sentryDsn requires building with the `sentry` feature");
+            }
+            if error_reports.burst_errors == 0 || error_reports.burst_window_secs == 0 {
+                anyhow::bail!("errorReports.burstErrors and burstWindowSecs must be at least 1");
+            }
+        }
+        
+        for webhook in &self.webhooks {
+            if !webhook.url.starts_with("http://") && !webhook.url.starts_with("https://") {
+                anyhow::bail!("Invalid webhook URL '{}': expected an http(s) URL", webhook.url);
+            }
+        }
+        
+        Ok(())
+    }
+    
+    /// Get provider and model configuration by path (e.g., "provider/model")
+    pub fn get_provider_model(&self, path: &str) -> Option<(&ProviderConfig, &ModelConfig)> {
+        let parts: Vec<&str> = path.splitn(2, '/').collect();
+        if parts.len() != 2 {
+            return None;
+        }
+        
+        let provider_name = parts[0];
+        let model_name = parts[1];
+        
+        let provider = self.providers.get(provider_name)?;
+        let model = provider.models.get(model_name)?;
+        
+        Some((provider, model))
+    }
+    
+    /// Retry policy of a provider: its own `retry` option, or the top-level one
+    pub fn retry_policy(&self, provider_name: &str) -> Option<&RetryConfig> {
+        self.providers.get(provider_name)
+            .and_then(|provider| provider.options.retry.as_ref())
+            .or(self.retry.as_ref())
+    }
+    
+    /// Whether an inbound header is listed in `forwardHeaders`
+    ///
+    /// Headers the proxy sets itself are never forwarded, even when a prefix
+    /// matches them.
+    pub fn forwards_header(&self, name: &str) -> bool {
+        let name = name.to_ascii_lowercase();
+        !PROTECTED_HEADERS.contains(&name.as_str()) && self.forward_headers.iter().any(|pattern| {
+            let pattern = pattern.to_ascii_lowercase();
+            match pattern.strip_suffix('*') {
+                Some(prefix) => name.starts_with(prefix),
+                None => name == pattern,
+            }
+        })
+    }
+    
+    /// Whether a provider/model path is taken out of service, with its
+    /// provider's or its own `enabled: false`
+    pub fn is_disabled(&self, path: &str) -> bool {
+        let Some((provider_name, model_name)) = path.split_once('/') else {
+            return false;
+        };
+        self.providers.get(provider_name).is_some_and(|provider| {
+            !provider.enabled || provider.models.get(model_name).is_some_and(|model| !model.enabled)
+        })
+    }
+    
+    /// Get provider configuration and a model override for a raw "provider/model" path
+    ///
+    /// Only available when `allowModelOverride` is enabled. Listed models keep
+    /// their configuration; other model names are passed through unchanged.
+    pub fn get_provider_model_override(&self, path: &str) -> Option<(&ProviderConfig, ModelConfig)> {
+        if !self.allow_model_override {
+            return None;
+        }
+        
+        let (provider_name, model_name) = path.split_once('/')?;
+        if model_name.is_empty() {
+            return None;
+        }
+        
+        let provider = self.providers.get(provider_name)?;
+        let model = provider.models.get(model_name)
+            .cloned()
+            .unwrap_or_else(|| ModelConfig::passthrough(model_name));
+        
+        Some((provider, model))
+    }
+    
+    /// Resolve a Claude model name to provider/model path
+    /// 
+    /// Returns the mapped path (the first of a failover chain) if found in
+    /// modelMapping, otherwise returns None
+    pub fn resolve_claude_model(&self, claude_model: &str) -> Option<&str> {
+        self.resolve_claude_model_chain(claude_model)?
+            .first()
+            .copied()
+    }
+    
+    /// Resolve a Claude model name to its mapped provider/model paths, in failover order
+    pub fn resolve_claude_model_chain(&self, claude_model: &str) -> Option<Vec<&str>> {
+        self.resolve_claude_model_target(claude_model).map(ModelTarget::paths)
+    }
+    
+    /// Find the modelMapping entry for a Claude model name
+    pub fn resolve_claude_model_target(&self, claude_model: &str) -> Option<&ModelTarget> {
+        // First check exact match in modelMapping
+        if let Some(target) = self.model_mapping.get(claude_model) {
+            return Some(target);
+        }
+        
+        // Check pattern matching (e.g., "sonnet" matches any model containing "sonnet")
+        self.model_mapping.iter()
+            .find(|(pattern, _)| model_matches_pattern(claude_model, pattern))
+            .map(|(_, target)| target)
+    }
+    
+    /// Find the target of the first routing rule matching a request
+    pub fn match_routing_rule(&self, model: &str, metadata: Option<&HashMap<String, serde_json::Value>>) -> Option<&str> {
+        self.routing_rules.iter()
+            .find(|rule| rule.matches(model, metadata))
+            .map(|rule| rule.target.as_str())
+    }
+    
+    /// List all available model paths
+    pub fn list_model_paths(&self) -> Vec<String> {
+        let mut paths = Vec::new();
+        for (provider_name, provider) in &self.providers {
+            for model_name in provider.models.keys() {
+                paths.push(format!("{}/{}", provider_name, model_name));
+            }
+        }
+        paths
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+    
+    fn create_test_config() -> String {
+        r#"{
+            "providers": {
+                "openai": {
+                    "type": "openai",
+                    "baseUrl": "https://api.openai.com/v1",
+                    "apiKey": "",
+                    "models": {
+                        "gpt-4o": {
+                            "name": "gpt-4o",
+                            "maxTokens": 8192
+                        },
+                        "gpt-4o-mini": {
+                            "name": "gpt-4o-mini",
+                            "maxTokens": 4096
+                        }
+                    }
+                },
+                "modelhub-sg1": {
+                    "type": "modelhub",
+                    "baseUrl": "https://modelhub-sg1.example.com",
+                    "apiKey": "",
+                    "options": {
+                        "apiKeyParam": "ak",
+                        "mode": "responses"
+                    },
+                    "models": {
+                        "gpt-5": {
+                            "name": "gpt-5",
+                            "maxTokens": 32768
+                        }
+                    }
+                },
+                "modelhub-gemini": {
+                    "type": "modelhub",
+                    "baseUrl": "https://modelhub-gemini.example.com",
+                    "apiKey": "",
+                    "options": {
+                        "apiKeyParam": "ak",
+                        "mode": "gemini"
+                    },
+                    "models": {
+                        "gemini-2.5-pro": {
+                            "name": "gemini-2.5-pro",
+                            "maxTokens": 65536,
+                            "options": {
+                                "supportsVision": true
+                            }
+                        }
+                    }
+                }
+            },
+            "modelMapping": {
+                "claude-3-sonnet": "modelhub-sg1/gpt-5",
+                "claude-3-opus": "openai/gpt-4o",
+                "sonnet": "modelhub-sg1/gpt-5"
+            }
+        }"#.to_string()
+    }
+    
+    #[test]
+    fn test_load_config() {
+        let config_str = create_test_config();
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(config_str.as_bytes()).unwrap();
+        
+        let config = AppConfig::load(file.path()).unwrap();
+        
+        assert_eq!(config.providers.len(), 3);
+        assert!(config.providers.contains_key("openai"));
+        assert!(config.providers.contains_key("modelhub-sg1"));
+        assert!(config.providers.contains_key("modelhub-gemini"));
+    }
+    
+    #[test]
+    fn test_get_provider_model() {
+        let config_str = create_test_config();
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(config_str.as_bytes()).unwrap();
+        
+        let config = AppConfig::load(file.path()).unwrap();
+        
+        // Test valid path
+        let result = config.get_provider_model("openai/gpt-4o");
+        assert!(result.is_some());
+        let (provider, model) = result.unwrap();
+        assert_eq!(provider.provider_type, "openai");
+        assert_eq!(model.name, "gpt-4o");
+        
+        // Test modelhub provider
+        let result = config.get_provider_model("modelhub-sg1/gpt-5");
+        assert!(result.is_some());
+        let (provider, model) = result.unwrap();
+        assert_eq!(provider.provider_type, "modelhub");
+        assert_eq!(provider.options.mode, Some("responses".to_string()));
+        assert_eq!(model.name, "gpt-5");
+        
+        // Test invalid path
+        assert!(config.get_provider_model("invalid").is_none());
+        assert!(config.get_provider_model("openai/nonexistent").is_none());
+    }
+    
+    #[test]
+    fn test_list_model_paths() {
+        let config_str = create_test_config();
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(config_str.as_bytes()).unwrap();
+        
+        let config = AppConfig::load(file.path()).unwrap();
+        let paths = config.list_model_paths();
+        
+        assert!(paths.contains(&"openai/gpt-4o".to_string()));
+        assert!(paths.contains(&"openai/gpt-4o-mini".to_string()));
+        assert!(paths.contains(&"modelhub-sg1/gpt-5".to_string()));
+        assert!(paths.contains(&"modelhub-gemini/gemini-2.5-pro".to_string()));
+    }
+    
+    #[test]
+    fn test_validation_empty_providers() {
+        let config_str = r#"{"providers": {}}"#;
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(config_str.as_bytes()).unwrap();
+        
+        let result = AppConfig::load(file.path());
+        assert!(result.is_err());
+    }
+    
+    #[test]
+    fn test_validation_invalid_provider_type() {
+        let config_str = r#"{
+            "providers": {
+                "test": {
+                    "type": "invalid_type",
+                    "baseUrl": "https://example.com",
+                    "models": {
+                        "model1": {"name": "model1"}
+                    }
+                }
+            }
+        }"#;
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(config_str.as_bytes()).unwrap();
+        
+        let result = AppConfig::load(file.path());
+        assert!(result.is_err());
+    }
+    
+    #[test]
+    fn test_validation_invalid_mode() {
+        let config_str = r#"{
+            "providers": {
+                "test": {
+                    "type": "modelhub",
+                    "baseUrl": "https://example.com",
+                    "options": {
+                        "mode": "invalid_mode"
+                    },
+                    "models": {
+                        "model1": {"name": "model1"}
+                    }
+                }
+            }
+        }"#;
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(config_str.as_bytes()).unwrap();
+        
+        let result = AppConfig::load(file.path());
+        assert!(result.is_err());
+    }
+    
+    #[test]
+    fn test_validation_invalid_structured_output() {
+        let config_str = r#"{
+            "providers": {
+                "test": {
+                    "type": "openai",
+                    "baseUrl": "https://example.com",
+                    "models": {
+                        "model1": {"name": "model1", "options": {"structuredOutput": "xml"}}
+                    }
+                }
+            }
+        }"#;
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(config_str.as_bytes()).unwrap();
+        
+        let result = AppConfig::load(file.path());
+        assert!(result.is_err());
+    }
+    
+    #[test]
+    fn test_validation_invalid_context_overflow() {
+        let config_str = r#"{
+            "providers": {
+                "test": {
+                    "type": "openai",
+                    "baseUrl": "https://example.com",
+                    "models": {
+                        "model1": {"name": "model1", "contextWindow": 8192, "options": {"contextOverflow": "summarize"}}
+                    }
+                }
+            }
+        }"#;
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(config_str.as_bytes()).unwrap();
+        
+        let result = AppConfig::load(file.path());
+        assert!(result.is_err());
+    }
+    
+    #[test]
+    fn test_validation_invalid_reasoning_effort() {
+        let config_str = r#"{
+            "providers": {
+                "test": {
+                    "type": "openai",
+                    "baseUrl": "https://example.com",
+                    "models": {
+                        "model1": {"name": "o3", "options": {"reasoningEffort": "max"}}
+                    }
+                }
+            }
+        }"#;
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(config_str.as_bytes()).unwrap();
+        
+        let result = AppConfig::load(file.path());
+        assert!(result.is_err());
+    }
+    
+    #[test]
+    fn test_validation_invalid_image_detail() {
+        let config_str = r#"{
+            "providers": {
+                "test": {
+                    "type": "ark",
+                    "baseUrl": "https://example.com",
+                    "models": {
+                        "model1": {"name": "doubao-seed-1.6", "options": {"imageDetail": "medium"}}
+                    }
+                }
             }
-            
-            // Validate models
-            if provider.models.is_empty() {
-                anyhow::bail!("Provider '{}' must have at least one model configured", name);
+        }"#;
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(config_str.as_bytes()).unwrap();
+        
+        let result = AppConfig::load(file.path());
+        assert!(result.is_err());
+    }
+    
+    #[test]
+    fn test_validation_invalid_stream_recovery() {
+        let config_str = r#"{
+            "providers": {
+                "test": {
+                    "type": "openai",
+                    "baseUrl": "https://example.com",
+                    "models": {
+                        "model1": {"name": "deepseek-chat", "options": {"streamRecovery": "retry"}}
+                    }
+                }
             }
-            
-            for (model_name, model_config) in &provider.models {
-                if model_config.name.is_empty() {
-                    anyhow::bail!("Model '{}' in provider '{}' must have a name", model_name, name);
+        }"#;
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(config_str.as_bytes()).unwrap();
+        
+        let result = AppConfig::load(file.path());
+        assert!(result.is_err());
+    }
+    
+    #[test]
+    fn test_validation_invalid_max_continuations() {
+        let config_str = r#"{
+            "providers": {
+                "test": {
+                    "type": "openai",
+                    "baseUrl": "https://example.com",
+                    "models": {
+                        "model1": {"name": "deepseek-chat", "options": {"maxContinuations": 11}}
+                    }
                 }
             }
-            
-            // Validate modelhub-specific options
-            if provider.provider_type == "modelhub" {
-                if let Some(mode) = &provider.options.mode {
-                    let valid_modes = ["responses", "gemini"];
-                    if !valid_modes.contains(&mode.as_str()) {
-                        anyhow::bail!("Invalid mode '{}' for modelhub provider '{}'. Valid modes: {:?}", mode, name, valid_modes);
+        }"#;
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(config_str.as_bytes()).unwrap();
+        
+        let error = AppConfig::load(file.path()).unwrap_err();
+        assert!(error.to_string().contains("Invalid maxContinuations"), "{}", error);
+        
+        let valid = config_str.replace("11", "3");
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(valid.as_bytes()).unwrap();
+        let config = AppConfig::load(file.path()).unwrap();
+        assert_eq!(config.providers["test"].models["model1"].options.max_continuations, Some(3));
+    }
+    
+    #[test]
+    fn test_validation_invalid_output_tokens() {
+        let config_str = r#"{
+            "providers": {
+                "test": {
+                    "type": "openai",
+                    "baseUrl": "https://example.com",
+                    "models": {
+                        "model1": {"name": "model1", "options": {"minOutputTokens": 4096, "capOutputTokens": 1024}}
                     }
                 }
             }
+        }"#;
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(config_str.as_bytes()).unwrap();
+        assert!(AppConfig::load(file.path()).is_err());
+        
+        let config_str = config_str.replace(r#""minOutputTokens": 4096"#, r#""clampStrategy": "truncate""#);
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(config_str.as_bytes()).unwrap();
+        assert!(AppConfig::load(file.path()).is_err());
+    }
+    
+    #[test]
+    fn test_get_provider_model_override() {
+        let config_str = create_test_config();
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(config_str.as_bytes()).unwrap();
+        
+        let mut config = AppConfig::load(file.path()).unwrap();
+        
+        // Disabled by default
+        assert!(!config.allow_model_override);
+        assert!(config.get_provider_model_override("openai/gpt-4.1").is_none());
+        
+        config.allow_model_override = true;
+        
+        // Unlisted model is passed through
+        let (provider, model) = config.get_provider_model_override("openai/gpt-4.1").unwrap();
+        assert_eq!(provider.provider_type, "openai");
+        assert_eq!(model.name, "gpt-4.1");
+        assert!(model.options.supports_streaming);
+        
+        // Listed model keeps its configuration
+        let (_, model) = config.get_provider_model_override("openai/gpt-4o").unwrap();
+        assert_eq!(model.max_tokens, Some(8192));
+        
+        // Unknown provider
+        assert!(config.get_provider_model_override("unknown/gpt-4.1").is_none());
+    }
+    
+    #[test]
+    fn test_resolve_claude_model() {
+        let config_str = create_test_config();
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(config_str.as_bytes()).unwrap();
+        
+        let config = AppConfig::load(file.path()).unwrap();
+        
+        // Exact match
+        assert_eq!(config.resolve_claude_model("claude-3-sonnet"), Some("modelhub-sg1/gpt-5"));
+        assert_eq!(config.resolve_claude_model("claude-3-opus"), Some("openai/gpt-4o"));
+        
+        // Pattern match (contains)
+        assert_eq!(config.resolve_claude_model("claude-3-sonnet-20240620"), Some("modelhub-sg1/gpt-5"));
+        
+        // Short alias
+        assert_eq!(config.resolve_claude_model("sonnet"), Some("modelhub-sg1/gpt-5"));
+        
+        // Not found
+        assert!(config.resolve_claude_model("unknown-model").is_none());
+    }
+    
+    #[test]
+    fn test_resolve_claude_model_chain() {
+        let config_str = create_test_config().replace(
+            r#""claude-3-opus": "openai/gpt-4o","#,
+            r#""claude-3-opus": ["modelhub-gemini/gemini-2.5-pro", "openai/gpt-4o"],"#,
+        );
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(config_str.as_bytes()).unwrap();
+        
+        let config = AppConfig::load(file.path()).unwrap();
+        
+        let chain = config.resolve_claude_model_chain("claude-3-opus").unwrap();
+        assert_eq!(chain, ["modelhub-gemini/gemini-2.5-pro", "openai/gpt-4o"]);
+        assert_eq!(config.resolve_claude_model("claude-3-opus"), Some("modelhub-gemini/gemini-2.5-pro"));
+        
+        // Single paths are one-entry chains
+        assert_eq!(config.resolve_claude_model_chain("claude-3-sonnet").unwrap(), ["modelhub-sg1/gpt-5"]);
+    }
+    
+    #[test]
+    fn test_weighted_model_mapping() {
+        let config_str = create_test_config().replace(
+            r#""claude-3-opus": "openai/gpt-4o","#,
+            r#""claude-3-opus": [{"target": "openai/gpt-4o", "weight": 70}, {"target": "modelhub-sg1/gpt-5", "weight": 30}],"#,
+        );
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(config_str.as_bytes()).unwrap();
+        
+        let config = AppConfig::load(file.path()).unwrap();
+        match config.resolve_claude_model_target("claude-3-opus") {
+            Some(ModelTarget::Weighted(targets)) => {
+                assert_eq!(targets.len(), 2);
+                assert_eq!(targets[0].weight, 70);
+            }
+            other => panic!("expected weighted targets, got {:?}", other),
+        }
+        assert_eq!(config.resolve_claude_model("claude-3-opus"), Some("openai/gpt-4o"));
+    }
+    
+    #[test]
+    fn test_validation_invalid_failover_chain() {
+        let invalid = [
+            r#"[]"#,
+            r#"["openai/gpt-4o", "unknown/gpt-4o"]"#,
+            r#"[{"target": "openai/gpt-4o", "weight": 0}]"#,
+            r#"[{"target": "unknown/gpt-4o", "weight": 1}]"#,
+        ];
+        for chain in invalid {
+            let config_str = create_test_config().replace(
+                r#""claude-3-opus": "openai/gpt-4o","#,
+                &format!(r#""claude-3-opus": {},"#, chain),
+            );
+            let mut file = NamedTempFile::new().unwrap();
+            file.write_all(config_str.as_bytes()).unwrap();
+            
+            let result = AppConfig::load(file.path());
+            assert!(result.is_err(), "chain {} should be rejected", chain);
+        }
+    }
+    
+    #[test]
+    fn test_adaptive_routing_config() {
+        let config_str = create_test_config().replace(
+            r#""modelMapping": {"#,
+            r#""adaptiveRouting": {"maxErrorRate": 0.25, "maxLatencyMs": 20000},
+            "modelMapping": {"#,
+        );
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(config_str.as_bytes()).unwrap();
+        
+        let config = AppConfig::load(file.path()).unwrap();
+        let adaptive = config.adaptive_routing.unwrap();
+        assert_eq!(adaptive.max_error_rate, 0.25);
+        assert_eq!(adaptive.max_latency_ms, Some(20000));
+        assert_eq!(adaptive.window_size, 20);
+        assert_eq!(adaptive.cooldown_secs, 30);
+        
+        let invalid = create_test_config().replace(
+            r#""modelMapping": {"#,
+            r#""adaptiveRouting": {"windowSize": 3, "minRequests": 5},
+            "modelMapping": {"#,
+        );
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(invalid.as_bytes()).unwrap();
+        assert!(AppConfig::load(file.path()).is_err());
+    }
+    
+    #[test]
+    fn test_provider_retry_config() {
+        let config_str = create_test_config().replace(
+            r#""apiKeyParam": "ak","#,
+            r#""apiKeyParam": "ak",
+                        "retry": {"maxRetries": 2, "retryStatuses": [429, 503]},"#,
+        );
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(config_str.as_bytes()).unwrap();
+        
+        let config = AppConfig::load(file.path()).unwrap();
+        let retry = config.providers["modelhub-sg1"].options.retry.as_ref().unwrap();
+        assert_eq!(retry.max_retries, 2);
+        assert_eq!(retry.retry_statuses, Some(vec![429, 503]));
+        assert_eq!(retry.base_delay_ms, 1000);
+        assert!(retry.jitter);
+        assert!(config.providers["openai"].options.retry.is_none());
+        assert!(config.retry_policy("openai").is_none());
+        
+        // The top-level policy applies to providers without their own
+        let mut config_value: serde_json::Value = serde_json::from_str(&config_str).unwrap();
+        config_value["retry"] = serde_json::json!({"maxRetries": 5});
+        let config: AppConfig = serde_json::from_value(config_value).unwrap();
+        assert_eq!(config.retry_policy("openai").unwrap().max_retries, 5);
+        assert_eq!(config.retry_policy("modelhub-sg1").unwrap().max_retries, 2);
+        
+        let invalid = create_test_config().replace(
+            r#""apiKeyParam": "ak","#,
+            r#""apiKeyParam": "ak",
+                        "retry": {"baseDelayMs": 5000, "maxDelayMs": 1000},"#,
+        );
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(invalid.as_bytes()).unwrap();
+        assert!(AppConfig::load(file.path()).is_err());
+    }
+    
+    #[test]
+    fn test_provider_http_proxy() {
+        let config_str = create_test_config().replace(
+            r#""apiKeyParam": "ak","#,
+            r#""apiKeyParam": "ak",
+                        "httpProxy": "http://proxy.corp:3128",
+                        "noProxy": ["localhost", ".internal"],"#,
+        );
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(config_str.as_bytes()).unwrap();
+        
+        let config = AppConfig::load(file.path()).unwrap();
+        let options = &config.providers["modelhub-sg1"].options;
+        assert_eq!(options.http_proxy.as_deref(), Some("http://proxy.corp:3128"));
+        assert_eq!(options.no_proxy, vec!["localhost", ".internal"]);
+        assert!(config.providers["openai"].options.http_proxy.is_none());
+        
+        let invalid = create_test_config().replace(
+            r#""apiKeyParam": "ak","#,
+            r#""apiKeyParam": "ak",
+                        "httpProxy": "not a proxy","#,
+        );
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(invalid.as_bytes()).unwrap();
+        assert!(AppConfig::load(file.path()).is_err());
+    }
+    
+    #[test]
+    fn test_provider_tls_options() {
+        let config_str = create_test_config().replace(
+            r#""apiKeyParam": "ak","#,
+            r#""apiKeyParam": "ak",
+                        "tls": {"caCertPaths": ["/etc/pki/internal-ca.pem"], "minVersion": "1.2"},"#,
+        );
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(config_str.as_bytes()).unwrap();
+        
+        let config = AppConfig::load(file.path()).unwrap();
+        let tls = config.providers["modelhub-sg1"].options.tls.as_ref().unwrap();
+        assert_eq!(tls.ca_cert_paths, vec![PathBuf::from("/etc/pki/internal-ca.pem")]);
+        assert_eq!(tls.min_version.as_deref(), Some("1.2"));
+        assert!(!tls.insecure_skip_verify);
+        assert!(config.providers["openai"].options.tls.is_none());
+        
+        let invalid = create_test_config().replace(
+            r#""apiKeyParam": "ak","#,
+            r#""apiKeyParam": "ak",
+                        "tls": {"minVersion": "1.3"},"#,
+        );
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(invalid.as_bytes()).unwrap();
+        assert!(AppConfig::load(file.path()).is_err());
+    }
+    
+    #[test]
+    fn test_provider_pool_config() {
+        let config_str = create_test_config().replace(
+            r#""apiKeyParam": "ak","#,
+            r#""apiKeyParam": "ak",
+                        "pool": {"maxIdlePerHost": 32, "idleTimeoutSecs": 60, "http2KeepAliveIntervalSecs": 15, "tcpKeepaliveSecs": 30},"#,
+        );
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(config_str.as_bytes()).unwrap();
+        
+        let config = AppConfig::load(file.path()).unwrap();
+        let pool = config.providers["modelhub-sg1"].options.pool.as_ref().unwrap();
+        assert_eq!(pool.max_idle_per_host, Some(32));
+        assert_eq!(pool.idle_timeout_secs, Some(60));
+        assert_eq!(pool.http2_keep_alive_interval_secs, Some(15));
+        assert_eq!(pool.http2_keep_alive_timeout_secs, None);
+        assert_eq!(pool.tcp_keepalive_secs, Some(30));
+        assert!(config.providers["openai"].options.pool.is_none());
+        
+        let invalid = create_test_config().replace(
+            r#""apiKeyParam": "ak","#,
+            r#""apiKeyParam": "ak",
+                        "pool": {"http2KeepAliveIntervalSecs": 0},"#,
+        );
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(invalid.as_bytes()).unwrap();
+        assert!(AppConfig::load(file.path()).is_err());
+    }
+    
+    #[test]
+    fn test_provider_rate_limit() {
+        let config_str = create_test_config().replace(
+            r#""apiKeyParam": "ak","#,
+            r#""apiKeyParam": "ak",
+                        "rateLimit": {"requestsPerMinute": 60, "tokensPerMinute": 200000},"#,
+        );
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(config_str.as_bytes()).unwrap();
+        
+        let config = AppConfig::load(file.path()).unwrap();
+        let rate_limit = config.providers["modelhub-sg1"].options.rate_limit.as_ref().unwrap();
+        assert_eq!(rate_limit.requests_per_minute, Some(60));
+        assert_eq!(rate_limit.tokens_per_minute, Some(200000));
+        assert_eq!(rate_limit.max_wait_ms, 30000);
+        assert!(config.providers["openai"].options.rate_limit.is_none());
+        
+        let invalid = create_test_config().replace(
+            r#""apiKeyParam": "ak","#,
+            r#""apiKeyParam": "ak",
+                        "rateLimit": {"tokensPerMinute": 0},"#,
+        );
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(invalid.as_bytes()).unwrap();
+        assert!(AppConfig::load(file.path()).is_err());
+    }
+    
+    #[test]
+    fn test_yaml_and_toml_config() {
+        let yaml = r#"
+# Cheap default for haiku-class requests
+providers:
+  openai:
+    type: openai
+    baseUrl: https://api.openai.com/v1
+    apiKey: ""
+    models:
+      gpt-4o:
+        name: gpt-4o
+        maxTokens: 8192
+modelMapping:
+  claude-3-5-haiku: openai/gpt-4o
+  sonnet: [openai/gpt-4o]
+"#;
+        let toml = r#"
+# Cheap default for haiku-class requests
+[providers.openai]
+type = "openai"
+baseUrl = "https://api.openai.com/v1"
+apiKey = ""
+
+[providers.openai.models.gpt-4o]
+name = "gpt-4o"
+maxTokens = 8192
+
+[modelMapping]
+claude-3-5-haiku = "openai/gpt-4o"
+sonnet = ["openai/gpt-4o"]
+"#;
+        for (suffix, content) in [(".yaml", yaml), (".yml", yaml), (".toml", toml)] {
+            let mut file = tempfile::Builder::new().suffix(suffix).tempfile().unwrap();
+            file.write_all(content.as_bytes()).unwrap();
+            
+            let config = AppConfig::load(file.path()).unwrap();
+            assert_eq!(config.providers["openai"].models["gpt-4o"].max_tokens, Some(8192));
+            assert_eq!(config.resolve_claude_model("claude-3-5-haiku"), Some("openai/gpt-4o"));
+            assert_eq!(config.resolve_claude_model_chain("sonnet"), Some(vec!["openai/gpt-4o"]));
+        }
+        
+        // The extension picks the parser, so JSON in a .toml file is a TOML error
+        let mut file = tempfile::Builder::new().suffix(".toml").tempfile().unwrap();
+        file.write_all(create_test_config().as_bytes()).unwrap();
+        let error = AppConfig::load(file.path()).unwrap_err();
+        assert!(error.to_string().contains("TOML"));
+    }
+    
+    #[test]
+    fn test_config_includes() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("clients")).unwrap();
+        std::fs::write(dir.path().join("providers.yaml"), r#"
+providers:
+  openai:
+    type: openai
+    baseUrl: https://api.openai.com/v1
+    apiKey: ""
+    models:
+      gpt-4o: { name: gpt-4o, maxTokens: 4096 }
+      gpt-4o-mini: { name: gpt-4o-mini }
+"#).unwrap();
+        std::fs::write(dir.path().join("mapping.toml"), r#"
+[modelMapping]
+sonnet = "openai/gpt-4o"
+haiku = "openai/gpt-4o"
+"#).unwrap();
+        std::fs::write(dir.path().join("clients/b.json"), format!(r#"{{"auth": {{"keys": [{{"name": "b", "keyHash": "{}"}}]}}}}"#, "b".repeat(64))).unwrap();
+        std::fs::write(dir.path().join("clients/a.json"), format!(r#"{{"auth": {{"keys": [{{"name": "a", "keyHash": "{}"}}]}}}}"#, "a".repeat(64))).unwrap();
+        std::fs::write(dir.path().join("clients/notes.txt"), "not a config file").unwrap();
+        let main_path = dir.path().join("aiapiproxy.json");
+        std::fs::write(&main_path, r#"{
+            "include": ["providers.yaml", "mapping.toml", "clients"],
+            "providers": {"openai": {"models": {"gpt-4o": {"name": "gpt-4o", "maxTokens": 8192}}}},
+            "modelMapping": {"haiku": "openai/gpt-4o-mini"}
+        }"#).unwrap();
+        
+        let config = AppConfig::load(&main_path).unwrap();
+        // The including file overrides its includes key by key
+        let openai = &config.providers["openai"];
+        assert_eq!(openai.base_url, "https://api.openai.com/v1");
+        assert_eq!(openai.models["gpt-4o"].max_tokens, Some(8192));
+        assert!(openai.models.contains_key("gpt-4o-mini"));
+        assert_eq!(config.resolve_claude_model("sonnet"), Some("openai/gpt-4o"));
+        assert_eq!(config.resolve_claude_model("haiku"), Some("openai/gpt-4o-mini"));
+        // Directory files merge in name order; arrays are replaced, not appended
+        let keys = &config.auth.as_ref().unwrap().keys;
+        assert_eq!(keys.len(), 1);
+        assert_eq!(keys[0].name, "b");
+        assert_eq!(config.source_files, vec![
+            main_path.clone(),
+            dir.path().join("providers.yaml"),
+            dir.path().join("mapping.toml"),
+            dir.path().join("clients/a.json"),
+            dir.path().join("clients/b.json"),
+        ]);
+        
+        // Cycles are rejected
+        std::fs::write(dir.path().join("providers.yaml"), "include: aiapiproxy.json\n").unwrap();
+        let error = AppConfig::load(&main_path).unwrap_err();
+        assert!(format!("{:#}", error).contains("includes itself"));
+        
+        // Also when the path is spelled differently
+        std::fs::write(dir.path().join("providers.yaml"), "{}\n").unwrap();
+        std::fs::write(dir.path().join("clients/a.json"), r#"{"include": "../clients/../aiapiproxy.json"}"#).unwrap();
+        let error = AppConfig::load(&main_path).unwrap_err();
+        assert!(format!("{:#}", error).contains("includes itself"));
+        
+        // Or reached through a symlink
+        #[cfg(unix)]
+        {
+            std::fs::write(dir.path().join("clients/a.json"), r#"{"include": "../main.json"}"#).unwrap();
+            std::os::unix::fs::symlink(&main_path, dir.path().join("main.json")).unwrap();
+            let error = AppConfig::load(&main_path).unwrap_err();
+            assert!(format!("{:#}", error).contains("includes itself"));
         }
+    }
+    
+    #[test]
+    fn test_thought_cache_config() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(create_test_config().as_bytes()).unwrap();
+        let config = AppConfig::load(file.path()).unwrap();
+        assert_eq!(config.thought_cache.capacity, 1000);
+        assert_eq!(config.thought_cache.ttl_secs, 86400);
+        assert!(config.thought_cache.persist_path.is_none());
+        
+        let config_str = create_test_config().replace(
+            r#""modelMapping": {"#,
+            r#""thoughtCache": {"capacity": 5000, "ttlSecs": 3600, "persistPath": "/var/lib/aiapiproxy/thoughts.json"},
+            "modelMapping": {"#,
+        );
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(config_str.as_bytes()).unwrap();
+        let config = AppConfig::load(file.path()).unwrap();
+        assert_eq!(config.thought_cache.capacity, 5000);
+        assert_eq!(config.thought_cache.ttl_secs, 3600);
+        assert_eq!(config.thought_cache.persist_path, Some(PathBuf::from("/var/lib/aiapiproxy/thoughts.json")));
+        assert_eq!(config.thought_cache.persist_interval_secs, 30);
+        
+        let invalid = create_test_config().replace(
+            r#""modelMapping": {"#,
+            r#""thoughtCache": {"capacity": 0},
+            "modelMapping": {"#,
+        );
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(invalid.as_bytes()).unwrap();
+        assert!(AppConfig::load(file.path()).is_err());
+    }
+    
+    #[test]
+    fn test_error_reports_config() {
+        let config_str = create_test_config().replace(
+            r#""modelMapping": {"#,
+            r#""errorReports": {"burstErrors": 5},
+            "modelMapping": {"#,
+        );
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(config_str.as_bytes()).unwrap();
+        let config = AppConfig::load(file.path()).unwrap();
+        let error_reports = config.error_reports.unwrap();
+        assert_eq!(error_reports.burst_errors, 5);
+        assert_eq!(error_reports.burst_window_secs, 60);
+        assert_eq!(error_reports.max_payload_bytes, 4096);
+        
+        let sentry = config_str.replace(r#""burstErrors": 5"#, r#""sentryDsn": "https://key@o1.ingest.sentry.io/2""#);
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(sentry.as_bytes()).unwrap();
+        assert_eq!(AppConfig::load(file.path()).is_ok(), cfg!(feature = "sentry"));
+        
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(config_str.replace(r#""burstErrors": 5"#, r#""burstErrors": 0"#).as_bytes()).unwrap();
+        assert!(AppConfig::load(file.path()).is_err());
+    }
+    
+    #[test]
+    fn test_transcripts_config() {
+        let config_str = create_test_config().replace(
+            r#""modelMapping": {"#,
+            r#""transcripts": {"directory": "/var/lib/aiapiproxy/transcripts"},
+            "modelMapping": {"#,
+        );
+        let config: AppConfig = serde_json::from_str(&config_str).unwrap();
+        assert!(config.validate().is_ok());
+        let transcripts = config.transcripts.unwrap();
+        assert_eq!(transcripts.directory.unwrap(), PathBuf::from("/var/lib/aiapiproxy/transcripts"));
+        
+        let sqlite = config_str.replace(r#""directory""#, r#""sqlitePath""#);
+        let config: AppConfig = serde_json::from_str(&sqlite).unwrap();
+        assert_eq!(config.validate().is_ok(), cfg!(feature = "sqlite"));
+        
+        let neither = config_str.replace(r#""directory": "/var/lib/aiapiproxy/transcripts""#, "");
+        let config: AppConfig = serde_json::from_str(&neither).unwrap();
+        assert!(config.validate().is_err());
+    }
+    
+    #[test]
+    fn test_state_backend_config() {
+        let config_str = create_test_config().replace(
+            r#""modelMapping": {"#,
+            r#""stateBackend": {"type": "memory"},
+            "modelMapping": {"#,
+        );
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(config_str.as_bytes()).unwrap();
+        let config = AppConfig::load(file.path()).unwrap();
+        let backend = config.state_backend.unwrap();
+        assert_eq!(backend.backend_type, "memory");
+        assert_eq!(backend.key_prefix, "aiapiproxy:");
+        assert_eq!(backend.timeout_ms, 500);
         
-        Ok(())
+        let redis = create_test_config().replace(
+            r#""modelMapping": {"#,
+            r#""stateBackend": {"type": "redis", "url": "redis://localhost:6379/0"},
+            "modelMapping": {"#,
+        );
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(redis.as_bytes()).unwrap();
+        assert_eq!(AppConfig::load(file.path()).is_ok(), cfg!(feature = "redis"));
+        
+        let missing_url = create_test_config().replace(
+            r#""modelMapping": {"#,
+            r#""stateBackend": {"type": "redis"},
+            "modelMapping": {"#,
+        );
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(missing_url.as_bytes()).unwrap();
+        assert!(AppConfig::load(file.path()).is_err());
     }
     
-    /// Get provider and model configuration by path (e.g., "provider/model")
-    pub fn get_provider_model(&self, path: &str) -> Option<(&ProviderConfig, &ModelConfig)> {
-        let parts: Vec<&str> = path.splitn(2, '/').collect();
-        if parts.len() != 2 {
-            return None;
-        }
+    #[test]
+    fn test_auth_config() {
+        let hash = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        let config_str = create_test_config().replace(
+            r#""modelMapping": {"#,
+            &format!(r#""auth": {{"keys": [{{"name": "laptop", "keyHash": "{}", "tenant": "search", "allowedModels": ["claude-3-5-haiku*"], "requestsPerMinute": 60}}]}},
+            "modelMapping": {{"#, hash),
+        );
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(config_str.as_bytes()).unwrap();
         
-        let provider_name = parts[0];
-        let model_name = parts[1];
+        let config = AppConfig::load(file.path()).unwrap();
+        let auth = config.auth.unwrap();
+        assert_eq!(auth.keys[0].name, "laptop");
+        assert_eq!(auth.keys[0].key_hash, hash);
+        assert_eq!(auth.keys[0].tenant.as_deref(), Some("search"));
+        assert_eq!(auth.keys[0].limits.allowed_models, ["claude-3-5-haiku*"]);
+        assert_eq!(auth.keys[0].limits.requests_per_minute, Some(60));
+        assert!(auth.keys[0].limits.tokens_per_day.is_none());
         
-        let provider = self.providers.get(provider_name)?;
-        let model = provider.models.get(model_name)?;
+        for keys in [r#"[]"#, r#"[{"name": "laptop", "keyHash": "sk-proxy-plaintext"}]"#] {
+            let invalid = create_test_config().replace(
+                r#""modelMapping": {"#,
+                &format!(r#""auth": {{"keys": {}}},
+                "modelMapping": {{"#, keys),
+            );
+            let mut file = NamedTempFile::new().unwrap();
+            file.write_all(invalid.as_bytes()).unwrap();
+            assert!(AppConfig::load(file.path()).is_err());
+        }
+    }
+    
+    #[test]
+    fn test_concurrency_config() {
+        let hash = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        let config_str = create_test_config().replace(
+            r#""modelMapping": {"#,
+            &format!(r#""concurrency": {{"maxConcurrent": 8, "maxQueued": 100}},
+            "auth": {{"keys": [{{"name": "nightly-eval", "keyHash": "{}", "priority": "batch"}}]}},
+            "modelMapping": {{"#, hash),
+        );
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(config_str.as_bytes()).unwrap();
         
-        Some((provider, model))
+        let config = AppConfig::load(file.path()).unwrap();
+        let concurrency = config.concurrency.unwrap();
+        assert_eq!(concurrency.max_concurrent, 8);
+        assert_eq!(concurrency.max_queued, Some(100));
+        assert_eq!(concurrency.queue_timeout_ms, 60000);
+        assert_eq!(config.auth.unwrap().keys[0].priority, Some(Priority::Batch));
+        
+        let invalid = create_test_config().replace(
+            r#""modelMapping": {"#,
+            r#""concurrency": {"maxConcurrent": 0},
+            "modelMapping": {"#,
+        );
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(invalid.as_bytes()).unwrap();
+        assert!(AppConfig::load(file.path()).is_err());
     }
     
-    /// Resolve a Claude model name to provider/model path
-    /// 
-    /// Returns the mapped path if found in modelMapping, otherwise returns None
-    pub fn resolve_claude_model(&self, claude_model: &str) -> Option<&str> {
-        // First check exact match in modelMapping
-        if let Some(path) = self.model_mapping.get(claude_model) {
-            return Some(path.as_str());
-        }
+    #[test]
+    fn test_cors_config() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(create_test_config().as_bytes()).unwrap();
+        let config = AppConfig::load(file.path()).unwrap();
+        assert!(config.server.cors.enabled);
+        assert!(config.server.cors.allows_any_origin());
         
-        // Check pattern matching (e.g., "sonnet" matches any model containing "sonnet")
-        let model_lower = claude_model.to_lowercase();
-        for (pattern, path) in &self.model_mapping {
-            let pattern_lower = pattern.to_lowercase();
-            if model_lower.contains(&pattern_lower) || pattern_lower.contains(&model_lower) {
-                return Some(path.as_str());
-            }
+        let config_str = create_test_config().replace(
+            r#""modelMapping": {"#,
+            r#""server": {"cors": {"allowedOrigins": ["https://app.example.com"], "allowCredentials": true, "maxAgeSecs": 600}},
+            "modelMapping": {"#,
+        );
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(config_str.as_bytes()).unwrap();
+        let cors = AppConfig::load(file.path()).unwrap().server.cors;
+        assert_eq!(cors.allowed_origins, ["https://app.example.com"]);
+        assert!(cors.allow_credentials);
+        assert_eq!(cors.max_age_secs, Some(600));
+        
+        for cors in [
+            r#"{"allowedOrigins": ["*"], "allowCredentials": true}"#,
+            r#"{"allowedOrigins": ["app.example.com"]}"#,
+            r#"{"allowedOrigins": ["https://app.example.com/"]}"#,
+        ] {
+            let invalid = create_test_config().replace(
+                r#""modelMapping": {"#,
+                &format!(r#""server": {{"cors": {}}},
+                "modelMapping": {{"#, cors),
+            );
+            let mut file = NamedTempFile::new().unwrap();
+            file.write_all(invalid.as_bytes()).unwrap();
+            assert!(AppConfig::load(file.path()).is_err());
         }
+    }
+    
+    #[test]
+    fn test_compression_config() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(create_test_config().as_bytes()).unwrap();
+        let compression = AppConfig::load(file.path()).unwrap().server.compression;
+        assert!(compression.enabled);
+        assert_eq!(compression.min_size_bytes, 1024);
+        assert!(!compression.streaming);
         
-        None
+        let config_str = create_test_config().replace(
+            r#""modelMapping": {"#,
+            r#""server": {"compression": {"minSizeBytes": 4096, "streaming": true}},
+            "modelMapping": {"#,
+        );
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(config_str.as_bytes()).unwrap();
+        let compression = AppConfig::load(file.path()).unwrap().server.compression;
+        assert!(compression.enabled);
+        assert_eq!(compression.min_size_bytes, 4096);
+        assert!(compression.streaming);
     }
     
-    /// List all available model paths
-    pub fn list_model_paths(&self) -> Vec<String> {
-        let mut paths = Vec::new();
-        for (provider_name, provider) in &self.providers {
-            for model_name in provider.models.keys() {
-                paths.push(format!("{}/{}", provider_name, model_name));
-            }
+    #[test]
+    fn test_tool_pairing_config() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(create_test_config().as_bytes()).unwrap();
+        assert_eq!(AppConfig::load(file.path()).unwrap().tool_pairing, ToolPairing::Off);
+        
+        let config_str = create_test_config().replace(
+            r#""modelMapping": {"#,
+            r#""toolPairing": "drop",
+            "modelMapping": {"#,
+        );
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(config_str.as_bytes()).unwrap();
+        assert_eq!(AppConfig::load(file.path()).unwrap().tool_pairing, ToolPairing::Drop);
+        
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(config_str.replace(r#""drop""#, r#""fix""#).as_bytes()).unwrap();
+        assert!(AppConfig::load(file.path()).is_err());
+    }
+    
+    #[test]
+    fn test_tls_config() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(create_test_config().as_bytes()).unwrap();
+        assert!(AppConfig::load(file.path()).unwrap().server.tls.is_none());
+        
+        let config_str = create_test_config().replace(
+            r#""modelMapping": {"#,
+            r#""server": {"host": "0.0.0.0", "port": 8443, "tls": {"certPath": "/etc/proxy/fullchain.pem", "keyPath": "/etc/proxy/privkey.pem", "reloadIntervalSecs": 3600}},
+            "modelMapping": {"#,
+        );
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(config_str.as_bytes()).unwrap();
+        let tls = AppConfig::load(file.path()).unwrap().server.tls.unwrap();
+        assert_eq!(tls.cert_path, PathBuf::from("/etc/proxy/fullchain.pem"));
+        assert_eq!(tls.key_path, PathBuf::from("/etc/proxy/privkey.pem"));
+        assert_eq!(tls.reload_interval_secs, Some(3600));
+        
+        for tls in [
+            r#"{"certPath": "", "keyPath": "/etc/proxy/privkey.pem"}"#,
+            r#"{"certPath": "/etc/proxy/fullchain.pem"}"#,
+            r#"{"certPath": "/etc/proxy/fullchain.pem", "keyPath": "/etc/proxy/privkey.pem", "reloadIntervalSecs": 0}"#,
+        ] {
+            let invalid = create_test_config().replace(
+                r#""modelMapping": {"#,
+                &format!(r#""server": {{"tls": {}}},
+                "modelMapping": {{"#, tls),
+            );
+            let mut file = NamedTempFile::new().unwrap();
+            file.write_all(invalid.as_bytes()).unwrap();
+            assert!(AppConfig::load(file.path()).is_err());
         }
-        paths
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::io::Write;
-    use tempfile::NamedTempFile;
     
-    fn create_test_config() -> String {
-        r#"{
-            "providers": {
-                "openai": {
-                    "type": "openai",
-                    "baseUrl": "https://api.openai.com/v1",
-                    "apiKey": "",
-                    "models": {
-                        "gpt-4o": {
-                            "name": "gpt-4o",
-                            "maxTokens": 8192
-                        },
-                        "gpt-4o-mini": {
-                            "name": "gpt-4o-mini",
-                            "maxTokens": 4096
-                        }
-                    }
-                },
-                "modelhub-sg1": {
-                    "type": "modelhub",
-                    "baseUrl": "https://modelhub-sg1.example.com",
-                    "apiKey": "",
-                    "options": {
-                        "apiKeyParam": "ak",
-                        "mode": "responses"
-                    },
-                    "models": {
-                        "gpt-5": {
-                            "name": "gpt-5",
-                            "maxTokens": 32768
-                        }
-                    }
-                },
-                "modelhub-gemini": {
-                    "type": "modelhub",
-                    "baseUrl": "https://modelhub-gemini.example.com",
-                    "apiKey": "",
-                    "options": {
-                        "apiKeyParam": "ak",
-                        "mode": "gemini"
-                    },
-                    "models": {
-                        "gemini-2.5-pro": {
-                            "name": "gemini-2.5-pro",
-                            "maxTokens": 65536,
-                            "options": {
-                                "supportsVision": true
-                            }
-                        }
-                    }
-                }
+    #[test]
+    fn test_keep_alive_config() {
+        let config_str = create_test_config().replace(
+            r#""modelMapping": {"#,
+            r#""keepAlive": {
+                "intervalSecs": 10,
+                "routes": {"/v1/chat/completions": {"format": "comment"}}
             },
-            "modelMapping": {
-                "claude-3-sonnet": "modelhub-sg1/gpt-5",
-                "claude-3-opus": "openai/gpt-4o",
-                "sonnet": "modelhub-sg1/gpt-5"
-            }
-        }"#.to_string()
+            "modelMapping": {"#,
+        );
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(config_str.as_bytes()).unwrap();
+        
+        let keep_alive = AppConfig::load(file.path()).unwrap().keep_alive;
+        let messages = keep_alive.route("/v1/messages");
+        assert_eq!((messages.interval(), messages.format()), (Duration::from_secs(10), KeepAliveFormat::Ping));
+        let chat = keep_alive.route("/v1/chat/completions");
+        assert_eq!((chat.interval(), chat.format(), chat.comment()), (Duration::from_secs(10), KeepAliveFormat::Comment, "keep-alive"));
+        assert_eq!(AppConfig::default().keep_alive.route("/v1/messages").interval(), Duration::from_secs(DEFAULT_KEEP_ALIVE_SECS));
+        
+        for invalid in [r#"{"intervalSecs": 0}"#, r#"{"format": "comment", "comment": "a\nb"}"#] {
+            let config_str = create_test_config().replace(
+                r#""modelMapping": {"#,
+                &format!(r#""keepAlive": {}, "modelMapping": {{"#, invalid),
+            );
+            let mut file = NamedTempFile::new().unwrap();
+            file.write_all(config_str.as_bytes()).unwrap();
+            assert!(AppConfig::load(file.path()).is_err(), "{}", invalid);
+        }
     }
     
     #[test]
-    fn test_load_config() {
-        let config_str = create_test_config();
+    fn test_experiments_config() {
+        let config_str = create_test_config().replace(
+            r#""modelMapping": {"#,
+            r#""experiments": [{"name": "glm-rollout", "model": "claude-sonnet-4", "control": "openai/gpt-4o", "treatment": "modelhub-sg1/gpt-5"}],
+            "modelMapping": {"#,
+        );
         let mut file = NamedTempFile::new().unwrap();
         file.write_all(config_str.as_bytes()).unwrap();
         
         let config = AppConfig::load(file.path()).unwrap();
+        let experiment = &config.experiments[0];
+        assert_eq!(experiment.treatment_percent, 50.0);
+        assert!(experiment.matches("claude-sonnet-4-5-20250929"));
+        assert!(!experiment.matches("claude-haiku-4-5"));
         
-        assert_eq!(config.providers.len(), 3);
-        assert!(config.providers.contains_key("openai"));
-        assert!(config.providers.contains_key("modelhub-sg1"));
-        assert!(config.providers.contains_key("modelhub-gemini"));
+        let experiment = r#"{"name": "glm-rollout", "model": "claude-sonnet-4", "control": "openai/gpt-4o", "treatment": "modelhub-sg1/gpt-5"}"#;
+        for invalid in [
+            r#"{"name": "", "model": "claude-sonnet-4", "control": "openai/gpt-4o", "treatment": "openai/gpt-4o-mini"}"#.to_string(),
+            r#"{"name": "glm-rollout", "model": "claude-sonnet-4", "control": "openai/gpt-4o", "treatment": "unknown/glm-4.6"}"#.to_string(),
+            r#"{"name": "glm-rollout", "model": "claude-sonnet-4", "control": "openai/gpt-4o", "treatment": "openai/gpt-4o-mini", "treatmentPercent": 101}"#.to_string(),
+            format!("{}, {}", experiment, experiment),
+        ] {
+            let config_str = create_test_config().replace(
+                r#""modelMapping": {"#,
+                &format!(r#""experiments": [{}], "modelMapping": {{"#, invalid),
+            );
+            let mut file = NamedTempFile::new().unwrap();
+            file.write_all(config_str.as_bytes()).unwrap();
+            assert!(AppConfig::load(file.path()).is_err(), "{}", invalid);
+        }
     }
     
     #[test]
-    fn test_get_provider_model() {
-        let config_str = create_test_config();
+    fn test_shadow_config() {
+        let config_str = create_test_config().replace(
+            r#""modelMapping": {"#,
+            r#""shadow": {"target": "openai/gpt-4o", "percent": 12.5, "path": "/tmp/shadow.jsonl"},
+            "modelMapping": {"#,
+        );
         let mut file = NamedTempFile::new().unwrap();
         file.write_all(config_str.as_bytes()).unwrap();
         
         let config = AppConfig::load(file.path()).unwrap();
+        let shadow = config.shadow.as_ref().unwrap();
+        assert_eq!(shadow.percent, 12.5);
+        assert!(shadow.models.is_empty());
+        assert_eq!(shadow.max_text_bytes, 16384);
+        assert_eq!(shadow.log_config().max_files, 5);
         
-        // Test valid path
-        let result = config.get_provider_model("openai/gpt-4o");
-        assert!(result.is_some());
-        let (provider, model) = result.unwrap();
-        assert_eq!(provider.provider_type, "openai");
-        assert_eq!(model.name, "gpt-4o");
+        for invalid in [
+            r#"{"target": "unknown/gpt-4o", "percent": 10, "path": "/tmp/shadow.jsonl"}"#,
+            r#"{"target": "openai/gpt-4o", "percent": 0, "path": "/tmp/shadow.jsonl"}"#,
+            r#"{"target": "openai/gpt-4o", "percent": 150, "path": "/tmp/shadow.jsonl"}"#,
+        ] {
+            let config_str = create_test_config().replace(
+                r#""modelMapping": {"#,
+                &format!(r#""shadow": {}, "modelMapping": {{"#, invalid),
+            );
+            let mut file = NamedTempFile::new().unwrap();
+            file.write_all(config_str.as_bytes()).unwrap();
+            assert!(AppConfig::load(file.path()).is_err(), "{}", invalid);
+        }
+    }
+    
+    #[test]
+    fn test_forward_headers() {
+        let config_str = create_test_config().replace(
+            r#""modelMapping": {"#,
+            r#""forwardHeaders": ["anthropic-version", "X-Stainless-*", "x-*"],
+            "modelMapping": {"#,
+        );
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(config_str.as_bytes()).unwrap();
         
-        // Test modelhub provider
-        let result = config.get_provider_model("modelhub-sg1/gpt-5");
-        assert!(result.is_some());
-        let (provider, model) = result.unwrap();
-        assert_eq!(provider.provider_type, "modelhub");
-        assert_eq!(provider.options.mode, Some("responses".to_string()));
-        assert_eq!(model.name, "gpt-5");
+        let config = AppConfig::load(file.path()).unwrap();
+        assert!(config.forwards_header("anthropic-version"));
+        assert!(config.forwards_header("x-stainless-os"));
+        assert!(config.forwards_header("X-Trace-Id"));
+        assert!(!config.forwards_header("anthropic-beta"));
+        assert!(!config.forwards_header("x-api-key"));
+        assert!(!AppConfig::default().forwards_header("anthropic-version"));
         
-        // Test invalid path
-        assert!(config.get_provider_model("invalid").is_none());
-        assert!(config.get_provider_model("openai/nonexistent").is_none());
+        for invalid in [r#""""#, r#""*""#, r#""x-*-id""#, r#""Authorization""#, r#""x trace""#] {
+            let config_str = create_test_config().replace(
+                r#""modelMapping": {"#,
+                &format!(r#""forwardHeaders": [{}], "modelMapping": {{"#, invalid),
+            );
+            let mut file = NamedTempFile::new().unwrap();
+            file.write_all(config_str.as_bytes()).unwrap();
+            assert!(AppConfig::load(file.path()).is_err(), "{}", invalid);
+        }
     }
     
     #[test]
-    fn test_list_model_paths() {
-        let config_str = create_test_config();
+    fn test_timeout_config() {
+        let config_str = create_test_config().replace(
+            r#""modelMapping": {"#,
+            r#""timeouts": {
+                "requestMs": 120000,
+                "streamFirstTokenMs": 60000,
+                "routes": {"/v1/messages/count_tokens": {"requestMs": 5000}}
+            },
+            "modelMapping": {"#,
+        );
         let mut file = NamedTempFile::new().unwrap();
         file.write_all(config_str.as_bytes()).unwrap();
         
-        let config = AppConfig::load(file.path()).unwrap();
-        let paths = config.list_model_paths();
+        let timeouts = AppConfig::load(file.path()).unwrap().timeouts.unwrap();
+        let messages = timeouts.budget("/v1/messages");
+        assert_eq!((messages.request_ms, messages.stream_first_token_ms), (Some(120000), Some(60000)));
+        let count_tokens = timeouts.budget("/v1/messages/count_tokens");
+        assert_eq!((count_tokens.request_ms, count_tokens.stream_first_token_ms), (Some(5000), Some(60000)));
         
-        assert!(paths.contains(&"openai/gpt-4o".to_string()));
-        assert!(paths.contains(&"openai/gpt-4o-mini".to_string()));
-        assert!(paths.contains(&"modelhub-sg1/gpt-5".to_string()));
-        assert!(paths.contains(&"modelhub-gemini/gemini-2.5-pro".to_string()));
+        let invalid = create_test_config().replace(
+            r#""modelMapping": {"#,
+            r#""timeouts": {"routes": {"/v1/messages": {"streamFirstTokenMs": 0}}},
+            "modelMapping": {"#,
+        );
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(invalid.as_bytes()).unwrap();
+        assert!(AppConfig::load(file.path()).is_err());
     }
     
     #[test]
-    fn test_validation_empty_providers() {
-        let config_str = r#"{"providers": {}}"#;
+    fn test_pricing_and_budgets() {
+        let hash = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        let config_str = create_test_config().replace(
+            r#""modelMapping": {"#,
+            &format!(r#""pricing": {{"openai/gpt-4o": {{"inputPerMTok": 2.5, "outputPerMTok": 10, "cachedInputPerMTok": 1.25}}}},
+            "auth": {{
+                "keys": [{{"name": "laptop", "keyHash": "{}", "tenant": "search", "dailyBudget": 5}}],
+                "tenants": {{"search": {{"monthlyBudget": 100}}}}
+            }},
+            "modelMapping": {{"#, hash),
+        );
         let mut file = NamedTempFile::new().unwrap();
         file.write_all(config_str.as_bytes()).unwrap();
         
-        let result = AppConfig::load(file.path());
-        assert!(result.is_err());
+        let config = AppConfig::load(file.path()).unwrap();
+        let pricing = config.pricing["openai/gpt-4o"];
+        assert_eq!(pricing.cost(1_000_000, 0, 100_000), 3.5);
+        assert_eq!(pricing.cost(0, 1_000_000, 0), 1.25);
+        let auth = config.auth.unwrap();
+        assert_eq!(auth.keys[0].limits.budget.daily, Some(5.0));
+        assert!(auth.keys[0].limits.budget.monthly.is_none());
+        assert_eq!(auth.tenants["search"].monthly, Some(100.0));
+        
+        for invalid in [
+            r#""pricing": {"openai/gpt-4o": {"inputPerMTok": -1, "outputPerMTok": 10}},"#.to_string(),
+            format!(r#""auth": {{"keys": [{{"name": "laptop", "keyHash": "{}", "monthlyBudget": -5}}]}},"#, hash),
+        ] {
+            let invalid = create_test_config().replace(r#""modelMapping": {"#, &format!("{}\n\"modelMapping\": {{", invalid));
+            let mut file = NamedTempFile::new().unwrap();
+            file.write_all(invalid.as_bytes()).unwrap();
+            assert!(AppConfig::load(file.path()).is_err());
+        }
     }
     
     #[test]
-    fn test_validation_invalid_provider_type() {
-        let config_str = r#"{
-            "providers": {
-                "test": {
-                    "type": "invalid_type",
-                    "baseUrl": "https://example.com",
-                    "models": {
-                        "model1": {"name": "model1"}
-                    }
-                }
-            }
-        }"#;
+    fn test_match_routing_rule() {
+        let config_str = create_test_config().replace(
+            r#""modelMapping": {"#,
+            r#""routingRules": [
+                {"metadata": {"team": "search"}, "model": "opus", "target": "modelhub-gemini/gemini-2.5-pro"},
+                {"metadata": {"team": "search"}, "target": "openai/gpt-4o-mini"}
+            ],
+            "modelMapping": {"#,
+        );
         let mut file = NamedTempFile::new().unwrap();
         file.write_all(config_str.as_bytes()).unwrap();
         
-        let result = AppConfig::load(file.path());
-        assert!(result.is_err());
+        let config = AppConfig::load(file.path()).unwrap();
+        let metadata = |team: &str| HashMap::from([
+            ("user_id".to_string(), serde_json::json!("user_1")),
+            ("team".to_string(), serde_json::json!(team)),
+        ]);
+        
+        // First matching rule wins
+        assert_eq!(config.match_routing_rule("claude-3-opus", Some(&metadata("search"))), Some("modelhub-gemini/gemini-2.5-pro"));
+        assert_eq!(config.match_routing_rule("claude-3-sonnet", Some(&metadata("search"))), Some("openai/gpt-4o-mini"));
+        
+        // Metadata must match
+        assert!(config.match_routing_rule("claude-3-sonnet", Some(&metadata("infra"))).is_none());
+        assert!(config.match_routing_rule("claude-3-sonnet", None).is_none());
     }
     
     #[test]
-    fn test_validation_invalid_mode() {
-        let config_str = r#"{
-            "providers": {
-                "test": {
-                    "type": "modelhub",
-                    "baseUrl": "https://example.com",
-                    "options": {
-                        "mode": "invalid_mode"
-                    },
-                    "models": {
-                        "model1": {"name": "model1"}
-                    }
-                }
-            }
-        }"#;
+    fn test_validation_invalid_routing_rule() {
+        let config_str = create_test_config().replace(
+            r#""modelMapping": {"#,
+            r#""routingRules": [{"metadata": {"team": "search"}, "target": "unknown/gpt-4o"}],
+            "modelMapping": {"#,
+        );
         let mut file = NamedTempFile::new().unwrap();
         file.write_all(config_str.as_bytes()).unwrap();
         
@@ -460,24 +3766,115 @@ mod tests {
     }
     
     #[test]
-    fn test_resolve_claude_model() {
-        let config_str = create_test_config();
+    fn test_prompt_rules() {
+        let config_str = create_test_config().replace(
+            r#""modelMapping": {"#,
+            r#""promptRules": [
+                {"prepend": "Follow the ACME coding standards."},
+                {"model": "haiku", "stripBoilerplate": true, "replace": [{"pattern": "Claude (\\w+)", "with": "$1"}]}
+            ],
+            "modelMapping": {"#,
+        );
         let mut file = NamedTempFile::new().unwrap();
         file.write_all(config_str.as_bytes()).unwrap();
         
         let config = AppConfig::load(file.path()).unwrap();
+        assert_eq!(config.prompt_rules.len(), 2);
+        assert!(config.prompt_rules[0].matches("claude-sonnet-4-5"));
+        assert!(!config.prompt_rules[1].matches("claude-sonnet-4-5"));
+        assert!(config.prompt_rules[1].strip_boilerplate);
+        assert_eq!(config.prompt_rules[1].replace[0].pattern, r"Claude (\w+)");
         
-        // Exact match
-        assert_eq!(config.resolve_claude_model("claude-3-sonnet"), Some("modelhub-sg1/gpt-5"));
-        assert_eq!(config.resolve_claude_model("claude-3-opus"), Some("openai/gpt-4o"));
+        let invalid = config_str.replace(r#""Claude (\\w+)""#, r#""Claude (""#);
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(invalid.as_bytes()).unwrap();
+        let error = AppConfig::load(file.path()).unwrap_err();
+        assert!(error.to_string().contains("Invalid promptRules pattern"));
+    }
+    
+    #[test]
+    fn test_payload_logging_config() {
+        let config: AppConfig = serde_json::from_str(&create_test_config()).unwrap();
+        assert_eq!(config.payload_logging.mode, PayloadLogMode::Summary);
+        assert!(config.payload_logging.mask_secrets);
+        assert!(!config.payload_logging.redact_content);
         
-        // Pattern match (contains)
-        assert_eq!(config.resolve_claude_model("claude-3-sonnet-20240620"), Some("modelhub-sg1/gpt-5"));
+        let config_str = create_test_config().replace(
+            r#""modelMapping": {"#,
+            r#""payloadLogging": {"mode": "full", "redactContent": true},
+            "modelMapping": {"#,
+        );
+        let config: AppConfig = serde_json::from_str(&config_str).unwrap();
+        assert_eq!(config.payload_logging.mode, PayloadLogMode::Full);
+        assert!(config.payload_logging.redact_content);
+        assert!(serde_json::from_str::<AppConfig>(&config_str.replace("full", "verbose")).is_err());
+    }
+    
+    #[test]
+    fn test_redaction_config() {
+        let config_str = create_test_config().replace(
+            r#""modelMapping": {"#,
+            r#""redaction": {"builtins": ["email"], "patterns": [{"name": "employee_id", "pattern": "EMP-\\d{6}"}]},
+            "modelMapping": {"#,
+        );
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(config_str.as_bytes()).unwrap();
         
-        // Short alias
-        assert_eq!(config.resolve_claude_model("sonnet"), Some("modelhub-sg1/gpt-5"));
+        let config = AppConfig::load(file.path()).unwrap();
+        let redaction = config.redaction.unwrap();
+        assert_eq!(redaction.builtins, vec!["email"]);
+        assert_eq!(redaction.patterns[0].pattern, r"EMP-\d{6}");
+        assert!(!redaction.restore);
         
-        // Not found
-        assert!(config.resolve_claude_model("unknown-model").is_none());
+        for (invalid, message) in [
+            (config_str.replace(r#"["email"]"#, r#"["ssn"]"#), "Invalid redaction builtin"),
+            (config_str.replace("employee_id", "employee id"), "Invalid redaction pattern name"),
+            (config_str.replace(r#"EMP-\\d{6}"#, "EMP-("), "Invalid redaction pattern"),
+        ] {
+            let mut file = NamedTempFile::new().unwrap();
+            file.write_all(invalid.as_bytes()).unwrap();
+            let error = AppConfig::load(file.path()).unwrap_err();
+            assert!(error.to_string().contains(message), "{}", error);
+        }
+    }
+    
+    #[test]
+    fn test_guardrails_config() {
+        let config_str = create_test_config().replace(
+            r#""modelMapping": {"#,
+            r#""guardrails": {"rules": [
+                {"name": "credentials", "pattern": "password=\\S+", "action": "redact"},
+                {"name": "competitors", "keywords": ["Acme"], "action": "annotate", "scope": "completion"},
+                {"name": "weapons", "denyTopic": {"terms": ["explosive", "detonator"], "minMatches": 2}}
+            ]},
+            "modelMapping": {"#,
+        );
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(config_str.as_bytes()).unwrap();
+        
+        let config = AppConfig::load(file.path()).unwrap();
+        let guardrails = config.guardrails.unwrap();
+        assert_eq!(guardrails.rules.len(), 3);
+        assert_eq!(guardrails.rules[0].action, GuardrailAction::Redact);
+        assert_eq!(guardrails.rules[0].replacement, "[FILTERED]");
+        assert_eq!(guardrails.rules[1].scope, GuardrailScope::Completion);
+        assert_eq!(guardrails.rules[2].action, GuardrailAction::Block);
+        assert_eq!(guardrails.rules[2].scope, GuardrailScope::Both);
+        assert_eq!(guardrails.rules[2].deny_topic.as_ref().unwrap().min_matches, 2);
+        assert_eq!(guardrails.message, "This content was blocked by a content filter.");
+        
+        for (invalid, message) in [
+            (config_str.replace(r#""name": "competitors""#, r#""name": "credentials""#), "Duplicate guardrail rule"),
+            (config_str.replace(r#""keywords": ["Acme"]"#, r#""keywords": ["Acme"], "pattern": "acme""#), "needs exactly one of"),
+            (config_str.replace(r#""keywords": ["Acme"]"#, r#""keywords": []"#), "needs exactly one of"),
+            (config_str.replace(r#"password=\\S+"#, "password=("), "Invalid guardrail pattern"),
+            (config_str.replace(r#""minMatches": 2"#, r#""minMatches": 3"#), "Invalid minMatches 3"),
+            (config_str.replace(r#"["Acme"]"#, r#"[" "]"#), "has an empty term"),
+        ] {
+            let mut file = NamedTempFile::new().unwrap();
+            file.write_all(invalid.as_bytes()).unwrap();
+            let error = AppConfig::load(file.path()).unwrap_err();
+            assert!(error.to_string().contains(message), "{}", error);
+        }
     }
 }
@@ -3,7 +3,10 @@
 //! Responsible for loading and managing application configuration, including environment variables, configuration files, etc.
 
 pub mod file;
+pub mod remote;
+pub mod secrets;
 pub mod settings;
 
-pub use file::{AppConfig, ModelConfig, ProviderConfig, ProviderOptions, ServerConfig};
+pub use file::{AdaptiveRoutingConfig, AnomalyLogConfig, ApiKeyConfig, AppConfig, AuditLogConfig, AuthConfig, Budget, CompressionConfig, ConcurrencyConfig, CorsConfig, DenyTopic, ErrorReportsConfig, ExperimentConfig, GuardrailAction, GuardrailRule, GuardrailScope, GuardrailsConfig, KeepAlive, KeepAliveConfig, KeepAliveFormat, KeyLimits, ModelConfig, ModelPricing, ModelTarget, MultipleChoicesMode, OrphanToolCalls, PayloadLogMode, PayloadLoggingConfig, PoolConfig, Priority, PromptReplacement, PromptRule, ProviderConfig, ProviderOptions, RateLimitConfig, RedactionConfig, RedactionPattern, RequestLogConfig, RequestParams, RetryConfig, RoutingRule, ServerConfig, ShadowConfig, StateBackendConfig, ThoughtCacheConfig, TimeoutBudget, TimeoutConfig, TlsConfig, ToolPairing, TranscriptsConfig, UpstreamTlsConfig, UsageStoreConfig, WebhookConfig, WeightedTarget};
+pub use remote::ConfigSource;
 pub use settings::Settings;
\ No newline at end of file
@@ -0,0 +1,296 @@
+//! Configuration fetched from a remote source
+//!
+//! `--config` (or `AIAPIPROXY_CONFIG`) can name a URL instead of a file, so a
+//! fleet of proxies shares centrally managed providers and model mappings:
+//!
+//! - `https://config.corp/aiapiproxy.yaml` is fetched with a GET request;
+//!   polls send the last `ETag` in `If-None-Match`
+//! - `consul://consul:8500/aiapiproxy/config.json` reads a Consul KV key,
+//!   with the ACL token of `CONSUL_HTTP_TOKEN`
+//! - `etcd://etcd:2379/aiapiproxy/config.json` reads an etcd key through the
+//!   v3 JSON gateway
+//!
+//! The document's format follows the extension of the URL path or key (JSON
+//! otherwise). A remote document cannot use `include`.
+
+use super::file::parse_config;
+use super::AppConfig;
+use anyhow::{Context, Result};
+use base64::prelude::*;
+use reqwest::header::{ETAG, IF_NONE_MATCH};
+use reqwest::{StatusCode, Url};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::debug;
+
+/// Timeout of one fetch
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Environment variable with the Consul ACL token
+const CONSUL_TOKEN_ENV_VAR: &str = "CONSUL_HTTP_TOKEN";
+
+/// Where the configuration is loaded from
+#[derive(Debug, Clone)]
+pub enum ConfigSource {
+    /// A file and the files it includes
+    File(PathBuf),
+    /// A remote document
+    Remote(RemoteSource),
+}
+
+impl ConfigSource {
+    /// Source named by `--config` or `AIAPIPROXY_CONFIG`: a supported URL or a file path
+    pub fn new(location: PathBuf) -> Result<Self> {
+        match location.to_str().map(RemoteSource::parse) {
+            Some(Some(remote)) => Ok(Self::Remote(remote?)),
+            _ => Ok(Self::File(location)),
+        }
+    }
+    
+    /// Load and validate the configuration
+    pub async fn load(&self) -> Result<AppConfig> {
+        match self {
+            Self::File(path) => AppConfig::load(path),
+            Self::Remote(remote) => Ok(remote.fetch(None).await?.context("Remote configuration not modified")?.config),
+        }
+    }
+}
+
+impl fmt::Display for ConfigSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::File(path) => write!(f, "{}", path.display()),
+            Self::Remote(remote) => write!(f, "{}", remote),
+        }
+    }
+}
+
+impl fmt::Display for RemoteSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.location)
+    }
+}
+
+/// Remote configuration document
+#[derive(Debug, Clone)]
+pub struct RemoteSource {
+    location: String,
+    kind: RemoteKind,
+    client: reqwest::Client,
+}
+
+#[derive(Debug, Clone)]
+enum RemoteKind {
+    Http(Url),
+    Consul { url: Url },
+    Etcd { url: Url, key: String },
+}
+
+/// A fetched configuration
+#[derive(Debug)]
+pub struct RemoteConfig {
+    pub config: AppConfig,
+    /// ETag, Consul index, etcd revision or content hash of the document
+    pub version: String,
+}
+
+impl RemoteSource {
+    /// Parse a remote location; `None` if it isn't a URL of a supported scheme
+    pub fn parse(location: &str) -> Option<Result<Self>> {
+        let url = Url::parse(location).ok()?;
+        let kind = match url.scheme() {
+            "http" | "https" => Ok(RemoteKind::Http(url.clone())),
+            "consul" => gateway_url(&url, "/v1/kv/").map(|mut consul| {
+                consul.set_query(Some("raw"));
+                RemoteKind::Consul { url: consul }
+            }),
+            "etcd" => gateway_url(&url, "/v3/kv/range").map(|etcd| RemoteKind::Etcd {
+                url: etcd,
+                key: url.path().trim_start_matches('/').to_string(),
+            }),
+            _ => return None,
+        };
+        let source = kind.and_then(|kind| {
+            let client = reqwest::Client::builder()
+                .timeout(FETCH_TIMEOUT)
+                .build()
+                .context("Failed to create the configuration client")?;
+            Ok(Self { location: location.to_string(), kind, client })
+        });
+        Some(source.with_context(|| format!("Invalid configuration URL '{}'", location)))
+    }
+    
+    /// Fetch the configuration, or `None` if it is still at `version`
+    pub async fn fetch(&self, version: Option<&str>) -> Result<Option<RemoteConfig>> {
+        let fetched = match &self.kind {
+            RemoteKind::Http(url) => self.fetch_http(url, version).await,
+            RemoteKind::Consul { url } => self.fetch_consul(url).await,
+            RemoteKind::Etcd { url, key } => self.fetch_etcd(url, key).await,
+        };
+        let Some((content, fetched_version)) = fetched.with_context(|| format!("Failed to fetch configuration from {}", self.location))? else {
+            return Ok(None);
+        };
+        // Without a version from the source, the content itself tells whether it changed
+        let fetched_version = fetched_version.unwrap_or_else(|| content_hash(&content));
+        if version == Some(fetched_version.as_str()) {
+            debug!("Remote configuration unchanged at version {}", fetched_version);
+            return Ok(None);
+        }
+        
+        let value = parse_config(&content, Path::new(self.document_path()), &self.location)?;
+        if value.get("include").is_some() {
+            anyhow::bail!("Remote configuration {} cannot include files", self.location);
+        }
+        let config = AppConfig::from_value(value, &self.location)?;
+        Ok(Some(RemoteConfig { config, version: fetched_version }))
+    }
+    
+    /// Path whose extension names the document format
+    fn document_path(&self) -> &str {
+        match &self.kind {
+            RemoteKind::Http(url) | RemoteKind::Consul { url } => url.path(),
+            RemoteKind::Etcd { key, .. } => key,
+        }
+    }
+    
+    async fn fetch_http(&self, url: &Url, version: Option<&str>) -> Result<Option<(String, Option<String>)>> {
+        let mut request = self.client.get(url.clone());
+        if let Some(version) = version {
+            request = request.header(IF_NONE_MATCH, version);
+        }
+        let response = request.send().await?;
+        if response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(None);
+        }
+        let response = response.error_for_status()?;
+        let etag = response.headers().get(ETAG)
+            .and_then(|etag| etag.to_str().ok())
+            .map(str::to_string);
+        Ok(Some((response.text().await?, etag)))
+    }
+    
+    async fn fetch_consul(&self, url: &Url) -> Result<Option<(String, Option<String>)>> {
+        let mut request = self.client.get(url.clone());
+        if let Some(token) = std::env::var(CONSUL_TOKEN_ENV_VAR).ok().filter(|token| !token.is_empty()) {
+            request = request.header("X-Consul-Token", token);
+        }
+        let response = request.send().await?.error_for_status()?;
+        let index = response.headers().get("X-Consul-Index")
+            .and_then(|index| index.to_str().ok())
+            .map(|index| format!("consul-{}", index));
+        Ok(Some((response.text().await?, index)))
+    }
+    
+    async fn fetch_etcd(&self, url: &Url, key: &str) -> Result<Option<(String, Option<String>)>> {
+        let body = serde_json::json!({ "key": BASE64_STANDARD.encode(key) });
+        let response: serde_json::Value = self.client.post(url.clone())
+            .json(&body)
+            .send().await?
+            .error_for_status()?
+            .json().await?;
+        let kv = response["kvs"].get(0).with_context(|| format!("etcd key '{}' not found", key))?;
+        let value = kv["value"].as_str().and_then(|value| BASE64_STANDARD.decode(value).ok())
+            .context("etcd returned an invalid value")?;
+        let content = String::from_utf8(value).context("etcd value is not UTF-8")?;
+        let revision = kv["mod_revision"].as_str().map(|revision| format!("etcd-{}", revision));
+        Ok(Some((content, revision)))
+    }
+}
+
+/// HTTP URL of a Consul or etcd API: `<scheme>://host:port/key` becomes
+/// `http://host:port<api_path>key` for Consul and `http://host:port<api_path>` for etcd
+fn gateway_url(url: &Url, api_path: &str) -> Result<Url> {
+    let host = url.host_str().context("missing host")?;
+    let key = url.path().trim_start_matches('/');
+    if key.is_empty() {
+        anyhow::bail!("missing key");
+    }
+    let port = url.port().map(|port| format!(":{}", port)).unwrap_or_default();
+    let path = if api_path.ends_with('/') { format!("{}{}", api_path, key) } else { api_path.to_string() };
+    Ok(Url::parse(&format!("http://{}{}{}", host, port, path))?)
+}
+
+fn content_hash(content: &str) -> String {
+    Sha256::digest(content.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    const CONFIG: &str = "providers:\n  openai:\n    type: openai\n    baseUrl: https://api.openai.com/v1\n    models:\n      gpt-4o:\n        name: gpt-4o\n";
+    
+    #[test]
+    fn test_config_source() {
+        assert!(matches!(ConfigSource::new(PathBuf::from("/etc/aiapiproxy.json")).unwrap(), ConfigSource::File(_)));
+        assert!(matches!(ConfigSource::new(PathBuf::from("https://config.corp/aiapiproxy.yaml")).unwrap(), ConfigSource::Remote(_)));
+        assert!(ConfigSource::new(PathBuf::from("consul://consul:8500/")).is_err());
+        
+        let RemoteKind::Consul { url } = RemoteSource::parse("consul://consul:8500/aiapiproxy/config.yaml").unwrap().unwrap().kind else {
+            panic!("expected a Consul source");
+        };
+        assert_eq!(url.as_str(), "http://consul:8500/v1/kv/aiapiproxy/config.yaml?raw");
+        
+        let RemoteKind::Etcd { url, key } = RemoteSource::parse("etcd://etcd:2379/aiapiproxy/config.json").unwrap().unwrap().kind else {
+            panic!("expected an etcd source");
+        };
+        assert_eq!(url.as_str(), "http://etcd:2379/v3/kv/range");
+        assert_eq!(key, "aiapiproxy/config.json");
+    }
+    
+    #[tokio::test]
+    async fn test_fetch_http_with_etag() {
+        let server = httpmock::MockServer::start();
+        let not_modified = server.mock(|when, then| {
+            when.path("/aiapiproxy.yaml").header("If-None-Match", "\"v1\"");
+            then.status(304);
+        });
+        let config = server.mock(|when, then| {
+            when.path("/aiapiproxy.yaml");
+            then.status(200).header("ETag", "\"v1\"").body(CONFIG);
+        });
+        
+        let remote = RemoteSource::parse(&server.url("/aiapiproxy.yaml")).unwrap().unwrap();
+        let fetched = remote.fetch(None).await.unwrap().unwrap();
+        assert_eq!(fetched.version, "\"v1\"");
+        assert!(fetched.config.providers.contains_key("openai"));
+        config.assert();
+        
+        assert!(remote.fetch(Some(&fetched.version)).await.unwrap().is_none());
+        not_modified.assert();
+    }
+    
+    #[tokio::test]
+    async fn test_fetch_consul_and_etcd() {
+        let server = httpmock::MockServer::start();
+        server.mock(|when, then| {
+            when.path("/v1/kv/aiapiproxy/config.yaml").query_param_exists("raw");
+            then.status(200).header("X-Consul-Index", "42").body(CONFIG);
+        });
+        server.mock(|when, then| {
+            when.method(httpmock::Method::POST)
+                .path("/v3/kv/range")
+                .json_body(serde_json::json!({"key": BASE64_STANDARD.encode("aiapiproxy/config.yaml")}));
+            then.status(200).json_body(serde_json::json!({
+                "kvs": [{"key": "", "value": BASE64_STANDARD.encode(CONFIG), "mod_revision": "7"}]
+            }));
+        });
+        let address = server.address();
+        
+        let consul = RemoteSource::parse(&format!("consul://{}/aiapiproxy/config.yaml", address)).unwrap().unwrap();
+        let fetched = consul.fetch(None).await.unwrap().unwrap();
+        assert_eq!(fetched.version, "consul-42");
+        // The same index means the key is unchanged
+        assert!(consul.fetch(Some("consul-42")).await.unwrap().is_none());
+        
+        let etcd = RemoteSource::parse(&format!("etcd://{}/aiapiproxy/config.yaml", address)).unwrap().unwrap();
+        let fetched = etcd.fetch(None).await.unwrap().unwrap();
+        assert_eq!(fetched.version, "etcd-7");
+        assert!(fetched.config.providers.contains_key("openai"));
+    }
+}
@@ -0,0 +1,110 @@
+//! Provider API keys from secret files and the OS keychain
+//!
+//! `apiKeyFile` reads a provider's key from a file, such as a Docker or
+//! Kubernetes secret mounted under `/run/secrets`; an `apiKey` of the form
+//! `keychain:<service>/<account>` reads it from the OS keychain (requires the
+//! `keychain` feature). Keys are read whenever the configuration is loaded, so
+//! a reload picks up rotated secrets.
+
+use super::AppConfig;
+use anyhow::{Context, Result};
+use std::path::Path;
+use tracing::debug;
+
+/// Prefix of `apiKey` values read from the OS keychain
+pub const KEYCHAIN_SCHEME: &str = "keychain:";
+
+/// Replace `apiKeyFile` and `keychain:` references with the keys they name
+pub fn resolve_api_keys(config: &mut AppConfig) -> Result<()> {
+    for (name, provider) in &mut config.providers {
+        if let Some(path) = &provider.api_key_file {
+            if !provider.api_key.is_empty() {
+                anyhow::bail!("Provider '{}' sets both apiKey and apiKeyFile", name);
+            }
+            provider.api_key = read_key_file(path)
+                .with_context(|| format!("Failed to read the API key of provider '{}' from {}", name, path.display()))?;
+            debug!("🔑 Read the API key of provider '{}' from {}", name, path.display());
+        } else if let Some(entry) = provider.api_key.strip_prefix(KEYCHAIN_SCHEME) {
+            let (service, account) = parse_keychain_entry(entry)?;
+            let key = read_keychain(service, account)
+                .with_context(|| format!("Failed to read the API key of provider '{}' from the keychain", name))?;
+            debug!("🔑 Read the API key of provider '{}' from keychain entry {}/{}", name, service, account);
+            provider.api_key = key;
+        }
+    }
+    Ok(())
+}
+
+/// Read a key file, ignoring surrounding whitespace such as a trailing newline
+fn read_key_file(path: &Path) -> Result<String> {
+    let key = std::fs::read_to_string(path)?;
+    let key = key.trim();
+    if key.is_empty() {
+        anyhow::bail!("{} is empty", path.display());
+    }
+    Ok(key.to_string())
+}
+
+/// Split `<service>/<account>`
+fn parse_keychain_entry(entry: &str) -> Result<(&str, &str)> {
+    entry.split_once('/')
+        .filter(|(service, account)| !service.is_empty() && !account.is_empty())
+        .with_context(|| format!("Invalid apiKey '{}{}': expected {}<service>/<account>", KEYCHAIN_SCHEME, entry, KEYCHAIN_SCHEME))
+}
+
+#[cfg(feature = "keychain")]
+fn read_keychain(service: &str, account: &str) -> Result<String> {
+    Ok(keyring::Entry::new(service, account)?.get_password()?)
+}
+
+#[cfg(not(feature = "keychain"))]
+fn read_keychain(_service: &str, _account: &str) -> Result<String> {
+    anyhow::bail!("Reading API keys from the keychain requires the `keychain` feature")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+    
+    fn load(config: &str) -> Result<AppConfig> {
+        let mut file = NamedTempFile::with_suffix(".json").unwrap();
+        file.write_all(config.as_bytes()).unwrap();
+        AppConfig::load(file.path())
+    }
+    
+    fn provider_config(key: &str) -> String {
+        format!(
+            r#"{{"providers": {{"openai": {{"type": "openai", "baseUrl": "https://api.openai.com/v1", {},
+                "models": {{"gpt-4o": {{"name": "gpt-4o"}}}}}}}}}}"#,
+            key
+        )
+    }
+    
+    #[test]
+    fn test_api_key_file() {
+        let mut secret = NamedTempFile::new().unwrap();
+        secret.write_all(b"sk-from-file\n").unwrap();
+        let key_file = format!(r#""apiKeyFile": {:?}"#, secret.path());
+        
+        let config = load(&provider_config(&key_file)).unwrap();
+        assert_eq!(config.providers["openai"].api_key, "sk-from-file");
+        
+        let both = format!(r#""apiKey": "sk-inline", {}"#, key_file);
+        assert!(load(&provider_config(&both)).unwrap_err().to_string().contains("both apiKey and apiKeyFile"));
+        
+        let missing = provider_config(r#""apiKeyFile": "/nonexistent/openai""#);
+        assert!(load(&missing).unwrap_err().to_string().contains("Failed to read the API key of provider 'openai'"));
+    }
+    
+    #[test]
+    fn test_parse_keychain_entry() {
+        assert_eq!(parse_keychain_entry("aiapiproxy/openai").unwrap(), ("aiapiproxy", "openai"));
+        assert!(parse_keychain_entry("openai").is_err());
+        assert!(parse_keychain_entry("aiapiproxy/").is_err());
+        
+        let invalid = load(&provider_config(r#""apiKey": "keychain:openai""#)).unwrap_err();
+        assert!(invalid.to_string().contains("expected keychain:<service>/<account>"));
+    }
+}
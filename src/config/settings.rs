@@ -65,6 +65,8 @@ pub struct ModelMapping {
 pub struct RequestConfig {
     /// Maximum request size in bytes
     pub max_request_size: usize,
+    /// Maximum size in bytes of requests carrying images
+    pub max_image_request_size: usize,
     /// Maximum concurrent requests
     pub max_concurrent_requests: usize,
     /// Request timeout in seconds
@@ -130,6 +132,9 @@ impl Settings {
                 max_request_size: get_env_or_default("MAX_REQUEST_SIZE", "10485760")
                     .parse()
                     .context("Invalid max request size")?,
+                max_image_request_size: get_env_or_default("MAX_IMAGE_REQUEST_SIZE", "33554432")
+                    .parse()
+                    .context("Invalid max image request size")?,
                 max_concurrent_requests: get_env_or_default("MAX_CONCURRENT_REQUESTS", "100")
                     .parse()
                     .context("Invalid maximum concurrent requests")?,
@@ -189,6 +194,9 @@ impl Settings {
         if self.request.max_request_size == 0 {
             anyhow::bail!("Maximum request size cannot be 0");
         }
+        if self.request.max_image_request_size < self.request.max_request_size {
+            anyhow::bail!("Maximum image request size cannot be below the maximum request size");
+        }
         
         // Validate concurrent request count
         if self.request.max_concurrent_requests == 0 {
@@ -279,6 +287,7 @@ mod tests {
             },
             request: RequestConfig {
                 max_request_size: 1024,
+                max_image_request_size: 4096,
                 max_concurrent_requests: 10,
                 timeout: 30,
             },
@@ -0,0 +1,180 @@
+//! Admin API handlers
+//!
+//! Open to client keys with `admin: true`; requires client authentication
+//! (`/admin/*`, `/debug/*` and `/v1/sessions/:id/transcript`)
+
+use crate::handlers::AppState;
+use crate::middleware::auth::ClientKey;
+use crate::services::audit_log::AuditEntry;
+use crate::services::debug_tap;
+use crate::services::transcripts::{self, TranscriptTurn};
+use crate::utils::error::AppError;
+use crate::utils::logging;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::{extract::{Path, Query, State}, response::Json, Extension};
+use futures::Stream;
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+
+/// Entries returned by `GET /admin/audit` without a `limit`
+const DEFAULT_AUDIT_LIMIT: usize = 20;
+
+#[derive(Debug, Deserialize)]
+pub struct AuditQuery {
+    /// Most recent entries to return (default: 20)
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuditResponse {
+    /// Most recent entries, oldest first
+    entries: Vec<AuditEntry>,
+}
+
+/// Recent configuration changes from the audit log
+///
+/// GET /admin/audit?limit=20
+pub async fn audit(
+    State(state): State<Arc<AppState>>,
+    client: Option<Extension<ClientKey>>,
+    Query(query): Query<AuditQuery>,
+) -> Result<Json<AuditResponse>, AppError> {
+    require_admin(&state, client)?;
+    let Some(audit_log) = &state.audit_log else {
+        return Err(AppError::NotFound("The audit log is not configured (auditLog)".to_string()));
+    };
+    let entries = audit_log.recent(query.limit.unwrap_or(DEFAULT_AUDIT_LIMIT));
+    Ok(Json(AuditResponse { entries }))
+}
+
+#[derive(Debug, Serialize)]
+pub struct TranscriptResponse {
+    session_id: String,
+    /// Requests of the session and their responses, oldest first
+    turns: Vec<TranscriptTurn>,
+}
+
+/// Saved conversation of a Claude Code session
+///
+/// GET /v1/sessions/:id/transcript
+pub async fn session_transcript(
+    State(state): State<Arc<AppState>>,
+    client: Option<Extension<ClientKey>>,
+    Path(session_id): Path<String>,
+) -> Result<Json<TranscriptResponse>, AppError> {
+    require_admin(&state, client)?;
+    let Some(store) = &state.transcripts else {
+        return Err(AppError::NotFound("Transcripts are not configured (transcripts)".to_string()));
+    };
+    transcripts::check_session_id(&session_id).map_err(|e| AppError::Validation(e.to_string()))?;
+    let store = store.clone();
+    let id = session_id.clone();
+    let turns = tokio::task::spawn_blocking(move || store.transcript(&id))
+        .await
+        .map_err(|e| AppError::Internal(e.to_string()))?
+        .map_err(|e| AppError::Internal(format!("{:#}", e)))?;
+    if turns.is_empty() {
+        return Err(AppError::NotFound(format!("No transcript for session {}", session_id)));
+    }
+    Ok(Json(TranscriptResponse { session_id, turns }))
+}
+
+/// Live mirror of the streaming conversions
+///
+/// Starts with an `active` event listing the streams in progress, followed by
+/// `request`, `upstream`, `client` and `end` events of every stream, and a
+/// `lagged` event when this watcher fell behind and events were skipped.
+///
+/// GET /debug/streams
+pub async fn debug_streams(
+    State(state): State<Arc<AppState>>,
+    client: Option<Extension<ClientKey>>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, AppError> {
+    require_admin(&state, client)?;
+    let (active, receiver) = debug_tap::global().subscribe();
+    let first = Event::default().event("active").json_data(&active).unwrap_or_default();
+    
+    let events = futures::stream::unfold((Some(first), receiver), |(first, mut receiver)| async move {
+        if let Some(first) = first {
+            return Some((Ok(first), (None, receiver)));
+        }
+        let event = match receiver.recv().await {
+            Ok(event) => Event::default().event(event.kind).json_data(&event).unwrap_or_default(),
+            Err(RecvError::Lagged(skipped)) => Event::default().event("lagged").data(skipped.to_string()),
+            Err(RecvError::Closed) => return None,
+        };
+        Some((Ok(event), (None, receiver)))
+    });
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LogLevelRequest {
+    /// Log filter directives, e.g. `debug` or `info,aiapiproxy::providers=trace`
+    level: String,
+    /// Restore the previous level after this many seconds (default: keep)
+    duration_secs: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LogLevelResponse {
+    /// Current log filter directives
+    level: String,
+    /// Directives before the change
+    #[serde(skip_serializing_if = "Option::is_none")]
+    previous: Option<String>,
+    /// Seconds until the previous level is restored
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reverts_in_secs: Option<u64>,
+}
+
+/// Current log level
+///
+/// GET /admin/log-level
+pub async fn log_level(
+    State(state): State<Arc<AppState>>,
+    client: Option<Extension<ClientKey>>,
+) -> Result<Json<LogLevelResponse>, AppError> {
+    require_admin(&state, client)?;
+    let level = logging::log_level().ok_or_else(|| AppError::NotFound("The log level can't be changed in this process".to_string()))?;
+    Ok(Json(LogLevelResponse { level, previous: None, reverts_in_secs: None }))
+}
+
+/// Change the log level without a restart, optionally for a limited time
+///
+/// PUT /admin/log-level {"level": "debug", "duration_secs": 300}
+pub async fn set_log_level(
+    State(state): State<Arc<AppState>>,
+    client: Option<Extension<ClientKey>>,
+    Json(request): Json<LogLevelRequest>,
+) -> Result<Json<LogLevelResponse>, AppError> {
+    let client = require_admin(&state, client)?;
+    if request.duration_secs == Some(0) {
+        return Err(AppError::Validation("duration_secs must be greater than 0".to_string()));
+    }
+    if logging::log_level().is_none() {
+        return Err(AppError::NotFound("The log level can't be changed in this process".to_string()));
+    }
+    let previous = logging::set_log_level(&request.level, request.duration_secs.map(Duration::from_secs))
+        .map_err(|e| AppError::Validation(format!("{:#}", e)))?;
+    if let Some(audit_log) = &state.audit_log {
+        let before = serde_json::json!({ "logLevel": previous });
+        let after = serde_json::json!({ "logLevel": request.level });
+        audit_log.record(&client.name, "log_level", None, &before, &after);
+    }
+    Ok(Json(LogLevelResponse { level: request.level, previous: Some(previous), reverts_in_secs: request.duration_secs }))
+}
+
+/// Reject callers that aren't authenticated with an admin key
+fn require_admin(state: &AppState, client: Option<Extension<ClientKey>>) -> Result<ClientKey, AppError> {
+    if state.api_keys.is_none() {
+        return Err(AppError::NotFound("The admin API requires client authentication".to_string()));
+    }
+    match client {
+        Some(Extension(client)) if client.admin => Ok(client),
+        _ => Err(AppError::Authorization("the admin API requires an admin key".to_string())),
+    }
+}
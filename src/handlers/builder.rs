@@ -0,0 +1,192 @@
+//! Router builder
+//!
+//! [`create_router`](super::create_router) builds the whole proxy from its
+//! configuration. [`RouterBuilder`] does the same, and lets an application that
+//! embeds the proxy in its own Axum app supply providers, routes, middleware or
+//! a pre-built [`AppState`].
+
+use super::*;
+use crate::providers::Provider;
+use axum::extract::Request;
+use axum::response::IntoResponse;
+use axum::routing::Route;
+use std::convert::Infallible;
+use tower::{Layer, Service};
+
+type BoxedLayer = Box<dyn FnOnce(Router) -> Router + Send>;
+
+/// Builder of the proxy's Axum router
+///
+/// ```no_run
+/// # async fn example(settings: aiapiproxy::Settings, app_config: aiapiproxy::AppConfig) -> anyhow::Result<()> {
+/// use aiapiproxy::handlers::RouterBuilder;
+///
+/// let (proxy, _state) = RouterBuilder::new(settings, app_config).build()?;
+/// let app = axum::Router::new().nest("/proxy", proxy);
+/// # Ok(())
+/// # }
+/// ```
+pub struct RouterBuilder {
+    settings: Settings,
+    app_config: AppConfig,
+    providers: ProviderRegistry,
+    state: Option<AppState>,
+    routes: Router<SharedState>,
+    layers: Vec<BoxedLayer>,
+}
+
+impl RouterBuilder {
+    /// Builder for the configuration
+    pub fn new(settings: Settings, app_config: AppConfig) -> Self {
+        Self {
+            settings,
+            app_config,
+            providers: ProviderRegistry::new(),
+            state: None,
+            routes: Router::new(),
+            layers: Vec::new(),
+        }
+    }
+    
+    /// Use the registered providers instead of building them from their `type`
+    ///
+    /// Each registered name must be a configured provider.
+    pub fn providers(mut self, registry: ProviderRegistry) -> Self {
+        self.providers = registry;
+        self
+    }
+    
+    /// Use a provider for the configured provider `name`
+    pub fn provider(mut self, name: impl Into<String>, provider: Arc<dyn Provider>) -> Self {
+        self.providers.register(name, provider);
+        self
+    }
+    
+    /// Serve a pre-built state instead of building it from the configuration
+    ///
+    /// The state is replaced on the first [`SharedState::reload`].
+    pub fn state(mut self, state: AppState) -> Self {
+        self.state = Some(state);
+        self
+    }
+    
+    /// Serve extra routes
+    ///
+    /// They are merged before the proxy's middleware, so they get request IDs,
+    /// client key authentication and body limits like the built-in routes.
+    pub fn routes(mut self, routes: Router<SharedState>) -> Self {
+        self.routes = self.routes.merge(routes);
+        self
+    }
+    
+    /// Wrap the router in a layer
+    ///
+    /// Layers wrap the proxy's own middleware, the last added outermost.
+    pub fn layer<L>(mut self, layer: L) -> Self
+    where
+        L: Layer<Route> + Clone + Send + 'static,
+        L::Service: Service<Request> + Clone + Send + 'static,
+        <L::Service as Service<Request>>::Response: IntoResponse + 'static,
+        <L::Service as Service<Request>>::Error: Into<Infallible> + 'static,
+        <L::Service as Service<Request>>::Future: Send + 'static,
+    {
+        self.layers.push(Box::new(move |router: Router| router.layer(layer)));
+        self
+    }
+    
+    /// Build the router, and the state to reload it
+    pub fn build(self) -> Result<(Router, SharedState)> {
+        let Self { settings, app_config, providers, state, routes, layers } = self;
+        
+        let state_backend = match &app_config.state_backend {
+            Some(config) => state_backend::from_config(config)?,
+            None => None,
+        };
+        if let Some(backend) = &state_backend {
+            info!("🗄️ Sharing session state and thought signatures through {}", backend.name());
+        }
+        sessions::global().set_backend(state_backend.clone());
+        
+        let thought_cache = thought_cache::global();
+        if let Err(e) = thought_cache.configure(&app_config.thought_cache) {
+            warn!("Starting with an empty thought signature cache: {:#}", e);
+        }
+        thought_cache.set_backend(state_backend);
+        thought_cache.spawn_persistence();
+        logging::set_payload_policy(&app_config.payload_logging);
+        
+        if let Some(config) = &app_config.error_reports {
+            let reporter = error_reports::global();
+            reporter.configure(config);
+            #[cfg(feature = "sentry")]
+            if config.sentry_dsn.is_some() {
+                reporter.set_sink(Some(Arc::new(error_reports::SentrySink::new(config)?)));
+                info!("🚨 Reporting panics, unparsable responses and provider error bursts to Sentry");
+            }
+            error_reports::install_panic_hook();
+        }
+        
+        let timeouts = app_config.timeouts.clone().map(Arc::new);
+        let cors = app_config.server.cors.enabled.then(|| cors_layer(&app_config.server.cors));
+        let compression = app_config.server.compression.clone();
+        
+        // Create application state
+        let app_state = match state {
+            Some(state) => state,
+            None => build_state(settings.clone(), &app_config, &providers, None)?,
+        };
+        let shared_state = SharedState::new(app_state, app_config, providers);
+        
+        // Create middleware stack
+        let middleware_stack = ServiceBuilder::new()
+            .layer(TraceLayer::new_for_http());
+        
+        // Create routes
+        let mut router = Router::new()
+            .route("/v1/messages", post(proxy::handle_messages))
+            .route("/v1/messages/count_tokens", post(proxy::handle_count_tokens))
+            .route("/usage", get(usage::usage))
+            .route("/admin/audit", get(admin::audit))
+            .route("/v1/sessions/:id/transcript", get(admin::session_transcript))
+            .route("/admin/log-level", get(admin::log_level).put(admin::set_log_level))
+            .route("/debug/streams", get(admin::debug_streams))
+            .route("/health", get(health::health_check))
+            .route("/health/live", get(health::liveness_check))
+            .merge(routes);
+        #[cfg(feature = "metrics")]
+        {
+            router = router.route("/metrics", get(metrics::metrics));
+        }
+        if let Some(timeouts) = timeouts {
+            router = router.layer(axum::middleware::from_fn_with_state(timeouts, request_timeout_middleware));
+        }
+        let body_limits = BodyLimits::from(&settings.request);
+        let mut router = router
+            .layer(axum::middleware::from_fn_with_state(body_limits, body_limit_middleware))
+            .layer(RequestBodyLimitLayer::new(body_limits.max_image_request_size))
+            .layer(DefaultBodyLimit::disable())
+            .layer(axum::middleware::map_response(claude_payload_too_large))
+            .layer(axum::middleware::from_fn_with_state(shared_state.clone(), client_key_middleware))
+            .layer(axum::middleware::from_fn(request_id_middleware))
+            .with_state(shared_state.clone());
+        if compression.enabled {
+            let min_size = SizeAbove::new(compression.min_size_bytes);
+            // The encoder flushes whenever the stream waits for upstream, so SSE
+            // events are not held back
+            router = if compression.streaming {
+                router.layer(CompressionLayer::new().compress_when(min_size))
+            } else {
+                router.layer(CompressionLayer::new().compress_when(min_size.and(NotForContentType::SSE)))
+            };
+        }
+        if let Some(cors) = cors {
+            router = router.layer(cors);
+        }
+        
+        let mut router = router.layer(middleware_stack);
+        for layer in layers {
+            router = layer(router);
+        }
+        Ok((router, shared_state))
+    }
+}
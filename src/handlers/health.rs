@@ -3,7 +3,9 @@
 //! Provides application health status check endpoints
 
 use crate::handlers::AppState;
-use axum::{extract::State, http::StatusCode, response::Json};
+use crate::utils::error::{AppError, AppResult};
+use crate::utils::thought_cache::{self, ThoughtCacheStats};
+use axum::{extract::State, response::Json};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use tracing::debug;
@@ -36,6 +38,9 @@ pub struct HealthDetails {
     /// Memory usage (optional)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub memory_usage: Option<MemoryUsage>,
+    /// Thought signature cache size and hit rate (optional)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thought_cache: Option<ThoughtCacheStats>,
 }
 
 /// Memory usage information
@@ -65,6 +70,7 @@ pub async fn health_check(State(_state): State<Arc<AppState>>) -> Json<HealthRes
             config: "valid".to_string(),
             uptime_seconds: get_uptime_seconds(),
             memory_usage: get_memory_usage(),
+            thought_cache: Some(thought_cache::global().stats()),
         }),
     };
     
@@ -75,7 +81,7 @@ pub async fn health_check(State(_state): State<Arc<AppState>>) -> Json<HealthRes
 /// 
 /// GET /health/ready
 /// Check if the service is ready to receive requests
-pub async fn readiness_check(State(state): State<Arc<AppState>>) -> Result<Json<HealthResponse>, StatusCode> {
+pub async fn readiness_check(State(state): State<Arc<AppState>>) -> AppResult<Json<HealthResponse>> {
     debug!("Executing readiness check");
     
     // Check router status (providers configured)
@@ -100,6 +106,7 @@ pub async fn readiness_check(State(state): State<Arc<AppState>>) -> Result<Json<
         config: config_status,
         uptime_seconds,
         memory_usage,
+        thought_cache: Some(thought_cache::global().stats()),
     };
     
     // Determine overall status
@@ -119,7 +126,7 @@ pub async fn readiness_check(State(state): State<Arc<AppState>>) -> Result<Json<
     
     // Return 503 status code if service is not ready
     if overall_status == "not_ready" {
-        return Err(StatusCode::SERVICE_UNAVAILABLE);
+        return Err(AppError::ServiceUnavailable(provider_status));
     }
     
     Ok(Json(response))
@@ -129,7 +136,7 @@ pub async fn readiness_check(State(state): State<Arc<AppState>>) -> Result<Json<
 /// 
 /// GET /health/live
 /// Check if the service is still running
-pub async fn liveness_check(State(_state): State<Arc<AppState>>) -> Result<Json<HealthResponse>, StatusCode> {
+pub async fn liveness_check(State(_state): State<Arc<AppState>>) -> AppResult<Json<HealthResponse>> {
     debug!("Executing liveness check");
     
     // Liveness check only needs to confirm the service is running
@@ -143,6 +150,7 @@ pub async fn liveness_check(State(_state): State<Arc<AppState>>) -> Result<Json<
         config: "valid".to_string(),
         uptime_seconds,
         memory_usage,
+        thought_cache: None,
     };
     
     let response = HealthResponse {
@@ -252,8 +260,10 @@ mod tests {
         let mut models = HashMap::new();
         models.insert("gpt-4o".to_string(), ModelConfig {
             name: "gpt-4o".to_string(),
+            enabled: true,
             alias: None,
             max_tokens: Some(8192),
+            context_window: None,
             temperature: None,
             options: Default::default(),
         });
@@ -261,16 +271,17 @@ mod tests {
         let mut providers = HashMap::new();
         providers.insert("openai".to_string(), ProviderConfig {
             provider_type: "openai".to_string(),
+            enabled: true,
             base_url: "https://api.openai.com/v1".to_string(),
             api_key: "test_key".to_string(),
+            api_key_file: None,
             options: Default::default(),
             models,
         });
         
-        AppConfig { 
-            server: crate::config::ServerConfig::default(),
+        AppConfig {
             providers,
-            model_mapping: HashMap::new(),
+            ..Default::default()
         }
     }
     
@@ -294,6 +305,7 @@ mod tests {
             },
             request: RequestConfig {
                 max_request_size: 1024,
+                max_image_request_size: 4096,
                 max_concurrent_requests: 10,
                 timeout: 30,
             },
@@ -315,6 +327,17 @@ mod tests {
             settings,
             converter,
             router,
+            api_keys: None,
+            concurrency: None,
+            coalescer: None,
+            redactor: None,
+            guardrails: None,
+            request_log: None,
+            experiments: None,
+            shadow: None,
+            audit_log: None,
+            transcripts: None,
+            hooks: Vec::new(),
         })
     }
     
@@ -0,0 +1,17 @@
+//! Metrics handler
+//! 
+//! Serves the stream metrics in the Prometheus text format
+
+use crate::services::stream_metrics;
+use axum::{http::header, response::IntoResponse};
+
+/// Content type of the Prometheus text exposition format
+const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Time to first token, output tokens per second and duration of streaming
+/// responses, per provider/model
+/// 
+/// GET /metrics
+pub async fn metrics() -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, PROMETHEUS_CONTENT_TYPE)], stream_metrics::global().render())
+}
@@ -2,20 +2,50 @@
 //! 
 //! Contains all HTTP endpoint handling logic
 
+pub mod admin;
+mod builder;
 pub mod health;
+#[cfg(feature = "metrics")]
+pub mod metrics;
 pub mod proxy;
+pub mod usage;
 
-use crate::config::{AppConfig, Settings};
-use crate::services::{ApiConverter, Router as ProviderRouter};
+pub use builder::RouterBuilder;
+
+use crate::config::{AppConfig, CorsConfig, GuardrailsConfig, Settings};
+use crate::middleware::auth::{client_key_middleware, ApiKeyStore};
+use crate::middleware::body_limit::{body_limit_middleware, claude_payload_too_large, BodyLimits};
+use crate::middleware::request_id::request_id_middleware;
+use crate::middleware::timeout::request_timeout_middleware;
+use crate::services::coalescing::RequestCoalescer;
+use crate::services::guardrails::{self, Guardrails};
+use crate::services::prompt_rules::PromptRewriter;
+use crate::services::redaction::Redactor;
+use crate::services::request_log::RequestLog;
+use crate::services::shadow::ShadowTraffic;
+use crate::services::audit_log::AuditLog;
+use crate::services::transcripts::TranscriptStore;
+use crate::services::hooks::{RequestHook, Webhook};
+use crate::services::anomalies::AnomalyLog;
+use crate::services::experiments::Experiments;
+#[cfg(feature = "sqlite")]
+use crate::services::usage_store::UsageStore;
+use crate::services::concurrency::ConcurrencyLimiter;
+use crate::services::{error_reports, sessions, state_backend, ApiConverter, ProviderRegistry, Router as ProviderRouter};
+use crate::utils::{logging, thought_cache};
 use anyhow::Result;
-use axum::{routing::get, routing::post, Router};
-use std::sync::Arc;
+use axum::{extract::{DefaultBodyLimit, FromRef}, routing::get, routing::post, Router};
+use axum::http::HeaderValue;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
 use tower::ServiceBuilder;
 use tower_http::{
-    cors::{Any, CorsLayer},
+    compression::{predicate::{NotForContentType, SizeAbove}, CompressionLayer, Predicate},
+    cors::{AllowHeaders, AllowMethods, Any, CorsLayer},
+    limit::RequestBodyLimitLayer,
     trace::TraceLayer,
 };
-use tracing::info;
+use tracing::{info, warn};
 
 /// Application state
 #[derive(Clone)]
@@ -26,6 +56,29 @@ pub struct AppState {
     pub converter: ApiConverter,
     /// Provider router for multi-provider support
     pub router: Arc<ProviderRouter>,
+    /// Proxy-issued client API keys, when `auth` is configured
+    pub api_keys: Option<Arc<ApiKeyStore>>,
+    /// Limit on concurrent upstream requests, when `concurrency` is configured
+    pub concurrency: Option<Arc<ConcurrencyLimiter>>,
+    /// Single-flight deduplication, with `coalesceRequests` enabled
+    pub coalescer: Option<Arc<RequestCoalescer>>,
+    /// Redaction of outgoing message content, when `redaction` is configured
+    pub redactor: Option<Arc<Redactor>>,
+    /// Content checks of prompts and completions, when `guardrails` is
+    /// configured or checks were registered
+    pub guardrails: Option<Arc<Guardrails>>,
+    /// Log of completed requests, when `requestLog` is configured
+    pub request_log: Option<Arc<RequestLog>>,
+    /// A/B experiments, when `experiments` are configured
+    pub experiments: Option<Arc<Experiments>>,
+    /// Requests mirrored to a candidate upstream, when `shadow` is configured
+    pub shadow: Option<Arc<ShadowTraffic>>,
+    /// Audit log of configuration changes, when `auditLog` is configured
+    pub audit_log: Option<Arc<AuditLog>>,
+    /// Transcripts of sessions, when `transcripts` is configured
+    pub transcripts: Option<Arc<TranscriptStore>>,
+    /// Called after each request: webhooks, the anomaly log and the usage store
+    pub hooks: Vec<Arc<dyn RequestHook>>,
 }
 
 impl std::fmt::Debug for AppState {
@@ -34,12 +87,105 @@ impl std::fmt::Debug for AppState {
             .field("settings", &self.settings)
             .field("converter", &"ApiConverter")
             .field("router", &"ProviderRouter")
+            .field("api_keys", &self.api_keys.is_some())
+            .field("concurrency", &self.concurrency.is_some())
+            .field("coalescer", &self.coalescer.is_some())
+            .field("redactor", &self.redactor.is_some())
+            .field("guardrails", &self.guardrails.is_some())
+            .field("request_log", &self.request_log.is_some())
+            .field("experiments", &self.experiments.is_some())
+            .field("shadow", &self.shadow.is_some())
+            .field("audit_log", &self.audit_log.is_some())
+            .field("transcripts", &self.transcripts.is_some())
+            .field("hooks", &self.hooks.len())
             .finish()
     }
 }
 
-/// Create application router with JSON config
-pub async fn create_router(settings: Settings, app_config: AppConfig) -> Result<Router> {
+/// Application state of the current configuration
+///
+/// Routes extract `State<Arc<AppState>>` from it, so a request keeps the state
+/// it started with while [`SharedState::reload`] swaps in a new configuration.
+#[derive(Clone)]
+pub struct SharedState {
+    current: Arc<RwLock<CurrentState>>,
+    /// Providers supplied by the embedding application, kept across reloads
+    registry: ProviderRegistry,
+}
+
+struct CurrentState {
+    state: Arc<AppState>,
+    config: AppConfig,
+}
+
+impl FromRef<SharedState> for Arc<AppState> {
+    fn from_ref(shared: &SharedState) -> Self {
+        shared.state()
+    }
+}
+
+impl SharedState {
+    fn new(state: AppState, config: AppConfig, registry: ProviderRegistry) -> Self {
+        Self {
+            current: Arc::new(RwLock::new(CurrentState { state: Arc::new(state), config })),
+            registry,
+        }
+    }
+    
+    /// State of the current configuration
+    pub fn state(&self) -> Arc<AppState> {
+        self.current.read().unwrap().state.clone()
+    }
+    
+    /// Apply a new configuration to subsequent requests
+    ///
+    /// Providers, model mappings, routing, client keys and limits are replaced;
+    /// usage counts carry over. Sections that shape the server itself (`server`,
+    /// `timeouts`, `thoughtCache`, `stateBackend`) keep their current values
+    /// until a restart.
+    pub fn reload(&self, app_config: AppConfig) -> Result<()> {
+        self.reload_by(app_config, "api", None)
+    }
+    
+    /// Apply a new configuration, recording in the audit log (if configured)
+    /// who triggered the reload and where the configuration came from
+    pub fn reload_by(&self, app_config: AppConfig, actor: &str, source: Option<&str>) -> Result<()> {
+        let mut current = self.current.write().unwrap();
+        let previous = &current.config;
+        let restart_only = [
+            ("server", section_changed(&previous.server, &app_config.server)),
+            ("timeouts", section_changed(&previous.timeouts, &app_config.timeouts)),
+            ("thoughtCache", section_changed(&previous.thought_cache, &app_config.thought_cache)),
+            ("stateBackend", section_changed(&previous.state_backend, &app_config.state_backend)),
+            ("errorReports", section_changed(&previous.error_reports, &app_config.error_reports)),
+        ];
+        for (section, _) in restart_only.iter().filter(|(_, changed)| *changed) {
+            warn!("🔄 Changes to '{}' take effect after a restart", section);
+        }
+        
+        let state = build_state(current.state.settings.clone(), &app_config, &self.registry, Some(&current.state))?;
+        if let Some(audit_log) = &state.audit_log {
+            if let Some(entry) = audit_log.record(actor, "config_reload", source, &current.config, &app_config) {
+                info!("📜 Audited {} configuration changes by {}", entry.changes.len(), actor);
+            }
+        }
+        logging::set_payload_policy(&app_config.payload_logging);
+        *current = CurrentState { state: Arc::new(state), config: app_config };
+        info!("🔄 Configuration reloaded");
+        Ok(())
+    }
+}
+
+fn section_changed<T: serde::Serialize>(previous: &T, next: &T) -> bool {
+    serde_json::to_value(previous).ok() != serde_json::to_value(next).ok()
+}
+
+/// Build the state for a configuration, with the registered providers
+///
+/// When reloading, usage counts, the concurrency queue (if its limits are
+/// unchanged), rate limit budgets and requests being coalesced are taken over
+/// from `previous`.
+fn build_state(settings: Settings, app_config: &AppConfig, registry: &ProviderRegistry, previous: Option<&AppState>) -> Result<AppState> {
     info!("Initializing with {} providers:", app_config.providers.len());
     for (name, provider) in &app_config.providers {
         let model_count = provider.models.len();
@@ -48,36 +194,220 @@ pub async fn create_router(settings: Settings, app_config: AppConfig) -> Result<
     }
     
     // Create API converter
-    let converter = ApiConverter::new(settings.clone());
+    let converter = ApiConverter::new(settings.clone())
+        .with_multiple_choices(app_config.multiple_choices)
+        .with_prompt_rules(PromptRewriter::new(&app_config.prompt_rules)?);
+    
+    let api_keys = app_config.auth.as_ref().map(|auth| {
+        info!("🔑 Client authentication enabled with {} API keys", auth.keys.len());
+        let store = ApiKeyStore::new(auth);
+        match previous.and_then(|previous| previous.api_keys.as_ref()) {
+            Some(previous) => Arc::new(store.with_usage_of(previous)),
+            None => Arc::new(store),
+        }
+    });
+    
+    let concurrency = app_config.concurrency.clone().map(|config| {
+        let unchanged = previous
+            .and_then(|previous| previous.concurrency.as_ref())
+            .filter(|limiter| *limiter.config() == config);
+        if let Some(limiter) = unchanged {
+            return limiter.clone();
+        }
+        info!("🚦 Limiting upstream requests to {} at a time", config.max_concurrent);
+        Arc::new(ConcurrencyLimiter::new(config))
+    });
+    
+    let coalescer = app_config.coalesce_requests.then(|| {
+        if let Some(coalescer) = previous.and_then(|previous| previous.coalescer.clone()) {
+            return coalescer;
+        }
+        info!("🔗 Coalescing identical in-flight requests");
+        Arc::new(RequestCoalescer::new())
+    });
+    
+    let redactor = match &app_config.redaction {
+        Some(config) => {
+            let redacted: Vec<&str> = config.builtins.iter()
+                .chain(config.patterns.iter().map(|pattern| &pattern.name))
+                .map(String::as_str)
+                .collect();
+            info!("🕶️ Redacting {} in outgoing messages", redacted.join(", "));
+            Some(Arc::new(Redactor::new(config)?))
+        }
+        None => None,
+    };
+    
+    let guardrails = match &app_config.guardrails {
+        Some(config) => {
+            let rules: Vec<&str> = config.rules.iter().map(|rule| rule.name.as_str()).collect();
+            info!("🛡️ Guardrails: {}", rules.join(", "));
+            Some(Arc::new(Guardrails::new(config)?))
+        }
+        None if !guardrails::registered().is_empty() => Some(Arc::new(Guardrails::new(&GuardrailsConfig::default())?)),
+        None => None,
+    };
+    
+    let request_log = match app_config.request_log.clone() {
+        Some(config) => {
+            let unchanged = previous
+                .and_then(|previous| previous.request_log.as_ref())
+                .filter(|log| *log.config() == config);
+            match unchanged {
+                Some(log) => Some(log.clone()),
+                None => {
+                    info!("📒 Logging completed requests to {}", config.path.display());
+                    Some(Arc::new(RequestLog::open(&config)?))
+                }
+            }
+        }
+        None => None,
+    };
+    
+    let shadow = match app_config.shadow.clone() {
+        Some(config) => {
+            let unchanged = previous
+                .and_then(|previous| previous.shadow.as_ref())
+                .filter(|shadow| *shadow.config() == config);
+            match unchanged {
+                Some(shadow) => Some(shadow.clone()),
+                None => {
+                    info!("👥 Mirroring {}% of requests to {}, logging to {}", config.percent, config.target, config.path.display());
+                    Some(Arc::new(ShadowTraffic::open(&config)?))
+                }
+            }
+        }
+        None => None,
+    };
+    
+    let audit_log = match app_config.audit_log.clone() {
+        Some(config) => {
+            let unchanged = previous
+                .and_then(|previous| previous.audit_log.as_ref())
+                .filter(|log| *log.config() == config);
+            match unchanged {
+                Some(log) => Some(log.clone()),
+                None => {
+                    info!("📜 Auditing configuration changes to {}", config.path.display());
+                    Some(Arc::new(AuditLog::open(&config)?))
+                }
+            }
+        }
+        None => None,
+    };
+    
+    let transcripts = match app_config.transcripts.clone() {
+        Some(config) => {
+            let unchanged = previous
+                .and_then(|previous| previous.transcripts.as_ref())
+                .filter(|store| *store.config() == config);
+            match unchanged {
+                Some(store) => Some(store.clone()),
+                None => {
+                    let location = config.directory.as_ref().or(config.sqlite_path.as_ref()).map(|path| path.display().to_string());
+                    info!("🗂️ Saving session transcripts to {}", location.unwrap_or_default());
+                    Some(Arc::new(TranscriptStore::open(&config)?))
+                }
+            }
+        }
+        None => None,
+    };
+    
+    let mut hooks = app_config.webhooks.iter()
+        .map(|config| Ok(Arc::new(Webhook::new(config)?) as Arc<dyn RequestHook>))
+        .collect::<Result<Vec<_>>>()?;
+    if !hooks.is_empty() {
+        info!("🪝 Calling {} webhooks after each request", hooks.len());
+    }
+    let experiments = (!app_config.experiments.is_empty()).then(|| {
+        let experiments = Experiments::new(&app_config.experiments);
+        // Arm totals carry over on reload
+        let experiments = match previous.and_then(|previous| previous.experiments.as_ref()) {
+            Some(previous) => experiments.with_stats_of(previous),
+            None => experiments,
+        };
+        let names: Vec<&str> = app_config.experiments.iter().map(|experiment| experiment.name.as_str()).collect();
+        info!("🧪 Running experiments {}", names.join(", "));
+        Arc::new(experiments)
+    });
+    if let Some(experiments) = &experiments {
+        hooks.push(experiments.clone());
+    }
+    if let Some(config) = &app_config.anomaly_log {
+        info!("⚠️ Logging requests slower than {} ms or with fewer than {} output tokens", config.slow_request_ms, config.min_output_tokens);
+        hooks.push(Arc::new(AnomalyLog::new(config)));
+    }
+    #[cfg(feature = "sqlite")]
+    if let Some(config) = &app_config.usage_store {
+        let store = UsageStore::open(config)?;
+        // Usage counts carry over on reload; restore them only on startup
+        if let (None, Some(api_keys)) = (previous, &api_keys) {
+            store.restore(api_keys.limiter())?;
+        }
+        info!("📒 Recording usage in {}", config.path.display());
+        hooks.push(Arc::new(store));
+    }
     
     // Create provider router
-    let router = Arc::new(ProviderRouter::new(app_config)?);
+    let router = ProviderRouter::with_registry(app_config.clone(), registry)?;
+    let router = Arc::new(match previous {
+        Some(previous) => router.with_rate_limits_of(&previous.router),
+        None => router,
+    });
     
-    // Create application state
-    let app_state = Arc::new(AppState {
-        settings: settings.clone(),
+    Ok(AppState {
+        settings,
         converter,
         router,
-    });
+        api_keys,
+        concurrency,
+        coalescer,
+        redactor,
+        guardrails,
+        request_log,
+        experiments,
+        shadow,
+        audit_log,
+        transcripts,
+        hooks,
+    })
+}
+
+/// Create application router with JSON config
+pub async fn create_router(settings: Settings, app_config: AppConfig) -> Result<Router> {
+    create_reloadable_router(settings, app_config).await.map(|(router, _)| router)
+}
+
+/// Create application router with JSON config, and the state to reload it
+///
+/// See [`RouterBuilder`] to supply providers, routes, middleware or state.
+pub async fn create_reloadable_router(settings: Settings, app_config: AppConfig) -> Result<(Router, SharedState)> {
+    RouterBuilder::new(settings, app_config).build()
+}
+
+/// CORS layer for the configured policy
+fn cors_layer(config: &CorsConfig) -> CorsLayer {
+    let mut layer = if config.allows_any_origin() {
+        CorsLayer::new().allow_origin(Any)
+    } else {
+        let origins: Vec<HeaderValue> = config.allowed_origins.iter()
+            .filter_map(|origin| HeaderValue::from_str(origin).ok())
+            .collect();
+        info!("CORS allowed origins: {}", config.allowed_origins.join(", "));
+        CorsLayer::new().allow_origin(origins)
+    };
     
-    // Create middleware stack
-    let middleware_stack = ServiceBuilder::new()
-        .layer(TraceLayer::new_for_http())
-        .layer(
-            CorsLayer::new()
-                .allow_origin(Any)
-                .allow_methods(Any)
-                .allow_headers(Any),
-        );
-    
-    // Create routes
-    let router = Router::new()
-        .route("/v1/messages", post(proxy::handle_messages))
-        .route("/health", get(health::health_check))
-        .route("/health/live", get(health::liveness_check))
-        .with_state(app_state)
-        .layer(middleware_stack);
-    
-    Ok(router)
+    // Wildcards are not allowed with credentials; echo the preflight's request instead
+    layer = if config.allow_credentials {
+        layer.allow_credentials(true)
+            .allow_methods(AllowMethods::mirror_request())
+            .allow_headers(AllowHeaders::mirror_request())
+    } else {
+        layer.allow_methods(Any).allow_headers(Any)
+    };
+    if let Some(max_age_secs) = config.max_age_secs {
+        layer = layer.max_age(Duration::from_secs(max_age_secs));
+    }
+    layer
 }
 
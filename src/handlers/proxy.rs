@@ -3,21 +3,63 @@
 //! Handles Claude API requests and converts them to OpenAI API calls
 //! Supports both legacy single-provider mode and multi-provider routing
 
+use crate::config::{KeepAlive, KeepAliveFormat, MultipleChoicesMode, Priority};
 use crate::handlers::AppState;
+use crate::middleware::auth::ClientKey;
+use crate::middleware::request_id::RequestId;
 use crate::models::claude::*;
 use crate::models::openai::*;
-use crate::utils::logging::{create_request_log_summary, create_claude_request_log_summary};
+use crate::providers::{BoxStream, ProviderError};
+use crate::services::{context_window, output_tokens, request_params, sessions, strict_validation, tool_pairing, ContentBlockTracker, ResponseConverter, StopSequenceTracker};
+use crate::services::coalescing::RequestCoalescer;
+use crate::services::hooks;
+use crate::services::debug_tap::{self, StreamTap};
+use crate::services::guardrails::{self, Findings, Guardrails, StreamGuard};
+use crate::services::concurrency::SlotPermit;
+use crate::services::continuation::{self, Continuation};
+use crate::services::router::{ModelDisabled, Routed};
+use crate::services::redaction::{Redactions, StreamRestorer};
+use crate::services::request_log::RequestRecord;
+use crate::services::shadow::ShadowRun;
+#[cfg(feature = "metrics")]
+use crate::services::stream_metrics::StreamTimer;
+use crate::services::stream_recovery::StreamRecovery;
+use crate::utils::error::{AppError, AppResult};
+use crate::utils::logging::{create_claude_request_log_summary, create_raw_log, create_request_log_summary, create_response_log};
 use axum::{
-    extract::State,
-    http::{HeaderMap, StatusCode},
+    extract::{Extension, State},
+    http::{HeaderMap, HeaderValue, StatusCode},
     response::{IntoResponse, Response, Sse},
     Json,
 };
-use axum::response::sse::{Event, KeepAlive};
+use axum::response::sse::Event;
+use futures::StreamExt;
+use std::collections::VecDeque;
 use std::sync::Arc;
-use std::time::Duration;
-use tokio_stream::wrappers::ReceiverStream;
-use tracing::{debug, error, warn};
+#[cfg(feature = "metrics")]
+use std::time::Instant;
+use tracing::{debug, error, info, warn, Instrument};
+
+/// Header listing enabled Anthropic beta features
+const ANTHROPIC_BETA_HEADER: &str = "anthropic-beta";
+
+/// Header declaring a request's priority class ("interactive" or "batch")
+const PRIORITY_HEADER: &str = "x-request-priority";
+
+/// Header asking for a dry run of one request
+const DRY_RUN_HEADER: &str = "x-aiapiproxy-dry-run";
+
+/// Header carrying the estimated cost of a response in USD
+const COST_HEADER: &str = "x-aiapiproxy-cost";
+
+/// Header naming the fallback provider/model path that answered a request
+const FALLBACK_HEADER: &str = "x-aiapiproxy-fallback";
+
+/// Header naming the guardrail rules that matched a request or its response
+const GUARDRAILS_HEADER: &str = "x-aiapiproxy-guardrails";
+
+/// Route path of the Claude messages API, for its `keepAlive` settings
+const MESSAGES_PATH: &str = "/v1/messages";
 
 /// Handle Claude message requests
 /// 
@@ -26,201 +68,950 @@ use tracing::{debug, error, warn};
 /// Routes requests to providers based on model path (e.g., "openai/gpt-4o", "modelhub-sg1/gpt-5")
 pub async fn handle_messages(
     State(state): State<Arc<AppState>>,
-    _headers: HeaderMap,
+    client: Option<Extension<ClientKey>>,
+    request_id: Option<Extension<RequestId>>,
+    headers: HeaderMap,
     Json(claude_request): Json<ClaudeRequest>,
-) -> Result<Response<axum::body::Body>, StatusCode> {
+) -> AppResult<Response<axum::body::Body>> {
+    let client = client.map(|Extension(client)| client);
+    let request_id = request_id.map(|Extension(request_id)| request_id);
+    let hooks = state.hooks.iter().cloned().chain(hooks::registered()).collect();
+    let mut record = RequestRecord::start(
+        state.request_log.clone(),
+        hooks,
+        state.transcripts.clone(),
+        request_id.as_ref().map(|request_id| request_id.0.as_str()),
+        client.as_ref(),
+        &claude_request,
+    );
+    
+    let response = proxy_messages(state, client, request_id, headers, claude_request, &mut record).await;
+    
+    // A streaming response took its record along and writes it when the stream ends
+    if let Some(record) = record.as_mut() {
+        record.set_status(match &response {
+            Ok(response) => response.status(),
+            Err(error) => error.status_code(),
+        });
+    }
+    response
+}
+
+/// Convert, route and send a Claude message request
+async fn proxy_messages(
+    state: Arc<AppState>,
+    client: Option<ClientKey>,
+    request_id: Option<RequestId>,
+    headers: HeaderMap,
+    mut claude_request: ClaudeRequest,
+    record: &mut Option<RequestRecord>,
+) -> AppResult<Response<axum::body::Body>> {
     debug!("Received Claude API request for model: {}", claude_request.model);
     
     // 🔍 DEBUG: 记录客户端请求摘要
-    let log_summary = create_claude_request_log_summary(&claude_request);
-    if let Ok(summary_json) = serde_json::to_string_pretty(&log_summary) {
+    if let Some(summary_json) = create_claude_request_log_summary(&claude_request) {
         debug!("📥 Client Request:\n{}", summary_json);
     }
     
     // Validate request
     if let Err(error_msg) = validate_claude_request(&claude_request) {
         warn!("Request validation failed: {}", error_msg);
-        return Ok(create_error_response("invalid_request_error", &error_msg, StatusCode::BAD_REQUEST));
+        return Err(AppError::InvalidRequest(error_msg));
+    }
+    // Strict validation sees the request as the client sent it, before
+    // pairing repairs what it would reject
+    if state.router.config().strict_validation {
+        if let Err(error_msg) = strict_validation::validate(&claude_request) {
+            warn!("Strict request validation failed: {}", error_msg);
+            return Err(AppError::InvalidRequest(error_msg));
+        }
+    }
+    match tool_pairing::apply(&mut claude_request, state.router.config().tool_pairing) {
+        Ok(repairs) if !repairs.is_empty() => {
+            let repairs: Vec<String> = repairs.iter().map(ToString::to_string).collect();
+            info!("🔧 Paired tool calls and results: {}", repairs.join(", "));
+        }
+        Ok(_) => {}
+        Err(error_msg) => {
+            warn!("Tool call pairing failed: {}", error_msg);
+            return Err(AppError::InvalidRequest(error_msg));
+        }
+    }
+    
+    // Enforce the client key's model allowlist and limits
+    if let (Some(client), Some(api_keys)) = (&client, &state.api_keys) {
+        if let Err(limit_error) = api_keys.limiter().check(client, &claude_request) {
+            warn!("🔑 Rejected request of tenant '{}': {}", client.tenant, limit_error);
+            return Err(limit_error.into());
+        }
+    }
+    
+    if claude_request.n.unwrap_or(1) > 1 && state.converter.multiple_choices() == MultipleChoicesMode::Reject {
+        warn!("Rejected request for {} choices", claude_request.n.unwrap_or(1));
+        return Err(AppError::InvalidRequest("n > 1 is not supported".to_string()));
+    }
+    
+    // A dry run converts and routes the request but doesn't call the upstream
+    let dry_run = state.router.config().dry_run || is_dry_run(&headers);
+    
+    // Wait for a concurrency slot, held until the response is complete
+    let permit = match &state.concurrency {
+        Some(concurrency) if !dry_run => {
+            let priority = match request_priority(&headers) {
+                Ok(priority) => priority.or(client.as_ref().map(|client| client.priority)).unwrap_or_default(),
+                Err(error_msg) => return Err(AppError::InvalidRequest(error_msg)),
+            };
+            match concurrency.acquire(priority).await {
+                Ok(permit) => Some(permit),
+                Err(queue_error) => {
+                    warn!("🚦 Rejected {:?} request: {}", priority, queue_error);
+                    return Err(AppError::Overloaded(queue_error.to_string()));
+                }
+            }
+        }
+        _ => None,
+    };
+    
+    claude_request.betas = anthropic_betas(&headers);
+    if !claude_request.betas.is_empty() {
+        debug!("Anthropic betas: requested={:?}", claude_request.betas);
+    }
+    
+    // Routing rules may send the request elsewhere based on its metadata, and
+    // weighted model mappings pick one of their upstreams
+    let route_model = state.router
+        .route_model(&claude_request.model, claude_request.metadata.as_ref())
+        .to_string();
+    let session_id = sessions::session_id_from_metadata(claude_request.metadata.as_ref());
+    // Sessions in an A/B experiment go to the path of their arm
+    let assignment = state.experiments.as_ref()
+        .filter(|_| route_model == claude_request.model)
+        .and_then(|experiments| experiments.assign(&claude_request.model, session_id));
+    let route_model = match assignment {
+        Some(assignment) => {
+            debug!("🧪 Session in the {} arm of experiment {}", assignment.arm.as_str(), assignment.experiment);
+            assignment.target.to_string()
+        }
+        None => state.router.balance(&route_model, session_id),
+    };
+    if let Some(record) = record.as_mut() {
+        record.set_route(&route_model, state.router.pricing(&route_model));
+        if let Some(assignment) = assignment {
+            record.set_experiment(assignment.experiment, assignment.arm);
+        }
+    }
+    info!(
+        "📨 Claude request: model={}, route={}, stream={}",
+        claude_request.model,
+        route_model,
+        claude_request.stream.unwrap_or(false)
+    );
+    // Metadata identifies end users (user_id), so it stays out of info logs
+    if let Some(metadata) = &claude_request.metadata {
+        debug!("📨 Claude request metadata: {}", serde_json::to_string(metadata).unwrap_or_default());
     }
     
     // Convert Claude request to OpenAI request
-    let openai_request = match state.converter.convert_request(claude_request.clone()) {
+    let mut openai_request = match state.converter.convert_request(claude_request.clone()) {
         Ok(mut req) => {
             // Keep the original model path for routing
-            req.model = claude_request.model.clone();
+            req.model = route_model.clone();
+            req.request_id = request_id.map(|request_id| request_id.0);
+            req.forwarded_headers = forwarded_headers(&state, &headers);
             
-            let log_summary = create_request_log_summary(&req);
-            if let Ok(summary_json) = serde_json::to_string_pretty(&log_summary) {
+            if let Some(summary_json) = create_request_log_summary(&req) {
                 debug!("🔄 Converted OpenAI Request:\n{}", summary_json);
             }
             req
         },
         Err(e) => {
             error!("Request conversion failed: {}", e);
-            return Ok(create_error_response("conversion_error", "Failed to convert request", StatusCode::INTERNAL_SERVER_ERROR));
+            return Err(AppError::Conversion(e.to_string()));
         }
     };
     
-    let original_model = claude_request.model.clone();
+    // Apply the model's request parameters and max_tokens policy, then reject
+    // or truncate prompts that don't fit the upstream model's context window
+    if let Some(model_config) = state.router.model_config(&route_model) {
+        request_params::apply(&mut openai_request, &model_config);
+        if let Err(error_msg) = output_tokens::apply(&mut openai_request, &model_config) {
+            warn!("Output token check failed for {}: {}", model_config.name, error_msg);
+            return Err(AppError::InvalidRequest(error_msg));
+        }
+        if let Err(error_msg) = context_window::enforce(&mut openai_request, &model_config) {
+            warn!("Context window check failed for {}: {}", model_config.name, error_msg);
+            return Err(AppError::InvalidRequest(error_msg));
+        }
+    }
+    
+    // Check the new turn against the content guardrails
+    let findings = state.guardrails.as_ref()
+        .map(|guardrails| guardrails.check_prompt(&mut openai_request))
+        .unwrap_or_default();
+    if !findings.is_empty() {
+        info!("🛡️ Guardrails matched the prompt: {}", findings);
+        if let Some(record) = record.as_mut() {
+            record.record_guardrails(&findings);
+        }
+    }
+    if let Some(guardrails) = state.guardrails.as_ref().filter(|_| findings.blocked) {
+        return Ok(blocked_prompt_response(guardrails, &claude_request, &findings, record));
+    }
+    
+    // Replace personal data and secrets before anything is sent upstream
+    let redactions = state.redactor.as_ref()
+        .map(|redactor| redactor.redact(&mut openai_request))
+        .filter(|redactions| !redactions.is_empty());
+    if let Some(redactions) = &redactions {
+        info!("🕶️ Redacted {}", redactions);
+    }
+    let restore = redactions.filter(|_| state.redactor.as_ref().is_some_and(|redactor| redactor.restores()));
+    
+    if dry_run {
+        return dry_run_response(&state, openai_request, &claude_request.model);
+    }
+    
+    if let Some(session_id) = &openai_request.session_id {
+        if sessions::global().begin_request(session_id, &openai_request) {
+            info!("🧵 System prompt changed in session {}, upstream prompt caches will miss", session_id);
+        }
+    }
+    
     let is_streaming = claude_request.stream.unwrap_or(false);
     
     if is_streaming {
-        handle_stream_request(state, openai_request, original_model).await
+        let mut response = handle_stream_request(state, openai_request, &claude_request, client, permit, restore, record).await?;
+        insert_guardrails_header(&mut response, &findings);
+        Ok(response)
     } else {
-        handle_normal_request(state, openai_request, original_model).await
+        handle_normal_request(state, openai_request, &claude_request, client, restore, findings, record).await
+    }
+}
+
+/// Answer a prompt a guardrail blocked with the configured message, without
+/// calling the upstream
+fn blocked_prompt_response(
+    guardrails: &Guardrails,
+    claude_request: &ClaudeRequest,
+    findings: &Findings,
+    record: &mut Option<RequestRecord>,
+) -> Response<axum::body::Body> {
+    let blocked = guardrails.blocked_response(&claude_request.model);
+    if let Some(record) = record.as_mut() {
+        record.record_response(&blocked);
     }
+    let mut response = if claude_request.stream.unwrap_or(false) {
+        let events = guardrails::blocked_events(&blocked).into_iter()
+            .filter_map(|event| sse_event(&event))
+            .map(Ok::<_, axum::Error>);
+        Sse::new(futures::stream::iter(events)).into_response()
+    } else {
+        Json(blocked).into_response()
+    };
+    insert_guardrails_header(&mut response, findings);
+    response
 }
 
+/// Name the guardrail rules that matched in the response headers
+fn insert_guardrails_header(response: &mut Response<axum::body::Body>, findings: &Findings) {
+    if findings.is_empty() {
+        return;
+    }
+    if let Ok(value) = HeaderValue::from_str(&findings.rules.join(",")) {
+        response.headers_mut().insert(GUARDRAILS_HEADER, value);
+    }
+}
 
-/// Categorize error message to appropriate error type and message
-fn categorize_error(error_message: &str) -> (&str, &str, StatusCode) {
-    if error_message.contains("429") || error_message.contains("TooManyRequests") || error_message.contains("RateLimitExceeded") || error_message.contains("Too Many Requests") {
-        ("rate_limit_error", "Rate limit exceeded. Please try again later.", StatusCode::TOO_MANY_REQUESTS)
-    } else if error_message.contains("authentication") || error_message.contains("Invalid API key") || error_message.contains("401") {
-        ("authentication_error", "Invalid API key provided.", StatusCode::UNAUTHORIZED)
-    } else if error_message.contains("insufficient_quota") || error_message.contains("quota") {
-        ("billing_error", "Insufficient quota or billing issue.", StatusCode::PAYMENT_REQUIRED)
-    } else if error_message.contains("not found") || error_message.contains("Model not found") || error_message.contains("404") {
-        ("not_found_error", "The requested model was not found.", StatusCode::NOT_FOUND)
-    } else if error_message.contains("400") || error_message.contains("Bad Request") {
-        ("invalid_request_error", "Bad request to upstream API.", StatusCode::BAD_REQUEST)
+/// Priority class requested with the `x-request-priority` header
+fn request_priority(headers: &HeaderMap) -> Result<Option<Priority>, String> {
+    let Some(value) = headers.get(PRIORITY_HEADER) else {
+        return Ok(None);
+    };
+    match value.to_str().map(|value| value.trim().to_ascii_lowercase()).as_deref() {
+        Ok("interactive") => Ok(Some(Priority::Interactive)),
+        Ok("batch") => Ok(Some(Priority::Batch)),
+        _ => Err(format!("{} must be \"interactive\" or \"batch\"", PRIORITY_HEADER)),
+    }
+}
+
+/// Whether the `x-aiapiproxy-dry-run` header asks for a dry run ("true" or "1")
+fn is_dry_run(headers: &HeaderMap) -> bool {
+    headers.get(DRY_RUN_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| matches!(value.trim().to_ascii_lowercase().as_str(), "true" | "1"))
+}
+
+/// Answer a dry run with the routing decision and the converted request
+fn dry_run_response(state: &AppState, openai_request: OpenAIRequest, model: &str) -> AppResult<Response<axum::body::Body>> {
+    match state.router.dry_run(openai_request) {
+        Ok(dry_run) => {
+            info!("🧪 Dry run for {}: {}", model, dry_run.targets.join(" -> "));
+            let mut body = serde_json::json!({ "type": "dry_run", "model": model });
+            if let (Some(body), Ok(serde_json::Value::Object(dry_run))) = (body.as_object_mut(), serde_json::to_value(&dry_run)) {
+                body.extend(dry_run);
+            }
+            Ok(Json(body).into_response())
+        }
+        Err(e) => {
+            warn!("🧪 Dry run for {} failed: {}", model, e);
+            Err(upstream_error(&e))
+        }
+    }
+}
+
+/// Collect the beta names of all `anthropic-beta` headers (comma-separated lists)
+fn anthropic_betas(headers: &HeaderMap) -> Vec<String> {
+    headers.get_all(ANTHROPIC_BETA_HEADER).iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|beta| beta.trim().to_string())
+        .filter(|beta| !beta.is_empty())
+        .collect()
+}
+
+/// Inbound headers listed in `forwardHeaders`, in order of appearance
+///
+/// The header carrying the client's API key is never forwarded.
+fn forwarded_headers(state: &AppState, headers: &HeaderMap) -> Vec<(String, String)> {
+    let config = state.router.config();
+    if config.forward_headers.is_empty() {
+        return Vec::new();
+    }
+    let api_key_header = &state.settings.security.api_key_header;
+    headers.iter()
+        .filter(|(name, _)| config.forwards_header(name.as_str()) && !name.as_str().eq_ignore_ascii_case(api_key_header))
+        .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+        .collect()
+}
+
+/// Handle Claude token counting requests
+/// 
+/// POST /v1/messages/count_tokens
+/// 
+/// Counts the converted request with the tokenizer of the upstream model.
+pub async fn handle_count_tokens(
+    State(state): State<Arc<AppState>>,
+    Json(count_request): Json<ClaudeCountTokensRequest>,
+) -> AppResult<Response<axum::body::Body>> {
+    debug!("Received token counting request for model: {}", count_request.model);
+    
+    let claude_request = ClaudeRequest::from(count_request);
+    let model = claude_request.model.clone();
+    let openai_request = match state.converter.convert_request(claude_request) {
+        Ok(req) => req,
+        Err(e) => {
+            error!("Request conversion failed: {}", e);
+            return Err(AppError::Conversion(e.to_string()));
+        }
+    };
+    
+    let input_tokens = state.router.token_counter(&model).count_request(&openai_request);
+    debug!("📊 Estimated {} input tokens", input_tokens);
+    Ok(Json(ClaudeCountTokensResponse { input_tokens }).into_response())
+}
+
+/// Map a failed routing or provider call to the error returned to the client
+///
+/// Provider errors keep their classification, disabled models are reported
+/// as overloaded and unknown models as not found.
+fn upstream_error(error: &anyhow::Error) -> AppError {
+    if let Some(provider_error) = error.downcast_ref::<ProviderError>() {
+        return AppError::Provider(provider_error.clone());
+    }
+    if let Some(disabled) = error.downcast_ref::<ModelDisabled>() {
+        return AppError::Overloaded(disabled.to_string());
+    }
+    let message = format!("{:#}", error);
+    if message.contains("not found") {
+        AppError::NotFound(message)
     } else {
-        ("api_error", "External API request failed.", StatusCode::BAD_GATEWAY)
+        AppError::ExternalApi(message)
     }
 }
 
+/// Response converter for a model: the provider's specialized one or the default
+fn response_converter(state: &AppState, model: &str) -> Arc<dyn ResponseConverter> {
+    state.router.response_converter(model)
+        .unwrap_or_else(|| Arc::new(state.converter.clone()))
+}
+
+/// Estimated cost in USD of a response's usage, for routes in the `pricing` table
+fn estimate_cost(state: &AppState, model: &str, usage: &ClaudeUsage) -> Option<f64> {
+    state.router.pricing(model)
+        .map(|pricing| pricing.cost(usage.input_tokens, usage.cache_read_input_tokens.unwrap_or(0), usage.output_tokens))
+}
+
+/// Record token usage and its estimated cost for the client key of a request
+fn record_client_usage(state: &AppState, client: Option<&ClientKey>, model: &str, usage: &ClaudeUsage) {
+    if let (Some(client), Some(api_keys)) = (client, &state.api_keys) {
+        let cost = estimate_cost(state, model, usage).unwrap_or(0.0);
+        api_keys.limiter().record_usage(client, usage.total_input_tokens(), usage.output_tokens, cost);
+    }
+}
+
+/// Send a sample of the requests to the candidate upstream of `shadow`
+fn mirror(state: &AppState, openai_request: &OpenAIRequest, model: &str) -> Option<ShadowRun> {
+    let shadow = state.shadow.as_ref()?;
+    let converter = response_converter(state, &shadow.config().target);
+    shadow.mirror(state.router.clone(), converter, openai_request, model)
+}
+
 /// Handle normal (non-streaming) requests
 async fn handle_normal_request(
     state: Arc<AppState>,
     openai_request: OpenAIRequest,
-    original_model: String,
-) -> Result<Response<axum::body::Body>, StatusCode> {
+    claude_request: &ClaudeRequest,
+    client: Option<ClientKey>,
+    restore: Option<Redactions>,
+    mut findings: Findings,
+    record: &mut Option<RequestRecord>,
+) -> AppResult<Response<axum::body::Body>> {
+    let original_model = claude_request.model.clone();
     debug!("Handling normal request for model: {}", original_model);
+    let mut shadow = mirror(&state, &openai_request, &original_model);
     
-    // Route and call provider API
-    let openai_response = match state.router.chat_complete(openai_request).await {
-        Ok(response) => {
-            if let Ok(response_json) = serde_json::to_string_pretty(&response) {
+    let stop_sequences = openai_request.stop.clone().unwrap_or_default();
+    let route_model = openai_request.model.clone();
+    let session_id = openai_request.session_id.clone();
+    // Kept to estimate usage if the upstream doesn't report it
+    let usage_request = openai_request.clone();
+    let mut continuation = Continuation::new(&openai_request, state.router.model_config(&route_model).as_ref(), claude_request.max_tokens);
+    
+    // Route and call provider API, sharing the call of an identical request in flight
+    let (result, coalesced) = match &state.coalescer {
+        Some(coalescer) => {
+            let key = RequestCoalescer::key(client.as_ref().map(|client| client.name.as_str()), &openai_request);
+            let router = state.router.clone();
+            coalescer.run(key, async move { router.chat_complete_routed(openai_request).await }).await
+        }
+        None => (state.router.chat_complete_routed(openai_request).await.map_err(Arc::new), false),
+    };
+    let (openai_response, fallback) = match result {
+        Ok(routed) => {
+            let fallback = fallback_notice(&routed);
+            let mut response = routed.response;
+            if let Some(continuation) = continuation.as_mut() {
+                continue_response(&state, continuation, &mut response).await;
+            }
+            if let Some(response_json) = create_response_log(&response) {
                 debug!("📤 Provider API Response:\n{}", response_json);
             }
-            response
+            if response.usage.as_ref().is_none_or(OpenAIUsage::is_empty) {
+                let usage = state.router.token_counter(&route_model).estimate_usage(&usage_request, &response);
+                debug!("📊 Upstream omitted usage, estimated {} + {} tokens", usage.prompt_tokens, usage.completion_tokens);
+                response.usage = Some(usage);
+            }
+            (response, fallback)
         },
         Err(e) => {
             error!("Provider API request failed: {}", e);
-            let error_msg = e.to_string();
-            let (error_type, claude_message, status_code) = categorize_error(&error_msg);
-            return Ok(create_error_response(error_type, claude_message, status_code));
+            return Err(upstream_error(&e));
         }
     };
     
     // Convert response format
-    let claude_response = match state.converter.convert_response(openai_response, &original_model) {
-        Ok(response) => {
-            if let Ok(claude_json) = serde_json::to_string_pretty(&response) {
+    let converter = response_converter(&state, &route_model);
+    let claude_response = match converter.convert_response(openai_response, &original_model) {
+        Ok(mut response) => {
+            state.converter.apply_stop_sequences(&mut response, &stop_sequences);
+            // A coalesced request was neither sent upstream nor billed again
+            if !coalesced {
+                if let Some(session_id) = &session_id {
+                    sessions::global().record_response(session_id, &response);
+                }
+                record_client_usage(&state, client.as_ref(), &route_model, &response.usage);
+            }
+            if let Some(shadow) = shadow.as_mut() {
+                shadow.record_response(&response);
+            }
+            if let Some(guardrails) = &state.guardrails {
+                let completion = guardrails.check_response(&mut response);
+                if !completion.is_empty() {
+                    info!("🛡️ Guardrails matched the response: {}", completion);
+                }
+                findings.merge(&completion);
+            }
+            if let Some(redactions) = &restore {
+                redactions.restore_response(&mut response);
+            }
+            if let Some(record) = record.as_mut() {
+                record.record_response(&response);
+                record.record_guardrails(&findings);
+            }
+            
+            if let Some(claude_json) = create_response_log(&response) {
                 debug!("📋 Final Claude Response:\n{}", claude_json);
             }
             response
         },
         Err(e) => {
             error!("Response conversion failed: {}", e);
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            return Err(AppError::Conversion(e.to_string()));
         }
     };
     
     debug!("Request processing completed");
-    Ok(Json(claude_response).into_response())
+    let cost = estimate_cost(&state, &route_model, &claude_response.usage);
+    let mut response = Json(claude_response).into_response();
+    if let Some(value) = cost.and_then(|cost| HeaderValue::from_str(&format!("{:.6}", cost)).ok()) {
+        response.headers_mut().insert(COST_HEADER, value);
+    }
+    insert_fallback_header(&mut response, fallback.as_ref());
+    insert_guardrails_header(&mut response, &findings);
+    Ok(response)
+}
+
+/// Continue a response the upstream cut off at its output limit, stitching
+/// the parts together
+///
+/// A failed continuation request leaves the response cut off.
+async fn continue_response(state: &AppState, continuation: &mut Continuation, response: &mut OpenAIResponse) {
+    while let Some(request) = continuation.continue_response(response) {
+        info!("✂️ Continuing response cut off at the output limit (round {})", continuation.rounds());
+        match state.router.chat_complete(request).await {
+            Ok(continued) => continuation::stitch(response, continued),
+            Err(e) => {
+                warn!("Continuing the response failed: {}", e);
+                return;
+            }
+        }
+    }
+}
+
+/// Notice for a request that a fallback of its upstream served
+fn fallback_notice<T>(routed: &Routed<T>) -> Option<FallbackNotice> {
+    routed.fallback_from.clone().map(|requested| FallbackNotice { requested, served_by: routed.model_path.clone() })
+}
+
+/// Name the fallback that served a request in the response headers
+fn insert_fallback_header(response: &mut Response<axum::body::Body>, fallback: Option<&FallbackNotice>) {
+    if let Some(value) = fallback.and_then(|fallback| HeaderValue::from_str(&fallback.served_by).ok()) {
+        response.headers_mut().insert(FALLBACK_HEADER, value);
+    }
 }
 
 /// Handle streaming requests
+///
+/// Events are produced as the client reads them: the upstream is only polled
+/// once the events of its previous chunk were sent, so a slow client slows the
+/// upstream down through flow control instead of events piling up in memory.
 async fn handle_stream_request(
     state: Arc<AppState>,
     mut openai_request: OpenAIRequest,
-    original_model: String,
-) -> Result<Response<axum::body::Body>, StatusCode> {
+    claude_request: &ClaudeRequest,
+    client: Option<ClientKey>,
+    permit: Option<SlotPermit>,
+    restore: Option<Redactions>,
+    record: &mut Option<RequestRecord>,
+) -> AppResult<Response<axum::body::Body>> {
+    let original_model = claude_request.model.clone();
     debug!("Handling streaming request for model: {}", original_model);
+    let shadow = mirror(&state, &openai_request, &original_model);
+    let guard = state.guardrails.as_ref().map(|guardrails| guardrails.stream_guard());
+    #[cfg(feature = "metrics")]
+    let started = Instant::now();
     
     openai_request.stream = Some(true);
     
-    let router = state.router.clone();
-    let converter = state.converter.clone();
-    let (tx, rx) = tokio::sync::mpsc::channel::<Result<Event, axum::Error>>(100);
+    let converter = response_converter(&state, &openai_request.model);
+    let route_model = openai_request.model.clone();
+    let session_id = openai_request.session_id.clone();
+    let stop_tracker = StopSequenceTracker::new(openai_request.stop.clone().unwrap_or_default());
+    let model_config = state.router.model_config(&openai_request.model);
+    let recovery = StreamRecovery::new(&openai_request, model_config.as_ref());
+    let continuation = Continuation::new(&openai_request, model_config.as_ref(), claude_request.max_tokens);
     
-    tokio::spawn(async move {
-        let stream = match router.chat_stream(openai_request).await {
-            Ok(stream) => stream,
-            Err(e) => {
-                error!("Provider streaming API request failed: {}", e);
-                let error_msg = e.to_string();
-                let (error_type, claude_message, _status_code) = categorize_error(&error_msg);
-                
-                let claude_error = ClaudeStreamEvent::Error {
-                    error: ClaudeError {
-                        error_type: error_type.to_string(),
-                        message: claude_message.to_string(),
-                    },
+    // Upstreams report usage at the end of the stream (if at all); message_start
+    // carries the prompt estimate so clients can show it immediately
+    let input_tokens = state.router.token_counter(&openai_request.model).count_request(&openai_request);
+    let tap = debug_tap::global().start(&original_model, &openai_request);
+    
+    // Connect upstream before starting the SSE response so failures keep their HTTP status
+    let (upstream, fallback) = match state.router.chat_stream_routed(openai_request).await {
+        Ok(routed) => {
+            let fallback = fallback_notice(&routed);
+            (routed.response, fallback)
+        }
+        Err(e) => {
+            error!("Provider streaming API request failed: {}", e);
+            return Err(upstream_error(&e));
+        }
+    };
+    
+    let pipeline = StreamPipeline {
+        keep_alive: state.router.config().keep_alive.route(MESSAGES_PATH),
+        fallback: fallback.clone(),
+        #[cfg(feature = "metrics")]
+        timer: StreamTimer::new(&route_model, started),
+        state,
+        upstream,
+        converter,
+        original_model,
+        route_model,
+        session_id,
+        client,
+        input_tokens,
+        block_tracker: ContentBlockTracker::new(),
+        stop_tracker,
+        recovery,
+        continuation,
+        restorer: restore.map(Redactions::into_stream_restorer),
+        guard,
+        record: record.take().map(|mut record| {
+            record.set_status(StatusCode::OK);
+            record
+        }),
+        shadow,
+        tap,
+        pending: VecDeque::new(),
+        started: false,
+        stopped: false,
+        resumed: false,
+        finished: false,
+        output: String::new(),
+        output_charged: false,
+        span: tracing::Span::current(),
+        _permit: permit,
+    };
+    let events = futures::stream::unfold(pipeline, |mut pipeline| {
+        let span = pipeline.span.clone();
+        async move {
+            let sse_event = pipeline.next_sse_event().await?;
+            Some((Ok::<_, axum::Error>(sse_event), pipeline))
+        }.instrument(span)
+    });
+    
+    debug!("Starting streaming response transmission");
+    let mut response = Sse::new(events).into_response();
+    insert_fallback_header(&mut response, fallback.as_ref());
+    Ok(response)
+}
+
+/// State of a streaming response between client reads
+///
+/// Dropping it, e.g. when the client disconnects, drops the upstream response,
+/// which closes its connection and stops generation.
+struct StreamPipeline {
+    state: Arc<AppState>,
+    upstream: BoxStream<'static, OpenAIStreamResponse>,
+    converter: Arc<dyn ResponseConverter>,
+    original_model: String,
+    route_model: String,
+    session_id: Option<String>,
+    client: Option<ClientKey>,
+    input_tokens: u32,
+    block_tracker: ContentBlockTracker,
+    stop_tracker: StopSequenceTracker,
+    recovery: StreamRecovery,
+    /// Continues a response the upstream cut off at its output limit
+    continuation: Option<Continuation>,
+    /// Puts redacted values back into the events sent to the client
+    restorer: Option<StreamRestorer>,
+    /// Checks the streamed text against the content guardrails
+    guard: Option<StreamGuard>,
+    /// Request log record, written when the stream is dropped
+    record: Option<RequestRecord>,
+    /// Routed response of a mirrored request, compared when the stream is dropped
+    shadow: Option<ShadowRun>,
+    /// Time to first token, throughput and duration, recorded when the stream is dropped
+    #[cfg(feature = "metrics")]
+    timer: StreamTimer,
+    /// Mirrors the stream to `/debug/streams` watchers
+    tap: StreamTap,
+    /// Sent while the upstream is idle
+    keep_alive: KeepAlive,
+    /// Added to `message_start` when a fallback upstream answers
+    fallback: Option<FallbackNotice>,
+    /// Converted events of the last upstream chunk not yet sent
+    pending: VecDeque<ClaudeStreamEvent>,
+    started: bool,
+    stopped: bool,
+    /// A resumed stream continues the message that was already started
+    resumed: bool,
+    /// The upstream is exhausted; only pending events remain
+    finished: bool,
+    /// Text and tool calls sent to the client, to estimate the output tokens
+    output: String,
+    /// The output tokens were charged to the client key and session
+    output_charged: bool,
+    span: tracing::Span,
+    /// The concurrency slot is held until the stream ends
+    _permit: Option<SlotPermit>,
+}
+
+impl StreamPipeline {
+    /// The next event for the client as SSE; a serialization failure ends the stream
+    ///
+    /// The client reading the stream drives it, so keep-alives are only made
+    /// when the client is ready for them and never queue up behind a slow reader.
+    async fn next_sse_event(&mut self) -> Option<Event> {
+        let mut event = match self.next_event().await? {
+            StreamItem::Event(event) => *event,
+            StreamItem::KeepAlive => match self.keep_alive.format() {
+                KeepAliveFormat::Ping => ClaudeStreamEvent::Ping,
+                KeepAliveFormat::Comment => return Some(Event::default().comment(self.keep_alive.comment())),
+            },
+        };
+        self.account_usage(&mut event);
+        #[cfg(feature = "metrics")]
+        self.timer.observe(&event);
+        self.tap.client_event(&event);
+        if let Some(record) = self.record.as_mut() {
+            record.record_stream_event(&event);
+        }
+        let sse_event = sse_event(&event);
+        self.finished |= sse_event.is_none();
+        sse_event
+    }
+    
+    /// The next event for the client, reading from the upstream if none is pending
+    async fn next_event(&mut self) -> Option<StreamItem> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                let Some(restorer) = self.restorer.as_mut() else {
+                    return Some(StreamItem::Event(Box::new(event)));
+                };
+                // Held back text comes out as extra events before the block ends
+                let mut events = restorer.process(event).into_iter();
+                let Some(event) = events.next() else {
+                    continue;
                 };
-                
-                if let Ok(error_json) = serde_json::to_string(&claude_error) {
-                    let error_event = Event::default()
-                        .event("error")
-                        .data(error_json);
-                    let _ = tx.send(Ok(error_event)).await;
+                for extra in events.rev() {
+                    self.pending.push_front(extra);
+                }
+                return Some(StreamItem::Event(Box::new(event)));
+            }
+            if self.finished {
+                return None;
+            }
+            
+            // Keep the connection alive while the upstream is idle (e.g. a reasoning
+            // model thinking), but not after message_stop: the client is done with
+            // the stream, only its end is outstanding
+            let next = match tokio::time::timeout(self.keep_alive.interval(), self.upstream.next()).await {
+                Ok(next) => next,
+                Err(_) if self.stopped => continue,
+                Err(_) => return Some(StreamItem::KeepAlive),
+            };
+            
+            match next {
+                Some(Ok(openai_chunk)) => self.convert_chunk(openai_chunk),
+                Some(Err(e)) => self.upstream_failed(e).await,
+                None => {
+                    // A response cut off at the output limit goes on with a continuation
+                    if let Some(request) = self.continuation.as_mut().and_then(Continuation::next_request) {
+                        if self.continue_stream(request).await {
+                            continue;
+                        }
+                    }
+                    self.finished = true;
+                    // The stream normally ends with the converter's message_stop; close it
+                    // explicitly if the upstream ended without a finish reason
+                    if self.started && !self.stopped {
+                        warn!("Upstream stream ended without a finish reason, sending message_stop");
+                        let events = self.block_tracker.process(ClaudeStreamEvent::MessageStop);
+                        let events = self.guard_events(events);
+                        self.pending.extend(events);
+                    }
                 }
+            }
+        }
+    }
+    
+    /// Convert an upstream chunk into pending Claude events
+    fn convert_chunk(&mut self, mut openai_chunk: OpenAIStreamResponse) {
+        self.tap.upstream_chunk(&openai_chunk);
+        if let Some(continuation) = self.continuation.as_mut() {
+            continuation.defer_stop(&mut openai_chunk);
+        }
+        let claude_events = match self.converter.convert_stream_chunk(openai_chunk, &self.original_model) {
+            Ok(claude_events) => claude_events,
+            Err(e) => {
+                error!("Streaming response conversion failed: {}", e);
+                self.finished = true;
                 return;
             }
         };
         
-        let mut stream = Box::pin(stream);
-        
-        while let Some(chunk_result) = futures::StreamExt::next(&mut stream).await {
-            match chunk_result {
-                Ok(openai_chunk) => {
-                    match converter.convert_stream_chunk(openai_chunk, &original_model) {
-                        Ok(claude_events) => {
-                            for event in claude_events {
-                                match serde_json::to_string(&event) {
-                                    Ok(json) => {
-                                        debug!("📤 Sending Claude event: {}", if json.len() > 200 { &json[..200] } else { &json });
-                                        let sse_event = Event::default().data(json);
-                                        if tx.send(Ok(sse_event)).await.is_err() {
-                                            debug!("Client disconnected");
-                                            return;
-                                        }
-                                    }
-                                    Err(e) => {
-                                        error!("Event serialization failed: {}", e);
-                                        return;
-                                    }
-                                }
-                            }
-                        }
-                        Err(e) => {
-                            error!("Streaming response conversion failed: {}", e);
-                            return;
-                        }
-                    }
+        let resumed = self.resumed;
+        let events: Vec<ClaudeStreamEvent> = claude_events.into_iter()
+            .filter(|event| !(resumed && matches!(event, ClaudeStreamEvent::MessageStart { .. })))
+            .flat_map(|event| self.block_tracker.process(event))
+            .collect();
+        // A continuation carries on from the text the model wrote, also text
+        // the guardrails still hold back
+        if let Some(continuation) = self.continuation.as_mut() {
+            events.iter().for_each(|event| continuation.observe(event));
+        }
+        for mut event in self.guard_events(events) {
+            self.stop_tracker.observe(&mut event);
+            let is_message_start = matches!(event, ClaudeStreamEvent::MessageStart { .. });
+            if let ClaudeStreamEvent::MessageStart { message } = &mut event {
+                message.usage.input_tokens = self.input_tokens;
+                message.fallback = self.fallback.clone();
+            }
+            self.recovery.observe(&event);
+            if let Some(shadow) = self.shadow.as_mut() {
+                shadow.record_stream_event(&event);
+            }
+            self.started |= is_message_start;
+            self.stopped |= matches!(event, ClaudeStreamEvent::MessageStop);
+            self.pending.push_back(event);
+            // Anthropic sends a ping right after message_start
+            if is_message_start {
+                self.pending.push_back(ClaudeStreamEvent::Ping);
+            }
+        }
+    }
+    
+    /// Go on with the continuation of a response cut off at the output limit
+    ///
+    /// Returns false if the continuation request failed; the message is then
+    /// closed as cut off at `max_tokens`.
+    async fn continue_stream(&mut self, request: OpenAIRequest) -> bool {
+        let round = self.continuation.as_ref().map_or(0, Continuation::rounds);
+        info!("✂️ Continuing response cut off at the output limit (round {})", round);
+        match self.state.router.chat_stream(request).await {
+            Ok(continued) => {
+                self.upstream = continued;
+                self.resumed = self.started;
+                true
+            }
+            Err(e) => {
+                error!("Continuing the response failed: {}", e);
+                for event in continuation::max_tokens_events() {
+                    let events = self.block_tracker.process(event);
+                    let events = self.guard_events(events);
+                    self.pending.extend(events);
                 }
-                Err(e) => {
-                    error!("Provider streaming response error: {}", e);
+                self.stopped = true;
+                false
+            }
+        }
+    }
+    
+    /// Resume the stream after an upstream failure, or end it with the failure events
+    async fn upstream_failed(&mut self, e: anyhow::Error) {
+        error!("Provider streaming response error: {}", e);
+        if self.stopped {
+            self.finished = true;
+            return;
+        }
+        
+        if let Some(request) = self.recovery.resume_request() {
+            warn!("🔁 Resuming stream after {} characters (attempt {})", self.recovery.text_len(), self.recovery.attempts());
+            match self.state.router.chat_stream(request).await {
+                Ok(resumed_stream) => {
+                    self.upstream = resumed_stream;
+                    self.resumed = self.started;
                     return;
                 }
+                Err(resume_error) => error!("Resuming the stream failed: {}", resume_error),
             }
         }
         
-        // Stream ends naturally after message_stop - no need to send additional events
-        // Claude API doesn't expect a "done" event with empty data
-    });
+        for event in self.recovery.failure_events(self.started, &e.to_string()) {
+            let events = self.block_tracker.process(event);
+            let events = self.guard_events(events);
+            self.pending.extend(events);
+        }
+        self.finished = true;
+    }
     
-    let stream = ReceiverStream::new(rx);
-    let sse = Sse::new(stream)
-        .keep_alive(
-            KeepAlive::new()
-                .interval(Duration::from_secs(15))
-                .text("keep-alive")
-        );
+    /// Record an event sent to the client in its session and charge its usage
+    /// to the client key
+    ///
+    /// Upstreams don't report usage in streams, so `message_delta` gets the
+    /// output tokens estimated from the content sent before it.
+    fn account_usage(&mut self, event: &mut ClaudeStreamEvent) {
+        match event {
+            ClaudeStreamEvent::ContentBlockStart { content_block: ClaudeContentBlock::ToolUse { name, .. }, .. } => {
+                self.output.push_str(name);
+            }
+            ClaudeStreamEvent::ContentBlockDelta { delta, .. } => match delta {
+                ClaudeContentDelta::TextDelta { text } => self.output.push_str(text),
+                ClaudeContentDelta::InputJsonDelta { partial_json } => self.output.push_str(partial_json),
+            },
+            ClaudeStreamEvent::MessageDelta { usage, .. } if usage.output_tokens == 0 => {
+                usage.output_tokens = self.state.router.token_counter(&self.route_model).count_text(&self.output);
+            }
+            _ => {}
+        }
+        if let Some(session_id) = &self.session_id {
+            sessions::global().record_stream_event(session_id, event);
+        }
+        match event {
+            ClaudeStreamEvent::MessageStart { message } => record_client_usage(&self.state, self.client.as_ref(), &self.route_model, &message.usage),
+            ClaudeStreamEvent::MessageDelta { usage, .. } => {
+                record_client_usage(&self.state, self.client.as_ref(), &self.route_model, usage);
+                self.output_charged = true;
+            }
+            _ => {}
+        }
+    }
     
-    debug!("Starting streaming response transmission");
-    Ok(sse.into_response())
+    /// Pass events through the stream guard, which holds back text until its
+    /// words are complete and ends a blocked stream
+    fn guard_events(&mut self, events: Vec<ClaudeStreamEvent>) -> Vec<ClaudeStreamEvent> {
+        let Some(guard) = self.guard.as_mut() else {
+            return events;
+        };
+        let was_blocked = guard.blocked();
+        let events = events.into_iter().flat_map(|event| guard.process(event)).collect();
+        if guard.blocked() && !was_blocked {
+            info!("🛡️ Guardrails blocked the response: {}", guard.findings());
+            self.finished = true;
+        }
+        events
+    }
+}
+
+/// What a streaming response sends next
+enum StreamItem {
+    Event(Box<ClaudeStreamEvent>),
+    KeepAlive,
+}
+
+impl Drop for StreamPipeline {
+    fn drop(&mut self) {
+        if let (Some(guard), Some(record)) = (&self.guard, self.record.as_mut()) {
+            record.record_guardrails(guard.findings());
+        }
+        // A stream the client left early is charged the output it got
+        if !self.output_charged && !self.output.is_empty() {
+            let usage = ClaudeUsage {
+                output_tokens: self.state.router.token_counter(&self.route_model).count_text(&self.output),
+                ..Default::default()
+            };
+            record_client_usage(&self.state, self.client.as_ref(), &self.route_model, &usage);
+            if let Some(session_id) = &self.session_id {
+                sessions::global().record_usage(session_id, 0, usage.output_tokens);
+            }
+        }
+        if !self.finished {
+            let _span = self.span.enter();
+            info!("Client disconnected, cancelling upstream stream");
+        }
+    }
+}
+
+/// Serialize a Claude stream event as an SSE event
+///
+/// The SSE `event` field is set to the event type, as in the Anthropic API.
+/// Returns None if serialization fails, which ends the stream.
+fn sse_event(event: &ClaudeStreamEvent) -> Option<Event> {
+    match serde_json::to_string(event) {
+        Ok(json) => {
+            if let Some(event_log) = create_raw_log(&json, 200) {
+                debug!("📤 Sending Claude event: {}", event_log);
+            }
+            Some(Event::default().event(event.event_type()).data(json))
+        }
+        Err(e) => {
+            error!("Event serialization failed: {}", e);
+            None
+        }
+    }
 }
 
 /// Validate Claude request
@@ -235,10 +1026,6 @@ fn validate_claude_request(request: &ClaudeRequest) -> Result<(), String> {
         return Err("max_tokens must be greater than 0".to_string());
     }
     
-    if request.max_tokens > 100000 {
-        return Err("max_tokens cannot exceed 100000".to_string());
-    }
-    
     // Check message list
     if request.messages.is_empty() {
         return Err("Message list cannot be empty".to_string());
@@ -289,18 +1076,23 @@ fn validate_claude_request(request: &ClaudeRequest) -> Result<(), String> {
     
     // Check temperature parameter
     if let Some(temp) = request.temperature {
-        if temp < 0.0 || temp > 2.0 {
+        if !(0.0..=2.0).contains(&temp) {
             return Err("temperature must be between 0.0 and 2.0".to_string());
         }
     }
     
     // Check top_p parameter
     if let Some(top_p) = request.top_p {
-        if top_p < 0.0 || top_p > 1.0 {
+        if !(0.0..=1.0).contains(&top_p) {
             return Err("top_p must be between 0.0 and 1.0".to_string());
         }
     }
     
+    // Check n parameter
+    if request.n == Some(0) {
+        return Err("n must be greater than 0".to_string());
+    }
+    
     // Check top_k parameter
     if let Some(top_k) = request.top_k {
         if top_k == 0 {
@@ -312,6 +1104,7 @@ fn validate_claude_request(request: &ClaudeRequest) -> Result<(), String> {
 }
 
 /// Extract authentication header
+#[allow(dead_code)]
 fn extract_auth_header(headers: &HeaderMap, auth_header_name: &str) -> Option<String> {
     headers
         .get(auth_header_name)
@@ -319,38 +1112,11 @@ fn extract_auth_header(headers: &HeaderMap, auth_header_name: &str) -> Option<St
         .map(|s| s.to_string())
 }
 
-/// Error response helper function that creates a Claude-compatible error response
-fn create_error_response(error_type: &str, message: &str, status_code: StatusCode) -> Response<axum::body::Body> {
-    // Create a response that matches Claude API error format but includes expected fields
-    let error_response = serde_json::json!({
-        "type": "error",
-        "error": {
-            "type": error_type,
-            "message": message
-        },
-        // Include content field as empty array to prevent client-side filter errors
-        "content": [],
-        // Include other fields that clients might expect
-        "id": format!("error_{}", uuid::Uuid::new_v4().simple()),
-        "model": "claude-3-sonnet",
-        "role": "assistant",
-        "stop_reason": "error",
-        "usage": {
-            "input_tokens": 0,
-            "output_tokens": 0
-        }
-    });
-    
-    Response::builder()
-        .status(status_code)
-        .header("Content-Type", "application/json")
-        .body(axum::body::Body::from(error_response.to_string()))
-        .unwrap()
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::providers::UpstreamError;
+    use axum::http::header;
     // use crate::models::claude::*; // 暂时注释掉未使用的导入
     
     #[test]
@@ -405,6 +1171,26 @@ mod tests {
         assert!(validate_claude_request(&invalid_request).is_err());
     }
     
+    #[test]
+    fn test_anthropic_betas() {
+        let mut headers = HeaderMap::new();
+        headers.append(ANTHROPIC_BETA_HEADER, "prompt-caching-2024-07-31, token-efficient-tools-2025-02-19".parse().unwrap());
+        headers.append(ANTHROPIC_BETA_HEADER, "files-api-2025-04-14".parse().unwrap());
+        
+        let betas = anthropic_betas(&headers);
+        assert_eq!(betas, vec!["prompt-caching-2024-07-31", "token-efficient-tools-2025-02-19", "files-api-2025-04-14"]);
+        assert!(anthropic_betas(&HeaderMap::new()).is_empty());
+    }
+    
+    #[test]
+    fn test_request_priority() {
+        let headers = |value: &str| HeaderMap::from_iter([(header::HeaderName::from_static(PRIORITY_HEADER), value.parse().unwrap())]);
+        assert_eq!(request_priority(&headers("Batch")), Ok(Some(Priority::Batch)));
+        assert_eq!(request_priority(&headers("interactive")), Ok(Some(Priority::Interactive)));
+        assert!(request_priority(&headers("urgent")).is_err());
+        assert_eq!(request_priority(&HeaderMap::new()), Ok(None));
+    }
+    
     #[test]
     fn test_extract_auth_header() {
         let mut headers = HeaderMap::new();
@@ -438,4 +1224,71 @@ mod tests {
         request.temperature = Some(-0.5);
         assert!(validate_claude_request(&request).is_err());
     }
-}
\ No newline at end of file
+    
+    #[test]
+    fn test_upstream_error() {
+        let upstream = |status: u16, body: &str, retry_after: Option<&str>| -> anyhow::Error {
+            ProviderError::from(UpstreamError {
+                provider: "OpenAI".to_string(),
+                status,
+                body: body.to_string(),
+                retry_after: retry_after.map(|v| v.to_string()),
+                rate_limit_reset: None,
+            }).into()
+        };
+        
+        let error = upstream_error(&upstream(429, "{}", Some("30")));
+        assert_eq!(error.error_type(), "rate_limit_error");
+        assert_eq!(error.status_code(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(error.retry_after().as_deref(), Some("30"));
+        
+        let error = upstream_error(&upstream(429, r#"{"error": {"message": "quota", "code": "insufficient_quota"}}"#, None));
+        assert_eq!(error.error_type(), "billing_error");
+        
+        let error = upstream_error(&upstream(529, "overloaded", None));
+        assert_eq!(error.error_type(), "overloaded_error");
+        assert_eq!(error.status_code().as_u16(), 529);
+        
+        let error = upstream_error(&upstream(503, "", None));
+        assert_eq!(error.error_type(), "overloaded_error");
+        
+        let body = r#"{"error": {"message": "Invalid schema for function 'f': 'object' is not valid", "type": "invalid_request_error"}}"#;
+        let error = upstream_error(&upstream(400, body, Some("5")));
+        assert_eq!(error.error_type(), "invalid_request_error");
+        assert_eq!(error.to_claude_error().error.message, "Invalid schema for function 'f': 'object' is not valid");
+        assert!(error.retry_after().is_none());
+        
+        assert_eq!(upstream_error(&upstream(401, "", None)).error_type(), "authentication_error");
+        assert_eq!(upstream_error(&upstream(403, "", None)).error_type(), "permission_error");
+        assert_eq!(upstream_error(&upstream(500, "", None)).status_code(), StatusCode::BAD_GATEWAY);
+        
+        let timeout = ProviderError::Timeout("Failed to send request: operation timed out".to_string());
+        let error = upstream_error(&anyhow::Error::from(timeout).context("openai/gpt-4o failed"));
+        assert_eq!(error.error_type(), "timeout_error");
+        assert_eq!(error.status_code(), StatusCode::GATEWAY_TIMEOUT);
+        
+        let error = upstream_error(&anyhow::anyhow!("Model not found: unknown"));
+        assert_eq!(error.error_type(), "not_found_error");
+        
+        let disabled = ModelDisabled { model: "openai/gpt-4o".to_string(), alternatives: vec!["ark/doubao".to_string()] };
+        let error = upstream_error(&disabled.into());
+        assert_eq!(error.error_type(), "overloaded_error");
+        assert_eq!(error.status_code().as_u16(), 529);
+        assert!(error.to_string().contains("try ark/doubao"));
+    }
+    
+    #[test]
+    fn test_upstream_error_response_retry_after() {
+        let error: anyhow::Error = ProviderError::from(UpstreamError {
+            provider: "OpenAI".to_string(),
+            status: 429,
+            body: String::new(),
+            retry_after: Some("12".to_string()),
+            rate_limit_reset: None,
+        }).into();
+        let response = upstream_error(&error).into_response();
+        
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers().get(header::RETRY_AFTER).unwrap(), "12");
+    }
+}
@@ -0,0 +1,43 @@
+//! Usage handler
+//! 
+//! Shows the calling client key its usage and remaining budgets, and the
+//! totals of the arms of running A/B experiments
+
+use crate::handlers::AppState;
+use crate::middleware::auth::ClientKey;
+use crate::services::experiments::ExperimentReport;
+use crate::services::tenants::UsageReport;
+use crate::utils::error::AppError;
+use axum::{extract::State, response::Json, Extension};
+use serde::Serialize;
+use std::sync::Arc;
+
+/// Response of `GET /usage`
+#[derive(Debug, Serialize)]
+pub struct UsageResponse {
+    /// Usage of the client key, with client authentication (`auth`)
+    #[serde(flatten)]
+    pub key: Option<UsageReport>,
+    /// Arms of the configured `experiments`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub experiments: Vec<ExperimentReport>,
+}
+
+/// Usage of the client key making the request, and of the experiment arms
+///
+/// Only available when client authentication (`auth`) or experiments are
+/// configured.
+pub async fn usage(
+    State(state): State<Arc<AppState>>,
+    client: Option<Extension<ClientKey>>,
+) -> Result<Json<UsageResponse>, AppError> {
+    let key = match (&state.api_keys, client) {
+        (Some(api_keys), Some(Extension(client))) => Some(api_keys.limiter().report(&client)),
+        _ => None,
+    };
+    let experiments = state.experiments.as_ref().map(|experiments| experiments.report()).unwrap_or_default();
+    if key.is_none() && experiments.is_empty() {
+        return Err(AppError::NotFound("Usage requires client authentication".to_string()));
+    }
+    Ok(Json(UsageResponse { key, experiments }))
+}
@@ -1,22 +1,37 @@
 //! AI API Proxy Library
 //! 
 //! Provides Claude API to OpenAI API conversion functionality
-//! with multi-provider routing support
+//! with multi-provider routing support. [`ProxyClient`] embeds the
+//! conversion and provider layer without running the HTTP server.
+//!
+//! The HTTP server (handlers, middleware and the per-client accounting
+//! behind them) is built with the default `server` feature. With
+//! `default-features = false` only the models, converters, providers and
+//! router are compiled.
 
 pub mod config;
+#[cfg(feature = "server")]
 pub mod handlers;
+#[cfg(feature = "server")]
 pub mod middleware;
 pub mod models;
 pub mod providers;
+pub mod proxy_client;
 pub mod services;
+#[cfg(feature = "test-util")]
+pub mod test_util;
 pub mod utils;
 
 // Re-export common types
 pub use config::{AppConfig, ModelConfig, ProviderConfig, Settings};
-pub use handlers::{create_router, AppState};
+#[cfg(feature = "server")]
+pub use handlers::{create_reloadable_router, create_router, AppState, RouterBuilder, SharedState};
 pub use models::{claude, openai};
+pub use models::claude::{ClaudeRequest, ClaudeRequestBuilder, ClaudeResponse, ClaudeStreamEvent};
+pub use models::openai::{OpenAIRequest, OpenAIRequestBuilder};
 pub use providers::{ModelHubProvider, OpenAIProvider, Provider};
-pub use services::{ApiConverter, Router};
+pub use proxy_client::ProxyClient;
+pub use services::{ApiConverter, ProviderRegistry, Router};
 pub use utils::error::{AppError, AppResult};
 
 /// Library version information
@@ -2,29 +2,237 @@
 //! 
 //! HTTP proxy service that converts Claude API requests to OpenAI API format
 //! with multi-provider routing via JSON configuration
+//!
+//! Without a subcommand the server is started; `validate-config`,
+//! `list-models` and `test` help with setting up the configuration, and
+//! `usage export` dumps the usage store for reporting.
 
+use aiapiproxy::config::remote::RemoteSource;
+use aiapiproxy::config::{ConfigSource, TlsConfig};
+use aiapiproxy::utils::logging;
+use aiapiproxy::{create_reloadable_router, AppConfig, ClaudeRequest, ProxyClient, Settings, SharedState};
 use anyhow::{Context, Result};
-use tracing::info;
+use axum_server::tls_rustls::RustlsConfig;
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+use tracing::{debug, info, warn};
+use tracing_subscriber::layer::SubscriberExt;
 
-mod config;
-mod handlers;
-mod middleware;
-mod models;
-mod providers;
-mod services;
-mod utils;
+/// Proxy converting Claude API requests to OpenAI-compatible providers
+#[derive(Debug, Parser)]
+#[command(name = "aiapiproxy", version)]
+struct Cli {
+    /// Configuration file or https://, consul:// or etcd:// URL [default: $AIAPIPROXY_CONFIG,
+    /// ~/.config/aiapiproxy/aiapiproxy.json, ./aiapiproxy.json]
+    #[arg(long, global = true, value_name = "PATH")]
+    config: Option<PathBuf>,
+    
+    #[command(subcommand)]
+    command: Option<Command>,
+}
 
-use config::{AppConfig, Settings};
-use handlers::create_router;
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Run the proxy server (default)
+    Serve,
+    /// Load and validate the configuration file
+    ValidateConfig,
+    /// List the configured provider/model paths and model mappings
+    ListModels,
+    /// Send a prompt upstream and print the converted request and the Claude response
+    Test {
+        /// Claude model name or provider/model path, e.g. "openai/gpt-4o"
+        model: String,
+        /// User message to send
+        prompt: String,
+        /// Maximum output tokens
+        #[arg(long, default_value_t = 1024)]
+        max_tokens: u32,
+    },
+    /// Read the usage store (requires the `sqlite` feature)
+    Usage {
+        #[command(subcommand)]
+        command: UsageCommand,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum UsageCommand {
+    /// Print the usage records of a time range, or their rollups
+    Export {
+        /// First day (YYYY-MM-DD, UTC) or RFC 3339 time included
+        #[arg(long)]
+        from: Option<String>,
+        /// Last day (YYYY-MM-DD, UTC) included, or RFC 3339 time excluded
+        #[arg(long)]
+        to: Option<String>,
+        /// Output format: csv or jsonl
+        #[arg(long, default_value = "csv")]
+        format: String,
+        /// Print totals per tenant, model or experiment arm instead of the records
+        #[arg(long, value_name = "tenant|model|experiment")]
+        rollup: Option<String>,
+        /// Write to a file instead of standard output
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // Initialize logging
-    init_logging();
+    let cli = Cli::parse();
+    let command = cli.command.unwrap_or(Command::Serve);
+    
+    // Initialize logging; other commands only log problems unless RUST_LOG says otherwise
+    init_logging(if matches!(command, Command::Serve) { "info" } else { "warn" });
+    
+    let config_path = match cli.config {
+        Some(config_path) => config_path,
+        None => AppConfig::default_path().context(
+            "Configuration file not found. Create ~/.config/aiapiproxy/aiapiproxy.json, \
+             or pass --config or set AIAPIPROXY_CONFIG",
+        )?,
+    };
+    let source = ConfigSource::new(config_path)?;
     
-    // Load provider configuration from JSON file (required)
-    let app_config = AppConfig::load_default()
-        .context("Failed to load provider configuration")?;
+    match command {
+        Command::Serve => serve(source).await,
+        Command::ValidateConfig => validate_config(&source).await,
+        Command::ListModels => list_models(&source).await,
+        Command::Test { model, prompt, max_tokens } => test_model(&source, model, prompt, max_tokens).await,
+        Command::Usage { command } => usage(&source, command).await,
+    }
+}
+
+/// Load the configuration file and the files it includes, or the remote configuration
+async fn load_config(source: &ConfigSource) -> Result<AppConfig> {
+    source.load().await
+        .with_context(|| format!("Failed to load configuration from {}", source))
+}
+
+/// Check the configuration and print a summary
+async fn validate_config(source: &ConfigSource) -> Result<()> {
+    let app_config = load_config(source).await?;
+    
+    let model_count: usize = app_config.providers.values().map(|provider| provider.models.len()).sum();
+    println!("✅ {} is valid", source);
+    for include in app_config.source_files.iter().skip(1) {
+        println!("   includes {}", include.display());
+    }
+    println!("   {} providers, {} models, {} model mappings", app_config.providers.len(), model_count, app_config.model_mapping.len());
+    Ok(())
+}
+
+/// Print the models requests can be routed to
+async fn list_models(source: &ConfigSource) -> Result<()> {
+    let app_config = load_config(source).await?;
+    
+    let mut paths = app_config.list_model_paths();
+    paths.sort();
+    println!("Models:");
+    for path in &paths {
+        let mut notes = Vec::new();
+        if let Some(alias) = app_config.get_provider_model(path).and_then(|(_, model)| model.alias.as_deref()) {
+            notes.push(format!("alias: {}", alias));
+        }
+        if app_config.is_disabled(path) {
+            notes.push("disabled".to_string());
+        }
+        match notes.is_empty() {
+            true => println!("  {}", path),
+            false => println!("  {} ({})", path, notes.join(", ")),
+        }
+    }
+    
+    let mut mappings: Vec<_> = app_config.model_mapping.iter().collect();
+    mappings.sort_by_key(|(claude_model, _)| claude_model.as_str());
+    if !mappings.is_empty() {
+        println!("\nModel mappings:");
+        for (claude_model, target) in mappings {
+            println!("  {} -> {}", claude_model, target.paths().join(", "));
+        }
+    }
+    Ok(())
+}
+
+/// Send one prompt through the conversion pipeline and print every stage
+async fn test_model(source: &ConfigSource, model: String, prompt: String, max_tokens: u32) -> Result<()> {
+    let app_config = load_config(source).await?;
+    let settings = Settings::new().context("Failed to load server settings")?;
+    let client = ProxyClient::new(settings, app_config)?;
+    
+    let request = ClaudeRequest::builder()
+        .model(&model)
+        .max_tokens(max_tokens)
+        .user(&prompt)
+        .build();
+    let openai_request = client.convert_request(request.clone())?;
+    let model_path = client.router().resolve_model(&openai_request.model)
+        .with_context(|| format!("Model not found: {}", openai_request.model))?;
+    println!("➡️  OpenAI request for {}:", model_path);
+    println!("{}", serde_json::to_string_pretty(&openai_request)?);
+    
+    let response = client.send(request).await
+        .with_context(|| format!("Request to {} failed", model_path))?;
+    println!("\n⬅️  Claude response:");
+    println!("{}", serde_json::to_string_pretty(&response)?);
+    Ok(())
+}
+
+/// Read the usage store
+#[cfg(feature = "sqlite")]
+async fn usage(source: &ConfigSource, command: UsageCommand) -> Result<()> {
+    use aiapiproxy::services::usage_store::{ExportQuery, UsageStore};
+    use std::io::Write;
+    
+    let app_config = load_config(source).await?;
+    let config = app_config.usage_store.context("No usageStore is configured")?;
+    if !config.path.exists() {
+        anyhow::bail!("Usage store {} does not exist", config.path.display());
+    }
+    let store = UsageStore::open(&config)?;
+    
+    match command {
+        UsageCommand::Export { from, to, format, rollup, output } => {
+            let query = ExportQuery {
+                from: from.as_deref().map(ExportQuery::parse_from).transpose()?,
+                to: to.as_deref().map(ExportQuery::parse_to).transpose()?,
+                rollup: rollup.as_deref().map(str::parse).transpose()?,
+            };
+            let format = format.parse()?;
+            let mut out: Box<dyn Write> = match &output {
+                Some(path) => Box::new(std::io::BufWriter::new(
+                    std::fs::File::create(path).with_context(|| format!("Failed to create {}", path.display()))?,
+                )),
+                None => Box::new(std::io::BufWriter::new(std::io::stdout().lock())),
+            };
+            let rows = store.export(&query, format, &mut out)?;
+            out.flush()?;
+            if let Some(path) = output {
+                println!("✅ Exported {} rows to {}", rows, path.display());
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(not(feature = "sqlite"))]
+async fn usage(_source: &ConfigSource, _command: UsageCommand) -> Result<()> {
+    anyhow::bail!("The usage store requires the `sqlite` feature")
+}
+
+/// Run the proxy server
+async fn serve(source: ConfigSource) -> Result<()> {
+    // Load provider configuration from the file or URL (required); polls of a
+    // remote configuration start from the version loaded
+    let (app_config, remote_version) = match &source {
+        ConfigSource::Remote(remote) => {
+            let fetched = remote.fetch(None).await?.context("Remote configuration not modified")?;
+            (fetched.config, Some(fetched.version))
+        }
+        ConfigSource::File(_) => (load_config(&source).await?, None),
+    };
     
     info!("📁 Provider configuration loaded");
     
@@ -33,11 +241,33 @@ async fn main() -> Result<()> {
     info!("Server settings loaded");
     
     // Create router
-    let app = create_router(settings.clone(), app_config.clone()).await?;
+    let (app, shared_state) = create_reloadable_router(settings.clone(), app_config.clone()).await?;
+    match source {
+        ConfigSource::File(config_path) => spawn_config_reload(shared_state, config_path, &app_config),
+        ConfigSource::Remote(remote) => spawn_remote_config_poll(shared_state, remote, remote_version, &app_config),
+    }
     
     // Build server address from JSON config
     let addr = format!("{}:{}", app_config.server.host, app_config.server.port);
     
+    // Serve HTTPS when certificates are configured
+    if let Some(tls) = &app_config.server.tls {
+        let rustls_config = load_tls(tls).await?;
+        
+        info!("🚀 AI API Proxy server started!");
+        info!("📝 Health check: https://{}/health", addr);
+        info!("🔄 Proxy endpoint: https://{}/v1/messages", addr);
+        
+        let socket_addr = addr.parse()
+            .with_context(|| format!("Invalid listen address: {}", addr))?;
+        axum_server::bind_rustls(socket_addr, rustls_config)
+            .serve(app.into_make_service())
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to start server: {}", e))?;
+        
+        return Ok(());
+    }
+    
     // Start server
     let listener = tokio::net::TcpListener::bind(&addr).await?;
     
@@ -52,31 +282,184 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
+/// Load the certificate and key, and keep reloading them if configured
+async fn load_tls(tls: &TlsConfig) -> Result<RustlsConfig> {
+    rustls::crypto::ring::default_provider()
+        .install_default()
+        .map_err(|_| anyhow::anyhow!("Failed to install the TLS crypto provider"))?;
+    
+    let rustls_config = RustlsConfig::from_pem_file(&tls.cert_path, &tls.key_path)
+        .await
+        .with_context(|| format!("Failed to load TLS certificate {} and key {}", tls.cert_path.display(), tls.key_path.display()))?;
+    info!("🔒 TLS certificate loaded from {}", tls.cert_path.display());
+    
+    if let Some(secs) = tls.reload_interval_secs {
+        let reloaded = rustls_config.clone();
+        let (cert_path, key_path) = (tls.cert_path.clone(), tls.key_path.clone());
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(secs));
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                // A failed reload keeps serving the previous certificate
+                match reloaded.reload_from_pem_file(&cert_path, &key_path).await {
+                    Ok(()) => debug!("🔒 TLS certificate reloaded"),
+                    Err(e) => warn!("🔒 Failed to reload TLS certificate: {}", e),
+                }
+            }
+        });
+    }
+    
+    Ok(rustls_config)
+}
+
+/// Reload the configuration file on SIGHUP, and when it or one of its
+/// includes changes if an interval is configured
+///
+/// A file that fails to load or validate keeps the previous configuration.
+fn spawn_config_reload(shared_state: SharedState, config_path: PathBuf, app_config: &AppConfig) {
+    let interval_secs = app_config.server.config_reload_interval_secs;
+    let mut source_files = app_config.source_files.clone();
+    tokio::spawn(async move {
+        let mut hangup = HangupSignal::new();
+        let mut interval = interval_secs.map(|secs| tokio::time::interval(Duration::from_secs(secs)));
+        let mut last_modified = modified_times(&source_files);
+        
+        loop {
+            let actor = tokio::select! {
+                _ = hangup.recv() => {
+                    info!("🔄 SIGHUP received, reloading {}", config_path.display());
+                    "sighup"
+                }
+                _ = async {
+                    match interval.as_mut() {
+                        Some(interval) => interval.tick().await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    if modified_times(&source_files) == last_modified {
+                        continue;
+                    }
+                    info!("🔄 {} changed, reloading", config_path.display());
+                    "file-watcher"
+                }
+            };
+            
+            let reloaded = AppConfig::load(&config_path).and_then(|config| {
+                let files = config.source_files.clone();
+                shared_state.reload_by(config, actor, Some(&config_path.display().to_string()))?;
+                Ok(files)
+            });
+            match reloaded {
+                Ok(files) => source_files = files,
+                Err(e) => warn!("🔄 Keeping the previous configuration: {:#}", e),
+            }
+            last_modified = modified_times(&source_files);
+        }
+    });
+}
+
+/// Default interval between polls of a remote configuration
+const REMOTE_POLL_INTERVAL_SECS: u64 = 60;
+
+/// Poll a remote configuration and reload it when it changed, at the
+/// configured interval (default: 60 seconds) and on SIGHUP
+///
+/// A configuration that fails to fetch or validate keeps the previous one.
+fn spawn_remote_config_poll(shared_state: SharedState, remote: RemoteSource, mut version: Option<String>, app_config: &AppConfig) {
+    let interval_secs = app_config.server.config_reload_interval_secs.unwrap_or(REMOTE_POLL_INTERVAL_SECS);
+    tokio::spawn(async move {
+        let mut hangup = HangupSignal::new();
+        let mut interval = tokio::time::interval(Duration::from_secs(interval_secs));
+        interval.tick().await;
+        
+        loop {
+            let (current, actor) = tokio::select! {
+                _ = hangup.recv() => {
+                    info!("🔄 SIGHUP received, reloading {}", remote);
+                    (None, "sighup")
+                }
+                _ = interval.tick() => (version.as_deref(), "remote-poll"),
+            };
+            
+            let reloaded = remote.fetch(current).await.and_then(|fetched| match fetched {
+                Some(fetched) => {
+                    info!("🔄 {} changed, reloading", remote);
+                    shared_state.reload_by(fetched.config, actor, Some(&remote.to_string()))?;
+                    Ok(Some(fetched.version))
+                }
+                None => Ok(None),
+            });
+            match reloaded {
+                Ok(Some(fetched_version)) => version = Some(fetched_version),
+                Ok(None) => {}
+                Err(e) => warn!("🔄 Keeping the previous configuration: {:#}", e),
+            }
+        }
+    });
+}
+
+fn modified_times(paths: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    paths.iter()
+        .map(|path| std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok())
+        .collect()
+}
+
+/// SIGHUP, on platforms that have it
+struct HangupSignal {
+    #[cfg(unix)]
+    signal: Option<tokio::signal::unix::Signal>,
+}
+
+impl HangupSignal {
+    fn new() -> Self {
+        #[cfg(unix)]
+        let signal = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+            .map_err(|e| warn!("🔄 Failed to listen for SIGHUP: {}", e))
+            .ok();
+        Self {
+            #[cfg(unix)]
+            signal,
+        }
+    }
+    
+    /// Wait for the next signal; never completes without one
+    async fn recv(&mut self) {
+        #[cfg(unix)]
+        if let Some(signal) = self.signal.as_mut() {
+            if signal.recv().await.is_some() {
+                return;
+            }
+            self.signal = None;
+        }
+        std::future::pending().await
+    }
+}
+
 /// Initialize logging system
-fn init_logging() {
-    // Get log level from environment variable, default to info
-    let log_level = std::env::var("RUST_LOG").unwrap_or_else(|_| "info".to_string());
+fn init_logging(default_level: &str) {
+    // Get log level from environment variable
+    let log_level = std::env::var("RUST_LOG").unwrap_or_else(|_| default_level.to_string());
     
     // Check if JSON format should be used
     let log_format = std::env::var("LOG_FORMAT").unwrap_or_else(|_| "text".to_string());
     
+    // The filter can be changed while running through PUT /admin/log-level
+    let registry = tracing_subscriber::registry().with(logging::reloadable_filter(&log_level));
     let subscriber: Box<dyn tracing::Subscriber + Send + Sync> = if log_format == "json" {
         // JSON format logs (production environment)
-        Box::new(tracing_subscriber::fmt()
-            .with_env_filter(log_level)
+        Box::new(registry.with(tracing_subscriber::fmt::layer()
             .json()
-            .with_current_span(false)
-            .with_span_list(false)
-            .finish())
+            // The request span carries the request ID
+            .with_current_span(true)
+            .with_span_list(false)))
     } else {
         // Human readable format (development environment)
-        Box::new(tracing_subscriber::fmt()
-            .with_env_filter(log_level)
+        Box::new(registry.with(tracing_subscriber::fmt::layer()
             .with_target(false)
             .with_thread_ids(false)
             .with_file(false)
-            .with_line_number(false)
-            .finish())
+            .with_line_number(false)))
     };
     
     tracing::subscriber::set_global_default(subscriber)
@@ -1,7 +1,15 @@
 //! Authentication middleware
 //! 
 //! Handles API key validation and access control
+//!
+//! With an `auth` section in the config, [`client_key_middleware`] only lets
+//! requests through that carry one of the proxy-issued keys, in `x-api-key`
+//! or as a Bearer token. Keys are matched by their SHA-256 hash. Their
+//! per-key limits are enforced by the [`TenantLimiter`].
 
+use crate::config::{AuthConfig, KeyLimits, Priority};
+use crate::services::tenants::TenantLimiter;
+use crate::utils::error::AppError;
 use axum::{
     extract::{Request, State},
     http::{HeaderMap, StatusCode},
@@ -9,9 +17,116 @@ use axum::{
     response::Response,
     body::Body,
 };
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
 use std::sync::Arc;
 use tracing::{debug, warn};
 
+/// Authenticated client, added to the request extensions by [`client_key_middleware`]
+#[derive(Debug, Clone)]
+pub struct ClientKey {
+    /// Name of the key's holder
+    pub name: String,
+    /// Tenant the key belongs to
+    pub tenant: String,
+    /// Default priority of the key's requests
+    pub priority: Priority,
+    /// Limits of the key's requests
+    pub limits: KeyLimits,
+    /// Whether the key may use the admin API
+    pub admin: bool,
+}
+
+/// Proxy-issued API keys by hash
+#[derive(Debug)]
+pub struct ApiKeyStore {
+    keys: HashMap<String, ClientKey>,
+    limiter: TenantLimiter,
+}
+
+impl ApiKeyStore {
+    /// Create a store from the `auth` config
+    pub fn new(config: &AuthConfig) -> Self {
+        let keys = config.keys.iter()
+            .map(|key| {
+                let client = ClientKey {
+                    name: key.name.clone(),
+                    tenant: key.tenant.clone().unwrap_or_else(|| key.name.clone()),
+                    priority: key.priority.unwrap_or_default(),
+                    limits: key.limits.clone(),
+                    admin: key.admin,
+                };
+                (key.key_hash.to_ascii_lowercase(), client)
+            })
+            .collect();
+        Self { keys, limiter: TenantLimiter::new(config.tenants.clone()) }
+    }
+    
+    /// Continue counting the usage of another store, e.g. after a reload
+    pub fn with_usage_of(self, previous: &ApiKeyStore) -> Self {
+        Self { limiter: self.limiter.with_usage_of(&previous.limiter), ..self }
+    }
+    
+    /// Limits and usage of the keys
+    pub fn limiter(&self) -> &TenantLimiter {
+        &self.limiter
+    }
+    
+    /// Client owning an API key, if the key is known
+    pub fn authenticate(&self, api_key: &str) -> Option<&ClientKey> {
+        self.keys.get(&hash_api_key(api_key))
+    }
+}
+
+/// Hex-encoded SHA-256 hash of an API key, as configured in `keyHash`
+pub fn hash_api_key(api_key: &str) -> String {
+    Sha256::digest(api_key.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// API key sent by a client: `x-api-key`, or a Bearer token in `Authorization`
+pub fn extract_client_key(headers: &HeaderMap) -> Option<&str> {
+    if let Some(key) = headers.get("x-api-key").and_then(|v| v.to_str().ok()) {
+        return Some(key.trim());
+    }
+    headers.get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(str::trim)
+}
+
+/// Client API key middleware
+///
+/// Rejects requests without a known key with a Claude `authentication_error`
+/// when `auth` is configured. Health checks stay open.
+pub async fn client_key_middleware(
+    State(state): State<Arc<crate::handlers::AppState>>,
+    mut request: Request<Body>,
+    next: Next,
+) -> Result<Response<axum::body::Body>, AppError> {
+    let Some(api_keys) = &state.api_keys else {
+        return Ok(next.run(request).await);
+    };
+    if request.uri().path().starts_with("/health") {
+        return Ok(next.run(request).await);
+    }
+    
+    let client = match extract_client_key(request.headers()) {
+        Some(key) => api_keys.authenticate(key).cloned(),
+        None => return Err(AppError::Authentication("missing API key (x-api-key header)".to_string())),
+    };
+    let Some(client) = client else {
+        warn!("🔑 Rejected request with an unknown API key");
+        return Err(AppError::Authentication("invalid x-api-key".to_string()));
+    };
+    
+    debug!("🔑 Authenticated client '{}' of tenant '{}'", client.name, client.tenant);
+    request.extensions_mut().insert(client);
+    Ok(next.run(request).await)
+}
+
 /// Authentication middleware
 /// 
 /// Validates API keys in requests
@@ -64,11 +179,7 @@ pub fn validate_api_key(api_key: &str) -> bool {
     }
     
     // Remove Bearer prefix if present
-    let token = if api_key.starts_with("Bearer ") {
-        &api_key[7..]
-    } else {
-        api_key
-    };
+    let token = api_key.strip_prefix("Bearer ").unwrap_or(api_key);
     
     // Validate token format
     validate_token_format(token)
@@ -201,6 +312,43 @@ async fn record_request(client_id: &str) {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::ApiKeyConfig;
+    
+    #[test]
+    fn test_api_key_store() {
+        let store = ApiKeyStore::new(&AuthConfig {
+            keys: vec![ApiKeyConfig {
+                name: "laptop".to_string(),
+                key_hash: hash_api_key("sk-proxy-1234567890").to_ascii_uppercase(),
+                tenant: None,
+                priority: None,
+                admin: false,
+                limits: Default::default(),
+            }],
+            tenants: Default::default(),
+        });
+        
+        let client = store.authenticate("sk-proxy-1234567890").unwrap();
+        assert_eq!(client.name, "laptop");
+        assert_eq!(client.tenant, "laptop");
+        assert!(store.authenticate("sk-proxy-0987654321").is_none());
+        assert_eq!(
+            hash_api_key("abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+    
+    #[test]
+    fn test_extract_client_key() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(extract_client_key(&headers), None);
+        
+        headers.insert("authorization", "Bearer sk-proxy-1".parse().unwrap());
+        assert_eq!(extract_client_key(&headers), Some("sk-proxy-1"));
+        
+        headers.insert("x-api-key", "sk-proxy-2".parse().unwrap());
+        assert_eq!(extract_client_key(&headers), Some("sk-proxy-2"));
+    }
     
     #[test]
     fn test_validate_api_key() {
@@ -0,0 +1,134 @@
+//! Request body size limits
+//!
+//! Requests may carry at most `MAX_REQUEST_SIZE` bytes, or
+//! `MAX_IMAGE_REQUEST_SIZE` bytes if they contain image blocks (base64 images
+//! quickly outgrow the regular limit). The larger cap is enforced by tower's
+//! `RequestBodyLimitLayer`; [`body_limit_middleware`] applies the regular one
+//! to requests without images, and [`claude_payload_too_large`] turns the
+//! layer's plain-text 413 responses into Claude errors.
+
+use crate::config::settings::RequestConfig;
+use crate::models::claude::{ClaudeError, ClaudeErrorResponse};
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::Value;
+use tracing::warn;
+
+/// Size limits of request bodies in bytes
+#[derive(Debug, Clone, Copy)]
+pub struct BodyLimits {
+    /// Limit of requests without images
+    pub max_request_size: usize,
+    /// Limit of requests with images
+    pub max_image_request_size: usize,
+}
+
+impl From<&RequestConfig> for BodyLimits {
+    fn from(config: &RequestConfig) -> Self {
+        Self {
+            max_request_size: config.max_request_size,
+            max_image_request_size: config.max_image_request_size,
+        }
+    }
+}
+
+/// Reject requests over the regular size limit unless they carry images
+///
+/// Bodies within the limit by `Content-Length` pass through unread.
+pub async fn body_limit_middleware(
+    State(limits): State<BodyLimits>,
+    request: Request<Body>,
+    next: Next,
+) -> Response<Body> {
+    let content_length = request.headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<usize>().ok());
+    if content_length.is_some_and(|length| length <= limits.max_request_size) {
+        return next.run(request).await;
+    }
+    
+    // The body limit layer caps the body at the image limit
+    let (parts, body) = request.into_parts();
+    let bytes = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(_) => return payload_too_large(limits.max_image_request_size),
+    };
+    if bytes.len() > limits.max_request_size && !has_images(&bytes) {
+        warn!("Rejected request of {} bytes without images (limit {})", bytes.len(), limits.max_request_size);
+        return payload_too_large(limits.max_request_size);
+    }
+    next.run(Request::from_parts(parts, Body::from(bytes))).await
+}
+
+/// Replace plain-text 413 responses (e.g. from `RequestBodyLimitLayer`) with a Claude error
+pub async fn claude_payload_too_large(response: Response<Body>) -> Response<Body> {
+    let is_json = response.headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|value| value.as_bytes().starts_with(b"application/json"));
+    if response.status() != StatusCode::PAYLOAD_TOO_LARGE || is_json {
+        return response;
+    }
+    let error = ClaudeErrorResponse {
+        error_type: "error".to_string(),
+        error: ClaudeError {
+            error_type: "request_too_large".to_string(),
+            message: "Request exceeds the maximum allowed size.".to_string(),
+        },
+    };
+    (StatusCode::PAYLOAD_TOO_LARGE, Json(error)).into_response()
+}
+
+fn payload_too_large(limit: usize) -> Response<Body> {
+    let error = ClaudeErrorResponse {
+        error_type: "error".to_string(),
+        error: ClaudeError {
+            error_type: "request_too_large".to_string(),
+            message: format!("Request exceeds the maximum allowed size of {} bytes.", limit),
+        },
+    };
+    (StatusCode::PAYLOAD_TOO_LARGE, Json(error)).into_response()
+}
+
+/// Whether a Claude request body contains an image block, also inside tool results
+fn has_images(body: &[u8]) -> bool {
+    fn contains_image(value: &Value) -> bool {
+        match value {
+            Value::Object(object) => {
+                object.get("type").and_then(Value::as_str) == Some("image")
+                    || object.get("content").is_some_and(contains_image)
+            }
+            Value::Array(items) => items.iter().any(contains_image),
+            _ => false,
+        }
+    }
+    
+    serde_json::from_slice::<Value>(body)
+        .ok()
+        .and_then(|request| request.get("messages").cloned())
+        .is_some_and(|messages| contains_image(&messages))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_has_images() {
+        let image = r#"{"type": "image", "source": {"type": "base64", "media_type": "image/png", "data": "iVBORw0KGgo="}}"#;
+        let with_image = format!(r#"{{"messages": [{{"role": "user", "content": [{{"type": "text", "text": "What is this?"}}, {}]}}]}}"#, image);
+        let in_tool_result = format!(r#"{{"messages": [{{"role": "user", "content": [{{"type": "tool_result", "tool_use_id": "t1", "content": [{}]}}]}}]}}"#, image);
+        let text_only = r#"{"messages": [{"role": "user", "content": "A picture of type image"}], "system": [{"type": "image"}]}"#;
+        
+        assert!(has_images(with_image.as_bytes()));
+        assert!(has_images(in_tool_result.as_bytes()));
+        assert!(!has_images(text_only.as_bytes()));
+        assert!(!has_images(b"not json"));
+    }
+}
@@ -1,7 +1,10 @@
 //! Middleware module
 //!
-//! Contains authentication, logging and other middleware
+//! Contains authentication, body size limit, logging, request IDs, request timeout and other middleware
 
 pub mod auth;
+pub mod body_limit;
 pub mod logging;
+pub mod request_id;
+pub mod timeout;
 
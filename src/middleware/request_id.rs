@@ -0,0 +1,60 @@
+//! Request ID middleware
+//!
+//! [`request_id_middleware`] gives every inbound request an ID. It is added to
+//! the request extensions as [`RequestId`], recorded on a tracing span so all
+//! log lines of the request carry it, returned in the `request-id` and
+//! `anthropic-request-id` response headers, and forwarded upstream as
+//! `X-Request-Id`, so proxy and provider logs can be correlated.
+
+use axum::{
+    body::Body,
+    extract::Request,
+    http::HeaderValue,
+    middleware::Next,
+    response::Response,
+};
+use tracing::Instrument;
+use uuid::Uuid;
+
+/// Response header carrying the request ID
+pub const REQUEST_ID_HEADER: &str = "request-id";
+
+/// Anthropic's name of the request ID response header
+pub const ANTHROPIC_REQUEST_ID_HEADER: &str = "anthropic-request-id";
+
+/// ID of an inbound request, added to the request extensions
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(pub String);
+
+impl RequestId {
+    /// Generate a new ID in Anthropic's `req_` format
+    pub fn new() -> Self {
+        Self(format!("req_{}", Uuid::new_v4().simple()))
+    }
+}
+
+impl Default for RequestId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Display for RequestId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Request ID middleware
+pub async fn request_id_middleware(mut request: Request<Body>, next: Next) -> Response<Body> {
+    let request_id = RequestId::new();
+    let span = tracing::info_span!("request", request_id = %request_id);
+    request.extensions_mut().insert(request_id.clone());
+    
+    let mut response = next.run(request).instrument(span).await;
+    if let Ok(value) = HeaderValue::from_str(&request_id.0) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value.clone());
+        response.headers_mut().insert(ANTHROPIC_REQUEST_ID_HEADER, value);
+    }
+    response
+}
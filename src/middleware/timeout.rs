@@ -0,0 +1,209 @@
+//! Request timeout middleware
+//!
+//! With `timeouts` configured, [`request_timeout_middleware`] bounds how long
+//! a client waits: non-streaming requests get `requestMs` for the complete
+//! response, streaming requests get `streamFirstTokenMs` until the first
+//! event from upstream (keep-alive pings don't count). A request over its
+//! budget is answered with a Claude `api_error`: a 504 response, or an SSE
+//! `error` event once the stream has started. Dropping the handler or the
+//! stream cancels the upstream request.
+
+use crate::config::TimeoutConfig;
+use crate::models::claude::{ClaudeError, ClaudeErrorResponse, ClaudeStreamEvent};
+use axum::{
+    body::{Body, Bytes},
+    extract::{Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use futures::{Stream, StreamExt};
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::warn;
+
+/// The only request field the middleware looks at
+#[derive(Deserialize)]
+struct StreamFlag {
+    #[serde(default)]
+    stream: Option<bool>,
+}
+
+/// Request timeout middleware
+///
+/// Routes without a budget pass through untouched.
+pub async fn request_timeout_middleware(
+    State(timeouts): State<Arc<TimeoutConfig>>,
+    request: Request<Body>,
+    next: Next,
+) -> Response<Body> {
+    let started = Instant::now();
+    let path = request.uri().path().to_string();
+    let budget = timeouts.budget(&path);
+    if budget.request_ms.is_none() && budget.stream_first_token_ms.is_none() {
+        return next.run(request).await;
+    }
+    
+    // Streaming is a body field; buffer the body (capped by the body limit layer) to read it
+    let (parts, body) = request.into_parts();
+    let bytes = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(e) => return error_response(StatusCode::PAYLOAD_TOO_LARGE, "request_too_large", &format!("Failed to read request body: {}", e)),
+    };
+    let streaming = serde_json::from_slice::<StreamFlag>(&bytes)
+        .is_ok_and(|flag| flag.stream.unwrap_or(false));
+    let request = Request::from_parts(parts, Body::from(bytes));
+    
+    let (limit, what) = if streaming {
+        (budget.stream_first_token_ms, "the first streamed token")
+    } else {
+        (budget.request_ms, "the response")
+    };
+    let Some(limit) = limit.map(Duration::from_millis) else {
+        return next.run(request).await;
+    };
+    let deadline = started + limit;
+    
+    let response = match tokio::time::timeout_at(deadline, next.run(request)).await {
+        Ok(response) => response,
+        Err(_) => {
+            let message = timeout_message(limit, what);
+            warn!("⏱️ {} {}", path, message);
+            return error_response(StatusCode::GATEWAY_TIMEOUT, "api_error", &message);
+        }
+    };
+    
+    let is_event_stream = response.headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("text/event-stream"));
+    if !is_event_stream {
+        return response;
+    }
+    let (parts, body) = response.into_parts();
+    Response::from_parts(parts, Body::from_stream(first_token_deadline(body, deadline, limit, path)))
+}
+
+/// Stream the body's events, ending with an `error` event if no event but
+/// keep-alives (pings or `:` comments) arrives before the deadline
+fn first_token_deadline(
+    body: Body,
+    deadline: Instant,
+    limit: Duration,
+    path: String,
+) -> impl Stream<Item = Result<Bytes, axum::Error>> + Send + 'static {
+    // `None` once the stream timed out
+    let state = Some((body.into_data_stream(), false));
+    futures::stream::unfold(state, move |state| {
+        let path = path.clone();
+        async move {
+            let (mut frames, started) = state?;
+            if started {
+                let frame = frames.next().await?;
+                return Some((frame, Some((frames, true))));
+            }
+            
+            match tokio::time::timeout_at(deadline, frames.next()).await {
+                Ok(Some(Ok(frame))) => {
+                    let started = !frame.starts_with(b"event: ping") && !frame.starts_with(b":");
+                    Some((Ok(frame), Some((frames, started))))
+                }
+                Ok(frame) => frame.map(|frame| (frame, Some((frames, true)))),
+                Err(_) => {
+                    let message = timeout_message(limit, "the first streamed token");
+                    warn!("⏱️ {} {}", path, message);
+                    Some((Ok(error_event(&message)), None))
+                }
+            }
+        }
+    })
+}
+
+fn timeout_message(limit: Duration, what: &str) -> String {
+    format!("Request timed out after {:?} waiting for {}", limit, what)
+}
+
+/// Claude error response
+fn error_response(status: StatusCode, error_type: &str, message: &str) -> Response<Body> {
+    let error = ClaudeErrorResponse {
+        error_type: "error".to_string(),
+        error: ClaudeError {
+            error_type: error_type.to_string(),
+            message: message.to_string(),
+        },
+    };
+    (status, Json(error)).into_response()
+}
+
+/// Claude SSE `error` event
+fn error_event(message: &str) -> Bytes {
+    let event = ClaudeStreamEvent::Error {
+        error: ClaudeError {
+            error_type: "api_error".to_string(),
+            message: message.to_string(),
+        },
+    };
+    let data = serde_json::to_string(&event).unwrap_or_default();
+    Bytes::from(format!("event: error\ndata: {}\n\n", data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[tokio::test]
+    async fn test_first_token_deadline() {
+        let ping = Bytes::from_static(b"event: ping\ndata: {\"type\":\"ping\"}\n\n");
+        let upstream = futures::stream::iter([Ok::<_, axum::Error>(ping.clone())])
+            .chain(futures::stream::pending());
+        let limit = Duration::from_millis(50);
+        
+        let frames: Vec<Bytes> = first_token_deadline(Body::from_stream(upstream), Instant::now() + limit, limit, "/v1/messages".to_string())
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0], ping);
+        let error = String::from_utf8(frames[1].to_vec()).unwrap();
+        assert!(error.starts_with("event: error\ndata: "));
+        assert!(error.contains(r#""type":"api_error""#));
+        assert!(error.contains("timed out after 50ms"));
+    }
+    
+    #[tokio::test]
+    async fn test_comment_keep_alives_dont_start_the_stream() {
+        let keep_alive = Bytes::from_static(b": keep-alive\n\n");
+        let upstream = futures::stream::iter([Ok::<_, axum::Error>(keep_alive.clone()), Ok(keep_alive.clone())])
+            .chain(futures::stream::pending());
+        let limit = Duration::from_millis(50);
+        
+        let frames: Vec<Bytes> = first_token_deadline(Body::from_stream(upstream), Instant::now() + limit, limit, "/v1/messages".to_string())
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[1], keep_alive);
+        assert!(frames[2].starts_with(b"event: error\ndata: "));
+    }
+    
+    #[tokio::test]
+    async fn test_started_stream_not_limited() {
+        let message_start = Bytes::from_static(b"event: message_start\ndata: {}\n\n");
+        let upstream = futures::stream::iter([Ok::<_, axum::Error>(message_start)])
+            .chain(futures::stream::once(async {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                Ok(Bytes::from_static(b"event: message_stop\ndata: {}\n\n"))
+            }));
+        let limit = Duration::from_millis(50);
+        
+        let frames: Vec<Bytes> = first_token_deadline(Body::from_stream(upstream), Instant::now() + limit, limit, "/v1/messages".to_string())
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        assert_eq!(frames.len(), 2);
+        assert!(frames[1].starts_with(b"event: message_stop"));
+    }
+}
@@ -11,8 +11,22 @@ use std::collections::HashMap;
 pub enum SystemPrompt {
     /// Single string system prompt
     String(String),
-    /// Array of system message blocks (for compatibility with some clients)
-    Array(Vec<ClaudeContentBlock>),
+    /// Array of system message blocks (order and cache markers are preserved)
+    Array(Vec<ClaudeSystemBlock>),
+}
+
+/// System prompt block
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ClaudeSystemBlock {
+    /// Block type (only "text" blocks are used)
+    #[serde(rename = "type")]
+    pub block_type: String,
+    /// Block text
+    #[serde(default)]
+    pub text: String,
+    /// Prompt caching marker (e.g. {"type": "ephemeral"})
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_control: Option<serde_json::Value>,
 }
 
 /// Claude API request structure
@@ -51,6 +65,84 @@ pub struct ClaudeRequest {
     /// Tool choice (optional) - controls tool usage
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tool_choice: Option<serde_json::Value>,
+    /// Number of choices (non-standard extension, see `multipleChoices` config)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub n: Option<u32>,
+    /// Extended thinking configuration (optional)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thinking: Option<ClaudeThinking>,
+    /// Beta features from the `anthropic-beta` header (internal use, not part of the body)
+    #[serde(skip)]
+    pub betas: Vec<String>,
+}
+
+/// Claude extended thinking configuration
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ClaudeThinking {
+    /// "enabled" or "disabled"
+    #[serde(rename = "type")]
+    pub thinking_type: String,
+    /// Thinking token budget (required when enabled)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub budget_tokens: Option<u32>,
+}
+
+impl ClaudeThinking {
+    /// Token budget if extended thinking is enabled
+    pub fn enabled_budget(&self) -> Option<u32> {
+        if self.thinking_type == "enabled" {
+            self.budget_tokens
+        } else {
+            None
+        }
+    }
+}
+
+/// Claude token counting request (POST /v1/messages/count_tokens)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClaudeCountTokensRequest {
+    /// Model name
+    pub model: String,
+    /// Message list
+    pub messages: Vec<ClaudeMessage>,
+    /// System prompt (optional)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system: Option<SystemPrompt>,
+    /// Tools (optional)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<ClaudeTool>>,
+    /// Tool choice (optional)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_choice: Option<serde_json::Value>,
+}
+
+impl From<ClaudeCountTokensRequest> for ClaudeRequest {
+    fn from(request: ClaudeCountTokensRequest) -> Self {
+        Self {
+            model: request.model,
+            max_tokens: 1,
+            messages: request.messages,
+            system: request.system,
+            temperature: None,
+            top_p: None,
+            top_k: None,
+            stop_sequences: None,
+            stream: None,
+            metadata: None,
+            tools: request.tools,
+            tool_choice: request.tool_choice,
+            n: None,
+            thinking: None,
+            betas: Vec::new(),
+        }
+    }
+}
+
+/// Claude token counting response
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClaudeCountTokensResponse {
+    /// Estimated prompt tokens
+    pub input_tokens: u32,
 }
 
 /// Claude message structure
@@ -101,10 +193,24 @@ pub enum ClaudeContentBlock {
     #[serde(rename = "tool_result")]
     ToolResult {
         tool_use_id: String,
-        content: String,
+        #[serde(default)]
+        content: ClaudeToolResultContent,
         #[serde(skip_serializing_if = "Option::is_none")]
         is_error: Option<bool>,
     },
+    /// Server tool use block (tool executed by the provider, e.g. web search)
+    #[serde(rename = "server_tool_use")]
+    ServerToolUse {
+        id: String,
+        name: String,
+        input: serde_json::Value,
+    },
+    /// Web search results for a server tool use block
+    #[serde(rename = "web_search_tool_result")]
+    WebSearchToolResult {
+        tool_use_id: String,
+        content: serde_json::Value,
+    },
     /// Unknown/unsupported block type - catch-all to prevent parsing errors
     #[serde(other)]
     Unknown,
@@ -113,25 +219,98 @@ pub enum ClaudeContentBlock {
 /// Claude image source
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct ClaudeImageSource {
-    /// Source type (base64)
+    /// Source type ("base64" or "url")
     #[serde(rename = "type")]
     pub source_type: String,
-    /// Media type
+    /// Media type (base64 sources only)
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub media_type: String,
-    /// Image data
+    /// Image data (base64 sources only)
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub data: String,
+    /// Image URL (url sources only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+}
+
+/// Claude tool result content
+///
+/// Either a plain string or a list of content blocks; tools such as screenshot
+/// tools return image blocks alongside text.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum ClaudeToolResultContent {
+    /// Plain text result
+    Text(String),
+    /// Text and image blocks
+    Blocks(Vec<ClaudeContentBlock>),
+}
+
+impl Default for ClaudeToolResultContent {
+    fn default() -> Self {
+        Self::Text(String::new())
+    }
+}
+
+impl From<String> for ClaudeToolResultContent {
+    fn from(text: String) -> Self {
+        Self::Text(text)
+    }
+}
+
+impl ClaudeToolResultContent {
+    /// Text of the result, with text blocks joined by newlines
+    pub fn text(&self) -> String {
+        match self {
+            Self::Text(text) => text.clone(),
+            Self::Blocks(blocks) => blocks.iter()
+                .filter_map(|block| match block {
+                    ClaudeContentBlock::Text { text } => Some(text.as_str()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+    
+    /// Image sources embedded in the result
+    pub fn images(&self) -> Vec<&ClaudeImageSource> {
+        match self {
+            Self::Text(_) => Vec::new(),
+            Self::Blocks(blocks) => blocks.iter()
+                .filter_map(|block| match block {
+                    ClaudeContentBlock::Image { source } => Some(source),
+                    _ => None,
+                })
+                .collect(),
+        }
+    }
 }
 
 /// Claude tool definition
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ClaudeTool {
+    /// Tool type for server tools (e.g. "web_search_20250305"); absent for custom tools
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub tool_type: Option<String>,
     /// Tool name
     pub name: String,
     /// Tool description (optional)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub description: Option<String>,
-    /// Input schema for the tool
+    /// Input schema for the tool (custom tools only)
+    #[serde(default)]
     pub input_schema: serde_json::Value,
+    /// Server tool settings (e.g. max_uses, allowed_domains, user_location)
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
+}
+
+impl ClaudeTool {
+    /// Whether this is the Claude web search server tool
+    pub fn is_web_search(&self) -> bool {
+        self.tool_type.as_deref().is_some_and(|t| t.starts_with("web_search_"))
+    }
 }
 
 /// Claude API response structure
@@ -154,15 +333,28 @@ pub struct ClaudeResponse {
     pub stop_sequence: Option<String>,
     /// Usage statistics
     pub usage: ClaudeUsage,
+    /// Proxy metadata (non-standard; e.g. additional choices when `n > 1`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<serde_json::Value>,
 }
 
 /// Claude usage statistics
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ClaudeUsage {
-    /// Input token count
+    /// Input token count, without the tokens read from the prompt cache
     pub input_tokens: u32,
     /// Output token count
     pub output_tokens: u32,
+    /// Input tokens read from the upstream's prompt cache
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_read_input_tokens: Option<u32>,
+}
+
+impl ClaudeUsage {
+    /// Input tokens including those read from the prompt cache
+    pub fn total_input_tokens(&self) -> u32 {
+        self.input_tokens + self.cache_read_input_tokens.unwrap_or(0)
+    }
 }
 
 /// Claude streaming response event
@@ -222,6 +414,18 @@ pub struct ClaudeStreamMessage {
     pub stop_reason: Option<String>,
     pub stop_sequence: Option<String>,
     pub usage: ClaudeUsage,
+    /// Set by the proxy when a fallback upstream answered (not part of the Claude API)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fallback: Option<FallbackNotice>,
+}
+
+/// Notice that a request was answered by a fallback of the upstream it was meant for
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FallbackNotice {
+    /// Provider/model path the request was meant for
+    pub requested: String,
+    /// Provider/model path that answered
+    pub served_by: String,
 }
 
 /// Claude content delta
@@ -267,18 +471,37 @@ impl SystemPrompt {
             SystemPrompt::Array(blocks) => {
                 blocks
                     .iter()
-                    .filter_map(|block| match block {
-                        ClaudeContentBlock::Text { text } => Some(text.clone()),
-                        ClaudeContentBlock::Image { .. } => None,
-                        ClaudeContentBlock::ToolUse { .. } => None,
-                        ClaudeContentBlock::ToolResult { content, .. } => Some(content.clone()),
-                        ClaudeContentBlock::Unknown => None,
-                    })
+                    .filter(|block| block.block_type == "text")
+                    .map(|block| block.text.clone())
                     .collect::<Vec<String>>()
                     .join(" ")
             }
         }
     }
+    
+    /// Check if any block carries a cache_control marker
+    pub fn has_cache_control(&self) -> bool {
+        match self {
+            SystemPrompt::String(_) => false,
+            SystemPrompt::Array(blocks) => blocks.iter().any(|block| block.cache_control.is_some()),
+        }
+    }
+}
+
+impl ClaudeStreamEvent {
+    /// Event type, used as the SSE `event` field
+    pub fn event_type(&self) -> &'static str {
+        match self {
+            ClaudeStreamEvent::MessageStart { .. } => "message_start",
+            ClaudeStreamEvent::ContentBlockStart { .. } => "content_block_start",
+            ClaudeStreamEvent::ContentBlockDelta { .. } => "content_block_delta",
+            ClaudeStreamEvent::ContentBlockStop { .. } => "content_block_stop",
+            ClaudeStreamEvent::MessageDelta { .. } => "message_delta",
+            ClaudeStreamEvent::MessageStop => "message_stop",
+            ClaudeStreamEvent::Ping => "ping",
+            ClaudeStreamEvent::Error { .. } => "error",
+        }
+    }
 }
 
 impl ClaudeContent {
@@ -293,7 +516,9 @@ impl ClaudeContent {
                         ClaudeContentBlock::Text { text } => Some(text.clone()),
                         ClaudeContentBlock::Image { .. } => None,
                         ClaudeContentBlock::ToolUse { .. } => None,
-                        ClaudeContentBlock::ToolResult { content, .. } => Some(content.clone()),
+                        ClaudeContentBlock::ToolResult { content, .. } => Some(content.text()),
+                        ClaudeContentBlock::ServerToolUse { .. } => None,
+                        ClaudeContentBlock::WebSearchToolResult { .. } => None,
                         ClaudeContentBlock::Unknown => None,
                     })
                     .collect::<Vec<String>>()
@@ -357,10 +582,137 @@ impl Default for ClaudeRequest {
             metadata: None,
             tools: None,
             tool_choice: None,
+            n: None,
+            thinking: None,
+            betas: Vec::new(),
         }
     }
 }
 
+impl ClaudeRequest {
+    /// Start building a request
+    ///
+    /// ```
+    /// use aiapiproxy::claude::ClaudeRequest;
+    ///
+    /// let request = ClaudeRequest::builder()
+    ///     .model("claude-sonnet-4")
+    ///     .max_tokens(1024)
+    ///     .system("You are helpful.")
+    ///     .user("Hello!")
+    ///     .build();
+    /// assert_eq!(request.messages.len(), 1);
+    /// ```
+    pub fn builder() -> ClaudeRequestBuilder {
+        ClaudeRequestBuilder::default()
+    }
+}
+
+/// Builder for [`ClaudeRequest`]
+#[derive(Debug, Clone, Default)]
+pub struct ClaudeRequestBuilder {
+    request: ClaudeRequest,
+}
+
+impl ClaudeRequestBuilder {
+    /// Model name or provider/model path
+    pub fn model(mut self, model: impl Into<String>) -> Self {
+        self.request.model = model.into();
+        self
+    }
+    
+    /// Maximum tokens to generate
+    pub fn max_tokens(mut self, max_tokens: u32) -> Self {
+        self.request.max_tokens = max_tokens;
+        self
+    }
+    
+    /// System prompt
+    pub fn system(mut self, system: impl Into<String>) -> Self {
+        self.request.system = Some(SystemPrompt::String(system.into()));
+        self
+    }
+    
+    /// Append a message
+    pub fn message(mut self, role: impl Into<String>, content: ClaudeContent) -> Self {
+        self.request.messages.push(ClaudeMessage { role: role.into(), content });
+        self
+    }
+    
+    /// Append a user text message
+    pub fn user(self, text: impl Into<String>) -> Self {
+        self.message("user", ClaudeContent::Text(text.into()))
+    }
+    
+    /// Append an assistant text message
+    pub fn assistant(self, text: impl Into<String>) -> Self {
+        self.message("assistant", ClaudeContent::Text(text.into()))
+    }
+    
+    pub fn temperature(mut self, temperature: f32) -> Self {
+        self.request.temperature = Some(temperature);
+        self
+    }
+    
+    pub fn top_p(mut self, top_p: f32) -> Self {
+        self.request.top_p = Some(top_p);
+        self
+    }
+    
+    pub fn top_k(mut self, top_k: u32) -> Self {
+        self.request.top_k = Some(top_k);
+        self
+    }
+    
+    /// Append a stop sequence
+    pub fn stop_sequence(mut self, stop_sequence: impl Into<String>) -> Self {
+        self.request.stop_sequences.get_or_insert_with(Vec::new).push(stop_sequence.into());
+        self
+    }
+    
+    pub fn stream(mut self, stream: bool) -> Self {
+        self.request.stream = Some(stream);
+        self
+    }
+    
+    /// Set a metadata entry (e.g. `user_id`)
+    pub fn metadata(mut self, key: impl Into<String>, value: impl Into<serde_json::Value>) -> Self {
+        self.request.metadata.get_or_insert_with(HashMap::new).insert(key.into(), value.into());
+        self
+    }
+    
+    /// Append a tool definition
+    pub fn tool(mut self, tool: ClaudeTool) -> Self {
+        self.request.tools.get_or_insert_with(Vec::new).push(tool);
+        self
+    }
+    
+    /// Tool choice (e.g. `{"type": "auto"}`)
+    pub fn tool_choice(mut self, tool_choice: serde_json::Value) -> Self {
+        self.request.tool_choice = Some(tool_choice);
+        self
+    }
+    
+    /// Enable extended thinking with a token budget
+    pub fn thinking(mut self, budget_tokens: u32) -> Self {
+        self.request.thinking = Some(ClaudeThinking {
+            thinking_type: "enabled".to_string(),
+            budget_tokens: Some(budget_tokens),
+        });
+        self
+    }
+    
+    /// Enable an Anthropic beta feature, as the `anthropic-beta` header does
+    pub fn beta(mut self, beta: impl Into<String>) -> Self {
+        self.request.betas.push(beta.into());
+        self
+    }
+    
+    pub fn build(self) -> ClaudeRequest {
+        self.request
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -395,4 +747,53 @@ mod tests {
         ]);
         assert_eq!(blocks_content.extract_text(), "Hello world");
     }
+    
+    #[test]
+    fn test_tool_result_content() {
+        let block: ClaudeContentBlock = serde_json::from_value(serde_json::json!({
+            "type": "tool_result",
+            "tool_use_id": "toolu_1",
+            "content": [
+                {"type": "text", "text": "Screenshot taken"},
+                {"type": "image", "source": {"type": "base64", "media_type": "image/png", "data": "abc"}}
+            ]
+        })).unwrap();
+        let ClaudeContentBlock::ToolResult { content, .. } = block else {
+            panic!("expected tool_result block");
+        };
+        assert_eq!(content.text(), "Screenshot taken");
+        assert_eq!(content.images().len(), 1);
+        
+        let block: ClaudeContentBlock = serde_json::from_value(serde_json::json!({
+            "type": "tool_result",
+            "tool_use_id": "toolu_2",
+            "content": "ok"
+        })).unwrap();
+        let ClaudeContentBlock::ToolResult { content, .. } = block else {
+            panic!("expected tool_result block");
+        };
+        assert_eq!(content, ClaudeToolResultContent::Text("ok".to_string()));
+        assert!(content.images().is_empty());
+    }    
+    #[test]
+    fn test_request_builder() {
+        let request = ClaudeRequest::builder()
+            .model("claude-sonnet-4")
+            .max_tokens(2048)
+            .user("Hi")
+            .assistant("Hello!")
+            .stop_sequence("END")
+            .metadata("user_id", "user-1")
+            .thinking(4096)
+            .beta("computer-use-2025-01-24")
+            .build();
+        
+        assert_eq!(request.model, "claude-sonnet-4");
+        assert_eq!(request.max_tokens, 2048);
+        assert_eq!(request.messages[1].role, "assistant");
+        assert_eq!(request.stop_sequences, Some(vec!["END".to_string()]));
+        assert_eq!(request.metadata.unwrap()["user_id"], "user-1");
+        assert_eq!(request.thinking.unwrap().enabled_budget(), Some(4096));
+        assert_eq!(request.betas, vec!["computer-use-2025-01-24".to_string()]);
+    }
 }
\ No newline at end of file
@@ -58,6 +58,21 @@ pub struct OpenAIRequest {
     /// Used by ModelHub for server-side caching
     #[serde(skip)]
     pub session_id: Option<String>,
+    /// ID of the proxy request (internal use, not sent in the body)
+    /// Forwarded upstream as `X-Request-Id`
+    #[serde(skip)]
+    pub request_id: Option<String>,
+    /// Inbound headers listed in `forwardHeaders` (internal use, not sent in the body)
+    #[serde(skip)]
+    pub forwarded_headers: Vec<(String, String)>,
+    /// Anthropic beta features enabled for the request (internal use, not sent to API)
+    #[serde(skip)]
+    pub betas: Vec<String>,
+    /// Extension parameters (internal use, not sent as-is)
+    /// Provider-specific sampling parameters such as `top_k`; providers that
+    /// accept them merge them into the request body, others omit them
+    #[serde(skip)]
+    pub extensions: HashMap<String, serde_json::Value>,
 }
 
 /// OpenAI message structure
@@ -94,7 +109,12 @@ pub enum OpenAIContent {
 pub enum OpenAIContentPart {
     /// Text part
     #[serde(rename = "text")]
-    Text { text: String },
+    Text {
+        text: String,
+        /// Prompt caching marker (passed through from Claude system blocks)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cache_control: Option<serde_json::Value>,
+    },
     /// Image URL part
     #[serde(rename = "image_url")]
     ImageUrl { image_url: OpenAIImageUrl },
@@ -113,9 +133,27 @@ pub struct OpenAIImageUrl {
 /// OpenAI response format
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OpenAIResponseFormat {
-    /// Format type
+    /// Format type ("text", "json_object" or "json_schema")
     #[serde(rename = "type")]
     pub format_type: String,
+    /// JSON schema definition (only for "json_schema" type)
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub json_schema: Option<OpenAIJsonSchema>,
+}
+
+/// OpenAI JSON schema response format definition
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OpenAIJsonSchema {
+    /// Schema name
+    pub name: String,
+    /// Schema description (optional)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// JSON schema
+    pub schema: serde_json::Value,
+    /// Whether to enforce strict schema adherence (optional)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strict: Option<bool>,
 }
 
 /// OpenAI tool
@@ -144,6 +182,9 @@ pub struct OpenAIFunction {
 /// OpenAI tool call
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OpenAIToolCall {
+    /// Position of the tool call within the message (streaming only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<u32>,
     /// Call ID (optional for streaming)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub id: Option<String>,
@@ -193,6 +234,25 @@ pub struct OpenAIResponse {
     /// System fingerprint (optional)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub system_fingerprint: Option<String>,
+    /// Web search results (Perplexity format; also filled from Responses API citations)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub search_results: Option<Vec<OpenAISearchResult>>,
+    /// Web search queries issued by the upstream model
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub search_queries: Option<Vec<String>>,
+}
+
+/// Web search result returned by search-enabled models
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct OpenAISearchResult {
+    /// Result URL
+    pub url: String,
+    /// Page title (optional)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// Publication date (optional)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub date: Option<String>,
 }
 
 /// OpenAI choice
@@ -211,17 +271,52 @@ pub struct OpenAIChoice {
 }
 
 /// OpenAI usage statistics
+///
+/// Missing or `null` counts (sent by some lenient upstreams) deserialize as 0.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OpenAIUsage {
     /// Prompt token count
-    #[serde(default)]
+    #[serde(default, deserialize_with = "null_as_default")]
     pub prompt_tokens: u32,
     /// Completion token count (optional for Gemini compatibility)
-    #[serde(default)]
+    #[serde(default, deserialize_with = "null_as_default")]
     pub completion_tokens: u32,
     /// Total token count
-    #[serde(default)]
+    #[serde(default, deserialize_with = "null_as_default")]
     pub total_tokens: u32,
+    /// Breakdown of the prompt tokens
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_tokens_details: Option<PromptTokensDetails>,
+}
+
+/// Breakdown of OpenAI prompt tokens
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PromptTokensDetails {
+    /// Prompt tokens read from the prompt cache
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub cached_tokens: u32,
+}
+
+impl OpenAIUsage {
+    /// Prompt tokens read from the prompt cache
+    pub fn cached_tokens(&self) -> u32 {
+        self.prompt_tokens_details.as_ref().map_or(0, |details| details.cached_tokens)
+    }
+    
+    
+    /// Whether the upstream reported no token counts at all
+    pub fn is_empty(&self) -> bool {
+        self.prompt_tokens == 0 && self.completion_tokens == 0 && self.total_tokens == 0
+    }
+}
+
+/// Deserialize `null` as the type's default value
+pub(crate) fn null_as_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Default + Deserialize<'de>,
+{
+    Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
 }
 
 /// OpenAI streaming response
@@ -243,6 +338,12 @@ pub struct OpenAIStreamResponse {
     pub system_fingerprint: Option<String>,
     /// Choice list
     pub choices: Vec<OpenAIStreamChoice>,
+    /// Web search results (Perplexity repeats them on every chunk)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub search_results: Option<Vec<OpenAISearchResult>>,
+    /// Web search queries issued by the upstream model
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub search_queries: Option<Vec<String>>,
 }
 
 /// OpenAI streaming choice
@@ -306,7 +407,7 @@ impl OpenAIContent {
                 parts
                     .iter()
                     .filter_map(|part| match part {
-                        OpenAIContentPart::Text { text } => Some(text.clone()),
+                        OpenAIContentPart::Text { text, .. } => Some(text.clone()),
                         _ => None,
                     })
                     .collect::<Vec<_>>()
@@ -346,14 +447,174 @@ impl Default for OpenAIRequest {
             tools: None,
             tool_choice: None,
             session_id: None,
+            request_id: None,
+            forwarded_headers: Vec::new(),
+            betas: Vec::new(),
+            extensions: HashMap::new(),
         }
     }
 }
 
+impl OpenAIRequest {
+    /// Serialize the request, merging selected extension parameters into the body
+    ///
+    /// `fields` maps extension keys to the upstream field names (e.g. `("top_k", "topK")`).
+    /// Extensions not listed are omitted.
+    pub fn to_json_with_extensions(&self, fields: &[(&str, &str)]) -> serde_json::Result<serde_json::Value> {
+        let mut body = serde_json::to_value(self)?;
+        if let serde_json::Value::Object(map) = &mut body {
+            for (key, field) in fields {
+                if let Some(value) = self.extensions.get(*key) {
+                    map.insert(field.to_string(), value.clone());
+                }
+            }
+        }
+        Ok(body)
+    }
+}
+
+impl OpenAIRequest {
+    /// Start building a request
+    ///
+    /// ```
+    /// use aiapiproxy::openai::OpenAIRequest;
+    ///
+    /// let request = OpenAIRequest::builder()
+    ///     .model("openai/gpt-4o")
+    ///     .system("You are helpful.")
+    ///     .user("Hello!")
+    ///     .max_tokens(1024)
+    ///     .build();
+    /// assert_eq!(request.messages.len(), 2);
+    /// ```
+    pub fn builder() -> OpenAIRequestBuilder {
+        OpenAIRequestBuilder::default()
+    }
+}
+
+/// Builder for [`OpenAIRequest`]
+#[derive(Debug, Clone, Default)]
+pub struct OpenAIRequestBuilder {
+    request: OpenAIRequest,
+}
+
+impl OpenAIRequestBuilder {
+    /// Model name or provider/model path
+    pub fn model(mut self, model: impl Into<String>) -> Self {
+        self.request.model = model.into();
+        self
+    }
+    
+    /// Append a message
+    pub fn message(mut self, message: OpenAIMessage) -> Self {
+        self.request.messages.push(message);
+        self
+    }
+    
+    /// Append a system text message
+    pub fn system(self, text: impl Into<String>) -> Self {
+        self.text_message("system", text.into())
+    }
+    
+    /// Append a user text message
+    pub fn user(self, text: impl Into<String>) -> Self {
+        self.text_message("user", text.into())
+    }
+    
+    /// Append an assistant text message
+    pub fn assistant(self, text: impl Into<String>) -> Self {
+        self.text_message("assistant", text.into())
+    }
+    
+    fn text_message(self, role: &str, text: String) -> Self {
+        self.message(OpenAIMessage {
+            role: role.to_string(),
+            content: Some(OpenAIContent::Text(text)),
+            name: None,
+            tool_calls: None,
+            tool_call_id: None,
+        })
+    }
+    
+    pub fn max_tokens(mut self, max_tokens: u32) -> Self {
+        self.request.max_tokens = Some(max_tokens);
+        self
+    }
+    
+    pub fn temperature(mut self, temperature: f32) -> Self {
+        self.request.temperature = Some(temperature);
+        self
+    }
+    
+    pub fn top_p(mut self, top_p: f32) -> Self {
+        self.request.top_p = Some(top_p);
+        self
+    }
+    
+    /// Append a stop sequence
+    pub fn stop(mut self, stop: impl Into<String>) -> Self {
+        self.request.stop.get_or_insert_with(Vec::new).push(stop.into());
+        self
+    }
+    
+    pub fn stream(mut self, stream: bool) -> Self {
+        self.request.stream = Some(stream);
+        self
+    }
+    
+    /// Append a function tool
+    pub fn tool(mut self, function: OpenAIFunction) -> Self {
+        self.request.tools.get_or_insert_with(Vec::new).push(OpenAITool {
+            tool_type: "function".to_string(),
+            function,
+        });
+        self
+    }
+    
+    /// Tool choice (e.g. `"auto"`)
+    pub fn tool_choice(mut self, tool_choice: serde_json::Value) -> Self {
+        self.request.tool_choice = Some(tool_choice);
+        self
+    }
+    
+    /// Session ID used by providers with server-side caching
+    pub fn session_id(mut self, session_id: impl Into<String>) -> Self {
+        self.request.session_id = Some(session_id.into());
+        self
+    }
+    
+    /// Set an extension parameter (e.g. `reasoning_effort`)
+    pub fn extension(mut self, key: impl Into<String>, value: impl Into<serde_json::Value>) -> Self {
+        self.request.extensions.insert(key.into(), value.into());
+        self
+    }
+    
+    pub fn build(self) -> OpenAIRequest {
+        self.request
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     
+    #[test]
+    fn test_to_json_with_extensions() {
+        let mut request = OpenAIRequest::default();
+        request.extensions.insert("top_k".to_string(), serde_json::json!(40));
+        
+        // Extensions are never serialized directly
+        let plain = serde_json::to_value(&request).unwrap();
+        assert!(plain.get("top_k").is_none());
+        
+        let body = request.to_json_with_extensions(&[("top_k", "topK")]).unwrap();
+        assert_eq!(body["topK"], 40);
+        assert!(body.get("top_k").is_none());
+        
+        let body = request.to_json_with_extensions(&[]).unwrap();
+        assert!(body.get("topK").is_none());
+    }
+    
     #[test]
     fn test_openai_request_serialization() {
         let request = OpenAIRequest {
@@ -382,8 +643,8 @@ mod tests {
         assert_eq!(text_content.extract_text(), "Hello world");
         
         let array_content = OpenAIContent::Array(vec![
-            OpenAIContentPart::Text { text: "Hello ".to_string() },
-            OpenAIContentPart::Text { text: "world".to_string() },
+            OpenAIContentPart::Text { text: "Hello ".to_string(), cache_control: None },
+            OpenAIContentPart::Text { text: "world".to_string(), cache_control: None },
         ]);
         assert_eq!(array_content.extract_text(), "Hello world");
     }
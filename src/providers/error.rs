@@ -0,0 +1,344 @@
+//! Provider error types
+//!
+//! Providers fail with a [`ProviderError`], classified once at the provider
+//! boundary: timeouts, rate limits with the delay the upstream asked for,
+//! other non-success HTTP responses ([`UpstreamError`]), unparsable responses
+//! and network failures. Retries, adaptive routing and the handlers decide on
+//! the variant, and the handlers map it to the matching Claude error.
+
+use crate::services::retry::{parse_rate_limit_reset, parse_retry_after};
+use http::StatusCode;
+use std::time::Duration;
+use thiserror::Error;
+
+/// Failure of a provider call
+#[derive(Debug, Error)]
+pub enum ProviderError {
+    /// No response within the timeout
+    #[error("Upstream request timed out: {0}")]
+    Timeout(String),
+    
+    /// 429 response, with the delay requested by `Retry-After` or a rate limit reset header
+    #[error("{upstream}")]
+    RateLimited {
+        retry_after: Option<Duration>,
+        upstream: UpstreamError,
+    },
+    
+    /// Other non-success HTTP response
+    #[error(transparent)]
+    Upstream(UpstreamError),
+    
+    /// Response that couldn't be parsed
+    #[error("Failed to parse upstream response: {0}")]
+    Parse(String),
+    
+    /// Connection failure, or the connection dropped before the response was read
+    #[error("Upstream connection failed: {0}")]
+    Network(String),
+    
+    /// Request the provider refuses to send, such as one with a tool call
+    /// left without a result
+    #[error("{0}")]
+    InvalidRequest(String),
+    
+    /// Failure before the request was sent, such as an unsupported mode
+    #[error(transparent)]
+    Other(anyhow::Error),
+}
+
+impl ProviderError {
+    /// Whether the failure is transient (timeout, rate limit, server error,
+    /// connection failure), so a retry or another upstream may succeed
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            ProviderError::Timeout(_) | ProviderError::RateLimited { .. } | ProviderError::Network(_) => true,
+            ProviderError::Upstream(upstream) => upstream.is_retryable(),
+            ProviderError::Parse(_) | ProviderError::InvalidRequest(_) | ProviderError::Other(_) => false,
+        }
+    }
+    
+    /// Upstream HTTP status, for errors from an upstream response
+    pub fn status(&self) -> Option<u16> {
+        self.upstream().map(|upstream| upstream.status)
+    }
+    
+    /// The upstream response of HTTP errors
+    pub fn upstream(&self) -> Option<&UpstreamError> {
+        match self {
+            ProviderError::RateLimited { upstream, .. } | ProviderError::Upstream(upstream) => Some(upstream),
+            _ => None,
+        }
+    }
+    
+    /// Delay the upstream asked for before the next request
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            ProviderError::RateLimited { retry_after, .. } => *retry_after,
+            ProviderError::Upstream(upstream) => upstream.requested_delay(),
+            _ => None,
+        }
+    }
+    
+    /// Claude error type and HTTP status the failure is reported to clients with
+    pub fn claude_error(&self) -> (&'static str, StatusCode) {
+        let overloaded = StatusCode::from_u16(529).unwrap_or(StatusCode::SERVICE_UNAVAILABLE);
+        let upstream = match self {
+            ProviderError::Timeout(_) => return ("timeout_error", StatusCode::GATEWAY_TIMEOUT),
+            ProviderError::InvalidRequest(_) => return ("invalid_request_error", StatusCode::BAD_REQUEST),
+            ProviderError::Parse(_) | ProviderError::Network(_) | ProviderError::Other(_) => return ("api_error", StatusCode::BAD_GATEWAY),
+            ProviderError::RateLimited { upstream, .. } | ProviderError::Upstream(upstream) => upstream,
+        };
+        if upstream.code().is_some_and(|c| c.contains("quota")) {
+            return ("billing_error", StatusCode::PAYMENT_REQUIRED);
+        }
+        match upstream.status {
+            400 | 422 => ("invalid_request_error", StatusCode::BAD_REQUEST),
+            401 => ("authentication_error", StatusCode::UNAUTHORIZED),
+            402 => ("billing_error", StatusCode::PAYMENT_REQUIRED),
+            403 => ("permission_error", StatusCode::FORBIDDEN),
+            404 => ("not_found_error", StatusCode::NOT_FOUND),
+            413 => ("request_too_large", StatusCode::PAYLOAD_TOO_LARGE),
+            429 => ("rate_limit_error", StatusCode::TOO_MANY_REQUESTS),
+            503 | 529 => ("overloaded_error", overloaded),
+            _ => ("api_error", StatusCode::BAD_GATEWAY),
+        }
+    }
+    
+    /// Message shown to clients: the upstream's own message for rejected
+    /// requests, a generic one otherwise
+    pub fn client_message(&self) -> String {
+        let message = match self.claude_error().0 {
+            "invalid_request_error" => return self.upstream().map(UpstreamError::message).unwrap_or_else(|| self.to_string()),
+            "timeout_error" => "Upstream API request timed out.",
+            "rate_limit_error" => "Rate limit exceeded. Please try again later.",
+            "billing_error" => "Insufficient quota or billing issue.",
+            "authentication_error" => "Invalid API key provided.",
+            "permission_error" => "Permission denied by upstream API.",
+            "not_found_error" => "The requested model was not found.",
+            "request_too_large" => "Request exceeds the maximum allowed size.",
+            "overloaded_error" => "Upstream API is overloaded. Please try again later.",
+            _ => "External API request failed.",
+        };
+        message.to_string()
+    }
+}
+
+/// Requests coalesced onto one upstream call share its failure; other
+/// errors are cloned as their message
+impl Clone for ProviderError {
+    fn clone(&self) -> Self {
+        match self {
+            ProviderError::Timeout(message) => ProviderError::Timeout(message.clone()),
+            ProviderError::RateLimited { retry_after, upstream } => ProviderError::RateLimited { retry_after: *retry_after, upstream: upstream.clone() },
+            ProviderError::Upstream(upstream) => ProviderError::Upstream(upstream.clone()),
+            ProviderError::Parse(message) => ProviderError::Parse(message.clone()),
+            ProviderError::Network(message) => ProviderError::Network(message.clone()),
+            ProviderError::InvalidRequest(message) => ProviderError::InvalidRequest(message.clone()),
+            ProviderError::Other(error) => ProviderError::Other(anyhow::anyhow!("{:#}", error)),
+        }
+    }
+}
+
+impl From<UpstreamError> for ProviderError {
+    fn from(upstream: UpstreamError) -> Self {
+        match upstream.status {
+            429 => ProviderError::RateLimited { retry_after: upstream.requested_delay(), upstream },
+            _ => ProviderError::Upstream(upstream),
+        }
+    }
+}
+
+/// Classify a provider's internal error by its causes
+impl From<anyhow::Error> for ProviderError {
+    fn from(error: anyhow::Error) -> Self {
+        let error = match error.downcast::<ProviderError>() {
+            Ok(provider_error) => return provider_error,
+            Err(error) => error,
+        };
+        let error = match error.downcast::<UpstreamError>() {
+            Ok(upstream) => return upstream.into(),
+            Err(error) => error,
+        };
+        let message = format!("{:#}", error);
+        for cause in error.chain() {
+            if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+                if e.is_timeout() {
+                    return ProviderError::Timeout(message);
+                }
+                if e.is_decode() {
+                    return ProviderError::Parse(message);
+                }
+                if e.is_connect() || e.is_request() || e.is_body() {
+                    return ProviderError::Network(message);
+                }
+            }
+            if cause.is::<serde_json::Error>() {
+                return ProviderError::Parse(message);
+            }
+        }
+        ProviderError::Other(error)
+    }
+}
+
+/// Non-success HTTP response from an upstream provider
+#[derive(Debug, Clone, Error)]
+#[error("{provider} API request failed: {status} - {body}")]
+pub struct UpstreamError {
+    /// Provider label used in logs (e.g. "OpenAI", "ModelHub")
+    pub provider: String,
+    /// Upstream HTTP status code
+    pub status: u16,
+    /// Raw response body
+    pub body: String,
+    /// `retry-after` header value, if any
+    pub retry_after: Option<String>,
+    /// `x-ratelimit-reset` header value (or its `-requests`/`-tokens` variant), if any
+    pub rate_limit_reset: Option<String>,
+}
+
+/// Rate limit reset headers, in order of preference
+const RATE_LIMIT_RESET_HEADERS: [&str; 3] = ["x-ratelimit-reset", "x-ratelimit-reset-requests", "x-ratelimit-reset-tokens"];
+
+impl UpstreamError {
+    /// Build an error from a failed upstream response, consuming its body
+    pub async fn from_response(provider: &str, response: reqwest::Response) -> Self {
+        let status = response.status().as_u16();
+        let retry_after = response
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_string());
+        let rate_limit_reset = RATE_LIMIT_RESET_HEADERS.iter()
+            .find_map(|name| response.headers().get(*name))
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_string());
+        let body = response.text().await.unwrap_or_default();
+        
+        Self {
+            provider: provider.to_string(),
+            status,
+            body,
+            retry_after,
+            rate_limit_reset,
+        }
+    }
+    
+    /// Upstream error message
+    ///
+    /// Extracted from `{"error": {"message": ...}}`, `{"error": "..."}` or
+    /// `{"message": ...}` bodies; falls back to the raw body.
+    pub fn message(&self) -> String {
+        let parsed: Option<serde_json::Value> = serde_json::from_str(&self.body).ok();
+        parsed
+            .as_ref()
+            .and_then(|v| {
+                v.get("error")
+                    .and_then(|e| e.get("message").or(Some(e)))
+                    .or_else(|| v.get("message"))
+            })
+            .and_then(|m| m.as_str())
+            .map(|m| m.to_string())
+            .unwrap_or_else(|| self.body.trim().to_string())
+    }
+    
+    /// Whether the failure is transient (timeout, rate limit, server error),
+    /// so another upstream may serve the request
+    pub fn is_retryable(&self) -> bool {
+        matches!(self.status, 408 | 429 | 500..=599)
+    }
+    
+    /// Delay requested by the `Retry-After` or rate limit reset header
+    pub fn requested_delay(&self) -> Option<Duration> {
+        self.retry_after.as_deref()
+            .and_then(parse_retry_after)
+            .or_else(|| self.rate_limit_reset.as_deref().and_then(parse_rate_limit_reset))
+    }
+    
+    /// Upstream error code or type (e.g. "insufficient_quota"), if any
+    pub fn code(&self) -> Option<String> {
+        let parsed: serde_json::Value = serde_json::from_str(&self.body).ok()?;
+        let error = parsed.get("error")?;
+        error
+            .get("code")
+            .and_then(|c| c.as_str())
+            .or_else(|| error.get("type").and_then(|t| t.as_str()))
+            .map(|c| c.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn upstream_error(status: u16, body: &str) -> UpstreamError {
+        UpstreamError {
+            provider: "OpenAI".to_string(),
+            status,
+            body: body.to_string(),
+            retry_after: None,
+            rate_limit_reset: None,
+        }
+    }
+    
+    #[test]
+    fn test_message() {
+        let error = upstream_error(400, r#"{"error": {"message": "Invalid schema for function 'get_weather'", "type": "invalid_request_error"}}"#);
+        assert_eq!(error.message(), "Invalid schema for function 'get_weather'");
+        assert_eq!(error.code().as_deref(), Some("invalid_request_error"));
+        
+        let error = upstream_error(400, r#"{"error": "bad request"}"#);
+        assert_eq!(error.message(), "bad request");
+        
+        let error = upstream_error(502, "Bad Gateway\n");
+        assert_eq!(error.message(), "Bad Gateway");
+        assert!(error.code().is_none());
+    }
+    
+    #[test]
+    fn test_is_retryable() {
+        assert!(upstream_error(429, "").is_retryable());
+        assert!(upstream_error(503, "").is_retryable());
+        assert!(upstream_error(529, "").is_retryable());
+        assert!(!upstream_error(400, "").is_retryable());
+        assert!(!upstream_error(401, "").is_retryable());
+    }
+    
+    #[test]
+    fn test_provider_error() {
+        let rate_limited = ProviderError::from(UpstreamError { retry_after: Some("7".to_string()), ..upstream_error(429, "slow down") });
+        assert!(matches!(rate_limited, ProviderError::RateLimited { retry_after: Some(delay), .. } if delay == Duration::from_secs(7)));
+        assert!(rate_limited.is_retryable());
+        assert_eq!(rate_limited.claude_error(), ("rate_limit_error", StatusCode::TOO_MANY_REQUESTS));
+        
+        let rejected = ProviderError::from(upstream_error(400, r#"{"error": {"message": "Invalid tool schema"}}"#));
+        assert!(!rejected.is_retryable());
+        assert_eq!(rejected.status(), Some(400));
+        assert_eq!(rejected.claude_error(), ("invalid_request_error", StatusCode::BAD_REQUEST));
+        assert_eq!(rejected.client_message(), "Invalid tool schema");
+        
+        let quota = ProviderError::from(upstream_error(429, r#"{"error": {"message": "Quota exceeded", "code": "insufficient_quota"}}"#));
+        assert_eq!(quota.claude_error(), ("billing_error", StatusCode::PAYMENT_REQUIRED));
+    }
+    
+    #[test]
+    fn test_classify() {
+        let upstream: anyhow::Error = upstream_error(503, "").into();
+        assert!(matches!(ProviderError::from(upstream.context("ModelHub request failed")), ProviderError::Upstream(_)));
+        
+        let parse = serde_json::from_str::<serde_json::Value>("{").unwrap_err();
+        let parse = ProviderError::from(anyhow::Error::new(parse).context("Failed to parse OpenAI response"));
+        assert!(matches!(&parse, ProviderError::Parse(message) if message.starts_with("Failed to parse OpenAI response: ")));
+        assert!(!parse.is_retryable());
+        
+        let other = ProviderError::from(anyhow::anyhow!("Unsupported Ark mode: chat"));
+        assert!(matches!(other, ProviderError::Other(_)));
+        assert_eq!(other.to_string(), "Unsupported Ark mode: chat");
+    }
+    
+    #[test]
+    fn test_display() {
+        let error = upstream_error(429, "slow down");
+        assert_eq!(error.to_string(), "OpenAI API request failed: 429 - slow down");
+    }
+}
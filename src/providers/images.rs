@@ -0,0 +1,128 @@
+//! Image preparation for Responses API providers
+//!
+//! Responses API upstreams (Ark, ModelHub responses mode) reject oversized or
+//! unsupported inline images with an opaque 400. Base64 data URLs are checked
+//! against the supported MIME types and the model's `maxImageBytes` before
+//! they are sent. With the `image-resize` feature, oversized images are
+//! downscaled and re-encoded as JPEG instead of being dropped.
+
+use std::borrow::Cow;
+
+/// Image types accepted by OpenAI-style vision inputs
+const SUPPORTED_MEDIA_TYPES: [&str; 4] = ["image/png", "image/jpeg", "image/gif", "image/webp"];
+
+/// Check an image URL against the model's limits
+///
+/// Returns the URL to send (a new data URL if the image was downscaled), or
+/// the reason the image can't be sent. Remote URLs are passed through.
+pub(crate) fn prepare_image_url(url: &str, max_bytes: Option<usize>) -> Result<Cow<'_, str>, String> {
+    let Some((media_type, data)) = url.strip_prefix("data:").and_then(|rest| rest.split_once(";base64,")) else {
+        return Ok(Cow::Borrowed(url));
+    };
+    
+    if !SUPPORTED_MEDIA_TYPES.contains(&media_type) {
+        return Err(format!("unsupported image type {}", media_type));
+    }
+    
+    let size = decoded_len(data);
+    match max_bytes {
+        Some(max_bytes) if size > max_bytes => shrink(data, size, max_bytes).map(Cow::Owned),
+        _ => Ok(Cow::Borrowed(url)),
+    }
+}
+
+/// Decoded size of base64 data (padded or not)
+fn decoded_len(data: &str) -> usize {
+    data.trim_end_matches('=').len() * 3 / 4
+}
+
+/// Downscale an oversized image until its JPEG encoding fits `max_bytes`
+#[cfg(feature = "image-resize")]
+fn shrink(data: &str, size: usize, max_bytes: usize) -> Result<String, String> {
+    use base64::prelude::*;
+    use image::{imageops::FilterType, DynamicImage, ImageOutputFormat};
+    use std::io::Cursor;
+    
+    const MAX_ATTEMPTS: usize = 5;
+    
+    let bytes = BASE64_STANDARD.decode(data).map_err(|e| format!("invalid base64 image data: {}", e))?;
+    let image = image::load_from_memory(&bytes).map_err(|e| format!("unreadable image: {}", e))?;
+    
+    // Encoded size shrinks roughly with the pixel count
+    let mut scale = (max_bytes as f64 / size as f64).sqrt();
+    for _ in 0..MAX_ATTEMPTS {
+        let width = ((image.width() as f64 * scale) as u32).max(1);
+        let height = ((image.height() as f64 * scale) as u32).max(1);
+        let resized = DynamicImage::ImageRgb8(image.resize(width, height, FilterType::Triangle).to_rgb8());
+        
+        let mut encoded = Vec::new();
+        resized.write_to(&mut Cursor::new(&mut encoded), ImageOutputFormat::Jpeg(85))
+            .map_err(|e| format!("failed to re-encode image: {}", e))?;
+        if encoded.len() <= max_bytes {
+            tracing::debug!("🖼️ Downscaled image from {} to {} bytes ({}x{})", size, encoded.len(), width, height);
+            return Ok(format!("data:image/jpeg;base64,{}", BASE64_STANDARD.encode(&encoded)));
+        }
+        scale *= 0.75;
+    }
+    
+    Err(format!("image of {} bytes could not be downscaled below the {} byte limit", size, max_bytes))
+}
+
+#[cfg(not(feature = "image-resize"))]
+fn shrink(_data: &str, size: usize, max_bytes: usize) -> Result<String, String> {
+    Err(format!("image of {} bytes exceeds the {} byte limit", size, max_bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    /// 1x1 PNG
+    const PNG_URL: &str = "data:image/png;base64,iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAYAAAAfFcSJAAAADUlEQVR42mNk+M9QDwADhgGAWjR9awAAAABJRU5ErkJggg==";
+    
+    #[test]
+    fn test_prepare_image_url() {
+        assert_eq!(prepare_image_url("https://example.com/cat.png", Some(1)).unwrap(), "https://example.com/cat.png");
+        assert_eq!(prepare_image_url(PNG_URL, None).unwrap(), PNG_URL);
+        assert_eq!(prepare_image_url(PNG_URL, Some(1024)).unwrap(), PNG_URL);
+        
+        let error = prepare_image_url("data:image/tiff;base64,AAAA", None).unwrap_err();
+        assert_eq!(error, "unsupported image type image/tiff");
+    }
+    
+    #[test]
+    fn test_decoded_len() {
+        assert_eq!(decoded_len("QQ=="), 1);
+        assert_eq!(decoded_len("QUI="), 2);
+        assert_eq!(decoded_len("QUJD"), 3);
+        assert_eq!(decoded_len("QUJDRA"), 4);
+    }
+    
+    #[cfg(not(feature = "image-resize"))]
+    #[test]
+    fn test_oversized_image_rejected() {
+        let error = prepare_image_url(PNG_URL, Some(10)).unwrap_err();
+        assert!(error.ends_with("exceeds the 10 byte limit"));
+    }
+    
+    #[cfg(feature = "image-resize")]
+    #[test]
+    fn test_oversized_image_downscaled() {
+        use base64::prelude::*;
+        
+        // Noise doesn't compress, so the PNG is far above the limit
+        let mut seed = 1u32;
+        let image = image::DynamicImage::ImageRgb8(image::ImageBuffer::from_fn(256, 256, |_, _| {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            image::Rgb([(seed >> 16) as u8, (seed >> 8) as u8, seed as u8])
+        }));
+        let mut png = Vec::new();
+        image.write_to(&mut std::io::Cursor::new(&mut png), image::ImageOutputFormat::Png).unwrap();
+        let url = format!("data:image/png;base64,{}", BASE64_STANDARD.encode(&png));
+        
+        let limit = 4096;
+        let resized = prepare_image_url(&url, Some(limit)).unwrap();
+        let data = resized.strip_prefix("data:image/jpeg;base64,").unwrap();
+        assert!(decoded_len(data) <= limit);
+    }
+}
@@ -0,0 +1,250 @@
+//! Legacy OpenAI function calling
+//!
+//! Some OpenAI-compatible servers predate `tools` and only understand the
+//! deprecated `functions` / `function_call` fields. For models with
+//! `"toolsFormat": "legacy"` the serialized request is rewritten to that
+//! format, and `function_call` replies are turned back into tool calls before
+//! they reach the converter.
+
+use crate::models::openai::OpenAIStreamResponse;
+use crate::utils::sse;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+/// `toolsFormat` value selecting the deprecated `functions` / `function_call` format
+pub const FORMAT_LEGACY: &str = "legacy";
+
+/// Rewrite a serialized chat completion request to the legacy format
+pub(crate) fn convert_request(body: &mut Value) {
+    let Some(object) = body.as_object_mut() else {
+        return;
+    };
+    
+    if let Some(tools) = object.remove("tools") {
+        let functions: Vec<Value> = tools.as_array().into_iter()
+            .flatten()
+            .filter_map(|tool| tool.get("function").cloned())
+            .collect();
+        debug!("Converted {} tools to legacy functions", functions.len());
+        object.insert("functions".to_string(), Value::Array(functions));
+    }
+    
+    if let Some(tool_choice) = object.remove("tool_choice") {
+        object.insert("function_call".to_string(), function_call_choice(&tool_choice));
+    }
+    
+    if let Some(messages) = object.get_mut("messages").and_then(Value::as_array_mut) {
+        convert_messages(messages);
+    }
+}
+
+/// Map `tool_choice` (OpenAI or Claude form) to `function_call`
+fn function_call_choice(tool_choice: &Value) -> Value {
+    if let Some(choice) = tool_choice.as_str() {
+        // "required" has no legacy equivalent
+        return json!(if choice == "none" { "none" } else { "auto" });
+    }
+    
+    let name = match tool_choice.get("type").and_then(Value::as_str) {
+        Some("function") => tool_choice.get("function").and_then(|f| f.get("name")),
+        Some("tool") => tool_choice.get("name"),
+        Some("none") => return json!("none"),
+        _ => None,
+    };
+    match name {
+        Some(name) => json!({ "name": name }),
+        None => json!("auto"),
+    }
+}
+
+/// Move tool calls to `function_call` and tool results to `function` messages
+fn convert_messages(messages: &mut [Value]) {
+    let mut call_names: HashMap<String, Value> = HashMap::new();
+    
+    for message in messages.iter_mut() {
+        let Some(message) = message.as_object_mut() else {
+            continue;
+        };
+        
+        match message.get("role").and_then(Value::as_str) {
+            Some("assistant") => {
+                let Some(Value::Array(tool_calls)) = message.remove("tool_calls") else {
+                    continue;
+                };
+                if tool_calls.len() > 1 {
+                    warn!("Legacy function calling supports one call per message, sending the first of {}", tool_calls.len());
+                }
+                for tool_call in &tool_calls {
+                    if let (Some(id), Some(name)) = (tool_call.get("id").and_then(Value::as_str), tool_call.pointer("/function/name")) {
+                        call_names.insert(id.to_string(), name.clone());
+                    }
+                }
+                if let Some(function) = tool_calls.first().and_then(|tc| tc.get("function")) {
+                    message.insert("function_call".to_string(), function.clone());
+                }
+            }
+            Some("tool") => {
+                let name = message.remove("tool_call_id")
+                    .and_then(|id| id.as_str().and_then(|id| call_names.get(id)).cloned())
+                    .unwrap_or_else(|| json!("function"));
+                message.insert("role".to_string(), json!("function"));
+                message.insert("name".to_string(), name);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Turn `function_call` replies of a chat completion response into tool calls
+pub(crate) fn convert_response(body: &mut Value) {
+    for choice in choices_mut(body) {
+        if let Some(message) = choice.get_mut("message").and_then(Value::as_object_mut) {
+            if let Some(function_call) = message.remove("function_call") {
+                let id = format!("call_{}", Uuid::new_v4().simple());
+                message.insert("tool_calls".to_string(), json!([{
+                    "id": id,
+                    "type": "function",
+                    "function": function_call,
+                }]));
+            }
+        }
+        map_finish_reason(choice);
+    }
+}
+
+/// Turn `function_call` deltas of a streaming chunk into tool call deltas
+fn convert_chunk(body: &mut Value) {
+    for choice in choices_mut(body) {
+        if let Some(delta) = choice.get_mut("delta").and_then(Value::as_object_mut) {
+            if let Some(function_call) = delta.remove("function_call") {
+                let mut tool_call = json!({
+                    "index": 0,
+                    "type": "function",
+                    "function": function_call,
+                });
+                // The name only comes with the first delta of the call
+                if tool_call.pointer("/function/name").is_some_and(|name| !name.is_null()) {
+                    tool_call["id"] = json!(format!("call_{}", Uuid::new_v4().simple()));
+                }
+                delta.insert("tool_calls".to_string(), json!([tool_call]));
+            }
+        }
+        map_finish_reason(choice);
+    }
+}
+
+/// Parse a legacy streaming chunk payload
+pub(crate) fn parse_chunk(data: &str) -> Option<OpenAIStreamResponse> {
+    match serde_json::from_str::<Value>(data.trim()) {
+        Ok(mut value) => {
+            convert_chunk(&mut value);
+            sse::parse_openai_chunk(&value.to_string())
+        }
+        // [DONE] and malformed payloads
+        Err(_) => sse::parse_openai_chunk(data),
+    }
+}
+
+fn choices_mut(body: &mut Value) -> impl Iterator<Item = &mut Map<String, Value>> {
+    body.get_mut("choices")
+        .and_then(Value::as_array_mut)
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_object_mut)
+}
+
+fn map_finish_reason(choice: &mut Map<String, Value>) {
+    if choice.get("finish_reason").and_then(Value::as_str) == Some("function_call") {
+        choice.insert("finish_reason".to_string(), json!("tool_calls"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::openai::OpenAIResponse;
+    
+    #[test]
+    fn test_convert_request() {
+        let mut body = json!({
+            "model": "gpt-3.5-turbo-0613",
+            "messages": [
+                {"role": "user", "content": "Weather?"},
+                {"role": "assistant", "content": null, "tool_calls": [
+                    {"id": "call_1", "type": "function", "function": {"name": "get_weather", "arguments": "{}"}}
+                ]},
+                {"role": "tool", "tool_call_id": "call_1", "content": "Sunny"}
+            ],
+            "tools": [{"type": "function", "function": {"name": "get_weather", "parameters": {"type": "object"}}}],
+            "tool_choice": {"type": "tool", "name": "get_weather"}
+        });
+        
+        convert_request(&mut body);
+        
+        assert!(body.get("tools").is_none());
+        assert_eq!(body["functions"][0]["name"], "get_weather");
+        assert_eq!(body["function_call"], json!({"name": "get_weather"}));
+        assert_eq!(body["messages"][1]["function_call"]["name"], "get_weather");
+        assert!(body["messages"][1].get("tool_calls").is_none());
+        assert_eq!(body["messages"][2]["role"], "function");
+        assert_eq!(body["messages"][2]["name"], "get_weather");
+        assert!(body["messages"][2].get("tool_call_id").is_none());
+    }
+    
+    #[test]
+    fn test_function_call_choice() {
+        assert_eq!(function_call_choice(&json!("auto")), json!("auto"));
+        assert_eq!(function_call_choice(&json!("none")), json!("none"));
+        assert_eq!(function_call_choice(&json!({"type": "any"})), json!("auto"));
+        assert_eq!(
+            function_call_choice(&json!({"type": "function", "function": {"name": "ls"}})),
+            json!({"name": "ls"})
+        );
+    }
+    
+    #[test]
+    fn test_convert_response() {
+        let mut body = json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion",
+            "created": 0,
+            "model": "gpt-3.5-turbo-0613",
+            "choices": [{
+                "index": 0,
+                "message": {"role": "assistant", "content": null, "function_call": {"name": "get_weather", "arguments": "{\"city\":\"Paris\"}"}},
+                "finish_reason": "function_call"
+            }]
+        });
+        
+        convert_response(&mut body);
+        let response: OpenAIResponse = serde_json::from_value(body).unwrap();
+        
+        let choice = &response.choices[0];
+        assert_eq!(choice.finish_reason.as_deref(), Some("tool_calls"));
+        let tool_call = &choice.message.tool_calls.as_ref().unwrap()[0];
+        assert!(tool_call.id.as_ref().unwrap().starts_with("call_"));
+        assert_eq!(tool_call.function.name.as_deref(), Some("get_weather"));
+    }
+    
+    #[test]
+    fn test_parse_chunk() {
+        let first = r#"{"id":"1","object":"chat.completion.chunk","created":0,"model":"m","choices":[{"index":0,"delta":{"role":"assistant","function_call":{"name":"ls","arguments":""}},"finish_reason":null}]}"#;
+        let chunk = parse_chunk(first).unwrap();
+        let tool_call = &chunk.choices[0].delta.tool_calls.as_ref().unwrap()[0];
+        assert_eq!(tool_call.index, Some(0));
+        assert!(tool_call.id.is_some());
+        
+        let next = r#"{"id":"1","object":"chat.completion.chunk","created":0,"model":"m","choices":[{"index":0,"delta":{"function_call":{"arguments":"{}"}},"finish_reason":null}]}"#;
+        let chunk = parse_chunk(next).unwrap();
+        let tool_call = &chunk.choices[0].delta.tool_calls.as_ref().unwrap()[0];
+        assert!(tool_call.id.is_none());
+        assert_eq!(tool_call.function.arguments.as_deref(), Some("{}"));
+        
+        let last = r#"{"id":"1","object":"chat.completion.chunk","created":0,"model":"m","choices":[{"index":0,"delta":{},"finish_reason":"function_call"}]}"#;
+        assert_eq!(parse_chunk(last).unwrap().choices[0].finish_reason.as_deref(), Some("tool_calls"));
+        
+        assert!(parse_chunk("[DONE]").is_none());
+    }
+}
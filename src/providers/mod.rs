@@ -3,18 +3,37 @@
 //! Defines the Provider trait and provider implementations
 
 pub mod ark;
+pub mod error;
+mod images;
+mod legacy_functions;
 pub mod modelhub;
 pub mod openai;
+mod responses_api;
 
 use crate::config::{ModelConfig, ProviderConfig};
 use crate::models::openai::{OpenAIRequest, OpenAIResponse, OpenAIStreamResponse};
-use anyhow::Result;
+use crate::services::ResponseConverter;
 use async_trait::async_trait;
 use std::pin::Pin;
+use std::sync::Arc;
 use tokio_stream::Stream;
 
+/// Header forwarding the proxy's request ID upstream
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// Forward the inbound headers listed in `forwardHeaders`, and the ID of a
+/// request so proxy and provider logs can be correlated
+fn with_request_headers(builder: reqwest::RequestBuilder, request: &OpenAIRequest) -> reqwest::RequestBuilder {
+    let builder = request.forwarded_headers.iter()
+        .fold(builder, |builder, (name, value)| builder.header(name, value));
+    match &request.request_id {
+        Some(request_id) => builder.header(REQUEST_ID_HEADER, request_id),
+        None => builder,
+    }
+}
+
 /// A boxed stream of streaming responses
-pub type BoxStream<'a, T> = Pin<Box<dyn Stream<Item = Result<T>> + Send + 'a>>;
+pub type BoxStream<'a, T> = Pin<Box<dyn Stream<Item = anyhow::Result<T>> + Send + 'a>>;
 
 /// Provider trait for upstream API providers
 ///
@@ -31,7 +50,7 @@ pub trait Provider: Send + Sync {
         request: OpenAIRequest,
         provider_config: &ProviderConfig,
         model_config: &ModelConfig,
-    ) -> Result<OpenAIResponse>;
+    ) -> Result<OpenAIResponse, ProviderError>;
     
     /// Send a chat completion request (streaming)
     async fn chat_stream(
@@ -39,9 +58,18 @@ pub trait Provider: Send + Sync {
         request: OpenAIRequest,
         provider_config: &ProviderConfig,
         model_config: &ModelConfig,
-    ) -> Result<BoxStream<'static, OpenAIStreamResponse>>;
+    ) -> Result<BoxStream<'static, OpenAIStreamResponse>, ProviderError>;
+    
+    /// Specialized response converter for a model of this provider
+    ///
+    /// Returns `None` to use the default `ApiConverter` conversion.
+    fn response_converter(&self, _model_config: &ModelConfig) -> Option<Arc<dyn ResponseConverter>> {
+        None
+    }
 }
 
 pub use ark::ArkProvider;
+pub use error::{ProviderError, UpstreamError};
 pub use modelhub::ModelHubProvider;
 pub use openai::OpenAIProvider;
+pub use responses_api::StreamParser as ResponsesStreamParser;
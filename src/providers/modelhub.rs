@@ -2,20 +2,59 @@
 //!
 //! Supports both OpenAI-compatible (responses) mode and Gemini mode
 
-use super::{BoxStream, Provider};
-use crate::config::{ModelConfig, ProviderConfig};
+use super::responses_api::{self, InputItemFormat};
+use super::{with_request_headers, BoxStream, Provider, ProviderError, UpstreamError};
+use crate::config::{ModelConfig, ProviderConfig, ProviderOptions};
 use crate::models::openai::*;
-use crate::utils::logging::{create_request_log_summary, VERBOSE_REQUEST_LOGGING};
+use crate::utils::sse;
+use crate::utils::logging::{create_raw_log, create_request_log_summary};
+use crate::services::{error_reports, http_client, sessions};
 use crate::utils::thought_cache::{cache_thought_signature, get_cached_thought_signature};
 use anyhow::{Context, Result};
 use async_trait::async_trait;
+use base64::prelude::*;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::net::{IpAddr, SocketAddr};
 use std::time::Duration;
-use tokio_stream::StreamExt;
 use tracing::{debug, error, warn};
 
+/// Largest remote image downloaded for Gemini mode
+const MAX_REMOTE_IMAGE_BYTES: usize = 20 * 1024 * 1024;
+
+/// Whether an address is reachable on the public internet, and not a
+/// loopback, private, link-local or otherwise reserved one
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                // Shared address space (RFC 6598) and "this network"
+                || (a == 100 && (64..128).contains(&b))
+                || a == 0)
+        }
+        IpAddr::V6(ip) => {
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return is_public(IpAddr::V4(ip));
+            }
+            let first = ip.segments()[0];
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                // Unique local (fc00::/7) and link-local (fe80::/10)
+                || (first & 0xfe00) == 0xfc00
+                || (first & 0xffc0) == 0xfe80)
+        }
+    }
+}
+
 /// Inject cached thought_signatures into tool_calls in the request
 /// This is needed because Claude Code doesn't preserve our custom thought_signature field
 fn inject_cached_thought_signatures(request: &mut OpenAIRequest) {
@@ -28,9 +67,12 @@ fn inject_cached_thought_signatures(request: &mut OpenAIRequest) {
                         continue;
                     }
                     
-                    // Try to get cached signature
+                    // Try the session's signatures first, then the global cache
                     if let Some(id) = &tc.id {
-                        if let Some(sig) = get_cached_thought_signature(id) {
+                        let cached = request.session_id.as_deref()
+                            .and_then(|sid| sessions::global().thought_signature(sid, id))
+                            .or_else(|| get_cached_thought_signature(id));
+                        if let Some(sig) = cached {
                             debug!("💉 Injecting cached thought_signature for tool_call_id: {}", id);
                             tc.signature = Some(sig.clone());
                             tc.extra_content = Some(serde_json::json!({
@@ -46,111 +88,6 @@ fn inject_cached_thought_signatures(request: &mut OpenAIRequest) {
     }
 }
 
-/// Create a filtered version of Responses API request for logging
-fn create_log_responses_request(request: &ResponsesApiRequest) -> serde_json::Value {
-    if VERBOSE_REQUEST_LOGGING {
-        serde_json::to_value(request).unwrap_or(serde_json::json!({"error": "failed to serialize"}))
-    } else {
-        serde_json::json!({
-            "model": request.model,
-            "max_output_tokens": request.max_output_tokens,
-            "temperature": request.temperature,
-            "stream": request.stream,
-            "input_count": request.input.len(),
-            "tools_count": request.tools.as_ref().map(|t| t.len()).unwrap_or(0),
-            "tools": "[omitted]",
-            "instructions": "[omitted]",
-        })
-    }
-}
-
-// ====== Responses API Structures ======
-
-/// OpenAI Responses API Request format
-#[derive(Debug, Serialize)]
-struct ResponsesApiRequest {
-    model: String,
-    /// Input can contain various types:
-    /// - Messages: { role: "user"|"assistant", content: [...] }
-    /// - Function calls: { type: "function_call", call_id, name, arguments }
-    /// - Function results: { type: "function_call_output", call_id, output }
-    input: Vec<Value>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    max_output_tokens: Option<u32>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    temperature: Option<f32>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    stream: Option<bool>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    tools: Option<Vec<Value>>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    instructions: Option<String>,
-}
-
-/// Input message for Responses API
-#[derive(Debug, Serialize)]
-struct ResponsesInputMessage {
-    role: String,
-    content: Value,
-}
-
-/// OpenAI Responses API Response format
-#[derive(Debug, Deserialize)]
-struct ResponsesApiResponse {
-    id: String,
-    #[serde(default)]
-    model: Option<String>,
-    output: Vec<ResponsesOutput>,
-    #[serde(default)]
-    usage: Option<ResponsesUsage>,
-    status: String,
-    // Additional fields that may be present but we don't need
-    #[serde(default)]
-    created_at: Option<u64>,
-    #[serde(default)]
-    incomplete_details: Option<serde_json::Value>,
-}
-
-#[derive(Debug, Deserialize)]
-struct ResponsesOutput {
-    #[serde(rename = "type")]
-    output_type: String,
-    #[serde(default)]
-    content: Option<Vec<ResponsesContent>>,
-    #[serde(default)]
-    id: Option<String>,
-    #[serde(default)]
-    role: Option<String>,
-    #[serde(default)]
-    status: Option<String>,
-    // For tool_use output
-    #[serde(default)]
-    call_id: Option<String>,
-    #[serde(default)]
-    name: Option<String>,
-    #[serde(default)]
-    arguments: Option<String>,
-    // For reasoning output
-    #[serde(default)]
-    summary: Option<Vec<serde_json::Value>>,
-}
-
-#[derive(Debug, Deserialize)]
-struct ResponsesContent {
-    #[serde(rename = "type")]
-    content_type: String,
-    #[serde(default)]
-    text: Option<String>,
-}
-
-#[derive(Debug, Deserialize)]
-struct ResponsesUsage {
-    input_tokens: u32,
-    output_tokens: u32,
-    #[serde(default)]
-    total_tokens: Option<u32>,
-}
-
 /// ModelHub Provider
 /// 
 /// Supports two modes:
@@ -158,7 +95,8 @@ struct ResponsesUsage {
 /// - "gemini": Gemini protocol adapter with request/response transformation
 pub struct ModelHubProvider {
     client: Client,
-    stream_client: Client,
+    timeout: Duration,
+    stream_timeout: Duration,
 }
 
 impl ModelHubProvider {
@@ -169,19 +107,15 @@ impl ModelHubProvider {
     
     /// Create a new ModelHub provider with custom timeouts
     pub fn with_timeouts(timeout_secs: u64, stream_timeout_secs: u64) -> Result<Self> {
-        let client = Client::builder()
-            .timeout(Duration::from_secs(timeout_secs))
-            .user_agent("aiapiproxy/0.1.0")
-            .build()
-            .context("Failed to create HTTP client")?;
-        
-        let stream_client = Client::builder()
-            .timeout(Duration::from_secs(stream_timeout_secs))
-            .user_agent("aiapiproxy/0.1.0")
-            .build()
-            .context("Failed to create streaming HTTP client")?;
-        
-        Ok(Self { client, stream_client })
+        let client = http_client::global().client(&ProviderOptions::default())?;
+        Ok(Self::with_client(client, Duration::from_secs(timeout_secs), Duration::from_secs(stream_timeout_secs)))
+    }
+    
+    /// Create a new ModelHub provider on a shared client
+    ///
+    /// The timeouts are applied to each request.
+    pub fn with_client(client: Client, timeout: Duration, stream_timeout: Duration) -> Self {
+        Self { client, timeout, stream_timeout }
     }
     
     /// Build request URL with API key parameter
@@ -237,6 +171,101 @@ impl ModelHubProvider {
         builder
     }
     
+    /// Download remote images and inline them as base64 data URLs
+    ///
+    /// Gemini via /v2/crawl only accepts inline image data, so http(s) URLs
+    /// coming from Claude `url` image sources are fetched here, within the
+    /// limits of [`fetch_image_as_data_url`](Self::fetch_image_as_data_url).
+    /// Images that fail to download are left untouched.
+    async fn inline_remote_images(&self, request: &mut OpenAIRequest, allowed_hosts: &[String]) {
+        for message in request.messages.iter_mut() {
+            let Some(OpenAIContent::Array(parts)) = message.content.as_mut() else {
+                continue;
+            };
+            
+            for part in parts.iter_mut() {
+                let OpenAIContentPart::ImageUrl { image_url } = part else {
+                    continue;
+                };
+                if !image_url.url.starts_with("http://") && !image_url.url.starts_with("https://") {
+                    continue;
+                }
+                
+                match self.fetch_image_as_data_url(&image_url.url, allowed_hosts, MAX_REMOTE_IMAGE_BYTES).await {
+                    Ok(data_url) => {
+                        debug!("🖼️ Inlined remote image: {}", image_url.url);
+                        image_url.url = data_url;
+                    }
+                    Err(e) => warn!("Failed to inline remote image {}: {:#}", image_url.url, e),
+                }
+            }
+        }
+    }
+    
+    /// Fetch an image and encode it as a data URL
+    ///
+    /// The URL comes from the client and is fetched from the proxy's network,
+    /// so only http(s) URLs of `allowed_hosts`, or of hosts resolving to
+    /// public addresses when there are none, are fetched. The connection goes
+    /// to the checked addresses, redirects aren't followed, and the response
+    /// must be an image of at most `max_bytes`.
+    async fn fetch_image_as_data_url(&self, url: &str, allowed_hosts: &[String], max_bytes: usize) -> Result<String> {
+        let url = reqwest::Url::parse(url).context("Invalid image URL")?;
+        if !matches!(url.scheme(), "http" | "https") {
+            anyhow::bail!("Unsupported image URL scheme {}", url.scheme());
+        }
+        let host = url.host_str().context("Image URL without a host")?.to_string();
+        let listed = allowed_hosts.iter().any(|allowed| allowed.eq_ignore_ascii_case(&host));
+        if !allowed_hosts.is_empty() && !listed {
+            anyhow::bail!("Host {} is not in imageFetchHosts", host);
+        }
+        
+        let port = url.port_or_known_default().unwrap_or(80);
+        let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host.trim_start_matches('[').trim_end_matches(']'), port))
+            .await
+            .context("Failed to resolve image host")?
+            .collect();
+        if !listed {
+            if let Some(addr) = addrs.iter().find(|addr| !is_public(addr.ip())) {
+                anyhow::bail!("Host {} resolves to non-public address {}", host, addr.ip());
+            }
+        }
+        
+        let client = Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .resolve_to_addrs(&host, &addrs)
+            .timeout(self.timeout)
+            .build()
+            .context("Failed to build image client")?;
+        let mut response = client.get(url).send().await.context("Failed to download image")?;
+        
+        let status = response.status();
+        if !status.is_success() {
+            anyhow::bail!("Image download failed: {}", status);
+        }
+        
+        let media_type = response.headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.split(';').next())
+            .map(|v| v.trim().to_string())
+            .filter(|v| v.starts_with("image/"))
+            .context("Response is not an image")?;
+        
+        if response.content_length().is_some_and(|length| length > max_bytes as u64) {
+            anyhow::bail!("Image is larger than {} bytes", max_bytes);
+        }
+        let mut bytes = Vec::new();
+        while let Some(chunk) = response.chunk().await.context("Failed to read image body")? {
+            if bytes.len() + chunk.len() > max_bytes {
+                anyhow::bail!("Image is larger than {} bytes", max_bytes);
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+        
+        Ok(format!("data:{};base64,{}", media_type, BASE64_STANDARD.encode(&bytes)))
+    }
+    
     // =============================
     // OpenAI Responses Mode Methods
     // =============================
@@ -250,10 +279,9 @@ impl ModelHubProvider {
         debug!("ModelHub: Using Responses API mode");
         
         // Convert OpenAI request to Responses API format
-        let responses_request = self.convert_to_responses_api(&request, model_config)?;
+        let responses_request = responses_api::convert_request(&request, model_config, InputItemFormat::default(), provider_config.options.orphan_tool_calls)?;
         
-        let log_request = create_log_responses_request(&responses_request);
-        if let Ok(req_json) = serde_json::to_string_pretty(&log_request) {
+        if let Some(req_json) = responses_api::create_log_request(&responses_request) {
             debug!("📤 Responses API Request:\n{}", req_json);
         }
         
@@ -261,10 +289,11 @@ impl ModelHubProvider {
         
         let builder = self.client
             .post(&url)
+            .timeout(self.timeout)
             .header("Content-Type", "application/json")
             .json(&responses_request);
         
-        let response = self.add_modelhub_headers(builder, provider_config, request.session_id.as_deref())
+        let response = self.add_modelhub_headers(with_request_headers(builder, &request), provider_config, request.session_id.as_deref())
             .send()
             .await
             .context("Failed to send request")?;
@@ -276,284 +305,15 @@ impl ModelHubProvider {
             let response_text = response.text().await
                 .context("Failed to read Responses API response body")?;
             
-            debug!("📥 Responses API Raw Response:\n{}", 
-                   if response_text.len() > 1000 { &response_text[..1000] } else { &response_text });
-            
-            let responses_api_response: ResponsesApiResponse = serde_json::from_str(&response_text)
-                .with_context(|| {
-                    error!("Failed to parse Responses API response. Raw response:\n{}", 
-                           if response_text.len() > 2000 { &response_text[..2000] } else { &response_text });
-                    "Failed to parse Responses API response"
-                })?;
-            
-            debug!("ModelHub Responses API request completed successfully");
-            
             // Convert Responses API response back to OpenAI format
-            Ok(self.convert_from_responses_api(responses_api_response))
-        } else {
-            let error_text = response.text().await.unwrap_or_default();
-            error!("ModelHub API request failed: {} - {}", status, error_text);
-            anyhow::bail!("ModelHub API request failed: {} - {}", status, error_text);
-        }
-    }
-    
-    /// Convert OpenAI request to Responses API format
-    fn convert_to_responses_api(&self, request: &OpenAIRequest, model_config: &ModelConfig) -> Result<ResponsesApiRequest> {
-        // Convert messages to input format
-        // Note: Responses API uses a different structure than chat completions
-        // - User messages use role: "user" with content blocks
-        // - Assistant messages use role: "assistant" with content blocks  
-        // - Tool calls are separate "function_call" items
-        // - Tool results are "function_call_output" items (NOT role: "tool")
-        let mut input: Vec<Value> = Vec::new();
-        let mut system_instructions: Option<String> = None;
-        
-        // First pass: collect all tool result call_ids
-        // This is needed because Codex requires every function_call to have a matching function_call_output
-        // But Claude Code may send incomplete tool call sequences (user can interrupt)
-        let mut tool_result_ids: std::collections::HashSet<String> = std::collections::HashSet::new();
-        for msg in &request.messages {
-            if msg.role == "tool" {
-                if let Some(id) = &msg.tool_call_id {
-                    tool_result_ids.insert(id.clone());
-                }
-            }
-        }
-        
-        // Debug: log message roles and tool info
-        for (i, msg) in request.messages.iter().enumerate() {
-            let has_tool_calls = msg.tool_calls.as_ref().map(|t| t.len()).unwrap_or(0);
-            let tool_call_id = msg.tool_call_id.as_ref().map(|s| s.as_str()).unwrap_or("none");
-            debug!("Message {}: role={}, has_tool_calls={}, tool_call_id={}", 
-                   i, msg.role, has_tool_calls, tool_call_id);
-        }
-        
-        for msg in &request.messages {
-            let role = msg.role.as_str();
-            
-            // Extract system message as instructions
-            if role == "system" {
-                if let Some(content) = &msg.content {
-                    system_instructions = Some(content.extract_text());
-                }
-                continue;
-            }
-            
-            // Handle tool role -> function_call_output
-            if role == "tool" {
-                if let Some(tool_call_id) = &msg.tool_call_id {
-                    let output = msg.content.as_ref()
-                        .map(|c| c.extract_text())
-                        .unwrap_or_default();
-                    debug!("Adding function_call_output with call_id={}", tool_call_id);
-                    input.push(serde_json::json!({
-                        "type": "function_call_output",
-                        "call_id": tool_call_id,
-                        "output": output
-                    }));
-                } else {
-                    warn!("Tool message without tool_call_id, skipping");
-                }
-                continue;
-            }
-            
-            // Handle assistant with tool_calls -> function_call items
-            if role == "assistant" {
-                let has_tool_calls = msg.tool_calls.as_ref().map(|t| !t.is_empty()).unwrap_or(false);
-                
-                if let Some(tool_calls) = &msg.tool_calls {
-                    for tc in tool_calls {
-                        if let Some(id) = &tc.id {
-                            // Only add function_call if there's a matching function_call_output
-                            // This handles the case where Claude Code sends incomplete tool call sequences
-                            if tool_result_ids.contains(id) {
-                                debug!("Adding function_call with call_id={}, name={:?}", id, tc.function.name);
-                                input.push(serde_json::json!({
-                                    "type": "function_call",
-                                    "call_id": id,
-                                    "name": tc.function.name,
-                                    "arguments": tc.function.arguments.clone().unwrap_or_default()
-                                }));
-                            } else {
-                                warn!("Skipping orphan function_call with call_id={} (no matching output)", id);
-                            }
-                        } else {
-                            warn!("Tool call without id, skipping");
-                        }
-                    }
-                }
-                
-                // Only add assistant text content if there are NO tool calls
-                // (If there are tool calls, adding text here would break the function_call/function_call_output sequence)
-                if !has_tool_calls {
-                    if let Some(content) = &msg.content {
-                        let text = content.extract_text();
-                        if !text.is_empty() {
-                            input.push(serde_json::json!({
-                                "role": "assistant",
-                                "content": [{ "type": "output_text", "text": text }]
-                            }));
-                        }
-                    }
-                }
-                continue;
-            }
+            let openai_response = responses_api::parse_response(&response_text)?;
+            debug!("ModelHub Responses API request completed successfully");
             
-            // Handle user messages
-            if role == "user" {
-                let content = if let Some(c) = &msg.content {
-                    match c {
-                        OpenAIContent::Text(text) => {
-                            vec![serde_json::json!({ "type": "input_text", "text": text })]
-                        },
-                        OpenAIContent::Array(parts) => {
-                            parts.iter().map(|p| {
-                                match p {
-                                    OpenAIContentPart::Text { text } => {
-                                        serde_json::json!({ "type": "input_text", "text": text })
-                                    },
-                                    OpenAIContentPart::ImageUrl { image_url } => {
-                                        serde_json::json!({
-                                            "type": "input_image",
-                                            "image_url": image_url.url
-                                        })
-                                    },
-                                }
-                            }).collect()
-                        }
-                    }
-                } else {
-                    vec![serde_json::json!({ "type": "input_text", "text": "" })]
-                };
-                
-                input.push(serde_json::json!({
-                    "role": "user",
-                    "content": content
-                }));
-            }
-        }
-        
-        // Convert tools to Responses API format
-        // OpenAI chat format: { type: "function", function: { name, description, parameters } }
-        // Responses API format: { type: "function", name, description, parameters }
-        let tools = request.tools.as_ref().map(|t| {
-            t.iter().map(|tool| {
-                serde_json::json!({
-                    "type": "function",
-                    "name": tool.function.name,
-                    "description": tool.function.description,
-                    "parameters": tool.function.parameters
-                })
-            }).collect()
-        });
-        
-        // Ensure max_output_tokens is reasonable
-        // Take the max of request and config values to avoid Claude Code's low default (e.g., 1)
-        let max_output_tokens = match (request.max_tokens, model_config.max_tokens) {
-            (Some(req), Some(cfg)) => Some(req.max(cfg)),
-            (Some(req), None) => Some(req.max(8192)), // default minimum for Codex
-            (None, Some(cfg)) => Some(cfg),
-            (None, None) => Some(8192),
-        };
-        debug!("📊 Responses API max_output_tokens: request={:?}, config={:?}, final={:?}",
-               request.max_tokens, model_config.max_tokens, max_output_tokens);
-        
-        // Only include temperature if the model supports it
-        // Reasoning models (o1, o3, etc.) don't support temperature
-        let temperature = if model_config.options.supports_temperature {
-            request.temperature.or(model_config.temperature)
+            Ok(openai_response)
         } else {
-            debug!("📊 Model {} does not support temperature, skipping parameter", model_config.name);
-            None
-        };
-        
-        Ok(ResponsesApiRequest {
-            model: model_config.name.clone(),
-            input,
-            max_output_tokens,
-            temperature,
-            stream: None,
-            tools,
-            instructions: system_instructions,
-        })
-    }
-    
-    /// Convert Responses API response to OpenAI format
-    fn convert_from_responses_api(&self, response: ResponsesApiResponse) -> OpenAIResponse {
-        let mut content_text = String::new();
-        let mut tool_calls: Vec<OpenAIToolCall> = Vec::new();
-        
-        for output in &response.output {
-            match output.output_type.as_str() {
-                "message" => {
-                    if let Some(contents) = &output.content {
-                        for c in contents {
-                            if c.content_type == "output_text" {
-                                if let Some(text) = &c.text {
-                                    content_text.push_str(text);
-                                }
-                            }
-                        }
-                    }
-                },
-                "function_call" | "tool_use" => {
-                    if let (Some(name), Some(arguments)) = (&output.name, &output.arguments) {
-                        tool_calls.push(OpenAIToolCall {
-                            id: output.call_id.clone(),
-                            tool_type: Some("function".to_string()),
-                            function: OpenAIFunctionCall {
-                                name: Some(name.clone()),
-                                arguments: Some(arguments.clone()),
-                            },
-                            signature: None,
-                            extra_content: None,
-                        });
-                    }
-                },
-                "reasoning" => {
-                    // Reasoning output doesn't contain text content, just internal reasoning
-                    // We can safely ignore it as it's for debugging/transparency
-                    debug!("Responses API: got reasoning output with {} summary items", 
-                           output.summary.as_ref().map(|s| s.len()).unwrap_or(0));
-                },
-                other => {
-                    debug!("Responses API: ignoring unknown output type: {}", other);
-                }
-            }
-        }
-        
-        // Build choice
-        let choice = OpenAIChoice {
-            index: 0,
-            message: OpenAIMessage {
-                role: "assistant".to_string(),
-                content: if content_text.is_empty() { None } else { Some(OpenAIContent::Text(content_text)) },
-                tool_calls: if tool_calls.is_empty() { None } else { Some(tool_calls) },
-                tool_call_id: None,
-                name: None,
-            },
-            logprobs: None,
-            finish_reason: Some(match response.status.as_str() {
-                "completed" => "stop".to_string(),
-                "cancelled" => "stop".to_string(),
-                _ => "stop".to_string(),
-            }),
-        };
-        
-        let usage = response.usage.map(|u| OpenAIUsage {
-            prompt_tokens: u.input_tokens,
-            completion_tokens: u.output_tokens,
-            total_tokens: u.total_tokens.unwrap_or(u.input_tokens + u.output_tokens),
-        });
-        
-        OpenAIResponse {
-            id: response.id,
-            object: "chat.completion".to_string(),
-            created: 0,
-            model: response.model.unwrap_or_default(),
-            choices: vec![choice],
-            usage,
-            system_fingerprint: None,
+            let upstream_error = UpstreamError::from_response("ModelHub", response).await;
+            error!("ModelHub API request failed: {} - {}", status, upstream_error.body);
+            Err(upstream_error.into())
         }
     }
     
@@ -566,265 +326,29 @@ impl ModelHubProvider {
         debug!("ModelHub: Using Responses API streaming mode");
         
         // Convert to Responses API format with stream=true
-        let mut responses_request = self.convert_to_responses_api(&request, model_config)?;
+        let mut responses_request = responses_api::convert_request(&request, model_config, InputItemFormat::default(), provider_config.options.orphan_tool_calls)?;
         responses_request.stream = Some(true);
         
         let url = self.build_url(provider_config, "/responses");
         
-        let builder = self.stream_client
+        let builder = self.client
             .post(&url)
+            .timeout(self.stream_timeout)
             .header("Content-Type", "application/json")
             .header("Accept", "text/event-stream")
             .json(&responses_request);
         
-        let response = self.add_modelhub_headers(builder, provider_config, request.session_id.as_deref())
+        let response = self.add_modelhub_headers(with_request_headers(builder, &request), provider_config, request.session_id.as_deref())
             .send()
             .await
             .context("Failed to send streaming request")?;
         
         if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_default();
-            anyhow::bail!("ModelHub API request failed: {} - {}", status, error_text);
+            return Err(UpstreamError::from_response("ModelHub", response).await.into());
         }
         
         // Parse Responses API SSE stream and convert to OpenAI stream format
-        // Use a shared buffer for handling incomplete lines across chunks
-        let line_buffer = std::sync::Arc::new(std::sync::Mutex::new(String::new()));
-        let role_sent = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
-        
-        let stream = response
-            .bytes_stream()
-            .filter_map(move |chunk_result| {
-                let line_buffer = line_buffer.clone();
-                let role_sent = role_sent.clone();
-                match chunk_result {
-                    Ok(chunk) => {
-                        // Convert bytes to string, replacing invalid UTF-8 with replacement char
-                        let chunk_str = String::from_utf8_lossy(&chunk);
-                        
-                        // Append to buffer
-                        let mut buffer = line_buffer.lock().unwrap();
-                        buffer.push_str(&chunk_str);
-                        
-                        // Process all complete lines (ending with \n)
-                        // Keep the incomplete last line in the buffer
-                        let mut result: Option<Result<OpenAIStreamResponse>> = None;
-                        
-                        while let Some(newline_pos) = buffer.find('\n') {
-                            let line = buffer[..newline_pos].to_string();
-                            *buffer = buffer[newline_pos + 1..].to_string();
-                            
-                            // Try to parse this line
-                            if let Some(parsed) = Self::parse_single_sse_line(&line, &role_sent) {
-                                result = Some(parsed);
-                                // Return immediately on first valid result
-                                break;
-                            }
-                        }
-                        
-                        result
-                    }
-                    Err(e) => Some(Err(anyhow::anyhow!("Stream error: {}", e))),
-                }
-            });
-        
-        Ok(Box::pin(stream))
-    }
-    
-    /// Parse a single SSE line
-    fn parse_single_sse_line(
-        line: &str, 
-        role_sent: &std::sync::Arc<std::sync::atomic::AtomicBool>,
-    ) -> Option<Result<OpenAIStreamResponse>> {
-        let line = line.trim();
-        
-        // Skip empty lines and event type lines
-        if line.is_empty() || line.starts_with("event:") || line.starts_with(':') {
-            return None;
-        }
-        
-        if let Some(data) = line.strip_prefix("data:") {
-            let data = data.trim();
-            
-            if data == "[DONE]" {
-                debug!("📡 SSE: received [DONE]");
-                return None;
-            }
-            
-            // Parse Responses API streaming event
-            match serde_json::from_str::<Value>(data) {
-                Ok(event) => {
-                    let event_type = event.get("type").and_then(|t| t.as_str()).unwrap_or("unknown");
-                    debug!("📡 SSE event: {}", event_type);
-                    
-                    match event_type {
-                        // Handle text delta - this is the main content event
-                        "response.output_text.delta" => {
-                            if let Some(delta) = event.get("delta").and_then(|d| d.as_str()) {
-                                // If role hasn't been sent yet, send it with the first content
-                                let need_role = !role_sent.swap(true, std::sync::atomic::Ordering::SeqCst);
-                                
-                                debug!("📡 Text delta: {} chars, need_role={}", delta.len(), need_role);
-                                return Some(Ok(OpenAIStreamResponse {
-                                    id: event.get("item_id").and_then(|i| i.as_str()).unwrap_or("").to_string(),
-                                    object: "chat.completion.chunk".to_string(),
-                                    created: 0,
-                                    model: String::new(),
-                                    system_fingerprint: None,
-                                    choices: vec![OpenAIStreamChoice {
-                                        index: 0,
-                                        delta: OpenAIStreamDelta {
-                                            role: if need_role { Some("assistant".to_string()) } else { None },
-                                            content: Some(delta.to_string()),
-                                            tool_calls: None,
-                                        },
-                                        logprobs: None,
-                                        finish_reason: None,
-                                    }],
-                                }));
-                            }
-                        },
-                        // Handle function call output item added - this starts a tool call
-                        "response.output_item.added" => {
-                            // Check if this is a function_call type item
-                            if let Some(item) = event.get("item") {
-                                let item_type = item.get("type").and_then(|t| t.as_str()).unwrap_or("");
-                                if item_type == "function_call" {
-                                    let call_id = item.get("call_id").and_then(|c| c.as_str()).unwrap_or("");
-                                    let name = item.get("name").and_then(|n| n.as_str()).unwrap_or("");
-                                    
-                                    // If role hasn't been sent yet, send it with the tool call
-                                    let need_role = !role_sent.swap(true, std::sync::atomic::Ordering::SeqCst);
-                                    
-                                    debug!("📡 Function call start: name={}, call_id={}, need_role={}", name, call_id, need_role);
-                                    return Some(Ok(OpenAIStreamResponse {
-                                        id: String::new(),
-                                        object: "chat.completion.chunk".to_string(),
-                                        created: 0,
-                                        model: String::new(),
-                                        system_fingerprint: None,
-                                        choices: vec![OpenAIStreamChoice {
-                                            index: 0,
-                                            delta: OpenAIStreamDelta {
-                                                role: if need_role { Some("assistant".to_string()) } else { None },
-                                                content: None,
-                                                tool_calls: Some(vec![OpenAIToolCall {
-                                                    id: Some(call_id.to_string()),
-                                                    tool_type: Some("function".to_string()),
-                                                    function: OpenAIFunctionCall {
-                                                        name: Some(name.to_string()),
-                                                        arguments: Some(String::new()),
-                                                    },
-                                                    signature: None,
-                                                    extra_content: None,
-                                                }]),
-                                            },
-                                            logprobs: None,
-                                            finish_reason: None,
-                                        }],
-                                    }));
-                                }
-                            }
-                        },
-                        // Handle function call arguments delta
-                        "response.function_call_arguments.delta" => {
-                            if let Some(delta) = event.get("delta").and_then(|d| d.as_str()) {
-                                debug!("📡 Function args delta: {} chars", delta.len());
-                                return Some(Ok(OpenAIStreamResponse {
-                                    id: String::new(),
-                                    object: "chat.completion.chunk".to_string(),
-                                    created: 0,
-                                    model: String::new(),
-                                    system_fingerprint: None,
-                                    choices: vec![OpenAIStreamChoice {
-                                        index: 0,
-                                        delta: OpenAIStreamDelta {
-                                            role: None,
-                                            content: None,
-                                            tool_calls: Some(vec![OpenAIToolCall {
-                                                id: None,
-                                                tool_type: None,
-                                                function: OpenAIFunctionCall {
-                                                    name: None,
-                                                    arguments: Some(delta.to_string()),
-                                                },
-                                                signature: None,
-                                                extra_content: None,
-                                            }]),
-                                        },
-                                        logprobs: None,
-                                        finish_reason: None,
-                                    }],
-                                }));
-                            }
-                        },
-                        // Handle function call arguments done - send tool_calls finish
-                        "response.function_call_arguments.done" => {
-                            debug!("📡 Function call done, sending tool_calls finish");
-                            // Include an empty tool_call to signal the converter that there's a tool block to close
-                            return Some(Ok(OpenAIStreamResponse {
-                                id: String::new(),
-                                object: "chat.completion.chunk".to_string(),
-                                created: 0,
-                                model: String::new(),
-                                system_fingerprint: None,
-                                choices: vec![OpenAIStreamChoice {
-                                    index: 0,
-                                    delta: OpenAIStreamDelta {
-                                        role: None,
-                                        content: None,
-                                        tool_calls: Some(vec![OpenAIToolCall {
-                                            id: None,
-                                            tool_type: None,
-                                            function: OpenAIFunctionCall {
-                                                name: None,
-                                                arguments: None,
-                                            },
-                                            signature: None,
-                                            extra_content: None,
-                                        }]),
-                                    },
-                                    logprobs: None,
-                                    finish_reason: Some("tool_calls".to_string()),
-                                }],
-                            }));
-                        },
-                        // Handle text completion events
-                        "response.output_text.done" => {
-                            debug!("📡 Text output done, sending stop");
-                            return Some(Ok(OpenAIStreamResponse {
-                                id: String::new(),
-                                object: "chat.completion.chunk".to_string(),
-                                created: 0,
-                                model: String::new(),
-                                system_fingerprint: None,
-                                choices: vec![OpenAIStreamChoice {
-                                    index: 0,
-                                    delta: OpenAIStreamDelta {
-                                        role: None,
-                                        content: None,
-                                        tool_calls: None,
-                                    },
-                                    logprobs: None,
-                                    finish_reason: Some("stop".to_string()),
-                                }],
-                            }));
-                        },
-                        "response.completed" | "response.done" => {
-                            debug!("📡 Stream completed event");
-                        },
-                        _ => {
-                            // Skip other event types silently
-                        }
-                    }
-                },
-                Err(_) => {
-                    // JSON parsing failed - likely truncated data, skip silently
-                }
-            }
-        }
-        None
+        Ok(responses_api::into_stream(response))
     }
     
     // ==================
@@ -842,20 +366,20 @@ impl ModelHubProvider {
     ) -> Result<OpenAIResponse> {
         debug!("ModelHub: Using Gemini mode (OpenAI chat format to /v2/crawl)");
         
-        // Log original max_tokens from request
-        let original_max_tokens = request.max_tokens;
-        
         // Update model name and apply defaults
         request.model = model_config.name.clone();
         
         // Use the maximum of request and config max_tokens to avoid too-small limits
         // Claude Code sometimes sends max_tokens=1 which causes immediate truncation
+        let original_max_tokens = request.max_tokens;
         request.max_tokens = match (request.max_tokens, model_config.max_tokens) {
             (Some(req), Some(cfg)) => Some(req.max(cfg)),
             (Some(req), None) => Some(req),
             (None, Some(cfg)) => Some(cfg),
             (None, None) => Some(8192), // Default fallback
         };
+        debug!("📊 max_tokens: original={:?}, config={:?}, final={:?}",
+               original_max_tokens, model_config.max_tokens, request.max_tokens);
         
         // Only set temperature if the model supports it
         // Reasoning models (o1, o3, etc.) don't support temperature
@@ -868,9 +392,6 @@ impl ModelHubProvider {
             request.temperature = None;
         }
         
-        debug!("📊 max_tokens: original={:?}, config={:?}, final={:?}",
-               original_max_tokens, model_config.max_tokens, request.max_tokens);
-        
         // Sanitize tools if present (Gemini rejects some JSON Schema features)
         if let Some(ref mut tools) = request.tools {
             for tool in tools.iter_mut() {
@@ -881,20 +402,24 @@ impl ModelHubProvider {
         // Inject cached thought_signatures into tool_calls
         inject_cached_thought_signatures(&mut request);
         
-        let log_request = create_request_log_summary(&request);
-        if let Ok(req_json) = serde_json::to_string_pretty(&log_request) {
+        // Gemini requires inline image data
+        self.inline_remote_images(&mut request, &provider_config.options.image_fetch_hosts).await;
+        
+        if let Some(req_json) = create_request_log_summary(&request) {
             debug!("📤 Gemini Mode Request:\n{}", req_json);
         }
         
         let url = self.build_url(provider_config, "/v2/crawl");
         let session_id = request.session_id.clone();
+        let body = gemini_request_body(&request, model_config)?;
         
         let builder = self.client
             .post(&url)
+            .timeout(self.timeout)
             .header("Content-Type", "application/json")
-            .json(&request);
+            .json(&body);
         
-        let response = self.add_modelhub_headers(builder, provider_config, session_id.as_deref())
+        let response = self.add_modelhub_headers(with_request_headers(builder, &request), provider_config, session_id.as_deref())
             .send()
             .await
             .context("Failed to send Gemini request")?;
@@ -908,10 +433,13 @@ impl ModelHubProvider {
                 .await
                 .context("Failed to read Gemini response body")?;
             
-            debug!("📥 Gemini Mode Raw Response:\n{}", &response_text);
+            if let Some(response_log) = create_raw_log(&response_text, 1000) {
+                debug!("📥 Gemini Mode Raw Response:\n{}", response_log);
+            }
             
             // Try to parse as OpenAI format
             let openai_response: OpenAIResponse = serde_json::from_str(&response_text)
+                .inspect_err(|e| error_reports::global().unparsable_response("Gemini response", e, &response_text))
                 .with_context(|| {
                     error!("Failed to parse Gemini response. Raw response:\n{}", &response_text);
                     format!("Failed to parse Gemini response (OpenAI format). Response: {}", 
@@ -946,9 +474,9 @@ impl ModelHubProvider {
             debug!("ModelHub Gemini mode request completed successfully");
             Ok(openai_response)
         } else {
-            let error_text = response.text().await.unwrap_or_default();
-            error!("ModelHub Gemini API request failed: {} - {}", status, error_text);
-            anyhow::bail!("ModelHub Gemini API request failed: {} - {}", status, error_text);
+            let upstream_error = UpstreamError::from_response("ModelHub Gemini", response).await;
+            error!("ModelHub Gemini API request failed: {} - {}", status, upstream_error.body);
+            Err(upstream_error.into())
         }
     }
     
@@ -960,21 +488,21 @@ impl ModelHubProvider {
     ) -> Result<BoxStream<'static, OpenAIStreamResponse>> {
         debug!("ModelHub: Using Gemini streaming mode (OpenAI chat format to /v2/crawl)");
         
-        // Log original max_tokens from request
-        let original_max_tokens = request.max_tokens;
-        
         // Update model name and apply defaults
         request.model = model_config.name.clone();
         request.stream = Some(true);
         
         // Use the maximum of request and config max_tokens to avoid too-small limits
         // Claude Code sometimes sends max_tokens=1 which causes immediate truncation
+        let original_max_tokens = request.max_tokens;
         request.max_tokens = match (request.max_tokens, model_config.max_tokens) {
             (Some(req), Some(cfg)) => Some(req.max(cfg)),
             (Some(req), None) => Some(req),
             (None, Some(cfg)) => Some(cfg),
             (None, None) => Some(8192), // Default fallback
         };
+        debug!("📊 max_tokens: original={:?}, config={:?}, final={:?}",
+               original_max_tokens, model_config.max_tokens, request.max_tokens);
         
         // Only set temperature if the model supports it
         // Reasoning models (o1, o3, etc.) don't support temperature
@@ -987,9 +515,6 @@ impl ModelHubProvider {
             request.temperature = None;
         }
         
-        debug!("📊 max_tokens: original={:?}, config={:?}, final={:?}",
-               original_max_tokens, model_config.max_tokens, request.max_tokens);
-        
         // Sanitize tools if present (Gemini rejects some JSON Schema features)
         if let Some(ref mut tools) = request.tools {
             for tool in tools.iter_mut() {
@@ -1000,73 +525,42 @@ impl ModelHubProvider {
         // Inject cached thought_signatures into tool_calls
         inject_cached_thought_signatures(&mut request);
         
-        let log_request = create_request_log_summary(&request);
-        if let Ok(req_json) = serde_json::to_string_pretty(&log_request) {
+        // Gemini requires inline image data
+        self.inline_remote_images(&mut request, &provider_config.options.image_fetch_hosts).await;
+        
+        if let Some(req_json) = create_request_log_summary(&request) {
             debug!("📤 Gemini Streaming Request:\n{}", req_json);
         }
         
         let url = self.build_url(provider_config, "/v2/crawl");
         let session_id = request.session_id.clone();
+        let body = gemini_request_body(&request, model_config)?;
         
-        let builder = self.stream_client
+        let builder = self.client
             .post(&url)
+            .timeout(self.stream_timeout)
             .header("Content-Type", "application/json")
             .header("Accept", "text/event-stream")
-            .json(&request);
+            .json(&body);
         
-        let response = self.add_modelhub_headers(builder, provider_config, session_id.as_deref())
+        let response = self.add_modelhub_headers(with_request_headers(builder, &request), provider_config, session_id.as_deref())
             .send()
             .await
             .context("Failed to send Gemini streaming request")?;
         
         if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_default();
-            anyhow::bail!("ModelHub Gemini API request failed: {} - {}", status, error_text);
+            return Err(UpstreamError::from_response("ModelHub Gemini", response).await.into());
         }
         
         // Response is in OpenAI streaming format
-        let stream = response
-            .bytes_stream()
-            .filter_map(move |chunk_result| {
-                match chunk_result {
-                    Ok(chunk) => {
-                        match std::str::from_utf8(&chunk) {
-                            Ok(chunk_str) => {
-                                Self::parse_openai_sse(chunk_str)
-                            }
-                            Err(e) => Some(Err(anyhow::anyhow!("Invalid UTF-8: {}", e))),
-                        }
-                    }
-                    Err(e) => Some(Err(anyhow::anyhow!("Stream error: {}", e))),
-                }
-            });
+        let stream = sse::openai_chunk_stream(response.bytes_stream());
         
         Ok(Box::pin(stream))
     }
     
-    /// Parse OpenAI SSE format (used by both Gemini mode streaming)
-    fn parse_openai_sse(chunk_str: &str) -> Option<Result<OpenAIStreamResponse>> {
-        for line in chunk_str.lines() {
-            if let Some(data) = line.strip_prefix("data: ") {
-                if data.trim() == "[DONE]" {
-                    return None;
-                }
-                
-                match serde_json::from_str::<OpenAIStreamResponse>(data) {
-                    Ok(stream_response) => {
-                        return Some(Ok(stream_response));
-                    }
-                    Err(e) => {
-                        warn!("Failed to parse OpenAI streaming response: {}", e);
-                    }
-                }
-            }
-        }
-        None
-    }
     
     /// Convert OpenAI request to Gemini format
+    #[allow(dead_code)]
     fn convert_to_gemini_request(&self, openai_req: &OpenAIRequest, model_config: &ModelConfig) -> Result<GeminiRequest> {
         let mut contents = Vec::new();
         let mut system_instruction = None;
@@ -1092,7 +586,7 @@ impl ModelHubProvider {
                         OpenAIContent::Array(arr) => {
                             for part in arr {
                                 match part {
-                                    OpenAIContentPart::Text { text } => {
+                                    OpenAIContentPart::Text { text, .. } => {
                                         parts.push(GeminiPart::Text { text: text.clone() });
                                     }
                                     OpenAIContentPart::ImageUrl { image_url } => {
@@ -1183,6 +677,7 @@ impl ModelHubProvider {
     }
     
     /// Convert Gemini response to OpenAI format
+    #[allow(dead_code)]
     fn convert_from_gemini_response(&self, gemini_resp: GeminiResponse, model: &str) -> Result<OpenAIResponse> {
         let mut content_text = String::new();
         let mut tool_calls = Vec::new();
@@ -1198,6 +693,7 @@ impl ModelHubProvider {
                             }
                             GeminiPart::FunctionCall { function_call } => {
                                 tool_calls.push(OpenAIToolCall {
+                                    index: None,
                                     id: Some(format!("call_{}", uuid::Uuid::new_v4().simple())),
                                     tool_type: Some("function".to_string()),
                                     function: OpenAIFunctionCall {
@@ -1239,10 +735,17 @@ impl ModelHubProvider {
             }
         }
         
-        // Build usage from metadata
-        let (prompt_tokens, completion_tokens) = gemini_resp.usage_metadata
-            .map(|u| (u.prompt_token_count.unwrap_or(0), u.candidates_token_count.unwrap_or(0)))
-            .unwrap_or((0, 0));
+        // Build usage from metadata; left unset when Gemini omits it so it can be estimated
+        let usage = gemini_resp.usage_metadata.map(|u| {
+            let prompt_tokens = u.prompt_token_count.unwrap_or(0);
+            let completion_tokens = u.candidates_token_count.unwrap_or(0);
+            OpenAIUsage {
+                prompt_tokens,
+                completion_tokens,
+                total_tokens: u.total_token_count.unwrap_or(prompt_tokens + completion_tokens),
+                prompt_tokens_details: None,
+            }
+        });
         
         Ok(OpenAIResponse {
             id: format!("chatcmpl-{}", uuid::Uuid::new_v4().simple()),
@@ -1264,16 +767,15 @@ impl ModelHubProvider {
                 logprobs: None,
                 finish_reason: Some(finish_reason),
             }],
-            usage: Some(OpenAIUsage {
-                prompt_tokens,
-                completion_tokens,
-                total_tokens: prompt_tokens + completion_tokens,
-            }),
+            usage,
             system_fingerprint: None,
+            search_results: None,
+            search_queries: None,
         })
     }
     
     /// Convert Gemini streaming chunk to OpenAI streaming format
+    #[allow(dead_code)]
     fn convert_gemini_stream_chunk(gemini_chunk: GeminiStreamResponse, model: &str) -> Option<OpenAIStreamResponse> {
         let mut content = None;
         let mut tool_calls = None;
@@ -1289,6 +791,7 @@ impl ModelHubProvider {
                             }
                             GeminiPart::FunctionCall { function_call } => {
                                 tool_calls = Some(vec![OpenAIToolCall {
+                                    index: None,
                                     id: Some(format!("call_{}", uuid::Uuid::new_v4().simple())),
                                     tool_type: Some("function".to_string()),
                                     function: OpenAIFunctionCall {
@@ -1330,6 +833,8 @@ impl ModelHubProvider {
                 .as_secs(),
             model: model.to_string(),
             system_fingerprint: None,
+            search_results: None,
+            search_queries: None,
             choices: vec![OpenAIStreamChoice {
                 index: 0,
                 delta: OpenAIStreamDelta {
@@ -1355,10 +860,10 @@ impl Provider for ModelHubProvider {
         request: OpenAIRequest,
         provider_config: &ProviderConfig,
         model_config: &ModelConfig,
-    ) -> Result<OpenAIResponse> {
+    ) -> Result<OpenAIResponse, ProviderError> {
         match self.get_mode(provider_config) {
-            "gemini" => self.chat_complete_gemini_mode(request, provider_config, model_config).await,
-            _ => self.openai_responses_mode(request, provider_config, model_config).await,
+            "gemini" => Ok(self.chat_complete_gemini_mode(request, provider_config, model_config).await?),
+            _ => Ok(self.openai_responses_mode(request, provider_config, model_config).await?),
         }
     }
     
@@ -1367,10 +872,10 @@ impl Provider for ModelHubProvider {
         request: OpenAIRequest,
         provider_config: &ProviderConfig,
         model_config: &ModelConfig,
-    ) -> Result<BoxStream<'static, OpenAIStreamResponse>> {
+    ) -> Result<BoxStream<'static, OpenAIStreamResponse>, ProviderError> {
         match self.get_mode(provider_config) {
-            "gemini" => self.chat_stream_gemini_mode(request, provider_config, model_config).await,
-            _ => self.openai_responses_mode_stream(request, provider_config, model_config).await,
+            "gemini" => Ok(self.chat_stream_gemini_mode(request, provider_config, model_config).await?),
+            _ => Ok(self.openai_responses_mode_stream(request, provider_config, model_config).await?),
         }
     }
 }
@@ -1507,6 +1012,7 @@ pub struct GeminiUsageMetadata {
 
 /// Decode UTF-8 bytes, returning valid string and any incomplete trailing bytes
 /// This handles the case where a chunk boundary cuts through a multi-byte UTF-8 character
+#[allow(dead_code)]
 fn decode_utf8_lossy_with_remainder(bytes: &[u8]) -> (String, Vec<u8>) {
     match std::str::from_utf8(bytes) {
         Ok(s) => (s.to_string(), Vec::new()),
@@ -1523,7 +1029,37 @@ fn decode_utf8_lossy_with_remainder(bytes: &[u8]) -> (String, Vec<u8>) {
     }
 }
 
+/// Build the Gemini mode request body
+///
+/// Gemini names the top_k sampling parameter `topK`; it is only sent when the
+/// model opts in via `supportsTopK`. The Claude web search tool becomes the
+/// Gemini `google_search` tool for models with `supportsWebSearch`.
+fn gemini_request_body(request: &OpenAIRequest, model_config: &ModelConfig) -> Result<Value> {
+    let fields: &[(&str, &str)] = if model_config.options.supports_top_k {
+        &[("top_k", "topK")]
+    } else {
+        &[]
+    };
+    let mut body = request.to_json_with_extensions(fields)
+        .context("Failed to serialize Gemini request")?;
+    
+    if request.extensions.contains_key("web_search") {
+        if model_config.options.supports_web_search {
+            let tool = serde_json::json!({ "google_search": {} });
+            match body.get_mut("tools").and_then(|t| t.as_array_mut()) {
+                Some(tools) => tools.push(tool),
+                None => body["tools"] = serde_json::json!([tool]),
+            }
+        } else {
+            warn!("Model {} does not support web search, dropping web_search tool", model_config.name);
+        }
+    }
+    
+    Ok(body)
+}
+
 /// Parse a data URL into mime type and base64 data
+#[allow(dead_code)]
 fn parse_data_url(url: &str) -> Option<(String, String)> {
     if !url.starts_with("data:") {
         return None;
@@ -1551,7 +1087,7 @@ fn parse_data_url(url: &str) -> Option<(String, String)> {
 /// Sanitize tool schema for Gemini compatibility
 /// Removes unsupported JSON Schema features like anyOf, allOf, oneOf
 pub fn sanitize_tool_schema(schema: Option<serde_json::Value>) -> Option<serde_json::Value> {
-    schema.map(|s| sanitize_schema_value(s))
+    schema.map(sanitize_schema_value)
 }
 
 fn sanitize_schema_value(value: serde_json::Value) -> serde_json::Value {
@@ -1644,18 +1180,111 @@ mod tests {
         assert_eq!(provider.name(), "modelhub");
     }
     
+    #[tokio::test]
+    async fn test_inline_remote_images() {
+        let server = httpmock::MockServer::start();
+        let image_mock = server.mock(|when, then| {
+            when.method(httpmock::Method::GET).path("/cat.png");
+            then.status(200)
+                .header("Content-Type", "image/png")
+                .body([0x89, 0x50, 0x4e, 0x47]);
+        });
+        
+        let mut request = OpenAIRequest {
+            messages: vec![OpenAIMessage {
+                role: "user".to_string(),
+                content: Some(OpenAIContent::Array(vec![
+                    OpenAIContentPart::ImageUrl {
+                        image_url: OpenAIImageUrl { url: server.url("/cat.png"), detail: None },
+                    },
+                    OpenAIContentPart::ImageUrl {
+                        image_url: OpenAIImageUrl { url: "data:image/gif;base64,R0lG".to_string(), detail: None },
+                    },
+                ])),
+                name: None,
+                tool_calls: None,
+                tool_call_id: None,
+            }],
+            ..Default::default()
+        };
+        
+        let urls = |request: &OpenAIRequest| -> Vec<String> {
+            let Some(OpenAIContent::Array(parts)) = &request.messages[0].content else {
+                panic!("Expected array content");
+            };
+            parts.iter().filter_map(|p| match p {
+                OpenAIContentPart::ImageUrl { image_url } => Some(image_url.url.clone()),
+                _ => None,
+            }).collect()
+        };
+        
+        // The mock server is on a loopback address, which isn't fetched by default
+        let provider = ModelHubProvider::new().unwrap();
+        let original = urls(&request);
+        provider.inline_remote_images(&mut request, &[]).await;
+        image_mock.assert_hits(0);
+        assert_eq!(urls(&request), original);
+        
+        provider.inline_remote_images(&mut request, &["127.0.0.1".to_string()]).await;
+        image_mock.assert();
+        assert_eq!(urls(&request), vec!["data:image/png;base64,iVBORw==", "data:image/gif;base64,R0lG"]);
+    }
+    
+    #[tokio::test]
+    async fn test_fetch_image_limits() {
+        let server = httpmock::MockServer::start();
+        server.mock(|when, then| {
+            when.method(httpmock::Method::GET).path("/page.html");
+            then.status(200).header("Content-Type", "text/html").body("<html></html>");
+        });
+        server.mock(|when, then| {
+            when.method(httpmock::Method::GET).path("/large.png");
+            then.status(200).header("Content-Type", "image/png").body(vec![0u8; 2048]);
+        });
+        server.mock(|when, then| {
+            when.method(httpmock::Method::GET).path("/moved.png");
+            then.status(302).header("Location", server.url("/large.png"));
+        });
+        
+        async fn fetch_error(url: &str, allowed_hosts: &[&str]) -> String {
+            let allowed_hosts: Vec<String> = allowed_hosts.iter().map(|host| host.to_string()).collect();
+            let provider = ModelHubProvider::new().unwrap();
+            provider.fetch_image_as_data_url(url, &allowed_hosts, 1024).await.unwrap_err().to_string()
+        }
+        
+        let local = ["127.0.0.1"];
+        assert_eq!(fetch_error(&server.url("/page.html"), &local).await, "Response is not an image");
+        assert_eq!(fetch_error(&server.url("/large.png"), &local).await, "Image is larger than 1024 bytes");
+        assert_eq!(fetch_error(&server.url("/moved.png"), &local).await, "Image download failed: 302 Found");
+        assert_eq!(fetch_error(&server.url("/large.png"), &[]).await, "Host 127.0.0.1 resolves to non-public address 127.0.0.1");
+        assert_eq!(fetch_error(&server.url("/large.png"), &["images.example.com"]).await, "Host 127.0.0.1 is not in imageFetchHosts");
+        assert_eq!(fetch_error("file:///etc/passwd", &[]).await, "Unsupported image URL scheme file");
+    }
+    
+    #[test]
+    fn test_is_public() {
+        for ip in ["8.8.8.8", "1.1.1.1", "2606:4700:4700::1111"] {
+            assert!(is_public(ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in ["127.0.0.1", "10.0.0.1", "172.16.0.1", "192.168.1.1", "169.254.169.254", "100.64.0.1", "0.0.0.0", "::1", "fd00::1", "fe80::1", "::ffff:127.0.0.1"] {
+            assert!(!is_public(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+    
     #[test]
     fn test_build_url_with_ak_param() {
         let provider = ModelHubProvider::new().unwrap();
         
         let mut config = ProviderConfig {
             provider_type: "modelhub".to_string(),
+            enabled: true,
             base_url: "https://modelhub.example.com".to_string(),
             api_key: "test-api-key".to_string(),
+            api_key_file: None,
             options: ProviderOptions {
                 api_key_param: Some("ak".to_string()),
                 mode: Some("responses".to_string()),
-                headers: Default::default(),
+                ..Default::default()
             },
             models: Default::default(),
         };
@@ -1739,12 +1368,14 @@ mod tests {
         
         let mut config = ProviderConfig {
             provider_type: "modelhub".to_string(),
+            enabled: true,
             base_url: "https://example.com".to_string(),
             api_key: "".to_string(),
+            api_key_file: None,
             options: ProviderOptions {
                 api_key_param: None,
                 mode: Some("gemini".to_string()),
-                headers: Default::default(),
+                ..Default::default()
             },
             models: Default::default(),
         };
@@ -2,20 +2,23 @@
 //!
 //! Standard OpenAI-compatible API provider
 
-use super::{BoxStream, Provider};
-use crate::config::{ModelConfig, ProviderConfig};
+use super::{legacy_functions, with_request_headers, BoxStream, Provider, ProviderError, UpstreamError};
+use crate::config::{ModelConfig, ProviderConfig, ProviderOptions};
 use crate::models::openai::*;
+use crate::services::{error_reports, http_client, reasoning};
+use crate::utils::sse;
 use anyhow::{Context, Result};
 use async_trait::async_trait;
+use futures::StreamExt;
 use reqwest::Client;
 use std::time::Duration;
-use tokio_stream::StreamExt;
 use tracing::{debug, error, warn};
 
 /// OpenAI Provider
 pub struct OpenAIProvider {
     client: Client,
-    stream_client: Client,
+    timeout: Duration,
+    stream_timeout: Duration,
 }
 
 impl OpenAIProvider {
@@ -26,19 +29,15 @@ impl OpenAIProvider {
     
     /// Create a new OpenAI provider with custom timeouts
     pub fn with_timeouts(timeout_secs: u64, stream_timeout_secs: u64) -> Result<Self> {
-        let client = Client::builder()
-            .timeout(Duration::from_secs(timeout_secs))
-            .user_agent("aiapiproxy/0.1.0")
-            .build()
-            .context("Failed to create HTTP client")?;
-        
-        let stream_client = Client::builder()
-            .timeout(Duration::from_secs(stream_timeout_secs))
-            .user_agent("aiapiproxy/0.1.0")
-            .build()
-            .context("Failed to create streaming HTTP client")?;
-        
-        Ok(Self { client, stream_client })
+        let client = http_client::global().client(&ProviderOptions::default())?;
+        Ok(Self::with_client(client, Duration::from_secs(timeout_secs), Duration::from_secs(stream_timeout_secs)))
+    }
+    
+    /// Create a new OpenAI provider on a shared client
+    ///
+    /// The timeouts are applied to each request.
+    pub fn with_client(client: Client, timeout: Duration, stream_timeout: Duration) -> Self {
+        Self { client, timeout, stream_timeout }
     }
     
     /// Build the request URL
@@ -57,34 +56,41 @@ impl OpenAIProvider {
         format!("Bearer {}", api_key)
     }
     
-    /// Parse SSE chunk from bytes
-    fn parse_sse_chunk(&self, chunk: &[u8]) -> Result<Option<OpenAIStreamResponse>> {
-        let chunk_str = std::str::from_utf8(chunk)
-            .context("Invalid UTF-8 data")?;
+    /// Build the request body, including extension parameters the model accepts
+    fn build_body(&self, request: &OpenAIRequest, model_config: &ModelConfig) -> Result<serde_json::Value> {
+        let mut fields = Vec::new();
+        if model_config.options.supports_top_k {
+            fields.push(("top_k", "top_k"));
+        }
+        if model_config.options.supports_metadata {
+            fields.push(("metadata", "metadata"));
+        }
+        // Set by the router only for models that take a reasoning effort
+        fields.push((reasoning::EXTENSION_KEY, "reasoning_effort"));
+        let mut body = request.to_json_with_extensions(&fields)
+            .context("Failed to serialize request")?;
         
-        for line in chunk_str.lines() {
-            if let Some(data) = line.strip_prefix("data: ") {
-                if data.trim() == "[DONE]" {
-                    debug!("Received streaming response end marker");
-                    return Ok(None);
-                }
-                
-                match serde_json::from_str::<OpenAIStreamResponse>(data) {
-                    Ok(stream_response) => {
-                        debug!("Successfully parsed streaming response chunk");
-                        return Ok(Some(stream_response));
-                    }
-                    Err(e) => {
-                        warn!("Failed to parse streaming response chunk: {} - data: {}", e, data);
-                    }
-                }
+        if let Some(web_search) = request.extensions.get("web_search") {
+            if model_config.options.supports_web_search {
+                body["web_search_options"] = web_search_options(web_search);
+            } else {
+                warn!("Model {} does not support web search, dropping web_search tool", model_config.name);
             }
         }
         
-        Ok(None)
+        if uses_legacy_functions(model_config) {
+            legacy_functions::convert_request(&mut body);
+        }
+        
+        Ok(body)
     }
 }
 
+/// Whether the model only supports the deprecated `functions` fields
+fn uses_legacy_functions(model_config: &ModelConfig) -> bool {
+    model_config.options.tools_format.as_deref() == Some(legacy_functions::FORMAT_LEGACY)
+}
+
 #[async_trait]
 impl Provider for OpenAIProvider {
     fn name(&self) -> &str {
@@ -96,7 +102,7 @@ impl Provider for OpenAIProvider {
         mut request: OpenAIRequest,
         provider_config: &ProviderConfig,
         model_config: &ModelConfig,
-    ) -> Result<OpenAIResponse> {
+    ) -> Result<OpenAIResponse, ProviderError> {
         debug!("Sending OpenAI chat completion request");
         
         // Override model name with provider's model name
@@ -112,12 +118,12 @@ impl Provider for OpenAIProvider {
         
         let url = self.build_url(provider_config);
         let auth = self.get_auth_header(provider_config);
+        let body = self.build_body(&request, model_config)?;
         
-        let response = self.client
-            .post(&url)
+        let response = with_request_headers(self.client.post(&url).timeout(self.timeout), &request)
             .header("Authorization", &auth)
             .header("Content-Type", "application/json")
-            .json(&request)
+            .json(&body)
             .send()
             .await
             .context("Failed to send request")?;
@@ -125,23 +131,23 @@ impl Provider for OpenAIProvider {
         let status = response.status();
         
         if status.is_success() {
-            let openai_response: OpenAIResponse = response
-                .json()
-                .await
+            let response_text = response.text().await.context("Failed to read OpenAI response")?;
+            let mut body: serde_json::Value = serde_json::from_str(&response_text)
+                .inspect_err(|e| error_reports::global().unparsable_response("OpenAI response", e, &response_text))
+                .context("Failed to parse OpenAI response")?;
+            if uses_legacy_functions(model_config) {
+                legacy_functions::convert_response(&mut body);
+            }
+            let openai_response: OpenAIResponse = serde_json::from_value(body)
+                .inspect_err(|e| error_reports::global().unparsable_response("OpenAI response", e, &response_text))
                 .context("Failed to parse OpenAI response")?;
             
             debug!("OpenAI request completed successfully");
             Ok(openai_response)
         } else {
-            let error_text = response.text().await.unwrap_or_default();
-            
-            if let Ok(error_response) = serde_json::from_str::<OpenAIErrorResponse>(&error_text) {
-                error!("OpenAI API error: {:?}", error_response.error);
-                anyhow::bail!("OpenAI API error: {}", error_response.error.message);
-            } else {
-                error!("OpenAI API request failed: {} - {}", status, error_text);
-                anyhow::bail!("OpenAI API request failed: {} - {}", status, error_text);
-            }
+            let upstream_error = UpstreamError::from_response("OpenAI", response).await;
+            error!("OpenAI API request failed: {} - {}", status, upstream_error.message());
+            Err(upstream_error.into())
         }
     }
     
@@ -150,7 +156,7 @@ impl Provider for OpenAIProvider {
         mut request: OpenAIRequest,
         provider_config: &ProviderConfig,
         model_config: &ModelConfig,
-    ) -> Result<BoxStream<'static, OpenAIStreamResponse>> {
+    ) -> Result<BoxStream<'static, OpenAIStreamResponse>, ProviderError> {
         debug!("Sending OpenAI streaming chat completion request");
         
         // Override model name with provider's model name
@@ -167,59 +173,55 @@ impl Provider for OpenAIProvider {
         
         let url = self.build_url(provider_config);
         let auth = self.get_auth_header(provider_config);
+        let body = self.build_body(&request, model_config)?;
         
-        let response = self.stream_client
-            .post(&url)
+        let response = with_request_headers(self.client.post(&url).timeout(self.stream_timeout), &request)
             .header("Authorization", &auth)
             .header("Content-Type", "application/json")
             .header("Accept", "text/event-stream")
-            .json(&request)
+            .json(&body)
             .send()
             .await
             .context("Failed to send streaming request")?;
         
         if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_default();
-            anyhow::bail!("OpenAI API request failed: {} - {}", status, error_text);
+            return Err(UpstreamError::from_response("OpenAI", response).await.into());
         }
         
-        let stream = response
-            .bytes_stream()
-            .filter_map(move |chunk_result| {
-                match chunk_result {
-                    Ok(chunk) => {
-                        match std::str::from_utf8(&chunk) {
-                            Ok(chunk_str) => {
-                                for line in chunk_str.lines() {
-                                    if let Some(data) = line.strip_prefix("data: ") {
-                                        if data.trim() == "[DONE]" {
-                                            return None;
-                                        }
-                                        
-                                        match serde_json::from_str::<OpenAIStreamResponse>(data) {
-                                            Ok(stream_response) => {
-                                                return Some(Ok(stream_response));
-                                            }
-                                            Err(e) => {
-                                                warn!("Failed to parse streaming response chunk: {}", e);
-                                            }
-                                        }
-                                    }
-                                }
-                                None
-                            }
-                            Err(e) => Some(Err(anyhow::anyhow!("Invalid UTF-8: {}", e))),
-                        }
-                    }
-                    Err(e) => Some(Err(anyhow::anyhow!("Stream error: {}", e))),
+        if uses_legacy_functions(model_config) {
+            let stream = sse::data_stream(response.bytes_stream()).filter_map(|payload| async move {
+                match payload {
+                    Ok(data) => legacy_functions::parse_chunk(&data).map(Ok),
+                    Err(e) => Some(Err(e)),
                 }
             });
+            return Ok(Box::pin(stream));
+        }
+        
+        let stream = sse::openai_chunk_stream(response.bytes_stream());
         
         Ok(Box::pin(stream))
     }
 }
 
+/// Build `web_search_options` from the Claude web search tool definition
+///
+/// Claude's `user_location` fields are nested under `approximate` in the OpenAI format.
+fn web_search_options(web_search: &serde_json::Value) -> serde_json::Value {
+    let mut options = serde_json::json!({});
+    if let Some(location) = web_search.get("user_location").and_then(|l| l.as_object()) {
+        let approximate: serde_json::Map<String, serde_json::Value> = location.iter()
+            .filter(|(key, _)| key.as_str() != "type")
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        options["user_location"] = serde_json::json!({
+            "type": "approximate",
+            "approximate": approximate,
+        });
+    }
+    options
+}
+
 impl Default for OpenAIProvider {
     fn default() -> Self {
         Self::new().expect("Failed to create default OpenAI provider")
@@ -236,6 +238,88 @@ mod tests {
         assert!(provider.is_ok());
     }
     
+    #[test]
+    fn test_build_body_top_k() {
+        let provider = OpenAIProvider::new().unwrap();
+        let mut request = OpenAIRequest::default();
+        request.extensions.insert("top_k".to_string(), serde_json::json!(20));
+        
+        let mut model_config = ModelConfig {
+            name: "llama3".to_string(),
+            enabled: true,
+            alias: None,
+            max_tokens: None,
+            context_window: None,
+            temperature: None,
+            options: Default::default(),
+        };
+        let body = provider.build_body(&request, &model_config).unwrap();
+        assert!(body.get("top_k").is_none());
+        
+        model_config.options.supports_top_k = true;
+        let body = provider.build_body(&request, &model_config).unwrap();
+        assert_eq!(body["top_k"], 20);
+    }
+    
+    #[test]
+    fn test_build_body_metadata() {
+        let provider = OpenAIProvider::new().unwrap();
+        let mut request = OpenAIRequest::default();
+        request.extensions.insert("metadata".to_string(), serde_json::json!({"team": "search"}));
+        
+        let mut model_config = ModelConfig::passthrough("gpt-4o");
+        let body = provider.build_body(&request, &model_config).unwrap();
+        assert!(body.get("metadata").is_none());
+        
+        model_config.options.supports_metadata = true;
+        let body = provider.build_body(&request, &model_config).unwrap();
+        assert_eq!(body["metadata"]["team"], "search");
+    }
+    
+    #[test]
+    fn test_build_body_reasoning_effort() {
+        let provider = OpenAIProvider::new().unwrap();
+        let mut request = OpenAIRequest::default();
+        let model_config = ModelConfig::passthrough("o3");
+        
+        let body = provider.build_body(&request, &model_config).unwrap();
+        assert!(body.get("reasoning_effort").is_none());
+        
+        request.extensions.insert(reasoning::EXTENSION_KEY.to_string(), serde_json::json!("medium"));
+        let body = provider.build_body(&request, &model_config).unwrap();
+        assert_eq!(body["reasoning_effort"], "medium");
+    }
+    
+    #[test]
+    fn test_build_body_web_search() {
+        let provider = OpenAIProvider::new().unwrap();
+        let mut request = OpenAIRequest::default();
+        request.extensions.insert("web_search".to_string(), serde_json::json!({
+            "type": "web_search_20250305",
+            "name": "web_search",
+            "user_location": {"type": "approximate", "city": "Paris", "country": "FR"}
+        }));
+        
+        let mut model_config = ModelConfig {
+            name: "gpt-4o-search-preview".to_string(),
+            enabled: true,
+            alias: None,
+            max_tokens: None,
+            context_window: None,
+            temperature: None,
+            options: Default::default(),
+        };
+        let body = provider.build_body(&request, &model_config).unwrap();
+        assert!(body.get("web_search_options").is_none());
+        
+        model_config.options.supports_web_search = true;
+        let body = provider.build_body(&request, &model_config).unwrap();
+        let location = &body["web_search_options"]["user_location"];
+        assert_eq!(location["type"], "approximate");
+        assert_eq!(location["approximate"]["city"], "Paris");
+        assert!(location["approximate"].get("type").is_none());
+    }
+    
     #[test]
     fn test_provider_name() {
         let provider = OpenAIProvider::new().unwrap();
@@ -248,8 +332,10 @@ mod tests {
         
         let config = ProviderConfig {
             provider_type: "openai".to_string(),
+            enabled: true,
             base_url: "https://api.openai.com/v1".to_string(),
             api_key: "".to_string(),
+            api_key_file: None,
             options: Default::default(),
             models: Default::default(),
         };
@@ -260,8 +346,10 @@ mod tests {
         // Test with trailing slash
         let config2 = ProviderConfig {
             provider_type: "openai".to_string(),
+            enabled: true,
             base_url: "https://api.openai.com/v1/".to_string(),
             api_key: "".to_string(),
+            api_key_file: None,
             options: Default::default(),
             models: Default::default(),
         };
@@ -269,19 +357,4 @@ mod tests {
         let url2 = provider.build_url(&config2);
         assert_eq!(url2, "https://api.openai.com/v1/chat/completions");
     }
-    
-    #[test]
-    fn test_parse_sse_chunk() {
-        let provider = OpenAIProvider::new().unwrap();
-        
-        // Test normal SSE data
-        let sse_data = b"data: {\"id\":\"test\",\"object\":\"chat.completion.chunk\",\"created\":1234567890,\"model\":\"gpt-4\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Hello\"},\"finish_reason\":null}]}\n\n";
-        let result = provider.parse_sse_chunk(sse_data).unwrap();
-        assert!(result.is_some());
-        
-        // Test end marker
-        let done_data = b"data: [DONE]\n\n";
-        let result = provider.parse_sse_chunk(done_data).unwrap();
-        assert!(result.is_none());
-    }
 }
@@ -0,0 +1,1276 @@
+//! Shared Responses API support
+//!
+//! Request/response structures, conversion and SSE stream handling used by all
+//! providers that speak the Responses API (Ark, ModelHub). Providers only add
+//! their URL, authentication and headers.
+
+use super::{images, BoxStream, ProviderError};
+use crate::config::{ModelConfig, OrphanToolCalls};
+use crate::models::openai::*;
+use crate::models::openai::null_as_default;
+use crate::services::{error_reports, reasoning};
+use crate::utils::logging::{create_raw_log, filter_payload};
+use crate::utils::sse::SseDecoder;
+use anyhow::{Context, Result};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashSet;
+use tracing::{debug, error, warn};
+
+// ====== Responses API Structures ======
+
+/// Responses API request format
+#[derive(Debug, Serialize)]
+pub(crate) struct ResponsesApiRequest {
+    pub model: String,
+    /// Input can contain various types:
+    /// - Messages: { role: "user"|"assistant"|"system", content: [...] }
+    /// - Function calls: { type: "function_call", call_id, name, arguments }
+    /// - Function results: { type: "function_call_output", call_id, output }
+    pub input: Vec<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_output_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<Value>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instructions: Option<String>,
+    /// Text output configuration (structured output format)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<Value>,
+    /// Request metadata (for models with `supportsMetadata`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<Value>,
+    /// Reasoning configuration (`{"effort": "low"|"medium"|"high"}`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reasoning: Option<Value>,
+}
+
+/// Responses API response format
+#[derive(Debug, Deserialize)]
+#[allow(dead_code)]
+pub(crate) struct ResponsesApiResponse {
+    id: String,
+    #[serde(default)]
+    model: Option<String>,
+    output: Vec<ResponsesOutput>,
+    #[serde(default)]
+    usage: Option<ResponsesUsage>,
+    status: String,
+    // Additional fields that may be present but we don't need
+    #[serde(default)]
+    created_at: Option<u64>,
+    #[serde(default)]
+    incomplete_details: Option<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+#[allow(dead_code)]
+struct ResponsesOutput {
+    #[serde(rename = "type")]
+    output_type: String,
+    #[serde(default)]
+    content: Option<Vec<ResponsesContent>>,
+    #[serde(default)]
+    id: Option<String>,
+    #[serde(default)]
+    role: Option<String>,
+    #[serde(default)]
+    status: Option<String>,
+    // For tool_use output
+    #[serde(default)]
+    call_id: Option<String>,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    arguments: Option<String>,
+    // For reasoning output
+    #[serde(default)]
+    summary: Option<Vec<Value>>,
+    // For web_search_call output
+    #[serde(default)]
+    action: Option<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ResponsesContent {
+    #[serde(rename = "type")]
+    content_type: String,
+    #[serde(default)]
+    text: Option<String>,
+    #[serde(default)]
+    annotations: Vec<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ResponsesUsage {
+    #[serde(default, deserialize_with = "null_as_default")]
+    input_tokens: u32,
+    #[serde(default, deserialize_with = "null_as_default")]
+    output_tokens: u32,
+    #[serde(default)]
+    total_tokens: Option<u32>,
+    #[serde(default)]
+    input_tokens_details: Option<ResponsesInputTokensDetails>,
+    #[serde(default)]
+    output_tokens_details: Option<ResponsesOutputTokensDetails>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ResponsesInputTokensDetails {
+    #[serde(default, deserialize_with = "null_as_default")]
+    cached_tokens: u32,
+}
+
+#[derive(Debug, Deserialize)]
+struct ResponsesOutputTokensDetails {
+    #[serde(default, deserialize_with = "null_as_default")]
+    reasoning_tokens: u32,
+}
+
+/// Provider-specific shape of input items
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct InputItemFormat {
+    /// Tag items with `type: "message"` and mark history as completed
+    /// (`status: "completed"`, `partial: false`), as required by Ark
+    pub completed_status: bool,
+}
+
+impl InputItemFormat {
+    /// Add the item type and completion markers to an input item
+    fn apply(&self, mut item: Value) -> Value {
+        if self.completed_status {
+            if item.get("type").is_none() {
+                item["type"] = Value::String("message".to_string());
+            }
+            item["status"] = Value::String("completed".to_string());
+            item["partial"] = Value::Bool(false);
+        }
+        item
+    }
+}
+
+/// Create a filtered version of Responses API request for logging
+pub(crate) fn create_log_request(request: &ResponsesApiRequest) -> Option<String> {
+    filter_payload(request, |request| {
+        serde_json::json!({
+            "model": request.model,
+            "max_output_tokens": request.max_output_tokens,
+            "temperature": request.temperature,
+            "stream": request.stream,
+            "input_count": request.input.len(),
+            "tools_count": request.tools.as_ref().map(|t| t.len()).unwrap_or(0),
+            "tools": "[omitted]",
+            "instructions": "[omitted]",
+        })
+    })
+}
+
+/// Convert an OpenAI response_format into the Responses API `text` field
+///
+/// Chat completions nest the schema under `json_schema`; the Responses API
+/// flattens it into `text.format`.
+fn text_format(format: &OpenAIResponseFormat) -> Value {
+    let mut text_format = serde_json::json!({ "type": format.format_type });
+    if let Some(schema) = &format.json_schema {
+        text_format["name"] = Value::String(schema.name.clone());
+        text_format["schema"] = schema.schema.clone();
+        if let Some(description) = &schema.description {
+            text_format["description"] = Value::String(description.clone());
+        }
+        if let Some(strict) = schema.strict {
+            text_format["strict"] = Value::Bool(strict);
+        }
+    }
+    serde_json::json!({ "format": text_format })
+}
+
+/// Convert an OpenAI chat request to the Responses API format
+///
+/// Responses API uses a different structure than chat completions:
+/// - User and assistant messages carry content blocks
+/// - Tool calls are separate "function_call" items
+/// - Tool results are "function_call_output" items (NOT role: "tool")
+///
+/// Tool calls without a result are handled according to `orphans`; with
+/// [`OrphanToolCalls::Error`] they fail the conversion.
+pub(crate) fn convert_request(
+    request: &OpenAIRequest,
+    model_config: &ModelConfig,
+    format: InputItemFormat,
+    orphans: OrphanToolCalls,
+) -> Result<ResponsesApiRequest, ProviderError> {
+    let mut input: Vec<Value> = Vec::new();
+    let mut system_instructions: Option<String> = None;
+    
+    // First pass: collect all tool result call_ids
+    // Every function_call needs a matching function_call_output, but Claude Code
+    // may send incomplete tool call sequences (user can interrupt)
+    let tool_result_ids: HashSet<&str> = request.messages.iter()
+        .filter(|msg| msg.role == "tool")
+        .filter_map(|msg| msg.tool_call_id.as_deref())
+        .collect();
+    
+    // Debug: log message roles and tool info
+    for (i, msg) in request.messages.iter().enumerate() {
+        let has_tool_calls = msg.tool_calls.as_ref().map(|t| t.len()).unwrap_or(0);
+        let tool_call_id = msg.tool_call_id.as_deref().unwrap_or("none");
+        debug!("Message {}: role={}, has_tool_calls={}, tool_call_id={}", 
+               i, msg.role, has_tool_calls, tool_call_id);
+    }
+    
+    for msg in &request.messages {
+        match msg.role.as_str() {
+            // Extract system message as instructions
+            "system" => {
+                if let Some(content) = &msg.content {
+                    push_system_message(content, &mut system_instructions, &mut input);
+                }
+            }
+            // Handle tool role -> function_call_output
+            "tool" => {
+                let Some(tool_call_id) = &msg.tool_call_id else {
+                    warn!("Tool message without tool_call_id, skipping");
+                    continue;
+                };
+                let output = msg.content.as_ref()
+                    .map(|c| c.extract_text())
+                    .unwrap_or_default();
+                debug!("Adding function_call_output with call_id={}", tool_call_id);
+                input.push(format.apply(serde_json::json!({
+                    "type": "function_call_output",
+                    "call_id": tool_call_id,
+                    "output": output
+                })));
+            }
+            // Handle assistant -> output_text message and function_call items
+            "assistant" => {
+                // Text goes before the calls, as in Responses API output, so it
+                // doesn't break the function_call/function_call_output sequence
+                let text = msg.content.as_ref().map(|c| c.extract_text()).unwrap_or_default();
+                if !text.is_empty() {
+                    input.push(format.apply(serde_json::json!({
+                        "role": "assistant",
+                        "content": [{ "type": "output_text", "text": text }]
+                    })));
+                }
+                
+                for tc in msg.tool_calls.iter().flatten() {
+                    let Some(id) = &tc.id else {
+                        warn!("Tool call without id, skipping");
+                        continue;
+                    };
+                    let orphan = !tool_result_ids.contains(id.as_str());
+                    match orphans {
+                        OrphanToolCalls::Drop if orphan => {
+                            warn!("Skipping orphan function_call with call_id={} (no matching output)", id);
+                            continue;
+                        }
+                        OrphanToolCalls::Error if orphan => {
+                            return Err(ProviderError::InvalidRequest(format!(
+                                "`tool_use` ids were found without `tool_result` blocks immediately after: {}. Each `tool_use` block must have a corresponding `tool_result` block in the next message.",
+                                id
+                            )));
+                        }
+                        _ => {}
+                    }
+                    debug!("Adding function_call with call_id={}, name={:?}", id, tc.function.name);
+                    input.push(format.apply(serde_json::json!({
+                        "type": "function_call",
+                        "call_id": id,
+                        "name": tc.function.name,
+                        "arguments": tc.function.arguments.clone().unwrap_or_default()
+                    })));
+                    if orphan {
+                        debug!("Adding empty function_call_output for orphan call_id={}", id);
+                        input.push(format.apply(serde_json::json!({
+                            "type": "function_call_output",
+                            "call_id": id,
+                            "output": ""
+                        })));
+                    }
+                }
+            }
+            // Handle user messages
+            "user" => {
+                let content: Vec<Value> = match &msg.content {
+                    Some(OpenAIContent::Text(text)) => {
+                        vec![serde_json::json!({ "type": "input_text", "text": text })]
+                    }
+                    Some(OpenAIContent::Array(parts)) => parts.iter().map(|p| match p {
+                        OpenAIContentPart::Text { text, .. } => {
+                            serde_json::json!({ "type": "input_text", "text": text })
+                        }
+                        OpenAIContentPart::ImageUrl { image_url } => input_image(image_url, model_config),
+                    }).collect(),
+                    None => vec![serde_json::json!({ "type": "input_text", "text": "" })],
+                };
+                
+                input.push(format.apply(serde_json::json!({
+                    "role": "user",
+                    "content": content
+                })));
+            }
+            other => warn!("Skipping message with unsupported role: {}", other),
+        }
+    }
+    
+    // Convert tools to Responses API format
+    // OpenAI chat format: { type: "function", function: { name, description, parameters } }
+    // Responses API format: { type: "function", name, description, parameters }
+    let mut tools: Option<Vec<Value>> = request.tools.as_ref().map(|t| {
+        t.iter().map(|tool| {
+            serde_json::json!({
+                "type": "function",
+                "name": tool.function.name,
+                "description": tool.function.description,
+                "parameters": tool.function.parameters
+            })
+        }).collect()
+    });
+    push_web_search_tool(request, model_config, &mut tools);
+    
+    // Ensure max_output_tokens is reasonable
+    // Take the max of request and config values to avoid Claude Code's low default (e.g., 1)
+    let max_output_tokens = match (request.max_tokens, model_config.max_tokens) {
+        (Some(req), Some(cfg)) => Some(req.max(cfg)),
+        (Some(req), None) => Some(req.max(8192)), // default minimum for Codex
+        (None, Some(cfg)) => Some(cfg),
+        (None, None) => Some(8192),
+    };
+    debug!("📊 Responses API max_output_tokens: request={:?}, config={:?}, final={:?}",
+           request.max_tokens, model_config.max_tokens, max_output_tokens);
+    
+    // Only include temperature if the model supports it
+    // Reasoning models (o1, o3, etc.) don't support temperature
+    let temperature = if model_config.options.supports_temperature {
+        request.temperature.or(model_config.temperature)
+    } else {
+        debug!("📊 Model {} does not support temperature, skipping parameter", model_config.name);
+        None
+    };
+    
+    Ok(ResponsesApiRequest {
+        model: model_config.name.clone(),
+        input,
+        max_output_tokens,
+        temperature,
+        stream: None,
+        tools,
+        instructions: system_instructions,
+        text: request.response_format.as_ref().map(text_format),
+        metadata: request.extensions.get("metadata")
+            .filter(|_| model_config.options.supports_metadata)
+            .cloned(),
+        reasoning: request.extensions.get(reasoning::EXTENSION_KEY)
+            .map(|effort| serde_json::json!({ "effort": effort })),
+    })
+}
+
+/// Parse a successful Responses API response body and convert it to OpenAI format
+pub(crate) fn parse_response(response_text: &str) -> Result<OpenAIResponse> {
+    if let Some(response_log) = create_raw_log(response_text, 1000) {
+        debug!("📥 Responses API Raw Response:\n{}", response_log);
+    }
+    
+    let response: ResponsesApiResponse = serde_json::from_str(response_text)
+        .inspect_err(|e| error_reports::global().unparsable_response("Responses API response", e, response_text))
+        .with_context(|| {
+            error!("Failed to parse Responses API response. Raw response:\n{}", 
+                   if response_text.len() > 2000 { &response_text[..2000] } else { response_text });
+            "Failed to parse Responses API response"
+        })?;
+    
+    Ok(convert_response(response))
+}
+
+/// Convert a Responses API response to OpenAI format
+pub(crate) fn convert_response(response: ResponsesApiResponse) -> OpenAIResponse {
+    let mut content_text = String::new();
+    let mut tool_calls: Vec<OpenAIToolCall> = Vec::new();
+    let mut search_queries: Vec<String> = Vec::new();
+    let mut search_results = Vec::new();
+    
+    for output in &response.output {
+        match output.output_type.as_str() {
+            "message" => {
+                for c in output.content.iter().flatten() {
+                    if c.content_type == "output_text" {
+                        if let Some(text) = &c.text {
+                            content_text.push_str(text);
+                        }
+                        collect_url_citations(&c.annotations, &mut search_results);
+                    }
+                }
+            },
+            "function_call" | "tool_use" => {
+                if let (Some(name), Some(arguments)) = (&output.name, &output.arguments) {
+                    tool_calls.push(OpenAIToolCall {
+                        index: None,
+                        id: output.call_id.clone(),
+                        tool_type: Some("function".to_string()),
+                        function: OpenAIFunctionCall {
+                            name: Some(name.clone()),
+                            arguments: Some(arguments.clone()),
+                        },
+                        signature: None,
+                        extra_content: None,
+                    });
+                }
+            },
+            "web_search_call" => {
+                if let Some(query) = web_search_query(output.action.as_ref()) {
+                    search_queries.push(query);
+                }
+            },
+            "reasoning" => {
+                // Reasoning is internal to the model; only its summary is logged
+                let summary = reasoning_summary(output.summary.as_deref().unwrap_or_default());
+                debug!("Responses API: got reasoning output ({} chars of summary)", summary.len());
+            },
+            other => {
+                debug!("Responses API: ignoring unknown output type: {}", other);
+            }
+        }
+    }
+    
+    let incomplete_reason = response.incomplete_details.as_ref()
+        .and_then(|details| details.get("reason"))
+        .and_then(|reason| reason.as_str());
+    let finish_reason = finish_reason(&response.status, incomplete_reason, !tool_calls.is_empty());
+    
+    // Build choice
+    let choice = OpenAIChoice {
+        index: 0,
+        message: OpenAIMessage {
+            role: "assistant".to_string(),
+            content: if content_text.is_empty() { None } else { Some(OpenAIContent::Text(content_text)) },
+            tool_calls: if tool_calls.is_empty() { None } else { Some(tool_calls) },
+            tool_call_id: None,
+            name: None,
+        },
+        logprobs: None,
+        finish_reason: Some(finish_reason.to_string()),
+    };
+    
+    let usage = response.usage.map(|u| {
+        if let Some(details) = &u.output_tokens_details {
+            debug!("📊 Responses API reasoning tokens: {}", details.reasoning_tokens);
+        }
+        OpenAIUsage {
+            prompt_tokens: u.input_tokens,
+            completion_tokens: u.output_tokens,
+            total_tokens: u.total_tokens.unwrap_or(u.input_tokens + u.output_tokens),
+            prompt_tokens_details: u.input_tokens_details.map(|details| PromptTokensDetails { cached_tokens: details.cached_tokens }),
+        }
+    });
+    
+    OpenAIResponse {
+        id: response.id,
+        object: "chat.completion".to_string(),
+        created: response.created_at.unwrap_or(0),
+        model: response.model.unwrap_or_default(),
+        choices: vec![choice],
+        usage,
+        system_fingerprint: None,
+        search_results: (!search_results.is_empty()).then_some(search_results),
+        search_queries: (!search_queries.is_empty()).then_some(search_queries),
+    }
+}
+
+/// Convert an image part to an `input_image` item
+///
+/// Images the model can't take (unsupported type, over `maxImageBytes`) are
+/// replaced by a text note so the model knows an image was there.
+fn input_image(image_url: &OpenAIImageUrl, model_config: &ModelConfig) -> Value {
+    let options = &model_config.options;
+    match images::prepare_image_url(&image_url.url, options.max_image_bytes) {
+        Ok(url) => {
+            let mut item = serde_json::json!({ "type": "input_image", "image_url": url });
+            if let Some(detail) = options.image_detail.as_ref().or(image_url.detail.as_ref()) {
+                item["detail"] = serde_json::json!(detail);
+            }
+            item
+        }
+        Err(reason) => {
+            warn!("Dropping image for {}: {}", model_config.name, reason);
+            serde_json::json!({ "type": "input_text", "text": format!("[Image omitted: {}]", reason) })
+        }
+    }
+}
+
+/// Map a response status to an OpenAI finish_reason
+///
+/// Incomplete responses report why they stopped in `incomplete_details.reason`
+/// (`max_output_tokens` or `content_filter`); a truncated response takes
+/// precedence over tool calls, whose arguments may be cut off.
+fn finish_reason(status: &str, incomplete_reason: Option<&str>, has_tool_calls: bool) -> &'static str {
+    match (status, incomplete_reason) {
+        ("incomplete", Some("content_filter")) => "content_filter",
+        ("incomplete", _) => "length",
+        _ if has_tool_calls => "tool_calls",
+        _ => "stop",
+    }
+}
+
+/// Join the text of reasoning summary parts
+fn reasoning_summary(summary: &[Value]) -> String {
+    summary.iter()
+        .filter_map(|part| part.get("text").and_then(|t| t.as_str()))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Convert a streaming Responses API response into OpenAI stream chunks
+pub(crate) fn into_stream(response: reqwest::Response) -> BoxStream<'static, OpenAIStreamResponse> {
+    let mut parser = StreamParser::default();
+    
+    let stream = response
+        .bytes_stream()
+        .map(move |chunk_result| match chunk_result {
+            Ok(chunk) => parser.push(&chunk).into_iter().map(Ok).collect::<Vec<_>>(),
+            Err(e) => vec![Err(anyhow::anyhow!("Stream error: {}", e))],
+        })
+        .flat_map(futures::stream::iter);
+    
+    Box::pin(stream)
+}
+
+/// Incremental parser for Responses API SSE streams
+///
+/// Decodes SSE lines with [`SseDecoder`] and turns Responses API events into
+/// OpenAI chat completion chunks. Function calls are numbered in the order
+/// they start so their argument deltas can be matched by `output_index`.
+#[derive(Debug, Default)]
+pub struct StreamParser {
+    decoder: SseDecoder,
+    role_sent: bool,
+    /// `output_index` of each streamed function call, in tool call order
+    tool_outputs: Vec<u64>,
+}
+
+impl StreamParser {
+    /// Feed a chunk of bytes, returning the converted chunks for all complete lines
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<OpenAIStreamResponse> {
+        let payloads = self.decoder.push(chunk);
+        payloads.iter().filter_map(|data| self.parse_data(data)).collect()
+    }
+    
+    /// Parse a single SSE `data:` payload
+    fn parse_data(&mut self, data: &str) -> Option<OpenAIStreamResponse> {
+        let data = data.trim();
+        
+        if data == "[DONE]" {
+            debug!("📡 SSE: received [DONE]");
+            return None;
+        }
+        
+        // JSON parsing failures are skipped
+        let event: Value = serde_json::from_str(data).ok()?;
+        let event_type = event.get("type").and_then(|t| t.as_str()).unwrap_or("unknown");
+        debug!("📡 SSE event: {}", event_type);
+        
+        match event_type {
+            // Response start - send role to initialize the stream
+            "response.created" | "response.in_progress" => {
+                if self.role_sent {
+                    return None;
+                }
+                self.role_sent = true;
+                Some(Self::chunk(OpenAIStreamDelta {
+                    role: Some("assistant".to_string()),
+                    content: None,
+                    tool_calls: None,
+                }, None))
+            }
+            // Text delta - this is the main content event
+            "response.output_text.delta" => {
+                let delta = event.get("delta").and_then(|d| d.as_str())?;
+                debug!("📡 Text delta: {} chars", delta.len());
+                Some(self.chunk_with_role(delta_text(delta), None))
+            }
+            // Function call output item added - this starts a tool call
+            "response.output_item.added" => {
+                let item = event.get("item")?;
+                if item.get("type").and_then(|t| t.as_str()) != Some("function_call") {
+                    return None;
+                }
+                let call_id = item.get("call_id").and_then(|c| c.as_str()).unwrap_or("");
+                let name = item.get("name").and_then(|n| n.as_str()).unwrap_or("");
+                let index = self.tool_outputs.len() as u32;
+                self.tool_outputs.push(output_index(&event).unwrap_or(index as u64));
+                debug!("📡 Function call start: name={}, call_id={}, index={}", name, call_id, index);
+                Some(self.chunk_with_role(delta_tool_call(OpenAIToolCall {
+                    index: Some(index),
+                    id: Some(call_id.to_string()),
+                    tool_type: Some("function".to_string()),
+                    function: OpenAIFunctionCall {
+                        name: Some(name.to_string()),
+                        arguments: Some(String::new()),
+                    },
+                    signature: None,
+                    extra_content: None,
+                }), None))
+            }
+            // Function call arguments delta
+            "response.function_call_arguments.delta" => {
+                let delta = event.get("delta").and_then(|d| d.as_str())?;
+                let Some(index) = self.tool_index(&event) else {
+                    debug!("📡 Skipping function args delta without a started call");
+                    return None;
+                };
+                debug!("📡 Function args delta: {} chars, index={}", delta.len(), index);
+                Some(Self::chunk(delta_tool_call(OpenAIToolCall {
+                    index: Some(index),
+                    id: None,
+                    tool_type: None,
+                    function: OpenAIFunctionCall { name: None, arguments: Some(delta.to_string()) },
+                    signature: None,
+                    extra_content: None,
+                }), None))
+            }
+            // Per-item completion; the stream finishes on response.completed
+            "response.function_call_arguments.done" | "response.output_text.done" => {
+                debug!("📡 Output item done");
+                None
+            }
+            // Reasoning summaries are not forwarded
+            "response.reasoning_summary_text.delta" | "response.reasoning_summary_text.done" => {
+                debug!("📡 Skipping reasoning summary event");
+                None
+            }
+            "response.completed" | "response.done" | "response.incomplete" => {
+                let status = event.pointer("/response/status")
+                    .and_then(|s| s.as_str())
+                    .unwrap_or(if event_type == "response.incomplete" { "incomplete" } else { "completed" });
+                let incomplete_reason = event.pointer("/response/incomplete_details/reason")
+                    .and_then(|r| r.as_str());
+                let finish_reason = finish_reason(status, incomplete_reason, !self.tool_outputs.is_empty());
+                debug!("📡 Stream completed event, finish_reason={}", finish_reason);
+                
+                // Include an empty entry per tool call so the converter closes every tool block
+                let delta = if self.tool_outputs.is_empty() {
+                    delta_text("")
+                } else {
+                    OpenAIStreamDelta {
+                        role: None,
+                        content: None,
+                        tool_calls: Some((0..self.tool_outputs.len() as u32)
+                            .map(|index| OpenAIToolCall {
+                                index: Some(index),
+                                id: None,
+                                tool_type: None,
+                                function: OpenAIFunctionCall { name: None, arguments: None },
+                                signature: None,
+                                extra_content: None,
+                            })
+                            .collect()),
+                    }
+                };
+                let mut chunk = self.chunk_with_role(delta, Some(finish_reason));
+                
+                // Web searches are only listed in the final response
+                let mut search_queries = Vec::new();
+                let mut search_results = Vec::new();
+                let outputs = event.pointer("/response/output").and_then(|o| o.as_array());
+                for output in outputs.into_iter().flatten() {
+                    match output.get("type").and_then(|t| t.as_str()) {
+                        Some("web_search_call") => search_queries.extend(web_search_query(output.get("action"))),
+                        Some("message") => {
+                            let contents = output.get("content").and_then(|c| c.as_array());
+                            for annotations in contents.into_iter().flatten().filter_map(|c| c.get("annotations")?.as_array()) {
+                                collect_url_citations(annotations, &mut search_results);
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                chunk.search_results = (!search_results.is_empty()).then_some(search_results);
+                chunk.search_queries = (!search_queries.is_empty()).then_some(search_queries);
+                Some(chunk)
+            }
+            // Skip other event types silently
+            _ => None,
+        }
+    }
+    
+    /// Tool call index for an event, by `output_index` or the most recent call
+    fn tool_index(&self, event: &Value) -> Option<u32> {
+        output_index(event)
+            .and_then(|output_index| self.tool_outputs.iter().position(|&o| o == output_index))
+            .or_else(|| self.tool_outputs.len().checked_sub(1))
+            .map(|position| position as u32)
+    }
+    
+    /// Build a chunk, attaching the assistant role if it hasn't been sent yet
+    fn chunk_with_role(&mut self, mut delta: OpenAIStreamDelta, finish_reason: Option<&str>) -> OpenAIStreamResponse {
+        if !self.role_sent {
+            self.role_sent = true;
+            delta.role = Some("assistant".to_string());
+        }
+        Self::chunk(delta, finish_reason)
+    }
+    
+    fn chunk(delta: OpenAIStreamDelta, finish_reason: Option<&str>) -> OpenAIStreamResponse {
+        OpenAIStreamResponse {
+            id: String::new(),
+            object: "chat.completion.chunk".to_string(),
+            created: 0,
+            model: String::new(),
+            system_fingerprint: None,
+            search_results: None,
+            search_queries: None,
+            choices: vec![OpenAIStreamChoice {
+                index: 0,
+                delta,
+                logprobs: None,
+                finish_reason: finish_reason.map(|f| f.to_string()),
+            }],
+        }
+    }
+}
+
+fn output_index(event: &Value) -> Option<u64> {
+    event.get("output_index").and_then(|i| i.as_u64())
+}
+
+fn delta_text(text: &str) -> OpenAIStreamDelta {
+    OpenAIStreamDelta {
+        role: None,
+        content: (!text.is_empty()).then(|| text.to_string()),
+        tool_calls: None,
+    }
+}
+
+fn delta_tool_call(tool_call: OpenAIToolCall) -> OpenAIStreamDelta {
+    OpenAIStreamDelta {
+        role: None,
+        content: None,
+        tool_calls: Some(vec![tool_call]),
+    }
+}
+
+/// Add a system message to a Responses API request
+///
+/// Plain system prompts go into `instructions`; multiple system messages are
+/// kept in order, separated by blank lines. `instructions` is a plain string,
+/// so prompts whose blocks carry `cache_control` markers are sent as a system
+/// input message instead, with one `input_text` part per block.
+pub(crate) fn push_system_message(
+    content: &OpenAIContent,
+    instructions: &mut Option<String>,
+    input: &mut Vec<Value>,
+) {
+    if let OpenAIContent::Array(parts) = content {
+        let has_cache_control = parts.iter().any(|part| {
+            matches!(part, OpenAIContentPart::Text { cache_control: Some(_), .. })
+        });
+        
+        if has_cache_control {
+            let content_parts: Vec<Value> = parts.iter()
+                .filter_map(|part| match part {
+                    OpenAIContentPart::Text { text, cache_control } => {
+                        let mut item = serde_json::json!({ "type": "input_text", "text": text });
+                        if let Some(cache_control) = cache_control {
+                            item["cache_control"] = cache_control.clone();
+                        }
+                        Some(item)
+                    }
+                    _ => None,
+                })
+                .collect();
+            
+            input.push(serde_json::json!({
+                "type": "message",
+                "role": "system",
+                "content": content_parts,
+            }));
+            return;
+        }
+    }
+    
+    let text = match content {
+        OpenAIContent::Text(text) => text.clone(),
+        OpenAIContent::Array(parts) => parts.iter()
+            .filter_map(|part| match part {
+                OpenAIContentPart::Text { text, .. } => Some(text.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+    };
+    
+    match instructions {
+        Some(existing) => {
+            existing.push_str("\n\n");
+            existing.push_str(&text);
+        }
+        None => *instructions = Some(text),
+    }
+}
+
+/// Add the native `web_search` tool when the request declares the Claude web search tool
+///
+/// Claude's `allowed_domains` maps to `filters.allowed_domains`; `user_location`
+/// has the same shape in both APIs.
+pub(crate) fn push_web_search_tool(
+    request: &OpenAIRequest,
+    model_config: &ModelConfig,
+    tools: &mut Option<Vec<Value>>,
+) {
+    let Some(web_search) = request.extensions.get("web_search") else {
+        return;
+    };
+    if !model_config.options.supports_web_search {
+        warn!("Model {} does not support web search, dropping web_search tool", model_config.name);
+        return;
+    }
+    
+    let mut tool = serde_json::json!({ "type": "web_search" });
+    if let Some(domains) = web_search.get("allowed_domains") {
+        tool["filters"] = serde_json::json!({ "allowed_domains": domains });
+    }
+    if let Some(location) = web_search.get("user_location") {
+        tool["user_location"] = location.clone();
+    }
+    tools.get_or_insert_with(Vec::new).push(tool);
+}
+
+/// Get the query of a `web_search_call` output item
+pub(crate) fn web_search_query(action: Option<&Value>) -> Option<String> {
+    action?.get("query")?.as_str().map(|q| q.to_string())
+}
+
+/// Collect `url_citation` annotations of an output text as search results
+///
+/// Results are deduplicated by URL across all text parts of the response.
+pub(crate) fn collect_url_citations(annotations: &[Value], results: &mut Vec<OpenAISearchResult>) {
+    for annotation in annotations {
+        if annotation.get("type").and_then(|t| t.as_str()) != Some("url_citation") {
+            continue;
+        }
+        let Some(url) = annotation.get("url").and_then(|u| u.as_str()) else {
+            continue;
+        };
+        if results.iter().any(|r| r.url == url) {
+            continue;
+        }
+        results.push(OpenAISearchResult {
+            url: url.to_string(),
+            title: annotation.get("title").and_then(|t| t.as_str()).map(|t| t.to_string()),
+            date: None,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_push_system_message_instructions() {
+        let mut instructions = None;
+        let mut input = Vec::new();
+        
+        push_system_message(&OpenAIContent::Text("First".to_string()), &mut instructions, &mut input);
+        push_system_message(&OpenAIContent::Text("Second".to_string()), &mut instructions, &mut input);
+        
+        assert_eq!(instructions.as_deref(), Some("First\n\nSecond"));
+        assert!(input.is_empty());
+    }
+    
+    #[test]
+    fn test_push_system_message_cache_control() {
+        let mut instructions = None;
+        let mut input = Vec::new();
+        
+        let content = OpenAIContent::Array(vec![
+            OpenAIContentPart::Text { text: "Static".to_string(), cache_control: Some(serde_json::json!({"type": "ephemeral"})) },
+            OpenAIContentPart::Text { text: "Dynamic".to_string(), cache_control: None },
+        ]);
+        push_system_message(&content, &mut instructions, &mut input);
+        
+        assert!(instructions.is_none());
+        assert_eq!(input.len(), 1);
+        assert_eq!(input[0]["role"], "system");
+        assert_eq!(input[0]["content"][0]["text"], "Static");
+        assert_eq!(input[0]["content"][0]["cache_control"]["type"], "ephemeral");
+        assert_eq!(input[0]["content"][1]["text"], "Dynamic");
+        assert!(input[0]["content"][1].get("cache_control").is_none());
+    }
+    
+    #[test]
+    fn test_push_web_search_tool() {
+        let mut request = OpenAIRequest::default();
+        request.extensions.insert("web_search".to_string(), serde_json::json!({
+            "type": "web_search_20250305",
+            "name": "web_search",
+            "allowed_domains": ["docs.rs"]
+        }));
+        let mut model_config = ModelConfig::passthrough("gpt-5");
+        
+        let mut tools = None;
+        push_web_search_tool(&request, &model_config, &mut tools);
+        assert!(tools.is_none());
+        
+        model_config.options.supports_web_search = true;
+        push_web_search_tool(&request, &model_config, &mut tools);
+        let tools = tools.unwrap();
+        assert_eq!(tools[0]["type"], "web_search");
+        assert_eq!(tools[0]["filters"]["allowed_domains"][0], "docs.rs");
+    }
+    
+    #[test]
+    fn test_collect_url_citations() {
+        let annotations = vec![
+            serde_json::json!({"type": "url_citation", "url": "https://docs.rs", "title": "Docs.rs"}),
+            serde_json::json!({"type": "file_citation", "file_id": "file-1"}),
+            serde_json::json!({"type": "url_citation", "url": "https://docs.rs", "title": "Docs.rs"}),
+        ];
+        let mut results = Vec::new();
+        collect_url_citations(&annotations, &mut results);
+        
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].url, "https://docs.rs");
+        assert_eq!(results[0].title.as_deref(), Some("Docs.rs"));
+        
+        let action = serde_json::json!({"type": "search", "query": "rust serde"});
+        assert_eq!(web_search_query(Some(&action)).as_deref(), Some("rust serde"));
+    }
+    
+    fn tool_call(id: &str, name: &str) -> OpenAIToolCall {
+        OpenAIToolCall {
+            index: None,
+            id: Some(id.to_string()),
+            tool_type: Some("function".to_string()),
+            function: OpenAIFunctionCall {
+                name: Some(name.to_string()),
+                arguments: Some("{}".to_string()),
+            },
+            signature: None,
+            extra_content: None,
+        }
+    }
+    
+    fn message(role: &str, content: Option<&str>) -> OpenAIMessage {
+        OpenAIMessage {
+            role: role.to_string(),
+            content: content.map(|c| OpenAIContent::Text(c.to_string())),
+            name: None,
+            tool_calls: None,
+            tool_call_id: None,
+        }
+    }
+    
+    #[test]
+    fn test_convert_request() {
+        let mut assistant = message("assistant", None);
+        assistant.tool_calls = Some(vec![tool_call("call_1", "read_file"), tool_call("call_orphan", "ls")]);
+        let mut tool_result = message("tool", Some("file contents"));
+        tool_result.tool_call_id = Some("call_1".to_string());
+        
+        let request = OpenAIRequest {
+            messages: vec![
+                message("system", Some("Be brief")),
+                message("user", Some("Read the file")),
+                assistant,
+                tool_result,
+            ],
+            max_tokens: Some(1),
+            extensions: [
+                ("metadata".to_string(), serde_json::json!({"team": "search"})),
+                ("reasoning_effort".to_string(), serde_json::json!("high")),
+            ].into_iter().collect(),
+            ..Default::default()
+        };
+        let mut model_config = ModelConfig::passthrough("gpt-5-codex");
+        
+        let converted = convert_request(&request, &model_config, InputItemFormat::default(), OrphanToolCalls::Drop).unwrap();
+        assert_eq!(converted.instructions.as_deref(), Some("Be brief"));
+        assert_eq!(converted.max_output_tokens, Some(8192));
+        assert_eq!(converted.input.len(), 3);
+        assert_eq!(converted.input[0]["role"], "user");
+        assert!(converted.input[0].get("status").is_none());
+        assert_eq!(converted.input[1]["type"], "function_call");
+        assert_eq!(converted.input[1]["call_id"], "call_1");
+        assert_eq!(converted.input[2]["type"], "function_call_output");
+        assert!(converted.metadata.is_none());
+        assert_eq!(converted.reasoning.unwrap()["effort"], "high");
+        
+        model_config.options.supports_metadata = true;
+        let format = InputItemFormat { completed_status: true };
+        let converted = convert_request(&request, &model_config, format, OrphanToolCalls::Drop).unwrap();
+        assert_eq!(converted.input[0]["type"], "message");
+        assert_eq!(converted.input[0]["status"], "completed");
+        assert_eq!(converted.input[1]["type"], "function_call");
+        assert_eq!(converted.input[2]["partial"], false);
+        assert_eq!(converted.metadata.unwrap()["team"], "search");
+        
+        // Orphan calls can be kept with an empty output, or fail the request
+        let converted = convert_request(&request, &model_config, InputItemFormat::default(), OrphanToolCalls::SynthesizeEmptyOutput).unwrap();
+        assert_eq!(converted.input.len(), 5);
+        assert_eq!(converted.input[2]["call_id"], "call_orphan");
+        assert_eq!(converted.input[3]["type"], "function_call_output");
+        assert_eq!(converted.input[3]["call_id"], "call_orphan");
+        assert_eq!(converted.input[3]["output"], "");
+        assert_eq!(converted.input[4]["call_id"], "call_1");
+        
+        let error = convert_request(&request, &model_config, InputItemFormat::default(), OrphanToolCalls::Error).unwrap_err();
+        assert_eq!(error.claude_error().0, "invalid_request_error");
+        assert!(error.to_string().contains("call_orphan"));
+    }
+    
+    #[test]
+    fn test_convert_request_assistant_text() {
+        let mut assistant = message("assistant", Some("Let me read it"));
+        assistant.tool_calls = Some(vec![tool_call("call_1", "read_file")]);
+        let mut tool_result = message("tool", Some("file contents"));
+        tool_result.tool_call_id = Some("call_1".to_string());
+        let request = OpenAIRequest {
+            messages: vec![message("user", Some("Read the file")), assistant, tool_result],
+            ..Default::default()
+        };
+        
+        // Text of a message with tool calls comes before the calls
+        let converted = convert_request(&request, &ModelConfig::passthrough("gpt-5-codex"), InputItemFormat::default(), OrphanToolCalls::Drop).unwrap();
+        assert_eq!(converted.input.len(), 4);
+        assert_eq!(converted.input[1]["role"], "assistant");
+        assert_eq!(converted.input[1]["content"][0]["text"], "Let me read it");
+        assert_eq!(converted.input[2]["type"], "function_call");
+        assert_eq!(converted.input[3]["type"], "function_call_output");
+    }
+    
+    #[test]
+    fn test_parse_response() {
+        let body = serde_json::json!({
+            "id": "resp_1",
+            "status": "completed",
+            "output": [
+                {"type": "reasoning", "summary": [{"type": "summary_text", "text": "Looking up the file"}]},
+                {"type": "function_call", "call_id": "call_1", "name": "read_file", "arguments": "{\"path\":\"a.rs\"}"}
+            ],
+            "usage": {
+                "input_tokens": 10,
+                "output_tokens": 20,
+                "input_tokens_details": {"cached_tokens": 8},
+                "output_tokens_details": {"reasoning_tokens": 12}
+            }
+        });
+        
+        let converted = parse_response(&body.to_string()).unwrap();
+        let choice = &converted.choices[0];
+        assert_eq!(choice.finish_reason.as_deref(), Some("tool_calls"));
+        assert!(choice.message.content.is_none());
+        let tool_calls = choice.message.tool_calls.as_ref().unwrap();
+        assert_eq!(tool_calls[0].function.name.as_deref(), Some("read_file"));
+        let usage = converted.usage.unwrap();
+        assert_eq!(usage.total_tokens, 30);
+        assert_eq!(usage.cached_tokens(), 8);
+        
+        // A response cut off by max_output_tokens ends with length, even mid tool call
+        let mut body = body;
+        body["status"] = serde_json::json!("incomplete");
+        body["incomplete_details"] = serde_json::json!({"reason": "max_output_tokens"});
+        let converted = parse_response(&body.to_string()).unwrap();
+        assert_eq!(converted.choices[0].finish_reason.as_deref(), Some("length"));
+        
+        assert!(parse_response("not json").is_err());
+    }
+    
+    #[test]
+    fn test_convert_web_search_output() {
+        let response: ResponsesApiResponse = serde_json::from_value(serde_json::json!({
+            "id": "resp_1",
+            "status": "completed",
+            "output": [
+                {"type": "web_search_call", "id": "ws_1", "status": "completed",
+                 "action": {"type": "search", "query": "rust 2024 edition"}},
+                {"type": "message", "role": "assistant", "content": [{
+                    "type": "output_text",
+                    "text": "Rust 2024 shipped with 1.85.",
+                    "annotations": [{"type": "url_citation", "url": "https://blog.rust-lang.org", "title": "Rust Blog"}]
+                }]}
+            ]
+        })).unwrap();
+        
+        let converted = convert_response(response);
+        assert_eq!(converted.search_queries, Some(vec!["rust 2024 edition".to_string()]));
+        let results = converted.search_results.unwrap();
+        assert_eq!(results[0].url, "https://blog.rust-lang.org");
+        assert_eq!(results[0].title.as_deref(), Some("Rust Blog"));
+    }
+    
+    #[test]
+    fn test_stream_parser() {
+        let mut parser = StreamParser::default();
+        
+        // Events split across chunk boundaries are buffered
+        let chunks = parser.push(b"event: response.created\ndata: {\"type\":\"response.created\"}\n\ndata: {\"type\":\"response.output_te");
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].choices[0].delta.role.as_deref(), Some("assistant"));
+        
+        let chunks = parser.push(b"xt.delta\",\"delta\":\"Hi\"}\n\ndata: {\"type\":\"response.output_item.added\",\"item\":{\"type\":\"function_call\",\"call_id\":\"call_1\",\"name\":\"ls\"}}\n");
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0].choices[0].delta.content.as_deref(), Some("Hi"));
+        assert!(chunks[0].choices[0].delta.role.is_none());
+        let tool_call = &chunks[1].choices[0].delta.tool_calls.as_ref().unwrap()[0];
+        assert_eq!(tool_call.id.as_deref(), Some("call_1"));
+        assert_eq!(tool_call.function.name.as_deref(), Some("ls"));
+        
+        let chunks = parser.push(b"data: {\"type\":\"response.output_text.done\"}\ndata: {\"type\":\"response.function_call_arguments.delta\",\"delta\":\"{}\"}\ndata: {\"type\":\"response.function_call_arguments.done\"}\n");
+        assert_eq!(chunks.len(), 1);
+        let tool_call = &chunks[0].choices[0].delta.tool_calls.as_ref().unwrap()[0];
+        assert_eq!(tool_call.index, Some(0));
+        assert_eq!(tool_call.function.arguments.as_deref(), Some("{}"));
+        assert!(chunks[0].choices[0].finish_reason.is_none());
+        
+        let chunks = parser.push(b"data: {\"type\":\"response.completed\"}\n");
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].choices[0].finish_reason.as_deref(), Some("tool_calls"));
+        assert!(chunks[0].search_results.is_none());
+    }
+    
+    #[test]
+    fn test_stream_parser_web_search() {
+        let mut parser = StreamParser::default();
+        let completed = serde_json::json!({
+            "type": "response.completed",
+            "response": {"status": "completed", "output": [
+                {"type": "web_search_call", "action": {"type": "search", "query": "rust 1.85"}},
+                {"type": "message", "content": [{"type": "output_text", "text": "Rust 1.85 is out.",
+                    "annotations": [{"type": "url_citation", "url": "https://blog.rust-lang.org", "title": "Rust Blog"}]}]}
+            ]}
+        });
+        let chunks = parser.push(format!("data: {}\n", completed).as_bytes());
+        assert_eq!(chunks[0].search_queries, Some(vec!["rust 1.85".to_string()]));
+        assert_eq!(chunks[0].search_results.as_ref().unwrap()[0].url, "https://blog.rust-lang.org");
+    }
+    
+    #[test]
+    fn test_stream_parser_multiple_tool_calls() {
+        let mut parser = StreamParser::default();
+        let events = [
+            r#"{"type":"response.output_item.added","output_index":1,"item":{"type":"function_call","call_id":"call_1","name":"read"}}"#,
+            r#"{"type":"response.output_item.added","output_index":2,"item":{"type":"function_call","call_id":"call_2","name":"ls"}}"#,
+            r#"{"type":"response.function_call_arguments.delta","output_index":1,"delta":"{\"path\":\"a\"}"}"#,
+            r#"{"type":"response.function_call_arguments.delta","output_index":2,"delta":"{}"}"#,
+            r#"{"type":"response.completed"}"#,
+        ];
+        let sse: String = events.iter().map(|e| format!("data: {}\n\n", e)).collect();
+        let chunks = parser.push(sse.as_bytes());
+        assert_eq!(chunks.len(), 5);
+        
+        let index_of = |chunk: &OpenAIStreamResponse| -> Vec<Option<u32>> {
+            chunk.choices[0].delta.tool_calls.as_ref().unwrap().iter().map(|t| t.index).collect()
+        };
+        assert_eq!(index_of(&chunks[0]), vec![Some(0)]);
+        assert_eq!(index_of(&chunks[1]), vec![Some(1)]);
+        assert_eq!(index_of(&chunks[2]), vec![Some(0)]);
+        assert_eq!(index_of(&chunks[3]), vec![Some(1)]);
+        assert_eq!(index_of(&chunks[4]), vec![Some(0), Some(1)]);
+        assert_eq!(chunks[4].choices[0].finish_reason.as_deref(), Some("tool_calls"));
+        
+        // Text-only responses finish with stop, truncated ones with length
+        let mut parser = StreamParser::default();
+        let chunks = parser.push(b"data: {\"type\":\"response.output_text.delta\",\"delta\":\"Hi\"}\ndata: {\"type\":\"response.completed\"}\n");
+        assert_eq!(chunks[1].choices[0].finish_reason.as_deref(), Some("stop"));
+        let mut parser = StreamParser::default();
+        let chunks = parser.push(b"data: {\"type\":\"response.incomplete\"}\n");
+        assert_eq!(chunks[0].choices[0].finish_reason.as_deref(), Some("length"));
+        
+        // Content filtered responses are reported through incomplete_details
+        let mut parser = StreamParser::default();
+        let chunks = parser.push(b"data: {\"type\":\"response.incomplete\",\"response\":{\"status\":\"incomplete\",\"incomplete_details\":{\"reason\":\"content_filter\"}}}\n");
+        assert_eq!(chunks[0].choices[0].finish_reason.as_deref(), Some("content_filter"));
+    }
+    
+    #[test]
+    fn test_input_image() {
+        let image_url = |url: &str, detail: Option<&str>| OpenAIImageUrl {
+            url: url.to_string(),
+            detail: detail.map(|d| d.to_string()),
+        };
+        let mut model_config = ModelConfig::passthrough("doubao-seed-1.6");
+        
+        let item = input_image(&image_url("https://example.com/cat.png", Some("auto")), &model_config);
+        assert_eq!(item["type"], "input_image");
+        assert_eq!(item["detail"], "auto");
+        
+        // Configured detail wins over the part's detail
+        model_config.options.image_detail = Some("low".to_string());
+        let item = input_image(&image_url("https://example.com/cat.png", Some("auto")), &model_config);
+        assert_eq!(item["detail"], "low");
+        
+        // Images that can't be sent become a text note
+        let item = input_image(&image_url("data:image/tiff;base64,AAAA", None), &model_config);
+        assert_eq!(item["type"], "input_text");
+        assert_eq!(item["text"], "[Image omitted: unsupported image type image/tiff]");
+    }
+    
+    #[test]
+    fn test_finish_reason() {
+        assert_eq!(finish_reason("completed", None, false), "stop");
+        assert_eq!(finish_reason("completed", None, true), "tool_calls");
+        assert_eq!(finish_reason("incomplete", Some("max_output_tokens"), true), "length");
+        assert_eq!(finish_reason("incomplete", None, false), "length");
+        assert_eq!(finish_reason("incomplete", Some("content_filter"), false), "content_filter");
+    }
+    
+    #[cfg(feature = "test-util")]
+    mod round_trip {
+        use super::*;
+        use crate::test_util::{self, check_no_content_loss, check_tool_ids, Contents};
+        use proptest::prelude::*;
+        use proptest::test_runner::TestCaseError;
+        
+        proptest! {
+            #[test]
+            fn test_convert_request_keeps_contents(request in test_util::openai_request(), completed_status: bool) {
+                let model_config = ModelConfig::passthrough("gpt-5-codex");
+                let format = InputItemFormat { completed_status };
+                let converted = convert_request(&request, &model_config, format, OrphanToolCalls::Error).unwrap();
+                let before = Contents::of_openai_request(&request);
+                let after = Contents::of_responses_input(converted.instructions.as_deref(), &converted.input);
+                check_tool_ids(&before, &after).map_err(TestCaseError::fail)?;
+                check_no_content_loss(&before, &after).map_err(TestCaseError::fail)?;
+            }
+            
+            #[test]
+            fn test_convert_response_keeps_contents(
+                text in prop::option::of(test_util::text()),
+                calls in prop::collection::vec(test_util::tool_call(), 0..4),
+            ) {
+                let mut output: Vec<Value> = text.iter()
+                    .map(|text| serde_json::json!({
+                        "type": "message",
+                        "role": "assistant",
+                        "content": [{"type": "output_text", "text": text, "annotations": []}]
+                    }))
+                    .collect();
+                output.extend(calls.iter().map(|call| serde_json::json!({
+                    "type": "function_call",
+                    "call_id": call.id,
+                    "name": call.name,
+                    "arguments": call.input.to_string()
+                })));
+                let body = serde_json::json!({"id": "resp_1", "status": "completed", "output": output});
+                let response = parse_response(&body.to_string()).unwrap();
+                
+                let expected = Contents {
+                    assistant: text.into_iter().collect(),
+                    tool_calls: calls.iter().map(|call| (call.id.clone(), call.name.clone(), call.input.clone())).collect(),
+                    ..Default::default()
+                };
+                let after = Contents::of_openai_response(&response);
+                check_tool_ids(&expected, &after).map_err(TestCaseError::fail)?;
+                check_no_content_loss(&expected, &after).map_err(TestCaseError::fail)?;
+                let finish_reason = if calls.is_empty() { "stop" } else { "tool_calls" };
+                prop_assert_eq!(response.choices[0].finish_reason.as_deref(), Some(finish_reason));
+            }
+        }
+    }
+}
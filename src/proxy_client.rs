@@ -0,0 +1,172 @@
+//! Embeddable proxy client
+//!
+//! [`ProxyClient`] runs the same conversion and provider pipeline as the
+//! `/v1/messages` endpoint, without the HTTP server: Claude requests are
+//! routed, converted, sent to the configured provider and the responses are
+//! converted back to Claude messages or stream events.
+//!
+//! ```no_run
+//! use aiapiproxy::{AppConfig, ClaudeRequest, ProxyClient, Settings};
+//!
+//! # async fn run() -> anyhow::Result<()> {
+//! let client = ProxyClient::new(Settings::new()?, AppConfig::load_default()?)?;
+//! let request = ClaudeRequest::builder()
+//!     .model("openai/gpt-4o")
+//!     .max_tokens(1024)
+//!     .user("Hello!")
+//!     .build();
+//! let response = client.send(request).await?;
+//! println!("{:?}", response.content);
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::config::{AppConfig, Settings};
+use crate::models::claude::{ClaudeRequest, ClaudeResponse, ClaudeStreamEvent};
+use crate::models::openai::{OpenAIRequest, OpenAIUsage};
+use crate::providers::BoxStream;
+use crate::services::prompt_rules::PromptRewriter;
+use crate::services::{context_window, output_tokens, request_params, sessions, ApiConverter, ContentBlockTracker, ResponseConverter, Router, StopSequenceTracker};
+use anyhow::Result;
+use futures::StreamExt;
+use std::sync::Arc;
+use tracing::debug;
+
+/// Client for the conversion and provider layer
+#[derive(Clone)]
+pub struct ProxyClient {
+    converter: ApiConverter,
+    router: Arc<Router>,
+}
+
+impl std::fmt::Debug for ProxyClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ProxyClient")
+            .field("models", &self.router.list_models())
+            .finish()
+    }
+}
+
+impl ProxyClient {
+    /// Create a client from the same settings and provider configuration as the server
+    pub fn new(settings: Settings, app_config: AppConfig) -> Result<Self> {
+        let converter = ApiConverter::new(settings)
+            .with_multiple_choices(app_config.multiple_choices)
+            .with_prompt_rules(PromptRewriter::new(&app_config.prompt_rules)?);
+        let router = Arc::new(Router::new(app_config)?);
+        Ok(Self { converter, router })
+    }
+    
+    /// Provider router used by this client
+    pub fn router(&self) -> &Router {
+        &self.router
+    }
+    
+    /// Convert a Claude request to the OpenAI request sent upstream
+    ///
+    /// Applies routing rules, the model's request parameters and its output
+    /// token and context window policies; policy violations are returned as errors.
+    pub fn convert_request(&self, request: ClaudeRequest) -> Result<OpenAIRequest> {
+        let route_model = self.router
+            .route_model(&request.model, request.metadata.as_ref())
+            .to_string();
+        let session_id = sessions::session_id_from_metadata(request.metadata.as_ref());
+        let route_model = self.router.balance(&route_model, session_id);
+        
+        let mut openai_request = self.converter.convert_request(request)?;
+        openai_request.model = route_model;
+        
+        if let Some(model_config) = self.router.model_config(&openai_request.model) {
+            request_params::apply(&mut openai_request, &model_config);
+            output_tokens::apply(&mut openai_request, &model_config).map_err(anyhow::Error::msg)?;
+            context_window::enforce(&mut openai_request, &model_config).map_err(anyhow::Error::msg)?;
+        }
+        
+        Ok(openai_request)
+    }
+    
+    /// Estimate the input tokens of a Claude request
+    pub fn count_tokens(&self, request: ClaudeRequest) -> Result<u32> {
+        let model = request.model.clone();
+        let openai_request = self.converter.convert_request(request)?;
+        Ok(self.router.token_counter(&model).count_request(&openai_request))
+    }
+    
+    /// Send a request and return the complete Claude response
+    pub async fn send(&self, request: ClaudeRequest) -> Result<ClaudeResponse> {
+        let original_model = request.model.clone();
+        let mut openai_request = self.convert_request(request)?;
+        openai_request.stream = None;
+        
+        let stop_sequences = openai_request.stop.clone().unwrap_or_default();
+        let route_model = openai_request.model.clone();
+        let session_id = openai_request.session_id.clone();
+        if let Some(session_id) = &session_id {
+            sessions::global().begin_request(session_id, &openai_request);
+        }
+        let usage_request = openai_request.clone();
+        
+        let mut response = self.router.chat_complete(openai_request).await?;
+        if response.usage.as_ref().is_none_or(OpenAIUsage::is_empty) {
+            let usage = self.router.token_counter(&route_model).estimate_usage(&usage_request, &response);
+            debug!("📊 Upstream omitted usage, estimated {} + {} tokens", usage.prompt_tokens, usage.completion_tokens);
+            response.usage = Some(usage);
+        }
+        
+        let mut claude_response = self.response_converter(&route_model)
+            .convert_response(response, &original_model)?;
+        self.converter.apply_stop_sequences(&mut claude_response, &stop_sequences);
+        if let Some(session_id) = &session_id {
+            sessions::global().record_response(session_id, &claude_response);
+        }
+        Ok(claude_response)
+    }
+    
+    /// Send a request and stream the Claude events
+    ///
+    /// `message_start` carries the estimated input tokens, as in the server's
+    /// SSE responses. Keep-alive pings are left to the caller.
+    pub async fn stream(&self, request: ClaudeRequest) -> Result<BoxStream<'static, ClaudeStreamEvent>> {
+        let original_model = request.model.clone();
+        let mut openai_request = self.convert_request(request)?;
+        openai_request.stream = Some(true);
+        
+        let session_id = openai_request.session_id.clone();
+        if let Some(session_id) = &session_id {
+            sessions::global().begin_request(session_id, &openai_request);
+        }
+        let converter = self.response_converter(&openai_request.model);
+        let mut block_tracker = ContentBlockTracker::new();
+        let mut stop_tracker = StopSequenceTracker::new(openai_request.stop.clone().unwrap_or_default());
+        let input_tokens = self.router.token_counter(&openai_request.model).count_request(&openai_request);
+        
+        let stream = self.router.chat_stream(openai_request).await?;
+        let events = stream.flat_map(move |chunk| {
+            let events: Vec<Result<ClaudeStreamEvent>> = match chunk.and_then(|chunk| converter.convert_stream_chunk(chunk, &original_model)) {
+                Ok(events) => events.into_iter()
+                    .flat_map(|event| block_tracker.process(event))
+                    .map(|mut event| {
+                        stop_tracker.observe(&mut event);
+                        if let ClaudeStreamEvent::MessageStart { message } = &mut event {
+                            message.usage.input_tokens = input_tokens;
+                        }
+                        if let Some(session_id) = &session_id {
+                            sessions::global().record_stream_event(session_id, &event);
+                        }
+                        Ok(event)
+                    })
+                    .collect(),
+                Err(e) => vec![Err(e)],
+            };
+            futures::stream::iter(events)
+        });
+        
+        Ok(Box::pin(events))
+    }
+    
+    /// Response converter for a model: the provider's specialized one or the default
+    fn response_converter(&self, model: &str) -> Arc<dyn ResponseConverter> {
+        self.router.response_converter(model)
+            .unwrap_or_else(|| Arc::new(self.converter.clone()))
+    }
+}
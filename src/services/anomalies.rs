@@ -0,0 +1,107 @@
+//! Warnings about requests that point at upstream regressions
+//!
+//! With `anomalyLog`, every completed `/v1/messages` request is checked when
+//! its [`RequestSummary`] is dispatched: a request slower than
+//! `slowRequestMs`, a successful response with fewer than `minOutputTokens`
+//! output tokens, or one cut off by `max_tokens` is logged as a WARN with
+//! the request's routing detail as structured fields, so a regressing
+//! upstream shows up in the logs without turning on debug logging.
+
+use crate::config::AnomalyLogConfig;
+use crate::services::hooks::RequestHook;
+use crate::services::request_log::RequestSummary;
+use async_trait::async_trait;
+use tracing::warn;
+
+/// Request hook logging anomalous requests
+#[derive(Debug)]
+pub struct AnomalyLog {
+    config: AnomalyLogConfig,
+}
+
+impl AnomalyLog {
+    pub fn new(config: &AnomalyLogConfig) -> Self {
+        Self { config: config.clone() }
+    }
+    
+    /// Anomalies of a request, empty when it looks normal
+    pub fn anomalies(&self, summary: &RequestSummary) -> Vec<String> {
+        let mut anomalies = Vec::new();
+        if summary.latency_ms >= self.config.slow_request_ms {
+            anomalies.push(format!("slow ({} ms)", summary.latency_ms));
+        }
+        // A stream the client left early has no stop reason; its token count says nothing
+        let completed = summary.status == 200 && summary.stop_reason.is_some();
+        if let Some(output_tokens) = summary.output_tokens.filter(|tokens| completed && *tokens < self.config.min_output_tokens) {
+            anomalies.push(format!("few output tokens ({})", output_tokens));
+        }
+        if self.config.log_length_stops && summary.stop_reason.as_deref() == Some("max_tokens") {
+            anomalies.push("stopped at max_tokens".to_string());
+        }
+        anomalies
+    }
+}
+
+#[async_trait]
+impl RequestHook for AnomalyLog {
+    async fn on_complete(&self, summary: &RequestSummary) {
+        let anomalies = self.anomalies(summary);
+        if anomalies.is_empty() {
+            return;
+        }
+        warn!(
+            request_id = summary.request_id.as_deref().unwrap_or(""),
+            key = summary.key.as_deref().unwrap_or(""),
+            tenant = summary.tenant.as_deref().unwrap_or(""),
+            model = %summary.model,
+            route = summary.route.as_deref().unwrap_or(""),
+            provider = summary.provider.as_deref().unwrap_or(""),
+            stream = summary.stream,
+            status = summary.status,
+            latency_ms = summary.latency_ms,
+            input_tokens = summary.input_tokens,
+            output_tokens = summary.output_tokens,
+            stop_reason = summary.stop_reason.as_deref().unwrap_or(""),
+            "⚠️ Anomalous request {} -> {}: {}",
+            summary.model,
+            summary.route.as_deref().unwrap_or("-"),
+            anomalies.join(", ")
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_anomalies() {
+        let log = AnomalyLog::new(&AnomalyLogConfig { slow_request_ms: 30_000, min_output_tokens: 5, log_length_stops: true });
+        let normal = RequestSummary {
+            model: "claude-sonnet-4-5".to_string(),
+            route: Some("openai/gpt-4o".to_string()),
+            status: 200,
+            latency_ms: 1200,
+            output_tokens: Some(120),
+            stop_reason: Some("end_turn".to_string()),
+            ..Default::default()
+        };
+        assert!(log.anomalies(&normal).is_empty());
+        
+        let regressed = RequestSummary {
+            latency_ms: 45_000,
+            output_tokens: Some(2),
+            stop_reason: Some("max_tokens".to_string()),
+            ..normal.clone()
+        };
+        assert_eq!(log.anomalies(&regressed), ["slow (45000 ms)", "few output tokens (2)", "stopped at max_tokens"]);
+        
+        // Streams the client abandoned and failed requests have no meaningful token count
+        let abandoned = RequestSummary { output_tokens: Some(0), stop_reason: None, ..normal.clone() };
+        assert!(log.anomalies(&abandoned).is_empty());
+        let uncounted = RequestSummary { output_tokens: None, ..normal.clone() };
+        assert!(log.anomalies(&uncounted).is_empty());
+        let failed = RequestSummary { status: 502, output_tokens: Some(0), ..normal };
+        assert!(log.anomalies(&failed).is_empty());
+    }
+}
@@ -0,0 +1,196 @@
+//! Anthropic-defined client tools
+//!
+//! Claude Code and computer-use agents declare Anthropic-defined tools
+//! (`computer_20250124`, `bash_20250124`, `text_editor_20250429`, ...) by type
+//! and name only; the model knows their schemas. OpenAI-style upstreams need
+//! an explicit function definition, so this module supplies the documented
+//! input schema for each of them. The client still executes the tools, and
+//! tool calls come back under the original tool name.
+
+use crate::models::claude::ClaudeTool;
+use crate::models::openai::OpenAIFunction;
+use serde_json::{json, Value};
+
+/// Function definition for an Anthropic-defined tool
+///
+/// Returns `None` for custom tools (no type, or `"custom"`) and for unknown
+/// tool types.
+pub fn function_for(tool: &ClaudeTool) -> Option<OpenAIFunction> {
+    let tool_type = tool.tool_type.as_deref()?;
+    let (description, parameters) = if tool_type.starts_with("computer_") {
+        (computer_description(tool), computer_schema())
+    } else if tool_type.starts_with("bash_") {
+        (BASH_DESCRIPTION.to_string(), bash_schema())
+    } else if tool_type.starts_with("text_editor_") {
+        (TEXT_EDITOR_DESCRIPTION.to_string(), text_editor_schema())
+    } else {
+        return None;
+    };
+    
+    Some(OpenAIFunction {
+        name: tool.name.clone(),
+        description: Some(tool.description.clone().unwrap_or(description)),
+        parameters: Some(parameters),
+    })
+}
+
+const BASH_DESCRIPTION: &str = "Run commands in a persistent bash shell. State is kept between calls; \
+use `restart` to start a fresh shell.";
+
+const TEXT_EDITOR_DESCRIPTION: &str = "View, create and edit files. `view` shows a file with line numbers \
+or lists a directory, `create` writes a new file, `str_replace` replaces an exact unique string, \
+`insert` adds text after a line, and `undo_edit` reverts the last edit.";
+
+fn computer_description(tool: &ClaudeTool) -> String {
+    let mut description = "Control the computer's screen, mouse and keyboard. \
+Take a screenshot to see the screen; coordinates are pixels from the top-left corner."
+        .to_string();
+    let width = tool.extra.get("display_width_px").and_then(Value::as_u64);
+    let height = tool.extra.get("display_height_px").and_then(Value::as_u64);
+    if let (Some(width), Some(height)) = (width, height) {
+        description.push_str(&format!(" The display is {}x{} pixels.", width, height));
+    }
+    description
+}
+
+fn computer_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "action": {
+                "type": "string",
+                "enum": [
+                    "key", "hold_key", "type", "cursor_position", "mouse_move",
+                    "left_mouse_down", "left_mouse_up", "left_click", "left_click_drag",
+                    "right_click", "middle_click", "double_click", "triple_click",
+                    "scroll", "wait", "screenshot"
+                ],
+                "description": "The action to perform"
+            },
+            "coordinate": {
+                "type": "array",
+                "items": {"type": "integer"},
+                "description": "(x, y) pixel position for mouse actions"
+            },
+            "start_coordinate": {
+                "type": "array",
+                "items": {"type": "integer"},
+                "description": "(x, y) start position for left_click_drag"
+            },
+            "text": {
+                "type": "string",
+                "description": "Text to type, or key combination for key/hold_key (e.g. \"ctrl+s\")"
+            },
+            "scroll_direction": {
+                "type": "string",
+                "enum": ["up", "down", "left", "right"]
+            },
+            "scroll_amount": {
+                "type": "integer",
+                "description": "Number of scroll wheel clicks"
+            },
+            "duration": {
+                "type": "number",
+                "description": "Seconds to wait or hold a key"
+            }
+        },
+        "required": ["action"]
+    })
+}
+
+fn bash_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "command": {
+                "type": "string",
+                "description": "The bash command to run"
+            },
+            "restart": {
+                "type": "boolean",
+                "description": "Restart the shell instead of running a command"
+            }
+        }
+    })
+}
+
+fn text_editor_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "command": {
+                "type": "string",
+                "enum": ["view", "create", "str_replace", "insert", "undo_edit"],
+                "description": "The edit command to run"
+            },
+            "path": {
+                "type": "string",
+                "description": "Absolute path of the file or directory"
+            },
+            "file_text": {
+                "type": "string",
+                "description": "Content of the new file (create)"
+            },
+            "old_str": {
+                "type": "string",
+                "description": "Exact text to replace (str_replace)"
+            },
+            "new_str": {
+                "type": "string",
+                "description": "Replacement text (str_replace) or text to insert (insert)"
+            },
+            "insert_line": {
+                "type": "integer",
+                "description": "Line after which to insert text (insert)"
+            },
+            "view_range": {
+                "type": "array",
+                "items": {"type": "integer"},
+                "description": "[start, end] line range to view; -1 as end means the end of the file"
+            }
+        },
+        "required": ["command", "path"]
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn tool(value: Value) -> ClaudeTool {
+        serde_json::from_value(value).unwrap()
+    }
+    
+    #[test]
+    fn test_function_for_computer() {
+        let computer = tool(json!({
+            "type": "computer_20250124",
+            "name": "computer",
+            "display_width_px": 1024,
+            "display_height_px": 768
+        }));
+        let function = function_for(&computer).unwrap();
+        assert_eq!(function.name, "computer");
+        assert!(function.description.unwrap().contains("1024x768"));
+        assert_eq!(function.parameters.unwrap()["required"], json!(["action"]));
+    }
+    
+    #[test]
+    fn test_function_for_editor_and_bash() {
+        let editor = function_for(&tool(json!({"type": "text_editor_20250429", "name": "str_replace_based_edit_tool"}))).unwrap();
+        assert_eq!(editor.name, "str_replace_based_edit_tool");
+        assert_eq!(editor.parameters.unwrap()["required"], json!(["command", "path"]));
+        
+        let bash = function_for(&tool(json!({"type": "bash_20250124", "name": "bash"}))).unwrap();
+        assert!(bash.parameters.unwrap()["properties"].get("command").is_some());
+    }
+    
+    #[test]
+    fn test_function_for_other_tools() {
+        let custom = tool(json!({"name": "get_weather", "input_schema": {"type": "object"}}));
+        assert!(function_for(&custom).is_none());
+        
+        let unknown = tool(json!({"type": "memory_20250818", "name": "memory"}));
+        assert!(function_for(&unknown).is_none());
+    }
+}
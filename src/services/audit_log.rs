@@ -0,0 +1,241 @@
+//! Audit log of configuration changes
+//!
+//! With `auditLog`, every change to the running configuration is appended to
+//! a file as one JSON line: when it happened, who made it (a SIGHUP, the file
+//! watcher, the remote poll or a library caller), where the configuration came
+//! from and which settings changed, with their values before and after. API
+//! keys are replaced with a fingerprint so a rotation still shows as a change.
+//! The most recent entries are also kept in memory for `GET /admin/audit`.
+
+use crate::config::AuditLogConfig;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeSet, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::sync::Mutex;
+use tracing::warn;
+
+/// Fields whose values are never written to the audit log
+const SECRET_FIELDS: &[&str] = &["apiKey", "password", "token"];
+
+/// Append-only audit log with its most recent entries in memory
+#[derive(Debug)]
+pub struct AuditLog {
+    config: AuditLogConfig,
+    inner: Mutex<AuditLogInner>,
+}
+
+#[derive(Debug)]
+struct AuditLogInner {
+    file: File,
+    recent: VecDeque<AuditEntry>,
+}
+
+/// One audited action
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// Time of the action (RFC 3339)
+    pub timestamp: String,
+    /// Who or what triggered the action, e.g. "sighup" or "file-watcher"
+    pub actor: String,
+    /// What was done, e.g. "config_reload"
+    pub action: String,
+    /// File or remote source the new configuration was read from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// Settings that changed
+    pub changes: Vec<AuditChange>,
+}
+
+/// A changed setting, by its dotted path in the configuration
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditChange {
+    pub path: String,
+    /// Previous value; absent for an added setting
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub before: Option<Value>,
+    /// New value; absent for a removed setting
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub after: Option<Value>,
+}
+
+impl AuditLog {
+    /// Open the log for appending, loading its most recent entries
+    pub fn open(config: &AuditLogConfig) -> Result<Self> {
+        if let Some(dir) = config.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).with_context(|| format!("Failed to create audit log directory {}", dir.display()))?;
+        }
+        let existing = match fs::read_to_string(&config.path) {
+            Ok(existing) => existing,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read audit log {}", config.path.display())),
+        };
+        let mut recent: VecDeque<AuditEntry> = existing.lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect();
+        while recent.len() > config.recent_entries {
+            recent.pop_front();
+        }
+        
+        let file = OpenOptions::new().create(true).append(true).open(&config.path)
+            .with_context(|| format!("Failed to open audit log {}", config.path.display()))?;
+        Ok(Self { config: config.clone(), inner: Mutex::new(AuditLogInner { file, recent }) })
+    }
+    
+    /// Configuration the log was opened with
+    pub fn config(&self) -> &AuditLogConfig {
+        &self.config
+    }
+    
+    /// Record an action that changed `before` into `after`
+    ///
+    /// Returns the entry, or None if nothing changed.
+    pub fn record<T: Serialize>(&self, actor: &str, action: &str, source: Option<&str>, before: &T, after: &T) -> Option<AuditEntry> {
+        let (before, after) = match (serde_json::to_value(before), serde_json::to_value(after)) {
+            (Ok(before), Ok(after)) => (before, after),
+            (Err(e), _) | (_, Err(e)) => {
+                warn!("📜 Failed to serialize audited {}: {}", action, e);
+                return None;
+            }
+        };
+        let mut changes = Vec::new();
+        diff(&mask_secrets(before), &mask_secrets(after), "", &mut changes);
+        if changes.is_empty() {
+            return None;
+        }
+        
+        let entry = AuditEntry {
+            timestamp: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            actor: actor.to_string(),
+            action: action.to_string(),
+            source: source.map(str::to_string),
+            changes,
+        };
+        self.append(entry.clone());
+        Some(entry)
+    }
+    
+    /// The most recent entries, oldest first
+    pub fn recent(&self, limit: usize) -> Vec<AuditEntry> {
+        let inner = self.inner.lock().unwrap();
+        inner.recent.iter().skip(inner.recent.len().saturating_sub(limit)).cloned().collect()
+    }
+    
+    fn append(&self, entry: AuditEntry) {
+        let mut inner = self.inner.lock().unwrap();
+        match serde_json::to_vec(&entry) {
+            Ok(mut line) => {
+                line.push(b'\n');
+                if let Err(e) = inner.file.write_all(&line) {
+                    warn!("📜 Failed to write audit log {}: {}", self.config.path.display(), e);
+                }
+            }
+            Err(e) => warn!("📜 Failed to serialize audit entry: {}", e),
+        }
+        inner.recent.push_back(entry);
+        while inner.recent.len() > self.config.recent_entries {
+            inner.recent.pop_front();
+        }
+    }
+}
+
+/// Collect the leaves that differ between two values; objects are compared
+/// key by key, anything else as a whole
+fn diff(before: &Value, after: &Value, path: &str, changes: &mut Vec<AuditChange>) {
+    match (before, after) {
+        (Value::Object(before), Value::Object(after)) => {
+            let keys: BTreeSet<&String> = before.keys().chain(after.keys()).collect();
+            for key in keys {
+                let path = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+                match (before.get(key), after.get(key)) {
+                    (Some(before), Some(after)) => diff(before, after, &path, changes),
+                    (before, after) => changes.push(AuditChange { path, before: before.cloned(), after: after.cloned() }),
+                }
+            }
+        }
+        _ if before != after => changes.push(AuditChange {
+            path: path.to_string(),
+            before: Some(before.clone()),
+            after: Some(after.clone()),
+        }),
+        _ => {}
+    }
+}
+
+/// Replace non-empty secret values with a fingerprint of their SHA-256 hash
+fn mask_secrets(value: Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(map.into_iter()
+            .map(|(key, value)| match value {
+                Value::String(secret) if SECRET_FIELDS.contains(&key.as_str()) && !secret.is_empty() => {
+                    let hash = Sha256::digest(secret.as_bytes());
+                    let fingerprint: String = hash.iter().take(4).map(|byte| format!("{:02x}", byte)).collect();
+                    (key, Value::String(format!("sha256:{}", fingerprint)))
+                }
+                value => (key, mask_secrets(value)),
+            })
+            .collect()),
+        Value::Array(items) => Value::Array(items.into_iter().map(mask_secrets).collect()),
+        value => value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    
+    fn open(dir: &tempfile::TempDir, recent_entries: usize) -> AuditLog {
+        AuditLog::open(&AuditLogConfig { path: dir.path().join("audit.jsonl"), recent_entries }).unwrap()
+    }
+    
+    #[test]
+    fn test_record_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = open(&dir, 10);
+        let before = json!({
+            "providers": {"openai": {"baseUrl": "https://api.openai.com/v1", "apiKey": "sk-old"}},
+            "modelMapping": {"claude-haiku": "openai/gpt-4o-mini"}
+        });
+        let after = json!({
+            "providers": {"openai": {"baseUrl": "https://api.openai.com/v1", "apiKey": "sk-new"}},
+            "modelMapping": {"claude-sonnet": "openai/gpt-4o"}
+        });
+        
+        assert!(log.record("sighup", "config_reload", None, &before, &before).is_none());
+        let entry = log.record("sighup", "config_reload", Some("config.json"), &before, &after).unwrap();
+        let paths: Vec<&str> = entry.changes.iter().map(|change| change.path.as_str()).collect();
+        assert_eq!(paths, ["modelMapping.claude-haiku", "modelMapping.claude-sonnet", "providers.openai.apiKey"]);
+        assert_eq!(entry.changes[0].after, None);
+        assert_eq!(entry.changes[1].before, None);
+        
+        // Keys are never logged, but their rotation is
+        let key_change = &entry.changes[2];
+        let masked = |change: &Option<Value>| change.as_ref().and_then(Value::as_str).unwrap().to_string();
+        assert!(masked(&key_change.before).starts_with("sha256:"));
+        assert_ne!(masked(&key_change.before), masked(&key_change.after));
+        let written = fs::read_to_string(dir.path().join("audit.jsonl")).unwrap();
+        assert!(!written.contains("sk-old") && !written.contains("sk-new"));
+    }
+    
+    #[test]
+    fn test_recent_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = open(&dir, 2);
+        for n in 0..3 {
+            log.record("api", "config_reload", None, &json!({"n": n}), &json!({"n": n + 1}));
+        }
+        let recent = log.recent(10);
+        assert_eq!(recent.len(), 2);
+        assert_eq!(recent[1].changes[0].after, Some(json!(3)));
+        assert_eq!(log.recent(1), recent[1..]);
+        
+        // Entries are read back when the log is opened again
+        drop(log);
+        let log = open(&dir, 5);
+        assert_eq!(log.recent(10).len(), 3);
+    }
+}
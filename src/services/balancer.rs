@@ -0,0 +1,145 @@
+//! Weighted load balancing across equivalent upstream models
+//!
+//! A `modelMapping` entry with weighted targets spreads the requests for one
+//! Claude model over several provider/model paths, e.g. a 70/30 split for
+//! cost spreading or a gradual migration. Paths are picked with smooth
+//! weighted round-robin (as in nginx): deterministic, and interleaved rather
+//! than in bursts, so every window of `total weight` requests matches the
+//! split exactly.
+//!
+//! With `stickySessions`, requests of a Claude Code session go to the same
+//! path instead, to keep hitting the upstream's prompt cache. The path is
+//! picked by weighted rendezvous hashing of the session ID: every replica picks
+//! the same one without sharing state, sessions still split by weight, and
+//! when a path drops out only its own sessions move elsewhere.
+
+use crate::config::WeightedTarget;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Weighted round-robin state of each weighted mapping
+#[derive(Debug, Default)]
+pub struct LoadBalancer {
+    /// Current weights by mapping key
+    current: Mutex<HashMap<String, Vec<i64>>>,
+}
+
+impl LoadBalancer {
+    /// Create a balancer with no history
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Pick the target for the next request of a weighted mapping
+    ///
+    /// `key` identifies the mapping; its state is reset if the targets change.
+    /// Returns `None` if no target has a positive weight.
+    pub fn pick<'a>(&self, key: &str, targets: &[&'a WeightedTarget]) -> Option<&'a str> {
+        let total: i64 = targets.iter().map(|t| i64::from(t.weight)).sum();
+        if total == 0 {
+            return None;
+        }
+        
+        let mut current = self.current.lock().ok()?;
+        let weights = current.entry(key.to_string()).or_default();
+        if weights.len() != targets.len() {
+            *weights = vec![0; targets.len()];
+        }
+        
+        for (weight, target) in weights.iter_mut().zip(targets) {
+            *weight += i64::from(target.weight);
+        }
+        let (best, _) = weights.iter()
+            .enumerate()
+            .max_by_key(|&(i, weight)| (*weight, std::cmp::Reverse(i)))?;
+        weights[best] -= total;
+        
+        Some(targets[best].target.as_str())
+    }
+    
+    /// Pick the target of a session
+    ///
+    /// Returns `None` if no target has a positive weight.
+    pub fn pick_for_session<'a>(session_id: &str, targets: &[&'a WeightedTarget]) -> Option<&'a str> {
+        targets.iter()
+            .filter(|target| target.weight > 0)
+            .map(|target| (rendezvous_score(session_id, target), target))
+            .max_by(|(a, _), (b, _)| a.total_cmp(b))
+            .map(|(_, target)| target.target.as_str())
+    }
+}
+
+/// Weighted rendezvous score of a target for a session
+///
+/// SHA-256 keeps the hash identical across builds, so replicas agree.
+fn rendezvous_score(session_id: &str, target: &WeightedTarget) -> f64 {
+    let digest = Sha256::new()
+        .chain_update(session_id.as_bytes())
+        .chain_update([0])
+        .chain_update(target.target.as_bytes())
+        .finalize();
+    let hash = u64::from_be_bytes(digest[..8].try_into().unwrap_or_default());
+    // Uniform in (0, 1); -weight / ln(u) favors larger weights proportionally
+    let uniform = ((hash >> 11) as f64 + 0.5) / (1u64 << 53) as f64;
+    -f64::from(target.weight) / uniform.ln()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn target(path: &str, weight: u32) -> WeightedTarget {
+        WeightedTarget { target: path.to_string(), weight }
+    }
+    
+    #[test]
+    fn test_weighted_split() {
+        let balancer = LoadBalancer::new();
+        let a = target("ark/glm-4.6", 70);
+        let b = target("openai/gpt-4o", 30);
+        
+        let picks: Vec<&str> = (0..10).map(|_| balancer.pick("sonnet", &[&a, &b]).unwrap()).collect();
+        assert_eq!(picks.iter().filter(|p| **p == "ark/glm-4.6").count(), 7);
+        // Interleaved, never more than three in a row
+        assert!(!picks.windows(4).any(|w| w.iter().all(|p| *p == "ark/glm-4.6")));
+        
+        // Mappings are balanced independently
+        assert_eq!(balancer.pick("haiku", &[&b, &a]), Some("ark/glm-4.6"));
+    }
+    
+    #[test]
+    fn test_zero_weights() {
+        let balancer = LoadBalancer::new();
+        let a = target("ark/glm-4.6", 0);
+        let b = target("openai/gpt-4o", 1);
+        
+        assert!((0..3).all(|_| balancer.pick("sonnet", &[&a, &b]) == Some("openai/gpt-4o")));
+        assert_eq!(balancer.pick("opus", &[&a]), None);
+        assert_eq!(balancer.pick("opus", &[]), None);
+    }
+    
+    #[test]
+    fn test_pick_for_session() {
+        let a = target("ark/glm-4.6", 70);
+        let b = target("openai/gpt-4o", 30);
+        let c = target("modelhub-sg1/gpt-5", 0);
+        
+        // Stable for a session, split by weight across sessions
+        let sessions: Vec<String> = (0..1000).map(|i| format!("session-{}", i)).collect();
+        let picks: Vec<&str> = sessions.iter()
+            .map(|session| LoadBalancer::pick_for_session(session, &[&a, &b, &c]).unwrap())
+            .collect();
+        assert!(sessions.iter().zip(&picks).all(|(session, pick)| {
+            LoadBalancer::pick_for_session(session, &[&b, &a]) == Some(*pick)
+        }));
+        let on_a = picks.iter().filter(|p| **p == "ark/glm-4.6").count();
+        assert!((600..800).contains(&on_a), "{} sessions on ark", on_a);
+        
+        // Without a, only its sessions move
+        assert!(sessions.iter().zip(&picks)
+            .filter(|(_, pick)| **pick == "openai/gpt-4o")
+            .all(|(session, _)| LoadBalancer::pick_for_session(session, &[&b, &c]) == Some("openai/gpt-4o")));
+        assert_eq!(LoadBalancer::pick_for_session("session-1", &[&c]), None);
+    }
+}
@@ -2,38 +2,29 @@
 //! 
 //! Encapsulates HTTP communication with OpenAI API
 
-use crate::config::Settings;
+use crate::config::{ProviderOptions, Settings};
 use crate::models::openai::*;
+use crate::providers::UpstreamError;
+use crate::services::{http_client, retry};
+use crate::utils::sse;
 use anyhow::{Context, Result};
 use reqwest::{Client, Response};
 use std::time::Duration;
-use tokio_stream::{Stream, StreamExt};
+use tokio_stream::Stream;
 use tracing::{debug, error, info, warn};
 
 /// OpenAI API client
 #[derive(Debug, Clone)]
 pub struct OpenAIClient {
     client: Client,
-    stream_client: Client,
     settings: Settings,
 }
 
 impl OpenAIClient {
-    /// Create a new client instance
+    /// Create a new client instance on the shared HTTP client
     pub fn new(settings: Settings) -> Result<Self> {
-        let client = Client::builder()
-            .timeout(Duration::from_secs(settings.openai.timeout))
-            .user_agent("aiapiproxy/0.1.0")
-            .build()
-            .context("Failed to create HTTP client")?;
-        
-        let stream_client = Client::builder()
-            .timeout(Duration::from_secs(settings.openai.stream_timeout))
-            .user_agent("aiapiproxy/0.1.0")
-            .build()
-            .context("Failed to create streaming HTTP client")?;
-        
-        Ok(Self { client, stream_client, settings })
+        let client = http_client::global().client(&ProviderOptions::default())?;
+        Ok(Self { client, settings })
     }
     
     /// Send chat completion request
@@ -44,6 +35,7 @@ impl OpenAIClient {
         
         let response = self.client
             .post(&url)
+            .timeout(Duration::from_secs(self.settings.openai.timeout))
             .header("Authorization", format!("Bearer {}", self.settings.openai.api_key))
             .header("Content-Type", "application/json")
             .json(&request)
@@ -63,8 +55,9 @@ impl OpenAIClient {
         
         let url = format!("{}/chat/completions", self.settings.openai.base_url);
         
-        let response = self.stream_client
+        let response = self.client
             .post(&url)
+            .timeout(Duration::from_secs(self.settings.openai.stream_timeout))
             .header("Authorization", format!("Bearer {}", self.settings.openai.api_key))
             .header("Content-Type", "application/json")
             .header("Accept", "text/event-stream")
@@ -74,25 +67,10 @@ impl OpenAIClient {
             .context("Failed to send streaming request")?;
         
         if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_default();
-            anyhow::bail!("OpenAI API request failed: {} - {}", status, error_text);
+            return Err(UpstreamError::from_response("OpenAI", response).await.into());
         }
         
-        let stream = response
-            .bytes_stream()
-            .map(|chunk_result| {
-                chunk_result
-                    .context("Failed to read streaming response chunk")
-                    .and_then(|chunk| self.parse_sse_chunk(&chunk))
-            })
-            .filter_map(|result| {
-                match result {
-                    Ok(Some(event)) => Some(Ok(event)),
-                    Ok(None) => None, // Skip empty events or non-data events
-                    Err(e) => Some(Err(e)),
-                }
-            });
+        let stream = sse::openai_chunk_stream(response.bytes_stream());
         
         Ok(stream)
     }
@@ -110,51 +88,12 @@ impl OpenAIClient {
             debug!("OpenAI request completed successfully");
             Ok(openai_response)
         } else {
-            let error_text = response.text().await.unwrap_or_default();
-            
-            // Try to parse as OpenAI error format
-            if let Ok(error_response) = serde_json::from_str::<OpenAIErrorResponse>(&error_text) {
-                error!("OpenAI API error: {:?}", error_response.error);
-                anyhow::bail!("OpenAI API error: {}", error_response.error.message);
-            } else {
-                error!("OpenAI API request failed: {} - {}", status, error_text);
-                anyhow::bail!("OpenAI API request failed: {} - {}", status, error_text);
-            }
+            let upstream_error = UpstreamError::from_response("OpenAI", response).await;
+            error!("OpenAI API request failed: {} - {}", status, upstream_error.message());
+            Err(upstream_error.into())
         }
     }
     
-    /// Parse Server-Sent Events (SSE) data chunk
-    fn parse_sse_chunk(&self, chunk: &[u8]) -> Result<Option<OpenAIStreamResponse>> {
-        let chunk_str = std::str::from_utf8(chunk)
-            .context("Invalid UTF-8 data")?;
-        
-        // SSE format: "data: {json}\n\n"
-        for line in chunk_str.lines() {
-            if let Some(data) = line.strip_prefix("data: ") {
-                // Check if it's the end marker
-                if data.trim() == "[DONE]" {
-                    debug!("Received streaming response end marker");
-                    return Ok(None);
-                }
-                
-                // Try to parse JSON data
-                match serde_json::from_str::<OpenAIStreamResponse>(data) {
-                    Ok(stream_response) => {
-                        debug!("Successfully parsed streaming response chunk");
-                        return Ok(Some(stream_response));
-                    }
-                    Err(e) => {
-                        warn!("Failed to parse streaming response chunk: {} - data: {}", e, data);
-                        // Continue processing next line, don't return error
-                    }
-                }
-            }
-        }
-        
-        // If no valid data found, return None
-        Ok(None)
-    }
-    
     /// Check API connection
     pub async fn health_check(&self) -> Result<bool> {
         debug!("Performing OpenAI API health check");
@@ -193,6 +132,7 @@ impl OpenAIClient {
         
         let response = self.client
             .get(&url)
+            .timeout(Duration::from_secs(self.settings.openai.timeout))
             .header("Authorization", format!("Bearer {}", self.settings.openai.api_key))
             .send()
             .await
@@ -220,26 +160,8 @@ impl OpenAIClient {
     }
 }
 
-/// Retry configuration
-#[derive(Debug, Clone)]
-pub struct RetryConfig {
-    /// Maximum retry attempts
-    pub max_retries: u32,
-    /// Base delay time (milliseconds)
-    pub base_delay_ms: u64,
-    /// Maximum delay time (milliseconds)
-    pub max_delay_ms: u64,
-}
-
-impl Default for RetryConfig {
-    fn default() -> Self {
-        Self {
-            max_retries: 3,
-            base_delay_ms: 1000,
-            max_delay_ms: 10000,
-        }
-    }
-}
+/// Retry configuration, shared with the per-provider `retry` option
+pub use crate::config::RetryConfig;
 
 /// Client wrapper with retry functionality
 #[derive(Debug, Clone)]
@@ -261,29 +183,12 @@ impl RetryableOpenAIClient {
     }
     
     /// Chat completion request with retry
+    ///
+    /// Only transient failures are retried; see [`retry`] for the policy.
     pub async fn chat_completions_with_retry(&self, request: OpenAIRequest) -> Result<OpenAIResponse> {
-        let mut last_error = None;
-        
-        for attempt in 0..=self.retry_config.max_retries {
-            match self.client.chat_completions(request.clone()).await {
-                Ok(response) => return Ok(response),
-                Err(e) => {
-                    last_error = Some(e);
-                    
-                    if attempt < self.retry_config.max_retries {
-                        let delay = std::cmp::min(
-                            self.retry_config.base_delay_ms * (2_u64.pow(attempt)),
-                            self.retry_config.max_delay_ms,
-                        );
-                        
-                        warn!("Request failed, retrying after {}ms (attempt {}/{})", delay, attempt + 1, self.retry_config.max_retries);
-                        tokio::time::sleep(Duration::from_millis(delay)).await;
-                    }
-                }
-            }
-        }
-        
-        Err(last_error.unwrap())
+        retry::run(Some(&self.retry_config), "OpenAI request", || {
+            self.client.chat_completions(request.clone())
+        }).await
     }
     
     /// Get inner client reference
@@ -317,6 +222,7 @@ mod tests {
             },
             request: RequestConfig {
                 max_request_size: 1024,
+                max_image_request_size: 4096,
                 max_concurrent_requests: 10,
                 timeout: 30,
             },
@@ -339,22 +245,6 @@ mod tests {
         assert!(client.is_ok());
     }
     
-    #[test]
-    fn test_sse_parsing() {
-        let settings = create_test_settings();
-        let client = OpenAIClient::new(settings).unwrap();
-        
-        // Test normal SSE data
-        let sse_data = b"data: {\"id\":\"test\",\"object\":\"chat.completion.chunk\",\"created\":1234567890,\"model\":\"gpt-4\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Hello\"},\"finish_reason\":null}]}\n\n";
-        let result = client.parse_sse_chunk(sse_data).unwrap();
-        assert!(result.is_some());
-        
-        // Test end marker
-        let done_data = b"data: [DONE]\n\n";
-        let result = client.parse_sse_chunk(done_data).unwrap();
-        assert!(result.is_none());
-    }
-    
     #[test]
     fn test_retry_config() {
         let config = RetryConfig::default();
@@ -0,0 +1,245 @@
+//! Request coalescing
+//!
+//! With `coalesceRequests` enabled, identical non-streaming requests of the
+//! same client key that arrive while the first one is still in flight (typical
+//! of client retries after a timeout) wait for that upstream call instead of
+//! making their own, and get a copy of its response. Requests are identical
+//! when their converted bodies, including the internal fields that change what
+//! is sent upstream, serialize to the same normalized JSON.
+
+use crate::models::openai::{OpenAIRequest, OpenAIResponse};
+use crate::services::router::Routed;
+use futures::future::{BoxFuture, FutureExt, Shared};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+use tracing::debug;
+
+/// Result of an upstream call, shared by all waiting requests
+pub type SharedResult = Result<Routed<OpenAIResponse>, Arc<anyhow::Error>>;
+
+type InFlight = Shared<BoxFuture<'static, SharedResult>>;
+
+/// Call in flight and the number of requests waiting for it
+struct Entry {
+    call: InFlight,
+    waiters: usize,
+}
+
+/// Single-flight deduplication of upstream calls
+#[derive(Default)]
+pub struct RequestCoalescer {
+    in_flight: Mutex<HashMap<u64, Entry>>,
+}
+
+impl RequestCoalescer {
+    /// Create a coalescer without requests in flight
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Key identifying a request of a client key
+    ///
+    /// Object keys are sorted, so the order of map entries doesn't matter.
+    pub fn key(client: Option<&str>, request: &OpenAIRequest) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        client.hash(&mut hasher);
+        serde_json::to_value(request).map(|body| body.to_string()).unwrap_or_default().hash(&mut hasher);
+        request.session_id.hash(&mut hasher);
+        request.betas.hash(&mut hasher);
+        serde_json::to_value(&request.extensions).map(|extensions| extensions.to_string()).unwrap_or_default().hash(&mut hasher);
+        hasher.finish()
+    }
+    
+    /// Run `call`, or wait for the identical request already in flight
+    ///
+    /// Returns the result and whether it came from another request. The call
+    /// keeps running while any request waits for it, even if the one that
+    /// started it goes away.
+    pub async fn run<F>(&self, key: u64, call: F) -> (SharedResult, bool)
+    where
+        F: Future<Output = anyhow::Result<Routed<OpenAIResponse>>> + Send + 'static,
+    {
+        let (in_flight, coalesced) = {
+            let mut in_flight = self.in_flight.lock().unwrap();
+            match in_flight.get_mut(&key) {
+                Some(entry) => {
+                    entry.waiters += 1;
+                    (entry.call.clone(), true)
+                }
+                None => {
+                    let shared = call.map(|result| result.map_err(Arc::new)).boxed().shared();
+                    in_flight.insert(key, Entry { call: shared.clone(), waiters: 1 });
+                    (shared, false)
+                }
+            }
+        };
+        if coalesced {
+            debug!("🔗 Waiting for identical request in flight");
+        }
+        
+        let mut guard = InFlightGuard { coalescer: self, key, in_flight: in_flight.clone(), completed: false };
+        let result = in_flight.await;
+        // A completed call is never joined, even while other requests are
+        // still picking up its result
+        guard.completed = true;
+        (result, coalesced)
+    }
+    
+    /// Number of distinct requests in flight
+    pub fn len(&self) -> usize {
+        self.in_flight.lock().unwrap().len()
+    }
+    
+    /// Whether no request is in flight
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Removes the entry of a call once it completed or its last waiting
+/// request is gone
+struct InFlightGuard<'a> {
+    coalescer: &'a RequestCoalescer,
+    key: u64,
+    in_flight: InFlight,
+    completed: bool,
+}
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        let mut in_flight = self.coalescer.in_flight.lock().unwrap();
+        let Some(entry) = in_flight.get_mut(&self.key).filter(|entry| entry.call.ptr_eq(&self.in_flight)) else {
+            return;
+        };
+        entry.waiters -= 1;
+        if self.completed || entry.waiters == 0 {
+            in_flight.remove(&self.key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    
+    fn request(content: &str) -> OpenAIRequest {
+        OpenAIRequest::builder()
+            .model("openai/gpt-4o")
+            .user(content)
+            .build()
+    }
+    
+    fn response() -> Routed<OpenAIResponse> {
+        let response = serde_json::from_value(serde_json::json!({
+            "id": "chatcmpl-1",
+            "model": "gpt-4o",
+            "choices": [{"message": {"role": "assistant", "content": "Hello!"}, "finish_reason": "stop"}]
+        })).unwrap();
+        Routed { response, model_path: "openai/gpt-4o".to_string(), fallback_from: None }
+    }
+    
+    #[test]
+    fn test_key() {
+        let key = RequestCoalescer::key(Some("laptop"), &request("Hello"));
+        assert_eq!(key, RequestCoalescer::key(Some("laptop"), &request("Hello")));
+        assert_ne!(key, RequestCoalescer::key(Some("laptop"), &request("Hi")));
+        assert_ne!(key, RequestCoalescer::key(Some("ci"), &request("Hello")));
+        assert_ne!(key, RequestCoalescer::key(None, &request("Hello")));
+        
+        let mut with_top_k = request("Hello");
+        with_top_k.extensions.insert("top_k".to_string(), serde_json::json!(40));
+        assert_ne!(key, RequestCoalescer::key(Some("laptop"), &with_top_k));
+    }
+    
+    #[tokio::test]
+    async fn test_identical_requests_share_one_call() {
+        let coalescer = RequestCoalescer::new();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let call = || {
+            let calls = calls.clone();
+            async move {
+                calls.fetch_add(1, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                Ok(response())
+            }
+        };
+        
+        let key = RequestCoalescer::key(None, &request("Hello"));
+        let ((first, first_coalesced), (second, second_coalesced)) = tokio::join!(
+            coalescer.run(key, call()),
+            coalescer.run(key, call()),
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert!(!first_coalesced);
+        assert!(second_coalesced);
+        assert_eq!(first.unwrap().response.id, second.unwrap().response.id);
+        assert!(coalescer.is_empty());
+        
+        // Once the call completed, the next identical request makes its own
+        let (_, coalesced) = coalescer.run(key, call()).await;
+        assert!(!coalesced);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+    
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_waiters_release_the_call() {
+        let coalescer = Arc::new(RequestCoalescer::new());
+        let calls = Arc::new(AtomicUsize::new(0));
+        let call = |calls: Arc<AtomicUsize>| async move {
+            calls.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            Ok(response())
+        };
+        
+        let key = RequestCoalescer::key(None, &request("Hello"));
+        let waiters: Vec<_> = (0..16)
+            .map(|_| {
+                let (coalescer, calls) = (coalescer.clone(), calls.clone());
+                tokio::spawn(async move { coalescer.run(key, call(calls)).await.1 })
+            })
+            .collect();
+        let mut coalesced = 0;
+        for waiter in waiters {
+            coalesced += waiter.await.unwrap() as usize;
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(coalesced, 15);
+        assert!(coalescer.is_empty());
+        
+        // A fresh request reaches the upstream instead of the stale result
+        let (_, coalesced) = coalescer.run(key, call(calls.clone())).await;
+        assert!(!coalesced);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+    
+    #[tokio::test]
+    async fn test_errors_are_shared() {
+        let coalescer = RequestCoalescer::new();
+        let key = RequestCoalescer::key(None, &request("Hello"));
+        let failing = async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            Err(anyhow::anyhow!("upstream failed"))
+        };
+        let ((first, _), (second, coalesced)) = tokio::join!(
+            coalescer.run(key, failing),
+            coalescer.run(key, async { Ok(response()) }),
+        );
+        assert!(coalesced);
+        assert_eq!(first.unwrap_err().to_string(), "upstream failed");
+        assert_eq!(second.unwrap_err().to_string(), "upstream failed");
+    }
+    
+    #[tokio::test]
+    async fn test_abandoned_call_is_removed() {
+        let coalescer = RequestCoalescer::new();
+        let key = RequestCoalescer::key(None, &request("Hello"));
+        let pending = coalescer.run(key, futures::future::pending());
+        assert!(tokio::time::timeout(Duration::from_millis(10), pending).await.is_err());
+        assert!(coalescer.is_empty());
+    }
+}
@@ -0,0 +1,197 @@
+//! Concurrency limit with priority classes
+//!
+//! With `concurrency` configured, at most `maxConcurrent` requests are sent
+//! upstream at a time. Further requests wait in one queue per [`Priority`]:
+//! a freed slot goes to the oldest waiting interactive request before any
+//! batch request, so background jobs can't starve editor sessions. A request
+//! holds its slot until its [`SlotPermit`] is dropped, i.e. until its
+//! response (or stream) is complete.
+
+use crate::config::{ConcurrencyConfig, Priority};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+use thiserror::Error;
+use tokio::sync::oneshot;
+use tracing::debug;
+
+/// Why a request got no slot
+#[derive(Debug, Error, PartialEq)]
+pub enum QueueError {
+    #[error("Too many requests waiting ({queued}), please try again later")]
+    QueueFull { queued: usize },
+    
+    #[error("No capacity after waiting {}s, please try again later", .0.as_secs())]
+    Timeout(Duration),
+}
+
+#[derive(Debug, Default)]
+struct QueueState {
+    /// Slots in use
+    active: usize,
+    /// Waiting requests by priority, in order of arrival
+    waiting: [VecDeque<oneshot::Sender<SlotPermit>>; 2],
+}
+
+impl QueueState {
+    /// Number of waiting requests, forgetting those that timed out or left
+    fn queued(&mut self) -> usize {
+        for waiting in &mut self.waiting {
+            waiting.retain(|sender| !sender.is_closed());
+        }
+        self.waiting.iter().map(VecDeque::len).sum()
+    }
+}
+
+/// A concurrency slot, released (or handed to the next waiting request) on drop
+#[derive(Debug)]
+pub struct SlotPermit {
+    state: Option<Arc<Mutex<QueueState>>>,
+}
+
+impl Drop for SlotPermit {
+    fn drop(&mut self) {
+        let Some(shared) = self.state.take() else {
+            return;
+        };
+        let mut state = shared.lock().unwrap_or_else(PoisonError::into_inner);
+        while let Some(waiter) = state.waiting.iter_mut().find_map(VecDeque::pop_front) {
+            match waiter.send(SlotPermit { state: Some(shared.clone()) }) {
+                Ok(()) => return,
+                // The waiting request is gone; the slot stays with this permit
+                Err(mut permit) => permit.state = None,
+            }
+        }
+        state.active -= 1;
+    }
+}
+
+/// Limit on concurrent upstream requests
+#[derive(Debug)]
+pub struct ConcurrencyLimiter {
+    config: ConcurrencyConfig,
+    state: Arc<Mutex<QueueState>>,
+}
+
+impl ConcurrencyLimiter {
+    /// Create a limiter with no requests in flight
+    pub fn new(config: ConcurrencyConfig) -> Self {
+        Self {
+            config,
+            state: Arc::default(),
+        }
+    }
+    
+    /// Limits the limiter was created with
+    pub fn config(&self) -> &ConcurrencyConfig {
+        &self.config
+    }
+    
+    /// Wait for a free slot
+    ///
+    /// Fails at once if `maxQueued` requests are already waiting, or after
+    /// `queueTimeoutMs` without a slot.
+    pub async fn acquire(&self, priority: Priority) -> Result<SlotPermit, QueueError> {
+        let mut receiver = {
+            let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+            if state.active < self.config.max_concurrent {
+                state.active += 1;
+                return Ok(SlotPermit { state: Some(self.state.clone()) });
+            }
+            
+            let queued = state.queued();
+            if self.config.max_queued.is_some_and(|max| queued >= max) {
+                return Err(QueueError::QueueFull { queued });
+            }
+            let (sender, receiver) = oneshot::channel();
+            state.waiting[priority as usize].push_back(sender);
+            debug!("🚦 All {} slots busy, queued {:?} request ({} waiting)", self.config.max_concurrent, priority, queued + 1);
+            receiver
+        };
+        
+        let timeout = Duration::from_millis(self.config.queue_timeout_ms);
+        match tokio::time::timeout(timeout, &mut receiver).await {
+            Ok(Ok(permit)) => Ok(permit),
+            _ => {
+                // A slot handed over just as the wait ended is still taken
+                receiver.close();
+                receiver.try_recv().map_err(|_| QueueError::Timeout(timeout))
+            }
+        }
+    }
+    
+    /// Number of requests waiting for a slot
+    pub fn queued(&self) -> usize {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner).queued()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn limiter(max_queued: Option<usize>, queue_timeout_ms: u64) -> Arc<ConcurrencyLimiter> {
+        Arc::new(ConcurrencyLimiter::new(ConcurrencyConfig { max_concurrent: 1, max_queued, queue_timeout_ms }))
+    }
+    
+    /// Start waiting for a slot in the background
+    async fn enqueue(limiter: &Arc<ConcurrencyLimiter>, priority: Priority) -> tokio::task::JoinHandle<Result<SlotPermit, QueueError>> {
+        let queued = limiter.queued();
+        let task = tokio::spawn({
+            let limiter = limiter.clone();
+            async move { limiter.acquire(priority).await }
+        });
+        while limiter.queued() == queued {
+            tokio::task::yield_now().await;
+        }
+        task
+    }
+    
+    #[tokio::test]
+    async fn test_interactive_dequeued_first() {
+        let limiter = limiter(None, 60000);
+        let running = limiter.acquire(Priority::Batch).await.unwrap();
+        
+        let batch = enqueue(&limiter, Priority::Batch).await;
+        let interactive = enqueue(&limiter, Priority::Interactive).await;
+        assert_eq!(limiter.queued(), 2);
+        
+        drop(running);
+        let permit = interactive.await.unwrap().unwrap();
+        assert!(!batch.is_finished());
+        
+        drop(permit);
+        let permit = batch.await.unwrap().unwrap();
+        drop(permit);
+        assert!(limiter.acquire(Priority::Batch).await.is_ok());
+    }
+    
+    #[tokio::test]
+    async fn test_queue_full_and_timeout() {
+        let limiter = limiter(Some(1), 50);
+        let _running = limiter.acquire(Priority::Interactive).await.unwrap();
+        
+        let waiting = enqueue(&limiter, Priority::Interactive).await;
+        assert_eq!(limiter.acquire(Priority::Interactive).await.unwrap_err(), QueueError::QueueFull { queued: 1 });
+        assert_eq!(waiting.await.unwrap().unwrap_err(), QueueError::Timeout(Duration::from_millis(50)));
+        
+        // Requests that timed out don't hold places in the queue
+        assert_eq!(limiter.queued(), 0);
+        let waiting = enqueue(&limiter, Priority::Batch).await;
+        assert_eq!(limiter.queued(), 1);
+        assert_eq!(waiting.await.unwrap().unwrap_err(), QueueError::Timeout(Duration::from_millis(50)));
+    }
+    
+    #[tokio::test]
+    async fn test_abandoned_wait_frees_slot() {
+        let limiter = limiter(None, 60000);
+        let running = limiter.acquire(Priority::Interactive).await.unwrap();
+        
+        // A client that disconnects while queued doesn't keep the slot
+        let abandoned = enqueue(&limiter, Priority::Interactive).await;
+        abandoned.abort();
+        let _ = abandoned.await;
+        drop(running);
+        assert!(limiter.acquire(Priority::Interactive).await.is_ok());
+    }
+}
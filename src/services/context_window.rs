@@ -0,0 +1,234 @@
+//! Context window overflow protection
+//!
+//! Before a request is sent upstream its prompt is estimated with
+//! [`TokenCounter`]. When the prompt plus the requested output would not fit
+//! the model's `contextWindow`, the model's `contextOverflow` policy decides
+//! whether the request is rejected or the oldest conversation turns are
+//! dropped until it fits.
+
+use crate::config::ModelConfig;
+use crate::models::openai::{OpenAIMessage, OpenAIRequest};
+use crate::services::TokenCounter;
+use tracing::{debug, warn};
+
+/// Reject requests that don't fit (default)
+pub const POLICY_REJECT: &str = "reject";
+
+/// Drop the oldest turns, keeping system messages and the latest turn
+pub const POLICY_TRUNCATE: &str = "truncate";
+
+/// Enforce the model's context window on a converted request
+///
+/// Returns the number of messages dropped by truncation, or the Claude
+/// `invalid_request_error` message when the request can't be sent.
+pub fn enforce(request: &mut OpenAIRequest, model_config: &ModelConfig) -> Result<usize, String> {
+    let Some(context_window) = model_config.context_window else {
+        return Ok(0);
+    };
+    
+    let counter = TokenCounter::for_model(&model_config.name);
+    let input_tokens = counter.count_request(request);
+    let max_tokens = request.max_tokens.unwrap_or(0);
+    
+    if input_tokens.saturating_add(max_tokens) <= context_window {
+        return Ok(0);
+    }
+    
+    if model_config.options.context_overflow.as_deref() == Some(POLICY_TRUNCATE) {
+        let budget = context_window.saturating_sub(max_tokens);
+        if let Some(dropped) = truncate(request, &counter, input_tokens, budget) {
+            warn!("Dropped {} oldest messages to fit {} into its {} token context window", dropped, model_config.name, context_window);
+            return Ok(dropped);
+        }
+    }
+    
+    if input_tokens > context_window {
+        Err(format!("prompt is too long: {} tokens > {} maximum", input_tokens, context_window))
+    } else {
+        Err(format!(
+            "input length and `max_tokens` exceed context limit: {} + {} > {}, decrease input length or `max_tokens` and try again",
+            input_tokens, max_tokens, context_window
+        ))
+    }
+}
+
+/// Drop whole turns from the start of the conversation until the prompt fits `budget`
+///
+/// A turn starts at a user message and runs until the next one, so an
+/// assistant tool call is never separated from its tool results. System
+/// messages and the latest turn (the current tool chain) are always kept.
+/// Returns `None` if the prompt can't be made to fit.
+fn truncate(request: &mut OpenAIRequest, counter: &TokenCounter, input_tokens: u32, budget: u32) -> Option<usize> {
+    let messages = &request.messages;
+    let last_turn = messages.iter().rposition(is_turn_start)?;
+    
+    let mut tokens = input_tokens;
+    let mut drop = vec![false; messages.len()];
+    let mut index = 0;
+    while tokens > budget && index < last_turn {
+        // Skip to the end of this turn
+        let end = messages[index + 1..last_turn].iter()
+            .position(is_turn_start)
+            .map(|offset| index + 1 + offset)
+            .unwrap_or(last_turn);
+        
+        for i in index..end {
+            if messages[i].role != "system" {
+                drop[i] = true;
+                tokens -= counter.count_message(&messages[i]);
+            }
+        }
+        index = end;
+    }
+    
+    if tokens > budget {
+        return None;
+    }
+    
+    let dropped = drop.iter().filter(|&&d| d).count();
+    let mut drop = drop.into_iter();
+    request.messages.retain(|_| !drop.next().unwrap_or(false));
+    debug!("📊 Truncated prompt from {} to {} tokens", input_tokens, tokens);
+    Some(dropped)
+}
+
+fn is_turn_start(message: &OpenAIMessage) -> bool {
+    message.role == "user"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::file::ModelOptions;
+    use crate::models::openai::{OpenAIContent, OpenAIFunctionCall, OpenAIToolCall};
+    
+    fn message(role: &str, text: &str) -> OpenAIMessage {
+        OpenAIMessage {
+            role: role.to_string(),
+            content: Some(OpenAIContent::Text(text.to_string())),
+            name: None,
+            tool_calls: None,
+            tool_call_id: None,
+        }
+    }
+    
+    fn tool_call_message(id: &str) -> OpenAIMessage {
+        OpenAIMessage {
+            role: "assistant".to_string(),
+            content: None,
+            name: None,
+            tool_calls: Some(vec![OpenAIToolCall {
+                index: None,
+                id: Some(id.to_string()),
+                tool_type: Some("function".to_string()),
+                function: OpenAIFunctionCall { name: Some("ls".to_string()), arguments: Some("{}".to_string()) },
+                signature: None,
+                extra_content: None,
+            }]),
+            tool_call_id: None,
+        }
+    }
+    
+    fn request(messages: Vec<OpenAIMessage>, max_tokens: u32) -> OpenAIRequest {
+        OpenAIRequest {
+            model: "gpt-4o".to_string(),
+            messages,
+            max_tokens: Some(max_tokens),
+            temperature: None,
+            top_p: None,
+            n: None,
+            stop: None,
+            stream: None,
+            presence_penalty: None,
+            frequency_penalty: None,
+            logit_bias: None,
+            user: None,
+            response_format: None,
+            seed: None,
+            tools: None,
+            tool_choice: None,
+            session_id: None,
+            request_id: None,
+            forwarded_headers: Vec::new(),
+            betas: Vec::new(),
+            extensions: Default::default(),
+        }
+    }
+    
+    fn model_config(context_window: u32, policy: Option<&str>) -> ModelConfig {
+        ModelConfig {
+            name: "gpt-4o".to_string(),
+            enabled: true,
+            alias: None,
+            max_tokens: None,
+            context_window: Some(context_window),
+            temperature: None,
+            options: ModelOptions {
+                context_overflow: policy.map(|p| p.to_string()),
+                ..Default::default()
+            },
+        }
+    }
+    
+    fn conversation() -> Vec<OpenAIMessage> {
+        let long = "word ".repeat(200);
+        vec![
+            message("system", "You are helpful."),
+            message("user", &long),
+            message("assistant", &long),
+            message("user", &long),
+            tool_call_message("call_1"),
+            message("tool", &long),
+            message("user", "List the files."),
+            tool_call_message("call_2"),
+            message("tool", "a.txt"),
+        ]
+    }
+    
+    #[test]
+    fn test_fits() {
+        let mut req = request(conversation(), 100);
+        assert_eq!(enforce(&mut req, &model_config(10_000, None)), Ok(0));
+        assert_eq!(req.messages.len(), 9);
+        
+        // No context window configured
+        let mut config = model_config(10, None);
+        config.context_window = None;
+        assert_eq!(enforce(&mut req, &config), Ok(0));
+    }
+    
+    #[test]
+    fn test_reject() {
+        let mut req = request(conversation(), 100);
+        let error = enforce(&mut req, &model_config(500, None)).unwrap_err();
+        assert!(error.starts_with("prompt is too long"));
+        
+        let input_tokens = TokenCounter::for_model("gpt-4o").count_request(&req);
+        let error = enforce(&mut req, &model_config(input_tokens + 50, Some(POLICY_REJECT))).unwrap_err();
+        assert!(error.starts_with("input length and `max_tokens` exceed context limit"));
+        assert_eq!(req.messages.len(), 9);
+    }
+    
+    #[test]
+    fn test_truncate() {
+        let mut req = request(conversation(), 100);
+        let dropped = enforce(&mut req, &model_config(700, Some(POLICY_TRUNCATE))).unwrap();
+        
+        // The first turn is dropped; the tool call and its result stay together
+        assert_eq!(dropped, 2);
+        let roles: Vec<&str> = req.messages.iter().map(|m| m.role.as_str()).collect();
+        assert_eq!(roles, vec!["system", "user", "assistant", "tool", "user", "assistant", "tool"]);
+        
+        // Dropping everything but the system prompt and latest turn
+        let mut req = request(conversation(), 100);
+        let dropped = enforce(&mut req, &model_config(200, Some(POLICY_TRUNCATE))).unwrap();
+        assert_eq!(dropped, 5);
+        let roles: Vec<&str> = req.messages.iter().map(|m| m.role.as_str()).collect();
+        assert_eq!(roles, vec!["system", "user", "assistant", "tool"]);
+        
+        // The latest turn alone is too long
+        let mut req = request(conversation(), 100);
+        assert!(enforce(&mut req, &model_config(100, Some(POLICY_TRUNCATE))).is_err());
+        assert_eq!(req.messages.len(), 9);
+    }
+}
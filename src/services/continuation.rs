@@ -0,0 +1,320 @@
+//! Continuation of responses cut off by the upstream's output limit
+//!
+//! Upstreams cap output tokens below what Claude clients ask for (Claude Code
+//! asks for 32000 when it writes files), and `clampStrategy: "clamp"` lowers
+//! `max_tokens` to `capOutputTokens`. With the model option
+//! `maxContinuations`, a response the upstream stops with `finish_reason:
+//! "length"` before the client's `max_tokens` is reached is continued: the
+//! request is sent again with the text so far as a trailing assistant message,
+//! up to `maxContinuations` times, and the parts are stitched into one Claude
+//! response or stream. The usage of a stitched response covers all rounds.
+//!
+//! Like stream resumption, this needs upstreams that continue a trailing
+//! assistant message. Responses with tool calls or thinking are not
+//! continued, since those can't be continued from a text prefix.
+
+use crate::config::ModelConfig;
+use crate::models::claude::{ClaudeContentBlock, ClaudeContentDelta, ClaudeMessageDelta, ClaudeStreamEvent, ClaudeUsage};
+use crate::models::openai::{OpenAIContent, OpenAIMessage, OpenAIRequest, OpenAIResponse, OpenAIStreamResponse, OpenAIUsage};
+use crate::services::tokens::TokenCounter;
+
+/// Finish reason of a response cut off by `max_tokens`
+const FINISH_LENGTH: &str = "length";
+
+/// State of a response that may be continued
+#[derive(Debug)]
+pub struct Continuation {
+    /// Request of the first round
+    request: OpenAIRequest,
+    /// `max_tokens` the client asked for
+    requested: u32,
+    max_rounds: u32,
+    rounds: u32,
+    counter: TokenCounter,
+    /// Text of the response so far
+    text: String,
+    /// False once a block that can't be continued from a text prefix was sent
+    continuable: bool,
+    /// The last round stopped at the output limit and may be continued
+    cut: bool,
+}
+
+impl Continuation {
+    /// Create the continuation state of a request, if its model continues
+    /// responses; `requested` is the client's `max_tokens`
+    pub fn new(request: &OpenAIRequest, model_config: Option<&ModelConfig>, requested: u32) -> Option<Self> {
+        let max_rounds = model_config?.options.max_continuations.filter(|rounds| *rounds > 0)?;
+        Some(Self {
+            request: request.clone(),
+            requested,
+            max_rounds,
+            rounds: 0,
+            counter: TokenCounter::for_model(&model_config?.name),
+            text: String::new(),
+            continuable: true,
+            cut: false,
+        })
+    }
+    
+    /// Number of continuation requests made
+    pub fn rounds(&self) -> u32 {
+        self.rounds
+    }
+    
+    /// Whether a response cut off now could be continued
+    fn can_continue(&self) -> bool {
+        self.continuable && self.rounds < self.max_rounds && self.counter.count_text(&self.text) < self.requested
+    }
+    
+    /// Request continuing a complete response, if it was cut off and can be continued
+    pub fn continue_response(&mut self, response: &OpenAIResponse) -> Option<OpenAIRequest> {
+        let choice = response.choices.first()?;
+        if choice.finish_reason.as_deref() != Some(FINISH_LENGTH) {
+            return None;
+        }
+        if choice.message.tool_calls.as_ref().is_some_and(|tool_calls| !tool_calls.is_empty()) {
+            self.continuable = false;
+        }
+        // The response holds the text of all rounds so far
+        match &choice.message.content {
+            Some(OpenAIContent::Text(text)) => self.text = text.clone(),
+            _ => self.continuable = false,
+        }
+        self.cut = self.can_continue();
+        self.next_request()
+    }
+    
+    /// Record an event sent to the client
+    pub fn observe(&mut self, event: &ClaudeStreamEvent) {
+        match event {
+            ClaudeStreamEvent::ContentBlockStart { content_block, .. } if !matches!(content_block, ClaudeContentBlock::Text { .. }) => {
+                self.continuable = false;
+            }
+            ClaudeStreamEvent::ContentBlockDelta { delta: ClaudeContentDelta::TextDelta { text }, .. } => {
+                self.text.push_str(text);
+            }
+            _ => {}
+        }
+    }
+    
+    /// Hold back the finish reason of a stream chunk cut off at the output
+    /// limit, if the stream can be continued
+    ///
+    /// The stream then goes on with [`next_request`](Self::next_request) when
+    /// the upstream ends.
+    pub fn defer_stop(&mut self, chunk: &mut OpenAIStreamResponse) {
+        let Some(choice) = chunk.choices.iter_mut().find(|choice| choice.index == 0) else {
+            return;
+        };
+        if choice.finish_reason.as_deref() == Some(FINISH_LENGTH) && self.can_continue() {
+            choice.finish_reason = None;
+            self.cut = true;
+        }
+    }
+    
+    /// Whether the last round was cut off and is waiting to be continued
+    pub fn is_cut(&self) -> bool {
+        self.cut
+    }
+    
+    /// Request continuing the response after the last round was cut off
+    ///
+    /// The text so far becomes a trailing assistant message and `max_tokens`
+    /// is lowered to what is left of the client's.
+    pub fn next_request(&mut self) -> Option<OpenAIRequest> {
+        if !std::mem::take(&mut self.cut) {
+            return None;
+        }
+        self.rounds += 1;
+        let mut request = self.request.clone();
+        let remaining = self.requested.saturating_sub(self.counter.count_text(&self.text)).max(1);
+        request.max_tokens = Some(request.max_tokens.map_or(remaining, |max_tokens| max_tokens.min(remaining)));
+        request.messages.push(OpenAIMessage {
+            role: "assistant".to_string(),
+            content: Some(OpenAIContent::Text(self.text.clone())),
+            name: None,
+            tool_calls: None,
+            tool_call_id: None,
+        });
+        Some(request)
+    }
+}
+
+/// Append the response to a continuation request to the response it continues
+///
+/// The continuation's finish reason replaces the cut off one and the usage
+/// is added up; it is left out if either response omitted it.
+pub fn stitch(response: &mut OpenAIResponse, continuation: OpenAIResponse) {
+    let (Some(choice), Some(next)) = (response.choices.first_mut(), continuation.choices.into_iter().next()) else {
+        return;
+    };
+    if let Some(OpenAIContent::Text(more)) = next.message.content {
+        match &mut choice.message.content {
+            Some(OpenAIContent::Text(text)) => text.push_str(&more),
+            content => *content = Some(OpenAIContent::Text(more)),
+        }
+    }
+    if next.message.tool_calls.is_some() {
+        choice.message.tool_calls = next.message.tool_calls;
+    }
+    choice.finish_reason = next.finish_reason;
+    response.usage = match (response.usage.take(), continuation.usage) {
+        (Some(usage), Some(more)) => Some(OpenAIUsage {
+            prompt_tokens: usage.prompt_tokens + more.prompt_tokens,
+            completion_tokens: usage.completion_tokens + more.completion_tokens,
+            total_tokens: usage.total_tokens + more.total_tokens,
+            prompt_tokens_details: usage.prompt_tokens_details,
+        }),
+        _ => None,
+    };
+}
+
+/// Events ending a stream whose continuation failed, as cut off at `max_tokens`
+pub fn max_tokens_events() -> Vec<ClaudeStreamEvent> {
+    vec![
+        ClaudeStreamEvent::MessageDelta {
+            delta: ClaudeMessageDelta { stop_reason: Some("max_tokens".to_string()), stop_sequence: None },
+            usage: ClaudeUsage::default(),
+        },
+        ClaudeStreamEvent::MessageStop,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::file::ModelOptions;
+    
+    fn model_config(max_continuations: Option<u32>) -> ModelConfig {
+        ModelConfig {
+            name: "deepseek-chat".to_string(),
+            enabled: true,
+            alias: None,
+            max_tokens: None,
+            context_window: None,
+            temperature: None,
+            options: ModelOptions { max_continuations, ..Default::default() },
+        }
+    }
+    
+    fn request() -> OpenAIRequest {
+        let mut request = OpenAIRequest::builder().model("deepseek-chat").user("Write a long file").build();
+        request.max_tokens = Some(8);
+        request
+    }
+    
+    fn response(message: serde_json::Value, finish_reason: &str, completion_tokens: u32) -> OpenAIResponse {
+        serde_json::from_value(serde_json::json!({
+            "id": "chatcmpl-1",
+            "model": "deepseek-chat",
+            "choices": [{"index": 0, "message": message, "finish_reason": finish_reason}],
+            "usage": {"prompt_tokens": 10, "completion_tokens": completion_tokens, "total_tokens": 10 + completion_tokens}
+        })).unwrap()
+    }
+    
+    fn text(message: &OpenAIMessage) -> &str {
+        match &message.content {
+            Some(OpenAIContent::Text(text)) => text,
+            _ => panic!("expected text content"),
+        }
+    }
+    
+    #[test]
+    fn test_continue_response() {
+        assert!(Continuation::new(&request(), Some(&model_config(None)), 100).is_none());
+        assert!(Continuation::new(&request(), Some(&model_config(Some(0))), 100).is_none());
+        
+        let mut continuation = Continuation::new(&request(), Some(&model_config(Some(2))), 100).unwrap();
+        let mut stitched = response(serde_json::json!({"role": "assistant", "content": "fn main() {\n"}), "length", 8);
+        let next = continuation.continue_response(&stitched).unwrap();
+        let prefix = next.messages.last().unwrap();
+        assert_eq!(prefix.role, "assistant");
+        assert_eq!(text(prefix), "fn main() {\n");
+        assert_eq!(next.max_tokens, Some(8));
+        
+        stitch(&mut stitched, response(serde_json::json!({"role": "assistant", "content": "    run();\n"}), "length", 8));
+        let next = continuation.continue_response(&stitched).unwrap();
+        assert_eq!(text(next.messages.last().unwrap()), "fn main() {\n    run();\n");
+        
+        stitch(&mut stitched, response(serde_json::json!({"role": "assistant", "content": "}\n"}), "stop", 2));
+        assert!(continuation.continue_response(&stitched).is_none());
+        assert_eq!(continuation.rounds(), 2);
+        assert_eq!(text(&stitched.choices[0].message), "fn main() {\n    run();\n}\n");
+        assert_eq!(stitched.choices[0].finish_reason.as_deref(), Some("stop"));
+        let usage = stitched.usage.unwrap();
+        assert_eq!((usage.prompt_tokens, usage.completion_tokens), (30, 18));
+    }
+    
+    #[test]
+    fn test_response_not_continued() {
+        // The rounds are used up
+        let mut continuation = Continuation::new(&request(), Some(&model_config(Some(1))), 100).unwrap();
+        let cut = response(serde_json::json!({"role": "assistant", "content": "Once upon"}), "length", 8);
+        assert!(continuation.continue_response(&cut).is_some());
+        assert!(continuation.continue_response(&cut).is_none());
+        
+        // The client's max_tokens is reached
+        let mut continuation = Continuation::new(&request(), Some(&model_config(Some(3))), 2).unwrap();
+        let long = response(serde_json::json!({"role": "assistant", "content": "Once upon a time there was"}), "length", 8);
+        assert!(continuation.continue_response(&long).is_none());
+        
+        // Tool calls can't be continued
+        let mut continuation = Continuation::new(&request(), Some(&model_config(Some(3))), 100).unwrap();
+        let tool_call = response(serde_json::json!({
+            "role": "assistant",
+            "content": null,
+            "tool_calls": [{"id": "call_1", "type": "function", "function": {"name": "write", "arguments": "{\"path\": \"a"}}]
+        }), "length", 8);
+        assert!(continuation.continue_response(&tool_call).is_none());
+    }
+    
+    #[test]
+    fn test_defer_stream_stop() {
+        let chunk = |finish_reason: &str| -> OpenAIStreamResponse {
+            serde_json::from_value(serde_json::json!({
+                "id": "chatcmpl-1",
+                "model": "deepseek-chat",
+                "choices": [{"index": 0, "delta": {"content": "more"}, "finish_reason": finish_reason}]
+            })).unwrap()
+        };
+        let text_delta = |text: &str| ClaudeStreamEvent::ContentBlockDelta {
+            index: 0,
+            delta: ClaudeContentDelta::TextDelta { text: text.to_string() },
+        };
+        
+        let mut continuation = Continuation::new(&request(), Some(&model_config(Some(1))), 100).unwrap();
+        let mut stopped = chunk("stop");
+        continuation.defer_stop(&mut stopped);
+        assert_eq!(stopped.choices[0].finish_reason.as_deref(), Some("stop"));
+        assert!(continuation.next_request().is_none());
+        
+        continuation.observe(&text_delta("Once upon "));
+        continuation.observe(&text_delta("a time"));
+        let mut cut = chunk("length");
+        continuation.defer_stop(&mut cut);
+        assert!(cut.choices[0].finish_reason.is_none());
+        assert!(continuation.is_cut());
+        let next = continuation.next_request().unwrap();
+        assert_eq!(text(next.messages.last().unwrap()), "Once upon a time");
+        assert!(!continuation.is_cut());
+        
+        // Out of rounds, the stop reason goes through
+        let mut cut = chunk("length");
+        continuation.defer_stop(&mut cut);
+        assert_eq!(cut.choices[0].finish_reason.as_deref(), Some("length"));
+        
+        let mut continuation = Continuation::new(&request(), Some(&model_config(Some(1))), 100).unwrap();
+        continuation.observe(&ClaudeStreamEvent::ContentBlockStart {
+            index: 0,
+            content_block: ClaudeContentBlock::ToolUse {
+                id: "toolu_1".to_string(),
+                name: "write".to_string(),
+                input: serde_json::json!({}),
+                thought_signature: None,
+            },
+        });
+        let mut cut = chunk("length");
+        continuation.defer_stop(&mut cut);
+        assert_eq!(cut.choices[0].finish_reason.as_deref(), Some("length"));
+    }
+}
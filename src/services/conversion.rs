@@ -0,0 +1,39 @@
+//! Response conversion trait
+//!
+//! [`ApiConverter`] converts provider responses back into the Claude format
+//! by default; a provider can supply a specialized [`ResponseConverter`] for
+//! its models via `Provider::response_converter`, which the handlers and
+//! [`ProxyClient`](crate::ProxyClient) use for complete and streamed
+//! responses. Requests are converted by [`ApiConverter`] before the provider
+//! is chosen.
+
+use crate::models::{claude::*, openai::*};
+use crate::services::ApiConverter;
+use anyhow::Result;
+
+/// Converts provider responses back into the Claude format
+pub trait ResponseConverter: Send + Sync {
+    /// Convert a complete response
+    fn convert_response(&self, openai_resp: OpenAIResponse, original_model: &str) -> Result<ClaudeResponse>;
+    
+    /// Convert a streaming chunk into Claude stream events
+    fn convert_stream_chunk(
+        &self,
+        openai_chunk: OpenAIStreamResponse,
+        original_model: &str,
+    ) -> Result<Vec<ClaudeStreamEvent>>;
+}
+
+impl ResponseConverter for ApiConverter {
+    fn convert_response(&self, openai_resp: OpenAIResponse, original_model: &str) -> Result<ClaudeResponse> {
+        ApiConverter::convert_response(self, openai_resp, original_model)
+    }
+    
+    fn convert_stream_chunk(
+        &self,
+        openai_chunk: OpenAIStreamResponse,
+        original_model: &str,
+    ) -> Result<Vec<ClaudeStreamEvent>> {
+        ApiConverter::convert_stream_chunk(self, openai_chunk, original_model)
+    }
+}
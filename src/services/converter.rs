@@ -2,32 +2,65 @@
 //! 
 //! Responsible for converting between Claude API and OpenAI API formats
 
-use crate::config::Settings;
+use crate::config::{MultipleChoicesMode, Settings};
 use crate::models::{
     claude::*, openai::*,
 };
+use crate::services::prompt_rules::PromptRewriter;
+use crate::services::{anthropic_tools, reasoning, sessions, TokenCounter};
 use crate::utils::thought_cache::cache_thought_signature;
 use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::sync::Arc;
 use tracing::{debug, warn};
 use uuid::Uuid;
 
+/// `max_tokens` for OpenAI requests converted to Claude without one (Claude requires it)
+const DEFAULT_CLAUDE_MAX_TOKENS: u32 = 4096;
+
+/// Stream position of web search blocks, apart from text (0) and tool calls (1..)
+const WEB_SEARCH_POSITION: u32 = u32::MAX;
+
 /// API converter
 #[derive(Debug, Clone)]
 pub struct ApiConverter {
     settings: Settings,
+    multiple_choices: MultipleChoicesMode,
+    prompt_rules: Arc<PromptRewriter>,
 }
 
 impl ApiConverter {
     /// Create a new converter instance
     pub fn new(settings: Settings) -> Self {
-        Self { settings }
+        Self { settings, multiple_choices: MultipleChoicesMode::default(), prompt_rules: Arc::default() }
+    }
+    
+    /// Set how requests for (and responses with) multiple choices are handled
+    pub fn with_multiple_choices(mut self, mode: MultipleChoicesMode) -> Self {
+        self.multiple_choices = mode;
+        self
+    }
+    
+    /// Set the system prompt rewrite rules (`promptRules`)
+    pub fn with_prompt_rules(mut self, prompt_rules: PromptRewriter) -> Self {
+        self.prompt_rules = Arc::new(prompt_rules);
+        self
+    }
+    
+    /// Get the multiple choices mode
+    pub fn multiple_choices(&self) -> MultipleChoicesMode {
+        self.multiple_choices
     }
     
     /// Convert Claude request to OpenAI request
     /// Implements the conversion logic as specified in the conversion guide
-    pub fn convert_request(&self, claude_req: ClaudeRequest) -> Result<OpenAIRequest> {
+    pub fn convert_request(&self, mut claude_req: ClaudeRequest) -> Result<OpenAIRequest> {
         debug!("Starting conversion from Claude request to OpenAI format");
         
+        if !self.prompt_rules.is_empty() {
+            claude_req.system = self.prompt_rules.apply(&claude_req.model, claude_req.system.take());
+        }
+        
         // Map model name according to conversion guide
         let openai_model = self.settings
             .get_openai_model(&claude_req.model)
@@ -38,24 +71,34 @@ impl ApiConverter {
         
         // Handle system prompt conversion as per guide
         if let Some(system) = claude_req.system {
-            let system_text = match system {
-                SystemPrompt::String(text) => text,
+            let has_cache_control = system.has_cache_control();
+            let system_content = match system {
+                SystemPrompt::String(text) => OpenAIContent::Text(text),
                 SystemPrompt::Array(blocks) => {
-                    // Merge array format into single string as per guide
-                    blocks.iter()
-                        .map(|block| match block {
-                            ClaudeContentBlock::Text { text } => text.clone(),
-                            _ => String::new(), // Skip non-text blocks in system prompt
-                        })
-                        .filter(|text| !text.is_empty())
-                        .collect::<Vec<_>>()
-                        .join("\n")
+                    let text_blocks = blocks.into_iter()
+                        .filter(|block| block.block_type == "text" && !block.text.is_empty());
+                    
+                    if has_cache_control {
+                        // Keep one part per block so cache_control markers survive
+                        OpenAIContent::Array(text_blocks
+                            .map(|block| OpenAIContentPart::Text {
+                                text: block.text,
+                                cache_control: block.cache_control,
+                            })
+                            .collect())
+                    } else {
+                        // Merge array format into single string as per guide
+                        OpenAIContent::Text(text_blocks
+                            .map(|block| block.text)
+                            .collect::<Vec<_>>()
+                            .join("\n"))
+                    }
                 }
             };
             
             openai_messages.push(OpenAIMessage {
                 role: "system".to_string(),
-                content: Some(OpenAIContent::Text(system_text)),
+                content: Some(system_content),
                 name: None,
                 tool_calls: None,
                 tool_call_id: None,
@@ -78,11 +121,9 @@ impl ApiConverter {
         
         // Extract session_id from user_id
         // Format: user_{hash}_account__session_{session-uuid}
-        let session_id = user_id.as_ref().and_then(|uid| {
-            uid.split("_session_")
-                .nth(1)
-                .map(|s| s.to_string())
-        });
+        let session_id = user_id.as_deref()
+            .and_then(sessions::session_id_from_user_id)
+            .map(|s| s.to_string());
         
         // 🔍 DEBUG: 记录metadata处理信息
         if let Some(metadata) = &claude_req.metadata {
@@ -91,23 +132,41 @@ impl ApiConverter {
                 debug!("Mapped user_id from metadata to OpenAI user field: {}", uid);
             }
             if let Some(ref sid) = session_id {
-                debug!("Extracted session_id for ModelHub: {}", sid);
+                debug!("Extracted session_id: {}", sid);
             }
         }
         
         // Convert tools if present - Claude to OpenAI format conversion
+        // The web search server tool has no function equivalent and is passed as an extension
+        let web_search = claude_req.tools.as_ref()
+            .and_then(|tools| tools.iter().find(|t| t.is_web_search()))
+            .map(|tool| serde_json::to_value(tool).unwrap_or_default());
         let openai_tools: Option<Vec<OpenAITool>> = claude_req.tools.as_ref().map(|claude_tools| {
-            claude_tools.iter().map(|claude_tool| {
-                OpenAITool {
-                    tool_type: "function".to_string(),
-                    function: OpenAIFunction {
+            claude_tools.iter().filter(|t| !t.is_web_search()).filter_map(|claude_tool| {
+                let function = match claude_tool.tool_type.as_deref() {
+                    None | Some("custom") => OpenAIFunction {
                         name: claude_tool.name.clone(),
                         description: claude_tool.description.clone(),
                         parameters: Some(claude_tool.input_schema.clone()),
                     },
-                }
-            }).collect()
-        });
+                    // Anthropic-defined tools (computer use, bash, text editor) get explicit schemas
+                    Some(tool_type) => match anthropic_tools::function_for(claude_tool) {
+                        Some(function) => {
+                            debug!("Translated {} tool '{}' to a function tool", tool_type, claude_tool.name);
+                            function
+                        }
+                        None => {
+                            warn!("Dropping unsupported tool type {} ('{}')", tool_type, claude_tool.name);
+                            return None;
+                        }
+                    },
+                };
+                Some(OpenAITool {
+                    tool_type: "function".to_string(),
+                    function,
+                })
+            }).collect::<Vec<_>>()
+        }).filter(|tools| !tools.is_empty() || web_search.is_none());
         
         debug!("Converted {} Claude tools to OpenAI format", 
                openai_tools.as_ref().map(|t| t.len()).unwrap_or(0));
@@ -119,6 +178,26 @@ impl ApiConverter {
             claude_req.max_tokens
         };
         
+        // Sampling parameters without an OpenAI equivalent are passed as extensions
+        // and only sent to providers that accept them
+        let mut extensions = HashMap::new();
+        if let Some(top_k) = claude_req.top_k {
+            extensions.insert("top_k".to_string(), serde_json::json!(top_k));
+        }
+        if let Some(web_search) = web_search {
+            extensions.insert("web_search".to_string(), web_search);
+        }
+        if let Some(metadata) = claude_req.metadata.as_ref().filter(|m| !m.is_empty()) {
+            extensions.insert("metadata".to_string(), upstream_metadata(metadata));
+        }
+        if let Some(budget) = claude_req.thinking.as_ref().and_then(|t| t.enabled_budget()) {
+            let effort = reasoning::effort_for_budget(budget);
+            debug!("Mapped thinking budget {} to reasoning effort {}", budget, effort);
+            extensions.insert(reasoning::EXTENSION_KEY.to_string(), serde_json::json!(effort));
+        }
+        
+        let n = self.requested_choices(claude_req.n, claude_req.stream);
+        
         // Build OpenAI request according to conversion guide
         let openai_req = OpenAIRequest {
             model: openai_model,
@@ -128,7 +207,7 @@ impl ApiConverter {
             top_p: claude_req.top_p,
             stop: claude_req.stop_sequences,
             stream: claude_req.stream,
-            n: Some(1), // Claude always returns a single response
+            n, // Claude returns a single response unless extra choices go to metadata
             presence_penalty: None,
             frequency_penalty: None,
             logit_bias: None,
@@ -138,6 +217,10 @@ impl ApiConverter {
             tools: openai_tools,
             tool_choice: claude_req.tool_choice.clone(),
             session_id, // For ModelHub server-side caching
+            request_id: None,
+            forwarded_headers: Vec::new(),
+            betas: claude_req.betas,
+            extensions,
         };
         
         debug!("Claude request conversion completed");
@@ -153,9 +236,140 @@ impl ApiConverter {
             anyhow::bail!("No choices in OpenAI response");
         }
         
-        let choice = &openai_resp.choices[0];
-        let message = &choice.message;
+        // Use the choice with index 0; some providers return multiple choices unexpectedly
+        let choice = openai_resp.choices.iter()
+            .find(|c| c.index == 0)
+            .unwrap_or(&openai_resp.choices[0]);
+        
+        // Build Claude content blocks according to conversion guide
+        // Web search results of search-enabled models come first, as with Claude's server tool
+        let mut content_blocks = self.convert_web_search(openai_resp.search_results.as_deref(), openai_resp.search_queries.as_deref());
+        content_blocks.extend(self.convert_message_content(&choice.message));
+        
+        // Additional choices are exposed via metadata or dropped
+        let metadata = if openai_resp.choices.len() > 1 {
+            match self.multiple_choices {
+                MultipleChoicesMode::Metadata => {
+                    let alternatives: Vec<serde_json::Value> = openai_resp.choices.iter()
+                        .filter(|c| !std::ptr::eq(*c, choice))
+                        .map(|c| serde_json::json!({
+                            "index": c.index,
+                            "content": self.convert_message_content(&c.message),
+                            "stop_reason": self.map_finish_reason_to_stop_reason(c.finish_reason.as_deref()),
+                        }))
+                        .collect();
+                    Some(serde_json::json!({ "choices": alternatives }))
+                }
+                MultipleChoicesMode::Reject => {
+                    warn!("Upstream returned {} choices, using the first one", openai_resp.choices.len());
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        
+        // Map finish reason to stop reason as per conversion guide
+        let stop_reason = self.map_finish_reason_to_stop_reason(choice.finish_reason.as_deref());
+        
+        // Extract usage info; estimate output tokens if the upstream omitted it.
+        // Claude counts prompt cache reads apart from the input tokens
+        let (input_tokens, output_tokens, cached_tokens) = match openai_resp.usage.as_ref().filter(|u| !u.is_empty()) {
+            Some(usage) => {
+                let cached_tokens = usage.cached_tokens().min(usage.prompt_tokens);
+                (usage.prompt_tokens - cached_tokens, usage.completion_tokens, cached_tokens)
+            }
+            None => {
+                let output_tokens = TokenCounter::for_model(&openai_resp.model).count_completion(&choice.message);
+                debug!("📊 Upstream omitted usage, estimated {} output tokens", output_tokens);
+                (0, output_tokens, 0)
+            }
+        };
+        
+        debug!("Converted OpenAI response: model={}, tokens={}+{}, stop_reason={}", 
+               original_model, input_tokens, output_tokens, &stop_reason);
+        
+        // Build Claude response according to conversion guide format
+        let claude_resp = ClaudeResponse {
+            id: format!("msg_{}", self.generate_id()),
+            response_type: "message".to_string(),
+            role: "assistant".to_string(),
+            content: content_blocks,
+            model: original_model.to_string(),
+            stop_reason: Some(stop_reason),
+            stop_sequence: None,
+            usage: ClaudeUsage {
+                input_tokens,
+                output_tokens,
+                cache_read_input_tokens: (cached_tokens > 0).then_some(cached_tokens),
+            },
+            metadata,
+        };
+        
+        debug!("OpenAI response conversion completed");
+        Ok(claude_resp)
+    }
+    
+    /// Number of choices to request upstream
+    ///
+    /// `n` is only forwarded in metadata mode for non-streaming requests.
+    fn requested_choices(&self, n: Option<u32>, stream: Option<bool>) -> Option<u32> {
+        match (self.multiple_choices, n, stream.unwrap_or(false)) {
+            (MultipleChoicesMode::Metadata, Some(n), false) if n > 1 => Some(n),
+            _ => Some(1),
+        }
+    }
+    
+    /// Convert upstream web search results into Claude server tool blocks
+    ///
+    /// Upstream searches are reported as a single `server_tool_use` block followed
+    /// by a `web_search_tool_result` block listing every result.
+    fn convert_web_search(
+        &self,
+        search_results: Option<&[OpenAISearchResult]>,
+        search_queries: Option<&[String]>,
+    ) -> Vec<ClaudeContentBlock> {
+        if search_results.is_none() && search_queries.is_none() {
+            return Vec::new();
+        }
+        
+        let tool_use_id = format!("srvtoolu_{}", self.generate_id());
+        let query = search_queries
+            .and_then(|queries| queries.first())
+            .cloned()
+            .unwrap_or_default();
+        let results: Vec<serde_json::Value> = search_results.into_iter()
+            .flatten()
+            .map(|result| {
+                let mut item = serde_json::json!({
+                    "type": "web_search_result",
+                    "url": result.url,
+                    "title": result.title.clone().unwrap_or_else(|| result.url.clone()),
+                });
+                if let Some(date) = &result.date {
+                    item["page_age"] = serde_json::json!(date);
+                }
+                item
+            })
+            .collect();
+        
+        debug!("Converted {} upstream web search results to Claude blocks", results.len());
         
+        vec![
+            ClaudeContentBlock::ServerToolUse {
+                id: tool_use_id.clone(),
+                name: "web_search".to_string(),
+                input: serde_json::json!({ "query": query }),
+            },
+            ClaudeContentBlock::WebSearchToolResult {
+                tool_use_id,
+                content: serde_json::Value::Array(results),
+            },
+        ]
+    }
+    
+    /// Convert an OpenAI response message into Claude content blocks
+    fn convert_message_content(&self, message: &OpenAIMessage) -> Vec<ClaudeContentBlock> {
         // Build Claude content blocks according to conversion guide
         let mut content_blocks = Vec::new();
         
@@ -212,35 +426,7 @@ impl ApiConverter {
             debug!("Converted {} OpenAI tool_calls to Claude ToolUse blocks", tool_calls.len());
         }
         
-        // Map finish reason to stop reason as per conversion guide
-        let stop_reason = self.map_finish_reason_to_stop_reason(choice.finish_reason.as_deref());
-        
-        // Extract usage info with defaults if not provided
-        let (input_tokens, output_tokens) = match &openai_resp.usage {
-            Some(usage) => (usage.prompt_tokens, usage.completion_tokens),
-            None => (0, 0), // Default to 0 if usage not provided
-        };
-        
-        debug!("Converted OpenAI response: model={}, tokens={}+{}, stop_reason={}", 
-               original_model, input_tokens, output_tokens, &stop_reason);
-        
-        // Build Claude response according to conversion guide format
-        let claude_resp = ClaudeResponse {
-            id: format!("msg_{}", self.generate_id()),
-            response_type: "message".to_string(),
-            role: "assistant".to_string(),
-            content: content_blocks,
-            model: original_model.to_string(),
-            stop_reason: Some(stop_reason),
-            stop_sequence: None,
-            usage: ClaudeUsage {
-                input_tokens,
-                output_tokens,
-            },
-        };
-        
-        debug!("OpenAI response conversion completed");
-        Ok(claude_resp)
+        content_blocks
     }
     
     /// Convert OpenAI stream response to Claude stream events
@@ -258,7 +444,11 @@ impl ApiConverter {
             return Ok(events);
         }
         
-        let choice = &openai_chunk.choices[0];
+        // Only the choice with index 0 is streamed; chunks for other choices are skipped
+        let Some(choice) = openai_chunk.choices.iter().find(|c| c.index == 0) else {
+            debug!("Skipping stream chunk without choice 0");
+            return Ok(events);
+        };
         let delta = &choice.delta;
         
         // Generate message_start event for first chunk (contains role)
@@ -272,10 +462,8 @@ impl ApiConverter {
                     model: original_model.to_string(),
                     stop_reason: None,
                     stop_sequence: None,
-                    usage: ClaudeUsage {
-                        input_tokens: 0,
-                        output_tokens: 0,
-                    },
+                    usage: ClaudeUsage::default(),
+                    fallback: None,
                 },
             });
             
@@ -302,6 +490,8 @@ impl ApiConverter {
         if let Some(tool_calls) = &delta.tool_calls {
             for (i, tool_call) in tool_calls.iter().enumerate() {
                 let function = &tool_call.function;
+                // Tool blocks follow the text block; ContentBlockTracker assigns the final indices
+                let block_index = tool_call.index.unwrap_or(i as u32) + 1;
                 
                 if let Some(name) = &function.name {
                     // Extract thought_signature if present
@@ -327,7 +517,7 @@ impl ApiConverter {
                     
                     // Tool use content block start
                     events.push(ClaudeStreamEvent::ContentBlockStart {
-                        index: (i + 1) as u32,
+                        index: block_index,
                         content_block: ClaudeContentBlock::ToolUse {
                             id: tool_id,
                             name: name.clone(),
@@ -340,7 +530,7 @@ impl ApiConverter {
                 if let Some(arguments) = &function.arguments {
                     // Tool input delta (partial JSON)
                     events.push(ClaudeStreamEvent::ContentBlockDelta {
-                        index: (i + 1) as u32,
+                        index: block_index,
                         delta: ClaudeContentDelta::InputJsonDelta {
                             partial_json: arguments.clone(),
                         },
@@ -354,11 +544,22 @@ impl ApiConverter {
             // Content block stop events
             events.push(ClaudeStreamEvent::ContentBlockStop { index: 0 });
             
+            // Web search results are sent with the finish chunk, since search-enabled
+            // upstreams repeat them on every chunk
+            let search_blocks = self.convert_web_search(
+                openai_chunk.search_results.as_deref(),
+                openai_chunk.search_queries.as_deref(),
+            );
+            for content_block in search_blocks {
+                events.push(ClaudeStreamEvent::ContentBlockStart { index: WEB_SEARCH_POSITION, content_block });
+                events.push(ClaudeStreamEvent::ContentBlockStop { index: WEB_SEARCH_POSITION });
+            }
+            
             // Stop tool use blocks if any
             if let Some(tool_calls) = &delta.tool_calls {
-                for i in 0..tool_calls.len() {
+                for (i, tool_call) in tool_calls.iter().enumerate() {
                     events.push(ClaudeStreamEvent::ContentBlockStop { 
-                        index: (i + 1) as u32 
+                        index: tool_call.index.unwrap_or(i as u32) + 1
                     });
                 }
             }
@@ -370,10 +571,7 @@ impl ApiConverter {
                     stop_reason: Some(stop_reason),
                     stop_sequence: None,
                 },
-                usage: ClaudeUsage {
-                    input_tokens: 0,
-                    output_tokens: 0,
-                },
+                usage: ClaudeUsage::default(),
             });
             
             // Final message stop event
@@ -421,6 +619,114 @@ impl ApiConverter {
          }
     }
     
+    /// Convert an OpenAI chat completion request to a Claude request
+    ///
+    /// The reverse of [`convert_request`](Self::convert_request), for sending
+    /// OpenAI-format requests to Claude upstreams. System and developer
+    /// messages become the system prompt, tool calls and tool messages become
+    /// `tool_use`/`tool_result` blocks, and consecutive messages with the same
+    /// role are merged since Claude requires alternating roles.
+    pub fn convert_openai_request_to_claude(&self, openai_req: OpenAIRequest) -> Result<ClaudeRequest> {
+        debug!("Starting conversion from OpenAI request to Claude format");
+        
+        let mut system_parts = Vec::new();
+        let mut messages: Vec<ClaudeMessage> = Vec::new();
+        
+        for message in openai_req.messages {
+            let (role, blocks) = match message.role.as_str() {
+                "system" | "developer" => {
+                    if let Some(content) = message.content {
+                        system_parts.push(openai_content_text(&content));
+                    }
+                    continue;
+                }
+                "user" => ("user", claude_blocks_from_openai(message.content)),
+                "assistant" => {
+                    let mut blocks = claude_blocks_from_openai(message.content);
+                    for tool_call in message.tool_calls.into_iter().flatten() {
+                        let arguments = tool_call.function.arguments.unwrap_or_default();
+                        let input = if arguments.trim().is_empty() {
+                            serde_json::json!({})
+                        } else {
+                            serde_json::from_str(&arguments).unwrap_or_else(|e| {
+                                warn!("Invalid tool call arguments, sending an empty input: {}", e);
+                                serde_json::json!({})
+                            })
+                        };
+                        blocks.push(ClaudeContentBlock::ToolUse {
+                            id: tool_call.id
+                                .filter(|id| !id.is_empty())
+                                .unwrap_or_else(|| format!("toolu_{}", self.generate_id())),
+                            name: tool_call.function.name.unwrap_or_default(),
+                            input,
+                            thought_signature: None,
+                        });
+                    }
+                    ("assistant", blocks)
+                }
+                "tool" => {
+                    let tool_use_id = message.tool_call_id
+                        .context("Tool message without tool_call_id")?;
+                    let text = message.content.as_ref().map(openai_content_text).unwrap_or_default();
+                    ("user", vec![ClaudeContentBlock::ToolResult {
+                        tool_use_id,
+                        content: ClaudeToolResultContent::Text(text),
+                        is_error: None,
+                    }])
+                }
+                other => anyhow::bail!("Unsupported message role: {}", other),
+            };
+            
+            match messages.last_mut() {
+                Some(ClaudeMessage { role: last_role, content: ClaudeContent::Blocks(last_blocks) }) if last_role == role => {
+                    last_blocks.extend(blocks);
+                }
+                _ => messages.push(ClaudeMessage {
+                    role: role.to_string(),
+                    content: ClaudeContent::Blocks(blocks),
+                }),
+            }
+        }
+        
+        let tools = openai_req.tools.map(|tools| {
+            tools.into_iter()
+                .map(|tool| ClaudeTool {
+                    tool_type: None,
+                    name: tool.function.name,
+                    description: tool.function.description,
+                    input_schema: tool.function.parameters
+                        .unwrap_or_else(|| serde_json::json!({"type": "object"})),
+                    extra: HashMap::new(),
+                })
+                .collect()
+        });
+        
+        let claude_req = ClaudeRequest {
+            model: openai_req.model,
+            max_tokens: openai_req.max_tokens.unwrap_or(DEFAULT_CLAUDE_MAX_TOKENS),
+            messages,
+            system: (!system_parts.is_empty()).then(|| SystemPrompt::String(system_parts.join("\n\n"))),
+            // Claude's temperature range is 0-1
+            temperature: openai_req.temperature.map(|t| t.min(1.0)),
+            top_p: openai_req.top_p,
+            top_k: openai_req.extensions.get("top_k")
+                .and_then(|v| v.as_u64())
+                .map(|v| v as u32),
+            stop_sequences: openai_req.stop,
+            stream: openai_req.stream,
+            metadata: openai_req.user
+                .map(|user| HashMap::from([("user_id".to_string(), serde_json::json!(user))])),
+            tools,
+            tool_choice: openai_req.tool_choice.as_ref().map(claude_tool_choice),
+            n: openai_req.n,
+            thinking: None,
+            betas: openai_req.betas,
+        };
+        
+        debug!("Converted OpenAI request to Claude format with {} messages", claude_req.messages.len());
+        Ok(claude_req)
+    }
+    
     /// Convert Claude message to OpenAI messages
     /// May return multiple messages (e.g., tool results become separate "tool" role messages)
     fn convert_claude_message_to_openai_messages(&self, claude_msg: ClaudeMessage) -> Result<Vec<OpenAIMessage>> {
@@ -445,23 +751,12 @@ impl ApiConverter {
                 for block in blocks {
                     match block {
                         ClaudeContentBlock::Text { text } => {
-                            openai_parts.push(OpenAIContentPart::Text { text });
+                            openai_parts.push(OpenAIContentPart::Text { text, cache_control: None });
                         }
                         ClaudeContentBlock::Image { source } => {
-                            // Convert Claude image format to OpenAI format
-                            let image_url = if source.source_type == "base64" {
-                                format!("data:{};base64,{}", source.media_type, source.data)
-                            } else {
-                                warn!("Unsupported image source type: {}", source.source_type);
-                                continue;
-                            };
-                            
-                            openai_parts.push(OpenAIContentPart::ImageUrl {
-                                image_url: OpenAIImageUrl {
-                                    url: image_url,
-                                    detail: Some("auto".to_string()),
-                                },
-                            });
+                            if let Some(part) = convert_image_source(&source) {
+                                openai_parts.push(part);
+                            }
                         }
                         ClaudeContentBlock::ToolUse { id, name, input, thought_signature } => {
                             // Convert Claude ToolUse to OpenAI tool call format
@@ -476,6 +771,7 @@ impl ApiConverter {
                             });
                             
                             tool_calls.push(OpenAIToolCall {
+                                index: None,
                                 id: Some(id),
                                 tool_type: Some("function".to_string()),
                                 function: OpenAIFunctionCall {
@@ -490,6 +786,10 @@ impl ApiConverter {
                             // Collect tool results to be sent as separate "tool" role messages
                             tool_results.push((tool_use_id, content, is_error));
                         }
+                        ClaudeContentBlock::ServerToolUse { .. } | ClaudeContentBlock::WebSearchToolResult { .. } => {
+                            // Server tool calls were executed upstream and cannot be replayed
+                            debug!("Skipping server tool block in message conversion");
+                        }
                         ClaudeContentBlock::Unknown => {
                             // Skip unknown block types
                             warn!("Skipping unknown content block type in message conversion");
@@ -507,15 +807,49 @@ impl ApiConverter {
         
         // If this message has tool results, create separate "tool" role messages for each
         if !tool_results.is_empty() {
+            // Tool messages only carry text; images returned by tools and the
+            // message's own content are sent in a user message following the
+            // tool results
+            let mut user_parts = Vec::new();
             for (tool_call_id, result_content, _is_error) in tool_results {
+                let images: Vec<OpenAIContentPart> = result_content.images().into_iter()
+                    .filter_map(convert_image_source)
+                    .collect();
+                let mut text = result_content.text();
+                if !images.is_empty() {
+                    debug!("Moving {} images of tool result {} to a user message", images.len(), tool_call_id);
+                    if text.is_empty() {
+                        text = format!("[{} image(s) attached in the next message]", images.len());
+                    }
+                    user_parts.push(OpenAIContentPart::Text {
+                        text: format!("Images returned by tool call {}:", tool_call_id),
+                        cache_control: None,
+                    });
+                    user_parts.extend(images);
+                }
+                
                 messages.push(OpenAIMessage {
                     role: "tool".to_string(),
-                    content: Some(OpenAIContent::Text(result_content)),
+                    content: Some(OpenAIContent::Text(text)),
                     name: None,
                     tool_calls: None,
                     tool_call_id: Some(tool_call_id),
                 });
             }
+            match content {
+                Some(OpenAIContent::Array(parts)) => user_parts.extend(parts),
+                Some(OpenAIContent::Text(text)) => user_parts.push(OpenAIContentPart::Text { text, cache_control: None }),
+                None => {}
+            }
+            if !user_parts.is_empty() {
+                messages.push(OpenAIMessage {
+                    role: "user".to_string(),
+                    content: Some(OpenAIContent::Array(user_parts)),
+                    name: None,
+                    tool_calls: None,
+                    tool_call_id: None,
+                });
+            }
             return Ok(messages);
         }
         
@@ -542,7 +876,7 @@ impl ApiConverter {
         match finish_reason {
             Some("stop") => "end_turn".to_string(),
             Some("length") => "max_tokens".to_string(),
-            Some("content_filter") => "stop_sequence".to_string(),
+            Some("content_filter") => "refusal".to_string(),
             Some("tool_calls") => "tool_use".to_string(),
             Some(other) => {
                 warn!("Unknown finish_reason: {}", other);
@@ -583,6 +917,283 @@ impl ApiConverter {
     fn generate_id(&self) -> String {
         Uuid::new_v4().simple().to_string()
     }
+    
+    /// Report the stop sequence that ended a response
+    ///
+    /// Upstreams report a stop-sequence hit as a plain "stop", so the tail of the
+    /// final text block is matched against the requested sequences. A matched
+    /// sequence is stripped from the text, as Claude does.
+    pub fn apply_stop_sequences(&self, response: &mut ClaudeResponse, stop_sequences: &[String]) {
+        if response.stop_reason.as_deref() != Some("end_turn") {
+            return;
+        }
+        
+        let Some(ClaudeContentBlock::Text { text }) = response.content.last_mut() else {
+            return;
+        };
+        
+        if let Some(sequence) = match_stop_sequence(text, stop_sequences) {
+            debug!("Response ended with stop sequence: {:?}", sequence);
+            text.truncate(text.len() - sequence.len());
+            response.stop_reason = Some("stop_sequence".to_string());
+            response.stop_sequence = Some(sequence);
+        }
+    }
+}
+
+/// Find the requested stop sequence that the text ends with (longest match wins)
+pub fn match_stop_sequence(text: &str, stop_sequences: &[String]) -> Option<String> {
+    stop_sequences
+        .iter()
+        .filter(|seq| !seq.is_empty() && text.ends_with(seq.as_str()))
+        .max_by_key(|seq| seq.len())
+        .cloned()
+}
+
+/// Tracks streamed text to report stop sequences in the final message_delta
+///
+/// Streaming counterpart of [`ApiConverter::apply_stop_sequences`]; only the
+/// tail of the output is kept.
+#[derive(Debug, Clone, Default)]
+pub struct StopSequenceTracker {
+    stop_sequences: Vec<String>,
+    tail: String,
+    max_len: usize,
+}
+
+impl StopSequenceTracker {
+    /// Create a tracker for the requested stop sequences
+    pub fn new(stop_sequences: Vec<String>) -> Self {
+        let max_len = stop_sequences.iter().map(|s| s.len()).max().unwrap_or(0);
+        Self { stop_sequences, tail: String::new(), max_len }
+    }
+    
+    /// Observe an outgoing event, updating the stop reason of message_delta if needed
+    pub fn observe(&mut self, event: &mut ClaudeStreamEvent) {
+        if self.max_len == 0 {
+            return;
+        }
+        
+        match event {
+            ClaudeStreamEvent::ContentBlockDelta { delta: ClaudeContentDelta::TextDelta { text }, .. } => {
+                self.tail.push_str(text);
+                if self.tail.len() > self.max_len {
+                    let mut cut = self.tail.len() - self.max_len;
+                    while !self.tail.is_char_boundary(cut) {
+                        cut -= 1;
+                    }
+                    self.tail.drain(..cut);
+                }
+            }
+            ClaudeStreamEvent::MessageDelta { delta, .. } if delta.stop_reason.as_deref() == Some("end_turn") => {
+                if let Some(sequence) = match_stop_sequence(&self.tail, &self.stop_sequences) {
+                    delta.stop_reason = Some("stop_sequence".to_string());
+                    delta.stop_sequence = Some(sequence);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Content block bookkeeping for a streamed response
+///
+/// The stream converter works chunk by chunk and labels blocks by their
+/// upstream position: 0 for text and `n + 1` for tool call `n`. The tracker
+/// turns this into the block sequence Claude clients expect: blocks get
+/// consecutive indices in the order they start, text blocks are opened by
+/// their first delta (text after a tool call starts a new block), and the
+/// open block is stopped before the next one starts and before `message_delta`.
+#[derive(Debug, Default)]
+pub struct ContentBlockTracker {
+    /// Upstream position and Claude index of the open block
+    open: Option<(u32, u32)>,
+    /// Claude indices of started tool blocks by upstream position
+    tool_blocks: HashMap<u32, u32>,
+    next_index: u32,
+}
+
+impl ContentBlockTracker {
+    /// Create a tracker for a new stream
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Process a converted event, returning the events to send in its place
+    pub fn process(&mut self, event: ClaudeStreamEvent) -> Vec<ClaudeStreamEvent> {
+        let mut events = Vec::new();
+        
+        match event {
+            ClaudeStreamEvent::MessageStart { .. } => {
+                *self = Self::default();
+                events.push(event);
+            }
+            // Text blocks are started by their first delta
+            ClaudeStreamEvent::ContentBlockStart { content_block: ClaudeContentBlock::Text { .. }, .. } => {}
+            ClaudeStreamEvent::ContentBlockStart { index: position, content_block } => {
+                let index = self.start_block(position, &mut events);
+                if matches!(content_block, ClaudeContentBlock::ToolUse { .. }) {
+                    self.tool_blocks.insert(position, index);
+                }
+                events.push(ClaudeStreamEvent::ContentBlockStart { index, content_block });
+            }
+            ClaudeStreamEvent::ContentBlockDelta { index: position, delta } => {
+                let index = match (self.open, &delta) {
+                    (Some((open_position, index)), _) if open_position == position => index,
+                    (_, ClaudeContentDelta::TextDelta { .. }) => {
+                        let index = self.start_block(position, &mut events);
+                        events.push(ClaudeStreamEvent::ContentBlockStart {
+                            index,
+                            content_block: ClaudeContentBlock::Text { text: String::new() },
+                        });
+                        index
+                    }
+                    (_, ClaudeContentDelta::InputJsonDelta { .. }) => match self.tool_blocks.get(&position) {
+                        // Late arguments of an earlier tool call
+                        Some(&index) => index,
+                        None => {
+                            warn!("Dropping arguments of tool call {} which was never started", position);
+                            return events;
+                        }
+                    },
+                };
+                events.push(ClaudeStreamEvent::ContentBlockDelta { index, delta });
+            }
+            ClaudeStreamEvent::ContentBlockStop { index: position } => {
+                if self.open.is_some_and(|(open_position, _)| open_position == position) {
+                    self.stop_block(&mut events);
+                }
+            }
+            ClaudeStreamEvent::MessageDelta { .. } | ClaudeStreamEvent::MessageStop => {
+                self.stop_block(&mut events);
+                events.push(event);
+            }
+            _ => events.push(event),
+        }
+        
+        events
+    }
+    
+    /// Stop the open block and start a new one, returning its index
+    fn start_block(&mut self, position: u32, events: &mut Vec<ClaudeStreamEvent>) -> u32 {
+        self.stop_block(events);
+        let index = self.next_index;
+        self.next_index += 1;
+        self.open = Some((position, index));
+        index
+    }
+    
+    fn stop_block(&mut self, events: &mut Vec<ClaudeStreamEvent>) {
+        if let Some((_, index)) = self.open.take() {
+            events.push(ClaudeStreamEvent::ContentBlockStop { index });
+        }
+    }
+}
+
+/// Convert a Claude image source to an OpenAI image part
+fn convert_image_source(source: &ClaudeImageSource) -> Option<OpenAIContentPart> {
+    let url = match (source.source_type.as_str(), &source.url) {
+        ("base64", _) => format!("data:{};base64,{}", source.media_type, source.data),
+        ("url", Some(url)) => url.clone(),
+        _ => {
+            warn!("Unsupported image source type: {}", source.source_type);
+            return None;
+        }
+    };
+    
+    Some(OpenAIContentPart::ImageUrl {
+        image_url: OpenAIImageUrl {
+            url,
+            detail: Some("auto".to_string()),
+        },
+    })
+}
+
+/// Concatenated text of OpenAI message content
+fn openai_content_text(content: &OpenAIContent) -> String {
+    match content {
+        OpenAIContent::Text(text) => text.clone(),
+        OpenAIContent::Array(parts) => parts.iter()
+            .filter_map(|part| match part {
+                OpenAIContentPart::Text { text, .. } => Some(text.as_str()),
+                OpenAIContentPart::ImageUrl { .. } => None,
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+    }
+}
+
+/// Convert OpenAI message content to Claude content blocks (empty text is dropped)
+fn claude_blocks_from_openai(content: Option<OpenAIContent>) -> Vec<ClaudeContentBlock> {
+    let parts = match content {
+        Some(OpenAIContent::Text(text)) => vec![OpenAIContentPart::Text { text, cache_control: None }],
+        Some(OpenAIContent::Array(parts)) => parts,
+        None => Vec::new(),
+    };
+    
+    parts.into_iter()
+        .filter_map(|part| match part {
+            OpenAIContentPart::Text { text, .. } if text.is_empty() => None,
+            OpenAIContentPart::Text { text, .. } => Some(ClaudeContentBlock::Text { text }),
+            OpenAIContentPart::ImageUrl { image_url } => Some(ClaudeContentBlock::Image {
+                source: claude_image_source(&image_url.url),
+            }),
+        })
+        .collect()
+}
+
+/// Convert an OpenAI image URL (data URL or remote URL) to a Claude image source
+fn claude_image_source(url: &str) -> ClaudeImageSource {
+    let data_url = url.strip_prefix("data:")
+        .and_then(|rest| rest.split_once(";base64,"));
+    match data_url {
+        Some((media_type, data)) => ClaudeImageSource {
+            source_type: "base64".to_string(),
+            media_type: media_type.to_string(),
+            data: data.to_string(),
+            url: None,
+        },
+        None => ClaudeImageSource {
+            source_type: "url".to_string(),
+            media_type: String::new(),
+            data: String::new(),
+            url: Some(url.to_string()),
+        },
+    }
+}
+
+/// Map an OpenAI `tool_choice` to the Claude format
+///
+/// Values already in the Claude format are passed through.
+fn claude_tool_choice(tool_choice: &serde_json::Value) -> serde_json::Value {
+    match tool_choice.as_str() {
+        Some("required") => return serde_json::json!({"type": "any"}),
+        Some(choice) => return serde_json::json!({"type": choice}),
+        None => {}
+    }
+    
+    match tool_choice.pointer("/function/name") {
+        Some(name) if tool_choice.get("type").and_then(|t| t.as_str()) == Some("function") => {
+            serde_json::json!({"type": "tool", "name": name})
+        }
+        _ => tool_choice.clone(),
+    }
+}
+
+/// Convert Claude `metadata` into an upstream `metadata` object
+///
+/// OpenAI-style APIs only accept string values, so other values are sent as JSON text.
+fn upstream_metadata(metadata: &HashMap<String, serde_json::Value>) -> serde_json::Value {
+    let map: serde_json::Map<String, serde_json::Value> = metadata.iter()
+        .map(|(key, value)| {
+            let value = match value {
+                serde_json::Value::String(s) => s.clone(),
+                other => other.to_string(),
+            };
+            (key.clone(), serde_json::Value::String(value))
+        })
+        .collect();
+    serde_json::Value::Object(map)
 }
 
 #[cfg(test)]
@@ -611,6 +1222,7 @@ mod tests {
             },
             request: RequestConfig {
                 max_request_size: 1024,
+                max_image_request_size: 4096,
                 max_concurrent_requests: 10,
                 timeout: 30,
             },
@@ -649,6 +1261,332 @@ mod tests {
         assert_eq!(openai_req.messages[0].role, "user");
     }
     
+    #[test]
+    fn test_convert_request_top_k_extension() {
+        let settings = create_test_settings();
+        let converter = ApiConverter::new(settings);
+        
+        let claude_req = ClaudeRequest {
+            model: "claude-3-sonnet".to_string(),
+            max_tokens: 100,
+            messages: vec![ClaudeMessage {
+                role: "user".to_string(),
+                content: ClaudeContent::Text("Hello".to_string()),
+            }],
+            top_k: Some(40),
+            ..Default::default()
+        };
+        
+        let openai_req = converter.convert_request(claude_req).unwrap();
+        
+        assert_eq!(openai_req.extensions.get("top_k"), Some(&serde_json::json!(40)));
+    }
+    
+    #[test]
+    fn test_convert_request_prompt_rules() {
+        let rules = [crate::config::PromptRule {
+            model: Some("haiku".to_string()),
+            append: Some("Answer concisely.".to_string()),
+            strip_boilerplate: true,
+            ..Default::default()
+        }];
+        let converter = ApiConverter::new(create_test_settings())
+            .with_prompt_rules(PromptRewriter::new(&rules).unwrap());
+        
+        let claude_req = |model: &str| ClaudeRequest {
+            model: model.to_string(),
+            max_tokens: 100,
+            system: Some(SystemPrompt::String("You are Claude Code, Anthropic's official CLI for Claude.\nHelp with code.".to_string())),
+            messages: vec![ClaudeMessage {
+                role: "user".to_string(),
+                content: ClaudeContent::Text("Hello".to_string()),
+            }],
+            ..Default::default()
+        };
+        
+        let openai_req = converter.convert_request(claude_req("claude-3-haiku")).unwrap();
+        assert!(matches!(
+            &openai_req.messages[0].content,
+            Some(OpenAIContent::Text(text)) if text == "Help with code.\n\nAnswer concisely."
+        ));
+        let openai_req = converter.convert_request(claude_req("claude-3-sonnet")).unwrap();
+        assert!(matches!(
+            &openai_req.messages[0].content,
+            Some(OpenAIContent::Text(text)) if text.starts_with("You are Claude Code")
+        ));
+    }
+    
+    #[test]
+    fn test_convert_request_metadata() {
+        let converter = ApiConverter::new(create_test_settings());
+        let claude_req = ClaudeRequest {
+            model: "claude-3-sonnet".to_string(),
+            max_tokens: 100,
+            messages: vec![ClaudeMessage {
+                role: "user".to_string(),
+                content: ClaudeContent::Text("Hello".to_string()),
+            }],
+            metadata: Some(HashMap::from([
+                ("user_id".to_string(), serde_json::json!("user_1")),
+                ("team".to_string(), serde_json::json!("search")),
+                ("priority".to_string(), serde_json::json!(2)),
+            ])),
+            ..Default::default()
+        };
+        
+        let openai_req = converter.convert_request(claude_req).unwrap();
+        
+        assert_eq!(openai_req.user.as_deref(), Some("user_1"));
+        let metadata = openai_req.extensions.get("metadata").unwrap();
+        assert_eq!(metadata["team"], "search");
+        assert_eq!(metadata["priority"], "2");
+        assert_eq!(metadata["user_id"], "user_1");
+    }
+    
+    #[test]
+    fn test_convert_request_thinking_budget() {
+        let converter = ApiConverter::new(create_test_settings());
+        let claude_req: ClaudeRequest = serde_json::from_value(serde_json::json!({
+            "model": "claude-3-sonnet",
+            "max_tokens": 16000,
+            "messages": [{"role": "user", "content": "Think hard"}],
+            "thinking": {"type": "enabled", "budget_tokens": 10000}
+        })).unwrap();
+        
+        let openai_req = converter.convert_request(claude_req.clone()).unwrap();
+        assert_eq!(openai_req.extensions.get("reasoning_effort"), Some(&serde_json::json!("medium")));
+        
+        let claude_req = ClaudeRequest {
+            thinking: Some(ClaudeThinking { thinking_type: "disabled".to_string(), budget_tokens: None }),
+            ..claude_req
+        };
+        let openai_req = converter.convert_request(claude_req).unwrap();
+        assert!(!openai_req.extensions.contains_key("reasoning_effort"));
+    }
+    
+    #[test]
+    fn test_convert_request_tool_result_images() {
+        let converter = ApiConverter::new(create_test_settings());
+        let claude_req: ClaudeRequest = serde_json::from_value(serde_json::json!({
+            "model": "claude-3-sonnet",
+            "max_tokens": 100,
+            "messages": [
+                {"role": "user", "content": "Take screenshots"},
+                {"role": "assistant", "content": [
+                    {"type": "tool_use", "id": "toolu_1", "name": "screenshot", "input": {}},
+                    {"type": "tool_use", "id": "toolu_2", "name": "ls", "input": {}}
+                ]},
+                {"role": "user", "content": [
+                    {"type": "tool_result", "tool_use_id": "toolu_1", "content": [
+                        {"type": "text", "text": "Captured"},
+                        {"type": "image", "source": {"type": "base64", "media_type": "image/png", "data": "iVBORw0KGgo="}}
+                    ]},
+                    {"type": "tool_result", "tool_use_id": "toolu_2", "content": "a.txt"},
+                    {"type": "text", "text": "Compare them"}
+                ]}
+            ]
+        })).unwrap();
+        
+        let openai_req = converter.convert_request(claude_req).unwrap();
+        let roles: Vec<&str> = openai_req.messages.iter().map(|m| m.role.as_str()).collect();
+        assert_eq!(roles, vec!["user", "assistant", "tool", "tool", "user"]);
+        
+        assert_eq!(openai_req.messages[2].content.as_ref().unwrap().extract_text(), "Captured");
+        assert_eq!(openai_req.messages[3].content.as_ref().unwrap().extract_text(), "a.txt");
+        
+        // Images and the text next to the tool results follow in a user message
+        let Some(OpenAIContent::Array(parts)) = &openai_req.messages[4].content else {
+            panic!("expected image parts");
+        };
+        assert_eq!(parts.len(), 3);
+        let OpenAIContentPart::ImageUrl { image_url } = &parts[1] else {
+            panic!("expected image_url part");
+        };
+        assert_eq!(image_url.url, "data:image/png;base64,iVBORw0KGgo=");
+        assert!(matches!(&parts[2], OpenAIContentPart::Text { text, .. } if text == "Compare them"));
+    }
+    
+    #[test]
+    fn test_convert_request_anthropic_tools() {
+        let converter = ApiConverter::new(create_test_settings());
+        let claude_req: ClaudeRequest = serde_json::from_value(serde_json::json!({
+            "model": "claude-3-sonnet",
+            "max_tokens": 100,
+            "messages": [{"role": "user", "content": "Open the browser"}],
+            "tools": [
+                {"type": "computer_20250124", "name": "computer", "display_width_px": 1280, "display_height_px": 800},
+                {"type": "bash_20250124", "name": "bash"},
+                {"type": "code_execution_20250522", "name": "code_execution"},
+                {"name": "get_weather", "input_schema": {"type": "object", "properties": {}}}
+            ]
+        })).unwrap();
+        
+        let openai_req = converter.convert_request(claude_req).unwrap();
+        let tools = openai_req.tools.unwrap();
+        let names: Vec<&str> = tools.iter().map(|t| t.function.name.as_str()).collect();
+        assert_eq!(names, vec!["computer", "bash", "get_weather"]);
+        assert!(tools.iter().all(|t| t.tool_type == "function"));
+        assert_eq!(tools[0].function.parameters.as_ref().unwrap()["type"], "object");
+    }
+    
+    #[test]
+    fn test_convert_request_web_search_tool() {
+        let converter = ApiConverter::new(create_test_settings());
+        
+        let claude_req: ClaudeRequest = serde_json::from_value(serde_json::json!({
+            "model": "claude-3-sonnet",
+            "max_tokens": 100,
+            "messages": [{"role": "user", "content": "What's new in Rust?"}],
+            "tools": [{"type": "web_search_20250305", "name": "web_search", "max_uses": 3}]
+        })).unwrap();
+        
+        let openai_req = converter.convert_request(claude_req).unwrap();
+        
+        assert!(openai_req.tools.is_none());
+        let web_search = openai_req.extensions.get("web_search").unwrap();
+        assert_eq!(web_search["type"], "web_search_20250305");
+        assert_eq!(web_search["max_uses"], 3);
+    }
+    
+    #[test]
+    fn test_convert_openai_request_to_claude() {
+        let converter = ApiConverter::new(create_test_settings());
+        
+        let openai_req: OpenAIRequest = serde_json::from_value(serde_json::json!({
+            "model": "anthropic/claude-sonnet-4",
+            "messages": [
+                {"role": "system", "content": "You are helpful."},
+                {"role": "user", "content": [
+                    {"type": "text", "text": "What's in this image, and the weather?"},
+                    {"type": "image_url", "image_url": {"url": "data:image/png;base64,abc"}}
+                ]},
+                {"role": "assistant", "content": null, "tool_calls": [
+                    {"id": "call_1", "type": "function", "function": {"name": "get_weather", "arguments": "{\"city\":\"Paris\"}"}},
+                    {"id": "call_2", "type": "function", "function": {"name": "get_time", "arguments": ""}}
+                ]},
+                {"role": "tool", "tool_call_id": "call_1", "content": "Sunny"},
+                {"role": "tool", "tool_call_id": "call_2", "content": "Noon"}
+            ],
+            "temperature": 1.5,
+            "stop": ["END"],
+            "user": "user-1",
+            "tools": [{"type": "function", "function": {"name": "get_weather", "parameters": {"type": "object"}}}],
+            "tool_choice": {"type": "function", "function": {"name": "get_weather"}}
+        })).unwrap();
+        
+        let claude_req = converter.convert_openai_request_to_claude(openai_req).unwrap();
+        
+        assert_eq!(claude_req.model, "anthropic/claude-sonnet-4");
+        assert_eq!(claude_req.max_tokens, DEFAULT_CLAUDE_MAX_TOKENS);
+        assert_eq!(claude_req.temperature, Some(1.0));
+        assert!(matches!(&claude_req.system, Some(SystemPrompt::String(s)) if s == "You are helpful."));
+        assert_eq!(claude_req.stop_sequences, Some(vec!["END".to_string()]));
+        assert_eq!(claude_req.metadata.unwrap()["user_id"], "user-1");
+        assert_eq!(claude_req.tools.unwrap()[0].name, "get_weather");
+        assert_eq!(claude_req.tool_choice, Some(serde_json::json!({"type": "tool", "name": "get_weather"})));
+        
+        // Tool results of both calls are merged into one user message
+        assert_eq!(claude_req.messages.len(), 3);
+        let ClaudeContent::Blocks(user_blocks) = &claude_req.messages[0].content else {
+            panic!("expected content blocks");
+        };
+        assert!(matches!(&user_blocks[1], ClaudeContentBlock::Image { source } if source.media_type == "image/png" && source.data == "abc"));
+        
+        let ClaudeContent::Blocks(assistant_blocks) = &claude_req.messages[1].content else {
+            panic!("expected content blocks");
+        };
+        assert_eq!(assistant_blocks.len(), 2);
+        assert!(matches!(&assistant_blocks[0], ClaudeContentBlock::ToolUse { id, input, .. } if id == "call_1" && input["city"] == "Paris"));
+        assert!(matches!(&assistant_blocks[1], ClaudeContentBlock::ToolUse { input, .. } if *input == serde_json::json!({})));
+        
+        assert_eq!(claude_req.messages[2].role, "user");
+        let ClaudeContent::Blocks(result_blocks) = &claude_req.messages[2].content else {
+            panic!("expected content blocks");
+        };
+        assert!(matches!(&result_blocks[1], ClaudeContentBlock::ToolResult { tool_use_id, content, .. } if tool_use_id == "call_2" && content.text() == "Noon"));
+    }
+    
+    #[test]
+    fn test_claude_tool_choice() {
+        assert_eq!(claude_tool_choice(&serde_json::json!("auto")), serde_json::json!({"type": "auto"}));
+        assert_eq!(claude_tool_choice(&serde_json::json!("none")), serde_json::json!({"type": "none"}));
+        assert_eq!(claude_tool_choice(&serde_json::json!("required")), serde_json::json!({"type": "any"}));
+        assert_eq!(claude_tool_choice(&serde_json::json!({"type": "any"})), serde_json::json!({"type": "any"}));
+    }
+    
+    #[test]
+    fn test_convert_response_web_search() {
+        let converter = ApiConverter::new(create_test_settings());
+        
+        let openai_resp: OpenAIResponse = serde_json::from_value(serde_json::json!({
+            "model": "sonar",
+            "choices": [{"index": 0, "message": {"role": "assistant", "content": "Rust 1.85 is out."}, "finish_reason": "stop"}],
+            "search_results": [{"url": "https://blog.rust-lang.org", "title": "Rust Blog", "date": "2025-02-20"}]
+        })).unwrap();
+        
+        let claude_resp = converter.convert_response(openai_resp, "claude-3-sonnet").unwrap();
+        
+        let ClaudeContentBlock::ServerToolUse { id, name, .. } = &claude_resp.content[0] else {
+            panic!("expected server_tool_use block");
+        };
+        assert_eq!(name, "web_search");
+        let ClaudeContentBlock::WebSearchToolResult { tool_use_id, content } = &claude_resp.content[1] else {
+            panic!("expected web_search_tool_result block");
+        };
+        assert_eq!(tool_use_id, id);
+        assert_eq!(content[0]["type"], "web_search_result");
+        assert_eq!(content[0]["url"], "https://blog.rust-lang.org");
+        assert_eq!(content[0]["page_age"], "2025-02-20");
+        assert_eq!(claude_resp.content[2], ClaudeContentBlock::Text { text: "Rust 1.85 is out.".to_string() });
+    }
+    
+    #[test]
+    fn test_convert_stream_web_search() {
+        let converter = ApiConverter::new(create_test_settings());
+        let search_results = serde_json::json!([{"url": "https://blog.rust-lang.org", "title": "Rust Blog"}]);
+        let chunk = |delta: serde_json::Value, finish_reason: Option<&str>| -> OpenAIStreamResponse {
+            serde_json::from_value(serde_json::json!({
+                "model": "sonar",
+                "choices": [{"index": 0, "delta": delta, "finish_reason": finish_reason}],
+                "search_results": search_results,
+                "search_queries": ["rust 1.85"]
+            })).unwrap()
+        };
+        let chunks = vec![
+            chunk(serde_json::json!({"role": "assistant", "content": "Rust 1.85"}), None),
+            chunk(serde_json::json!({"content": " is out."}), None),
+            chunk(serde_json::json!({}), Some("stop")),
+        ];
+        
+        let mut tracker = ContentBlockTracker::new();
+        let events: Vec<ClaudeStreamEvent> = chunks.into_iter()
+            .flat_map(|chunk| converter.convert_stream_chunk(chunk, "claude-3-sonnet").unwrap())
+            .flat_map(|event| tracker.process(event))
+            .collect();
+        
+        // The results are reported once, after the text
+        let blocks: Vec<(u32, &ClaudeContentBlock)> = events.iter()
+            .filter_map(|event| match event {
+                ClaudeStreamEvent::ContentBlockStart { index, content_block } => Some((*index, content_block)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(blocks.len(), 3);
+        let (1, ClaudeContentBlock::ServerToolUse { id, input, .. }) = blocks[1] else {
+            panic!("expected server_tool_use block");
+        };
+        assert_eq!(input["query"], "rust 1.85");
+        let (2, ClaudeContentBlock::WebSearchToolResult { tool_use_id, content }) = blocks[2] else {
+            panic!("expected web_search_tool_result block");
+        };
+        assert_eq!(tool_use_id, id);
+        assert_eq!(content[0]["url"], "https://blog.rust-lang.org");
+        
+        let stops = events.iter().filter(|event| matches!(event, ClaudeStreamEvent::ContentBlockStop { .. })).count();
+        assert_eq!(stops, 3);
+        assert!(matches!(events.last(), Some(ClaudeStreamEvent::MessageStop)));
+    }
+    
     #[test]
     fn test_convert_response() {
         let settings = create_test_settings();
@@ -675,8 +1613,11 @@ mod tests {
                 prompt_tokens: 10,
                 completion_tokens: 5,
                 total_tokens: 15,
+                prompt_tokens_details: None,
             }),
             system_fingerprint: None,
+            search_results: None,
+            search_queries: None,
         };
         
         let claude_resp = converter.convert_response(openai_resp, "claude-3-sonnet").unwrap();
@@ -686,6 +1627,223 @@ mod tests {
         assert_eq!(claude_resp.stop_reason, Some("end_turn".to_string()));
         assert_eq!(claude_resp.usage.input_tokens, 10);
         assert_eq!(claude_resp.usage.output_tokens, 5);
+        assert!(claude_resp.usage.cache_read_input_tokens.is_none());
+    }
+    
+    #[test]
+    fn test_convert_response_cached_tokens() {
+        let converter = ApiConverter::new(create_test_settings());
+        let openai_resp: OpenAIResponse = serde_json::from_value(serde_json::json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion",
+            "created": 0,
+            "model": "gpt-4o",
+            "choices": [{"index": 0, "message": {"role": "assistant", "content": "Hi"}, "finish_reason": "stop"}],
+            "usage": {"prompt_tokens": 1000, "completion_tokens": 5, "total_tokens": 1005, "prompt_tokens_details": {"cached_tokens": 900}}
+        })).unwrap();
+        
+        let usage = converter.convert_response(openai_resp, "claude-3-sonnet").unwrap().usage;
+        assert_eq!(usage.input_tokens, 100);
+        assert_eq!(usage.cache_read_input_tokens, Some(900));
+        assert_eq!(usage.total_input_tokens(), 1000);
+    }
+    
+    #[test]
+    fn test_convert_response_multiple_choices() {
+        let choice = |index: u32, text: &str| OpenAIChoice {
+            index,
+            message: OpenAIMessage {
+                role: "assistant".to_string(),
+                content: Some(OpenAIContent::Text(text.to_string())),
+                name: None,
+                tool_calls: None,
+                tool_call_id: None,
+            },
+            logprobs: None,
+            finish_reason: Some("stop".to_string()),
+        };
+        let openai_resp = OpenAIResponse {
+            id: "chatcmpl-test".to_string(),
+            object: "chat.completion".to_string(),
+            created: 0,
+            model: "gpt-4o".to_string(),
+            choices: vec![choice(1, "second"), choice(0, "first")],
+            usage: None,
+            system_fingerprint: None,
+            search_results: None,
+            search_queries: None,
+        };
+        
+        // Default mode: extra choices are dropped, index 0 wins
+        let converter = ApiConverter::new(create_test_settings());
+        let claude_resp = converter.convert_response(openai_resp.clone(), "claude-3-sonnet").unwrap();
+        assert_eq!(claude_resp.content[0], ClaudeContentBlock::Text { text: "first".to_string() });
+        assert!(claude_resp.metadata.is_none());
+        // Missing usage is estimated from the selected choice
+        assert_eq!(claude_resp.usage.input_tokens, 0);
+        assert_eq!(claude_resp.usage.output_tokens, 1);
+        
+        // Metadata mode: extra choices are exposed
+        let converter = ApiConverter::new(create_test_settings())
+            .with_multiple_choices(MultipleChoicesMode::Metadata);
+        let claude_resp = converter.convert_response(openai_resp, "claude-3-sonnet").unwrap();
+        assert_eq!(claude_resp.content[0], ClaudeContentBlock::Text { text: "first".to_string() });
+        let alternatives = &claude_resp.metadata.unwrap()["choices"];
+        assert_eq!(alternatives[0]["index"], 1);
+        assert_eq!(alternatives[0]["content"][0]["text"], "second");
+    }
+    
+    #[test]
+    fn test_requested_choices() {
+        let converter = ApiConverter::new(create_test_settings());
+        assert_eq!(converter.requested_choices(Some(3), None), Some(1));
+        
+        let converter = converter.with_multiple_choices(MultipleChoicesMode::Metadata);
+        assert_eq!(converter.requested_choices(Some(3), None), Some(3));
+        assert_eq!(converter.requested_choices(Some(3), Some(true)), Some(1));
+        assert_eq!(converter.requested_choices(None, None), Some(1));
+    }
+    
+    #[test]
+    fn test_apply_stop_sequences() {
+        let converter = ApiConverter::new(create_test_settings());
+        let mut response = ClaudeResponse {
+            id: "msg_test".to_string(),
+            response_type: "message".to_string(),
+            role: "assistant".to_string(),
+            content: vec![ClaudeContentBlock::Text { text: "1, 2, 3\n\nHuman:".to_string() }],
+            model: "claude-3-sonnet".to_string(),
+            stop_reason: Some("end_turn".to_string()),
+            stop_sequence: None,
+            usage: ClaudeUsage::default(),
+            metadata: None,
+        };
+        
+        let stop_sequences = vec!["END".to_string(), "\n\nHuman:".to_string()];
+        converter.apply_stop_sequences(&mut response, &stop_sequences);
+        
+        assert_eq!(response.stop_reason.as_deref(), Some("stop_sequence"));
+        assert_eq!(response.stop_sequence.as_deref(), Some("\n\nHuman:"));
+        assert_eq!(response.content[0], ClaudeContentBlock::Text { text: "1, 2, 3".to_string() });
+        
+        // Natural end of turn is left untouched
+        let mut response = ClaudeResponse {
+            content: vec![ClaudeContentBlock::Text { text: "done".to_string() }],
+            stop_reason: Some("end_turn".to_string()),
+            stop_sequence: None,
+            ..response
+        };
+        converter.apply_stop_sequences(&mut response, &stop_sequences);
+        assert_eq!(response.stop_reason.as_deref(), Some("end_turn"));
+        assert!(response.stop_sequence.is_none());
+    }
+    
+    #[test]
+    fn test_stop_sequence_tracker() {
+        let mut tracker = StopSequenceTracker::new(vec!["STOP".to_string()]);
+        
+        for text in ["counting 1 2 3 ", "ST", "OP"] {
+            tracker.observe(&mut ClaudeStreamEvent::ContentBlockDelta {
+                index: 0,
+                delta: ClaudeContentDelta::TextDelta { text: text.to_string() },
+            });
+        }
+        
+        let mut event = ClaudeStreamEvent::MessageDelta {
+            delta: ClaudeMessageDelta {
+                stop_reason: Some("end_turn".to_string()),
+                stop_sequence: None,
+            },
+            usage: ClaudeUsage::default(),
+        };
+        tracker.observe(&mut event);
+        
+        let ClaudeStreamEvent::MessageDelta { delta, .. } = event else {
+            panic!("Expected MessageDelta");
+        };
+        assert_eq!(delta.stop_reason.as_deref(), Some("stop_sequence"));
+        assert_eq!(delta.stop_sequence.as_deref(), Some("STOP"));
+    }
+    
+    #[test]
+    fn test_content_block_tracker() {
+        let converter = ApiConverter::new(create_test_settings());
+        let chunk = |delta: serde_json::Value, finish_reason: Option<&str>| -> OpenAIStreamResponse {
+            serde_json::from_value(serde_json::json!({
+                "id": "chatcmpl-1",
+                "object": "chat.completion.chunk",
+                "created": 0,
+                "model": "gpt-4o",
+                "choices": [{"index": 0, "delta": delta, "finish_reason": finish_reason}]
+            })).unwrap()
+        };
+        let chunks = vec![
+            chunk(serde_json::json!({"role": "assistant", "content": "Let me check."}), None),
+            chunk(serde_json::json!({"tool_calls": [{"index": 0, "id": "call_1", "type": "function", "function": {"name": "ls", "arguments": ""}}]}), None),
+            chunk(serde_json::json!({"tool_calls": [{"index": 0, "function": {"arguments": "{}"}}]}), None),
+            chunk(serde_json::json!({"tool_calls": [{"index": 1, "id": "call_2", "type": "function", "function": {"name": "pwd", "arguments": "{}"}}]}), None),
+            // The finish chunk doesn't repeat the tool calls
+            chunk(serde_json::json!({}), Some("tool_calls")),
+        ];
+        
+        let mut tracker = ContentBlockTracker::new();
+        let events: Vec<ClaudeStreamEvent> = chunks.into_iter()
+            .flat_map(|chunk| converter.convert_stream_chunk(chunk, "claude-3-sonnet").unwrap())
+            .flat_map(|event| tracker.process(event))
+            .collect();
+        
+        let summary: Vec<String> = events.iter()
+            .map(|event| match event {
+                ClaudeStreamEvent::ContentBlockStart { index, .. } => format!("start {}", index),
+                ClaudeStreamEvent::ContentBlockDelta { index, .. } => format!("delta {}", index),
+                ClaudeStreamEvent::ContentBlockStop { index } => format!("stop {}", index),
+                other => other.event_type().to_string(),
+            })
+            .collect();
+        assert_eq!(summary, [
+            "message_start",
+            "start 0", "delta 0", "stop 0",
+            "start 1", "delta 1", "delta 1", "stop 1",
+            "start 2", "delta 2", "stop 2",
+            "message_delta", "message_stop",
+        ]);
+    }
+    
+    #[test]
+    fn test_content_block_tracker_tool_only() {
+        let mut tracker = ContentBlockTracker::new();
+        
+        // The empty text block announced at message start is never opened
+        let events = tracker.process(ClaudeStreamEvent::ContentBlockStart {
+            index: 0,
+            content_block: ClaudeContentBlock::Text { text: String::new() },
+        });
+        assert!(events.is_empty());
+        
+        let events = tracker.process(ClaudeStreamEvent::ContentBlockStart {
+            index: 1,
+            content_block: ClaudeContentBlock::ToolUse {
+                id: "toolu_1".to_string(),
+                name: "ls".to_string(),
+                input: serde_json::json!({}),
+                thought_signature: None,
+            },
+        });
+        assert!(matches!(events[..], [ClaudeStreamEvent::ContentBlockStart { index: 0, .. }]));
+        
+        // Text after a tool call starts a new block
+        let events = tracker.process(ClaudeStreamEvent::ContentBlockDelta {
+            index: 0,
+            delta: ClaudeContentDelta::TextDelta { text: "Done".to_string() },
+        });
+        assert!(matches!(events[..], [
+            ClaudeStreamEvent::ContentBlockStop { index: 0 },
+            ClaudeStreamEvent::ContentBlockStart { index: 1, .. },
+            ClaudeStreamEvent::ContentBlockDelta { index: 1, .. },
+        ]));
+        
+        let events = tracker.process(ClaudeStreamEvent::MessageStop);
+        assert!(matches!(events[..], [ClaudeStreamEvent::ContentBlockStop { index: 1 }, ClaudeStreamEvent::MessageStop]));
     }
     
     #[test]
@@ -695,7 +1853,7 @@ mod tests {
         
         assert_eq!(converter.map_finish_reason_to_stop_reason(Some("stop")), "end_turn");
         assert_eq!(converter.map_finish_reason_to_stop_reason(Some("length")), "max_tokens");
-        assert_eq!(converter.map_finish_reason_to_stop_reason(Some("content_filter")), "stop_sequence");
+        assert_eq!(converter.map_finish_reason_to_stop_reason(Some("content_filter")), "refusal");
         assert_eq!(converter.map_finish_reason_to_stop_reason(None), "end_turn");
     }
 }
\ No newline at end of file
@@ -0,0 +1,205 @@
+//! Live mirror of streaming conversions
+//!
+//! Every streaming `/v1/messages` request is tapped by a [`StreamTap`]. While
+//! an admin watches `GET /debug/streams`, the tap publishes the converted
+//! request sent upstream, each upstream chunk, each Claude event sent to the
+//! client, and the end of the stream. Payloads are sanitized: API keys and
+//! tokens are masked and long strings (such as inline images) are cut short.
+//! Nothing is serialized while nobody is watching.
+//!
+//! Handlers reach the shared instance through [`global`].
+
+use crate::models::claude::ClaudeStreamEvent;
+use crate::models::openai::{OpenAIRequest, OpenAIStreamResponse};
+use crate::services::redaction::mask_secrets;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use tokio::sync::broadcast;
+
+/// Events buffered per watcher; a watcher further behind skips events
+const CHANNEL_CAPACITY: usize = 1024;
+
+/// Longest string shown; the rest is cut
+const MAX_STRING_CHARS: usize = 2000;
+
+static DEBUG_TAP: Lazy<DebugTap> = Lazy::new(DebugTap::default);
+
+/// The process-wide debug tap
+pub fn global() -> &'static DebugTap {
+    &DEBUG_TAP
+}
+
+/// A stream in progress, as listed to a new watcher
+#[derive(Debug, Clone, Serialize)]
+pub struct ActiveStream {
+    pub stream: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    /// Claude model requested
+    pub model: String,
+    /// Provider/model path the stream is read from
+    pub route: String,
+}
+
+/// One mirrored step of a stream
+#[derive(Debug, Clone, Serialize)]
+pub struct TapEvent {
+    /// Stream the event belongs to
+    pub stream: u64,
+    /// `request`, `upstream`, `client` or `end`
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    /// Sanitized payload
+    #[serde(skip_serializing_if = "Value::is_null")]
+    pub data: Value,
+}
+
+/// Publishes the steps of all streams to the watchers
+#[derive(Debug)]
+pub struct DebugTap {
+    sender: broadcast::Sender<TapEvent>,
+    next_id: AtomicU64,
+    active: Mutex<BTreeMap<u64, ActiveStream>>,
+}
+
+impl Default for DebugTap {
+    fn default() -> Self {
+        Self {
+            sender: broadcast::channel(CHANNEL_CAPACITY).0,
+            next_id: AtomicU64::new(1),
+            active: Mutex::default(),
+        }
+    }
+}
+
+impl DebugTap {
+    /// Start watching: the streams in progress, and the events of all streams from now on
+    pub fn subscribe(&self) -> (Vec<ActiveStream>, broadcast::Receiver<TapEvent>) {
+        let active = self.active.lock().unwrap();
+        (active.values().cloned().collect(), self.sender.subscribe())
+    }
+    
+    /// Whether anyone is watching
+    pub fn is_watched(&self) -> bool {
+        self.sender.receiver_count() > 0
+    }
+    
+    /// Tap a stream whose request is about to be sent upstream
+    pub fn start(&'static self, original_model: &str, request: &OpenAIRequest) -> StreamTap {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let stream = ActiveStream {
+            stream: id,
+            request_id: request.request_id.clone(),
+            model: original_model.to_string(),
+            route: request.model.clone(),
+        };
+        self.publish(id, "request", || {
+            let mut data = serde_json::to_value(&stream).unwrap_or_default();
+            data["request"] = serde_json::to_value(request).unwrap_or_default();
+            data
+        });
+        self.active.lock().unwrap().insert(id, stream);
+        StreamTap { tap: self, id }
+    }
+    
+    fn publish(&self, stream: u64, kind: &'static str, data: impl FnOnce() -> Value) {
+        if !self.is_watched() {
+            return;
+        }
+        let mut data = data();
+        sanitize(&mut data);
+        // Fails only when the last watcher just left
+        let _ = self.sender.send(TapEvent { stream, kind, data });
+    }
+}
+
+/// Tap of one stream; publishes its end when dropped
+#[derive(Debug)]
+pub struct StreamTap {
+    tap: &'static DebugTap,
+    id: u64,
+}
+
+impl StreamTap {
+    /// Publish a chunk received from the upstream
+    pub fn upstream_chunk(&self, chunk: &OpenAIStreamResponse) {
+        self.tap.publish(self.id, "upstream", || serde_json::to_value(chunk).unwrap_or_default());
+    }
+    
+    /// Publish an event sent to the client
+    pub fn client_event(&self, event: &ClaudeStreamEvent) {
+        self.tap.publish(self.id, "client", || serde_json::to_value(event).unwrap_or_default());
+    }
+}
+
+impl Drop for StreamTap {
+    fn drop(&mut self) {
+        self.tap.active.lock().unwrap().remove(&self.id);
+        self.tap.publish(self.id, "end", || Value::Null);
+    }
+}
+
+/// Mask secrets and cut long strings anywhere in a payload
+fn sanitize(value: &mut Value) {
+    match value {
+        Value::String(text) => {
+            let masked = mask_secrets(text).into_owned();
+            *text = match masked.char_indices().nth(MAX_STRING_CHARS) {
+                Some((end, _)) => format!("{}… [{} chars]", &masked[..end], masked.chars().count()),
+                None => masked,
+            };
+        }
+        Value::Array(values) => values.iter_mut().for_each(sanitize),
+        Value::Object(map) => map.values_mut().for_each(sanitize),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::claude::{ClaudeContentDelta, ClaudeStreamEvent};
+    
+    #[test]
+    fn test_sanitize() {
+        let mut value = serde_json::json!({
+            "content": ["token sk-abcdefghijklmnopqrstuvwx", {"url": "x".repeat(5000)}],
+            "n": 1
+        });
+        sanitize(&mut value);
+        assert_eq!(value["content"][0], "token [REDACTED_SECRET]");
+        let url = value["content"][1]["url"].as_str().unwrap();
+        assert!(url.starts_with(&"x".repeat(MAX_STRING_CHARS)));
+        assert!(url.ends_with("… [5000 chars]"));
+        assert_eq!(value["n"], 1);
+    }
+    
+    #[test]
+    fn test_tap_stream() {
+        let tap: &'static DebugTap = Box::leak(Box::default());
+        let request = OpenAIRequest::builder().model("openai/gpt-4o").user("Hi").stream(true).build();
+        
+        // Streams started before anyone watches are listed when watching starts
+        let first = tap.start("claude-sonnet-4-5", &request);
+        let (active, mut receiver) = tap.subscribe();
+        assert_eq!(active.len(), 1);
+        assert_eq!(active[0].route, "openai/gpt-4o");
+        
+        first.client_event(&ClaudeStreamEvent::ContentBlockDelta {
+            index: 0,
+            delta: ClaudeContentDelta::TextDelta { text: "Hello".to_string() },
+        });
+        drop(first);
+        
+        let event = receiver.try_recv().unwrap();
+        assert_eq!((event.stream, event.kind), (1, "client"));
+        assert_eq!(event.data["delta"]["text"], "Hello");
+        let event = receiver.try_recv().unwrap();
+        assert_eq!((event.stream, event.kind), (1, "end"));
+        assert!(tap.subscribe().0.is_empty());
+    }
+}
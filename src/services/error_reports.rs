@@ -0,0 +1,315 @@
+//! Error reports for operators
+//!
+//! With `errorReports`, failures that point at bugs or upstream regressions
+//! are sent to an [`ErrorSink`]: panics, such as one in a conversion, upstream
+//! responses that couldn't be parsed, with the start of their raw payload,
+//! and bursts of errors from one provider/model path. With the `sentry`
+//! feature, `sentryDsn` sends them to Sentry; an embedding application can set
+//! its own sink instead. Nothing is collected while no sink is set.
+//!
+//! The code that fails reaches the shared reporter through [`global`].
+
+use crate::config::ErrorReportsConfig;
+use crate::services::redaction::mask_secrets;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::sync::{Arc, Mutex, Once, RwLock};
+use std::time::{Duration, Instant};
+
+static ERROR_REPORTER: Lazy<ErrorReporter> = Lazy::new(ErrorReporter::default);
+
+/// The process-wide error reporter
+pub fn global() -> &'static ErrorReporter {
+    &ERROR_REPORTER
+}
+
+/// What went wrong
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportKind {
+    /// A panic, which ends the request it happened in
+    Panic,
+    /// An upstream response or stream chunk that couldn't be parsed
+    UnparsableResponse,
+    /// Errors of one provider/model path reached `burstErrors` within `burstWindowSecs`
+    ProviderErrorBurst,
+}
+
+impl ReportKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReportKind::Panic => "panic",
+            ReportKind::UnparsableResponse => "unparsable_response",
+            ReportKind::ProviderErrorBurst => "provider_error_burst",
+        }
+    }
+}
+
+/// One reported failure
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ErrorReport {
+    pub kind: ReportKind,
+    pub message: String,
+    /// Where it happened: source location, upstream API or provider/model path
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// Start of the raw upstream payload, secrets masked
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payload: Option<String>,
+}
+
+/// Destination of error reports
+pub trait ErrorSink: Send + Sync {
+    /// Name shown in logs
+    fn name(&self) -> &str;
+    
+    /// Send a report; called on the failing thread, so it must not block
+    fn report(&self, report: &ErrorReport);
+}
+
+/// Errors of one path in the current window
+#[derive(Debug)]
+struct Burst {
+    started: Instant,
+    errors: u32,
+}
+
+/// Sends failures to the configured sink
+#[derive(Default)]
+pub struct ErrorReporter {
+    sink: RwLock<Option<Arc<dyn ErrorSink>>>,
+    settings: RwLock<ErrorReportsConfig>,
+    bursts: Mutex<HashMap<String, Burst>>,
+}
+
+impl std::fmt::Debug for ErrorReporter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ErrorReporter")
+            .field("sink", &self.sink().map(|sink| sink.name().to_string()))
+            .field("settings", &self.settings.read().unwrap())
+            .finish()
+    }
+}
+
+impl ErrorReporter {
+    /// Apply new payload and burst settings
+    pub fn configure(&self, config: &ErrorReportsConfig) {
+        *self.settings.write().unwrap() = config.clone();
+        self.bursts.lock().unwrap().clear();
+    }
+    
+    /// Send reports to a sink, or stop reporting with `None`
+    pub fn set_sink(&self, sink: Option<Arc<dyn ErrorSink>>) {
+        *self.sink.write().unwrap() = sink;
+    }
+    
+    fn sink(&self) -> Option<Arc<dyn ErrorSink>> {
+        self.sink.read().unwrap().clone()
+    }
+    
+    /// Whether reports are sent anywhere
+    pub fn is_enabled(&self) -> bool {
+        self.sink.read().unwrap().is_some()
+    }
+    
+    /// Send a report to the sink, if one is set
+    pub fn report(&self, report: ErrorReport) {
+        if let Some(sink) = self.sink() {
+            sink.report(&report);
+        }
+    }
+    
+    /// Report an upstream response that couldn't be parsed, with the start of its body
+    pub fn unparsable_response(&self, source: &str, error: &dyn Display, payload: &str) {
+        if !self.is_enabled() {
+            return;
+        }
+        let max_payload_bytes = self.settings.read().unwrap().max_payload_bytes;
+        let payload = (max_payload_bytes > 0).then(|| truncate(&mask_secrets(payload), max_payload_bytes));
+        self.report(ErrorReport {
+            kind: ReportKind::UnparsableResponse,
+            message: format!("Failed to parse {}: {}", source, error),
+            source: Some(source.to_string()),
+            payload,
+        });
+    }
+    
+    /// Count a failed upstream request, reporting once per window when a
+    /// path's errors reach the burst threshold
+    pub fn provider_error(&self, model_path: &str, error: &anyhow::Error) {
+        if !self.is_enabled() {
+            return;
+        }
+        let (threshold, window) = {
+            let settings = self.settings.read().unwrap();
+            (settings.burst_errors, Duration::from_secs(settings.burst_window_secs))
+        };
+        let errors = {
+            let mut bursts = self.bursts.lock().unwrap();
+            let now = Instant::now();
+            let burst = bursts.entry(model_path.to_string()).or_insert(Burst { started: now, errors: 0 });
+            if now.duration_since(burst.started) >= window {
+                *burst = Burst { started: now, errors: 0 };
+            }
+            burst.errors += 1;
+            burst.errors
+        };
+        if errors == threshold {
+            self.report(ErrorReport {
+                kind: ReportKind::ProviderErrorBurst,
+                message: format!("{} errors from {} within {} s, the last: {:#}", errors, model_path, window.as_secs(), error),
+                source: Some(model_path.to_string()),
+                payload: None,
+            });
+        }
+    }
+}
+
+/// Report panics after the default panic output (installed once per process)
+pub fn install_panic_hook() {
+    static INSTALLED: Once = Once::new();
+    INSTALLED.call_once(|| {
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            previous(info);
+            let payload = info.payload();
+            let message = payload.downcast_ref::<&str>().map(|message| message.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "panic".to_string());
+            global().report(ErrorReport {
+                kind: ReportKind::Panic,
+                message,
+                source: info.location().map(|location| format!("{}:{}", location.file(), location.line())),
+                payload: None,
+            });
+        }));
+    });
+}
+
+/// Cut text to at most `max_bytes`, at a character boundary
+fn truncate(text: &str, max_bytes: usize) -> String {
+    if text.len() <= max_bytes {
+        return text.to_string();
+    }
+    let mut end = max_bytes;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}… [{} bytes]", &text[..end], text.len())
+}
+
+#[cfg(feature = "sentry")]
+pub use self::sentry_sink::SentrySink;
+
+#[cfg(feature = "sentry")]
+mod sentry_sink {
+    use super::*;
+    use anyhow::{Context, Result};
+    
+    /// Sends reports to Sentry as error events, tagged with their kind and source
+    pub struct SentrySink {
+        _guard: sentry::ClientInitGuard,
+    }
+    
+    impl SentrySink {
+        /// Start the Sentry client for `sentryDsn`; it sends events from a
+        /// background thread and flushes them when the sink is dropped
+        pub fn new(config: &ErrorReportsConfig) -> Result<Self> {
+            let dsn: sentry::types::Dsn = config.sentry_dsn.as_deref()
+                .context("errorReports.sentryDsn is not set")?
+                .parse()
+                .context("Invalid errorReports.sentryDsn")?;
+            let guard = sentry::init(sentry::ClientOptions {
+                dsn: Some(dsn),
+                release: sentry::release_name!(),
+                environment: config.environment.clone().map(Into::into),
+                ..Default::default()
+            });
+            Ok(Self { _guard: guard })
+        }
+    }
+    
+    impl ErrorSink for SentrySink {
+        fn name(&self) -> &str {
+            "Sentry"
+        }
+        
+        fn report(&self, report: &ErrorReport) {
+            sentry::with_scope(
+                |scope| {
+                    scope.set_tag("kind", report.kind.as_str());
+                    if let Some(source) = &report.source {
+                        scope.set_tag("source", source);
+                    }
+                    if let Some(payload) = &report.payload {
+                        scope.set_extra("payload", payload.clone().into());
+                    }
+                },
+                || sentry::capture_message(&report.message, sentry::Level::Error),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    /// Sink keeping the reports
+    #[derive(Default)]
+    struct Collect(Mutex<Vec<ErrorReport>>);
+    
+    impl ErrorSink for Collect {
+        fn name(&self) -> &str {
+            "test"
+        }
+        
+        fn report(&self, report: &ErrorReport) {
+            self.0.lock().unwrap().push(report.clone());
+        }
+    }
+    
+    #[test]
+    fn test_unparsable_response() {
+        let reporter = ErrorReporter::default();
+        reporter.configure(&ErrorReportsConfig { max_payload_bytes: 40, ..Default::default() });
+        // Without a sink nothing is collected
+        reporter.unparsable_response("OpenAI response", &"expected value", "not json");
+        
+        let sink = Arc::new(Collect::default());
+        reporter.set_sink(Some(sink.clone()));
+        let payload = format!("{{\"key\": \"sk-abcdefghijklmnopqrstuvwx\", \"text\": \"{}\"}}", "x".repeat(100));
+        reporter.unparsable_response("OpenAI response", &"expected value", &payload);
+        
+        let reports = sink.0.lock().unwrap();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].kind, ReportKind::UnparsableResponse);
+        assert_eq!(reports[0].message, "Failed to parse OpenAI response: expected value");
+        let payload = reports[0].payload.as_deref().unwrap();
+        assert!(payload.starts_with(r#"{"key": "[REDACTED_SECRET]""#));
+        assert!(payload.ends_with("… [140 bytes]"));
+    }
+    
+    #[test]
+    fn test_provider_error_burst() {
+        let reporter = ErrorReporter::default();
+        reporter.configure(&ErrorReportsConfig { burst_errors: 3, ..Default::default() });
+        let sink = Arc::new(Collect::default());
+        reporter.set_sink(Some(sink.clone()));
+        
+        let error = anyhow::anyhow!("OpenAI API error (503): overloaded");
+        for _ in 0..5 {
+            reporter.provider_error("openai/gpt-4o", &error);
+        }
+        reporter.provider_error("gemini/gemini-2.5-pro", &error);
+        
+        // One report per path and window, when the threshold is reached
+        let reports = sink.0.lock().unwrap();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].kind, ReportKind::ProviderErrorBurst);
+        assert_eq!(reports[0].source.as_deref(), Some("openai/gpt-4o"));
+        assert_eq!(reports[0].message, "3 errors from openai/gpt-4o within 60 s, the last: OpenAI API error (503): overloaded");
+    }
+}
@@ -0,0 +1,273 @@
+//! A/B experiments by session
+//!
+//! An experiment splits the Claude Code sessions requesting a Claude model
+//! between a control and a treatment provider/model path. A session's arm is
+//! picked by hashing the experiment name and session ID, so all requests of a
+//! session go to the same arm (keeping the upstream's prompt cache warm) and
+//! every replica assigns it alike without sharing state. Requests without a
+//! session ID, and requests a routing rule matched, don't take part.
+//!
+//! Requests in an experiment carry its name and arm in their
+//! [`RequestSummary`] (request log, webhooks, usage store), and the
+//! [`Experiments`] request hook keeps per-arm totals of errors, truncated
+//! responses, latency, tokens and cost, shown by `GET /usage`.
+
+use crate::config::ExperimentConfig;
+use crate::services::hooks::RequestHook;
+use crate::services::request_log::RequestSummary;
+use async_trait::async_trait;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Arm of an experiment
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arm {
+    Control,
+    Treatment,
+}
+
+impl Arm {
+    /// Name of the arm in usage records
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Arm::Control => "control",
+            Arm::Treatment => "treatment",
+        }
+    }
+}
+
+/// Arm a session is assigned to in an experiment
+///
+/// SHA-256 keeps the hash identical across builds, so replicas agree.
+pub fn arm_of(experiment: &ExperimentConfig, session_id: &str) -> Arm {
+    let digest = Sha256::new()
+        .chain_update(experiment.name.as_bytes())
+        .chain_update([0])
+        .chain_update(session_id.as_bytes())
+        .finalize();
+    let hash = u64::from_be_bytes(digest[..8].try_into().unwrap_or_default());
+    // Uniform in [0, 100)
+    let bucket = (hash >> 11) as f64 / (1u64 << 53) as f64 * 100.0;
+    if bucket < experiment.treatment_percent {
+        Arm::Treatment
+    } else {
+        Arm::Control
+    }
+}
+
+/// Experiment arm a request is routed to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Assignment<'a> {
+    pub experiment: &'a str,
+    pub arm: Arm,
+    /// Provider/model path of the arm
+    pub target: &'a str,
+}
+
+/// Totals of one arm
+#[derive(Debug, Clone, Default)]
+struct ArmStats {
+    requests: u64,
+    errors: u64,
+    truncated: u64,
+    latency_ms: u64,
+    input_tokens: u64,
+    output_tokens: u64,
+    cost_usd: f64,
+}
+
+/// Configured experiments and the totals of their arms
+#[derive(Debug, Default)]
+pub struct Experiments {
+    configs: Vec<ExperimentConfig>,
+    /// Totals by experiment name and provider/model path, shared with the
+    /// experiments of reloaded configurations
+    stats: Arc<Mutex<HashMap<(String, String), ArmStats>>>,
+}
+
+impl Experiments {
+    /// Create the experiments of a configuration with no totals
+    pub fn new(configs: &[ExperimentConfig]) -> Self {
+        Self { configs: configs.to_vec(), ..Self::default() }
+    }
+    
+    /// Continue the totals of another configuration's experiments, e.g. after
+    /// a reload; an arm whose path changed starts over
+    pub fn with_stats_of(self, previous: &Experiments) -> Self {
+        Self { stats: previous.stats.clone(), ..self }
+    }
+    
+    /// Arm of the first experiment a request for a Claude model takes part in
+    pub fn assign(&self, model: &str, session_id: Option<&str>) -> Option<Assignment<'_>> {
+        let session_id = session_id?;
+        let experiment = self.configs.iter().find(|experiment| experiment.matches(model))?;
+        let arm = arm_of(experiment, session_id);
+        let target = match arm {
+            Arm::Control => &experiment.control,
+            Arm::Treatment => &experiment.treatment,
+        };
+        Some(Assignment { experiment: &experiment.name, arm, target })
+    }
+    
+    /// Add a completed request to the totals of its arm
+    pub fn record(&self, summary: &RequestSummary) {
+        let (Some(experiment), Some(route)) = (&summary.experiment, &summary.route) else {
+            return;
+        };
+        let mut stats = self.stats.lock().unwrap_or_else(PoisonError::into_inner);
+        let arm = stats.entry((experiment.clone(), route.clone())).or_default();
+        arm.requests += 1;
+        arm.errors += u64::from(summary.status >= 400 || summary.error.is_some());
+        arm.truncated += u64::from(summary.stop_reason.as_deref() == Some("max_tokens"));
+        arm.latency_ms += summary.latency_ms;
+        arm.input_tokens += u64::from(summary.input_tokens.unwrap_or(0));
+        arm.output_tokens += u64::from(summary.output_tokens.unwrap_or(0));
+        arm.cost_usd += summary.cost_usd.unwrap_or(0.0);
+    }
+    
+    /// Totals of the arms of every experiment
+    pub fn report(&self) -> Vec<ExperimentReport> {
+        let stats = self.stats.lock().unwrap_or_else(PoisonError::into_inner);
+        let arm = |experiment: &ExperimentConfig, target: &str| {
+            let totals = stats.get(&(experiment.name.clone(), target.to_string())).cloned().unwrap_or_default();
+            ArmReport::new(target, &totals)
+        };
+        self.configs.iter()
+            .map(|experiment| ExperimentReport {
+                name: experiment.name.clone(),
+                model: experiment.model.clone(),
+                control: arm(experiment, &experiment.control),
+                treatment: arm(experiment, &experiment.treatment),
+            })
+            .collect()
+    }
+}
+
+#[async_trait]
+impl RequestHook for Experiments {
+    async fn on_complete(&self, summary: &RequestSummary) {
+        self.record(summary);
+    }
+}
+
+/// Totals of an experiment as shown by `GET /usage`
+#[derive(Debug, Clone, Serialize)]
+pub struct ExperimentReport {
+    pub name: String,
+    /// Claude model pattern of the experiment
+    pub model: String,
+    pub control: ArmReport,
+    pub treatment: ArmReport,
+}
+
+/// Totals of one arm as shown by `GET /usage`
+#[derive(Debug, Clone, Serialize)]
+pub struct ArmReport {
+    /// Provider/model path of the arm
+    pub target: String,
+    pub requests: u64,
+    /// Failed requests and streams that ended with an error
+    pub errors: u64,
+    pub error_rate: f64,
+    /// Responses that stopped at `max_tokens`
+    pub truncated: u64,
+    pub truncation_rate: f64,
+    pub mean_latency_ms: f64,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    /// Estimated cost in USD, for paths in the `pricing` table
+    pub cost_usd: f64,
+    pub mean_cost_usd: f64,
+}
+
+impl ArmReport {
+    fn new(target: &str, stats: &ArmStats) -> Self {
+        let per_request = |total: f64| if stats.requests == 0 { 0.0 } else { total / stats.requests as f64 };
+        Self {
+            target: target.to_string(),
+            requests: stats.requests,
+            errors: stats.errors,
+            error_rate: per_request(stats.errors as f64),
+            truncated: stats.truncated,
+            truncation_rate: per_request(stats.truncated as f64),
+            mean_latency_ms: per_request(stats.latency_ms as f64),
+            input_tokens: stats.input_tokens,
+            output_tokens: stats.output_tokens,
+            cost_usd: stats.cost_usd,
+            mean_cost_usd: per_request(stats.cost_usd),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn experiment(treatment_percent: f64) -> ExperimentConfig {
+        ExperimentConfig {
+            name: "glm-rollout".to_string(),
+            model: "claude-sonnet-4".to_string(),
+            control: "openai/gpt-4o".to_string(),
+            treatment: "ark/glm-4.6".to_string(),
+            treatment_percent,
+        }
+    }
+    
+    #[test]
+    fn test_assign() {
+        let experiments = Experiments::new(&[experiment(20.0)]);
+        assert!(experiments.assign("claude-sonnet-4-5", None).is_none());
+        assert!(experiments.assign("claude-haiku-4", Some("session-1")).is_none());
+        
+        // Stable for a session, split by treatmentPercent across sessions
+        let sessions: Vec<String> = (0..1000).map(|i| format!("session-{}", i)).collect();
+        let assignments: Vec<Assignment> = sessions.iter()
+            .map(|session| experiments.assign("claude-sonnet-4-5", Some(session)).unwrap())
+            .collect();
+        assert!(sessions.iter().zip(&assignments).all(|(session, assignment)| {
+            experiments.assign("claude-sonnet-4-5", Some(session)) == Some(*assignment)
+        }));
+        let treated: Vec<&Assignment> = assignments.iter().filter(|assignment| assignment.arm == Arm::Treatment).collect();
+        assert!((120..280).contains(&treated.len()), "{} sessions treated", treated.len());
+        assert!(treated.iter().all(|assignment| assignment.target == "ark/glm-4.6" && assignment.experiment == "glm-rollout"));
+        
+        assert!(sessions.iter().all(|session| arm_of(&experiment(0.0), session) == Arm::Control));
+        assert!(sessions.iter().all(|session| arm_of(&experiment(100.0), session) == Arm::Treatment));
+    }
+    
+    #[test]
+    fn test_report() {
+        let experiments = Experiments::new(&[experiment(50.0)]);
+        let summary = |route: &str, status: u16, stop_reason: &str, latency_ms: u64| RequestSummary {
+            model: "claude-sonnet-4-5".to_string(),
+            route: Some(route.to_string()),
+            experiment: Some("glm-rollout".to_string()),
+            arm: Some("treatment".to_string()),
+            status,
+            latency_ms,
+            output_tokens: Some(10),
+            cost_usd: Some(0.5),
+            stop_reason: Some(stop_reason.to_string()).filter(|stop_reason| !stop_reason.is_empty()),
+            ..Default::default()
+        };
+        experiments.record(&summary("ark/glm-4.6", 200, "end_turn", 1000));
+        experiments.record(&summary("ark/glm-4.6", 200, "max_tokens", 3000));
+        experiments.record(&summary("ark/glm-4.6", 502, "", 500));
+        experiments.record(&RequestSummary { experiment: None, ..summary("openai/gpt-4o", 200, "end_turn", 100) });
+        
+        let reloaded = Experiments::new(&[experiment(50.0)]).with_stats_of(&experiments);
+        let report = reloaded.report();
+        assert_eq!(report.len(), 1);
+        let treatment = &report[0].treatment;
+        assert_eq!(treatment.requests, 3);
+        assert_eq!(treatment.errors, 1);
+        assert_eq!(treatment.truncated, 1);
+        assert_eq!(treatment.mean_latency_ms, 1500.0);
+        assert_eq!(treatment.output_tokens, 30);
+        assert_eq!(treatment.mean_cost_usd, 0.5);
+        assert_eq!(report[0].control.requests, 0);
+        assert_eq!(report[0].control.error_rate, 0.0);
+    }
+}
@@ -0,0 +1,635 @@
+//! Content guardrails for prompts and completions
+//!
+//! With a `guardrails` section, the text of each request's new turn (the
+//! messages after the last assistant message) is checked before it is sent
+//! upstream, and the text of responses before it is sent to the client. A
+//! rule matches a regular expression, any of a list of keywords (whole words,
+//! ignoring case), or a deny topic: at least `minMatches` distinct terms of a
+//! list. What happens on a match depends on the rule's action:
+//!
+//! - `"block"` (default): a blocked prompt is not sent upstream and the
+//!   client gets the configured message with `stop_reason: "content_filter"`;
+//!   a blocked completion is replaced by that message, and a blocked stream
+//!   is closed with that stop reason before the matching text
+//! - `"redact"`: the matches are replaced with the rule's replacement
+//! - `"annotate"`: the text is passed on unchanged
+//!
+//! The rules that matched are named in the `x-aiapiproxy-guardrails` header
+//! and the request summary. Streamed text is checked as whole words arrive,
+//! over the last [`STREAM_WINDOW`] bytes of its block, so a redaction only
+//! covers the part of a match that wasn't sent yet. Embedding applications
+//! can [`register`] their own [`Guardrail`] checks before the server starts.
+
+use crate::config::{GuardrailAction, GuardrailRule, GuardrailScope, GuardrailsConfig};
+use crate::models::claude::{
+    ClaudeContentBlock, ClaudeContentDelta, ClaudeMessageDelta, ClaudeResponse, ClaudeStreamEvent, ClaudeStreamMessage, ClaudeUsage,
+};
+use crate::models::openai::{OpenAIContent, OpenAIContentPart, OpenAIRequest};
+use anyhow::{Context, Result};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::collections::HashSet;
+use std::fmt;
+use std::ops::Range;
+use std::sync::{Arc, RwLock};
+
+/// Stop reason of a blocked prompt or completion
+pub const CONTENT_FILTER: &str = "content_filter";
+
+/// Bytes of a streamed text block checked with each new stretch of words
+pub const STREAM_WINDOW: usize = 1024;
+
+/// Text a content guardrail checks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// Inbound message text
+    Prompt,
+    /// Response text
+    Completion,
+}
+
+/// A guardrail that matched a text
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    /// Rule name reported in the header and request summary
+    pub rule: String,
+    pub action: GuardrailAction,
+    /// Byte ranges of the matches; empty when the whole text is concerned
+    pub spans: Vec<Range<usize>>,
+    /// Text replacing the matches of a redaction
+    pub replacement: String,
+}
+
+/// Content check run on prompts and completions
+///
+/// Checks run inline with the request and on every stretch of a streamed
+/// response, so they should be quick.
+pub trait Guardrail: Send + Sync {
+    /// Check a text; None if it passes
+    fn check(&self, stage: Stage, text: &str) -> Option<Violation>;
+}
+
+static REGISTERED: Lazy<RwLock<Vec<Arc<dyn Guardrail>>>> = Lazy::new(Default::default);
+
+/// Register a check for the guardrails set up after this call
+///
+/// Checks apply even without a `guardrails` section; they run after the
+/// configured rules.
+pub fn register(guardrail: Arc<dyn Guardrail>) {
+    REGISTERED.write().unwrap().push(guardrail);
+}
+
+/// Checks added with [`register`]
+pub fn registered() -> Vec<Arc<dyn Guardrail>> {
+    REGISTERED.read().unwrap().clone()
+}
+
+/// Configured guardrail rule, compiled
+#[derive(Debug)]
+struct Rule {
+    name: String,
+    action: GuardrailAction,
+    scope: GuardrailScope,
+    replacement: String,
+    regex: Regex,
+    /// Distinct matches needed, for deny topics
+    min_matches: usize,
+}
+
+impl Rule {
+    fn new(rule: &GuardrailRule) -> Result<Self> {
+        let (pattern, min_matches) = match (&rule.pattern, &rule.deny_topic) {
+            (Some(pattern), _) => (pattern.clone(), 1),
+            (None, Some(topic)) => (terms_pattern(&topic.terms), topic.min_matches),
+            (None, None) => (terms_pattern(&rule.keywords), 1),
+        };
+        let regex = Regex::new(&pattern).with_context(|| format!("Invalid guardrail pattern '{}'", rule.name))?;
+        Ok(Self {
+            name: rule.name.clone(),
+            action: rule.action,
+            scope: rule.scope,
+            replacement: rule.replacement.clone(),
+            regex,
+            min_matches,
+        })
+    }
+}
+
+/// Pattern matching any of a list of words or phrases, ignoring case
+///
+/// Word boundaries are only required next to word characters, so terms such
+/// as "c++" still match.
+fn terms_pattern(terms: &[String]) -> String {
+    let is_word = |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric() || c == '_');
+    let alternatives: Vec<String> = terms.iter()
+        .map(|term| {
+            let start = if is_word(term.chars().next()) { r"\b" } else { "" };
+            let end = if is_word(term.chars().last()) { r"\b" } else { "" };
+            format!("{}{}{}", start, regex::escape(term), end)
+        })
+        .collect();
+    format!("(?i)(?:{})", alternatives.join("|"))
+}
+
+impl Guardrail for Rule {
+    fn check(&self, stage: Stage, text: &str) -> Option<Violation> {
+        let applies = match self.scope {
+            GuardrailScope::Both => true,
+            GuardrailScope::Prompt => stage == Stage::Prompt,
+            GuardrailScope::Completion => stage == Stage::Completion,
+        };
+        if !applies {
+            return None;
+        }
+        let matches: Vec<regex::Match> = self.regex.find_iter(text).collect();
+        let distinct: HashSet<String> = matches.iter().map(|m| m.as_str().to_lowercase()).collect();
+        if matches.is_empty() || distinct.len() < self.min_matches {
+            return None;
+        }
+        Some(Violation {
+            rule: self.name.clone(),
+            action: self.action,
+            spans: matches.iter().map(|m| m.range()).collect(),
+            replacement: self.replacement.clone(),
+        })
+    }
+}
+
+/// Rules that matched a request or response
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Findings {
+    /// Names of the rules, in order of first match
+    pub rules: Vec<String>,
+    /// A block rule matched
+    pub blocked: bool,
+}
+
+impl Findings {
+    /// Whether no rule matched
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+    
+    /// Add the rules of other findings
+    pub fn merge(&mut self, other: &Findings) {
+        for rule in &other.rules {
+            self.add(rule);
+        }
+        self.blocked |= other.blocked;
+    }
+    
+    fn add(&mut self, rule: &str) {
+        if !self.rules.iter().any(|known| known == rule) {
+            self.rules.push(rule.to_string());
+        }
+    }
+}
+
+impl fmt::Display for Findings {
+    /// Rule names, e.g. `credentials, competitors`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.rules.join(", "))
+    }
+}
+
+/// Configured rules and registered checks
+pub struct Guardrails {
+    checks: Vec<Arc<dyn Guardrail>>,
+    message: String,
+}
+
+impl fmt::Debug for Guardrails {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Guardrails")
+            .field("checks", &self.checks.len())
+            .field("message", &self.message)
+            .finish()
+    }
+}
+
+impl Guardrails {
+    /// Compile the rules of a configuration and add the registered checks
+    pub fn new(config: &GuardrailsConfig) -> Result<Self> {
+        let mut checks = config.rules.iter()
+            .map(|rule| Ok(Arc::new(Rule::new(rule)?) as Arc<dyn Guardrail>))
+            .collect::<Result<Vec<_>>>()?;
+        checks.extend(registered());
+        Ok(Self { checks, message: config.message.clone() })
+    }
+    
+    /// Check a text, redacting it in place; stops at the first block
+    fn check_text(&self, stage: Stage, text: &mut String, findings: &mut Findings) {
+        for check in &self.checks {
+            let Some(violation) = check.check(stage, text) else {
+                continue;
+            };
+            findings.add(&violation.rule);
+            match violation.action {
+                GuardrailAction::Block => {
+                    findings.blocked = true;
+                    return;
+                }
+                GuardrailAction::Redact => redact(text, &violation.spans, &violation.replacement),
+                GuardrailAction::Annotate => {}
+            }
+        }
+    }
+    
+    /// Check the text of a request's new turn: the messages after the last
+    /// assistant message
+    ///
+    /// Earlier messages were checked when they were new, or are completions.
+    pub fn check_prompt(&self, request: &mut OpenAIRequest) -> Findings {
+        let mut findings = Findings::default();
+        let new_turn = request.messages.iter()
+            .rposition(|message| message.role == "assistant")
+            .map_or(0, |position| position + 1);
+        for message in &mut request.messages[new_turn..] {
+            match &mut message.content {
+                Some(OpenAIContent::Text(text)) => self.check_text(Stage::Prompt, text, &mut findings),
+                Some(OpenAIContent::Array(parts)) => {
+                    for part in parts {
+                        if let OpenAIContentPart::Text { text, .. } = part {
+                            self.check_text(Stage::Prompt, text, &mut findings);
+                        }
+                    }
+                }
+                None => {}
+            }
+            if findings.blocked {
+                break;
+            }
+        }
+        findings
+    }
+    
+    /// Check the text of a response; a blocked response is replaced by the
+    /// configured message
+    pub fn check_response(&self, response: &mut ClaudeResponse) -> Findings {
+        let mut findings = Findings::default();
+        for block in &mut response.content {
+            if let ClaudeContentBlock::Text { text } = block {
+                self.check_text(Stage::Completion, text, &mut findings);
+                if findings.blocked {
+                    break;
+                }
+            }
+        }
+        if findings.blocked {
+            response.content = vec![ClaudeContentBlock::Text { text: self.message.clone() }];
+            response.stop_reason = Some(CONTENT_FILTER.to_string());
+            response.stop_sequence = None;
+        }
+        findings
+    }
+    
+    /// Response to a blocked prompt
+    pub fn blocked_response(&self, model: &str) -> ClaudeResponse {
+        ClaudeResponse {
+            id: format!("msg_{}", uuid::Uuid::new_v4().simple()),
+            response_type: "message".to_string(),
+            role: "assistant".to_string(),
+            content: vec![ClaudeContentBlock::Text { text: self.message.clone() }],
+            model: model.to_string(),
+            stop_reason: Some(CONTENT_FILTER.to_string()),
+            stop_sequence: None,
+            usage: ClaudeUsage::default(),
+            metadata: None,
+        }
+    }
+    
+    /// Check the text of a stream's events
+    pub fn stream_guard(self: &Arc<Self>) -> StreamGuard {
+        StreamGuard {
+            guardrails: self.clone(),
+            block: None,
+            sent: String::new(),
+            held: String::new(),
+            findings: Findings::default(),
+        }
+    }
+}
+
+/// Replace the given byte ranges of a text, ignoring overlaps
+fn redact(text: &mut String, spans: &[Range<usize>], replacement: &str) {
+    let mut spans = spans.to_vec();
+    spans.sort_by_key(|span| span.start);
+    let mut end = text.len() + 1;
+    for span in spans.into_iter().rev() {
+        if span.end <= end && text.is_char_boundary(span.start) && text.is_char_boundary(span.end) {
+            text.replace_range(span.clone(), replacement);
+            end = span.start;
+        }
+    }
+}
+
+/// Events of a blocked prompt's response, for a streaming request
+pub fn blocked_events(response: &ClaudeResponse) -> Vec<ClaudeStreamEvent> {
+    let mut events = vec![ClaudeStreamEvent::MessageStart {
+        message: ClaudeStreamMessage {
+            id: response.id.clone(),
+            message_type: "message".to_string(),
+            role: "assistant".to_string(),
+            content: vec![],
+            model: response.model.clone(),
+            stop_reason: None,
+            stop_sequence: None,
+            usage: response.usage.clone(),
+            fallback: None,
+        },
+    }];
+    for (index, block) in response.content.iter().enumerate() {
+        let ClaudeContentBlock::Text { text } = block else {
+            continue;
+        };
+        let index = index as u32;
+        events.push(ClaudeStreamEvent::ContentBlockStart { index, content_block: ClaudeContentBlock::Text { text: String::new() } });
+        events.push(ClaudeStreamEvent::ContentBlockDelta { index, delta: ClaudeContentDelta::TextDelta { text: text.clone() } });
+        events.push(ClaudeStreamEvent::ContentBlockStop { index });
+    }
+    events.push(ClaudeStreamEvent::MessageDelta {
+        delta: ClaudeMessageDelta { stop_reason: response.stop_reason.clone(), stop_sequence: None },
+        usage: response.usage.clone(),
+    });
+    events.push(ClaudeStreamEvent::MessageStop);
+    events
+}
+
+/// Checks the text blocks of a stream
+///
+/// Text is held back until a word is complete, and checked together with the
+/// text of its block sent before it. A block closes the open block and the
+/// message with `stop_reason: "content_filter"`; later events are dropped.
+#[derive(Debug)]
+pub struct StreamGuard {
+    guardrails: Arc<Guardrails>,
+    /// Index of the open text block
+    block: Option<u32>,
+    /// Tail of the open block's text sent so far
+    sent: String,
+    /// Text held back until a word is complete
+    held: String,
+    findings: Findings,
+}
+
+impl StreamGuard {
+    /// Rules that matched so far
+    pub fn findings(&self) -> &Findings {
+        &self.findings
+    }
+    
+    /// Whether the stream was blocked; the events that closed it were sent
+    pub fn blocked(&self) -> bool {
+        self.findings.blocked
+    }
+    
+    /// Events to send for an event: none while text is held back or after
+    /// the stream was blocked, more when held text is released or the
+    /// stream is closed
+    pub fn process(&mut self, event: ClaudeStreamEvent) -> Vec<ClaudeStreamEvent> {
+        if self.blocked() {
+            return Vec::new();
+        }
+        match &event {
+            ClaudeStreamEvent::ContentBlockStart { index, content_block: ClaudeContentBlock::Text { .. } } => {
+                self.block = Some(*index);
+                self.sent.clear();
+                self.held.clear();
+                vec![event]
+            }
+            ClaudeStreamEvent::ContentBlockDelta { index, delta: ClaudeContentDelta::TextDelta { text } } if self.block == Some(*index) => {
+                let index = *index;
+                self.held.push_str(text);
+                let ready_len = self.held
+                    .rfind(char::is_whitespace)
+                    .map_or(0, |position| position + self.held[position..].chars().next().map_or(0, char::len_utf8));
+                if ready_len == 0 {
+                    return Vec::new();
+                }
+                let ready: String = self.held.drain(..ready_len).collect();
+                self.release(index, ready)
+            }
+            ClaudeStreamEvent::ContentBlockStop { index } if self.block == Some(*index) => {
+                let held = std::mem::take(&mut self.held);
+                let mut events = match held.is_empty() {
+                    true => Vec::new(),
+                    false => self.release(*index, held),
+                };
+                if !self.blocked() {
+                    self.block = None;
+                    events.push(event);
+                }
+                events
+            }
+            _ => vec![event],
+        }
+    }
+    
+    /// Check released text with the tail of the block before it
+    fn release(&mut self, index: u32, mut text: String) -> Vec<ClaudeStreamEvent> {
+        let offset = self.sent.len();
+        for check in &self.guardrails.checks {
+            let window = format!("{}{}", self.sent, text);
+            let Some(violation) = check.check(Stage::Completion, &window) else {
+                continue;
+            };
+            // Matches within the text sent before were handled then
+            if !violation.spans.is_empty() && violation.spans.iter().all(|span| span.end <= offset) {
+                continue;
+            }
+            self.findings.add(&violation.rule);
+            match violation.action {
+                GuardrailAction::Block => {
+                    self.findings.blocked = true;
+                    return vec![
+                        ClaudeStreamEvent::ContentBlockStop { index },
+                        ClaudeStreamEvent::MessageDelta {
+                            delta: ClaudeMessageDelta { stop_reason: Some(CONTENT_FILTER.to_string()), stop_sequence: None },
+                            usage: ClaudeUsage::default(),
+                        },
+                        ClaudeStreamEvent::MessageStop,
+                    ];
+                }
+                GuardrailAction::Redact => {
+                    let spans: Vec<Range<usize>> = violation.spans.iter()
+                        .filter(|span| span.end > offset)
+                        .map(|span| span.start.max(offset) - offset..span.end - offset)
+                        .collect();
+                    redact(&mut text, &spans, &violation.replacement);
+                }
+                GuardrailAction::Annotate => {}
+            }
+        }
+        
+        self.sent.push_str(&text);
+        if self.sent.len() > STREAM_WINDOW {
+            let mut start = self.sent.len() - STREAM_WINDOW;
+            while !self.sent.is_char_boundary(start) {
+                start += 1;
+            }
+            self.sent.drain(..start);
+        }
+        vec![ClaudeStreamEvent::ContentBlockDelta { index, delta: ClaudeContentDelta::TextDelta { text } }]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DenyTopic;
+    use crate::models::openai::OpenAIMessage;
+    
+    fn rule(name: &str, action: GuardrailAction, scope: GuardrailScope) -> GuardrailRule {
+        GuardrailRule {
+            name: name.to_string(),
+            pattern: None,
+            keywords: Vec::new(),
+            deny_topic: None,
+            action,
+            scope,
+            replacement: "[FILTERED]".to_string(),
+        }
+    }
+    
+    fn guardrails() -> Arc<Guardrails> {
+        let config = GuardrailsConfig {
+            rules: vec![
+                GuardrailRule { pattern: Some(r"password=\S+".to_string()), ..rule("credentials", GuardrailAction::Redact, GuardrailScope::Both) },
+                GuardrailRule { keywords: vec!["Acme".to_string(), "c++".to_string()], ..rule("competitors", GuardrailAction::Annotate, GuardrailScope::Both) },
+                GuardrailRule {
+                    deny_topic: Some(DenyTopic { terms: vec!["explosive".to_string(), "detonator".to_string()], min_matches: 2 }),
+                    ..rule("weapons", GuardrailAction::Block, GuardrailScope::Both)
+                },
+                GuardrailRule { keywords: vec!["internal only".to_string()], ..rule("leaks", GuardrailAction::Block, GuardrailScope::Completion) },
+            ],
+            ..Default::default()
+        };
+        Arc::new(Guardrails::new(&config).unwrap())
+    }
+    
+    fn text(message: &OpenAIMessage) -> &str {
+        match &message.content {
+            Some(OpenAIContent::Text(text)) => text,
+            _ => panic!("expected text content"),
+        }
+    }
+    
+    fn response(text: &str) -> ClaudeResponse {
+        ClaudeResponse {
+            content: vec![ClaudeContentBlock::Text { text: text.to_string() }],
+            stop_reason: Some("end_turn".to_string()),
+            ..guardrails().blocked_response("claude-sonnet-4-5")
+        }
+    }
+    
+    #[test]
+    fn test_check_prompt() {
+        let guardrails = guardrails();
+        let mut request = OpenAIRequest::builder()
+            .user("Old question about Acme")
+            .assistant("Old answer")
+            .user("Log in with password=hunter2 and compare Acme, acme and C++. Internal only.")
+            .build();
+        let findings = guardrails.check_prompt(&mut request);
+        assert_eq!(findings.rules, vec!["credentials", "competitors"]);
+        assert!(!findings.blocked);
+        // Only the new turn is checked; completion rules don't apply to prompts
+        assert_eq!(text(&request.messages[0]), "Old question about Acme");
+        assert_eq!(text(&request.messages[2]), "Log in with [FILTERED] and compare Acme, acme and C++. Internal only.");
+        
+        let mut partial = OpenAIRequest::builder().user("An explosive plot, no Acmes").build();
+        assert!(guardrails.check_prompt(&mut partial).is_empty());
+        
+        let mut topic = OpenAIRequest::builder().user("Wire the detonator to the explosive").build();
+        let findings = guardrails.check_prompt(&mut topic);
+        assert_eq!(findings.rules, vec!["weapons"]);
+        assert!(findings.blocked);
+    }
+    
+    #[test]
+    fn test_check_response() {
+        let guardrails = guardrails();
+        let mut passed = response("Acme is a competitor");
+        let findings = guardrails.check_response(&mut passed);
+        assert_eq!(findings.to_string(), "competitors");
+        assert_eq!(passed.stop_reason.as_deref(), Some("end_turn"));
+        
+        let mut blocked = response("This is internal only");
+        let findings = guardrails.check_response(&mut blocked);
+        assert!(findings.blocked);
+        assert_eq!(blocked.stop_reason.as_deref(), Some(CONTENT_FILTER));
+        let ClaudeContentBlock::Text { text } = &blocked.content[0] else { panic!("expected text") };
+        assert_eq!(text, "This content was blocked by a content filter.");
+        
+        let events = blocked_events(&blocked);
+        assert!(matches!(&events[2], ClaudeStreamEvent::ContentBlockDelta { delta: ClaudeContentDelta::TextDelta { text }, .. } if text.contains("content filter")));
+        assert!(matches!(&events[4], ClaudeStreamEvent::MessageDelta { delta, .. } if delta.stop_reason.as_deref() == Some(CONTENT_FILTER)));
+        assert!(matches!(events.last(), Some(ClaudeStreamEvent::MessageStop)));
+    }
+    
+    fn stream(guard: &mut StreamGuard, deltas: &[&str]) -> (String, Vec<ClaudeStreamEvent>) {
+        let start = ClaudeStreamEvent::ContentBlockStart { index: 0, content_block: ClaudeContentBlock::Text { text: String::new() } };
+        let deltas = deltas.iter().map(|text| ClaudeStreamEvent::ContentBlockDelta {
+            index: 0,
+            delta: ClaudeContentDelta::TextDelta { text: text.to_string() },
+        });
+        let mut streamed = String::new();
+        let mut events = Vec::new();
+        for event in std::iter::once(start).chain(deltas).chain([ClaudeStreamEvent::ContentBlockStop { index: 0 }, ClaudeStreamEvent::MessageStop]) {
+            for event in guard.process(event) {
+                if let ClaudeStreamEvent::ContentBlockDelta { delta: ClaudeContentDelta::TextDelta { text }, .. } = &event {
+                    streamed.push_str(text);
+                }
+                events.push(event);
+            }
+        }
+        (streamed, events)
+    }
+    
+    #[test]
+    fn test_stream_guard() {
+        let guardrails = guardrails();
+        let mut guard = guardrails.stream_guard();
+        let (streamed, _) = stream(&mut guard, &["Use pass", "word=hun", "ter2 to log", " in at Ac", "me"]);
+        assert_eq!(streamed, "Use [FILTERED] to log in at Acme");
+        assert_eq!(guard.findings().rules, vec!["credentials", "competitors"]);
+        assert!(!guard.blocked());
+        
+        // Terms of a topic count across deltas; the text with the match is never sent
+        let mut guard = guardrails.stream_guard();
+        let (streamed, events) = stream(&mut guard, &["The detonator ", "is safe. ", "An explosive ", "mix"]);
+        assert_eq!(streamed, "The detonator is safe. ");
+        assert!(guard.blocked());
+        let stops: Vec<&ClaudeStreamEvent> = events.iter().rev().take(3).collect();
+        assert!(matches!(stops[0], ClaudeStreamEvent::MessageStop));
+        assert!(matches!(stops[1], ClaudeStreamEvent::MessageDelta { delta, .. } if delta.stop_reason.as_deref() == Some(CONTENT_FILTER)));
+        assert!(matches!(stops[2], ClaudeStreamEvent::ContentBlockStop { index: 0 }));
+        assert_eq!(events.iter().filter(|event| matches!(event, ClaudeStreamEvent::MessageStop)).count(), 1);
+    }
+    
+    struct NoShouting;
+    
+    impl Guardrail for NoShouting {
+        fn check(&self, stage: Stage, text: &str) -> Option<Violation> {
+            let shouting = stage == Stage::Completion && text.len() > 3 && text.chars().all(|c| !c.is_lowercase());
+            shouting.then(|| Violation {
+                rule: "no-shouting".to_string(),
+                action: GuardrailAction::Block,
+                spans: Vec::new(),
+                replacement: String::new(),
+            })
+        }
+    }
+    
+    #[test]
+    fn test_custom_guardrail() {
+        let guardrails = Guardrails { checks: vec![Arc::new(NoShouting)], message: "Calm down.".to_string() };
+        let mut request = OpenAIRequest::builder().user("HELLO THERE").build();
+        assert!(guardrails.check_prompt(&mut request).is_empty());
+        
+        let mut shouted = response("HELLO THERE");
+        assert_eq!(guardrails.check_response(&mut shouted).rules, vec!["no-shouting"]);
+        let ClaudeContentBlock::Text { text } = &shouted.content[0] else { panic!("expected text") };
+        assert_eq!(text, "Calm down.");
+    }
+}
@@ -0,0 +1,117 @@
+//! Hooks called after each request
+//!
+//! A [`RequestHook`] receives the [`RequestSummary`] of every completed
+//! `/v1/messages` request (model, route, tokens, estimated cost, latency and
+//! status), so billing or analytics systems can subscribe without scraping
+//! logs. Each configured `webhooks` entry POSTs the summary as JSON to a URL;
+//! embedding applications can [`register`] their own hooks. Hooks run in the
+//! background and never delay or fail the request.
+
+use crate::config::WebhookConfig;
+use crate::services::request_log::RequestSummary;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use once_cell::sync::Lazy;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tracing::{debug, warn};
+
+/// Called with the summary of each completed request
+#[async_trait]
+pub trait RequestHook: Send + Sync {
+    async fn on_complete(&self, summary: &RequestSummary);
+}
+
+static REGISTERED: Lazy<RwLock<Vec<Arc<dyn RequestHook>>>> = Lazy::new(Default::default);
+
+/// Register a hook for the requests of all routers in this process
+pub fn register(hook: Arc<dyn RequestHook>) {
+    REGISTERED.write().unwrap().push(hook);
+}
+
+/// Hooks added with [`register`]
+pub fn registered() -> Vec<Arc<dyn RequestHook>> {
+    REGISTERED.read().unwrap().clone()
+}
+
+/// Run the hooks on a summary in the background
+pub fn dispatch(hooks: &[Arc<dyn RequestHook>], summary: &RequestSummary) {
+    if hooks.is_empty() {
+        return;
+    }
+    let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+        debug!("🪝 No async runtime, skipping request hooks");
+        return;
+    };
+    for hook in hooks {
+        let hook = hook.clone();
+        let summary = summary.clone();
+        runtime.spawn(async move { hook.on_complete(&summary).await });
+    }
+}
+
+/// POSTs request summaries to a URL; request and response bodies are left out
+#[derive(Debug)]
+pub struct Webhook {
+    config: WebhookConfig,
+    client: reqwest::Client,
+}
+
+impl Webhook {
+    pub fn new(config: &WebhookConfig) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(config.timeout_ms))
+            .build()
+            .context("Failed to create webhook HTTP client")?;
+        Ok(Self { config: config.clone(), client })
+    }
+}
+
+#[async_trait]
+impl RequestHook for Webhook {
+    async fn on_complete(&self, summary: &RequestSummary) {
+        let payload = RequestSummary { request: None, response: None, ..summary.clone() };
+        let mut builder = self.client.post(&self.config.url).json(&payload);
+        for (name, value) in &self.config.headers {
+            builder = builder.header(name, value);
+        }
+        match builder.send().await {
+            Ok(response) if response.status().is_success() => {}
+            Ok(response) => warn!("🪝 Webhook {} answered {}", self.config.url, response.status()),
+            Err(e) => warn!("🪝 Webhook {} failed: {}", self.config.url, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    
+    #[tokio::test]
+    async fn test_webhook_posts_summary() {
+        let server = httpmock::MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(httpmock::Method::POST)
+                .path("/hooks/requests")
+                .header("authorization", "Bearer hook-secret")
+                .json_body_partial(r#"{"model": "claude-sonnet-4-5", "status": 200, "output_tokens": 12}"#);
+            then.status(204);
+        });
+        
+        let webhook = Webhook::new(&WebhookConfig {
+            url: server.url("/hooks/requests"),
+            headers: HashMap::from([("authorization".to_string(), "Bearer hook-secret".to_string())]),
+            timeout_ms: 1000,
+        }).unwrap();
+        let summary = RequestSummary {
+            model: "claude-sonnet-4-5".to_string(),
+            status: 200,
+            output_tokens: Some(12),
+            request: Some("secret prompt".to_string()),
+            ..Default::default()
+        };
+        webhook.on_complete(&summary).await;
+        mock.assert();
+    }
+}
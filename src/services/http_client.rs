@@ -0,0 +1,219 @@
+//! Shared HTTP clients
+//!
+//! reqwest keeps one connection pool per `Client`, so upstream requests share
+//! clients wherever they can: the [`HttpClientFactory`] builds one client per
+//! distinct set of network options (`httpProxy`, `noProxy`, `tls` and
+//! `pool`), and providers with the same options reuse its pool. Timeouts are not part of
+//! the client; callers set them per request with `RequestBuilder::timeout`,
+//! which lets regular and streaming requests use the same connections.
+//!
+//! Providers and the standalone OpenAI client reach the shared instance
+//! through [`global`].
+
+use crate::config::{PoolConfig, ProviderOptions, UpstreamTlsConfig};
+use anyhow::{Context, Result};
+use once_cell::sync::Lazy;
+use reqwest::Client;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use tracing::debug;
+
+/// Timeout for regular upstream requests without a configured one
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Timeout for streaming upstream requests without a configured one
+pub const DEFAULT_STREAM_TIMEOUT: Duration = Duration::from_secs(300);
+
+static FACTORY: Lazy<HttpClientFactory> = Lazy::new(HttpClientFactory::default);
+
+/// The process-wide client factory
+pub fn global() -> &'static HttpClientFactory {
+    &FACTORY
+}
+
+/// Network options that require a client of their own
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct ClientKey {
+    http_proxy: Option<String>,
+    no_proxy: Vec<String>,
+    tls: Option<UpstreamTlsConfig>,
+    pool: Option<PoolConfig>,
+}
+
+impl ClientKey {
+    fn new(options: &ProviderOptions) -> Self {
+        Self {
+            http_proxy: options.http_proxy.clone(),
+            no_proxy: options.no_proxy.clone(),
+            tls: options.tls.clone(),
+            pool: options.pool.clone(),
+        }
+    }
+}
+
+/// Builds and caches HTTP clients by network options
+#[derive(Debug, Default)]
+pub struct HttpClientFactory {
+    clients: Mutex<HashMap<ClientKey, Client>>,
+}
+
+impl HttpClientFactory {
+    /// Create an empty factory
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Get the client for a provider's options, building it on first use
+    ///
+    /// Clients are cheap to clone and share the connection pool.
+    pub fn client(&self, options: &ProviderOptions) -> Result<Client> {
+        let key = ClientKey::new(options);
+        let mut clients = self.clients.lock().unwrap();
+        if let Some(client) = clients.get(&key) {
+            return Ok(client.clone());
+        }
+        
+        let client = build_client(options)?;
+        debug!("Created HTTP client #{} (proxy: {:?})", clients.len() + 1, options.http_proxy);
+        clients.insert(key, client.clone());
+        Ok(client)
+    }
+    
+    /// Number of distinct clients built so far
+    pub fn len(&self) -> usize {
+        self.clients.lock().unwrap().len()
+    }
+    
+    /// Whether no client has been built yet
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Build a client for one set of network options
+///
+/// A configured `httpProxy` replaces the proxies from the environment, so
+/// only the traffic of providers that set it goes through it.
+fn build_client(options: &ProviderOptions) -> Result<Client> {
+    let mut builder = Client::builder().user_agent("aiapiproxy/0.1.0");
+    if let Some(proxy_url) = &options.http_proxy {
+        let proxy = reqwest::Proxy::all(proxy_url.as_str())
+            .with_context(|| format!("Invalid httpProxy: {}", proxy_url))?
+            .no_proxy(reqwest::NoProxy::from_string(&options.no_proxy.join(",")));
+        builder = builder.proxy(proxy);
+    }
+    if let Some(tls) = &options.tls {
+        for certificate in load_root_certificates(tls)? {
+            builder = builder.add_root_certificate(certificate);
+        }
+        builder = builder.danger_accept_invalid_certs(tls.insecure_skip_verify);
+        if let Some(version) = tls.min_version.as_deref() {
+            builder = builder.min_tls_version(tls_version(version)?);
+        }
+    }
+    if let Some(pool) = &options.pool {
+        builder = apply_pool(builder, pool);
+    }
+    builder.build().context("Failed to create HTTP client")
+}
+
+/// Apply connection pool and keep-alive settings
+fn apply_pool(mut builder: reqwest::ClientBuilder, pool: &PoolConfig) -> reqwest::ClientBuilder {
+    if let Some(max_idle) = pool.max_idle_per_host {
+        builder = builder.pool_max_idle_per_host(max_idle);
+    }
+    if let Some(secs) = pool.idle_timeout_secs {
+        builder = builder.pool_idle_timeout(Duration::from_secs(secs));
+    }
+    if let Some(secs) = pool.http2_keep_alive_interval_secs {
+        builder = builder
+            .http2_keep_alive_interval(Duration::from_secs(secs))
+            .http2_keep_alive_while_idle(true);
+    }
+    if let Some(secs) = pool.http2_keep_alive_timeout_secs {
+        builder = builder.http2_keep_alive_timeout(Duration::from_secs(secs));
+    }
+    if let Some(secs) = pool.tcp_keepalive_secs {
+        builder = builder.tcp_keepalive(Duration::from_secs(secs));
+    }
+    builder
+}
+
+/// Read the additional root certificates of a provider
+fn load_root_certificates(tls: &UpstreamTlsConfig) -> Result<Vec<reqwest::Certificate>> {
+    let mut certificates = Vec::new();
+    for path in &tls.ca_cert_paths {
+        let pem = std::fs::read(path)
+            .with_context(|| format!("Failed to read CA certificate {}", path.display()))?;
+        let bundle = reqwest::Certificate::from_pem_bundle(&pem)
+            .with_context(|| format!("Invalid CA certificate {}", path.display()))?;
+        if bundle.is_empty() {
+            anyhow::bail!("No certificates found in {}", path.display());
+        }
+        certificates.extend(bundle);
+    }
+    Ok(certificates)
+}
+
+/// Parse a configured minimum TLS version
+fn tls_version(version: &str) -> Result<reqwest::tls::Version> {
+    match version {
+        "1.0" => Ok(reqwest::tls::Version::TLS_1_0),
+        "1.1" => Ok(reqwest::tls::Version::TLS_1_1),
+        "1.2" => Ok(reqwest::tls::Version::TLS_1_2),
+        _ => anyhow::bail!("Unsupported minimum TLS version: {}", version),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_clients_shared_by_network_options() {
+        let factory = HttpClientFactory::new();
+        let mut options = ProviderOptions {
+            api_key_param: Some("ak".to_string()),
+            ..Default::default()
+        };
+        factory.client(&options).unwrap();
+        // Options unrelated to the connection share the client
+        factory.client(&ProviderOptions::default()).unwrap();
+        assert_eq!(factory.len(), 1);
+        
+        options.http_proxy = Some("http://proxy.corp:3128".to_string());
+        factory.client(&options).unwrap();
+        factory.client(&options).unwrap();
+        assert_eq!(factory.len(), 2);
+        
+        options.tls = Some(UpstreamTlsConfig {
+            min_version: Some("1.2".to_string()),
+            ..Default::default()
+        });
+        factory.client(&options).unwrap();
+        assert_eq!(factory.len(), 3);
+        
+        options.pool = Some(PoolConfig {
+            max_idle_per_host: Some(32),
+            http2_keep_alive_interval_secs: Some(15),
+            ..Default::default()
+        });
+        factory.client(&options).unwrap();
+        assert_eq!(factory.len(), 4);
+    }
+    
+    #[test]
+    fn test_invalid_options_are_not_cached() {
+        let factory = HttpClientFactory::new();
+        let options = ProviderOptions {
+            tls: Some(UpstreamTlsConfig {
+                ca_cert_paths: vec!["/nonexistent/ca.pem".into()],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert!(factory.client(&options).is_err());
+        assert!(factory.is_empty());
+    }
+}
@@ -1,11 +1,66 @@
 //! Service layer module
 //!
-//! Contains API converter, HTTP client wrapper, and request router
+//! Contains API converter, HTTP client wrapper, shared HTTP clients, request router,
+//! anomaly log, audit log, debug tap, error reports, A/B experiments, request hooks, load balancer, concurrency limit, upstream rate limits, request coalescing, retry policy, per-model
+//! request parameters, system prompt rules, redaction, content guardrails, request log, shadow
+//! traffic, session tracking, shared state backends, stream metrics, stream recovery, response continuation, strict request
+//! validation, tool call pairing, tenant limits, session transcripts, upstream health, usage store and token counter
+//!
+//! Client accounting (request log, hooks, anomalies, tenant limits,
+//! transcripts and the usage store), experiments and shadow traffic need the
+//! `server` feature, stream metrics the `metrics` feature.
 
+#[cfg(feature = "server")]
+pub mod anomalies;
+pub mod anthropic_tools;
+pub mod audit_log;
+pub mod balancer;
 pub mod client;
+pub mod coalescing;
+pub mod concurrency;
+pub mod context_window;
+pub mod continuation;
+pub mod conversion;
 pub mod converter;
+pub mod debug_tap;
+pub mod error_reports;
+#[cfg(feature = "server")]
+pub mod experiments;
+pub mod guardrails;
+#[cfg(feature = "server")]
+pub mod hooks;
+pub mod http_client;
+pub mod output_tokens;
+pub mod prompt_rules;
+pub mod rate_limits;
+pub mod reasoning;
+pub mod redaction;
+#[cfg(feature = "server")]
+pub mod request_log;
+pub mod request_params;
+pub mod retry;
 pub mod router;
+pub mod sessions;
+#[cfg(feature = "server")]
+pub mod shadow;
+pub mod state_backend;
+#[cfg(feature = "metrics")]
+pub mod stream_metrics;
+pub mod stream_recovery;
+pub mod strict_validation;
+pub mod structured_output;
+#[cfg(feature = "server")]
+pub mod tenants;
+pub mod tokens;
+pub mod tool_pairing;
+#[cfg(feature = "server")]
+pub mod transcripts;
+pub mod upstream_health;
+#[cfg(feature = "sqlite")]
+pub mod usage_store;
 
 pub use client::*;
+pub use conversion::ResponseConverter;
 pub use converter::*;
-pub use router::Router;
\ No newline at end of file
+pub use router::{ProviderRegistry, Routed, Router};
+pub use tokens::TokenCounter;
\ No newline at end of file
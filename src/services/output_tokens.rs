@@ -0,0 +1,127 @@
+//! Output token (`max_tokens`) policy
+//!
+//! Claude clients don't know the output limits of the upstream model: Claude
+//! Code sends `max_tokens: 1` for quota probes and large values for normal
+//! turns. Every request goes through [`apply`] before it is routed, so all
+//! providers see the same `max_tokens` for a model:
+//!
+//! - a missing `max_tokens` falls back to the model's `maxTokens`
+//! - values below `minOutputTokens` are raised to it
+//! - values above `capOutputTokens` (default 100000) are lowered to it or
+//!   rejected, depending on `clampStrategy`
+//!
+//! ModelHub (Gemini mode) and Responses API providers keep their own floor
+//! of the model's `maxTokens` (8192 without one) on top of this policy.
+
+use crate::config::ModelConfig;
+use crate::models::openai::OpenAIRequest;
+use tracing::debug;
+
+/// Reject requests above the cap with an invalid_request_error (default)
+pub const STRATEGY_REJECT: &str = "reject";
+
+/// Lower `max_tokens` to the cap
+pub const STRATEGY_CLAMP: &str = "clamp";
+
+/// Output token cap for models without `capOutputTokens`
+pub const DEFAULT_OUTPUT_TOKEN_CAP: u32 = 100_000;
+
+/// Apply the model's output token policy to a converted request
+///
+/// Returns the Claude `invalid_request_error` message when the request
+/// exceeds the cap and the model rejects such requests.
+pub fn apply(request: &mut OpenAIRequest, model_config: &ModelConfig) -> Result<(), String> {
+    let options = &model_config.options;
+    let original = request.max_tokens;
+    let Some(mut max_tokens) = request.max_tokens.or(model_config.max_tokens) else {
+        return Ok(());
+    };
+    
+    if let Some(min) = options.min_output_tokens {
+        max_tokens = max_tokens.max(min);
+    }
+    
+    let cap = options.cap_output_tokens.unwrap_or(DEFAULT_OUTPUT_TOKEN_CAP);
+    if max_tokens > cap {
+        if options.clamp_strategy.as_deref() != Some(STRATEGY_CLAMP) {
+            return Err(format!(
+                "max_tokens: {} > {}, which is the maximum allowed number of output tokens for {}",
+                max_tokens, cap, model_config.name
+            ));
+        }
+        max_tokens = cap;
+    }
+    
+    debug!("📊 max_tokens for {}: requested={:?}, final={}", model_config.name, original, max_tokens);
+    request.max_tokens = Some(max_tokens);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::file::ModelOptions;
+    
+    fn model_config(min: Option<u32>, cap: Option<u32>, strategy: Option<&str>) -> ModelConfig {
+        ModelConfig {
+            name: "gpt-5-codex".to_string(),
+            enabled: true,
+            alias: None,
+            max_tokens: Some(8192),
+            context_window: None,
+            temperature: None,
+            options: ModelOptions {
+                min_output_tokens: min,
+                cap_output_tokens: cap,
+                clamp_strategy: strategy.map(|s| s.to_string()),
+                ..Default::default()
+            },
+        }
+    }
+    
+    fn request(max_tokens: Option<u32>) -> OpenAIRequest {
+        OpenAIRequest {
+            max_tokens,
+            ..Default::default()
+        }
+    }
+    
+    #[test]
+    fn test_defaults() {
+        let config = model_config(None, None, None);
+        
+        let mut req = request(Some(1));
+        assert!(apply(&mut req, &config).is_ok());
+        assert_eq!(req.max_tokens, Some(1));
+        
+        // Missing max_tokens falls back to the model's maxTokens
+        let mut req = request(None);
+        assert!(apply(&mut req, &config).is_ok());
+        assert_eq!(req.max_tokens, Some(8192));
+        
+        let mut req = request(Some(DEFAULT_OUTPUT_TOKEN_CAP + 1));
+        assert!(apply(&mut req, &config).is_err());
+    }
+    
+    #[test]
+    fn test_min_output_tokens() {
+        let mut req = request(Some(1));
+        assert!(apply(&mut req, &model_config(Some(1024), None, None)).is_ok());
+        assert_eq!(req.max_tokens, Some(1024));
+    }
+    
+    #[test]
+    fn test_cap_output_tokens() {
+        let mut req = request(Some(64000));
+        let error = apply(&mut req, &model_config(None, Some(32000), Some(STRATEGY_REJECT))).unwrap_err();
+        assert!(error.starts_with("max_tokens: 64000 > 32000"));
+        assert_eq!(req.max_tokens, Some(64000));
+        
+        assert!(apply(&mut req, &model_config(None, Some(32000), Some(STRATEGY_CLAMP))).is_ok());
+        assert_eq!(req.max_tokens, Some(32000));
+        
+        // A cap above the default limit allows larger requests
+        let mut req = request(Some(128000));
+        assert!(apply(&mut req, &model_config(None, Some(128000), None)).is_ok());
+    }
+}
@@ -0,0 +1,215 @@
+//! System prompt rewrite rules
+//!
+//! `promptRules` adapt the system prompt sent upstream to the Claude model a
+//! request asks for: organization-wide instructions can be put before or
+//! after it, Claude Code's Anthropic-specific boilerplate (its identity line,
+//! the model name and knowledge cutoff) can be removed for models it only
+//! confuses, and phrases can be replaced with regular expressions. Every
+//! matching rule is applied, in order, before the request is converted.
+
+use crate::config::PromptRule;
+use crate::models::claude::{ClaudeSystemBlock, SystemPrompt};
+use anyhow::{Context, Result};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::borrow::Cow;
+use tracing::debug;
+
+/// Claude Code and Agent SDK lines that only make sense for Anthropic models
+static BOILERPLATE: Lazy<Vec<Regex>> = Lazy::new(|| {
+    [
+        r"(?m)^You are Claude Code, Anthropic's official CLI for Claude\.[ \t]*\n?",
+        r"(?m)^You are a Claude agent, built on Anthropic's Claude Agent SDK\.[ \t]*\n?",
+        r"(?m)^You are powered by the model .*\n?",
+        r"(?m)^Assistant knowledge cutoff is .*\n?",
+        r"(?s)<claude_background_info>.*?</claude_background_info>\n?",
+    ]
+    .iter()
+    .map(|pattern| Regex::new(pattern).expect("valid boilerplate pattern"))
+    .collect()
+});
+
+/// Blank lines left behind by removed boilerplate
+static EXTRA_BLANK_LINES: Lazy<Regex> = Lazy::new(|| Regex::new(r"\n{3,}").expect("valid pattern"));
+
+/// Compiled `promptRules`
+#[derive(Debug, Clone, Default)]
+pub struct PromptRewriter {
+    rules: Vec<CompiledRule>,
+}
+
+#[derive(Debug, Clone)]
+struct CompiledRule {
+    rule: PromptRule,
+    replacements: Vec<(Regex, String)>,
+}
+
+impl PromptRewriter {
+    /// Compile the rules' regular expressions
+    pub fn new(rules: &[PromptRule]) -> Result<Self> {
+        let rules = rules.iter()
+            .map(|rule| {
+                let replacements = rule.replace.iter()
+                    .map(|replacement| {
+                        let pattern = Regex::new(&replacement.pattern)
+                            .with_context(|| format!("Invalid promptRules pattern '{}'", replacement.pattern))?;
+                        Ok((pattern, replacement.with.clone()))
+                    })
+                    .collect::<Result<_>>()?;
+                Ok(CompiledRule { rule: rule.clone(), replacements })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { rules })
+    }
+    
+    /// Whether there are no rules
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+    
+    /// Rewrite the system prompt of a request for a Claude model
+    pub fn apply(&self, model: &str, mut system: Option<SystemPrompt>) -> Option<SystemPrompt> {
+        for compiled in self.rules.iter().filter(|compiled| compiled.rule.matches(model)) {
+            debug!("✏️ Applying prompt rule {:?} to {}", compiled.rule.model, model);
+            system = compiled.apply(system);
+        }
+        system
+    }
+}
+
+impl CompiledRule {
+    fn apply(&self, system: Option<SystemPrompt>) -> Option<SystemPrompt> {
+        let (prepend, append) = (self.rule.prepend.as_deref(), self.rule.append.as_deref());
+        match system {
+            None => {
+                let text = prepend.into_iter().chain(append).collect::<Vec<_>>().join("\n\n");
+                (!text.is_empty()).then_some(SystemPrompt::String(text))
+            }
+            Some(SystemPrompt::String(text)) => {
+                let text = self.rewrite(&text);
+                let parts: Vec<&str> = prepend.into_iter()
+                    .chain(Some(text.as_str()).filter(|text| !text.is_empty()))
+                    .chain(append)
+                    .collect();
+                Some(SystemPrompt::String(parts.join("\n\n")))
+            }
+            // Added text gets blocks of its own so the client's cache markers stay put
+            Some(SystemPrompt::Array(blocks)) => {
+                let text_block = |text: &str| ClaudeSystemBlock {
+                    block_type: "text".to_string(),
+                    text: text.to_string(),
+                    cache_control: None,
+                };
+                let blocks = prepend.map(text_block).into_iter()
+                    .chain(blocks.into_iter().map(|mut block| {
+                        if block.block_type == "text" {
+                            block.text = self.rewrite(&block.text);
+                        }
+                        block
+                    }))
+                    .chain(append.map(text_block))
+                    .collect();
+                Some(SystemPrompt::Array(blocks))
+            }
+        }
+    }
+    
+    /// Strip boilerplate and apply the replacements to one text
+    fn rewrite(&self, text: &str) -> String {
+        let mut text = Cow::Borrowed(text);
+        if self.rule.strip_boilerplate {
+            for pattern in BOILERPLATE.iter() {
+                text = Cow::Owned(pattern.replace_all(&text, "").into_owned());
+            }
+            text = Cow::Owned(EXTRA_BLANK_LINES.replace_all(text.trim(), "\n\n").into_owned());
+        }
+        for (pattern, replacement) in &self.replacements {
+            text = Cow::Owned(pattern.replace_all(&text, replacement.as_str()).into_owned());
+        }
+        text.into_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PromptReplacement;
+    
+    const CLAUDE_CODE_PROMPT: &str = "You are Claude Code, Anthropic's official CLI for Claude.\n\
+        You are an interactive CLI tool that helps users with software engineering tasks.\n\n\
+        You are powered by the model named Sonnet 4.5. The exact model ID is claude-sonnet-4-5-20250929.\n\n\
+        Assistant knowledge cutoff is January 2025.\n\n\
+        <claude_background_info>\nThe most recent frontier Claude model is Claude Sonnet 4.5.\n</claude_background_info>\n\n\
+        Use the TodoWrite tool to plan tasks.";
+    
+    fn text(system: Option<SystemPrompt>) -> String {
+        match system {
+            Some(SystemPrompt::String(text)) => text,
+            other => panic!("expected a string system prompt, got {:?}", other),
+        }
+    }
+    
+    #[test]
+    fn test_strip_boilerplate() {
+        let rewriter = PromptRewriter::new(&[PromptRule {
+            model: Some("haiku".to_string()),
+            strip_boilerplate: true,
+            ..Default::default()
+        }]).unwrap();
+        
+        let system = Some(SystemPrompt::String(CLAUDE_CODE_PROMPT.to_string()));
+        assert_eq!(
+            text(rewriter.apply("claude-3-5-haiku-20241022", system.clone())),
+            "You are an interactive CLI tool that helps users with software engineering tasks.\n\nUse the TodoWrite tool to plan tasks."
+        );
+        // Other models keep the prompt
+        assert_eq!(text(rewriter.apply("claude-sonnet-4-5", system)), CLAUDE_CODE_PROMPT);
+    }
+    
+    #[test]
+    fn test_prepend_append_and_replace() {
+        let rewriter = PromptRewriter::new(&[
+            PromptRule {
+                prepend: Some("Follow the ACME coding standards.".to_string()),
+                append: Some("Never push to main.".to_string()),
+                ..Default::default()
+            },
+            PromptRule {
+                replace: vec![PromptReplacement { pattern: r"Claude (\w+)".to_string(), with: "the ${1} assistant".to_string() }],
+                ..Default::default()
+            },
+        ]).unwrap();
+        
+        let system = Some(SystemPrompt::String("You are Claude Code.".to_string()));
+        assert_eq!(
+            text(rewriter.apply("sonnet", system)),
+            "Follow the ACME coding standards.\n\nYou are the Code assistant.\n\nNever push to main."
+        );
+        assert_eq!(
+            text(rewriter.apply("sonnet", None)),
+            "Follow the ACME coding standards.\n\nNever push to main."
+        );
+        
+        // Cached blocks keep their markers, the added text gets its own blocks
+        let cached = ClaudeSystemBlock {
+            block_type: "text".to_string(),
+            text: "You are Claude Code.".to_string(),
+            cache_control: Some(serde_json::json!({"type": "ephemeral"})),
+        };
+        let Some(SystemPrompt::Array(blocks)) = rewriter.apply("sonnet", Some(SystemPrompt::Array(vec![cached]))) else {
+            panic!("expected system blocks");
+        };
+        assert_eq!(blocks.len(), 3);
+        assert_eq!(blocks[1].text, "You are the Code assistant.");
+        assert!(blocks[1].cache_control.is_some());
+    }
+    
+    #[test]
+    fn test_invalid_pattern() {
+        let rules = [PromptRule {
+            replace: vec![PromptReplacement { pattern: "(".to_string(), with: String::new() }],
+            ..Default::default()
+        }];
+        assert!(PromptRewriter::new(&rules).is_err());
+    }
+}
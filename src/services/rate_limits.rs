@@ -0,0 +1,252 @@
+//! Upstream rate limit budgets
+//!
+//! A provider's `rateLimit` option declares the requests and tokens per
+//! minute its upstream allows. Instead of sending requests that would come
+//! back with a 429, the router schedules them under those limits: each
+//! request reserves one request and its estimated tokens (prompt plus
+//! `max_tokens`, which is how upstreams count them) from the provider's
+//! budgets, waiting until they have refilled enough. Budgets refill
+//! continuously, a minute's worth at most, and reservations are taken in
+//! order of arrival, so concurrent sessions share a provider fairly.
+//!
+//! A request that would wait longer than `maxWaitMs` fails at once with a
+//! rate limit error, which a failover chain moves on from.
+//!
+//! Paths that answer with a 429 anyway are put on cool-down for the delay the
+//! upstream asked for (a minute when it doesn't say), and the router sends
+//! their requests to other upstreams until it has passed.
+
+use crate::config::{AppConfig, RateLimitConfig};
+use crate::providers::{ProviderError, UpstreamError};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::debug;
+
+/// Budget a request draws from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Kind {
+    Requests,
+    Tokens,
+}
+
+/// Per-minute budget, refilled continuously
+#[derive(Debug, Clone, Copy)]
+struct Budget {
+    /// Available amount, negative while reservations wait for a refill
+    available: f64,
+    /// Time `available` was last refilled
+    updated: Instant,
+}
+
+impl Budget {
+    fn full(limit: u32, now: Instant) -> Self {
+        Self { available: f64::from(limit), updated: now }
+    }
+    
+    /// Refill up to `now` and return the wait until `cost` is available
+    fn delay(&mut self, limit: u32, cost: f64, now: Instant) -> Duration {
+        let limit = f64::from(limit);
+        let per_sec = limit / 60.0;
+        self.available = (self.available + now.saturating_duration_since(self.updated).as_secs_f64() * per_sec).min(limit);
+        self.updated = now;
+        // A request larger than the whole budget waits for a full one
+        Duration::from_secs_f64(((cost.min(limit) - self.available) / per_sec).max(0.0))
+    }
+}
+
+/// Cool-down of a rate limited path when the upstream doesn't say how long to wait
+pub const DEFAULT_COOL_DOWN: Duration = Duration::from_secs(60);
+
+/// Requests and tokens per minute sent to each provider with a `rateLimit`,
+/// and the paths cooling down after a 429
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    configs: HashMap<String, RateLimitConfig>,
+    budgets: Arc<Mutex<HashMap<(String, Kind), Budget>>>,
+    /// End of the cool-down of each rate limited provider/model path
+    cool_downs: Arc<Mutex<HashMap<String, Instant>>>,
+}
+
+impl RateLimiter {
+    /// Create a limiter with full budgets for the providers of a configuration
+    pub fn new(config: &AppConfig) -> Self {
+        let configs = config.providers.iter()
+            .filter_map(|(name, provider)| Some((name.clone(), provider.options.rate_limit.clone()?)))
+            .collect();
+        Self { configs, ..Self::default() }
+    }
+    
+    /// Take over the budgets spent so far and the cool-downs, when reloading
+    /// the configuration
+    pub fn with_state_of(self, previous: &RateLimiter) -> Self {
+        Self { budgets: previous.budgets.clone(), cool_downs: previous.cool_downs.clone(), ..self }
+    }
+    
+    /// Put a path on cool-down, or extend its cool-down
+    pub fn cool_down(&self, model_path: &str, duration: Duration) {
+        let until = Instant::now() + duration;
+        let mut cool_downs = self.cool_downs.lock().unwrap_or_else(PoisonError::into_inner);
+        let entry = cool_downs.entry(model_path.to_string()).or_insert(until);
+        *entry = (*entry).max(until);
+    }
+    
+    /// Whether a path is cooling down after a 429
+    pub fn is_cooling_down(&self, model_path: &str) -> bool {
+        let mut cool_downs = self.cool_downs.lock().unwrap_or_else(PoisonError::into_inner);
+        match cool_downs.get(model_path) {
+            Some(until) if *until > Instant::now() => true,
+            Some(_) => {
+                cool_downs.remove(model_path);
+                false
+            }
+            None => false,
+        }
+    }
+    
+    /// Whether a provider has a `rateLimit`
+    pub fn is_limited(&self, provider: &str) -> bool {
+        self.configs.contains_key(provider)
+    }
+    
+    /// Wait until a provider's budgets allow a request of `tokens` estimated tokens
+    ///
+    /// Fails with a rate limit error, without reserving anything, when the
+    /// wait would exceed the provider's `maxWaitMs`.
+    pub async fn acquire(&self, provider: &str, tokens: u32) -> Result<(), ProviderError> {
+        let Some(config) = self.configs.get(provider) else {
+            return Ok(());
+        };
+        let costs = [
+            (Kind::Requests, config.requests_per_minute, 1.0),
+            (Kind::Tokens, config.tokens_per_minute, f64::from(tokens)),
+        ];
+        
+        let delay = {
+            let mut budgets = self.budgets.lock().unwrap_or_else(PoisonError::into_inner);
+            let now = Instant::now();
+            let mut delay = Duration::ZERO;
+            for (kind, limit, cost) in costs {
+                if let Some(limit) = limit {
+                    let budget = budgets.entry((provider.to_string(), kind)).or_insert_with(|| Budget::full(limit, now));
+                    delay = delay.max(budget.delay(limit, cost, now));
+                }
+            }
+            let max_wait = Duration::from_millis(config.max_wait_ms);
+            if delay > max_wait {
+                return Err(budget_exhausted(provider, delay));
+            }
+            for (kind, limit, cost) in costs {
+                if let (Some(limit), Some(budget)) = (limit, budgets.get_mut(&(provider.to_string(), kind))) {
+                    budget.available -= cost.min(f64::from(limit));
+                }
+            }
+            delay
+        };
+        
+        if !delay.is_zero() {
+            debug!("⏳ Holding request to {} for {}ms to stay under its rate limit", provider, delay.as_millis());
+            tokio::time::sleep(delay).await;
+        }
+        Ok(())
+    }
+}
+
+/// Error for a request the budgets can't take within `maxWaitMs`
+fn budget_exhausted(provider: &str, delay: Duration) -> ProviderError {
+    let retry_after = delay.as_secs_f64().ceil() as u64;
+    let message = format!("Rate limit of provider '{}' reached, next request possible in {}s", provider, retry_after);
+    ProviderError::RateLimited {
+        retry_after: Some(delay),
+        upstream: UpstreamError {
+            provider: provider.to_string(),
+            status: 429,
+            body: serde_json::json!({"error": {"type": "rate_limit_error", "message": message}}).to_string(),
+            retry_after: Some(retry_after.to_string()),
+            rate_limit_reset: None,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ProviderConfig;
+    
+    fn limiter(rate_limit: RateLimitConfig) -> RateLimiter {
+        let mut provider = ProviderConfig {
+            provider_type: "openai".to_string(),
+            base_url: "https://api.openai.com/v1".to_string(),
+            api_key: String::new(),
+            api_key_file: None,
+            enabled: true,
+            options: Default::default(),
+            models: HashMap::new(),
+        };
+        provider.options.rate_limit = Some(rate_limit);
+        let mut config = AppConfig::default();
+        config.providers.insert("openai".to_string(), provider);
+        RateLimiter::new(&config)
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn test_requests_per_minute() {
+        let limiter = limiter(RateLimitConfig { requests_per_minute: Some(2), tokens_per_minute: None, max_wait_ms: 60000 });
+        let started = Instant::now();
+        limiter.acquire("openai", 0).await.unwrap();
+        limiter.acquire("openai", 0).await.unwrap();
+        assert!(started.elapsed() < Duration::from_millis(10));
+        
+        // The third request waits for half a minute's refill
+        limiter.acquire("openai", 0).await.unwrap();
+        assert!(started.elapsed() >= Duration::from_secs(29));
+        
+        // Providers without a rateLimit aren't held back
+        limiter.acquire("other", 0).await.unwrap();
+    }
+    
+    #[tokio::test]
+    async fn test_tokens_per_minute_exhausted() {
+        let limiter = limiter(RateLimitConfig { requests_per_minute: None, tokens_per_minute: Some(6000), max_wait_ms: 1000 });
+        limiter.acquire("openai", 5000).await.unwrap();
+        
+        // 4000 more tokens would take 30 seconds to refill
+        let error = limiter.acquire("openai", 4000).await.unwrap_err();
+        assert!(error.is_retryable());
+        assert_eq!(error.claude_error().0, "rate_limit_error");
+        assert!(error.retry_after().is_some_and(|delay| delay > Duration::from_secs(29)));
+        
+        // The failed request reserved nothing
+        limiter.acquire("openai", 1000).await.unwrap();
+    }
+    
+    #[tokio::test]
+    async fn test_budgets_carry_over() {
+        let config = RateLimitConfig { requests_per_minute: Some(1), tokens_per_minute: None, max_wait_ms: 0 };
+        let previous = limiter(config.clone());
+        previous.acquire("openai", 0).await.unwrap();
+        
+        assert!(limiter(config.clone()).acquire("openai", 0).await.is_ok());
+        assert!(limiter(config).with_state_of(&previous).acquire("openai", 0).await.is_err());
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn test_cool_down() {
+        let limiter = RateLimiter::default();
+        limiter.cool_down("openai/gpt-4o", Duration::from_secs(20));
+        limiter.cool_down("openai/gpt-4o", Duration::from_secs(10));
+        assert!(limiter.is_cooling_down("openai/gpt-4o"));
+        assert!(!limiter.is_cooling_down("openai/gpt-4o-mini"));
+        
+        // A shorter cool-down doesn't cut the longer one short
+        tokio::time::advance(Duration::from_secs(15)).await;
+        assert!(limiter.is_cooling_down("openai/gpt-4o"));
+        tokio::time::advance(Duration::from_secs(5)).await;
+        assert!(!limiter.is_cooling_down("openai/gpt-4o"));
+        
+        let reloaded = RateLimiter::default().with_state_of(&limiter);
+        limiter.cool_down("openai/gpt-4o", Duration::from_secs(10));
+        assert!(reloaded.is_cooling_down("openai/gpt-4o"));
+    }
+}
@@ -0,0 +1,141 @@
+//! Reasoning effort support
+//!
+//! OpenAI reasoning models take a coarse `reasoning_effort` (low/medium/high)
+//! instead of Claude's extended thinking token budget. The effort comes from
+//! the model's `reasoningEffort` option, or is derived from the request's
+//! `thinking.budget_tokens` when the client enabled extended thinking.
+
+use crate::config::ModelConfig;
+use crate::models::openai::OpenAIRequest;
+use tracing::debug;
+
+/// Extension key carrying the effort to providers
+pub const EXTENSION_KEY: &str = "reasoning_effort";
+
+pub const EFFORT_LOW: &str = "low";
+pub const EFFORT_MEDIUM: &str = "medium";
+pub const EFFORT_HIGH: &str = "high";
+
+/// Thinking budgets below this map to low effort ("think" in Claude Code is 4k)
+const LOW_BUDGET_LIMIT: u32 = 8192;
+
+/// Thinking budgets below this map to medium effort ("think hard" is 10k,
+/// "ultrathink" is 32k)
+const MEDIUM_BUDGET_LIMIT: u32 = 24576;
+
+/// Map a Claude thinking token budget to a reasoning effort
+pub fn effort_for_budget(budget_tokens: u32) -> &'static str {
+    if budget_tokens < LOW_BUDGET_LIMIT {
+        EFFORT_LOW
+    } else if budget_tokens < MEDIUM_BUDGET_LIMIT {
+        EFFORT_MEDIUM
+    } else {
+        EFFORT_HIGH
+    }
+}
+
+/// Whether an upstream model is an OpenAI reasoning model (o-series, GPT-5)
+pub fn is_reasoning_model(model: &str) -> bool {
+    let model = model.rsplit('/').next().unwrap_or(model).to_lowercase();
+    model.starts_with("gpt-5")
+        || ["o1", "o3", "o4"].iter().any(|prefix| {
+            model == *prefix || model.starts_with(&format!("{}-", prefix))
+        })
+}
+
+/// Resolve the effort sent upstream and store it in the request extensions
+///
+/// An effort derived from the request's thinking budget wins over the
+/// configured `reasoningEffort`. It is only sent to models that opted in via
+/// `reasoningEffort` or are known reasoning models; otherwise it is dropped.
+pub fn apply(request: &mut OpenAIRequest, model_config: &ModelConfig) {
+    let requested = request.extensions.remove(EXTENSION_KEY)
+        .and_then(|effort| effort.as_str().map(|e| e.to_string()));
+    let configured = model_config.options.reasoning_effort.clone();
+    
+    if configured.is_none() && !is_reasoning_model(&model_config.name) {
+        if let Some(effort) = requested {
+            debug!("📊 Model {} does not take a reasoning effort, dropping {}", model_config.name, effort);
+        }
+        return;
+    }
+    
+    if let Some(effort) = requested.or(configured) {
+        debug!("📊 Reasoning effort for {}: {}", model_config.name, effort);
+        request.extensions.insert(EXTENSION_KEY.to_string(), serde_json::json!(effort));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::file::ModelOptions;
+    
+    fn model_config(name: &str, effort: Option<&str>) -> ModelConfig {
+        ModelConfig {
+            name: name.to_string(),
+            enabled: true,
+            alias: None,
+            max_tokens: None,
+            context_window: None,
+            temperature: None,
+            options: ModelOptions {
+                reasoning_effort: effort.map(|e| e.to_string()),
+                ..Default::default()
+            },
+        }
+    }
+    
+    fn request(effort: Option<&str>) -> OpenAIRequest {
+        let mut request = OpenAIRequest::default();
+        if let Some(effort) = effort {
+            request.extensions.insert(EXTENSION_KEY.to_string(), serde_json::json!(effort));
+        }
+        request
+    }
+    
+    fn effort(request: &OpenAIRequest) -> Option<&str> {
+        request.extensions.get(EXTENSION_KEY).and_then(|e| e.as_str())
+    }
+    
+    #[test]
+    fn test_effort_for_budget() {
+        assert_eq!(effort_for_budget(1024), EFFORT_LOW);
+        assert_eq!(effort_for_budget(4000), EFFORT_LOW);
+        assert_eq!(effort_for_budget(10000), EFFORT_MEDIUM);
+        assert_eq!(effort_for_budget(31999), EFFORT_HIGH);
+    }
+    
+    #[test]
+    fn test_is_reasoning_model() {
+        assert!(is_reasoning_model("o3-mini"));
+        assert!(is_reasoning_model("o1"));
+        assert!(is_reasoning_model("openai/gpt-5-codex"));
+        assert!(!is_reasoning_model("gpt-4o"));
+        assert!(!is_reasoning_model("o1x"));
+        assert!(!is_reasoning_model("glm-4.6"));
+    }
+    
+    #[test]
+    fn test_apply() {
+        // Configured effort is used when the request has no thinking budget
+        let mut req = request(None);
+        apply(&mut req, &model_config("doubao-seed-1.6", Some("medium")));
+        assert_eq!(effort(&req), Some("medium"));
+        
+        // A thinking-derived effort wins over the configured one
+        let mut req = request(Some("high"));
+        apply(&mut req, &model_config("doubao-seed-1.6", Some("low")));
+        assert_eq!(effort(&req), Some("high"));
+        
+        // Reasoning models accept a derived effort without configuration
+        let mut req = request(Some("low"));
+        apply(&mut req, &model_config("o3", None));
+        assert_eq!(effort(&req), Some("low"));
+        
+        // Other models never receive it
+        let mut req = request(Some("low"));
+        apply(&mut req, &model_config("gpt-4o", None));
+        assert_eq!(effort(&req), None);
+    }
+}
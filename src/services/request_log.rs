@@ -0,0 +1,406 @@
+//! Log of completed requests
+//!
+//! With `requestLog`, every `/v1/messages` request is written as one JSON line
+//! when it completes: when it started, the client key and tenant, the Claude
+//! model and the provider/model path it was routed to, latency, token usage,
+//! HTTP status and stop reason, and, with `maxBodyBytes`, the request and
+//! response bodies cut to that size. The file is separate from the tracing
+//! output and rotated by size, for offline analysis.
+//!
+//! The same [`RequestSummary`] is passed to the [request hooks](super::hooks),
+//! such as the configured webhooks, and requests of a session are saved to
+//! its [transcript](super::transcripts).
+
+use crate::config::{ModelPricing, RequestLogConfig};
+use crate::middleware::auth::ClientKey;
+use crate::models::claude::{ClaudeContentDelta, ClaudeRequest, ClaudeResponse, ClaudeStreamEvent};
+use crate::services::experiments::Arm;
+use crate::services::guardrails::Findings;
+use crate::services::hooks::{self, RequestHook};
+use crate::services::sessions;
+use crate::services::transcripts::{PendingTurn, TranscriptStore};
+use anyhow::{Context, Result};
+use axum::http::StatusCode;
+use serde::Serialize;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tracing::warn;
+
+/// Rotating JSON Lines file of completed requests
+#[derive(Debug)]
+pub struct RequestLog {
+    config: RequestLogConfig,
+    file: Mutex<LogFile>,
+}
+
+#[derive(Debug)]
+struct LogFile {
+    file: File,
+    size: u64,
+}
+
+impl RequestLog {
+    /// Open the log file for appending, creating it and its directory if needed
+    pub fn open(config: &RequestLogConfig) -> Result<Self> {
+        if let Some(dir) = config.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).with_context(|| format!("Failed to create request log directory {}", dir.display()))?;
+        }
+        let file = open_append(&config.path)
+            .with_context(|| format!("Failed to open request log {}", config.path.display()))?;
+        let size = file.metadata()?.len();
+        Ok(Self { config: config.clone(), file: Mutex::new(LogFile { file, size }) })
+    }
+    
+    /// Configuration the log was opened with
+    pub fn config(&self) -> &RequestLogConfig {
+        &self.config
+    }
+    
+    /// Append one line, rotating the file first if the line doesn't fit
+    pub(crate) fn write(&self, entry: &impl Serialize) {
+        let mut line = match serde_json::to_vec(entry) {
+            Ok(line) => line,
+            Err(e) => {
+                warn!("📒 Failed to serialize request log entry: {}", e);
+                return;
+            }
+        };
+        line.push(b'\n');
+        
+        let mut file = self.file.lock().unwrap();
+        if file.size > 0 && file.size + line.len() as u64 > self.config.max_file_bytes {
+            if let Err(e) = self.rotate(&mut file) {
+                warn!("📒 Failed to rotate request log {}: {}", self.config.path.display(), e);
+            }
+        }
+        match file.file.write_all(&line) {
+            Ok(()) => file.size += line.len() as u64,
+            Err(e) => warn!("📒 Failed to write request log {}: {}", self.config.path.display(), e),
+        }
+    }
+    
+    /// Shift `path.1` .. `path.{maxFiles - 1}` up by one, move the current
+    /// file to `path.1` and start a new one
+    fn rotate(&self, file: &mut LogFile) -> io::Result<()> {
+        let path = &self.config.path;
+        let rotated = |n: usize| {
+            let mut rotated = path.clone().into_os_string();
+            rotated.push(format!(".{}", n));
+            PathBuf::from(rotated)
+        };
+        
+        file.file = if self.config.max_files == 0 {
+            File::create(path)?
+        } else {
+            let oldest = rotated(self.config.max_files);
+            if oldest.exists() {
+                fs::remove_file(oldest)?;
+            }
+            for n in (1..self.config.max_files).rev() {
+                if rotated(n).exists() {
+                    fs::rename(rotated(n), rotated(n + 1))?;
+                }
+            }
+            fs::rename(path, rotated(1))?;
+            open_append(path)?
+        };
+        file.size = 0;
+        Ok(())
+    }
+}
+
+fn open_append(path: &std::path::Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// Summary of a completed request: a line of the request log and the
+/// payload of request hooks
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RequestSummary {
+    /// Time the request was received (RFC 3339)
+    pub timestamp: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    /// Claude model requested
+    pub model: String,
+    /// Provider/model path the request was routed to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub route: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+    /// A/B experiment the request took part in, and its arm
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub experiment: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub arm: Option<String>,
+    pub stream: bool,
+    pub status: u16,
+    pub latency_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub input_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_tokens: Option<u32>,
+    /// Input tokens read from the upstream's prompt cache, not included in `input_tokens`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_read_input_tokens: Option<u32>,
+    /// Estimated cost in USD, for routes in the `pricing` table
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cost_usd: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop_reason: Option<String>,
+    /// Type of the error event that ended a stream
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Guardrail rules that matched the prompt or the response
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub guardrails: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response: Option<String>,
+}
+
+/// A request being recorded; when the record is dropped its summary is
+/// written to the request log and passed to the request hooks, and its turn
+/// is appended to the session transcript
+///
+/// Non-streaming requests are complete when the handler returns; a streaming
+/// response's record moves into the stream and is dropped with it, also when
+/// the client disconnects early (the summary then has no `stop_reason`).
+pub struct RequestRecord {
+    log: Option<Arc<RequestLog>>,
+    hooks: Vec<Arc<dyn RequestHook>>,
+    transcript: Option<PendingTurn>,
+    pricing: Option<ModelPricing>,
+    max_body_bytes: usize,
+    started: Instant,
+    entry: RequestSummary,
+}
+
+impl std::fmt::Debug for RequestRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RequestRecord")
+            .field("log", &self.log.is_some())
+            .field("hooks", &self.hooks.len())
+            .field("transcript", &self.transcript)
+            .field("entry", &self.entry)
+            .finish()
+    }
+}
+
+impl RequestRecord {
+    /// Start the record of a request, or None without a log, hooks or
+    /// transcript to pass it to
+    pub fn start(
+        log: Option<Arc<RequestLog>>,
+        hooks: Vec<Arc<dyn RequestHook>>,
+        transcripts: Option<Arc<TranscriptStore>>,
+        request_id: Option<&str>,
+        client: Option<&ClientKey>,
+        request: &ClaudeRequest,
+    ) -> Option<Self> {
+        let session_id = sessions::session_id_from_metadata(request.metadata.as_ref());
+        let transcript = transcripts.and_then(|store| PendingTurn::start(store, session_id, request));
+        if log.is_none() && hooks.is_empty() && transcript.is_none() {
+            return None;
+        }
+        let max_body_bytes = log.as_ref().map_or(0, |log| log.config.max_body_bytes);
+        let body = (max_body_bytes > 0)
+            .then(|| serde_json::to_string(request).ok())
+            .flatten()
+            .map(|body| truncate(body, max_body_bytes));
+        let entry = RequestSummary {
+            timestamp: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            request_id: request_id.map(str::to_string),
+            key: client.map(|client| client.name.clone()),
+            tenant: client.map(|client| client.tenant.clone()),
+            model: request.model.clone(),
+            stream: request.stream.unwrap_or(false),
+            request: body,
+            ..Default::default()
+        };
+        Some(Self { log, hooks, transcript, pricing: None, max_body_bytes, started: Instant::now(), entry })
+    }
+    
+    /// Record the provider/model path the request is sent to, and its pricing
+    pub fn set_route(&mut self, route: &str, pricing: Option<ModelPricing>) {
+        self.entry.provider = route.split_once('/').map(|(provider, _)| provider.to_string());
+        self.entry.route = Some(route.to_string());
+        self.pricing = pricing;
+    }
+    
+    /// Record the experiment arm the request was routed to
+    pub fn set_experiment(&mut self, experiment: &str, arm: Arm) {
+        self.entry.experiment = Some(experiment.to_string());
+        self.entry.arm = Some(arm.as_str().to_string());
+    }
+    
+    /// Record the guardrail rules that matched the prompt or the response
+    pub fn record_guardrails(&mut self, findings: &Findings) {
+        for rule in &findings.rules {
+            if !self.entry.guardrails.contains(rule) {
+                self.entry.guardrails.push(rule.clone());
+            }
+        }
+    }
+    
+    /// Record the HTTP status of the response
+    pub fn set_status(&mut self, status: StatusCode) {
+        self.entry.status = status.as_u16();
+    }
+    
+    /// Record a complete response
+    pub fn record_response(&mut self, response: &ClaudeResponse) {
+        self.entry.input_tokens = Some(response.usage.input_tokens);
+        self.entry.output_tokens = Some(response.usage.output_tokens);
+        self.entry.cache_read_input_tokens = response.usage.cache_read_input_tokens;
+        self.entry.stop_reason = response.stop_reason.clone();
+        if let Some(transcript) = &mut self.transcript {
+            transcript.record_response(response);
+        }
+        if self.max_body_bytes > 0 {
+            self.entry.response = serde_json::to_string(response).ok().map(|body| truncate(body, self.max_body_bytes));
+        }
+    }
+    
+    /// Record an event sent to the client; the response body is the streamed text
+    pub fn record_stream_event(&mut self, event: &ClaudeStreamEvent) {
+        if let Some(transcript) = &mut self.transcript {
+            transcript.record_stream_event(event);
+        }
+        match event {
+            ClaudeStreamEvent::MessageStart { message } => self.entry.input_tokens = Some(message.usage.input_tokens),
+            ClaudeStreamEvent::MessageDelta { delta, usage } => {
+                self.entry.output_tokens = Some(usage.output_tokens);
+                self.entry.stop_reason = delta.stop_reason.clone();
+            }
+            ClaudeStreamEvent::ContentBlockDelta { delta: ClaudeContentDelta::TextDelta { text }, .. } => {
+                let max_body_bytes = self.max_body_bytes;
+                if max_body_bytes == 0 {
+                    return;
+                }
+                let response = self.entry.response.get_or_insert_with(String::new);
+                if max_body_bytes > response.len() {
+                    response.push_str(text);
+                    *response = truncate(std::mem::take(response), max_body_bytes);
+                }
+            }
+            ClaudeStreamEvent::Error { error } => self.entry.error = Some(error.error_type.clone()),
+            _ => {}
+        }
+    }
+}
+
+impl Drop for RequestRecord {
+    fn drop(&mut self) {
+        self.entry.latency_ms = self.started.elapsed().as_millis() as u64;
+        let (input_tokens, output_tokens) = (self.entry.input_tokens, self.entry.output_tokens);
+        if let Some(pricing) = self.pricing.as_ref().filter(|_| input_tokens.is_some() || output_tokens.is_some()) {
+            let cached_tokens = self.entry.cache_read_input_tokens.unwrap_or(0);
+            self.entry.cost_usd = Some(pricing.cost(input_tokens.unwrap_or(0), cached_tokens, output_tokens.unwrap_or(0)));
+        }
+        if let Some(log) = &self.log {
+            log.write(&self.entry);
+        }
+        hooks::dispatch(&self.hooks, &self.entry);
+        if let Some(transcript) = self.transcript.take() {
+            transcript.finish(&self.entry);
+        }
+    }
+}
+
+/// Cut a body to at most `max_bytes`, at a character boundary
+pub(crate) fn truncate(mut body: String, max_bytes: usize) -> String {
+    if body.len() > max_bytes {
+        let end = (0..=max_bytes).rev().find(|&end| body.is_char_boundary(end)).unwrap_or(0);
+        body.truncate(end);
+    }
+    body
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::claude::{ClaudeMessageDelta, ClaudeUsage};
+    
+    fn config(dir: &tempfile::TempDir, max_file_bytes: u64) -> RequestLogConfig {
+        RequestLogConfig {
+            path: dir.path().join("logs").join("requests.jsonl"),
+            max_file_bytes,
+            max_files: 2,
+            max_body_bytes: 16,
+        }
+    }
+    
+    fn lines(path: &std::path::Path) -> Vec<serde_json::Value> {
+        fs::read_to_string(path).unwrap_or_default()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+    
+    #[test]
+    fn test_record_stream() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = Arc::new(RequestLog::open(&config(&dir, 1 << 20)).unwrap());
+        let request = ClaudeRequest::builder().model("claude-sonnet-4-5").max_tokens(100).user("Hello").stream(true).build();
+        
+        let mut record = RequestRecord::start(Some(log.clone()), Vec::new(), None, Some("req_1"), None, &request).unwrap();
+        let pricing = ModelPricing { input_per_mtok: 1.0, output_per_mtok: 1_000_000.0, ..Default::default() };
+        record.set_route("openai/gpt-4o", Some(pricing));
+        record.set_status(StatusCode::OK);
+        for text in ["Hello there, ", "how can I help you?"] {
+            record.record_stream_event(&ClaudeStreamEvent::ContentBlockDelta {
+                index: 0,
+                delta: ClaudeContentDelta::TextDelta { text: text.to_string() },
+            });
+        }
+        record.record_stream_event(&ClaudeStreamEvent::MessageDelta {
+            delta: ClaudeMessageDelta { stop_reason: Some("end_turn".to_string()), stop_sequence: None },
+            usage: ClaudeUsage { output_tokens: 7, ..Default::default() },
+        });
+        drop(record);
+        
+        let lines = lines(&log.config().path);
+        assert_eq!(lines.len(), 1);
+        let entry = &lines[0];
+        assert_eq!(entry["request_id"], "req_1");
+        assert_eq!(entry["model"], "claude-sonnet-4-5");
+        assert_eq!(entry["route"], "openai/gpt-4o");
+        assert_eq!(entry["provider"], "openai");
+        assert_eq!(entry["stream"], true);
+        assert_eq!(entry["status"], 200);
+        assert_eq!(entry["output_tokens"], 7);
+        assert_eq!(entry["cost_usd"], 7.0);
+        assert_eq!(entry["stop_reason"], "end_turn");
+        assert_eq!(entry["response"], "Hello there, how");
+        assert_eq!(entry["request"].as_str().unwrap().len(), 16);
+        assert!(entry.get("tenant").is_none());
+    }
+    
+    #[test]
+    fn test_rotation() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = Arc::new(RequestLog::open(&config(&dir, 300)).unwrap());
+        let request = ClaudeRequest::builder().model("claude-sonnet-4-5").max_tokens(100).user("Hello").build();
+        for _ in 0..10 {
+            RequestRecord::start(Some(log.clone()), Vec::new(), None, None, None, &request).unwrap().set_status(StatusCode::OK);
+        }
+        
+        let path = &log.config().path;
+        let rotated = |n: usize| PathBuf::from(format!("{}.{}", path.display(), n));
+        assert!(!lines(path).is_empty());
+        assert!(!lines(&rotated(1)).is_empty());
+        assert!(!lines(&rotated(2)).is_empty());
+        // Only maxFiles rotated files are kept
+        assert!(!rotated(3).exists());
+        assert!(fs::metadata(path).unwrap().len() <= 300);
+    }
+}
@@ -0,0 +1,219 @@
+//! Per-model request parameters
+//!
+//! Models can tune upstream sampling without touching clients:
+//! `requestDefaults` fill in `top_p`, `presence_penalty`, `frequency_penalty`,
+//! `seed` and `stop` when the request leaves them unset, `requestOverrides`
+//! replace them, and `systemPromptPrefix`/`systemPromptSuffix` wrap the system
+//! prompt (a system message is added when the request has none). Prompt
+//! caching markers are dropped for models without `supportsPromptCaching`.
+
+use crate::config::file::RequestParams;
+use crate::config::ModelConfig;
+use crate::models::openai::{OpenAIContent, OpenAIContentPart, OpenAIMessage, OpenAIRequest};
+use tracing::debug;
+
+/// Apply the model's request defaults, overrides and system prompt additions
+pub fn apply(request: &mut OpenAIRequest, model_config: &ModelConfig) {
+    let options = &model_config.options;
+    fill(request, &options.request_defaults, false);
+    fill(request, &options.request_overrides, true);
+    wrap_system_prompt(request, options.system_prompt_prefix.as_deref(), options.system_prompt_suffix.as_deref());
+    if !options.supports_prompt_caching {
+        strip_cache_control(request);
+    }
+}
+
+/// Drop the cache_control markers of content parts, merging text-only
+/// content back into a single string as for prompts without markers
+fn strip_cache_control(request: &mut OpenAIRequest) {
+    for message in &mut request.messages {
+        let Some(OpenAIContent::Array(parts)) = &mut message.content else {
+            continue;
+        };
+        let mut stripped = false;
+        for part in parts.iter_mut() {
+            if let OpenAIContentPart::Text { cache_control, .. } = part {
+                stripped |= cache_control.take().is_some();
+            }
+        }
+        if !stripped {
+            continue;
+        }
+        
+        debug!("🧹 Dropped cache_control markers of a {} message for {}", message.role, request.model);
+        let texts: Option<Vec<&str>> = parts.iter()
+            .map(|part| match part {
+                OpenAIContentPart::Text { text, .. } => Some(text.as_str()),
+                _ => None,
+            })
+            .collect();
+        if let Some(texts) = texts {
+            message.content = Some(OpenAIContent::Text(texts.join("\n")));
+        }
+    }
+}
+
+/// Set the configured parameters, keeping the request's own unless `replace`
+fn fill(request: &mut OpenAIRequest, params: &RequestParams, replace: bool) {
+    fn set<T: Clone>(field: &mut Option<T>, value: &Option<T>, replace: bool) {
+        if value.is_some() && (replace || field.is_none()) {
+            *field = value.clone();
+        }
+    }
+    
+    if params.is_empty() {
+        return;
+    }
+    set(&mut request.top_p, &params.top_p, replace);
+    set(&mut request.presence_penalty, &params.presence_penalty, replace);
+    set(&mut request.frequency_penalty, &params.frequency_penalty, replace);
+    set(&mut request.seed, &params.seed, replace);
+    set(&mut request.stop, &params.stop, replace);
+    debug!("🎛️ Applied request {} for {}", if replace { "overrides" } else { "defaults" }, request.model);
+}
+
+/// Put the prefix before and the suffix after the leading system message
+fn wrap_system_prompt(request: &mut OpenAIRequest, prefix: Option<&str>, suffix: Option<&str>) {
+    if prefix.is_none() && suffix.is_none() {
+        return;
+    }
+    
+    let system = request.messages.first_mut().filter(|message| message.role == "system");
+    let Some(system) = system else {
+        let text = prefix.into_iter().chain(suffix).collect::<Vec<_>>().join("\n\n");
+        request.messages.insert(0, OpenAIMessage {
+            role: "system".to_string(),
+            content: Some(OpenAIContent::Text(text)),
+            name: None,
+            tool_calls: None,
+            tool_call_id: None,
+        });
+        return;
+    };
+    
+    match system.content.get_or_insert_with(|| OpenAIContent::Text(String::new())) {
+        OpenAIContent::Text(text) => {
+            let parts: Vec<&str> = prefix.into_iter()
+                .chain(Some(text.as_str()).filter(|text| !text.is_empty()))
+                .chain(suffix)
+                .collect();
+            *text = parts.join("\n\n");
+        }
+        // Separate parts keep the cache_control markers of the client's blocks
+        OpenAIContent::Array(parts) => {
+            let text_part = |text: &str| OpenAIContentPart::Text { text: text.to_string(), cache_control: None };
+            if let Some(prefix) = prefix {
+                parts.insert(0, text_part(prefix));
+            }
+            if let Some(suffix) = suffix {
+                parts.push(text_part(suffix));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn model_config(defaults: RequestParams, overrides: RequestParams, prefix: Option<&str>, suffix: Option<&str>) -> ModelConfig {
+        let mut config = ModelConfig::passthrough("deepseek-chat");
+        config.options.request_defaults = defaults;
+        config.options.request_overrides = overrides;
+        config.options.system_prompt_prefix = prefix.map(String::from);
+        config.options.system_prompt_suffix = suffix.map(String::from);
+        config
+    }
+    
+    #[test]
+    fn test_defaults_and_overrides() {
+        let config = model_config(
+            RequestParams { top_p: Some(0.9), seed: Some(7), stop: Some(vec!["END".to_string()]), ..Default::default() },
+            RequestParams { frequency_penalty: Some(0.5), seed: Some(42), ..Default::default() },
+            None,
+            None,
+        );
+        let mut request = OpenAIRequest {
+            top_p: Some(0.5),
+            frequency_penalty: Some(1.0),
+            ..Default::default()
+        };
+        apply(&mut request, &config);
+        
+        // Defaults keep the request's values, overrides replace them
+        assert_eq!(request.top_p, Some(0.5));
+        assert_eq!(request.stop, Some(vec!["END".to_string()]));
+        assert_eq!(request.frequency_penalty, Some(0.5));
+        assert_eq!(request.seed, Some(42));
+        assert_eq!(request.presence_penalty, None);
+    }
+    
+    #[test]
+    fn test_system_prompt() {
+        let config = model_config(Default::default(), Default::default(), Some("Answer in English."), Some("Be brief."));
+        
+        let mut request = OpenAIRequest::builder().system("You are helpful.").user("Hi").build();
+        apply(&mut request, &config);
+        assert!(matches!(
+            &request.messages[0].content,
+            Some(OpenAIContent::Text(text)) if text == "Answer in English.\n\nYou are helpful.\n\nBe brief."
+        ));
+        
+        let mut request = OpenAIRequest::builder().user("Hi").build();
+        apply(&mut request, &config);
+        assert_eq!(request.messages.len(), 2);
+        assert_eq!(request.messages[0].role, "system");
+        assert!(matches!(
+            &request.messages[0].content,
+            Some(OpenAIContent::Text(text)) if text == "Answer in English.\n\nBe brief."
+        ));
+        
+        let mut config = config;
+        config.options.supports_prompt_caching = true;
+        let mut request = cached_request();
+        apply(&mut request, &config);
+        let Some(OpenAIContent::Array(parts)) = &request.messages[0].content else {
+            panic!("expected content parts");
+        };
+        assert_eq!(parts.len(), 3);
+        assert!(matches!(&parts[1], OpenAIContentPart::Text { cache_control: Some(_), .. }));
+    }
+    
+    #[test]
+    fn test_prompt_caching_markers() {
+        let mut config = model_config(Default::default(), Default::default(), None, None);
+        
+        // Kept for models that support them
+        config.options.supports_prompt_caching = true;
+        let mut request = cached_request();
+        apply(&mut request, &config);
+        assert!(matches!(&request.messages[0].content, Some(OpenAIContent::Array(parts)) if parts.len() == 1));
+        
+        // Dropped otherwise, with the blocks merged into one string
+        config.options.supports_prompt_caching = false;
+        let mut request = cached_request();
+        if let Some(OpenAIContent::Array(parts)) = &mut request.messages[0].content {
+            parts.push(OpenAIContentPart::Text { text: "Today is Friday.".to_string(), cache_control: None });
+        }
+        apply(&mut request, &config);
+        assert!(matches!(
+            &request.messages[0].content,
+            Some(OpenAIContent::Text(text)) if text == "You are helpful.\nToday is Friday."
+        ));
+        assert!(!serde_json::to_string(&request).unwrap().contains("cache_control"));
+    }
+    
+    /// A request whose system prompt is one block with a cache_control marker
+    fn cached_request() -> OpenAIRequest {
+        let cached = OpenAIContentPart::Text { text: "You are helpful.".to_string(), cache_control: Some(serde_json::json!({"type": "ephemeral"})) };
+        OpenAIRequest::builder()
+            .message(OpenAIMessage {
+                role: "system".to_string(),
+                content: Some(OpenAIContent::Array(vec![cached])),
+                name: None,
+                tool_calls: None,
+                tool_call_id: None,
+            })
+            .build()
+    }
+}
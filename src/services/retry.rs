@@ -0,0 +1,336 @@
+//! Retry policy for transient upstream failures
+//!
+//! A provider's `retry` option retries connection errors, timeouts and the
+//! configured HTTP statuses (408, 429 and 5xx by default) on the same
+//! upstream before a failover chain moves on. Delays grow exponentially from
+//! `baseDelayMs` up to `maxDelayMs`, with jitter so concurrent requests don't
+//! retry in lockstep. When the upstream says how long to wait (`Retry-After`
+//! as seconds or an HTTP date, `x-ratelimit-reset` as seconds, a Unix
+//! timestamp or a duration like `6m0s`), that delay is used instead; requests
+//! to wait longer than `maxRetryAfterMs` fail immediately so the client sees
+//! the upstream's `Retry-After`.
+//!
+//! Streams are also reconnected when they fail before their first chunk:
+//! nothing has reached the client yet, so the retry is transparent.
+
+use crate::config::RetryConfig;
+use crate::providers::{BoxStream, ProviderError};
+use anyhow::Result;
+use futures::StreamExt;
+use std::future::Future;
+use std::time::Duration;
+use tracing::warn;
+
+/// Numeric rate limit resets at or above this are Unix timestamps, not seconds
+const UNIX_TIMESTAMP_THRESHOLD: f64 = 1_000_000_000.0;
+
+/// Whether an error is transient: a retryable upstream status, a connection
+/// failure or a timeout
+pub fn is_transient(error: &anyhow::Error) -> bool {
+    if let Some(provider_error) = error.downcast_ref::<ProviderError>() {
+        return provider_error.is_retryable();
+    }
+    error.chain().any(|cause| {
+        cause.downcast_ref::<reqwest::Error>()
+            .is_some_and(|e| e.is_connect() || e.is_timeout())
+    })
+}
+
+/// Whether the policy retries an error
+pub fn should_retry(config: &RetryConfig, error: &anyhow::Error) -> bool {
+    let status = error.downcast_ref::<ProviderError>().and_then(ProviderError::status);
+    match (status, &config.retry_statuses) {
+        (Some(status), Some(statuses)) => statuses.contains(&status),
+        _ => is_transient(error),
+    }
+}
+
+/// Delay before retry number `attempt + 1`, or `None` if the error is not retried
+pub fn retry_delay(config: &RetryConfig, attempt: u32, error: &anyhow::Error) -> Option<Duration> {
+    if attempt >= config.max_retries || !should_retry(config, error) {
+        return None;
+    }
+    backoff(config, attempt, error)
+}
+
+/// Delay before reconnecting a stream that failed before its first chunk
+///
+/// Besides the errors [`retry_delay`] retries, stream errors (the connection
+/// dropped before any data) are retried.
+pub fn first_chunk_retry_delay(config: &RetryConfig, attempt: u32, error: &anyhow::Error) -> Option<Duration> {
+    let status = error.downcast_ref::<ProviderError>().and_then(ProviderError::status);
+    let retryable = status.is_none() || should_retry(config, error);
+    if attempt >= config.max_retries || !retryable {
+        return None;
+    }
+    backoff(config, attempt, error)
+}
+
+/// Upstream-requested delay, or the jittered exponential backoff of an attempt
+fn backoff(config: &RetryConfig, attempt: u32, error: &anyhow::Error) -> Option<Duration> {
+    if let Some(requested) = requested_delay(error) {
+        return (requested <= Duration::from_millis(config.max_retry_after_ms)).then_some(requested);
+    }
+    
+    let backoff_ms = config.base_delay_ms
+        .saturating_mul(2_u64.saturating_pow(attempt))
+        .min(config.max_delay_ms);
+    let delay_ms = if config.jitter {
+        let half = backoff_ms / 2;
+        half + random_u64() % (backoff_ms - half + 1)
+    } else {
+        backoff_ms
+    };
+    Some(Duration::from_millis(delay_ms))
+}
+
+/// Delay requested by the upstream's `Retry-After` or rate limit reset header
+pub fn requested_delay(error: &anyhow::Error) -> Option<Duration> {
+    error.downcast_ref::<ProviderError>()?.retry_after()
+}
+
+/// Parse a `Retry-After` value: delay seconds or an HTTP date
+pub fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    Some((date.with_timezone(&chrono::Utc) - chrono::Utc::now()).to_std().unwrap_or_default())
+}
+
+/// Parse an `x-ratelimit-reset` value: seconds, a Unix timestamp or a
+/// duration such as `1s`, `250ms` or `6m0s`
+pub fn parse_rate_limit_reset(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(number) = value.parse::<f64>() {
+        if !number.is_finite() || number < 0.0 {
+            return None;
+        }
+        if number >= UNIX_TIMESTAMP_THRESHOLD {
+            let now = chrono::Utc::now().timestamp_millis() as f64 / 1000.0;
+            return Duration::try_from_secs_f64((number - now).max(0.0)).ok();
+        }
+        return Duration::try_from_secs_f64(number).ok();
+    }
+    parse_duration(value)
+}
+
+/// Parse a Go-style duration (`1h2m3.5s`, `250ms`)
+fn parse_duration(value: &str) -> Option<Duration> {
+    let mut rest = value;
+    let mut total = 0.0;
+    while !rest.is_empty() {
+        let number_len = rest.find(|c: char| !(c.is_ascii_digit() || c == '.'))?;
+        let number: f64 = rest[..number_len].parse().ok()?;
+        rest = &rest[number_len..];
+        let unit_len = rest.find(|c: char| c.is_ascii_digit() || c == '.').unwrap_or(rest.len());
+        let seconds_per_unit = match &rest[..unit_len] {
+            "h" => 3600.0,
+            "m" => 60.0,
+            "s" => 1.0,
+            "ms" => 0.001,
+            _ => return None,
+        };
+        total += number * seconds_per_unit;
+        rest = &rest[unit_len..];
+    }
+    // Values too large for a `Duration` are ignored rather than trusted
+    Duration::try_from_secs_f64(total).ok().filter(|_| !value.is_empty())
+}
+
+/// Run a request, retrying it as the policy allows
+///
+/// Without a policy the request is sent once. `label` names the upstream in logs.
+pub async fn run<T, F, Fut>(config: Option<&RetryConfig>, label: &str, mut send: F) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut attempt = 0;
+    loop {
+        let error = match send().await {
+            Ok(result) => return Ok(result),
+            Err(e) => e,
+        };
+        let Some(delay) = config.and_then(|config| retry_delay(config, attempt, &error)) else {
+            return Err(error);
+        };
+        
+        attempt += 1;
+        let max_retries = config.map(|config| config.max_retries).unwrap_or_default();
+        warn!("🔁 {} failed, retrying after {}ms (attempt {}/{}): {}", label, delay.as_millis(), attempt, max_retries, error);
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// Reconnect a stream whose first chunk fails, as the policy allows
+///
+/// `connect` opens a new stream for the same request. Once a chunk arrives,
+/// later failures are passed through.
+pub fn retry_first_chunk<T, F, Fut>(stream: BoxStream<'static, T>, config: RetryConfig, label: String, mut connect: F) -> BoxStream<'static, T>
+where
+    T: Send + 'static,
+    F: FnMut() -> Fut + Send + 'static,
+    Fut: Future<Output = Result<BoxStream<'static, T>>> + Send,
+{
+    let first_chunk = async move {
+        let mut connection = Ok(stream);
+        let mut attempt = 0;
+        loop {
+            let error = match connection {
+                Ok(mut stream) => match stream.next().await {
+                    Some(Err(error)) => error,
+                    first => return futures::stream::iter(first).chain(stream).boxed(),
+                },
+                Err(error) => error,
+            };
+            let Some(delay) = first_chunk_retry_delay(&config, attempt, &error) else {
+                return futures::stream::iter([Err(error)]).boxed();
+            };
+            
+            attempt += 1;
+            warn!("🔁 {} stream failed before the first chunk, reconnecting after {}ms (attempt {}/{}): {}", label, delay.as_millis(), attempt, config.max_retries, error);
+            tokio::time::sleep(delay).await;
+            connection = connect().await;
+        }
+    };
+    Box::pin(futures::stream::once(first_chunk).flatten())
+}
+
+/// Random value for jitter
+fn random_u64() -> u64 {
+    uuid::Uuid::new_v4().as_u64_pair().0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::UpstreamError;
+    
+    fn upstream(status: u16, retry_after: Option<&str>, rate_limit_reset: Option<&str>) -> anyhow::Error {
+        ProviderError::from(UpstreamError {
+            provider: "OpenAI".to_string(),
+            status,
+            body: String::new(),
+            retry_after: retry_after.map(|v| v.to_string()),
+            rate_limit_reset: rate_limit_reset.map(|v| v.to_string()),
+        }).into()
+    }
+    
+    fn no_jitter() -> RetryConfig {
+        RetryConfig { jitter: false, ..Default::default() }
+    }
+    
+    #[test]
+    fn test_backoff() {
+        let config = no_jitter();
+        let error = upstream(503, None, None);
+        let delays: Vec<_> = (0..4).map(|attempt| retry_delay(&config, attempt, &error)).collect();
+        assert_eq!(delays, [
+            Some(Duration::from_millis(1000)),
+            Some(Duration::from_millis(2000)),
+            Some(Duration::from_millis(4000)),
+            None,
+        ]);
+        
+        let config = RetryConfig { max_retries: 10, ..no_jitter() };
+        assert_eq!(retry_delay(&config, 8, &error), Some(Duration::from_millis(10000)));
+        
+        let jittered = retry_delay(&RetryConfig::default(), 1, &error).unwrap();
+        assert!(jittered >= Duration::from_millis(1000) && jittered <= Duration::from_millis(2000));
+    }
+    
+    #[test]
+    fn test_classification() {
+        let config = no_jitter();
+        assert!(should_retry(&config, &upstream(429, None, None)));
+        assert!(!should_retry(&config, &upstream(400, None, None)));
+        assert!(!should_retry(&config, &anyhow::anyhow!("Failed to parse OpenAI response")));
+        
+        let config = RetryConfig { retry_statuses: Some(vec![429]), ..no_jitter() };
+        assert!(should_retry(&config, &upstream(429, None, None)));
+        assert!(!should_retry(&config, &upstream(503, None, None)));
+    }
+    
+    #[test]
+    fn test_requested_delay() {
+        let config = no_jitter();
+        assert_eq!(retry_delay(&config, 0, &upstream(429, Some("7"), None)), Some(Duration::from_secs(7)));
+        assert_eq!(retry_delay(&config, 0, &upstream(429, None, Some("6m0s"))), None);
+        assert_eq!(retry_delay(&config, 0, &upstream(429, None, Some("1.5s"))), Some(Duration::from_millis(1500)));
+        
+        let past = "Wed, 21 Oct 2015 07:28:00 GMT";
+        assert_eq!(parse_retry_after(past), Some(Duration::ZERO));
+        assert_eq!(parse_retry_after("soon"), None);
+        
+        assert_eq!(parse_rate_limit_reset("20"), Some(Duration::from_secs(20)));
+        assert_eq!(parse_rate_limit_reset("250ms"), Some(Duration::from_millis(250)));
+        assert_eq!(parse_rate_limit_reset("1h2m3s"), Some(Duration::from_secs(3723)));
+        let reset_at = chrono::Utc::now().timestamp() + 30;
+        let delay = parse_rate_limit_reset(&reset_at.to_string()).unwrap();
+        assert!(delay > Duration::from_secs(28) && delay <= Duration::from_secs(30));
+        assert_eq!(parse_rate_limit_reset("2x"), None);
+        
+        // Huge, negative and non-finite values are ignored
+        assert_eq!(parse_rate_limit_reset("1e300"), None);
+        assert_eq!(parse_rate_limit_reset("99999999999999999999h"), None);
+        assert_eq!(parse_rate_limit_reset(&"9".repeat(400)), None);
+        assert_eq!(parse_rate_limit_reset(&format!("{}s", "9".repeat(400))), None);
+        assert_eq!(parse_rate_limit_reset("-5"), None);
+        assert_eq!(parse_rate_limit_reset("NaN"), None);
+        assert_eq!(parse_rate_limit_reset("inf"), None);
+        assert_eq!(retry_delay(&config, 0, &upstream(429, None, Some("1e300"))), retry_delay(&config, 0, &upstream(429, None, None)));
+    }
+    
+    #[tokio::test]
+    async fn test_retry_first_chunk() {
+        let config = RetryConfig { base_delay_ms: 1, max_delay_ms: 1, ..Default::default() };
+        let failing = || -> BoxStream<'static, u32> {
+            Box::pin(futures::stream::iter([Err(anyhow::anyhow!("Stream error: connection reset"))]))
+        };
+        
+        let mut connects = 0;
+        let stream = retry_first_chunk(failing(), config.clone(), "openai/gpt-4o".to_string(), move || {
+            connects += 1;
+            let stream: BoxStream<'static, u32> = if connects < 2 {
+                failing()
+            } else {
+                Box::pin(futures::stream::iter([Ok(1), Err(anyhow::anyhow!("Stream error: connection reset"))]))
+            };
+            async move { Ok(stream) }
+        });
+        let items: Vec<_> = stream.collect().await;
+        // Failures after the first chunk are passed through
+        assert_eq!(items.len(), 2);
+        assert_eq!(*items[0].as_ref().unwrap(), 1);
+        assert!(items[1].is_err());
+        
+        let stream = retry_first_chunk(failing(), config, "openai/gpt-4o".to_string(), || async {
+            Err(upstream(400, None, None))
+        });
+        let items: Vec<_> = stream.collect().await;
+        assert_eq!(items.len(), 1);
+        assert!(items[0].as_ref().unwrap_err().downcast_ref::<ProviderError>().is_some());
+    }
+    
+    #[tokio::test]
+    async fn test_run() {
+        let config = RetryConfig { base_delay_ms: 1, max_delay_ms: 1, ..Default::default() };
+        let mut calls = 0;
+        let result = run(Some(&config), "openai/gpt-4o", || {
+            calls += 1;
+            let outcome = if calls < 3 { Err(upstream(503, None, None)) } else { Ok(calls) };
+            async move { outcome }
+        }).await;
+        assert_eq!(result.unwrap(), 3);
+        
+        let mut calls = 0;
+        let result: Result<()> = run(Some(&config), "openai/gpt-4o", || {
+            calls += 1;
+            async { Err(upstream(400, None, None)) }
+        }).await;
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+    }
+}
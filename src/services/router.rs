@@ -1,68 +1,227 @@
 //! Request Router
 //!
 //! Routes requests to appropriate providers based on model path
+//!
+//! Requests to providers with a `retry` policy (their own or the top-level
+//! one) are retried on the same path first. Claude models mapped to a failover chain are sent to each path of the
+//! chain in turn until one does not fail with a retryable error; models mapped to
+//! weighted targets are spread across them by the [`LoadBalancer`]. With
+//! `adaptiveRouting`, upstreams the [`HealthTracker`] marks degraded are
+//! tried last in chains and skipped by weighted mappings.
+//!
+//! Providers and models with `enabled: false` are skipped the same way; a
+//! request with no enabled path left fails with [`ModelDisabled`].
+//!
+//! Each attempt on a provider with a `rateLimit` first waits for its
+//! [`RateLimiter`] budgets. Paths that answer with a 429 cool down for the
+//! delay the upstream asked for, and are tried last (and skipped by weighted
+//! mappings) until then. The [`Routed`] result of a request tells whether a
+//! fallback served it.
+//!
+//! Providers are built from their `type`, except those an embedding
+//! application supplies in a [`ProviderRegistry`].
 
-use crate::config::{AppConfig, ModelConfig, ProviderConfig};
+use crate::config::{AppConfig, ModelConfig, ModelPricing, ModelTarget, ProviderConfig, RetryConfig, WeightedTarget};
 use crate::models::openai::{OpenAIRequest, OpenAIResponse, OpenAIStreamResponse};
-use crate::providers::{ArkProvider, BoxStream, ModelHubProvider, OpenAIProvider, Provider};
+use crate::providers::{ArkProvider, BoxStream, ModelHubProvider, OpenAIProvider, Provider, ProviderError};
+use crate::services::balancer::LoadBalancer;
+use crate::services::http_client;
+use crate::services::rate_limits::{RateLimiter, DEFAULT_COOL_DOWN};
+use crate::services::upstream_health::HealthTracker;
+use crate::services::retry::{self, is_transient};
+use crate::services::{error_reports, reasoning, structured_output, ResponseConverter, TokenCounter};
 use anyhow::{Context, Result};
+use serde::Serialize;
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::future::Future;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+use thiserror::Error;
 use tracing::{debug, info, warn};
 
+/// Enabled model paths suggested when a requested model is disabled
+const MAX_ALTERNATIVES: usize = 5;
+
+/// All paths of a requested model are disabled in the configuration
+#[derive(Debug, Error, PartialEq)]
+#[error("Model {model} is temporarily disabled{}", suggestion(.alternatives))]
+pub struct ModelDisabled {
+    /// Requested model
+    pub model: String,
+    /// Enabled provider/model paths to use instead
+    pub alternatives: Vec<String>,
+}
+
+fn suggestion(alternatives: &[String]) -> String {
+    if alternatives.is_empty() {
+        String::new()
+    } else {
+        format!("; try {}", alternatives.join(", "))
+    }
+}
+
+/// Where and how a request would be sent, for dry runs
+#[derive(Debug, Clone, Serialize)]
+pub struct DryRun {
+    /// Provider/model paths the request would be tried on, in failover order
+    pub targets: Vec<String>,
+    /// Type of the first target's provider
+    pub provider_type: String,
+    /// Model name sent to the first target's provider
+    pub upstream_model: String,
+    /// Request passed to the first target's provider
+    pub request: OpenAIRequest,
+}
+
+/// Provider instances supplied by an embedding application, by provider name
+///
+/// Each name must be configured under `providers`, whose entry still supplies
+/// the base URL, API key, models and options passed to the provider; its
+/// `type` is ignored.
+#[derive(Clone, Default)]
+pub struct ProviderRegistry {
+    providers: HashMap<String, Arc<dyn Provider>>,
+}
+
+impl std::fmt::Debug for ProviderRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.providers.keys()).finish()
+    }
+}
+
+impl ProviderRegistry {
+    /// Create an empty registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Use `provider` for the provider configured as `name`
+    pub fn register(&mut self, name: impl Into<String>, provider: Arc<dyn Provider>) {
+        self.providers.insert(name.into(), provider);
+    }
+    
+    /// Provider registered for a name
+    pub fn get(&self, name: &str) -> Option<&Arc<dyn Provider>> {
+        self.providers.get(name)
+    }
+    
+    /// Whether no providers are registered
+    pub fn is_empty(&self) -> bool {
+        self.providers.is_empty()
+    }
+}
+
+/// Response of a routed request, with the upstream path that served it
+#[derive(Debug, Clone)]
+pub struct Routed<T> {
+    pub response: T,
+    /// Provider/model path that served the request
+    pub model_path: String,
+    /// Path the request was meant for, when a fallback served it instead
+    /// (because that path failed, was cooling down or degraded)
+    pub fallback_from: Option<String>,
+}
+
 /// Request Router
 ///
 /// Holds provider instances and routes requests based on model path
 pub struct Router {
     /// Application configuration
     config: AppConfig,
-    /// Provider instances by type
+    /// Provider instances by provider name
     providers: HashMap<String, Arc<dyn Provider>>,
+    /// State of weighted model mappings
+    balancer: LoadBalancer,
+    /// Upstream health, with adaptive routing enabled
+    health: Option<HealthTracker>,
+    /// Requests and tokens per minute sent to providers with a `rateLimit`
+    rate_limits: RateLimiter,
 }
 
 impl Router {
     /// Create a new router from configuration
+    ///
+    /// Every configured provider gets its own instance with its timeouts;
+    /// providers with the same network options share an HTTP client.
     pub fn new(config: AppConfig) -> Result<Self> {
+        Self::with_registry(config, &ProviderRegistry::default())
+    }
+    
+    /// Create a router that uses the registered providers instead of building them
+    pub fn with_registry(config: AppConfig, registry: &ProviderRegistry) -> Result<Self> {
+        if let Some(name) = registry.providers.keys().find(|name| !config.providers.contains_key(*name)) {
+            anyhow::bail!("Provider '{}' is registered but not configured under providers", name);
+        }
         let mut providers: HashMap<String, Arc<dyn Provider>> = HashMap::new();
         
-        // Initialize required provider types based on config
-        for provider_config in config.providers.values() {
+        for (name, provider_config) in &config.providers {
+            if let Some(provider) = registry.get(name) {
+                debug!("Using the registered provider {} for '{}'", provider.name(), name);
+                providers.insert(name.clone(), provider.clone());
+                continue;
+            }
             let provider_type = &provider_config.provider_type;
-            
-            if !providers.contains_key(provider_type) {
-                let provider: Arc<dyn Provider> = match provider_type.as_str() {
-                    "openai" => Arc::new(OpenAIProvider::new()?),
-                    "modelhub" => Arc::new(ModelHubProvider::new()?),
-                    "ark" => Arc::new(ArkProvider::new()?),
-                    "anthropic" => {
-                        // For anthropic type, we can use OpenAI provider with custom URL
-                        // as the API format is handled by the converter
-                        Arc::new(OpenAIProvider::new()?)
-                    }
-                    _ => {
-                        warn!("Unknown provider type: {}, using OpenAI provider", provider_type);
-                        Arc::new(OpenAIProvider::new()?)
-                    }
-                };
-                
-                providers.insert(provider_type.clone(), provider);
+            let options = &provider_config.options;
+            if options.tls.as_ref().is_some_and(|tls| tls.insecure_skip_verify) {
+                warn!("TLS certificate verification is disabled for provider '{}'", name);
             }
+            let client = http_client::global().client(options)
+                .with_context(|| format!("Failed to create HTTP client for provider '{}'", name))?;
+            let timeout = options.timeout_secs.map_or(http_client::DEFAULT_TIMEOUT, Duration::from_secs);
+            let stream_timeout = options.stream_timeout_secs.map_or(http_client::DEFAULT_STREAM_TIMEOUT, Duration::from_secs);
+            let provider: Arc<dyn Provider> = match provider_type.as_str() {
+                "openai" => Arc::new(OpenAIProvider::with_client(client, timeout, stream_timeout)),
+                "modelhub" => Arc::new(ModelHubProvider::with_client(client, timeout, stream_timeout)),
+                "ark" => Arc::new(ArkProvider::with_client(client, timeout, stream_timeout)),
+                "anthropic" => {
+                    // For anthropic type, we can use OpenAI provider with custom URL
+                    // as the API format is handled by the converter
+                    Arc::new(OpenAIProvider::with_client(client, timeout, stream_timeout))
+                }
+                _ => {
+                    warn!("Unknown provider type: {}, using OpenAI provider", provider_type);
+                    Arc::new(OpenAIProvider::with_client(client, timeout, stream_timeout))
+                }
+            };
+            
+            providers.insert(name.clone(), provider);
         }
         
-        info!("Router initialized with {} provider types", providers.len());
+        info!("Router initialized with {} providers", providers.len());
+        
+        let health = config.adaptive_routing.clone().map(HealthTracker::new);
+        let rate_limits = RateLimiter::new(&config);
         
-        Ok(Self { config, providers })
+        Ok(Self { config, providers, balancer: LoadBalancer::new(), health, rate_limits })
+    }
+    
+    /// Take over the rate limit budgets of the router of the previous
+    /// configuration, so a reload doesn't refill them
+    pub fn with_rate_limits_of(self, previous: &Router) -> Self {
+        let rate_limits = self.rate_limits.with_state_of(&previous.rate_limits);
+        Self { rate_limits, ..self }
     }
     
     /// Route a model path to provider and model config
     ///
     /// Model path format: "{provider}/{model}" (e.g., "openai/gpt-4o", "modelhub-sg1/gpt-5")
-    pub fn route(&self, model_path: &str) -> Option<(Arc<dyn Provider>, &ProviderConfig, &ModelConfig)> {
+    /// With `allowModelOverride`, models not listed under the provider are passed
+    /// through to the upstream as-is.
+    pub fn route(&self, model_path: &str) -> Option<(Arc<dyn Provider>, &ProviderConfig, Cow<'_, ModelConfig>)> {
         // Split model path into provider and model
-        let (provider_config, model_config) = self.config.get_provider_model(model_path)?;
+        let (provider_config, model_config) = match self.config.get_provider_model(model_path) {
+            Some((provider_config, model_config)) => (provider_config, Cow::Borrowed(model_config)),
+            None => {
+                let (provider_config, model_config) = self.config.get_provider_model_override(model_path)?;
+                debug!("Using model override for unlisted model: {}", model_path);
+                (provider_config, Cow::Owned(model_config))
+            }
+        };
         
-        // Get provider instance by type
-        let provider = self.providers.get(&provider_config.provider_type)?;
+        // Get provider instance by name
+        let (provider_name, _) = model_path.split_once('/')?;
+        let provider = self.providers.get(provider_name)?;
         
         debug!("Routed {} to provider type: {}", model_path, provider_config.provider_type);
         
@@ -73,23 +232,26 @@ impl Router {
     ///
     /// Resolution order:
     /// 1. If model contains '/', treat as provider/model path directly
+    ///    (any upstream model of a configured provider with `allowModelOverride`)
     /// 2. Check Claude model mapping (e.g., "claude-3-sonnet" -> "modelhub-sg1/gpt-5")
     /// 3. Search for model name in all providers
     /// 4. Search for model alias in all providers
     pub fn resolve_model(&self, model: &str) -> Option<String> {
         // 1. If already in provider/model format
-        if model.contains('/') {
-            if self.config.get_provider_model(model).is_some() {
-                return Some(model.to_string());
-            }
+        if model.contains('/') && self.config.get_provider_model(model).is_some() {
+            return Some(model.to_string());
         }
         
-        // 2. Check Claude model mapping
-        if let Some(mapped_path) = self.config.resolve_claude_model(model) {
-            if self.config.get_provider_model(mapped_path).is_some() {
-                debug!("Mapped Claude model '{}' to '{}'", model, mapped_path);
-                return Some(mapped_path.to_string());
-            }
+        // 1b. Raw provider/model override bypassing the mapping table
+        if model.contains('/') && self.config.get_provider_model_override(model).is_some() {
+            debug!("Using per-request model override: {}", model);
+            return Some(model.to_string());
+        }
+        
+        // 2. Check Claude model mapping (first routable path of a failover chain)
+        if let Some(mapped_path) = self.mapped_paths(model).into_iter().next() {
+            debug!("Mapped Claude model '{}' to '{}'", model, mapped_path);
+            return Some(mapped_path);
         }
         
         // 3. Search for model in all providers by exact name
@@ -111,11 +273,293 @@ impl Router {
         None
     }
     
+    /// Provider/model paths to try for a model, in failover order
+    ///
+    /// A model resolved through a `modelMapping` failover chain yields the
+    /// chain's routable paths; any other model a single path.
+    pub fn resolve_chain(&self, model: &str) -> Vec<String> {
+        let Some(primary) = self.resolve_model(model) else {
+            return Vec::new();
+        };
+        
+        let chain = self.mapped_paths(model);
+        if chain.first() == Some(&primary) {
+            chain
+        } else {
+            vec![primary]
+        }
+    }
+    
+    /// Upstream path for a model mapped to weighted targets
+    ///
+    /// Picks the next path of the weighted split among the routable targets,
+    /// or with `stickySessions` the path of the request's session; other
+    /// models are returned unchanged.
+    pub fn balance(&self, model: &str, session_id: Option<&str>) -> String {
+        if self.route(model).is_some() {
+            return model.to_string();
+        }
+        let Some(ModelTarget::Weighted(targets)) = self.config.resolve_claude_model_target(model) else {
+            return model.to_string();
+        };
+        
+        let routable: Vec<&WeightedTarget> = targets.iter()
+            .filter(|target| self.config.get_provider_model(&target.target).is_some())
+            .filter(|target| !self.config.is_disabled(&target.target))
+            .collect();
+        // Shift the share of degraded and cooling down upstreams to the others
+        let healthy: Vec<&WeightedTarget> = routable.iter()
+            .copied()
+            .filter(|target| !self.is_avoided(&target.target))
+            .collect();
+        let routable = if healthy.is_empty() { routable } else { healthy };
+        let picked = match session_id.filter(|_| self.config.sticky_sessions) {
+            Some(session_id) => LoadBalancer::pick_for_session(session_id, &routable),
+            None => self.balancer.pick(model, &routable),
+        };
+        match picked {
+            Some(path) => {
+                debug!("⚖️ Balanced {} to {}", model, path);
+                path.to_string()
+            }
+            None => model.to_string(),
+        }
+    }
+    
+    /// Routable paths of the `modelMapping` entry for a Claude model
+    fn mapped_paths(&self, model: &str) -> Vec<String> {
+        self.config.resolve_claude_model_chain(model)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|path| self.config.get_provider_model(path).is_some())
+            .map(String::from)
+            .collect()
+    }
+    
+    /// Model to route a request to
+    ///
+    /// The target of the first routing rule matching the request metadata, or
+    /// the requested model itself.
+    pub fn route_model<'a>(&'a self, model: &'a str, metadata: Option<&HashMap<String, serde_json::Value>>) -> &'a str {
+        match self.config.match_routing_rule(model, metadata) {
+            Some(target) => {
+                debug!("Routing rule matched for '{}': {}", model, target);
+                target
+            }
+            None => model,
+        }
+    }
+    
+    /// Upstream model configuration for a Claude model name or provider/model path
+    pub fn model_config(&self, model: &str) -> Option<ModelConfig> {
+        let model_path = self.resolve_model(model)?;
+        let (_, _, model_config) = self.route(&model_path)?;
+        Some(model_config.into_owned())
+    }
+    
+    /// Price of the upstream model serving a Claude model name or provider/model path
+    pub fn pricing(&self, model: &str) -> Option<ModelPricing> {
+        if let Some(pricing) = self.config.pricing.get(model) {
+            return Some(*pricing);
+        }
+        self.config.pricing.get(&self.resolve_model(model)?).copied()
+    }
+    
+    /// Specialized response converter of the provider serving a model, if any
+    pub fn response_converter(&self, model: &str) -> Option<Arc<dyn ResponseConverter>> {
+        let model_path = self.resolve_model(model)?;
+        let (provider, _, model_config) = self.route(&model_path)?;
+        provider.response_converter(&model_config)
+    }
+    
+    /// Token counter for the upstream model serving a Claude model
+    ///
+    /// Unknown models fall back to the tokenizer picked from the requested name.
+    pub fn token_counter(&self, model: &str) -> TokenCounter {
+        match self.model_config(model) {
+            Some(model_config) => TokenCounter::for_model(&model_config.name),
+            None => TokenCounter::for_model(model),
+        }
+    }
+    
     /// Chat completion (non-streaming)
-    pub async fn chat_complete(&self, mut request: OpenAIRequest) -> Result<OpenAIResponse> {
-        let model_path = self.resolve_model(&request.model)
-            .with_context(|| format!("Model not found: {}", request.model))?;
+    pub async fn chat_complete(&self, request: OpenAIRequest) -> Result<OpenAIResponse> {
+        Ok(self.chat_complete_routed(request).await?.response)
+    }
+    
+    /// Chat completion (non-streaming), with the path that served it
+    pub async fn chat_complete_routed(&self, request: OpenAIRequest) -> Result<Routed<OpenAIResponse>> {
+        self.with_failover(request, |request, model_path| self.chat_complete_path(request, model_path)).await
+    }
+    
+    /// Chat completion (streaming)
+    ///
+    /// Failover covers establishing the stream; failures mid-stream are left
+    /// to the caller.
+    pub async fn chat_stream(&self, request: OpenAIRequest) -> Result<BoxStream<'static, OpenAIStreamResponse>> {
+        Ok(self.chat_stream_routed(request).await?.response)
+    }
+    
+    /// Chat completion (streaming), with the path that served it
+    pub async fn chat_stream_routed(&self, request: OpenAIRequest) -> Result<Routed<BoxStream<'static, OpenAIStreamResponse>>> {
+        self.with_failover(request, |request, model_path| self.chat_stream_path(request, model_path)).await
+    }
+    
+    /// Send a request along the failover chain of its model
+    ///
+    /// Retryable failures move on to the next path; the last path's result,
+    /// or the first non-retryable failure, is returned.
+    async fn with_failover<T, F, Fut>(&self, request: OpenAIRequest, send: F) -> Result<Routed<T>>
+    where
+        F: Fn(OpenAIRequest, String) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let (chain, preferred) = self.failover_chain(&request)?;
+        let Some((last, fallbacks)) = chain.split_last() else {
+            anyhow::bail!("Model not found: {}", request.model);
+        };
+        let routed = |response, model_path: &String| {
+            let fallback_from = preferred.as_ref().filter(|preferred| *preferred != model_path).cloned();
+            if let Some(preferred) = &fallback_from {
+                info!("🔀 Request for {} served by fallback {} instead of {}", request.model, model_path, preferred);
+            }
+            Routed { response, model_path: model_path.clone(), fallback_from }
+        };
         
+        for (i, model_path) in fallbacks.iter().enumerate() {
+            match self.send_path(model_path, &request, &send).await {
+                Ok(response) => return Ok(routed(response, model_path)),
+                Err(e) if is_transient(&e) => {
+                    warn!("🔀 {} failed, falling back to {}: {}", model_path, chain[i + 1], e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        
+        let response = self.send_path(last, &request, &send).await?;
+        Ok(routed(response, last))
+    }
+    
+    /// Send a request to one path, retrying it per the provider's retry policy
+    async fn send_path<T, F, Fut>(&self, model_path: &str, request: &OpenAIRequest, send: &F) -> Result<T>
+    where
+        F: Fn(OpenAIRequest, String) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let result = retry::run(self.retry_policy(model_path), model_path, || async {
+            self.throttle(model_path, request).await?;
+            self.send_tracked(model_path, send(request.clone(), model_path.to_string())).await
+        }).await;
+        if let Err(e) = &result {
+            self.cool_down_if_rate_limited(model_path, e);
+        }
+        result
+    }
+    
+    /// Put a path on cool-down when it failed with a 429, for the delay the
+    /// upstream asked for
+    fn cool_down_if_rate_limited(&self, model_path: &str, error: &anyhow::Error) {
+        let Some(ProviderError::RateLimited { retry_after, .. }) = error.downcast_ref::<ProviderError>() else {
+            return;
+        };
+        let cool_down = retry_after.unwrap_or(DEFAULT_COOL_DOWN);
+        warn!("🧊 {} is rate limited, trying other upstreams first for {}s", model_path, cool_down.as_secs());
+        self.rate_limits.cool_down(model_path, cool_down);
+    }
+    
+    /// Wait until the rate limits of a path's provider allow a request
+    async fn throttle(&self, model_path: &str, request: &OpenAIRequest) -> Result<()> {
+        let Some((provider, _)) = model_path.split_once('/') else {
+            return Ok(());
+        };
+        if !self.rate_limits.is_limited(provider) {
+            return Ok(());
+        }
+        let tokens = self.token_counter(model_path).count_request(request) + request.max_tokens.unwrap_or(0);
+        Ok(self.rate_limits.acquire(provider, tokens).await?)
+    }
+    
+    /// Enabled paths a request is tried on, in order, and the one it was
+    /// meant for before unhealthy paths were moved to the end
+    fn failover_chain(&self, request: &OpenAIRequest) -> Result<(Vec<String>, Option<String>)> {
+        let mut chain = self.resolve_chain(&self.balance(&request.model, request.session_id.as_deref()));
+        if !chain.is_empty() && chain.iter().all(|path| self.config.is_disabled(path)) {
+            warn!("⛔ Rejected request for {}: disabled in the configuration", request.model);
+            return Err(self.disabled_error(&request.model).into());
+        }
+        chain.retain(|path| !self.config.is_disabled(path));
+        let preferred = chain.first().cloned();
+        // Degraded and cooling down upstreams are tried last
+        chain.sort_by_key(|path| self.is_avoided(path));
+        Ok((chain, preferred))
+    }
+    
+    /// Route a request without sending it: the paths it would be tried on and
+    /// the request as passed to the provider of the first one
+    pub fn dry_run(&self, mut request: OpenAIRequest) -> Result<DryRun> {
+        let (targets, _) = self.failover_chain(&request)?;
+        let Some(model_path) = targets.first() else {
+            anyhow::bail!("Model not found: {}", request.model);
+        };
+        let (_, provider_config, model_config) = self.route(model_path)
+            .with_context(|| format!("Failed to route model: {}", model_path))?;
+        
+        request.model = model_path.clone();
+        reasoning::apply(&mut request, &model_config);
+        Self::apply_structured_output(&mut request, &model_config);
+        
+        Ok(DryRun {
+            provider_type: provider_config.provider_type.clone(),
+            upstream_model: model_config.name.clone(),
+            targets,
+            request,
+        })
+    }
+    
+    /// Error for a model whose paths are all disabled, suggesting enabled ones
+    fn disabled_error(&self, model: &str) -> ModelDisabled {
+        let mut alternatives: Vec<String> = self.config.list_model_paths()
+            .into_iter()
+            .filter(|path| !self.config.is_disabled(path))
+            .collect();
+        alternatives.sort();
+        alternatives.truncate(MAX_ALTERNATIVES);
+        ModelDisabled { model: model.to_string(), alternatives }
+    }
+    
+    /// Retry policy of the provider of a path
+    fn retry_policy(&self, model_path: &str) -> Option<&RetryConfig> {
+        let (provider, _) = model_path.split_once('/')?;
+        self.config.retry_policy(provider)
+    }
+    
+    /// Await a request to one path, recording its outcome for adaptive routing
+    /// and counting upstream failures towards an error burst report
+    async fn send_tracked<T>(&self, model_path: &str, request: impl Future<Output = Result<T>>) -> Result<T> {
+        if let Some(health) = &self.health {
+            health.begin_request(model_path);
+        }
+        let started = Instant::now();
+        let result = request.await;
+        let failure = result.as_ref().err().filter(|e| is_transient(e));
+        if let Some(e) = failure {
+            error_reports::global().provider_error(model_path, e);
+        }
+        if let Some(health) = &self.health {
+            health.record(model_path, failure.is_none(), started.elapsed());
+        }
+        result
+    }
+    
+    /// Whether adaptive routing currently avoids a path, or it is cooling
+    /// down after a 429
+    fn is_avoided(&self, model_path: &str) -> bool {
+        self.health.as_ref().is_some_and(|health| health.is_avoided(model_path))
+            || self.rate_limits.is_cooling_down(model_path)
+    }
+    
+    /// Chat completion (non-streaming) on one provider/model path
+    async fn chat_complete_path(&self, mut request: OpenAIRequest, model_path: String) -> Result<OpenAIResponse> {
         let (provider, provider_config, model_config) = self.route(&model_path)
             .with_context(|| format!("Failed to route model: {}", model_path))?;
         
@@ -124,23 +568,59 @@ impl Router {
         // Update request model to the resolved path for tracking
         request.model = model_path;
         
-        provider.chat_complete(request, provider_config, model_config).await
+        reasoning::apply(&mut request, &model_config);
+        let forced_tool = Self::apply_structured_output(&mut request, &model_config);
+        
+        let mut response = provider.chat_complete(request, provider_config, &model_config).await?;
+        
+        if let Some(tool_name) = forced_tool {
+            structured_output::restore_tool_call(&mut response, &tool_name);
+        }
+        
+        Ok(response)
     }
     
-    /// Chat completion (streaming)
-    pub async fn chat_stream(&self, mut request: OpenAIRequest) -> Result<BoxStream<'static, OpenAIStreamResponse>> {
-        let model_path = self.resolve_model(&request.model)
-            .with_context(|| format!("Model not found: {}", request.model))?;
-        
+    /// Chat completion (streaming) on one provider/model path
+    async fn chat_stream_path(&self, mut request: OpenAIRequest, model_path: String) -> Result<BoxStream<'static, OpenAIStreamResponse>> {
         let (provider, provider_config, model_config) = self.route(&model_path)
             .with_context(|| format!("Failed to route model: {}", model_path))?;
+        let retry_config = self.retry_policy(&model_path).cloned();
         
         debug!("Processing streaming chat completion for model: {}", model_path);
         
         // Update request model to the resolved path for tracking
         request.model = model_path;
         
-        provider.chat_stream(request, provider_config, model_config).await
+        reasoning::apply(&mut request, &model_config);
+        let forced_tool = Self::apply_structured_output(&mut request, &model_config);
+        
+        let stream = match retry_config {
+            Some(retry_config) => {
+                let stream = provider.chat_stream(request.clone(), provider_config, &model_config).await?;
+                let provider_config = provider_config.clone();
+                let model_config = model_config.into_owned();
+                retry::retry_first_chunk(stream, retry_config, request.model.clone(), move || {
+                    let (provider, provider_config, model_config, request) = (provider.clone(), provider_config.clone(), model_config.clone(), request.clone());
+                    async move { Ok(provider.chat_stream(request, &provider_config, &model_config).await?) }
+                })
+            }
+            None => provider.chat_stream(request, provider_config, &model_config).await?,
+        };
+        
+        Ok(match forced_tool {
+            Some(tool_name) => structured_output::restore_tool_call_stream(stream, tool_name),
+            None => stream,
+        })
+    }
+    
+    /// Apply the model's structured output mode to the request
+    ///
+    /// Returns the forced tool name when the request was rewritten to json_schema
+    fn apply_structured_output(request: &mut OpenAIRequest, model_config: &ModelConfig) -> Option<String> {
+        match model_config.options.structured_output.as_deref() {
+            Some(structured_output::MODE_JSON_SCHEMA) => structured_output::apply_json_schema(request),
+            _ => None,
+        }
     }
     
     /// List all available model paths
@@ -158,7 +638,7 @@ impl Router {
 mod tests {
     use super::*;
     use std::collections::HashMap;
-    use crate::config::{ModelConfig, ProviderConfig, ProviderOptions};
+    use crate::config::{ModelConfig, ModelTarget, ProviderConfig, ProviderOptions, WeightedTarget};
     
     fn create_test_config() -> AppConfig {
         let mut providers = HashMap::new();
@@ -167,16 +647,20 @@ mod tests {
         let mut openai_models = HashMap::new();
         openai_models.insert("gpt-4o".to_string(), ModelConfig {
             name: "gpt-4o".to_string(),
+            enabled: true,
             alias: Some("gpt4".to_string()),
             max_tokens: Some(8192),
+            context_window: None,
             temperature: None,
             options: Default::default(),
         });
         
         providers.insert("openai".to_string(), ProviderConfig {
             provider_type: "openai".to_string(),
+            enabled: true,
             base_url: "https://api.openai.com/v1".to_string(),
             api_key: "".to_string(),
+            api_key_file: None,
             options: Default::default(),
             models: openai_models,
         });
@@ -185,28 +669,31 @@ mod tests {
         let mut modelhub_models = HashMap::new();
         modelhub_models.insert("gpt-5".to_string(), ModelConfig {
             name: "gpt-5".to_string(),
+            enabled: true,
             alias: None,
             max_tokens: Some(32768),
+            context_window: None,
             temperature: None,
             options: Default::default(),
         });
         
         providers.insert("modelhub-sg1".to_string(), ProviderConfig {
             provider_type: "modelhub".to_string(),
+            enabled: true,
             base_url: "https://modelhub-sg1.example.com".to_string(),
             api_key: "".to_string(),
+            api_key_file: None,
             options: ProviderOptions {
                 api_key_param: Some("ak".to_string()),
                 mode: Some("responses".to_string()),
-                headers: Default::default(),
+                ..Default::default()
             },
             models: modelhub_models,
         });
         
-        AppConfig { 
-            server: crate::config::ServerConfig::default(),
+        AppConfig {
             providers,
-            model_mapping: HashMap::new(),
+            ..Default::default()
         }
     }
     
@@ -274,6 +761,108 @@ mod tests {
         assert_eq!(model_config.name, "gpt-5");
     }
     
+    #[test]
+    fn test_model_override() {
+        let mut config = create_test_config();
+        
+        // Unlisted models are rejected unless overrides are enabled
+        let router = Router::new(config.clone()).unwrap();
+        assert!(router.resolve_model("openai/gpt-4.1").is_none());
+        assert!(router.route("openai/gpt-4.1").is_none());
+        
+        config.allow_model_override = true;
+        let router = Router::new(config).unwrap();
+        assert_eq!(router.resolve_model("openai/gpt-4.1"), Some("openai/gpt-4.1".to_string()));
+        
+        let (_, provider_config, model_config) = router.route("openai/gpt-4.1").unwrap();
+        assert_eq!(provider_config.provider_type, "openai");
+        assert_eq!(model_config.name, "gpt-4.1");
+        
+        // Unknown providers still fail
+        assert!(router.resolve_model("unknown/gpt-4.1").is_none());
+    }
+    
+    #[test]
+    fn test_resolve_chain() {
+        let mut config = create_test_config();
+        config.model_mapping.insert("claude-sonnet-4".to_string(), ModelTarget::Chain(vec![
+            "unknown/glm-4.6".to_string(),
+            "modelhub-sg1/gpt-5".to_string(),
+            "openai/gpt-4o".to_string(),
+        ]));
+        let router = Router::new(config).unwrap();
+        
+        // Unroutable entries are skipped
+        assert_eq!(router.resolve_model("claude-sonnet-4"), Some("modelhub-sg1/gpt-5".to_string()));
+        assert_eq!(router.resolve_chain("claude-sonnet-4"), ["modelhub-sg1/gpt-5", "openai/gpt-4o"]);
+        
+        // Paths and unmapped models have no fallbacks
+        assert_eq!(router.resolve_chain("openai/gpt-4o"), ["openai/gpt-4o"]);
+        assert_eq!(router.resolve_chain("gpt4"), ["openai/gpt-4o"]);
+        assert!(router.resolve_chain("unknown-model").is_empty());
+    }
+    
+    #[test]
+    fn test_balance() {
+        let mut config = create_test_config();
+        config.model_mapping.insert("claude-haiku-4".to_string(), ModelTarget::Weighted(vec![
+            WeightedTarget { target: "openai/gpt-4o".to_string(), weight: 3 },
+            WeightedTarget { target: "modelhub-sg1/gpt-5".to_string(), weight: 1 },
+            WeightedTarget { target: "openai/unknown".to_string(), weight: 100 },
+        ]));
+        let router = Router::new(config).unwrap();
+        
+        // Unroutable targets get no share
+        let picks: Vec<String> = (0..4).map(|_| router.balance("claude-haiku-4", None)).collect();
+        assert_eq!(picks.iter().filter(|p| *p == "openai/gpt-4o").count(), 3);
+        assert_eq!(picks.iter().filter(|p| *p == "modelhub-sg1/gpt-5").count(), 1);
+        
+        // Other models are unchanged
+        assert_eq!(router.balance("openai/gpt-4o", None), "openai/gpt-4o");
+        assert_eq!(router.balance("gpt4", None), "gpt4");
+    }
+    
+    #[test]
+    fn test_sticky_sessions() {
+        let mut config = create_test_config();
+        config.model_mapping.insert("claude-haiku-4".to_string(), ModelTarget::Weighted(vec![
+            WeightedTarget { target: "openai/gpt-4o".to_string(), weight: 1 },
+            WeightedTarget { target: "modelhub-sg1/gpt-5".to_string(), weight: 1 },
+        ]));
+        config.sticky_sessions = true;
+        let router = Router::new(config).unwrap();
+        
+        let first = router.balance("claude-haiku-4", Some("session-1"));
+        assert!((0..5).all(|_| router.balance("claude-haiku-4", Some("session-1")) == first));
+        // Requests without a session are still spread
+        let picks: Vec<String> = (0..2).map(|_| router.balance("claude-haiku-4", None)).collect();
+        assert_ne!(picks[0], picks[1]);
+    }
+    
+    #[tokio::test]
+    async fn test_disabled_models() {
+        let mut config = create_test_config();
+        config.model_mapping.insert("claude-haiku-4".to_string(), ModelTarget::Weighted(vec![
+            WeightedTarget { target: "openai/gpt-4o".to_string(), weight: 1 },
+            WeightedTarget { target: "modelhub-sg1/gpt-5".to_string(), weight: 1 },
+        ]));
+        config.providers.get_mut("openai").unwrap().models.get_mut("gpt-4o").unwrap().enabled = false;
+        let router = Router::new(config.clone()).unwrap();
+        assert!((0..4).all(|_| router.balance("claude-haiku-4", None) == "modelhub-sg1/gpt-5"));
+        
+        // With the whole provider disabled too, nothing is left to route to
+        config.providers.get_mut("modelhub-sg1").unwrap().enabled = false;
+        config.providers.get_mut("openai").unwrap().models.get_mut("gpt-4o").unwrap().enabled = true;
+        let router = Router::new(config).unwrap();
+        let request = OpenAIRequest::builder().model("modelhub-sg1/gpt-5").user("Hi").build();
+        let error = router.chat_complete(request).await.unwrap_err();
+        assert_eq!(error.downcast_ref::<ModelDisabled>(), Some(&ModelDisabled {
+            model: "modelhub-sg1/gpt-5".to_string(),
+            alternatives: vec!["openai/gpt-4o".to_string()],
+        }));
+        assert_eq!(error.to_string(), "Model modelhub-sg1/gpt-5 is temporarily disabled; try openai/gpt-4o");
+    }
+    
     #[test]
     fn test_list_models() {
         let config = create_test_config();
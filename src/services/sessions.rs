@@ -0,0 +1,447 @@
+//! Claude Code session tracking
+//!
+//! Claude Code identifies its session in `metadata.user_id`
+//! (`user_{hash}_account__session_{uuid}`), which the converter copies to
+//! `OpenAIRequest.session_id`. The [`SessionManager`] keeps per-session state
+//! across requests:
+//!
+//! - request count and running token usage, for usage accounting
+//! - a hash of the system prompt, to notice when the cacheable prefix changes
+//! - thought signatures of the session's tool calls, which live as long as the
+//!   session instead of in the size-capped global cache
+//!
+//! Handlers and providers reach the shared instance through [`global`].
+//! Sessions idle for an hour are evicted when room is needed.
+//!
+//! With a shared [`StateBackend`], counters, the system prompt hash and
+//! thought signatures are kept there as well, so a session continues on
+//! whichever replica serves its next request.
+
+use crate::models::claude::{ClaudeContentBlock, ClaudeResponse, ClaudeStreamEvent};
+use crate::models::openai::OpenAIRequest;
+use crate::services::state_backend::{self, StateBackend};
+use once_cell::sync::Lazy;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+/// Sessions idle for longer than this may be evicted
+const SESSION_IDLE_TTL: Duration = Duration::from_secs(3600);
+
+/// Maximum number of tracked sessions
+const MAX_SESSIONS: usize = 1000;
+
+static SESSIONS: Lazy<SessionManager> = Lazy::new(SessionManager::default);
+
+/// The process-wide session manager
+pub fn global() -> &'static SessionManager {
+    &SESSIONS
+}
+
+/// Extract the Claude Code session ID from a `metadata.user_id` value
+pub fn session_id_from_user_id(user_id: &str) -> Option<&str> {
+    user_id.split("_session_").nth(1).filter(|id| !id.is_empty())
+}
+
+/// Extract the Claude Code session ID from request metadata
+pub fn session_id_from_metadata(metadata: Option<&HashMap<String, serde_json::Value>>) -> Option<&str> {
+    metadata?.get("user_id")?.as_str().and_then(session_id_from_user_id)
+}
+
+/// State kept for one session
+#[derive(Debug, Clone)]
+pub struct SessionState {
+    /// Number of requests seen
+    pub requests: u64,
+    /// Input tokens used by all requests
+    pub input_tokens: u64,
+    /// Output tokens used by all requests
+    pub output_tokens: u64,
+    /// Hash of the system prompt of the latest request
+    pub system_prompt_hash: Option<u64>,
+    /// Thought signatures by tool call ID
+    pub thought_signatures: HashMap<String, String>,
+    /// Time of the latest request
+    pub last_seen: Instant,
+}
+
+impl SessionState {
+    fn new() -> Self {
+        Self {
+            requests: 0,
+            input_tokens: 0,
+            output_tokens: 0,
+            system_prompt_hash: None,
+            thought_signatures: HashMap::new(),
+            last_seen: Instant::now(),
+        }
+    }
+}
+
+/// Per-session state store
+#[derive(Debug)]
+pub struct SessionManager {
+    sessions: RwLock<HashMap<String, SessionState>>,
+    idle_ttl: Duration,
+    max_sessions: usize,
+    backend: RwLock<Option<Arc<dyn StateBackend>>>,
+}
+
+impl Default for SessionManager {
+    fn default() -> Self {
+        Self::new(SESSION_IDLE_TTL, MAX_SESSIONS)
+    }
+}
+
+impl SessionManager {
+    /// Create a manager with an idle TTL and a session limit
+    pub fn new(idle_ttl: Duration, max_sessions: usize) -> Self {
+        Self {
+            sessions: RwLock::new(HashMap::new()),
+            idle_ttl,
+            max_sessions: max_sessions.max(1),
+            backend: RwLock::new(None),
+        }
+    }
+    
+    /// Share session state through a backend, or stop sharing with `None`
+    pub fn set_backend(&self, backend: Option<Arc<dyn StateBackend>>) {
+        if let Ok(mut current) = self.backend.write() {
+            *current = backend;
+        }
+    }
+    
+    /// Record a request of a session
+    ///
+    /// Returns true if the system prompt differs from the session's previous
+    /// request (upstream prompt caches will miss).
+    pub fn begin_request(&self, session_id: &str, request: &OpenAIRequest) -> bool {
+        let hash = system_prompt_hash(request);
+        // The shared state counts requests served by all replicas
+        let shared = self.shared(|backend| {
+            let requests = backend.incr(&shared_key(session_id, "requests"), 1, self.idle_ttl)?;
+            let hash_value = hash.map(|hash| hash.to_string()).unwrap_or_default();
+            let previous = backend.swap(&shared_key(session_id, "system_prompt"), &hash_value, self.idle_ttl)?;
+            Ok((requests, previous.map(|previous| previous.parse::<u64>().ok())))
+        });
+        
+        let Ok(mut sessions) = self.sessions.write() else {
+            return false;
+        };
+        
+        if !sessions.contains_key(session_id) && sessions.len() >= self.max_sessions {
+            self.evict(&mut sessions);
+        }
+        
+        let session = sessions.entry(session_id.to_string()).or_insert_with(SessionState::new);
+        session.requests += 1;
+        session.last_seen = Instant::now();
+        let mut previous_hash = session.system_prompt_hash;
+        if let Some((requests, shared_hash)) = shared {
+            session.requests = requests;
+            previous_hash = shared_hash.flatten();
+        }
+        let changed = previous_hash.is_some_and(|previous| Some(previous) != hash);
+        session.system_prompt_hash = hash;
+        
+        debug!("🧵 Session {}: request #{}, system prompt changed: {}", session_id, session.requests, changed);
+        changed
+    }
+    
+    /// Add token usage to a session
+    pub fn record_usage(&self, session_id: &str, input_tokens: u32, output_tokens: u32) {
+        if input_tokens > 0 || output_tokens > 0 {
+            let (input_key, output_key, ttl) = (shared_key(session_id, "input_tokens"), shared_key(session_id, "output_tokens"), self.idle_ttl);
+            self.shared_write(move |backend| {
+                backend.incr(&input_key, u64::from(input_tokens), ttl)?;
+                backend.incr(&output_key, u64::from(output_tokens), ttl)?;
+                Ok(())
+            });
+        }
+        self.update(session_id, |session| {
+            session.input_tokens += u64::from(input_tokens);
+            session.output_tokens += u64::from(output_tokens);
+        });
+    }
+    
+    /// Record the usage and tool call thought signatures of a complete response
+    pub fn record_response(&self, session_id: &str, response: &ClaudeResponse) {
+        self.record_usage(session_id, response.usage.total_input_tokens(), response.usage.output_tokens);
+        for block in &response.content {
+            self.record_block(session_id, block);
+        }
+    }
+    
+    /// Record the usage and tool call thought signatures of a stream event
+    pub fn record_stream_event(&self, session_id: &str, event: &ClaudeStreamEvent) {
+        match event {
+            ClaudeStreamEvent::MessageStart { message } => {
+                self.record_usage(session_id, message.usage.total_input_tokens(), message.usage.output_tokens);
+            }
+            ClaudeStreamEvent::MessageDelta { usage, .. } => {
+                self.record_usage(session_id, usage.total_input_tokens(), usage.output_tokens);
+            }
+            ClaudeStreamEvent::ContentBlockStart { content_block, .. } => {
+                self.record_block(session_id, content_block);
+            }
+            _ => {}
+        }
+    }
+    
+    fn record_block(&self, session_id: &str, block: &ClaudeContentBlock) {
+        if let ClaudeContentBlock::ToolUse { id, thought_signature: Some(signature), .. } = block {
+            let (key, value, ttl) = (shared_key(session_id, &format!("thought:{}", id)), signature.clone(), self.idle_ttl);
+            self.shared_write(move |backend| backend.set(&key, &value, ttl));
+            self.update(session_id, |session| {
+                session.thought_signatures.insert(id.clone(), signature.clone());
+            });
+        }
+    }
+    
+    /// Thought signature of a tool call made in a session
+    pub fn thought_signature(&self, session_id: &str, tool_call_id: &str) -> Option<String> {
+        let local = self.sessions.read().ok()
+            .and_then(|sessions| sessions.get(session_id)?.thought_signatures.get(tool_call_id).cloned());
+        local.or_else(|| {
+            self.shared(|backend| backend.get(&shared_key(session_id, &format!("thought:{}", tool_call_id))))
+                .flatten()
+        })
+    }
+    
+    /// Snapshot of a session's state
+    ///
+    /// With a shared backend, the counters include requests served by other replicas.
+    pub fn get(&self, session_id: &str) -> Option<SessionState> {
+        let mut session = self.sessions.read().ok()?.get(session_id).cloned()?;
+        let counters = self.shared(|backend| {
+            let counter = |name: &str| -> anyhow::Result<u64> {
+                Ok(backend.get(&shared_key(session_id, name))?.and_then(|value| value.parse().ok()).unwrap_or(0))
+            };
+            Ok((counter("requests")?, counter("input_tokens")?, counter("output_tokens")?))
+        });
+        if let Some((requests, input_tokens, output_tokens)) = counters {
+            session.requests = requests;
+            session.input_tokens = input_tokens;
+            session.output_tokens = output_tokens;
+        }
+        Some(session)
+    }
+    
+    /// Number of tracked sessions
+    pub fn len(&self) -> usize {
+        self.sessions.read().map(|sessions| sessions.len()).unwrap_or(0)
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    
+    /// Run an operation on the shared backend, if any; failures are logged
+    fn shared<T>(&self, f: impl FnOnce(&dyn StateBackend) -> anyhow::Result<T>) -> Option<T> {
+        let backend = self.backend.read().ok()?.clone()?;
+        f(backend.as_ref())
+            .inspect_err(|e| warn!("🧵 Shared session state unavailable in {}: {:#}", backend.name(), e))
+            .ok()
+    }
+    
+    /// Run a write on the shared backend, if any, without waiting for it
+    fn shared_write(&self, f: impl FnOnce(&dyn StateBackend) -> anyhow::Result<()> + Send + 'static) {
+        let Some(backend) = self.backend.read().ok().and_then(|backend| backend.clone()) else {
+            return;
+        };
+        state_backend::spawn_write(move || {
+            if let Err(e) = f(backend.as_ref()) {
+                warn!("🧵 Shared session state unavailable in {}: {:#}", backend.name(), e);
+            }
+        });
+    }
+    
+    fn update(&self, session_id: &str, f: impl FnOnce(&mut SessionState)) {
+        if let Ok(mut sessions) = self.sessions.write() {
+            if let Some(session) = sessions.get_mut(session_id) {
+                f(session);
+            }
+        }
+    }
+    
+    /// Drop idle sessions, or the least recently seen one if none is idle
+    fn evict(&self, sessions: &mut HashMap<String, SessionState>) {
+        let before = sessions.len();
+        sessions.retain(|_, session| session.last_seen.elapsed() < self.idle_ttl);
+        
+        if sessions.len() >= self.max_sessions {
+            let oldest = sessions.iter()
+                .min_by_key(|(_, session)| session.last_seen)
+                .map(|(id, _)| id.clone());
+            if let Some(id) = oldest {
+                sessions.remove(&id);
+            }
+        }
+        debug!("🧵 Evicted {} sessions", before - sessions.len());
+    }
+}
+
+/// Key of a session's value in the shared backend
+fn shared_key(session_id: &str, name: &str) -> String {
+    format!("session:{}:{}", session_id, name)
+}
+
+/// Hash of the system messages of a request
+fn system_prompt_hash(request: &OpenAIRequest) -> Option<u64> {
+    let mut system_messages = request.messages.iter()
+        .filter(|message| message.role == "system")
+        .peekable();
+    system_messages.peek()?;
+    
+    let mut hasher = DefaultHasher::new();
+    for message in system_messages {
+        serde_json::to_string(&message.content).unwrap_or_default().hash(&mut hasher);
+    }
+    Some(hasher.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::claude::ClaudeUsage;
+    
+    fn request(system: &str) -> OpenAIRequest {
+        OpenAIRequest::builder().system(system).user("Hi").build()
+    }
+    
+    #[test]
+    fn test_session_id_from_user_id() {
+        let user_id = "user_abc123_account__session_0f6d5c3e-1111-2222-3333-444455556666";
+        assert_eq!(session_id_from_user_id(user_id), Some("0f6d5c3e-1111-2222-3333-444455556666"));
+        assert_eq!(session_id_from_user_id("user_abc123"), None);
+        assert_eq!(session_id_from_user_id("user_abc123_account__session_"), None);
+    }
+    
+    #[test]
+    fn test_session_state() {
+        let manager = SessionManager::default();
+        
+        assert!(!manager.begin_request("s1", &request("You are helpful.")));
+        assert!(!manager.begin_request("s1", &request("You are helpful.")));
+        assert!(manager.begin_request("s1", &request("You are terse.")));
+        
+        let response: ClaudeResponse = serde_json::from_value(serde_json::json!({
+            "id": "msg_1",
+            "type": "message",
+            "role": "assistant",
+            "content": [{"type": "tool_use", "id": "toolu_1", "name": "ls", "input": {}, "thought_signature": "sig"}],
+            "model": "gemini-2.5-pro",
+            "stop_reason": "tool_use",
+            "stop_sequence": null,
+            "usage": {"input_tokens": 100, "output_tokens": 20}
+        })).unwrap();
+        manager.record_response("s1", &response);
+        manager.record_stream_event("s1", &ClaudeStreamEvent::MessageDelta {
+            delta: crate::models::claude::ClaudeMessageDelta { stop_reason: None, stop_sequence: None },
+            usage: ClaudeUsage { output_tokens: 5, ..Default::default() },
+        });
+        
+        let session = manager.get("s1").unwrap();
+        assert_eq!(session.requests, 3);
+        assert_eq!(session.input_tokens, 100);
+        assert_eq!(session.output_tokens, 25);
+        assert_eq!(manager.thought_signature("s1", "toolu_1").as_deref(), Some("sig"));
+        assert_eq!(manager.thought_signature("s2", "toolu_1"), None);
+    }
+    
+    #[test]
+    fn test_eviction() {
+        let manager = SessionManager::new(SESSION_IDLE_TTL, 2);
+        manager.begin_request("s1", &request("a"));
+        manager.begin_request("s2", &request("a"));
+        manager.begin_request("s1", &request("a"));
+        manager.begin_request("s3", &request("a"));
+        
+        // The least recently seen session makes room
+        assert_eq!(manager.len(), 2);
+        assert!(manager.get("s2").is_none());
+        assert!(manager.get("s1").is_some());
+    }
+    
+    #[test]
+    fn test_shared_backend() {
+        let backend: Arc<dyn StateBackend> = Arc::new(crate::services::state_backend::MemoryBackend::default());
+        let replica_a = SessionManager::default();
+        let replica_b = SessionManager::default();
+        replica_a.set_backend(Some(backend.clone()));
+        replica_b.set_backend(Some(backend));
+        
+        assert!(!replica_a.begin_request("s1", &request("You are helpful.")));
+        replica_a.record_block("s1", &ClaudeContentBlock::ToolUse {
+            id: "toolu_1".to_string(),
+            name: "ls".to_string(),
+            input: serde_json::json!({}),
+            thought_signature: Some("sig".to_string()),
+        });
+        replica_a.record_usage("s1", 100, 20);
+        
+        // The next request of the session lands on the other replica
+        assert!(replica_b.begin_request("s1", &request("You are terse.")));
+        assert_eq!(replica_b.thought_signature("s1", "toolu_1").as_deref(), Some("sig"));
+        let session = replica_b.get("s1").unwrap();
+        assert_eq!(session.requests, 2);
+        assert_eq!(session.input_tokens, 100);
+        assert_eq!(session.output_tokens, 20);
+    }
+    
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_shared_writes_dont_block() {
+        use crate::services::state_backend::MemoryBackend;
+        
+        /// Backend taking its time with every write
+        #[derive(Debug, Default)]
+        struct SlowBackend(MemoryBackend);
+        
+        impl StateBackend for SlowBackend {
+            fn name(&self) -> &'static str {
+                "slow"
+            }
+            
+            fn get(&self, key: &str) -> anyhow::Result<Option<String>> {
+                self.0.get(key)
+            }
+            
+            fn set(&self, key: &str, value: &str, ttl: Duration) -> anyhow::Result<()> {
+                std::thread::sleep(Duration::from_millis(200));
+                self.0.set(key, value, ttl)
+            }
+            
+            fn swap(&self, key: &str, value: &str, ttl: Duration) -> anyhow::Result<Option<String>> {
+                self.0.swap(key, value, ttl)
+            }
+            
+            fn incr(&self, key: &str, delta: u64, ttl: Duration) -> anyhow::Result<u64> {
+                std::thread::sleep(Duration::from_millis(200));
+                self.0.incr(key, delta, ttl)
+            }
+        }
+        
+        let backend = Arc::new(SlowBackend::default());
+        let manager = SessionManager::default();
+        manager.set_backend(Some(backend.clone()));
+        
+        // Usage and signatures recorded from stream events don't wait for the backend
+        let started = Instant::now();
+        manager.record_usage("s1", 100, 20);
+        manager.record_block("s1", &ClaudeContentBlock::ToolUse {
+            id: "toolu_1".to_string(),
+            name: "ls".to_string(),
+            input: serde_json::json!({}),
+            thought_signature: Some("sig".to_string()),
+        });
+        assert!(started.elapsed() < Duration::from_millis(100));
+        
+        tokio::time::sleep(Duration::from_millis(1000)).await;
+        assert_eq!(backend.get(&shared_key("s1", "input_tokens")).unwrap().as_deref(), Some("100"));
+        assert_eq!(backend.get(&shared_key("s1", "output_tokens")).unwrap().as_deref(), Some("20"));
+        assert_eq!(backend.get(&shared_key("s1", "thought:toolu_1")).unwrap().as_deref(), Some("sig"));
+    }
+}
@@ -0,0 +1,357 @@
+//! Shadow traffic to a candidate upstream
+//!
+//! With `shadow`, a share of `/v1/messages` requests is also sent to a
+//! candidate provider/model path in the background. The client only ever
+//! gets the response of the path the request was routed to; the candidate's
+//! response is discarded after it was written, next to the routed one, as a
+//! JSON line of a rotating file: the text and tool calls of both responses,
+//! their stop reasons, output tokens and latencies. Diffing them over
+//! production traffic shows whether a new upstream can take over a mapping
+//! before the mapping is switched.
+//!
+//! Requests are sampled evenly (every tenth at 10%). The candidate gets the
+//! request as sent upstream, after redaction, adapted to the candidate
+//! model's options; it doesn't stream and doesn't fail over, and its errors
+//! are logged in place of its response. Both responses are logged before
+//! redacted values are restored.
+
+use crate::config::ShadowConfig;
+use crate::models::claude::{ClaudeContentBlock, ClaudeContentDelta, ClaudeResponse, ClaudeStreamEvent};
+use crate::models::openai::OpenAIRequest;
+use crate::services::conversion::ResponseConverter;
+use crate::services::request_log::{truncate, RequestLog};
+use crate::services::{context_window, output_tokens, request_params, Router};
+use anyhow::Result;
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::oneshot;
+use tracing::{debug, info};
+
+/// Mirrors sampled requests to the candidate path and logs the comparisons
+#[derive(Debug)]
+pub struct ShadowTraffic {
+    config: ShadowConfig,
+    log: RequestLog,
+    /// Requests eligible for mirroring so far, for even sampling
+    eligible: AtomicU64,
+}
+
+impl ShadowTraffic {
+    /// Open the comparison log, creating it and its directory if needed
+    pub fn open(config: &ShadowConfig) -> Result<Self> {
+        let log = RequestLog::open(&config.log_config())?;
+        Ok(Self { config: config.clone(), log, eligible: AtomicU64::new(0) })
+    }
+    
+    /// Configuration the shadow traffic was set up with
+    pub fn config(&self) -> &ShadowConfig {
+        &self.config
+    }
+    
+    /// Whether a request for a Claude model routed to `route` is mirrored
+    fn sample(&self, model: &str, route: &str) -> bool {
+        if route == self.config.target || !(self.config.models.is_empty() || self.config.models.iter().any(|m| m == model)) {
+            return false;
+        }
+        let n = self.eligible.fetch_add(1, Ordering::Relaxed) as f64;
+        let share = self.config.percent / 100.0;
+        ((n + 1.0) * share).floor() > (n * share).floor()
+    }
+    
+    /// Send a copy of a request for a Claude model to the candidate, if it is sampled
+    ///
+    /// Returns the run recording the routed response; the comparison is
+    /// logged when the run is dropped and the candidate has answered.
+    pub fn mirror(
+        self: &Arc<Self>,
+        router: Arc<Router>,
+        converter: Arc<dyn ResponseConverter>,
+        request: &OpenAIRequest,
+        model: &str,
+    ) -> Option<ShadowRun> {
+        if !self.sample(model, &request.model) {
+            return None;
+        }
+        let target = self.config.target.clone();
+        let mut candidate = request.clone();
+        candidate.model = target.clone();
+        candidate.stream = None;
+        if let Some(model_config) = router.model_config(&target) {
+            request_params::apply(&mut candidate, &model_config);
+            let adapted = output_tokens::apply(&mut candidate, &model_config)
+                .and_then(|_| context_window::enforce(&mut candidate, &model_config));
+            if let Err(error_msg) = adapted {
+                debug!("👥 Not mirroring request to {}: {}", target, error_msg);
+                return None;
+            }
+        }
+        
+        let comparison = Comparison {
+            timestamp: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            request_id: request.request_id.clone(),
+            model: model.to_string(),
+            identical: false,
+            primary: Outcome { route: request.model.clone(), ..Default::default() },
+            candidate: Outcome { route: target, ..Default::default() },
+        };
+        let (sender, receiver) = oneshot::channel::<Output>();
+        let started = Instant::now();
+        let max_text_bytes = self.config.max_text_bytes;
+        let shadow = self.clone();
+        tokio::spawn(async move {
+            let mut comparison = comparison;
+            let result = match router.chat_complete(candidate).await {
+                Ok(response) => converter.convert_response(response, &comparison.model),
+                Err(e) => Err(e),
+            };
+            let mut candidate = match result {
+                Ok(response) => Output::of_response(&response, max_text_bytes),
+                Err(e) => Output { error: Some(e.to_string()), ..Default::default() },
+            };
+            candidate.latency_ms = started.elapsed().as_millis() as u64;
+            
+            // The routed response may still be streaming
+            let Ok(primary) = receiver.await else {
+                return;
+            };
+            comparison.identical = primary.error.is_none() && candidate.error.is_none()
+                && primary.text == candidate.text
+                && primary.tool_calls == candidate.tool_calls;
+            info!(
+                "👥 Shadow request to {}: {} ms vs {} ms on {}{}",
+                comparison.candidate.route,
+                candidate.latency_ms,
+                primary.latency_ms,
+                comparison.primary.route,
+                if comparison.identical { ", identical output" } else { "" }
+            );
+            comparison.primary.output = primary;
+            comparison.candidate.output = candidate;
+            shadow.log.write(&comparison);
+        });
+        
+        Some(ShadowRun { sender: Some(sender), started, max_text_bytes, output: Output::default(), tool_inputs: Vec::new() })
+    }
+}
+
+/// Line of the comparison log
+#[derive(Debug, Serialize)]
+struct Comparison {
+    /// Time the request was mirrored (RFC 3339)
+    timestamp: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+    /// Claude model requested
+    model: String,
+    /// Both responses have the same text and tool calls
+    identical: bool,
+    /// Response of the path the request was routed to
+    primary: Outcome,
+    /// Response of the candidate
+    candidate: Outcome,
+}
+
+/// Response of one path
+#[derive(Debug, Default, Serialize)]
+struct Outcome {
+    route: String,
+    #[serde(flatten)]
+    output: Output,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct Output {
+    latency_ms: u64,
+    /// The request failed or, for the routed response, the stream was cut short
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stop_reason: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    output_tokens: Option<u32>,
+    /// Text of the response, cut to `maxTextBytes`
+    text: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tool_calls: Vec<ToolCall>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct ToolCall {
+    name: String,
+    input: serde_json::Value,
+}
+
+impl Output {
+    fn of_response(response: &ClaudeResponse, max_text_bytes: usize) -> Self {
+        let mut output = Self {
+            stop_reason: response.stop_reason.clone(),
+            output_tokens: Some(response.usage.output_tokens),
+            ..Default::default()
+        };
+        for block in &response.content {
+            match block {
+                ClaudeContentBlock::Text { text } => output.push_text(text, max_text_bytes),
+                ClaudeContentBlock::ToolUse { name, input, .. } => {
+                    output.tool_calls.push(ToolCall { name: name.clone(), input: input.clone() });
+                }
+                _ => {}
+            }
+        }
+        output
+    }
+    
+    fn push_text(&mut self, text: &str, max_text_bytes: usize) {
+        if self.text.len() < max_text_bytes {
+            self.text.push_str(text);
+            self.text = truncate(std::mem::take(&mut self.text), max_text_bytes);
+        }
+    }
+}
+
+/// The routed response of a mirrored request, passed to the comparison when dropped
+///
+/// A non-streaming response is complete when the handler returns; a streaming
+/// response's run moves into the stream and is dropped with it. A run dropped
+/// before the response stopped logs the routed response as failed.
+#[derive(Debug)]
+pub struct ShadowRun {
+    sender: Option<oneshot::Sender<Output>>,
+    started: Instant,
+    max_text_bytes: usize,
+    output: Output,
+    /// Arguments of the streamed tool calls, parsed when the stream ends
+    tool_inputs: Vec<String>,
+}
+
+impl ShadowRun {
+    /// Record a complete response
+    pub fn record_response(&mut self, response: &ClaudeResponse) {
+        self.output = Output::of_response(response, self.max_text_bytes);
+    }
+    
+    /// Record a streamed event
+    pub fn record_stream_event(&mut self, event: &ClaudeStreamEvent) {
+        match event {
+            ClaudeStreamEvent::ContentBlockStart { content_block: ClaudeContentBlock::ToolUse { name, .. }, .. } => {
+                self.output.tool_calls.push(ToolCall { name: name.clone(), input: serde_json::Value::Null });
+                self.tool_inputs.push(String::new());
+            }
+            ClaudeStreamEvent::ContentBlockDelta { delta, .. } => match delta {
+                ClaudeContentDelta::TextDelta { text } => self.output.push_text(text, self.max_text_bytes),
+                ClaudeContentDelta::InputJsonDelta { partial_json } => {
+                    if let Some(input) = self.tool_inputs.last_mut() {
+                        input.push_str(partial_json);
+                    }
+                }
+            },
+            ClaudeStreamEvent::MessageDelta { delta, usage } => {
+                self.output.stop_reason = delta.stop_reason.clone();
+                self.output.output_tokens = Some(usage.output_tokens);
+            }
+            ClaudeStreamEvent::Error { error } => self.output.error = Some(error.error_type.clone()),
+            _ => {}
+        }
+    }
+}
+
+impl Drop for ShadowRun {
+    fn drop(&mut self) {
+        let mut output = std::mem::take(&mut self.output);
+        output.latency_ms = self.started.elapsed().as_millis() as u64;
+        for (call, input) in output.tool_calls.iter_mut().zip(&self.tool_inputs) {
+            // A tool call without arguments streams no input
+            call.input = match input.as_str() {
+                "" => serde_json::json!({}),
+                input => serde_json::from_str(input).unwrap_or_else(|_| input.into()),
+            };
+        }
+        if output.stop_reason.is_none() && output.error.is_none() {
+            output.error = Some("incomplete response".to_string());
+        }
+        if let Some(sender) = self.sender.take() {
+            let _ = sender.send(output);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::claude::{ClaudeMessageDelta, ClaudeUsage};
+    
+    fn traffic(percent: f64, models: &[&str]) -> ShadowTraffic {
+        let dir = tempfile::tempdir().unwrap();
+        ShadowTraffic::open(&ShadowConfig {
+            target: "candidate/glm-4.6".to_string(),
+            percent,
+            models: models.iter().map(|model| model.to_string()).collect(),
+            path: dir.path().join("shadow.jsonl"),
+            max_file_bytes: 1 << 20,
+            max_files: 1,
+            max_text_bytes: 8,
+        }).unwrap()
+    }
+    
+    #[test]
+    fn test_sample() {
+        let shadow = traffic(25.0, &[]);
+        let sampled = (0..100).filter(|_| shadow.sample("claude-sonnet-4", "openai/gpt-4o")).count();
+        assert_eq!(sampled, 25);
+        // Requests already routed to the candidate aren't mirrored
+        assert!(!(0..4).any(|_| shadow.sample("claude-sonnet-4", "candidate/glm-4.6")));
+        
+        let shadow = traffic(100.0, &["claude-sonnet-4"]);
+        assert!(shadow.sample("claude-sonnet-4", "openai/gpt-4o"));
+        assert!(!shadow.sample("claude-haiku-4", "openai/gpt-4o-mini"));
+    }
+    
+    fn start_run() -> (ShadowRun, oneshot::Receiver<Output>) {
+        let (sender, receiver) = oneshot::channel();
+        let run = ShadowRun { sender: Some(sender), started: Instant::now(), max_text_bytes: 8, output: Output::default(), tool_inputs: Vec::new() };
+        (run, receiver)
+    }
+    
+    #[tokio::test]
+    async fn test_run_records_stream() {
+        let (mut run, receiver) = start_run();
+        let events = [
+            ClaudeStreamEvent::ContentBlockDelta { index: 0, delta: ClaudeContentDelta::TextDelta { text: "Listing ".to_string() } },
+            ClaudeStreamEvent::ContentBlockDelta { index: 0, delta: ClaudeContentDelta::TextDelta { text: "files".to_string() } },
+            ClaudeStreamEvent::ContentBlockStart {
+                index: 1,
+                content_block: ClaudeContentBlock::ToolUse { id: "toolu_1".to_string(), name: "ls".to_string(), input: serde_json::json!({}), thought_signature: None },
+            },
+            ClaudeStreamEvent::ContentBlockDelta { index: 1, delta: ClaudeContentDelta::InputJsonDelta { partial_json: r#"{"path": "#.to_string() } },
+            ClaudeStreamEvent::ContentBlockDelta { index: 1, delta: ClaudeContentDelta::InputJsonDelta { partial_json: r#""src"}"#.to_string() } },
+            ClaudeStreamEvent::ContentBlockStart {
+                index: 2,
+                content_block: ClaudeContentBlock::ToolUse { id: "toolu_2".to_string(), name: "pwd".to_string(), input: serde_json::json!({}), thought_signature: None },
+            },
+            ClaudeStreamEvent::MessageDelta {
+                delta: ClaudeMessageDelta { stop_reason: Some("tool_use".to_string()), stop_sequence: None },
+                usage: ClaudeUsage { output_tokens: 12, ..Default::default() },
+            },
+        ];
+        for event in &events {
+            run.record_stream_event(event);
+        }
+        drop(run);
+        
+        let output = receiver.await.unwrap();
+        assert_eq!(output.text, "Listing ");
+        assert_eq!(output.tool_calls, [
+            ToolCall { name: "ls".to_string(), input: serde_json::json!({"path": "src"}) },
+            ToolCall { name: "pwd".to_string(), input: serde_json::json!({}) },
+        ]);
+        assert_eq!(output.stop_reason.as_deref(), Some("tool_use"));
+        assert_eq!(output.output_tokens, Some(12));
+        assert!(output.error.is_none());
+        
+        // A stream cut short is logged as failed
+        let (run, receiver) = start_run();
+        drop(run);
+        assert!(receiver.await.unwrap().error.is_some());
+    }
+}
@@ -0,0 +1,218 @@
+//! Shared state backends
+//!
+//! By default session state and thought signatures live in process memory,
+//! so every replica behind a load balancer has its own. With a `stateBackend`
+//! section, the [`SessionManager`](super::sessions::SessionManager) and the
+//! [`ThoughtCache`](crate::utils::thought_cache::ThoughtCache) also write
+//! to a [`StateBackend`] and read from it when a key is missing locally,
+//! so a tool call answered by another replica still finds its signature.
+//!
+//! The Redis backend requires the `redis` feature. Writes whose result isn't
+//! needed, such as usage counters and signatures recorded from stream
+//! events, run on tokio's blocking pool with [`spawn_write`]. Lookups block
+//! with a short timeout, inside `block_in_place` so the worker's other tasks
+//! move to another thread. Failures are logged and the local state is used
+//! instead.
+
+use crate::config::StateBackendConfig;
+use anyhow::Result;
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Key-value store shared by proxy replicas
+///
+/// Keys are relative; backends add their configured prefix.
+pub trait StateBackend: Debug + Send + Sync {
+    /// Name shown in logs
+    fn name(&self) -> &'static str;
+    
+    /// Value of a key
+    fn get(&self, key: &str) -> Result<Option<String>>;
+    
+    /// Set a key that expires after `ttl`
+    fn set(&self, key: &str, value: &str, ttl: Duration) -> Result<()>;
+    
+    /// Set a key that expires after `ttl`, returning its previous value
+    fn swap(&self, key: &str, value: &str, ttl: Duration) -> Result<Option<String>>;
+    
+    /// Add to a counter that expires after `ttl`, returning the new value
+    fn incr(&self, key: &str, delta: u64, ttl: Duration) -> Result<u64>;
+}
+
+/// Run a backend write off the request path, on tokio's blocking pool
+///
+/// Outside a runtime the write runs right away.
+pub fn spawn_write(write: impl FnOnce() + Send + 'static) {
+    match tokio::runtime::Handle::try_current() {
+        Ok(handle) => drop(handle.spawn_blocking(write)),
+        Err(_) => write(),
+    }
+}
+
+/// Create the configured backend, or `None` to keep state in memory only
+pub fn from_config(config: &StateBackendConfig) -> Result<Option<Arc<dyn StateBackend>>> {
+    match config.backend_type.as_str() {
+        "memory" => Ok(None),
+        #[cfg(feature = "redis")]
+        "redis" => Ok(Some(Arc::new(RedisBackend::new(config)?))),
+        other => anyhow::bail!("Unsupported stateBackend type '{}'", other),
+    }
+}
+
+#[cfg(feature = "redis")]
+pub use self::redis_backend::RedisBackend;
+
+#[cfg(feature = "redis")]
+mod redis_backend {
+    use super::*;
+    use anyhow::Context;
+    use std::sync::Mutex;
+    
+    /// Idle connections kept for reuse
+    const MAX_IDLE_CONNECTIONS: usize = 16;
+    
+    /// State stored in Redis
+    pub struct RedisBackend {
+        client: redis::Client,
+        idle: Mutex<Vec<redis::Connection>>,
+        key_prefix: String,
+        timeout: Duration,
+    }
+    
+    impl Debug for RedisBackend {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("RedisBackend")
+                .field("key_prefix", &self.key_prefix)
+                .field("timeout", &self.timeout)
+                .finish()
+        }
+    }
+    
+    impl RedisBackend {
+        /// Create a backend for `stateBackend.url`; connections are opened on first use
+        pub fn new(config: &StateBackendConfig) -> Result<Self> {
+            let url = config.url.as_deref().context("stateBackend.url is required for Redis")?;
+            let client = redis::Client::open(url).context("Invalid stateBackend.url")?;
+            Ok(Self {
+                client,
+                idle: Mutex::new(Vec::new()),
+                key_prefix: config.key_prefix.clone(),
+                timeout: Duration::from_millis(config.timeout_ms),
+            })
+        }
+        
+        /// Run commands on an idle or new connection
+        ///
+        /// Connections that failed are dropped rather than reused.
+        fn with_connection<T>(&self, f: impl FnOnce(&mut redis::Connection) -> redis::RedisResult<T>) -> Result<T> {
+            blocking(|| self.run(f))
+        }
+        
+        fn run<T>(&self, f: impl FnOnce(&mut redis::Connection) -> redis::RedisResult<T>) -> Result<T> {
+            let idle = self.idle.lock().unwrap().pop();
+            let mut connection = match idle {
+                Some(connection) => connection,
+                None => {
+                    let connection = self.client.get_connection_with_timeout(self.timeout)
+                        .context("Failed to connect to Redis")?;
+                    connection.set_read_timeout(Some(self.timeout))?;
+                    connection.set_write_timeout(Some(self.timeout))?;
+                    connection
+                }
+            };
+            let value = f(&mut connection).context("Redis command failed")?;
+            let mut idle = self.idle.lock().unwrap();
+            if idle.len() < MAX_IDLE_CONNECTIONS {
+                idle.push(connection);
+            }
+            Ok(value)
+        }
+        
+        fn key(&self, key: &str) -> String {
+            format!("{}{}", self.key_prefix, key)
+        }
+    }
+    
+    impl StateBackend for RedisBackend {
+        fn name(&self) -> &'static str {
+            "redis"
+        }
+        
+        fn get(&self, key: &str) -> Result<Option<String>> {
+            self.with_connection(|connection| redis::cmd("GET").arg(self.key(key)).query(connection))
+        }
+        
+        fn set(&self, key: &str, value: &str, ttl: Duration) -> Result<()> {
+            self.with_connection(|connection| {
+                redis::cmd("SET").arg(self.key(key)).arg(value).arg("EX").arg(expiry(ttl)).query(connection)
+            })
+        }
+        
+        fn swap(&self, key: &str, value: &str, ttl: Duration) -> Result<Option<String>> {
+            // SET ... GET needs Redis 6.2
+            self.with_connection(|connection| {
+                redis::cmd("SET").arg(self.key(key)).arg(value).arg("EX").arg(expiry(ttl)).arg("GET").query(connection)
+            })
+        }
+        
+        fn incr(&self, key: &str, delta: u64, ttl: Duration) -> Result<u64> {
+            let key = self.key(key);
+            let (value,): (u64,) = self.with_connection(|connection| {
+                redis::pipe().atomic()
+                    .cmd("INCRBY").arg(&key).arg(delta)
+                    .cmd("EXPIRE").arg(&key).arg(expiry(ttl)).ignore()
+                    .query(connection)
+            })?;
+            Ok(value)
+        }
+    }
+    
+    /// Run blocking I/O without stalling the tokio worker it's called on
+    fn blocking<T>(f: impl FnOnce() -> T) -> T {
+        use tokio::runtime::{Handle, RuntimeFlavor};
+        match Handle::try_current() {
+            Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => tokio::task::block_in_place(f),
+            _ => f(),
+        }
+    }
+    
+    /// Expiry in whole seconds; Redis rejects 0
+    fn expiry(ttl: Duration) -> u64 {
+        ttl.as_secs().max(1)
+    }
+}
+
+/// In-process backend standing in for a shared store in tests
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub(crate) struct MemoryBackend {
+    values: std::sync::Mutex<std::collections::HashMap<String, String>>,
+}
+
+#[cfg(test)]
+impl StateBackend for MemoryBackend {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+    
+    fn get(&self, key: &str) -> Result<Option<String>> {
+        Ok(self.values.lock().unwrap().get(key).cloned())
+    }
+    
+    fn set(&self, key: &str, value: &str, _ttl: Duration) -> Result<()> {
+        self.values.lock().unwrap().insert(key.to_string(), value.to_string());
+        Ok(())
+    }
+    
+    fn swap(&self, key: &str, value: &str, _ttl: Duration) -> Result<Option<String>> {
+        Ok(self.values.lock().unwrap().insert(key.to_string(), value.to_string()))
+    }
+    
+    fn incr(&self, key: &str, delta: u64, _ttl: Duration) -> Result<u64> {
+        let mut values = self.values.lock().unwrap();
+        let value = values.get(key).and_then(|value| value.parse::<u64>().ok()).unwrap_or(0) + delta;
+        values.insert(key.to_string(), value.to_string());
+        Ok(value)
+    }
+}
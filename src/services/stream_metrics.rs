@@ -0,0 +1,259 @@
+//! Throughput and latency metrics of streaming responses
+//!
+//! Every streaming request is timed by a [`StreamTimer`]: time to the first
+//! token (from calling the upstream to the first content delta sent to the
+//! client, so it includes connecting), output tokens per second after the
+//! first token, and total stream duration. When the stream ends the
+//! measurements are logged and added to per provider/model histograms, which
+//! `GET /metrics` serves in the Prometheus text format.
+//!
+//! Handlers reach the shared instance through [`global`].
+
+use crate::models::claude::ClaudeStreamEvent;
+use once_cell::sync::Lazy;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::info;
+
+/// Bucket bounds of time to first token, in seconds
+const TTFT_BUCKETS: &[f64] = &[0.1, 0.25, 0.5, 1.0, 2.0, 5.0, 10.0, 30.0];
+
+/// Bucket bounds of output tokens per second
+const TOKENS_PER_SECOND_BUCKETS: &[f64] = &[5.0, 10.0, 20.0, 50.0, 100.0, 200.0, 500.0];
+
+/// Bucket bounds of stream duration, in seconds
+const DURATION_BUCKETS: &[f64] = &[1.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0];
+
+/// Name and help text of the histograms, in the order of [`PathMetrics::histograms`]
+const HISTOGRAMS: [(&str, &str); 3] = [
+    ("aiapiproxy_stream_time_to_first_token_seconds", "Time from calling the upstream to the first streamed token"),
+    ("aiapiproxy_stream_output_tokens_per_second", "Output tokens per second after the first token"),
+    ("aiapiproxy_stream_duration_seconds", "Time from calling the upstream to the end of the stream"),
+];
+
+static STREAM_METRICS: Lazy<StreamMetrics> = Lazy::new(StreamMetrics::default);
+
+/// The process-wide stream metrics
+pub fn global() -> &'static StreamMetrics {
+    &STREAM_METRICS
+}
+
+/// Measurements of one finished stream
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StreamSample {
+    pub time_to_first_token: Option<Duration>,
+    pub output_tokens: u32,
+    pub duration: Duration,
+}
+
+impl StreamSample {
+    /// Output tokens per second between the first token and the end of the stream
+    pub fn tokens_per_second(&self) -> Option<f64> {
+        let generating = self.duration.checked_sub(self.time_to_first_token?)?.as_secs_f64();
+        (self.output_tokens > 0 && generating > 0.0).then(|| f64::from(self.output_tokens) / generating)
+    }
+}
+
+/// Cumulative histogram in the Prometheus sense
+#[derive(Debug, Clone)]
+struct Histogram {
+    bounds: &'static [f64],
+    /// Observations per bucket (not cumulative); the last is `+Inf`
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Self { bounds, counts: vec![0; bounds.len() + 1], sum: 0.0, count: 0 }
+    }
+    
+    fn observe(&mut self, value: f64) {
+        let bucket = self.bounds.iter().position(|bound| value <= *bound).unwrap_or(self.bounds.len());
+        self.counts[bucket] += 1;
+        self.sum += value;
+        self.count += 1;
+    }
+    
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let mut cumulative = 0;
+        for (bound, count) in self.bounds.iter().map(|bound| bound.to_string()).chain(["+Inf".to_string()]).zip(&self.counts) {
+            cumulative += count;
+            let _ = writeln!(out, "{}_bucket{{{},le=\"{}\"}} {}", name, labels, bound, cumulative);
+        }
+        let _ = writeln!(out, "{}_sum{{{}}} {}", name, labels, self.sum);
+        let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, self.count);
+    }
+}
+
+/// Histograms of one provider/model path
+#[derive(Debug, Clone)]
+struct PathMetrics {
+    streams: u64,
+    time_to_first_token: Histogram,
+    tokens_per_second: Histogram,
+    duration: Histogram,
+}
+
+impl PathMetrics {
+    fn histograms(&self) -> [&Histogram; 3] {
+        [&self.time_to_first_token, &self.tokens_per_second, &self.duration]
+    }
+}
+
+impl Default for PathMetrics {
+    fn default() -> Self {
+        Self {
+            streams: 0,
+            time_to_first_token: Histogram::new(TTFT_BUCKETS),
+            tokens_per_second: Histogram::new(TOKENS_PER_SECOND_BUCKETS),
+            duration: Histogram::new(DURATION_BUCKETS),
+        }
+    }
+}
+
+/// Stream measurements by provider/model path
+#[derive(Debug, Default)]
+pub struct StreamMetrics {
+    paths: Mutex<BTreeMap<String, PathMetrics>>,
+}
+
+impl StreamMetrics {
+    /// Add a finished stream of a provider/model path
+    pub fn record(&self, path: &str, sample: &StreamSample) {
+        let mut paths = self.paths.lock().unwrap();
+        let metrics = paths.entry(path.to_string()).or_default();
+        metrics.streams += 1;
+        if let Some(time_to_first_token) = sample.time_to_first_token {
+            metrics.time_to_first_token.observe(time_to_first_token.as_secs_f64());
+        }
+        if let Some(tokens_per_second) = sample.tokens_per_second() {
+            metrics.tokens_per_second.observe(tokens_per_second);
+        }
+        metrics.duration.observe(sample.duration.as_secs_f64());
+    }
+    
+    /// All metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let paths = self.paths.lock().unwrap();
+        let labeled: Vec<(String, &PathMetrics)> = paths.iter()
+            .map(|(path, metrics)| {
+                let (provider, model) = path.split_once('/').unwrap_or((path, ""));
+                (format!("provider=\"{}\",model=\"{}\"", escape_label(provider), escape_label(model)), metrics)
+            })
+            .collect();
+        
+        let mut out = String::new();
+        out.push_str("# HELP aiapiproxy_streams_total Streaming responses finished\n");
+        out.push_str("# TYPE aiapiproxy_streams_total counter\n");
+        for (labels, metrics) in &labeled {
+            let _ = writeln!(out, "aiapiproxy_streams_total{{{}}} {}", labels, metrics.streams);
+        }
+        for (index, (name, help)) in HISTOGRAMS.iter().enumerate() {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} histogram", name);
+            for (labels, metrics) in &labeled {
+                metrics.histograms()[index].render(&mut out, name, labels);
+            }
+        }
+        out
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Times one streaming response; the measurements are logged and recorded
+/// when it is dropped, also when the client disconnects early
+#[derive(Debug)]
+pub struct StreamTimer {
+    path: String,
+    started: Instant,
+    first_token: Option<Instant>,
+    output_tokens: u32,
+}
+
+impl StreamTimer {
+    /// Time a stream to a provider/model path whose upstream call began at `started`
+    pub fn new(path: &str, started: Instant) -> Self {
+        Self { path: path.to_string(), started, first_token: None, output_tokens: 0 }
+    }
+    
+    /// Note an event sent to the client
+    pub fn observe(&mut self, event: &ClaudeStreamEvent) {
+        match event {
+            ClaudeStreamEvent::ContentBlockDelta { .. } if self.first_token.is_none() => {
+                self.first_token = Some(Instant::now());
+            }
+            // Estimated by the stream pipeline when the upstream reports no usage
+            ClaudeStreamEvent::MessageDelta { usage, .. } => self.output_tokens = usage.output_tokens,
+            _ => {}
+        }
+    }
+    
+    fn sample(&self) -> StreamSample {
+        StreamSample {
+            time_to_first_token: self.first_token.map(|first_token| first_token - self.started),
+            output_tokens: self.output_tokens,
+            duration: self.started.elapsed(),
+        }
+    }
+}
+
+impl Drop for StreamTimer {
+    fn drop(&mut self) {
+        let sample = self.sample();
+        info!(
+            "⏱️ Stream of {} finished: ttft={}, {} output tokens at {} tokens/s, duration={:.2}s",
+            self.path,
+            sample.time_to_first_token.map_or("-".to_string(), |ttft| format!("{:.2}s", ttft.as_secs_f64())),
+            sample.output_tokens,
+            sample.tokens_per_second().map_or("-".to_string(), |rate| format!("{:.1}", rate)),
+            sample.duration.as_secs_f64(),
+        );
+        global().record(&self.path, &sample);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_tokens_per_second() {
+        let sample = StreamSample {
+            time_to_first_token: Some(Duration::from_secs(1)),
+            output_tokens: 100,
+            duration: Duration::from_secs(3),
+        };
+        assert_eq!(sample.tokens_per_second(), Some(50.0));
+        assert_eq!(StreamSample { time_to_first_token: None, ..sample }.tokens_per_second(), None);
+        assert_eq!(StreamSample { output_tokens: 0, ..sample }.tokens_per_second(), None);
+    }
+    
+    #[test]
+    fn test_render() {
+        let metrics = StreamMetrics::default();
+        let sample = StreamSample {
+            time_to_first_token: Some(Duration::from_millis(300)),
+            output_tokens: 40,
+            duration: Duration::from_millis(2300),
+        };
+        metrics.record("openai/gpt-4o", &sample);
+        metrics.record("openai/gpt-4o", &StreamSample { time_to_first_token: None, output_tokens: 0, ..sample });
+        
+        let text = metrics.render();
+        let labels = r#"provider="openai",model="gpt-4o""#;
+        assert!(text.contains(&format!("aiapiproxy_streams_total{{{}}} 2\n", labels)));
+        assert!(text.contains(&format!("aiapiproxy_stream_time_to_first_token_seconds_bucket{{{},le=\"0.25\"}} 0\n", labels)));
+        assert!(text.contains(&format!("aiapiproxy_stream_time_to_first_token_seconds_bucket{{{},le=\"0.5\"}} 1\n", labels)));
+        assert!(text.contains(&format!("aiapiproxy_stream_time_to_first_token_seconds_count{{{}}} 1\n", labels)));
+        assert!(text.contains(&format!("aiapiproxy_stream_output_tokens_per_second_bucket{{{},le=\"20\"}} 1\n", labels)));
+        assert!(text.contains(&format!("aiapiproxy_stream_duration_seconds_bucket{{{},le=\"+Inf\"}} 2\n", labels)));
+        assert!(text.contains("# TYPE aiapiproxy_stream_duration_seconds histogram\n"));
+    }
+}
@@ -0,0 +1,221 @@
+//! Recovery from upstream streams that fail mid-response
+//!
+//! When the upstream SSE connection drops after the SSE response has started,
+//! the HTTP status can no longer be changed. The model's `streamRecovery`
+//! option decides what the client sees instead:
+//!
+//! - `"error"` (default): an `error` event, as the Anthropic API sends for
+//!   failures mid-stream
+//! - `"stop"`: the open block and the message are closed with `stop_reason:
+//!   "pause_turn"`, so the client gets a well-formed partial message it can
+//!   send back to continue
+//! - `"resume"`: the request is retried with the text streamed so far appended
+//!   as an assistant prefix, for upstreams that continue a trailing assistant
+//!   message; the continuation is spliced into the same Claude message. Falls
+//!   back to `"stop"` once a tool call or thinking block was streamed or the
+//!   retries are used up
+
+use crate::config::ModelConfig;
+use crate::models::claude::{ClaudeContentBlock, ClaudeContentDelta, ClaudeError, ClaudeMessageDelta, ClaudeStreamEvent, ClaudeUsage};
+use crate::models::openai::{OpenAIContent, OpenAIMessage, OpenAIRequest};
+
+/// Send an `error` event (default)
+pub const RECOVERY_ERROR: &str = "error";
+
+/// Close the message with `stop_reason: "pause_turn"`
+pub const RECOVERY_STOP: &str = "stop";
+
+/// Retry with the streamed text as an assistant prefix
+pub const RECOVERY_RESUME: &str = "resume";
+
+/// Retries of a single stream in resume mode
+const MAX_RESUME_ATTEMPTS: u32 = 2;
+
+/// Stream state needed to recover from an upstream failure
+#[derive(Debug)]
+pub struct StreamRecovery {
+    policy: String,
+    /// Request of the stream, kept in resume mode
+    request: Option<OpenAIRequest>,
+    /// Text sent to the client so far
+    text: String,
+    /// False once a block that can't be continued from a text prefix was sent
+    resumable: bool,
+    attempts: u32,
+}
+
+impl StreamRecovery {
+    /// Create the recovery state for a streaming request
+    pub fn new(request: &OpenAIRequest, model_config: Option<&ModelConfig>) -> Self {
+        let policy = model_config
+            .and_then(|config| config.options.stream_recovery.clone())
+            .unwrap_or_else(|| RECOVERY_ERROR.to_string());
+        Self {
+            request: (policy == RECOVERY_RESUME).then(|| request.clone()),
+            policy,
+            text: String::new(),
+            resumable: true,
+            attempts: 0,
+        }
+    }
+    
+    /// Record an event sent to the client
+    pub fn observe(&mut self, event: &ClaudeStreamEvent) {
+        match event {
+            ClaudeStreamEvent::ContentBlockStart { content_block, .. } if !matches!(content_block, ClaudeContentBlock::Text { .. }) => {
+                self.resumable = false;
+            }
+            ClaudeStreamEvent::ContentBlockDelta { delta: ClaudeContentDelta::TextDelta { text }, .. } => {
+                self.text.push_str(text);
+            }
+            _ => {}
+        }
+    }
+    
+    /// Request continuing the stream, if the policy and the streamed content allow it
+    ///
+    /// The text streamed so far becomes a trailing assistant message.
+    pub fn resume_request(&mut self) -> Option<OpenAIRequest> {
+        if !self.resumable || self.attempts >= MAX_RESUME_ATTEMPTS {
+            return None;
+        }
+        let mut request = self.request.clone()?;
+        self.attempts += 1;
+        
+        if !self.text.is_empty() {
+            request.messages.push(OpenAIMessage {
+                role: "assistant".to_string(),
+                content: Some(OpenAIContent::Text(self.text.clone())),
+                name: None,
+                tool_calls: None,
+                tool_call_id: None,
+            });
+        }
+        Some(request)
+    }
+    
+    /// Number of resume attempts made
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+    
+    /// Length of the text sent to the client so far
+    pub fn text_len(&self) -> usize {
+        self.text.len()
+    }
+    
+    /// Events ending the stream after an unrecoverable upstream failure
+    ///
+    /// `started` tells whether `message_start` was sent; a message can only be
+    /// closed cleanly once it has started. The events still need to go through
+    /// the stream's `ContentBlockTracker` to close the open block.
+    pub fn failure_events(&self, started: bool, message: &str) -> Vec<ClaudeStreamEvent> {
+        if !started || self.policy == RECOVERY_ERROR {
+            return vec![ClaudeStreamEvent::Error {
+                error: ClaudeError {
+                    error_type: "api_error".to_string(),
+                    message: format!("Upstream stream failed: {}", message),
+                },
+            }];
+        }
+        
+        vec![
+            ClaudeStreamEvent::MessageDelta {
+                delta: ClaudeMessageDelta {
+                    stop_reason: Some("pause_turn".to_string()),
+                    stop_sequence: None,
+                },
+                usage: ClaudeUsage::default(),
+            },
+            ClaudeStreamEvent::MessageStop,
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::file::ModelOptions;
+    
+    fn model_config(policy: &str) -> ModelConfig {
+        ModelConfig {
+            name: "deepseek-chat".to_string(),
+            enabled: true,
+            alias: None,
+            max_tokens: None,
+            context_window: None,
+            temperature: None,
+            options: ModelOptions {
+                stream_recovery: Some(policy.to_string()),
+                ..Default::default()
+            },
+        }
+    }
+    
+    fn text_delta(text: &str) -> ClaudeStreamEvent {
+        ClaudeStreamEvent::ContentBlockDelta {
+            index: 0,
+            delta: ClaudeContentDelta::TextDelta { text: text.to_string() },
+        }
+    }
+    
+    #[test]
+    fn test_resume_request() {
+        let request = OpenAIRequest::builder().model("deepseek-chat").user("Tell me a story").build();
+        let mut recovery = StreamRecovery::new(&request, Some(&model_config(RECOVERY_RESUME)));
+        recovery.observe(&text_delta("Once upon "));
+        recovery.observe(&text_delta("a time"));
+        
+        let resumed = recovery.resume_request().unwrap();
+        let prefix = resumed.messages.last().unwrap();
+        assert_eq!(prefix.role, "assistant");
+        assert!(matches!(&prefix.content, Some(OpenAIContent::Text(text)) if text == "Once upon a time"));
+        
+        assert!(recovery.resume_request().is_some());
+        assert!(recovery.resume_request().is_none());
+        assert_eq!(recovery.attempts(), MAX_RESUME_ATTEMPTS);
+    }
+    
+    #[test]
+    fn test_no_resume_after_tool_call() {
+        let request = OpenAIRequest::builder().user("List files").build();
+        let mut recovery = StreamRecovery::new(&request, Some(&model_config(RECOVERY_RESUME)));
+        recovery.observe(&ClaudeStreamEvent::ContentBlockStart {
+            index: 0,
+            content_block: ClaudeContentBlock::ToolUse {
+                id: "toolu_1".to_string(),
+                name: "ls".to_string(),
+                input: serde_json::json!({}),
+                thought_signature: None,
+            },
+        });
+        assert!(recovery.resume_request().is_none());
+        
+        let mut recovery = StreamRecovery::new(&request, Some(&model_config(RECOVERY_STOP)));
+        assert!(recovery.resume_request().is_none());
+    }
+    
+    #[test]
+    fn test_failure_events() {
+        let request = OpenAIRequest::builder().user("Tell me a story").build();
+        let stop = StreamRecovery::new(&request, Some(&model_config(RECOVERY_STOP)));
+        let events = stop.failure_events(true, "connection reset");
+        assert!(matches!(
+            &events[0],
+            ClaudeStreamEvent::MessageDelta { delta, .. } if delta.stop_reason.as_deref() == Some("pause_turn")
+        ));
+        assert!(matches!(events[1], ClaudeStreamEvent::MessageStop));
+        
+        // Nothing to close before message_start
+        assert!(matches!(stop.failure_events(false, "connection reset")[0], ClaudeStreamEvent::Error { .. }));
+        
+        let default = StreamRecovery::new(&request, None);
+        match &default.failure_events(true, "connection reset")[0] {
+            ClaudeStreamEvent::Error { error } => {
+                assert_eq!(error.error_type, "api_error");
+                assert_eq!(error.message, "Upstream stream failed: connection reset");
+            }
+            other => panic!("expected error event, got {:?}", other),
+        }
+    }
+}
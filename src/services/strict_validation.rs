@@ -0,0 +1,170 @@
+//! Strict Claude request validation
+//!
+//! The proxy accepts requests the Anthropic API would reject, and leaves it to
+//! the upstream to cope. With `strictValidation`, requests are checked against
+//! the Anthropic API rules before they are converted, and rejected with its
+//! `invalid_request_error` messages:
+//!
+//! - `temperature` between 0 and 1
+//! - messages alternate between `user` and `assistant`, starting with `user`
+//! - every `tool_result` answers a `tool_use` of the preceding assistant message
+//! - at most 100 images, each at most 5 MB
+
+use crate::models::claude::{ClaudeContent, ClaudeContentBlock, ClaudeImageSource, ClaudeRequest};
+use std::collections::HashSet;
+
+/// Images allowed in one request
+pub const MAX_IMAGES: usize = 100;
+
+/// Largest decoded size of a base64 image in bytes
+pub const MAX_IMAGE_BYTES: usize = 5 * 1024 * 1024;
+
+/// Check a request against the Anthropic API rules
+///
+/// Returns the Claude `invalid_request_error` message of the first violation.
+pub fn validate(request: &ClaudeRequest) -> Result<(), String> {
+    if let Some(temperature) = request.temperature {
+        if !(0.0..=1.0).contains(&temperature) {
+            return Err(format!("temperature: {} is out of range 0..1", temperature));
+        }
+    }
+    
+    let mut images = 0;
+    let mut previous_tool_uses = HashSet::new();
+    for (i, message) in request.messages.iter().enumerate() {
+        let expected = if i % 2 == 0 { "user" } else { "assistant" };
+        if message.role != expected {
+            return Err(if i == 0 {
+                format!("messages: first message must use the \"user\" role, got \"{}\"", message.role)
+            } else {
+                format!("messages: roles must alternate between \"user\" and \"assistant\", but found \"{}\" at messages.{}", message.role, i)
+            });
+        }
+        
+        let blocks = match &message.content {
+            ClaudeContent::Blocks(blocks) => blocks.as_slice(),
+            _ => &[],
+        };
+        let mut tool_uses = HashSet::new();
+        for (j, block) in blocks.iter().enumerate() {
+            match block {
+                ClaudeContentBlock::ToolUse { id, .. } => {
+                    tool_uses.insert(id.as_str());
+                }
+                ClaudeContentBlock::ToolResult { tool_use_id, content, .. } => {
+                    if !previous_tool_uses.contains(tool_use_id.as_str()) {
+                        return Err(format!(
+                            "messages.{}.content.{}: unexpected `tool_use_id` found in `tool_result` blocks: {}. Each `tool_result` block must have a corresponding `tool_use` block in the previous message.",
+                            i, j, tool_use_id
+                        ));
+                    }
+                    for source in content.images() {
+                        images += 1;
+                        check_image(source, i, j)?;
+                    }
+                }
+                ClaudeContentBlock::Image { source } => {
+                    images += 1;
+                    check_image(source, i, j)?;
+                }
+                _ => {}
+            }
+        }
+        if images > MAX_IMAGES {
+            return Err(format!("messages: too many images: {} > {} maximum", images, MAX_IMAGES));
+        }
+        previous_tool_uses = tool_uses;
+    }
+    
+    Ok(())
+}
+
+/// Check the decoded size of a base64 image
+fn check_image(source: &ClaudeImageSource, message: usize, block: usize) -> Result<(), String> {
+    let bytes = source.data.trim_end_matches('=').len() * 3 / 4;
+    if bytes > MAX_IMAGE_BYTES {
+        return Err(format!(
+            "messages.{}.content.{}.image.source.base64: image exceeds 5 MB maximum: {} bytes > {} bytes",
+            message, block, bytes, MAX_IMAGE_BYTES
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::claude::ClaudeToolResultContent;
+    
+    fn tool_use(id: &str) -> ClaudeContentBlock {
+        ClaudeContentBlock::ToolUse { id: id.to_string(), name: "read".to_string(), input: serde_json::json!({}), thought_signature: None }
+    }
+    
+    fn tool_result(id: &str) -> ClaudeContentBlock {
+        ClaudeContentBlock::ToolResult { tool_use_id: id.to_string(), content: ClaudeToolResultContent::default(), is_error: None }
+    }
+    
+    fn image(bytes: usize) -> ClaudeContentBlock {
+        ClaudeContentBlock::Image {
+            source: ClaudeImageSource {
+                source_type: "base64".to_string(),
+                media_type: "image/png".to_string(),
+                data: "A".repeat(bytes / 3 * 4),
+                url: None,
+            },
+        }
+    }
+    
+    #[test]
+    fn test_roles_and_temperature() {
+        let request = ClaudeRequest::builder().user("Hi").assistant("Hello").user("How are you?").temperature(1.0).build();
+        assert!(validate(&request).is_ok());
+        
+        let request = ClaudeRequest::builder().user("Hi").temperature(1.5).build();
+        assert_eq!(validate(&request).unwrap_err(), "temperature: 1.5 is out of range 0..1");
+        
+        let request = ClaudeRequest::builder().assistant("Hello").build();
+        assert!(validate(&request).unwrap_err().starts_with("messages: first message must use the \"user\" role"));
+        
+        let request = ClaudeRequest::builder().user("Hi").user("Anyone?").build();
+        assert!(validate(&request).unwrap_err().ends_with("but found \"user\" at messages.1"));
+    }
+    
+    #[test]
+    fn test_tool_results() {
+        let request = ClaudeRequest::builder()
+            .user("Read the file")
+            .message("assistant", ClaudeContent::Blocks(vec![tool_use("toolu_1")]))
+            .message("user", ClaudeContent::Blocks(vec![tool_result("toolu_1")]))
+            .build();
+        assert!(validate(&request).is_ok());
+        
+        // A result must answer a call of the message right before it
+        let request = ClaudeRequest::builder()
+            .user("Read the file")
+            .message("assistant", ClaudeContent::Blocks(vec![tool_use("toolu_1")]))
+            .message("user", ClaudeContent::Blocks(vec![tool_result("toolu_1")]))
+            .assistant("Done")
+            .message("user", ClaudeContent::Blocks(vec![tool_result("toolu_1")]))
+            .build();
+        assert!(validate(&request).unwrap_err().starts_with("messages.4.content.0: unexpected `tool_use_id` found in `tool_result` blocks: toolu_1."));
+    }
+    
+    #[test]
+    fn test_images() {
+        let request = ClaudeRequest::builder()
+            .message("user", ClaudeContent::Blocks(vec![image(1024), image(MAX_IMAGE_BYTES)]))
+            .build();
+        assert!(validate(&request).is_ok());
+        
+        let request = ClaudeRequest::builder()
+            .message("user", ClaudeContent::Blocks(vec![image(MAX_IMAGE_BYTES + 3)]))
+            .build();
+        assert!(validate(&request).unwrap_err().starts_with("messages.0.content.0.image.source.base64: image exceeds 5 MB maximum"));
+        
+        let request = ClaudeRequest::builder()
+            .message("user", ClaudeContent::Blocks(vec![image(3); MAX_IMAGES + 1]))
+            .build();
+        assert_eq!(validate(&request).unwrap_err(), "messages: too many images: 101 > 100 maximum");
+    }
+}
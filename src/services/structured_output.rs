@@ -0,0 +1,307 @@
+//! Structured output support
+//!
+//! Claude clients request structured output by forcing a single tool
+//! (`tool_choice: {"type": "tool", "name": ...}`) whose input schema describes
+//! the expected JSON. Providers that support OpenAI `response_format`
+//! json_schema can produce the same result natively; this module rewrites the
+//! request for them and turns the JSON text reply back into a tool call.
+
+use crate::models::openai::{
+    OpenAIFunctionCall, OpenAIJsonSchema, OpenAIRequest, OpenAIResponse, OpenAIResponseFormat,
+    OpenAIStreamResponse, OpenAIToolCall,
+};
+use crate::providers::BoxStream;
+use futures::StreamExt;
+use tracing::debug;
+
+/// Keep the forced tool call as-is (default)
+pub const MODE_TOOL: &str = "tool";
+
+/// Map the forced tool to `response_format: {"type": "json_schema"}`
+pub const MODE_JSON_SCHEMA: &str = "json_schema";
+
+/// Get the name of the tool forced by `tool_choice`, if any
+///
+/// Accepts both the Claude form `{"type": "tool", "name": "x"}` and the
+/// OpenAI form `{"type": "function", "function": {"name": "x"}}`.
+pub fn forced_tool_name(request: &OpenAIRequest) -> Option<String> {
+    let choice = request.tool_choice.as_ref()?;
+    let name = match choice.get("type").and_then(|t| t.as_str())? {
+        "tool" => choice.get("name").and_then(|n| n.as_str()),
+        "function" => choice
+            .get("function")
+            .and_then(|f| f.get("name"))
+            .and_then(|n| n.as_str()),
+        _ => None,
+    }?;
+
+    // The forced tool must be declared in the request
+    let declared = request
+        .tools
+        .as_ref()
+        .is_some_and(|tools| tools.iter().any(|t| t.function.name == name));
+
+    declared.then(|| name.to_string())
+}
+
+/// Rewrite a forced single-tool request into a json_schema response format
+///
+/// Returns the forced tool name when the request was rewritten, so the
+/// response can be mapped back with [`restore_tool_call`].
+pub fn apply_json_schema(request: &mut OpenAIRequest) -> Option<String> {
+    let name = forced_tool_name(request)?;
+    let tool = request
+        .tools
+        .as_ref()?
+        .iter()
+        .find(|t| t.function.name == name)?
+        .clone();
+
+    debug!("📊 Mapping forced tool '{}' to json_schema response format", name);
+
+    request.response_format = Some(OpenAIResponseFormat {
+        format_type: MODE_JSON_SCHEMA.to_string(),
+        json_schema: Some(OpenAIJsonSchema {
+            name: tool.function.name,
+            description: tool.function.description,
+            schema: tool
+                .function
+                .parameters
+                .unwrap_or_else(|| serde_json::json!({"type": "object"})),
+            strict: None,
+        }),
+    });
+    request.tools = None;
+    request.tool_choice = None;
+
+    Some(name)
+}
+
+/// Convert the JSON text reply of a json_schema request back into a tool call
+pub fn restore_tool_call(response: &mut OpenAIResponse, tool_name: &str) {
+    for choice in &mut response.choices {
+        let Some(content) = choice.message.content.take() else {
+            continue;
+        };
+        let arguments = content.extract_text();
+
+        choice.message.tool_calls = Some(vec![OpenAIToolCall {
+            index: None,
+            id: Some(format!("toolu_{}", uuid::Uuid::new_v4().simple())),
+            tool_type: Some("function".to_string()),
+            function: OpenAIFunctionCall {
+                name: Some(tool_name.to_string()),
+                arguments: Some(arguments),
+            },
+            signature: None,
+            extra_content: None,
+        }]);
+
+        if choice.finish_reason.as_deref() == Some("stop") {
+            choice.finish_reason = Some("tool_calls".to_string());
+        }
+    }
+}
+
+/// Streaming variant of [`restore_tool_call`]
+///
+/// Text deltas become argument deltas of a single tool call; the first one
+/// carries the tool id and name. Without any text the stream is left as-is,
+/// so no empty tool call is made up.
+pub fn restore_tool_call_stream(
+    stream: BoxStream<'static, OpenAIStreamResponse>,
+    tool_name: String,
+) -> BoxStream<'static, OpenAIStreamResponse> {
+    let tool_id = format!("toolu_{}", uuid::Uuid::new_v4().simple());
+    let mut started = false;
+
+    Box::pin(stream.map(move |chunk| {
+        let mut chunk = chunk?;
+
+        for choice in &mut chunk.choices {
+            let mut tool_call = None;
+
+            if let Some(text) = choice.delta.content.take() {
+                if !text.is_empty() {
+                    let first = !started;
+                    started = true;
+                    tool_call = Some(OpenAIToolCall {
+                        index: None,
+                        id: first.then(|| tool_id.clone()),
+                        tool_type: first.then(|| "function".to_string()),
+                        function: OpenAIFunctionCall {
+                            name: first.then(|| tool_name.clone()),
+                            arguments: Some(text),
+                        },
+                        signature: None,
+                        extra_content: None,
+                    });
+                }
+            }
+
+            // The open tool block is closed with the message delta
+            if started && choice.finish_reason.as_deref() == Some("stop") {
+                choice.finish_reason = Some("tool_calls".to_string());
+            }
+
+            if let Some(tool_call) = tool_call {
+                choice.delta.tool_calls = Some(vec![tool_call]);
+            }
+        }
+
+        Ok(chunk)
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::openai::{
+        OpenAIChoice, OpenAIContent, OpenAIFunction, OpenAIMessage, OpenAIStreamChoice,
+        OpenAIStreamDelta, OpenAITool,
+    };
+    use serde_json::json;
+
+    fn create_request(tool_choice: serde_json::Value) -> OpenAIRequest {
+        OpenAIRequest {
+            tools: Some(vec![OpenAITool {
+                tool_type: "function".to_string(),
+                function: OpenAIFunction {
+                    name: "record_summary".to_string(),
+                    description: Some("Record a summary".to_string()),
+                    parameters: Some(json!({
+                        "type": "object",
+                        "properties": {"summary": {"type": "string"}}
+                    })),
+                },
+            }]),
+            tool_choice: Some(tool_choice),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_forced_tool_name() {
+        let request = create_request(json!({"type": "tool", "name": "record_summary"}));
+        assert_eq!(forced_tool_name(&request), Some("record_summary".to_string()));
+
+        let request = create_request(json!({"type": "function", "function": {"name": "record_summary"}}));
+        assert_eq!(forced_tool_name(&request), Some("record_summary".to_string()));
+
+        let request = create_request(json!({"type": "auto"}));
+        assert!(forced_tool_name(&request).is_none());
+
+        let request = create_request(json!({"type": "tool", "name": "undeclared"}));
+        assert!(forced_tool_name(&request).is_none());
+    }
+
+    #[test]
+    fn test_apply_json_schema() {
+        let mut request = create_request(json!({"type": "tool", "name": "record_summary"}));
+        let name = apply_json_schema(&mut request);
+
+        assert_eq!(name, Some("record_summary".to_string()));
+        assert!(request.tools.is_none());
+        assert!(request.tool_choice.is_none());
+
+        let format = request.response_format.unwrap();
+        assert_eq!(format.format_type, "json_schema");
+        let schema = format.json_schema.unwrap();
+        assert_eq!(schema.name, "record_summary");
+        assert_eq!(schema.schema["properties"]["summary"]["type"], "string");
+    }
+
+    #[test]
+    fn test_restore_tool_call() {
+        let mut response = OpenAIResponse {
+            id: "chatcmpl-1".to_string(),
+            object: "chat.completion".to_string(),
+            created: 0,
+            model: "gpt-4o".to_string(),
+            choices: vec![OpenAIChoice {
+                index: 0,
+                message: OpenAIMessage {
+                    role: "assistant".to_string(),
+                    content: Some(OpenAIContent::Text(r#"{"summary":"ok"}"#.to_string())),
+                    name: None,
+                    tool_calls: None,
+                    tool_call_id: None,
+                },
+                logprobs: None,
+                finish_reason: Some("stop".to_string()),
+            }],
+            usage: None,
+            system_fingerprint: None,
+            search_results: None,
+            search_queries: None,
+        };
+
+        restore_tool_call(&mut response, "record_summary");
+
+        let choice = &response.choices[0];
+        assert!(choice.message.content.is_none());
+        assert_eq!(choice.finish_reason.as_deref(), Some("tool_calls"));
+        let tool_call = &choice.message.tool_calls.as_ref().unwrap()[0];
+        assert_eq!(tool_call.function.name.as_deref(), Some("record_summary"));
+        assert_eq!(tool_call.function.arguments.as_deref(), Some(r#"{"summary":"ok"}"#));
+    }
+
+    #[tokio::test]
+    async fn test_restore_tool_call_stream() {
+        let chunk = |content: Option<&str>, finish_reason: Option<&str>| OpenAIStreamResponse {
+            id: "chatcmpl-1".to_string(),
+            object: "chat.completion.chunk".to_string(),
+            created: 0,
+            model: "gpt-4o".to_string(),
+            system_fingerprint: None,
+            search_results: None,
+            search_queries: None,
+            choices: vec![OpenAIStreamChoice {
+                index: 0,
+                delta: OpenAIStreamDelta {
+                    role: None,
+                    content: content.map(|c| c.to_string()),
+                    tool_calls: None,
+                },
+                logprobs: None,
+                finish_reason: finish_reason.map(|f| f.to_string()),
+            }],
+        };
+
+        let upstream: BoxStream<'static, OpenAIStreamResponse> = Box::pin(futures::stream::iter(vec![
+            Ok(chunk(Some(r#"{"summary":"#), None)),
+            Ok(chunk(Some(r#""ok"}"#), None)),
+            Ok(chunk(None, Some("stop"))),
+        ]));
+
+        let chunks: Vec<_> = restore_tool_call_stream(upstream, "record_summary".to_string())
+            .map(|c| c.unwrap())
+            .collect()
+            .await;
+
+        let first = &chunks[0].choices[0].delta;
+        assert!(first.content.is_none());
+        let first_call = &first.tool_calls.as_ref().unwrap()[0];
+        assert_eq!(first_call.function.name.as_deref(), Some("record_summary"));
+        assert!(first_call.id.is_some());
+
+        let second_call = &chunks[1].choices[0].delta.tool_calls.as_ref().unwrap()[0];
+        assert!(second_call.function.name.is_none());
+        assert_eq!(second_call.function.arguments.as_deref(), Some(r#""ok"}"#));
+
+        assert_eq!(chunks[2].choices[0].finish_reason.as_deref(), Some("tool_calls"));
+        assert!(chunks[2].choices[0].delta.tool_calls.is_none());
+
+        // Nothing streamed, no tool call
+        let upstream: BoxStream<'static, OpenAIStreamResponse> = Box::pin(futures::stream::iter(vec![
+            Ok(chunk(Some(""), None)),
+            Ok(chunk(None, Some("stop"))),
+        ]));
+        let chunks: Vec<_> = restore_tool_call_stream(upstream, "record_summary".to_string())
+            .map(|c| c.unwrap())
+            .collect()
+            .await;
+        assert!(chunks.iter().all(|c| c.choices[0].delta.tool_calls.is_none()));
+        assert_eq!(chunks[1].choices[0].finish_reason.as_deref(), Some("stop"));
+    }
+}
@@ -0,0 +1,490 @@
+//! Per-key limits and usage of multi-tenant client keys
+//!
+//! Each proxy-issued key can restrict the Claude models it may request, cap
+//! `max_tokens`, and limit its requests per minute and tokens per day. The
+//! [`TenantLimiter`] checks requests against the key's limits before they are
+//! routed and records their usage, tagged with the key's tenant.
+//!
+//! Keys and tenants can also have daily and monthly budgets. The cost of each
+//! request is estimated from the `pricing` table; once a budget is spent,
+//! requests are rejected until the day or month (UTC) is over.
+
+use crate::config::Budget;
+use crate::middleware::auth::ClientKey;
+use crate::models::claude::ClaudeRequest;
+use axum::http::StatusCode;
+use chrono::{Datelike, NaiveDate};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tracing::debug;
+
+/// Window of the requests-per-minute limit
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// Request rejected by its key's limits
+#[derive(Debug, Error, PartialEq)]
+pub enum LimitError {
+    #[error("API key '{key}' is not allowed to use model '{model}'")]
+    ModelNotAllowed { key: String, model: String },
+    
+    #[error("max_tokens {requested} exceeds the limit of {limit} for API key '{key}'")]
+    MaxTokensExceeded { key: String, requested: u32, limit: u32 },
+    
+    #[error("API key '{key}' exceeded its limit of {limit} requests per minute")]
+    RateLimited { key: String, limit: u32, retry_after: Duration },
+    
+    #[error("API key '{key}' used its daily budget of {limit} tokens")]
+    TokenBudgetExhausted { key: String, limit: u64 },
+    
+    #[error("The {period} budget of ${limit:.2} for {owner} is used up")]
+    BudgetExceeded { owner: String, period: &'static str, limit: f64 },
+}
+
+impl LimitError {
+    /// Claude error type
+    pub fn error_type(&self) -> &'static str {
+        match self {
+            LimitError::ModelNotAllowed { .. } => "permission_error",
+            LimitError::MaxTokensExceeded { .. } => "invalid_request_error",
+            LimitError::RateLimited { .. } | LimitError::TokenBudgetExhausted { .. } => "rate_limit_error",
+            LimitError::BudgetExceeded { .. } => "billing_error",
+        }
+    }
+    
+    /// HTTP status code
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            LimitError::ModelNotAllowed { .. } => StatusCode::FORBIDDEN,
+            LimitError::MaxTokensExceeded { .. } => StatusCode::BAD_REQUEST,
+            LimitError::RateLimited { .. } | LimitError::TokenBudgetExhausted { .. } => StatusCode::TOO_MANY_REQUESTS,
+            LimitError::BudgetExceeded { .. } => StatusCode::PAYMENT_REQUIRED,
+        }
+    }
+    
+    /// Seconds until the request may succeed, for the `retry-after` header
+    pub fn retry_after_secs(&self) -> Option<u64> {
+        match self {
+            LimitError::RateLimited { retry_after, .. } => Some(retry_after.as_secs().max(1)),
+            _ => None,
+        }
+    }
+}
+
+/// Estimated spend in USD of the current day and month (UTC)
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct Spend {
+    /// Spend of the current day
+    pub today: f64,
+    /// Spend of the current month
+    pub month: f64,
+    /// Day the spend was last updated
+    #[serde(skip)]
+    day: Option<NaiveDate>,
+}
+
+impl Spend {
+    /// Reset the totals of a past day or month
+    fn roll(&mut self, today: NaiveDate) {
+        let Some(day) = self.day.replace(today) else {
+            return;
+        };
+        if day != today {
+            self.today = 0.0;
+        }
+        if (day.year(), day.month()) != (today.year(), today.month()) {
+            self.month = 0.0;
+        }
+    }
+    
+    fn add(&mut self, cost: f64) {
+        self.today += cost;
+        self.month += cost;
+    }
+    
+    /// First budget period that is used up, with its limit
+    fn exceeded(&self, budget: &Budget) -> Option<(&'static str, f64)> {
+        [("daily", budget.daily, self.today), ("monthly", budget.monthly, self.month)]
+            .into_iter()
+            .find_map(|(period, limit, spent)| limit.filter(|limit| spent >= *limit).map(|limit| (period, limit)))
+    }
+}
+
+/// Usage of one key
+#[derive(Debug, Clone, Default)]
+pub struct KeyUsage {
+    /// Tenant of the key
+    pub tenant: String,
+    /// Requests made
+    pub requests: u64,
+    /// Input tokens used by all requests
+    pub input_tokens: u64,
+    /// Output tokens used by all requests
+    pub output_tokens: u64,
+    /// Input and output tokens used today (UTC)
+    pub tokens_today: u64,
+    /// Estimated spend
+    pub spend: Spend,
+    /// Start times of the requests in the rate window
+    recent: VecDeque<Instant>,
+}
+
+impl KeyUsage {
+    /// Reset the daily and monthly totals when the day changed
+    fn roll(&mut self, today: NaiveDate) {
+        if self.spend.day.is_some_and(|day| day != today) {
+            self.tokens_today = 0;
+        }
+        self.spend.roll(today);
+    }
+}
+
+/// Usage of a key as kept by the usage store, to restore after a restart
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct UsageTotals {
+    pub requests: u64,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    /// Input and output tokens used today (UTC)
+    pub tokens_today: u64,
+    /// Spend in USD of the current day
+    pub spend_today: f64,
+    /// Spend in USD of the current month
+    pub spend_month: f64,
+}
+
+/// Remaining amount of one budget period
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct BudgetStatus {
+    /// Budget in USD
+    pub limit: f64,
+    /// Budget left in USD
+    pub remaining: f64,
+}
+
+/// Spend and remaining budgets of a key or tenant
+#[derive(Debug, Clone, Serialize)]
+pub struct SpendReport {
+    pub spend: Spend,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub daily_budget: Option<BudgetStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub monthly_budget: Option<BudgetStatus>,
+}
+
+impl SpendReport {
+    fn new(spend: Spend, budget: &Budget) -> Self {
+        let status = |limit: Option<f64>, spent: f64| limit.map(|limit| BudgetStatus { limit, remaining: (limit - spent).max(0.0) });
+        Self {
+            daily_budget: status(budget.daily, spend.today),
+            monthly_budget: status(budget.monthly, spend.month),
+            spend,
+        }
+    }
+}
+
+/// Usage of a key as shown by `GET /usage`
+#[derive(Debug, Clone, Serialize)]
+pub struct UsageReport {
+    pub key: String,
+    pub tenant: String,
+    pub requests: u64,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub tokens_today: u64,
+    #[serde(flatten)]
+    pub key_spend: SpendReport,
+    /// Spend of all keys of the tenant, against the tenant's budget
+    pub tenant_spend: SpendReport,
+}
+
+#[derive(Debug, Default)]
+struct LimiterState {
+    keys: HashMap<String, KeyUsage>,
+    tenants: HashMap<String, Spend>,
+}
+
+impl LimiterState {
+    /// Usage of a key, rolled over to the current day
+    fn key(&mut self, client: &ClientKey, today: NaiveDate) -> &mut KeyUsage {
+        let usage = self.keys.entry(client.name.clone())
+            .or_insert_with(|| KeyUsage { tenant: client.tenant.clone(), ..Default::default() });
+        usage.roll(today);
+        usage
+    }
+    
+    /// Spend of a tenant, rolled over to the current day
+    fn tenant(&mut self, tenant: &str, today: NaiveDate) -> &mut Spend {
+        let spend = self.tenants.entry(tenant.to_string()).or_default();
+        spend.roll(today);
+        spend
+    }
+}
+
+/// Limit checks and usage by client key
+#[derive(Debug, Default)]
+pub struct TenantLimiter {
+    /// Budgets of tenants by name
+    tenant_budgets: HashMap<String, Budget>,
+    /// Shared with the limiters of reloaded configurations
+    state: Arc<Mutex<LimiterState>>,
+}
+
+impl TenantLimiter {
+    /// Create a limiter with the tenants' budgets and no usage
+    pub fn new(tenant_budgets: HashMap<String, Budget>) -> Self {
+        Self {
+            tenant_budgets,
+            state: Arc::default(),
+        }
+    }
+    
+    /// Continue counting the usage of another limiter, e.g. after a reload
+    pub fn with_usage_of(self, previous: &TenantLimiter) -> Self {
+        Self { state: previous.state.clone(), ..self }
+    }
+    
+    /// Check a request against its key's limits, counting it if it passes
+    pub fn check(&self, client: &ClientKey, request: &ClaudeRequest) -> Result<(), LimitError> {
+        let limits = &client.limits;
+        if !limits.allowed_models.is_empty() && !limits.allowed_models.iter().any(|pattern| model_matches(pattern, &request.model)) {
+            return Err(LimitError::ModelNotAllowed { key: client.name.clone(), model: request.model.clone() });
+        }
+        if let Some(limit) = limits.max_tokens.filter(|limit| request.max_tokens > *limit) {
+            return Err(LimitError::MaxTokensExceeded { key: client.name.clone(), requested: request.max_tokens, limit });
+        }
+        
+        let Ok(mut state) = self.state.lock() else {
+            return Ok(());
+        };
+        let today = today();
+        
+        if let Some(budget) = self.tenant_budgets.get(&client.tenant) {
+            if let Some((period, limit)) = state.tenant(&client.tenant, today).exceeded(budget) {
+                return Err(LimitError::BudgetExceeded { owner: format!("tenant '{}'", client.tenant), period, limit });
+            }
+        }
+        
+        let usage = state.key(client, today);
+        if let Some((period, limit)) = usage.spend.exceeded(&limits.budget) {
+            return Err(LimitError::BudgetExceeded { owner: format!("API key '{}'", client.name), period, limit });
+        }
+        if let Some(limit) = limits.tokens_per_day.filter(|limit| usage.tokens_today >= *limit) {
+            return Err(LimitError::TokenBudgetExhausted { key: client.name.clone(), limit });
+        }
+        
+        let now = Instant::now();
+        while usage.recent.front().is_some_and(|started| now.duration_since(*started) >= RATE_WINDOW) {
+            usage.recent.pop_front();
+        }
+        if let Some(limit) = limits.requests_per_minute.filter(|limit| usage.recent.len() >= *limit as usize) {
+            let retry_after = usage.recent.front()
+                .map(|oldest| RATE_WINDOW.saturating_sub(now.duration_since(*oldest)))
+                .unwrap_or(RATE_WINDOW);
+            return Err(LimitError::RateLimited { key: client.name.clone(), limit, retry_after });
+        }
+        
+        usage.recent.push_back(now);
+        usage.requests += 1;
+        Ok(())
+    }
+    
+    /// Record the token usage and estimated cost (USD) of a request made with a key
+    pub fn record_usage(&self, client: &ClientKey, input_tokens: u32, output_tokens: u32, cost: f64) {
+        if input_tokens == 0 && output_tokens == 0 {
+            return;
+        }
+        debug!(
+            "📊 Usage: tenant={}, key={}, input_tokens={}, output_tokens={}, cost=${:.6}",
+            client.tenant, client.name, input_tokens, output_tokens, cost
+        );
+        
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        let today = today();
+        let usage = state.key(client, today);
+        usage.input_tokens += u64::from(input_tokens);
+        usage.output_tokens += u64::from(output_tokens);
+        usage.tokens_today += u64::from(input_tokens) + u64::from(output_tokens);
+        usage.spend.add(cost);
+        state.tenant(&client.tenant, today).add(cost);
+    }
+    
+    /// Set the usage of a key, e.g. from the usage store after a restart
+    pub fn restore_key(&self, key_name: &str, tenant: &str, totals: UsageTotals) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        let spend = Spend { today: totals.spend_today, month: totals.spend_month, day: Some(today()) };
+        state.keys.insert(key_name.to_string(), KeyUsage {
+            tenant: tenant.to_string(),
+            requests: totals.requests,
+            input_tokens: totals.input_tokens,
+            output_tokens: totals.output_tokens,
+            tokens_today: totals.tokens_today,
+            spend,
+            recent: VecDeque::new(),
+        });
+    }
+    
+    /// Set the spend of a tenant in USD, e.g. from the usage store after a restart
+    pub fn restore_tenant(&self, tenant: &str, spend_today: f64, spend_month: f64) {
+        if let Ok(mut state) = self.state.lock() {
+            state.tenants.insert(tenant.to_string(), Spend { today: spend_today, month: spend_month, day: Some(today()) });
+        }
+    }
+    
+    /// Snapshot of a key's usage
+    pub fn usage(&self, key_name: &str) -> Option<KeyUsage> {
+        let mut usage = self.state.lock().ok()?.keys.get(key_name).cloned()?;
+        usage.roll(today());
+        Some(usage)
+    }
+    
+    /// Usage and remaining budgets of a key and its tenant
+    pub fn report(&self, client: &ClientKey) -> UsageReport {
+        let today = today();
+        let (usage, tenant_spend) = match self.state.lock() {
+            Ok(mut state) => (state.key(client, today).clone(), *state.tenant(&client.tenant, today)),
+            Err(_) => (KeyUsage::default(), Spend::default()),
+        };
+        let tenant_budget = self.tenant_budgets.get(&client.tenant).cloned().unwrap_or_default();
+        
+        UsageReport {
+            key: client.name.clone(),
+            tenant: client.tenant.clone(),
+            requests: usage.requests,
+            input_tokens: usage.input_tokens,
+            output_tokens: usage.output_tokens,
+            tokens_today: usage.tokens_today,
+            key_spend: SpendReport::new(usage.spend, &client.limits.budget),
+            tenant_spend: SpendReport::new(tenant_spend, &tenant_budget),
+        }
+    }
+}
+
+/// Current day (UTC)
+fn today() -> NaiveDate {
+    chrono::Utc::now().date_naive()
+}
+
+/// Whether a model matches an `allowedModels` pattern
+fn model_matches(pattern: &str, model: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => model.starts_with(prefix),
+        None => pattern == model,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::KeyLimits;
+    
+    fn client(limits: KeyLimits) -> ClientKey {
+        ClientKey { name: "laptop".to_string(), tenant: "search".to_string(), priority: Default::default(), limits, admin: false }
+    }
+    
+    fn request(model: &str, max_tokens: u32) -> ClaudeRequest {
+        ClaudeRequest::builder().model(model).max_tokens(max_tokens).user("Hi").build()
+    }
+    
+    #[test]
+    fn test_model_and_max_tokens() {
+        let limiter = TenantLimiter::default();
+        let client = client(KeyLimits {
+            allowed_models: vec!["claude-3-5-haiku*".to_string(), "sonnet".to_string()],
+            max_tokens: Some(4096),
+            ..Default::default()
+        });
+        
+        assert!(limiter.check(&client, &request("claude-3-5-haiku-20241022", 1024)).is_ok());
+        assert!(limiter.check(&client, &request("sonnet", 4096)).is_ok());
+        
+        let error = limiter.check(&client, &request("claude-opus-4", 1024)).unwrap_err();
+        assert_eq!(error.error_type(), "permission_error");
+        let error = limiter.check(&client, &request("sonnet", 8192)).unwrap_err();
+        assert_eq!(error, LimitError::MaxTokensExceeded { key: "laptop".to_string(), requested: 8192, limit: 4096 });
+    }
+    
+    #[test]
+    fn test_rate_and_token_budget() {
+        let limiter = TenantLimiter::default();
+        let client = client(KeyLimits {
+            requests_per_minute: Some(2),
+            tokens_per_day: Some(100),
+            ..Default::default()
+        });
+        
+        assert!(limiter.check(&client, &request("sonnet", 1024)).is_ok());
+        assert!(limiter.check(&client, &request("sonnet", 1024)).is_ok());
+        let error = limiter.check(&client, &request("sonnet", 1024)).unwrap_err();
+        assert_eq!(error.status_code(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(error.retry_after_secs().is_some_and(|secs| secs <= 60));
+        
+        limiter.record_usage(&client, 80, 20, 0.0);
+        let usage = limiter.usage("laptop").unwrap();
+        assert_eq!(usage.tenant, "search");
+        assert_eq!(usage.requests, 2);
+        assert_eq!(usage.tokens_today, 100);
+        
+        let unlimited_rate = ClientKey { limits: KeyLimits { tokens_per_day: Some(100), ..Default::default() }, ..client };
+        assert!(matches!(
+            limiter.check(&unlimited_rate, &request("sonnet", 1024)),
+            Err(LimitError::TokenBudgetExhausted { limit: 100, .. })
+        ));
+    }
+    
+    #[test]
+    fn test_budgets() {
+        let limiter = TenantLimiter::new(HashMap::from([
+            ("search".to_string(), Budget { daily: None, monthly: Some(10.0) }),
+        ]));
+        let client = client(KeyLimits {
+            budget: Budget { daily: Some(1.0), monthly: None },
+            ..Default::default()
+        });
+        let other = ClientKey { name: "ci".to_string(), ..client.clone() };
+        
+        assert!(limiter.check(&client, &request("sonnet", 1024)).is_ok());
+        limiter.record_usage(&client, 1000, 1000, 1.0);
+        let error = limiter.check(&client, &request("sonnet", 1024)).unwrap_err();
+        assert_eq!(error, LimitError::BudgetExceeded { owner: "API key 'laptop'".to_string(), period: "daily", limit: 1.0 });
+        assert_eq!(error.status_code(), StatusCode::PAYMENT_REQUIRED);
+        assert_eq!(error.to_string(), "The daily budget of $1.00 for API key 'laptop' is used up");
+        
+        // Keys of a tenant share its budget
+        assert!(limiter.check(&other, &request("sonnet", 1024)).is_ok());
+        limiter.record_usage(&other, 1000, 1000, 9.5);
+        assert!(matches!(
+            limiter.check(&other, &request("sonnet", 1024)),
+            Err(LimitError::BudgetExceeded { period: "monthly", .. })
+        ));
+        
+        let report = limiter.report(&client);
+        assert_eq!(report.key_spend.spend.today, 1.0);
+        assert_eq!(report.key_spend.daily_budget.unwrap().remaining, 0.0);
+        assert_eq!(report.tenant_spend.spend.month, 10.5);
+        assert_eq!(report.tenant_spend.monthly_budget.unwrap().remaining, 0.0);
+    }
+    
+    #[test]
+    fn test_spend_roll() {
+        let day = |d: u32, m: u32| NaiveDate::from_ymd_opt(2025, m, d).unwrap();
+        let mut spend = Spend::default();
+        spend.roll(day(30, 6));
+        spend.add(2.0);
+        spend.roll(day(30, 6));
+        spend.add(1.0);
+        assert_eq!((spend.today, spend.month), (3.0, 3.0));
+        
+        spend.roll(day(1, 6 + 1));
+        assert_eq!((spend.today, spend.month), (0.0, 0.0));
+        spend.add(1.0);
+        spend.roll(day(2, 7));
+        assert_eq!((spend.today, spend.month), (0.0, 1.0));
+    }
+}
@@ -0,0 +1,270 @@
+//! Token counting
+//!
+//! [`TokenCounter`] estimates token counts for converted requests and provider
+//! responses. OpenAI-family models are counted with their tiktoken encoding;
+//! other models use a character-based heuristic. The estimates back the
+//! `count_tokens` endpoint, context window checks and usage for upstreams that
+//! don't report it.
+
+use crate::models::openai::*;
+use once_cell::sync::Lazy;
+use tiktoken_rs::CoreBPE;
+use tracing::warn;
+
+/// Fixed overhead per message (role and separators), as in OpenAI's cookbook
+const TOKENS_PER_MESSAGE: u32 = 3;
+
+/// Overhead of the reply priming tokens
+const TOKENS_PER_REPLY: u32 = 3;
+
+/// Estimate for one image (a high-detail 1024x1024 image in OpenAI's accounting)
+const TOKENS_PER_IMAGE: u32 = 765;
+
+static CL100K_BASE: Lazy<Option<CoreBPE>> = Lazy::new(|| load_encoding("cl100k_base", tiktoken_rs::cl100k_base));
+static O200K_BASE: Lazy<Option<CoreBPE>> = Lazy::new(|| load_encoding("o200k_base", tiktoken_rs::o200k_base));
+
+fn load_encoding(name: &str, load: fn() -> anyhow::Result<CoreBPE>) -> Option<CoreBPE> {
+    load()
+        .map_err(|e| warn!("Failed to load {} encoding, using heuristic token counts: {}", name, e))
+        .ok()
+}
+
+/// Tokenizer used for a model
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tokenizer {
+    /// GPT-4o, GPT-4.1, GPT-5 and o-series models
+    O200kBase,
+    /// GPT-4 and GPT-3.5 models
+    Cl100kBase,
+    /// Character-based estimate for non-OpenAI models
+    Heuristic,
+}
+
+/// Token counter for a single upstream model
+#[derive(Debug, Clone, Copy)]
+pub struct TokenCounter {
+    tokenizer: Tokenizer,
+}
+
+impl TokenCounter {
+    /// Create a counter for an upstream model name
+    pub fn for_model(model: &str) -> Self {
+        Self { tokenizer: Self::tokenizer_for(model) }
+    }
+    
+    /// Pick the tokenizer for an upstream model name
+    pub fn tokenizer_for(model: &str) -> Tokenizer {
+        // Strip a "provider/" prefix and any vendor namespace ("openai/gpt-4o")
+        let model = model.rsplit('/').next().unwrap_or(model).to_lowercase();
+        
+        if model.starts_with("gpt-4o")
+            || model.starts_with("gpt-4.1")
+            || model.starts_with("gpt-4.5")
+            || model.starts_with("gpt-5")
+            || model.starts_with("chatgpt-4o")
+            || model.starts_with("o1")
+            || model.starts_with("o3")
+            || model.starts_with("o4")
+        {
+            Tokenizer::O200kBase
+        } else if model.starts_with("gpt-4") || model.starts_with("gpt-3.5") || model.starts_with("text-embedding") {
+            Tokenizer::Cl100kBase
+        } else {
+            Tokenizer::Heuristic
+        }
+    }
+    
+    /// Tokenizer used by this counter
+    pub fn tokenizer(&self) -> Tokenizer {
+        self.tokenizer
+    }
+    
+    /// Count the tokens in a piece of text
+    pub fn count_text(&self, text: &str) -> u32 {
+        if text.is_empty() {
+            return 0;
+        }
+        
+        let encoding = match self.tokenizer {
+            Tokenizer::O200kBase => O200K_BASE.as_ref(),
+            Tokenizer::Cl100kBase => CL100K_BASE.as_ref(),
+            Tokenizer::Heuristic => None,
+        };
+        
+        match encoding {
+            Some(bpe) => bpe.encode_ordinary(text).len() as u32,
+            None => estimate_tokens(text),
+        }
+    }
+    
+    /// Count the prompt tokens of a converted request (messages and tool definitions)
+    pub fn count_request(&self, request: &OpenAIRequest) -> u32 {
+        let messages: u32 = request.messages.iter()
+            .map(|message| self.count_message(message))
+            .sum();
+        
+        let tools: u32 = request.tools.iter()
+            .flatten()
+            .map(|tool| {
+                let parameters = tool.function.parameters.as_ref()
+                    .map(|p| p.to_string())
+                    .unwrap_or_default();
+                self.count_text(&tool.function.name)
+                    + self.count_text(tool.function.description.as_deref().unwrap_or(""))
+                    + self.count_text(&parameters)
+            })
+            .sum();
+        
+        messages + tools + TOKENS_PER_REPLY
+    }
+    
+    /// Count the completion tokens of a response (first choice)
+    pub fn count_response(&self, response: &OpenAIResponse) -> u32 {
+        response.choices.first()
+            .map(|choice| self.count_completion(&choice.message))
+            .unwrap_or(0)
+    }
+    
+    /// Count the completion tokens of a generated message (without message overhead)
+    pub fn count_completion(&self, message: &OpenAIMessage) -> u32 {
+        self.count_message(message).saturating_sub(TOKENS_PER_MESSAGE)
+    }
+    
+    /// Estimate usage for a response whose upstream didn't report it
+    pub fn estimate_usage(&self, request: &OpenAIRequest, response: &OpenAIResponse) -> OpenAIUsage {
+        let prompt_tokens = self.count_request(request);
+        let completion_tokens = self.count_response(response);
+        OpenAIUsage {
+            prompt_tokens,
+            completion_tokens,
+            total_tokens: prompt_tokens + completion_tokens,
+            prompt_tokens_details: None,
+        }
+    }
+    
+    /// Count the tokens of a single message, including its overhead
+    pub fn count_message(&self, message: &OpenAIMessage) -> u32 {
+        let content = match &message.content {
+            Some(OpenAIContent::Text(text)) => self.count_text(text),
+            Some(OpenAIContent::Array(parts)) => parts.iter()
+                .map(|part| match part {
+                    OpenAIContentPart::Text { text, .. } => self.count_text(text),
+                    OpenAIContentPart::ImageUrl { .. } => TOKENS_PER_IMAGE,
+                })
+                .sum(),
+            None => 0,
+        };
+        
+        let tool_calls: u32 = message.tool_calls.iter()
+            .flatten()
+            .map(|tool_call| {
+                self.count_text(tool_call.function.name.as_deref().unwrap_or(""))
+                    + self.count_text(tool_call.function.arguments.as_deref().unwrap_or(""))
+            })
+            .sum();
+        
+        TOKENS_PER_MESSAGE + content + tool_calls
+    }
+}
+
+/// Heuristic token estimate
+///
+/// Roughly four ASCII characters per token; other characters (CJK in
+/// particular) are counted as one token each.
+fn estimate_tokens(text: &str) -> u32 {
+    let (ascii, other) = text.chars().fold((0u32, 0u32), |(ascii, other), c| {
+        if c.is_ascii() { (ascii + 1, other) } else { (ascii, other + 1) }
+    });
+    ascii.div_ceil(4) + other
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn message(role: &str, text: &str) -> OpenAIMessage {
+        OpenAIMessage {
+            role: role.to_string(),
+            content: Some(OpenAIContent::Text(text.to_string())),
+            name: None,
+            tool_calls: None,
+            tool_call_id: None,
+        }
+    }
+    
+    #[test]
+    fn test_tokenizer_for() {
+        assert_eq!(TokenCounter::tokenizer_for("gpt-4o-mini"), Tokenizer::O200kBase);
+        assert_eq!(TokenCounter::tokenizer_for("openai/gpt-5"), Tokenizer::O200kBase);
+        assert_eq!(TokenCounter::tokenizer_for("o3-mini"), Tokenizer::O200kBase);
+        assert_eq!(TokenCounter::tokenizer_for("gpt-4-turbo"), Tokenizer::Cl100kBase);
+        assert_eq!(TokenCounter::tokenizer_for("gpt-3.5-turbo"), Tokenizer::Cl100kBase);
+        assert_eq!(TokenCounter::tokenizer_for("glm-4.6"), Tokenizer::Heuristic);
+        assert_eq!(TokenCounter::tokenizer_for("gemini-2.5-pro"), Tokenizer::Heuristic);
+    }
+    
+    #[test]
+    fn test_count_text() {
+        let counter = TokenCounter::for_model("gpt-4o");
+        assert_eq!(counter.count_text(""), 0);
+        assert_eq!(counter.count_text("Hello, world!"), 4);
+        
+        let counter = TokenCounter::for_model("gpt-4");
+        assert_eq!(counter.count_text("Hello, world!"), 4);
+        
+        let counter = TokenCounter::for_model("glm-4.6");
+        assert_eq!(counter.count_text("Hello, world!"), 4);
+        assert_eq!(counter.count_text("你好"), 2);
+    }
+    
+    #[test]
+    fn test_count_request_and_usage() {
+        let counter = TokenCounter::for_model("gpt-4o");
+        let request = OpenAIRequest {
+            model: "gpt-4o".to_string(),
+            messages: vec![message("system", "You are helpful."), message("user", "Hello, world!")],
+            max_tokens: Some(100),
+            temperature: None,
+            top_p: None,
+            n: None,
+            stop: None,
+            stream: None,
+            presence_penalty: None,
+            frequency_penalty: None,
+            logit_bias: None,
+            user: None,
+            response_format: None,
+            seed: None,
+            tools: None,
+            tool_choice: None,
+            session_id: None,
+            request_id: None,
+            forwarded_headers: Vec::new(),
+            betas: Vec::new(),
+            extensions: Default::default(),
+        };
+        let prompt_tokens = counter.count_request(&request);
+        assert_eq!(prompt_tokens, 3 + 4 + 3 + 4 + 3);
+        
+        let response = OpenAIResponse {
+            id: "chatcmpl-1".to_string(),
+            object: "chat.completion".to_string(),
+            created: 0,
+            model: "gpt-4o".to_string(),
+            choices: vec![OpenAIChoice {
+                index: 0,
+                message: message("assistant", "Hello, world!"),
+                logprobs: None,
+                finish_reason: Some("stop".to_string()),
+            }],
+            usage: None,
+            system_fingerprint: None,
+            search_results: None,
+            search_queries: None,
+        };
+        let usage = counter.estimate_usage(&request, &response);
+        assert_eq!(usage.prompt_tokens, prompt_tokens);
+        assert_eq!(usage.completion_tokens, 4);
+        assert_eq!(usage.total_tokens, prompt_tokens + 4);
+    }
+}
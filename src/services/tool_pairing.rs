@@ -0,0 +1,312 @@
+//! Pairing of `tool_use` and `tool_result` blocks
+//!
+//! The Anthropic API requires every `tool_result` to answer a `tool_use` of the
+//! assistant message right before it, and every `tool_use` to be answered in
+//! the next message. Claude Code doesn't always keep to this: a tool call the
+//! user interrupted is followed by a plain user message, and compaction can
+//! leave results whose call is gone. OpenAI-style upstreams reject both, so
+//! before conversion the `toolPairing` strategy decides what happens to
+//! blocks that don't pair up:
+//!
+//! - `repair`: interrupted calls get an error result, results without a
+//!   call become text, and repeated results are dropped
+//! - `drop`: interrupted calls, results without a call and repeated results
+//!   are left out
+//! - `reject`: the request fails with the Anthropic API's
+//!   `invalid_request_error` message
+//! - `off` (default): the messages are sent as they are
+//!
+//! A tool call in the last message has no result yet and is left alone.
+//! Pairing runs after `strictValidation`, which rejects results without a
+//! call before they could be repaired, and before conversion, so Responses
+//! API providers only apply `orphanToolCalls` to calls it left in place.
+
+use crate::config::ToolPairing;
+use crate::models::claude::{ClaudeContent, ClaudeContentBlock, ClaudeRequest, ClaudeToolResultContent};
+use std::collections::HashSet;
+use std::fmt;
+
+/// Result given to interrupted tool calls by `repair`
+pub const INTERRUPTED_RESULT: &str = "Tool use was interrupted by the user";
+
+/// Text left in a message whose only blocks were dropped by `drop`
+const DROPPED_TEXT: &str = "(interrupted)";
+
+/// Block that didn't pair up, and was repaired or dropped
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Repair {
+    /// A tool call without a result in the next message
+    Interrupted(String),
+    /// A result without a call in the preceding assistant message
+    Orphan(String),
+    /// A second result for the same call
+    Duplicate(String),
+}
+
+impl fmt::Display for Repair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Repair::Interrupted(id) => write!(f, "interrupted tool call {}", id),
+            Repair::Orphan(id) => write!(f, "tool result {} without a call", id),
+            Repair::Duplicate(id) => write!(f, "repeated tool result {}", id),
+        }
+    }
+}
+
+/// Pair the tool calls and results of a request according to `strategy`
+///
+/// Returns the blocks that were repaired or dropped, or with `reject` the
+/// Claude `invalid_request_error` message of the first that doesn't pair up.
+pub fn apply(request: &mut ClaudeRequest, strategy: ToolPairing) -> Result<Vec<Repair>, String> {
+    let mut repairs = Vec::new();
+    if strategy == ToolPairing::Off {
+        return Ok(repairs);
+    }
+    
+    for i in 0..request.messages.len() {
+        if request.messages[i].role != "user" {
+            continue;
+        }
+        let (previous, rest) = request.messages.split_at_mut(i);
+        let message = &mut rest[0];
+        let calls = match previous.last() {
+            Some(assistant) if assistant.role == "assistant" => tool_use_ids(&assistant.content),
+            _ => Vec::new(),
+        };
+        
+        let before = repairs.len();
+        let mut answered = HashSet::new();
+        if let ClaudeContent::Blocks(blocks) = &mut message.content {
+            let mut paired = Vec::with_capacity(blocks.len());
+            for (j, block) in std::mem::take(blocks).into_iter().enumerate() {
+                let ClaudeContentBlock::ToolResult { tool_use_id, content, .. } = &block else {
+                    paired.push(block);
+                    continue;
+                };
+                if !calls.contains(tool_use_id) {
+                    if strategy == ToolPairing::Reject {
+                        return Err(format!(
+                            "messages.{}.content.{}: unexpected `tool_use_id` found in `tool_result` blocks: {}. Each `tool_result` block must have a corresponding `tool_use` block in the previous message.",
+                            i, j, tool_use_id
+                        ));
+                    }
+                    if strategy == ToolPairing::Repair {
+                        paired.extend(result_as_text(tool_use_id, content));
+                    }
+                    repairs.push(Repair::Orphan(tool_use_id.clone()));
+                } else if !answered.insert(tool_use_id.clone()) {
+                    if strategy == ToolPairing::Reject {
+                        return Err(format!(
+                            "messages.{}.content.{}: each `tool_use` must have a single result. Found multiple `tool_result` blocks with id: {}",
+                            i, j, tool_use_id
+                        ));
+                    }
+                    repairs.push(Repair::Duplicate(tool_use_id.clone()));
+                } else {
+                    paired.push(block);
+                }
+            }
+            *blocks = paired;
+        }
+        
+        let interrupted: Vec<String> = calls.into_iter().filter(|id| !answered.contains(id)).collect();
+        if interrupted.is_empty() {
+            if repairs.len() > before {
+                fill_if_empty(&mut message.content);
+            }
+            continue;
+        }
+        match strategy {
+            ToolPairing::Reject => {
+                return Err(format!(
+                    "messages.{}: `tool_use` ids were found without `tool_result` blocks immediately after: {}. Each `tool_use` block must have a corresponding `tool_result` block in the next message.",
+                    i - 1,
+                    interrupted.join(", ")
+                ));
+            }
+            ToolPairing::Repair => {
+                // Results come before any other block of the message
+                let mut blocks: Vec<ClaudeContentBlock> = interrupted.iter()
+                    .map(|id| ClaudeContentBlock::ToolResult {
+                        tool_use_id: id.clone(),
+                        content: ClaudeToolResultContent::Text(INTERRUPTED_RESULT.to_string()),
+                        is_error: Some(true),
+                    })
+                    .collect();
+                match std::mem::replace(&mut message.content, ClaudeContent::Blocks(Vec::new())) {
+                    ClaudeContent::Blocks(rest) => blocks.extend(rest),
+                    ClaudeContent::Text(text) if !text.is_empty() => blocks.push(ClaudeContentBlock::Text { text }),
+                    _ => {}
+                }
+                message.content = ClaudeContent::Blocks(blocks);
+            }
+            ToolPairing::Drop | ToolPairing::Off => {
+                let assistant = previous.last_mut().expect("tool calls come from the previous message");
+                if let ClaudeContent::Blocks(blocks) = &mut assistant.content {
+                    blocks.retain(|block| !matches!(block, ClaudeContentBlock::ToolUse { id, .. } if interrupted.contains(id)));
+                }
+                fill_if_empty(&mut assistant.content);
+                fill_if_empty(&mut message.content);
+            }
+        }
+        repairs.extend(interrupted.into_iter().map(Repair::Interrupted));
+    }
+    
+    Ok(repairs)
+}
+
+/// IDs of the tool calls of a message, in order
+fn tool_use_ids(content: &ClaudeContent) -> Vec<String> {
+    let ClaudeContent::Blocks(blocks) = content else {
+        return Vec::new();
+    };
+    blocks.iter()
+        .filter_map(|block| match block {
+            ClaudeContentBlock::ToolUse { id, .. } => Some(id.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Blocks keeping the content of a result without a call
+fn result_as_text(tool_use_id: &str, content: &ClaudeToolResultContent) -> Vec<ClaudeContentBlock> {
+    let mut blocks = vec![ClaudeContentBlock::Text { text: format!("Result of tool call {}:", tool_use_id) }];
+    match content {
+        ClaudeToolResultContent::Text(text) if !text.is_empty() => blocks.push(ClaudeContentBlock::Text { text: text.clone() }),
+        ClaudeToolResultContent::Text(_) => {}
+        ClaudeToolResultContent::Blocks(content) => blocks.extend(content.iter().cloned()),
+    }
+    blocks
+}
+
+/// Keep a message whose blocks were all dropped from being empty
+fn fill_if_empty(content: &mut ClaudeContent) {
+    if matches!(content, ClaudeContent::Blocks(blocks) if blocks.is_empty()) {
+        *content = ClaudeContent::Blocks(vec![ClaudeContentBlock::Text { text: DROPPED_TEXT.to_string() }]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn tool_use(id: &str) -> ClaudeContentBlock {
+        ClaudeContentBlock::ToolUse { id: id.to_string(), name: "read".to_string(), input: serde_json::json!({}), thought_signature: None }
+    }
+    
+    fn tool_result(id: &str, text: &str) -> ClaudeContentBlock {
+        ClaudeContentBlock::ToolResult { tool_use_id: id.to_string(), content: ClaudeToolResultContent::Text(text.to_string()), is_error: None }
+    }
+    
+    fn text(text: &str) -> ClaudeContentBlock {
+        ClaudeContentBlock::Text { text: text.to_string() }
+    }
+    
+    fn blocks(request: &ClaudeRequest, i: usize) -> serde_json::Value {
+        serde_json::to_value(&request.messages[i].content).unwrap()
+    }
+    
+    /// Two calls of which the user interrupted the second, then a result
+    /// whose call was compacted away
+    fn interrupted() -> ClaudeRequest {
+        ClaudeRequest::builder()
+            .user("Read both files")
+            .message("assistant", ClaudeContent::Blocks(vec![text("Reading."), tool_use("toolu_1"), tool_use("toolu_2")]))
+            .message("user", ClaudeContent::Blocks(vec![tool_result("toolu_1", "fn main() {}"), text("Stop, read a.rs instead")]))
+            .assistant("OK")
+            .message("user", ClaudeContent::Blocks(vec![tool_result("toolu_0", "old output"), text("Go on")]))
+            .build()
+    }
+    
+    #[test]
+    fn test_paired_request_unchanged() {
+        let mut request = ClaudeRequest::builder()
+            .user("Read the file")
+            .message("assistant", ClaudeContent::Blocks(vec![tool_use("toolu_1")]))
+            .message("user", ClaudeContent::Blocks(vec![tool_result("toolu_1", "fn main() {}")]))
+            .message("assistant", ClaudeContent::Blocks(vec![tool_use("toolu_2")]))
+            .build();
+        let before = serde_json::to_value(&request.messages).unwrap();
+        for strategy in [ToolPairing::Repair, ToolPairing::Drop, ToolPairing::Reject] {
+            assert!(apply(&mut request, strategy).unwrap().is_empty());
+        }
+        assert_eq!(serde_json::to_value(&request.messages).unwrap(), before);
+    }
+    
+    #[test]
+    fn test_repair() {
+        let mut request = interrupted();
+        let repairs = apply(&mut request, ToolPairing::Repair).unwrap();
+        assert_eq!(repairs, vec![Repair::Interrupted("toolu_2".to_string()), Repair::Orphan("toolu_0".to_string())]);
+        assert_eq!(blocks(&request, 2), serde_json::json!([
+            {"type": "tool_result", "tool_use_id": "toolu_2", "content": INTERRUPTED_RESULT, "is_error": true},
+            {"type": "tool_result", "tool_use_id": "toolu_1", "content": "fn main() {}"},
+            {"type": "text", "text": "Stop, read a.rs instead"}
+        ]));
+        assert_eq!(blocks(&request, 4), serde_json::json!([
+            {"type": "text", "text": "Result of tool call toolu_0:"},
+            {"type": "text", "text": "old output"},
+            {"type": "text", "text": "Go on"}
+        ]));
+        
+        // A plain user message after the calls
+        let mut request = ClaudeRequest::builder()
+            .user("Read the file")
+            .message("assistant", ClaudeContent::Blocks(vec![tool_use("toolu_1")]))
+            .user("[Request interrupted by user for tool use]")
+            .build();
+        apply(&mut request, ToolPairing::Repair).unwrap();
+        assert_eq!(blocks(&request, 2), serde_json::json!([
+            {"type": "tool_result", "tool_use_id": "toolu_1", "content": INTERRUPTED_RESULT, "is_error": true},
+            {"type": "text", "text": "[Request interrupted by user for tool use]"}
+        ]));
+        
+        // Repeated results
+        let mut request = ClaudeRequest::builder()
+            .user("Read the file")
+            .message("assistant", ClaudeContent::Blocks(vec![tool_use("toolu_1")]))
+            .message("user", ClaudeContent::Blocks(vec![tool_result("toolu_1", "a"), tool_result("toolu_1", "b")]))
+            .build();
+        assert_eq!(apply(&mut request, ToolPairing::Repair).unwrap(), vec![Repair::Duplicate("toolu_1".to_string())]);
+        assert_eq!(blocks(&request, 2), serde_json::json!([{"type": "tool_result", "tool_use_id": "toolu_1", "content": "a"}]));
+    }
+    
+    #[test]
+    fn test_drop() {
+        let mut request = interrupted();
+        assert_eq!(apply(&mut request, ToolPairing::Drop).unwrap().len(), 2);
+        assert_eq!(blocks(&request, 1), serde_json::json!([
+            {"type": "text", "text": "Reading."},
+            {"type": "tool_use", "id": "toolu_1", "name": "read", "input": {}}
+        ]));
+        assert_eq!(blocks(&request, 4), serde_json::json!([{"type": "text", "text": "Go on"}]));
+        
+        // Messages left without blocks keep a placeholder
+        let mut request = ClaudeRequest::builder()
+            .user("Read the file")
+            .message("assistant", ClaudeContent::Blocks(vec![tool_use("toolu_1")]))
+            .message("user", ClaudeContent::Blocks(vec![tool_result("toolu_0", "old output")]))
+            .build();
+        apply(&mut request, ToolPairing::Drop).unwrap();
+        assert_eq!(blocks(&request, 1), serde_json::json!([{"type": "text", "text": DROPPED_TEXT}]));
+        assert_eq!(blocks(&request, 2), serde_json::json!([{"type": "text", "text": DROPPED_TEXT}]));
+    }
+    
+    #[test]
+    fn test_reject() {
+        let error = apply(&mut interrupted(), ToolPairing::Reject).unwrap_err();
+        assert!(error.starts_with("messages.1: `tool_use` ids were found without `tool_result` blocks immediately after: toolu_2."), "{}", error);
+        
+        let mut request = ClaudeRequest::builder()
+            .user("Go on")
+            .assistant("OK")
+            .message("user", ClaudeContent::Blocks(vec![text("Here"), tool_result("toolu_0", "old output")]))
+            .build();
+        let error = apply(&mut request, ToolPairing::Reject).unwrap_err();
+        assert!(error.starts_with("messages.2.content.1: unexpected `tool_use_id` found in `tool_result` blocks: toolu_0."), "{}", error);
+        
+        let mut request = interrupted();
+        assert!(apply(&mut request, ToolPairing::Off).unwrap().is_empty());
+        assert_eq!(blocks(&request, 4), blocks(&interrupted(), 4));
+    }
+}
@@ -0,0 +1,356 @@
+//! Transcripts of Claude Code sessions
+//!
+//! With `transcripts`, every `/v1/messages` request of a session (identified
+//! by the session id in `metadata.user_id`) is saved as a turn: the system
+//! prompt and messages the client sent, in Claude format, and the response
+//! the proxy returned, also when it was streamed. Turns are appended to one
+//! JSON Lines file per session in `directory`, or, with the `sqlite` feature,
+//! to the `turns` table of `sqlitePath`.
+//!
+//! `GET /v1/sessions/:id/transcript` returns the turns of a session, for
+//! debugging agent behavior and compliance review. Transcripts hold the full
+//! conversation content, unredacted; nothing is ever deleted by the proxy.
+
+use crate::config::TranscriptsConfig;
+use crate::models::claude::{ClaudeContentBlock, ClaudeContentDelta, ClaudeMessage, ClaudeRequest, ClaudeResponse, ClaudeStreamEvent, SystemPrompt};
+use crate::services::request_log::RequestSummary;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tracing::warn;
+
+/// One request of a session and its response
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TranscriptTurn {
+    /// Time the request was received (RFC 3339)
+    pub timestamp: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    /// Claude model requested
+    pub model: String,
+    /// Provider/model path the request was routed to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub route: Option<String>,
+    #[serde(default)]
+    pub status: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system: Option<SystemPrompt>,
+    /// Conversation as the client sent it
+    pub messages: Vec<ClaudeMessage>,
+    /// Content of the response; streamed blocks are reassembled
+    #[serde(default)]
+    pub response: Vec<ClaudeContentBlock>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop_reason: Option<String>,
+}
+
+/// The turn of a request in progress, appended to the store by [`finish`](Self::finish)
+pub struct PendingTurn {
+    store: Arc<TranscriptStore>,
+    session_id: String,
+    turn: TranscriptTurn,
+    /// Tool arguments streamed so far, by block index
+    partial_json: HashMap<u32, String>,
+}
+
+impl std::fmt::Debug for PendingTurn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PendingTurn").field("session_id", &self.session_id).finish()
+    }
+}
+
+impl PendingTurn {
+    /// Start the turn of a request, or None if it isn't part of a session
+    pub fn start(store: Arc<TranscriptStore>, session_id: Option<&str>, request: &ClaudeRequest) -> Option<Self> {
+        let turn = TranscriptTurn {
+            system: request.system.clone(),
+            messages: request.messages.clone(),
+            ..Default::default()
+        };
+        Some(Self { store, session_id: session_id?.to_string(), turn, partial_json: HashMap::new() })
+    }
+    
+    /// Record a complete response
+    pub fn record_response(&mut self, response: &ClaudeResponse) {
+        self.turn.response = response.content.clone();
+    }
+    
+    /// Add an event sent to the client to the response
+    pub fn record_stream_event(&mut self, event: &ClaudeStreamEvent) {
+        let response = &mut self.turn.response;
+        match event {
+            ClaudeStreamEvent::ContentBlockStart { index, content_block } => {
+                response.push(content_block.clone());
+                self.partial_json.remove(index);
+            }
+            ClaudeStreamEvent::ContentBlockDelta { index, delta } => match delta {
+                ClaudeContentDelta::TextDelta { text: delta } => {
+                    if let Some(ClaudeContentBlock::Text { text }) = response.last_mut() {
+                        text.push_str(delta);
+                    }
+                }
+                ClaudeContentDelta::InputJsonDelta { partial_json } => {
+                    self.partial_json.entry(*index).or_default().push_str(partial_json);
+                }
+            },
+            ClaudeStreamEvent::ContentBlockStop { index } => {
+                let Some(json) = self.partial_json.remove(index) else { return };
+                if let Some(ClaudeContentBlock::ToolUse { input, .. } | ClaudeContentBlock::ServerToolUse { input, .. }) = response.last_mut() {
+                    // Arguments cut off by a dropped stream are kept as text
+                    *input = serde_json::from_str(&json).unwrap_or(serde_json::Value::String(json));
+                }
+            }
+            _ => {}
+        }
+    }
+    
+    /// Append the turn, with the route, status and stop reason of the request's summary
+    pub fn finish(mut self, summary: &RequestSummary) {
+        self.turn.timestamp = summary.timestamp.clone();
+        self.turn.request_id = summary.request_id.clone();
+        self.turn.key = summary.key.clone();
+        self.turn.model = summary.model.clone();
+        self.turn.route = summary.route.clone();
+        self.turn.status = summary.status;
+        self.turn.stop_reason = summary.stop_reason.clone();
+        if let Err(e) = self.store.append(&self.session_id, &self.turn) {
+            warn!("📜 Failed to save the transcript of session {}: {:#}", self.session_id, e);
+        }
+    }
+}
+
+/// Where the turns are kept
+enum Backend {
+    Directory(PathBuf),
+    #[cfg(feature = "sqlite")]
+    Sqlite(Mutex<rusqlite::Connection>),
+}
+
+/// Store of session transcripts
+pub struct TranscriptStore {
+    config: TranscriptsConfig,
+    backend: Backend,
+    /// Serializes appends to the session files
+    write_lock: Mutex<()>,
+}
+
+impl std::fmt::Debug for TranscriptStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TranscriptStore").field("config", &self.config).finish()
+    }
+}
+
+impl TranscriptStore {
+    /// Open the store, creating its directory or database if needed
+    pub fn open(config: &TranscriptsConfig) -> Result<Self> {
+        let backend = match (&config.directory, &config.sqlite_path) {
+            (Some(dir), _) => {
+                fs::create_dir_all(dir).with_context(|| format!("Failed to create transcripts directory {}", dir.display()))?;
+                Backend::Directory(dir.clone())
+            }
+            #[cfg(feature = "sqlite")]
+            (None, Some(path)) => Backend::Sqlite(Mutex::new(sqlite::open(path)?)),
+            _ => anyhow::bail!("transcripts needs either a directory or a sqlitePath"),
+        };
+        Ok(Self { config: config.clone(), backend, write_lock: Mutex::new(()) })
+    }
+    
+    /// Configuration the store was opened with
+    pub fn config(&self) -> &TranscriptsConfig {
+        &self.config
+    }
+    
+    /// Append a turn to the transcript of a session
+    pub fn append(&self, session_id: &str, turn: &TranscriptTurn) -> Result<()> {
+        check_session_id(session_id)?;
+        let json = serde_json::to_string(turn)?;
+        match &self.backend {
+            Backend::Directory(dir) => {
+                let path = dir.join(format!("{}.jsonl", session_id));
+                let _guard = self.write_lock.lock().unwrap();
+                let mut file = OpenOptions::new().create(true).append(true).open(&path)
+                    .with_context(|| format!("Failed to open transcript {}", path.display()))?;
+                file.write_all(format!("{}\n", json).as_bytes())
+                    .with_context(|| format!("Failed to write transcript {}", path.display()))
+            }
+            #[cfg(feature = "sqlite")]
+            Backend::Sqlite(connection) => sqlite::insert(&connection.lock().unwrap(), session_id, &turn.timestamp, &json),
+        }
+    }
+    
+    /// Turns of a session, oldest first; empty for an unknown session
+    pub fn transcript(&self, session_id: &str) -> Result<Vec<TranscriptTurn>> {
+        check_session_id(session_id)?;
+        let lines = match &self.backend {
+            Backend::Directory(dir) => {
+                let path = dir.join(format!("{}.jsonl", session_id));
+                match fs::read_to_string(&path) {
+                    Ok(text) => text.lines().filter(|line| !line.is_empty()).map(str::to_string).collect(),
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+                    Err(e) => return Err(e).with_context(|| format!("Failed to read transcript {}", path.display())),
+                }
+            }
+            #[cfg(feature = "sqlite")]
+            Backend::Sqlite(connection) => sqlite::turns(&connection.lock().unwrap(), session_id)?,
+        };
+        lines.iter()
+            .map(|line| serde_json::from_str(line).context("Corrupt transcript turn"))
+            .collect()
+    }
+}
+
+/// Session ids become file names, so only Claude Code's UUID alphabet is accepted
+pub fn check_session_id(session_id: &str) -> Result<()> {
+    let valid = !session_id.is_empty()
+        && session_id.len() <= 128
+        && session_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    anyhow::ensure!(valid, "Invalid session id '{}'", session_id);
+    Ok(())
+}
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use anyhow::{Context, Result};
+    use rusqlite::{params, Connection};
+    use std::path::Path;
+    
+    const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS turns (
+        id INTEGER PRIMARY KEY,
+        session_id TEXT NOT NULL,
+        timestamp TEXT NOT NULL,
+        turn TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS turns_session ON turns (session_id, id);
+    ";
+    
+    pub fn open(path: &Path) -> Result<Connection> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir).with_context(|| format!("Failed to create transcripts directory {}", dir.display()))?;
+        }
+        let connection = Connection::open(path)
+            .with_context(|| format!("Failed to open transcripts database {}", path.display()))?;
+        connection.pragma_update(None, "journal_mode", "WAL")?;
+        connection.execute_batch(SCHEMA).context("Failed to create the transcripts schema")?;
+        Ok(connection)
+    }
+    
+    pub fn insert(connection: &Connection, session_id: &str, timestamp: &str, turn: &str) -> Result<()> {
+        connection.execute(
+            "INSERT INTO turns (session_id, timestamp, turn) VALUES (?1, ?2, ?3)",
+            params![session_id, timestamp, turn],
+        )?;
+        Ok(())
+    }
+    
+    pub fn turns(connection: &Connection, session_id: &str) -> Result<Vec<String>> {
+        let mut statement = connection.prepare("SELECT turn FROM turns WHERE session_id = ?1 ORDER BY id")?;
+        let rows = statement.query_map(params![session_id], |row| row.get(0))?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::claude::ClaudeContent;
+    
+    fn turn(text: &str) -> TranscriptTurn {
+        TranscriptTurn {
+            timestamp: "2025-06-01T12:00:00.000Z".to_string(),
+            model: "claude-sonnet-4-5".to_string(),
+            status: 200,
+            messages: vec![ClaudeMessage { role: "user".to_string(), content: ClaudeContent::Text(text.to_string()) }],
+            response: vec![ClaudeContentBlock::Text { text: "Hi".to_string() }],
+            ..Default::default()
+        }
+    }
+    
+    fn json(turns: &[TranscriptTurn]) -> serde_json::Value {
+        serde_json::to_value(turns).unwrap()
+    }
+    
+    fn check_store(store: &TranscriptStore) {
+        store.append("0199a3e5-7c1d", &turn("Hello")).unwrap();
+        store.append("0199a3e5-7c1d", &turn("How are you?")).unwrap();
+        store.append("other", &turn("Hello")).unwrap();
+        
+        let turns = store.transcript("0199a3e5-7c1d").unwrap();
+        assert_eq!(json(&turns), json(&[turn("Hello"), turn("How are you?")]));
+        assert!(store.transcript("unknown").unwrap().is_empty());
+        // Session ids are file names
+        assert!(store.transcript("../secrets").is_err());
+        assert!(store.append("a/b", &turn("Hello")).is_err());
+    }
+    
+    #[test]
+    fn test_directory_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = TranscriptsConfig { directory: Some(dir.path().join("transcripts")), sqlite_path: None };
+        check_store(&TranscriptStore::open(&config).unwrap());
+        assert!(dir.path().join("transcripts").join("other.jsonl").exists());
+    }
+    
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_sqlite_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = TranscriptsConfig { directory: None, sqlite_path: Some(dir.path().join("transcripts.db")) };
+        check_store(&TranscriptStore::open(&config).unwrap());
+    }
+    
+    #[test]
+    fn test_stream_reassembly() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = TranscriptsConfig { directory: Some(dir.path().to_path_buf()), sqlite_path: None };
+        let store = Arc::new(TranscriptStore::open(&config).unwrap());
+        let request = ClaudeRequest::builder().model("claude-sonnet-4-5").system("Be brief").user("Read main.rs").build();
+        assert!(PendingTurn::start(store.clone(), None, &request).is_none());
+        
+        let mut pending = PendingTurn::start(store.clone(), Some("session-1"), &request).unwrap();
+        let events = [
+            ClaudeStreamEvent::ContentBlockStart { index: 0, content_block: ClaudeContentBlock::Text { text: String::new() } },
+            ClaudeStreamEvent::ContentBlockDelta { index: 0, delta: ClaudeContentDelta::TextDelta { text: "Let me ".to_string() } },
+            ClaudeStreamEvent::ContentBlockDelta { index: 0, delta: ClaudeContentDelta::TextDelta { text: "look.".to_string() } },
+            ClaudeStreamEvent::ContentBlockStop { index: 0 },
+            ClaudeStreamEvent::ContentBlockStart {
+                index: 1,
+                content_block: ClaudeContentBlock::ToolUse {
+                    id: "toolu_1".to_string(),
+                    name: "read".to_string(),
+                    input: serde_json::json!({}),
+                    thought_signature: None,
+                },
+            },
+            ClaudeStreamEvent::ContentBlockDelta { index: 1, delta: ClaudeContentDelta::InputJsonDelta { partial_json: r#"{"path": "#.to_string() } },
+            ClaudeStreamEvent::ContentBlockDelta { index: 1, delta: ClaudeContentDelta::InputJsonDelta { partial_json: r#""src/main.rs"}"#.to_string() } },
+            ClaudeStreamEvent::ContentBlockStop { index: 1 },
+        ];
+        for event in &events {
+            pending.record_stream_event(event);
+        }
+        pending.finish(&RequestSummary {
+            model: "claude-sonnet-4-5".to_string(),
+            route: Some("openai/gpt-4o".to_string()),
+            status: 200,
+            stop_reason: Some("tool_use".to_string()),
+            ..Default::default()
+        });
+        
+        let turns = json(&store.transcript("session-1").unwrap());
+        assert_eq!(turns[0]["system"], "Be brief");
+        assert_eq!(turns[0]["messages"][0]["content"], "Read main.rs");
+        assert_eq!(turns[0]["route"], "openai/gpt-4o");
+        assert_eq!(turns[0]["stop_reason"], "tool_use");
+        assert_eq!(turns[0]["response"], serde_json::json!([
+            {"type": "text", "text": "Let me look."},
+            {"type": "tool_use", "id": "toolu_1", "name": "read", "input": {"path": "src/main.rs"}},
+        ]));
+    }
+}
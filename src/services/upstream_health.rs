@@ -0,0 +1,221 @@
+//! Upstream health tracking for adaptive routing
+//!
+//! With `adaptiveRouting` configured, the router records the outcome and
+//! latency of every upstream request per provider/model path. Paths whose
+//! rolling error rate or average latency exceed the thresholds are marked
+//! degraded: failover chains try them last and weighted mappings skip them.
+//! After the cooldown a single probe request is let through; success brings
+//! the path back, failure restarts the cooldown. Only transient failures (the
+//! ones failover retries) count as errors; other errors show the upstream is
+//! reachable.
+
+use crate::config::AdaptiveRoutingConfig;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// A probe without a recorded result (e.g. cancelled request) is replaced after this
+const PROBE_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Outcome of one upstream request
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    success: bool,
+    latency: Duration,
+}
+
+/// Rolling statistics of one provider/model path
+#[derive(Debug, Default)]
+struct PathStats {
+    samples: VecDeque<Sample>,
+    /// Set while the path is degraded
+    degraded_until: Option<Instant>,
+    /// Start of the probe request in flight
+    probe_started: Option<Instant>,
+}
+
+impl PathStats {
+    /// Degraded and neither due for a probe nor probing
+    fn is_avoided(&self) -> bool {
+        let Some(until) = self.degraded_until else {
+            return false;
+        };
+        let probing = self.probe_started.is_some_and(|started| started.elapsed() < PROBE_TIMEOUT);
+        Instant::now() < until || probing
+    }
+}
+
+/// Per-path health of the upstreams
+#[derive(Debug)]
+pub struct HealthTracker {
+    config: AdaptiveRoutingConfig,
+    stats: Mutex<HashMap<String, PathStats>>,
+}
+
+impl HealthTracker {
+    /// Create a tracker with the given thresholds
+    pub fn new(config: AdaptiveRoutingConfig) -> Self {
+        Self {
+            config,
+            stats: Mutex::new(HashMap::new()),
+        }
+    }
+    
+    /// Whether traffic should avoid a path
+    ///
+    /// True while the path is degraded, except when its cooldown is over and
+    /// no probe is in flight.
+    pub fn is_avoided(&self, path: &str) -> bool {
+        self.stats.lock()
+            .ok()
+            .and_then(|stats| stats.get(path).map(PathStats::is_avoided))
+            .unwrap_or(false)
+    }
+    
+    /// Note a request about to be sent to a path
+    ///
+    /// On a degraded path due for a probe, the request becomes the probe.
+    pub fn begin_request(&self, path: &str) {
+        let Ok(mut stats) = self.stats.lock() else {
+            return;
+        };
+        if let Some(path_stats) = stats.get_mut(path) {
+            if path_stats.degraded_until.is_some() && !path_stats.is_avoided() {
+                info!("🩺 Probing degraded upstream {}", path);
+                path_stats.probe_started = Some(Instant::now());
+            }
+        }
+    }
+    
+    /// Whether a path is currently degraded
+    pub fn is_degraded(&self, path: &str) -> bool {
+        self.stats.lock()
+            .ok()
+            .and_then(|stats| stats.get(path).map(|s| s.degraded_until.is_some()))
+            .unwrap_or(false)
+    }
+    
+    /// Record the outcome of a request to a path
+    pub fn record(&self, path: &str, success: bool, latency: Duration) {
+        let Ok(mut stats) = self.stats.lock() else {
+            return;
+        };
+        let path_stats = stats.entry(path.to_string()).or_default();
+        
+        if path_stats.degraded_until.is_some() {
+            // Only the probe's result counts while degraded
+            if path_stats.probe_started.take().is_none() {
+                return;
+            }
+            if success {
+                info!("🩺 Upstream {} recovered", path);
+                *path_stats = PathStats::default();
+            } else {
+                warn!("🩺 Probe of upstream {} failed, avoiding it for another {}s", path, self.config.cooldown_secs);
+                path_stats.degraded_until = Some(Instant::now() + self.cooldown());
+            }
+            return;
+        }
+        
+        path_stats.samples.push_back(Sample { success, latency });
+        while path_stats.samples.len() > self.config.window_size {
+            path_stats.samples.pop_front();
+        }
+        
+        if let Some(reason) = self.degradation(&path_stats.samples) {
+            warn!("🩺 Upstream {} degraded ({}), avoiding it for {}s", path, reason, self.config.cooldown_secs);
+            path_stats.samples.clear();
+            path_stats.degraded_until = Some(Instant::now() + self.cooldown());
+        }
+    }
+    
+    /// Why the samples exceed a threshold, if they do
+    fn degradation(&self, samples: &VecDeque<Sample>) -> Option<String> {
+        if samples.len() < self.config.min_requests {
+            return None;
+        }
+        
+        let failures = samples.iter().filter(|s| !s.success).count();
+        let error_rate = failures as f64 / samples.len() as f64;
+        if error_rate > self.config.max_error_rate {
+            return Some(format!("error rate {:.0}%", error_rate * 100.0));
+        }
+        
+        let max_latency_ms = self.config.max_latency_ms?;
+        let average_ms = samples.iter().map(|s| s.latency.as_millis()).sum::<u128>() / samples.len() as u128;
+        (average_ms > u128::from(max_latency_ms)).then(|| format!("average latency {}ms", average_ms))
+    }
+    
+    fn cooldown(&self) -> Duration {
+        Duration::from_secs(self.config.cooldown_secs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn tracker(cooldown_secs: u64) -> HealthTracker {
+        HealthTracker::new(AdaptiveRoutingConfig {
+            window_size: 4,
+            min_requests: 4,
+            max_error_rate: 0.5,
+            max_latency_ms: Some(1000),
+            cooldown_secs,
+        })
+    }
+    
+    #[test]
+    fn test_error_rate_degrades() {
+        let health = tracker(60);
+        let fast = Duration::from_millis(100);
+        
+        for success in [true, false, true, false] {
+            health.record("ark/glm-4.6", success, fast);
+        }
+        // 50% is within the threshold
+        assert!(!health.is_degraded("ark/glm-4.6"));
+        
+        health.record("ark/glm-4.6", false, fast);
+        assert!(health.is_degraded("ark/glm-4.6"));
+        assert!(health.is_avoided("ark/glm-4.6"));
+        assert!(!health.is_avoided("openai/gpt-4o"));
+        
+        // Requests sent anyway (last resort) don't count before the cooldown
+        health.begin_request("ark/glm-4.6");
+        health.record("ark/glm-4.6", true, fast);
+        assert!(health.is_degraded("ark/glm-4.6"));
+    }
+    
+    #[test]
+    fn test_latency_degrades() {
+        let health = tracker(60);
+        for _ in 0..4 {
+            health.record("openai/gpt-4o", true, Duration::from_millis(1500));
+        }
+        assert!(health.is_degraded("openai/gpt-4o"));
+    }
+    
+    #[test]
+    fn test_recovery_probe() {
+        let health = tracker(0);
+        for _ in 0..4 {
+            health.record("ark/glm-4.6", false, Duration::ZERO);
+        }
+        assert!(health.is_degraded("ark/glm-4.6"));
+        
+        // One probe at a time after the cooldown
+        assert!(!health.is_avoided("ark/glm-4.6"));
+        health.begin_request("ark/glm-4.6");
+        assert!(health.is_avoided("ark/glm-4.6"));
+        
+        health.record("ark/glm-4.6", false, Duration::ZERO);
+        assert!(health.is_degraded("ark/glm-4.6"));
+        
+        health.begin_request("ark/glm-4.6");
+        health.record("ark/glm-4.6", true, Duration::ZERO);
+        assert!(!health.is_degraded("ark/glm-4.6"));
+        assert!(!health.is_avoided("ark/glm-4.6"));
+    }
+}
@@ -0,0 +1,466 @@
+//! Persistent usage accounting in SQLite
+//!
+//! With `usageStore` (requires the `sqlite` feature), the summary of every
+//! completed request is inserted into the `requests` table: client key and
+//! tenant, Claude model, provider/model path, tokens, estimated cost, latency
+//! and outcome. When the proxy starts, the usage and spend of each client key
+//! and tenant are restored from it, so `GET /usage` and budgets survive
+//! restarts.
+//!
+//! `aiapiproxy usage export` dumps the records of a time range, or their
+//! per-tenant, per-model or per-experiment-arm rollups, as CSV or JSON Lines.
+
+use crate::config::UsageStoreConfig;
+use crate::services::hooks::RequestHook;
+use crate::services::request_log::RequestSummary;
+use crate::services::tenants::{TenantLimiter, UsageTotals};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use chrono::Datelike;
+use rusqlite::types::ValueRef;
+use rusqlite::{params, Connection};
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use tracing::{info, warn};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS requests (
+    id INTEGER PRIMARY KEY,
+    timestamp TEXT NOT NULL,
+    request_id TEXT,
+    key TEXT,
+    tenant TEXT,
+    model TEXT NOT NULL,
+    route TEXT,
+    provider TEXT,
+    experiment TEXT,
+    arm TEXT,
+    stream INTEGER NOT NULL,
+    status INTEGER NOT NULL,
+    latency_ms INTEGER NOT NULL,
+    input_tokens INTEGER,
+    output_tokens INTEGER,
+    cache_read_input_tokens INTEGER,
+    cost_usd REAL,
+    stop_reason TEXT,
+    error TEXT
+);
+CREATE INDEX IF NOT EXISTS requests_timestamp ON requests (timestamp);
+CREATE INDEX IF NOT EXISTS requests_key ON requests (key, timestamp);
+CREATE INDEX IF NOT EXISTS requests_tenant ON requests (tenant, timestamp);
+";
+
+/// Output format of `usage export`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// Comma-separated values with a header line
+    Csv,
+    /// One JSON object per line
+    Jsonl,
+}
+
+impl FromStr for ExportFormat {
+    type Err = anyhow::Error;
+    
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "csv" => Ok(ExportFormat::Csv),
+            "jsonl" => Ok(ExportFormat::Jsonl),
+            _ => anyhow::bail!("Unknown export format '{}': expected csv or jsonl", s),
+        }
+    }
+}
+
+/// Totals `usage export` groups the records into
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rollup {
+    /// One row per tenant
+    Tenant,
+    /// One row per Claude model and provider/model path
+    Model,
+    /// One row per experiment arm
+    Experiment,
+}
+
+impl FromStr for Rollup {
+    type Err = anyhow::Error;
+    
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "tenant" => Ok(Rollup::Tenant),
+            "model" => Ok(Rollup::Model),
+            "experiment" => Ok(Rollup::Experiment),
+            _ => anyhow::bail!("Unknown rollup '{}': expected tenant, model or experiment", s),
+        }
+    }
+}
+
+/// Records selected by `usage export`
+#[derive(Debug, Clone, Default)]
+pub struct ExportQuery {
+    /// First timestamp included (RFC 3339 or a date prefix of one)
+    pub from: Option<String>,
+    /// First timestamp excluded (RFC 3339 or a date prefix of one)
+    pub to: Option<String>,
+    /// Totals to export instead of the records
+    pub rollup: Option<Rollup>,
+}
+
+impl ExportQuery {
+    /// Parse a `--from` bound: a date (`2025-06-01`) starts at midnight UTC
+    pub fn parse_from(value: &str) -> Result<String> {
+        parse_bound(value, false)
+    }
+    
+    /// Parse a `--to` bound: a date (`2025-06-30`) includes the whole day
+    pub fn parse_to(value: &str) -> Result<String> {
+        parse_bound(value, true)
+    }
+}
+
+fn parse_bound(value: &str, end: bool) -> Result<String> {
+    if let Ok(date) = chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        let date = if end { date.succ_opt().context("Date out of range")? } else { date };
+        return Ok(date.format("%Y-%m-%d").to_string());
+    }
+    let time = chrono::DateTime::parse_from_rfc3339(value)
+        .with_context(|| format!("Invalid time '{}': expected YYYY-MM-DD or an RFC 3339 timestamp", value))?;
+    Ok(time.with_timezone(&chrono::Utc).to_rfc3339_opts(chrono::SecondsFormat::Millis, true))
+}
+
+/// SQLite database of request usage
+#[derive(Debug)]
+pub struct UsageStore {
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl UsageStore {
+    /// Open the database, creating it and its table if needed
+    pub fn open(config: &UsageStoreConfig) -> Result<Self> {
+        if let Some(dir) = config.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir).with_context(|| format!("Failed to create usage store directory {}", dir.display()))?;
+        }
+        let connection = Connection::open(&config.path)
+            .with_context(|| format!("Failed to open usage store {}", config.path.display()))?;
+        connection.pragma_update(None, "journal_mode", "WAL")?;
+        connection.execute_batch(SCHEMA).context("Failed to create the usage store schema")?;
+        // Added after the first release of the table
+        for (column, column_type) in [("cache_read_input_tokens", "INTEGER"), ("experiment", "TEXT"), ("arm", "TEXT")] {
+            if connection.prepare(&format!("SELECT {} FROM requests LIMIT 0", column)).is_err() {
+                connection.execute_batch(&format!("ALTER TABLE requests ADD COLUMN {} {}", column, column_type))?;
+            }
+        }
+        Ok(Self { connection: Arc::new(Mutex::new(connection)) })
+    }
+    
+    /// Insert the summary of a completed request
+    pub fn insert(&self, summary: &RequestSummary) -> Result<()> {
+        insert(&self.connection.lock().unwrap(), summary)
+    }
+    
+    /// Restore the usage and spend of all keys and tenants into a limiter
+    pub fn restore(&self, limiter: &TenantLimiter) -> Result<()> {
+        let now = chrono::Utc::now().date_naive();
+        let today = now.format("%Y-%m-%d").to_string();
+        let month = format!("{:04}-{:02}", now.year(), now.month());
+        let connection = self.connection.lock().unwrap();
+        
+        let mut keys = connection.prepare(
+            "SELECT key, tenant, COUNT(*), SUM(COALESCE(input_tokens, 0)), SUM(COALESCE(output_tokens, 0)),
+                    SUM(CASE WHEN timestamp >= ?1 THEN COALESCE(input_tokens, 0) + COALESCE(output_tokens, 0) ELSE 0 END),
+                    SUM(CASE WHEN timestamp >= ?1 THEN COALESCE(cost_usd, 0) ELSE 0 END),
+                    SUM(CASE WHEN timestamp >= ?2 THEN COALESCE(cost_usd, 0) ELSE 0 END)
+             FROM requests WHERE key IS NOT NULL GROUP BY key",
+        )?;
+        let rows = keys.query_map(params![today, month], |row| {
+            let totals = UsageTotals {
+                requests: row.get(2)?,
+                input_tokens: row.get(3)?,
+                output_tokens: row.get(4)?,
+                tokens_today: row.get(5)?,
+                spend_today: row.get(6)?,
+                spend_month: row.get(7)?,
+            };
+            Ok((row.get::<_, String>(0)?, row.get::<_, Option<String>>(1)?, totals))
+        })?;
+        let mut restored = 0;
+        for row in rows {
+            let (key, tenant, totals) = row?;
+            limiter.restore_key(&key, tenant.as_deref().unwrap_or(&key), totals);
+            restored += 1;
+        }
+        
+        let mut tenants = connection.prepare(
+            "SELECT tenant,
+                    SUM(CASE WHEN timestamp >= ?1 THEN COALESCE(cost_usd, 0) ELSE 0 END),
+                    SUM(CASE WHEN timestamp >= ?2 THEN COALESCE(cost_usd, 0) ELSE 0 END)
+             FROM requests WHERE tenant IS NOT NULL AND timestamp >= ?2 GROUP BY tenant",
+        )?;
+        let rows = tenants.query_map(params![today, month], |row| Ok((row.get::<_, String>(0)?, row.get(1)?, row.get(2)?)))?;
+        for row in rows {
+            let (tenant, spend_today, spend_month) = row?;
+            limiter.restore_tenant(&tenant, spend_today, spend_month);
+        }
+        
+        info!("📒 Restored the usage of {} client keys from the usage store", restored);
+        Ok(())
+    }
+    
+    /// Write the records or rollups of a time range, returning the number of rows
+    pub fn export(&self, query: &ExportQuery, format: ExportFormat, out: &mut dyn Write) -> Result<usize> {
+        const RANGE: &str = "(?1 IS NULL OR timestamp >= ?1) AND (?2 IS NULL OR timestamp < ?2)";
+        const TOTALS: &str = "COUNT(*) AS requests,
+                    SUM(CASE WHEN status >= 400 THEN 1 ELSE 0 END) AS errors,
+                    SUM(COALESCE(input_tokens, 0)) AS input_tokens,
+                    SUM(COALESCE(output_tokens, 0)) AS output_tokens,
+                    SUM(COALESCE(cache_read_input_tokens, 0)) AS cache_read_input_tokens,
+                    SUM(COALESCE(cost_usd, 0)) AS cost_usd";
+        let sql = match query.rollup {
+            None => format!(
+                "SELECT timestamp, request_id, key, tenant, model, route, provider, experiment, arm, stream, status, latency_ms,
+                        input_tokens, output_tokens, cache_read_input_tokens, cost_usd, stop_reason, error
+                 FROM requests WHERE {RANGE} ORDER BY timestamp, id"
+            ),
+            Some(Rollup::Tenant) => format!(
+                "SELECT tenant, {TOTALS} FROM requests WHERE {RANGE} GROUP BY tenant ORDER BY tenant"
+            ),
+            Some(Rollup::Model) => format!(
+                "SELECT model, route, {TOTALS} FROM requests WHERE {RANGE} GROUP BY model, route ORDER BY model, route"
+            ),
+            Some(Rollup::Experiment) => format!(
+                "SELECT experiment, arm, route, {TOTALS}, AVG(latency_ms) AS mean_latency_ms FROM requests
+                 WHERE {RANGE} AND experiment IS NOT NULL GROUP BY experiment, arm, route ORDER BY experiment, arm, route"
+            ),
+        };
+        
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare(&sql)?;
+        let columns: Vec<String> = statement.column_names().into_iter().map(String::from).collect();
+        if format == ExportFormat::Csv {
+            writeln!(out, "{}", columns.join(","))?;
+        }
+        
+        let mut rows = statement.query(params![query.from, query.to])?;
+        let mut count = 0;
+        while let Some(row) = rows.next()? {
+            let values = (0..columns.len())
+                .map(|i| Ok(json_value(row.get_ref(i)?)))
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            match format {
+                ExportFormat::Csv => {
+                    let fields: Vec<String> = values.iter().map(csv_field).collect();
+                    writeln!(out, "{}", fields.join(","))?;
+                }
+                ExportFormat::Jsonl => {
+                    let object: serde_json::Map<_, _> = columns.iter().cloned().zip(values).collect();
+                    writeln!(out, "{}", serde_json::Value::Object(object))?;
+                }
+            }
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+
+fn json_value(value: ValueRef<'_>) -> serde_json::Value {
+    match value {
+        ValueRef::Null => serde_json::Value::Null,
+        ValueRef::Integer(i) => i.into(),
+        ValueRef::Real(f) => f.into(),
+        ValueRef::Text(text) | ValueRef::Blob(text) => String::from_utf8_lossy(text).into(),
+    }
+}
+
+/// CSV field, quoted when it contains a separator, quote or line break
+fn csv_field(value: &serde_json::Value) -> String {
+    let text = match value {
+        serde_json::Value::Null => return String::new(),
+        serde_json::Value::String(text) => text.clone(),
+        other => other.to_string(),
+    };
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text
+    }
+}
+
+fn insert(connection: &Connection, summary: &RequestSummary) -> Result<()> {
+    connection.execute(
+        "INSERT INTO requests (timestamp, request_id, key, tenant, model, route, provider, experiment, arm, stream, status,
+                               latency_ms, input_tokens, output_tokens, cache_read_input_tokens, cost_usd, stop_reason, error)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18)",
+        params![
+            summary.timestamp,
+            summary.request_id,
+            summary.key,
+            summary.tenant,
+            summary.model,
+            summary.route,
+            summary.provider,
+            summary.experiment,
+            summary.arm,
+            summary.stream,
+            summary.status,
+            summary.latency_ms,
+            summary.input_tokens,
+            summary.output_tokens,
+            summary.cache_read_input_tokens,
+            summary.cost_usd,
+            summary.stop_reason,
+            summary.error,
+        ],
+    )?;
+    Ok(())
+}
+
+#[async_trait]
+impl RequestHook for UsageStore {
+    async fn on_complete(&self, summary: &RequestSummary) {
+        let connection = self.connection.clone();
+        let summary = summary.clone();
+        let inserted = tokio::task::spawn_blocking(move || insert(&connection.lock().unwrap(), &summary)).await;
+        match inserted {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => warn!("📒 Failed to record usage: {:#}", e),
+            Err(e) => warn!("📒 Failed to record usage: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{KeyLimits, Priority};
+    use crate::middleware::auth::ClientKey;
+    use std::collections::HashMap;
+    
+    fn summary(key: &str, timestamp: &str, output_tokens: u32, cost_usd: f64) -> RequestSummary {
+        RequestSummary {
+            timestamp: timestamp.to_string(),
+            key: Some(key.to_string()),
+            tenant: Some("search".to_string()),
+            model: "claude-sonnet-4-5".to_string(),
+            route: Some("openai/gpt-4o".to_string()),
+            provider: Some("openai".to_string()),
+            status: 200,
+            input_tokens: Some(10),
+            output_tokens: Some(output_tokens),
+            cost_usd: Some(cost_usd),
+            ..Default::default()
+        }
+    }
+    
+    #[test]
+    fn test_restore_after_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = UsageStoreConfig { path: dir.path().join("usage.db") };
+        let now = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true);
+        {
+            let store = UsageStore::open(&config).unwrap();
+            store.insert(&summary("laptop", &now, 20, 0.5)).unwrap();
+            store.insert(&summary("laptop", &now, 30, 0.25)).unwrap();
+            store.insert(&summary("laptop", "2000-01-01T00:00:00.000Z", 1000, 100.0)).unwrap();
+            store.insert(&summary("ci", &now, 5, 1.0)).unwrap();
+        }
+        
+        let store = UsageStore::open(&config).unwrap();
+        let limiter = TenantLimiter::new(HashMap::new());
+        store.restore(&limiter).unwrap();
+        
+        let usage = limiter.usage("laptop").unwrap();
+        assert_eq!(usage.tenant, "search");
+        assert_eq!(usage.requests, 3);
+        assert_eq!(usage.output_tokens, 1050);
+        assert_eq!(usage.tokens_today, 70);
+        assert_eq!(usage.spend.today, 0.75);
+        
+        let client = ClientKey {
+            name: "laptop".to_string(),
+            tenant: "search".to_string(),
+            priority: Priority::default(),
+            limits: KeyLimits::default(),
+            admin: false,
+        };
+        assert_eq!(limiter.report(&client).tenant_spend.spend.month, 1.75);
+    }
+    
+    #[test]
+    fn test_export() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = UsageStore::open(&UsageStoreConfig { path: dir.path().join("usage.db") }).unwrap();
+        store.insert(&summary("laptop", "2025-05-31T23:59:59.000Z", 1, 9.0)).unwrap();
+        store.insert(&summary("laptop", "2025-06-01T08:00:00.000Z", 20, 0.5)).unwrap();
+        store.insert(&RequestSummary {
+            error: Some("upstream said \"no\", twice".to_string()),
+            status: 502,
+            ..summary("ci", "2025-06-30T12:00:00.000Z", 0, 0.25)
+        }).unwrap();
+        store.insert(&summary("laptop", "2025-07-01T00:00:00.000Z", 1, 9.0)).unwrap();
+        
+        let june = ExportQuery {
+            from: Some(ExportQuery::parse_from("2025-06-01").unwrap()),
+            to: Some(ExportQuery::parse_to("2025-06-30").unwrap()),
+            rollup: None,
+        };
+        let mut out = Vec::new();
+        assert_eq!(store.export(&june, ExportFormat::Csv, &mut out).unwrap(), 2);
+        let csv = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert!(lines[0].starts_with("timestamp,request_id,key,tenant,model,route"));
+        assert!(lines[1].starts_with("2025-06-01T08:00:00.000Z,,laptop,search,claude-sonnet-4-5,openai/gpt-4o"));
+        assert!(lines[2].ends_with(",\"upstream said \"\"no\"\", twice\""));
+        
+        let mut out = Vec::new();
+        let by_tenant = ExportQuery { rollup: Some(Rollup::Tenant), ..june };
+        assert_eq!(store.export(&by_tenant, ExportFormat::Jsonl, &mut out).unwrap(), 1);
+        let total: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(total, serde_json::json!({
+            "tenant": "search",
+            "requests": 2,
+            "errors": 1,
+            "input_tokens": 20,
+            "output_tokens": 20,
+            "cache_read_input_tokens": 0,
+            "cost_usd": 0.75,
+        }));
+    }
+    
+    #[test]
+    fn test_export_experiment_rollup() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = UsageStore::open(&UsageStoreConfig { path: dir.path().join("usage.db") }).unwrap();
+        let in_arm = |arm: &str, route: &str, latency_ms: u64| RequestSummary {
+            experiment: Some("glm-rollout".to_string()),
+            arm: Some(arm.to_string()),
+            route: Some(route.to_string()),
+            latency_ms,
+            ..summary("laptop", "2025-06-01T08:00:00.000Z", 10, 0.5)
+        };
+        store.insert(&in_arm("treatment", "ark/glm-4.6", 1000)).unwrap();
+        store.insert(&in_arm("treatment", "ark/glm-4.6", 3000)).unwrap();
+        store.insert(&in_arm("control", "openai/gpt-4o", 2000)).unwrap();
+        store.insert(&summary("laptop", "2025-06-01T08:00:00.000Z", 10, 0.5)).unwrap();
+        
+        let mut out = Vec::new();
+        let by_arm = ExportQuery { rollup: Some(Rollup::Experiment), ..Default::default() };
+        assert_eq!(store.export(&by_arm, ExportFormat::Jsonl, &mut out).unwrap(), 2);
+        let rows: Vec<serde_json::Value> = out.split(|b| *b == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice(line).unwrap())
+            .collect();
+        assert_eq!(rows[0]["arm"], "control");
+        assert_eq!(rows[1]["route"], "ark/glm-4.6");
+        assert_eq!(rows[1]["requests"], 2);
+        assert_eq!(rows[1]["mean_latency_ms"], 2000.0);
+    }
+    
+    #[test]
+    fn test_parse_bounds() {
+        assert_eq!(ExportQuery::parse_to("2025-12-31").unwrap(), "2026-01-01");
+        assert_eq!(ExportQuery::parse_from("2025-06-01T10:00:00+02:00").unwrap(), "2025-06-01T08:00:00.000Z");
+        assert!(ExportQuery::parse_from("June").is_err());
+        assert!("xml".parse::<ExportFormat>().is_err());
+    }
+}
@@ -0,0 +1,355 @@
+//! Test support for the converters (feature `test-util`)
+//!
+//! [`conversation`] generates tool-using conversations, as Claude Code sends
+//! them, which [`Conversation::claude_request`] and
+//! [`Conversation::openai_request`] turn into requests of either API. The
+//! checks compare the [`Contents`] of a request before and after a
+//! conversion: roles still alternate, tool ids are kept, and no text is lost.
+//! They return a description of the first violation, for use with
+//! `proptest`'s `TestCaseError::fail` or a plain `assert!`.
+
+use crate::models::claude::*;
+use crate::models::openai::*;
+use proptest::prelude::*;
+use serde_json::Value;
+
+/// A tool call of an assistant message and its result
+#[derive(Debug, Clone)]
+pub struct ToolCall {
+    pub id: String,
+    pub name: String,
+    /// JSON object of arguments
+    pub input: Value,
+    pub output: String,
+}
+
+/// An assistant message and the user message answering it
+#[derive(Debug, Clone)]
+pub struct Exchange {
+    /// Text before the tool calls
+    pub assistant_text: Option<String>,
+    pub tool_calls: Vec<ToolCall>,
+    /// Text after the tool results, or the whole reply without tool calls
+    pub user_text: Option<String>,
+}
+
+/// A conversation that starts and ends with a user message
+#[derive(Debug, Clone)]
+pub struct Conversation {
+    pub system: Option<String>,
+    pub first: String,
+    pub exchanges: Vec<Exchange>,
+}
+
+impl Conversation {
+    /// The conversation as a Claude request
+    pub fn claude_request(&self, model: &str) -> ClaudeRequest {
+        let mut builder = ClaudeRequest::builder().model(model).max_tokens(1024).user(self.first.clone());
+        if let Some(system) = &self.system {
+            builder = builder.system(system.clone());
+        }
+        for exchange in &self.exchanges {
+            let mut assistant: Vec<ClaudeContentBlock> = exchange.assistant_text.iter()
+                .map(|text| ClaudeContentBlock::Text { text: text.clone() })
+                .collect();
+            let mut user = Vec::new();
+            for call in &exchange.tool_calls {
+                assistant.push(ClaudeContentBlock::ToolUse {
+                    id: call.id.clone(),
+                    name: call.name.clone(),
+                    input: call.input.clone(),
+                    thought_signature: None,
+                });
+                user.push(ClaudeContentBlock::ToolResult {
+                    tool_use_id: call.id.clone(),
+                    content: ClaudeToolResultContent::Text(call.output.clone()),
+                    is_error: None,
+                });
+            }
+            user.extend(exchange.user_text.iter().map(|text| ClaudeContentBlock::Text { text: text.clone() }));
+            builder = builder
+                .message("assistant", ClaudeContent::Blocks(assistant))
+                .message("user", ClaudeContent::Blocks(user));
+        }
+        builder.build()
+    }
+    
+    /// The conversation as an OpenAI chat request
+    pub fn openai_request(&self, model: &str) -> OpenAIRequest {
+        let mut builder = OpenAIRequest::builder().model(model).max_tokens(1024);
+        if let Some(system) = &self.system {
+            builder = builder.system(system.clone());
+        }
+        builder = builder.user(self.first.clone());
+        for exchange in &self.exchanges {
+            let tool_calls = exchange.tool_calls.iter()
+                .map(|call| OpenAIToolCall {
+                    index: None,
+                    id: Some(call.id.clone()),
+                    tool_type: Some("function".to_string()),
+                    function: OpenAIFunctionCall { name: Some(call.name.clone()), arguments: Some(call.input.to_string()) },
+                    signature: None,
+                    extra_content: None,
+                })
+                .collect::<Vec<_>>();
+            builder = builder.message(OpenAIMessage {
+                role: "assistant".to_string(),
+                content: exchange.assistant_text.clone().map(OpenAIContent::Text),
+                name: None,
+                tool_calls: (!tool_calls.is_empty()).then_some(tool_calls),
+                tool_call_id: None,
+            });
+            for call in &exchange.tool_calls {
+                builder = builder.message(OpenAIMessage {
+                    role: "tool".to_string(),
+                    content: Some(OpenAIContent::Text(call.output.clone())),
+                    name: None,
+                    tool_calls: None,
+                    tool_call_id: Some(call.id.clone()),
+                });
+            }
+            if let Some(text) = &exchange.user_text {
+                builder = builder.user(text.clone());
+            }
+        }
+        builder.build()
+    }
+}
+
+/// Non-empty text with punctuation, newlines and non-ASCII characters
+pub fn text() -> impl Strategy<Value = String> {
+    "[a-zA-Z0-9 .,:;!?'\"(){}\\[\\]\n\t<>/éü中文🙂-]{1,60}"
+        .prop_filter("text must not be blank", |text| !text.trim().is_empty())
+}
+
+/// Tool arguments: an object of strings, integers, booleans and nulls
+pub fn tool_input() -> impl Strategy<Value = Value> {
+    let value = prop_oneof![
+        text().prop_map(Value::from),
+        any::<i64>().prop_map(Value::from),
+        any::<bool>().prop_map(Value::from),
+        Just(Value::Null),
+    ];
+    prop::collection::btree_map("[a-z_]{1,12}", value, 0..4)
+        .prop_map(|fields| Value::Object(fields.into_iter().collect()))
+}
+
+/// A tool call with an id, a name, arguments and a result
+pub fn tool_call() -> impl Strategy<Value = ToolCall> {
+    ("(toolu|call)_[A-Za-z0-9]{8,24}", "[a-z][a-z_]{0,15}", tool_input(), text())
+        .prop_map(|(id, name, input, output)| ToolCall { id, name, input, output })
+}
+
+fn exchange() -> impl Strategy<Value = Exchange> {
+    (prop::option::of(text()), prop::collection::vec(tool_call(), 0..4), prop::option::of(text()))
+        .prop_map(|(assistant_text, tool_calls, user_text)| {
+            // Without tool calls both messages need text
+            let plain = tool_calls.is_empty();
+            Exchange {
+                assistant_text: assistant_text.or_else(|| plain.then(|| "OK".to_string())),
+                tool_calls,
+                user_text: user_text.or_else(|| plain.then(|| "Go on".to_string())),
+            }
+        })
+}
+
+/// Conversations of up to 6 exchanges, with unique tool ids
+pub fn conversation() -> impl Strategy<Value = Conversation> {
+    (prop::option::of(text()), text(), prop::collection::vec(exchange(), 0..6))
+        .prop_map(|(system, first, mut exchanges)| {
+            for (i, exchange) in exchanges.iter_mut().enumerate() {
+                for (j, call) in exchange.tool_calls.iter_mut().enumerate() {
+                    call.id = format!("{}_{}_{}", call.id, i, j);
+                }
+            }
+            Conversation { system, first, exchanges }
+        })
+}
+
+/// Claude requests of generated conversations
+pub fn claude_request() -> impl Strategy<Value = ClaudeRequest> {
+    conversation().prop_map(|conversation| conversation.claude_request("claude-sonnet-4-5"))
+}
+
+/// OpenAI chat requests of generated conversations
+pub fn openai_request() -> impl Strategy<Value = OpenAIRequest> {
+    conversation().prop_map(|conversation| conversation.openai_request("gpt-4o"))
+}
+
+/// What a request or response says, by channel, in order
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Contents {
+    pub system: Vec<String>,
+    pub user: Vec<String>,
+    pub assistant: Vec<String>,
+    /// Tool calls: id, name and arguments
+    pub tool_calls: Vec<(String, String, Value)>,
+    /// Tool results: call id and text
+    pub tool_results: Vec<(String, String)>,
+}
+
+impl Contents {
+    /// Contents of a Claude request
+    pub fn of_claude_request(request: &ClaudeRequest) -> Self {
+        let mut contents = Self::default();
+        match &request.system {
+            Some(SystemPrompt::String(text)) => contents.system.push(text.clone()),
+            Some(SystemPrompt::Array(blocks)) => contents.system.extend(blocks.iter().map(|block| block.text.clone())),
+            None => {}
+        }
+        for message in &request.messages {
+            match &message.content {
+                ClaudeContent::Text(text) => contents.push_text(&message.role, text),
+                ClaudeContent::Blocks(blocks) => contents.push_blocks(&message.role, blocks),
+                ClaudeContent::Other(_) => {}
+            }
+        }
+        contents
+    }
+    
+    /// Contents of a Claude response
+    pub fn of_claude_response(response: &ClaudeResponse) -> Self {
+        let mut contents = Self::default();
+        contents.push_blocks(&response.role, &response.content);
+        contents
+    }
+    
+    /// Contents of an OpenAI chat request
+    pub fn of_openai_request(request: &OpenAIRequest) -> Self {
+        let mut contents = Self::default();
+        for message in &request.messages {
+            contents.push_openai_message(message);
+        }
+        contents
+    }
+    
+    /// Contents of the first choice of an OpenAI chat response
+    pub fn of_openai_response(response: &OpenAIResponse) -> Self {
+        let mut contents = Self::default();
+        if let Some(choice) = response.choices.first() {
+            contents.push_openai_message(&choice.message);
+        }
+        contents
+    }
+    
+    /// Contents of a Responses API request's `instructions` and `input` items
+    pub fn of_responses_input(instructions: Option<&str>, input: &[Value]) -> Self {
+        let mut contents = Self::default();
+        contents.system.extend(instructions.map(str::to_string));
+        let str_field = |item: &Value, key: &str| item.get(key).and_then(Value::as_str).unwrap_or_default().to_string();
+        for item in input {
+            match item.get("type").and_then(Value::as_str) {
+                Some("function_call") => {
+                    let input = serde_json::from_str(&str_field(item, "arguments")).unwrap_or(Value::Null);
+                    contents.tool_calls.push((str_field(item, "call_id"), str_field(item, "name"), input));
+                }
+                Some("function_call_output") => {
+                    contents.tool_results.push((str_field(item, "call_id"), str_field(item, "output")));
+                }
+                _ => {
+                    let role = str_field(item, "role");
+                    for part in item.get("content").and_then(Value::as_array).into_iter().flatten() {
+                        contents.push_text(&role, part.get("text").and_then(Value::as_str).unwrap_or_default());
+                    }
+                }
+            }
+        }
+        contents
+    }
+    
+    fn push_text(&mut self, role: &str, text: &str) {
+        let channel = match role {
+            "system" | "developer" => &mut self.system,
+            "assistant" => &mut self.assistant,
+            _ => &mut self.user,
+        };
+        channel.push(text.to_string());
+    }
+    
+    fn push_blocks(&mut self, role: &str, blocks: &[ClaudeContentBlock]) {
+        for block in blocks {
+            match block {
+                ClaudeContentBlock::Text { text } => self.push_text(role, text),
+                ClaudeContentBlock::ToolUse { id, name, input, .. } => {
+                    self.tool_calls.push((id.clone(), name.clone(), input.clone()));
+                }
+                ClaudeContentBlock::ToolResult { tool_use_id, content, .. } => {
+                    self.tool_results.push((tool_use_id.clone(), content.text()));
+                }
+                _ => {}
+            }
+        }
+    }
+    
+    fn push_openai_message(&mut self, message: &OpenAIMessage) {
+        let text = message.content.as_ref().map(OpenAIContent::extract_text).unwrap_or_default();
+        match message.role.as_str() {
+            "tool" => self.tool_results.push((message.tool_call_id.clone().unwrap_or_default(), text)),
+            role => {
+                if !text.is_empty() {
+                    self.push_text(role, &text);
+                }
+                for call in message.tool_calls.iter().flatten() {
+                    let arguments = call.function.arguments.as_deref().unwrap_or_default();
+                    self.tool_calls.push((
+                        call.id.clone().unwrap_or_default(),
+                        call.function.name.clone().unwrap_or_default(),
+                        serde_json::from_str(arguments).unwrap_or(Value::Null),
+                    ));
+                }
+            }
+        }
+    }
+}
+
+/// Messages start with `user` and alternate between `user` and `assistant`
+pub fn check_role_alternation(request: &ClaudeRequest) -> Result<(), String> {
+    for (i, message) in request.messages.iter().enumerate() {
+        let expected = if i % 2 == 0 { "user" } else { "assistant" };
+        if message.role != expected {
+            return Err(format!("messages.{} has role {}, expected {}", i, message.role, expected));
+        }
+    }
+    Ok(())
+}
+
+/// Tool calls keep their ids, names and arguments, and results their call ids,
+/// in the same order; every result answers an earlier call
+pub fn check_tool_ids(before: &Contents, after: &Contents) -> Result<(), String> {
+    if before.tool_calls != after.tool_calls {
+        return Err(format!("tool calls changed: {:?} became {:?}", before.tool_calls, after.tool_calls));
+    }
+    let ids = |results: &[(String, String)]| results.iter().map(|(id, _)| id.clone()).collect::<Vec<_>>();
+    if ids(&before.tool_results) != ids(&after.tool_results) {
+        return Err(format!("tool result ids changed: {:?} became {:?}", ids(&before.tool_results), ids(&after.tool_results)));
+    }
+    for (id, _) in &after.tool_results {
+        if !after.tool_calls.iter().any(|(call_id, _, _)| call_id == id) {
+            return Err(format!("tool result {} answers no tool call", id));
+        }
+    }
+    Ok(())
+}
+
+/// Every text of `before` is found in `after`, in the same channel and order;
+/// texts may be joined or separated differently
+pub fn check_no_content_loss(before: &Contents, after: &Contents) -> Result<(), String> {
+    let result_texts = |contents: &Contents| contents.tool_results.iter().map(|(_, text)| text.clone()).collect::<Vec<_>>();
+    let channels = [
+        ("system", before.system.clone(), after.system.clone()),
+        ("user", before.user.clone(), after.user.clone()),
+        ("assistant", before.assistant.clone(), after.assistant.clone()),
+        ("tool result", result_texts(before), result_texts(after)),
+    ];
+    for (channel, before, after) in channels {
+        let after = after.concat();
+        let mut rest = after.as_str();
+        for text in &before {
+            match rest.find(text.as_str()) {
+                Some(start) => rest = &rest[start + text.len()..],
+                None => return Err(format!("{} text {:?} was lost", channel, text)),
+            }
+        }
+    }
+    Ok(())
+}
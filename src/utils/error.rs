@@ -2,11 +2,16 @@
 //! 
 //! Defines error types and handling logic used in the project
 
+#[cfg(feature = "server")]
 use axum::{
-    http::StatusCode,
+    http::{header, HeaderValue},
     response::{IntoResponse, Response},
     Json,
 };
+use crate::providers::ProviderError;
+#[cfg(feature = "server")]
+use crate::services::tenants::LimitError;
+use http::StatusCode;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
@@ -37,6 +42,16 @@ pub enum AppError {
     #[error("Request validation failed: {0}")]
     Validation(String),
     
+    /// Request rejected with a message passed to the client as is, such as
+    /// Anthropic's "prompt is too long"
+    #[error("{0}")]
+    InvalidRequest(String),
+    
+    /// Request rejected by its client key's limits
+    #[cfg(feature = "server")]
+    #[error(transparent)]
+    Limit(#[from] LimitError),
+    
     /// API conversion failed
     #[error("API conversion failed: {0}")]
     Conversion(String),
@@ -45,6 +60,10 @@ pub enum AppError {
     #[error("External API error: {0}")]
     ExternalApi(String),
     
+    /// Classified failure of a provider call
+    #[error("Provider error: {0}")]
+    Provider(#[from] ProviderError),
+    
     /// Rate limit exceeded
     #[error("Rate limit exceeded, please try again later")]
     RateLimit,
@@ -53,6 +72,10 @@ pub enum AppError {
     #[error("Service temporarily unavailable: {0}")]
     ServiceUnavailable(String),
     
+    /// No capacity for the request right now (529, like Anthropic's overloaded responses)
+    #[error("{0}")]
+    Overloaded(String),
+    
     /// Internal server error
     #[error("Internal server error: {0}")]
     Internal(String),
@@ -110,13 +133,17 @@ impl AppError {
         match self {
             AppError::Authentication(_) => StatusCode::UNAUTHORIZED,
             AppError::Authorization(_) => StatusCode::FORBIDDEN,
-            AppError::Validation(_) => StatusCode::BAD_REQUEST,
+            AppError::Validation(_) | AppError::InvalidRequest(_) => StatusCode::BAD_REQUEST,
+            #[cfg(feature = "server")]
+            AppError::Limit(error) => error.status_code(),
             AppError::NotFound(_) => StatusCode::NOT_FOUND,
             AppError::RateLimit => StatusCode::TOO_MANY_REQUESTS,
             AppError::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
             AppError::Timeout => StatusCode::REQUEST_TIMEOUT,
             AppError::ServiceUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::Overloaded(_) => StatusCode::from_u16(529).unwrap_or(StatusCode::SERVICE_UNAVAILABLE),
             AppError::ExternalApi(_) => StatusCode::BAD_GATEWAY,
+            AppError::Provider(error) => error.claude_error().1,
             AppError::Config(_) 
             | AppError::HttpClient(_) 
             | AppError::Serialization(_) 
@@ -130,13 +157,16 @@ impl AppError {
         match self {
             AppError::Authentication(_) => "authentication_error",
             AppError::Authorization(_) => "permission_error",
-            AppError::Validation(_) => "invalid_request_error",
+            AppError::Validation(_) | AppError::InvalidRequest(_) => "invalid_request_error",
+            #[cfg(feature = "server")]
+            AppError::Limit(error) => error.error_type(),
             AppError::NotFound(_) => "not_found_error",
             AppError::RateLimit => "rate_limit_error",
             AppError::PayloadTooLarge => "invalid_request_error",
             AppError::Timeout => "timeout_error",
-            AppError::ServiceUnavailable(_) => "overloaded_error",
+            AppError::ServiceUnavailable(_) | AppError::Overloaded(_) => "overloaded_error",
             AppError::ExternalApi(_) => "api_error",
+            AppError::Provider(error) => error.claude_error().0,
             AppError::Config(_) 
             | AppError::HttpClient(_) 
             | AppError::Serialization(_) 
@@ -145,27 +175,48 @@ impl AppError {
         }
     }
     
-    /// Whether detailed error information should be logged
-    pub fn should_log_details(&self) -> bool {
+    /// `retry-after` value for errors that may succeed later: the client
+    /// key's rate limit, or the upstream's own header on rate limits and
+    /// server errors
+    pub fn retry_after(&self) -> Option<String> {
         match self {
-            AppError::Authentication(_) | AppError::Authorization(_) => false,
-            _ => true,
+            #[cfg(feature = "server")]
+            AppError::Limit(error) => error.retry_after_secs().map(|secs| secs.to_string()),
+            AppError::Provider(error) => {
+                let status = self.status_code();
+                if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS {
+                    error.upstream().and_then(|upstream| upstream.retry_after.clone())
+                } else {
+                    None
+                }
+            }
+            _ => None,
         }
     }
     
+    /// Whether detailed error information should be logged
+    pub fn should_log_details(&self) -> bool {
+        !matches!(self, AppError::Authentication(_) | AppError::Authorization(_))
+    }
+    
     /// Convert to Claude API error format
     pub fn to_claude_error(&self) -> ClaudeErrorResponse {
+        let message = match self {
+            AppError::Provider(error) => error.client_message(),
+            _ => self.to_string(),
+        };
         ClaudeErrorResponse {
             error_type: "error".to_string(),
             error: ClaudeError {
                 error_type: self.error_type().to_string(),
-                message: self.to_string(),
+                message,
             },
         }
     }
 }
 
 /// Implement IntoResponse trait to allow errors to be returned directly as HTTP responses
+#[cfg(feature = "server")]
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
         let status = self.status_code();
@@ -180,7 +231,11 @@ impl IntoResponse for AppError {
         // Create error response
         let error_response = self.to_claude_error();
         
-        (status, Json(error_response)).into_response()
+        let mut response = (status, Json(error_response)).into_response();
+        if let Some(retry_after) = self.retry_after().and_then(|value| HeaderValue::from_str(&value).ok()) {
+            response.headers_mut().insert(header::RETRY_AFTER, retry_after);
+        }
+        response
     }
 }
 
@@ -292,6 +347,28 @@ mod tests {
         assert_eq!(claude_error.error.message, "Request validation failed: Invalid input");
     }
     
+    #[test]
+    fn test_provider_error_conversion() {
+        let app_error = AppError::from(ProviderError::Timeout("operation timed out".to_string()));
+        assert_eq!(app_error.status_code(), StatusCode::GATEWAY_TIMEOUT);
+        let claude_error = app_error.to_claude_error();
+        assert_eq!(claude_error.error.error_type, "timeout_error");
+        assert_eq!(claude_error.error.message, "Upstream API request timed out.");
+    }
+    
+    #[cfg(feature = "server")]
+    #[test]
+    fn test_error_response() {
+        let limit = LimitError::RateLimited { key: "laptop".to_string(), limit: 10, retry_after: std::time::Duration::from_secs(12) };
+        let response = AppError::from(limit).into_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers().get(header::RETRY_AFTER).unwrap(), "12");
+        
+        let error = AppError::InvalidRequest("prompt is too long: 250000 tokens > 200000 maximum".to_string());
+        assert_eq!(error.to_claude_error().error.message, "prompt is too long: 250000 tokens > 200000 maximum");
+        assert_eq!(AppError::Overloaded("queue is full".to_string()).status_code().as_u16(), 529);
+    }
+    
     #[test]
     fn test_error_context() {
         let result: Result<(), std::io::Error> = Err(std::io::Error::new(
@@ -1,13 +1,175 @@
 //! Logging utilities
 //!
-//! Shared logging configuration and helper functions
+//! Shared logging configuration and helper functions. The log filter can be
+//! changed while running with [`set_log_level`]. Payloads logged at debug
+//! level follow the `payloadLogging` policy: not logged, summarized or
+//! complete, with message content replaced by its length and secrets masked
+//! on request.
 
+use crate::config::{PayloadLogMode, PayloadLoggingConfig};
 use crate::models::claude::{ClaudeContent, ClaudeContentBlock, ClaudeRequest};
 use crate::models::openai::{OpenAIContent, OpenAIMessage, OpenAIRequest};
+use crate::services::redaction::mask_secrets;
+use anyhow::{Context, Result};
+use once_cell::sync::{Lazy, OnceCell};
+use serde::Serialize;
+use serde_json::Value;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::RwLock;
+use std::time::Duration;
+use tracing::info;
+use tracing_subscriber::{reload, EnvFilter, Registry};
 
-/// Set to true to include full request details (tools, system prompts) in debug logs
-/// Default is false to reduce log verbosity
-pub const VERBOSE_REQUEST_LOGGING: bool = false;
+/// Fields holding message content, replaced by their length with `redactContent`
+const CONTENT_FIELDS: [&str; 12] = [
+    "text", "content", "thinking", "reasoning_content", "input", "arguments",
+    "output", "instructions", "system", "partial_json", "data", "url",
+];
+
+/// Longest string kept in summaries of responses
+const SUMMARY_STRING_LEN: usize = 200;
+
+static PAYLOAD_POLICY: Lazy<RwLock<PayloadLoggingConfig>> = Lazy::new(Default::default);
+
+static LOG_FILTER: OnceCell<reload::Handle<EnvFilter, Registry>> = OnceCell::new();
+
+/// Changes of the log filter; a timed change is reverted only if it's still the latest
+static LOG_FILTER_CHANGES: AtomicU64 = AtomicU64::new(0);
+
+/// Log filter layer whose directives can be changed with [`set_log_level`]
+/// (the first one created)
+pub fn reloadable_filter(directives: &str) -> reload::Layer<EnvFilter, Registry> {
+    let (layer, handle) = reload::Layer::new(EnvFilter::new(directives));
+    let _ = LOG_FILTER.set(handle);
+    layer
+}
+
+/// Directives of the log filter, None when it can't be changed
+pub fn log_level() -> Option<String> {
+    LOG_FILTER.get()?.with_current(|filter| filter.to_string()).ok()
+}
+
+/// Replace the log filter directives (such as `debug` or
+/// `info,aiapiproxy::providers=trace`), returning the previous ones; with
+/// `revert_after`, the previous directives are restored after that time
+/// unless the filter was changed again
+pub fn set_log_level(directives: &str, revert_after: Option<Duration>) -> Result<String> {
+    let handle = LOG_FILTER.get().context("The log filter can't be changed in this process")?;
+    let filter = EnvFilter::try_new(directives).context("Invalid log level")?;
+    let previous = handle.with_current(|filter| filter.to_string())?;
+    handle.reload(filter)?;
+    let change = LOG_FILTER_CHANGES.fetch_add(1, Ordering::SeqCst) + 1;
+    info!("🔧 Log level changed from '{}' to '{}'", previous, directives);
+    
+    if let Some(delay) = revert_after {
+        let restore = previous.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            let unchanged = LOG_FILTER_CHANGES.compare_exchange(change, change + 1, Ordering::SeqCst, Ordering::SeqCst).is_ok();
+            if unchanged {
+                if let Ok(()) = handle.reload(EnvFilter::new(&restore)) {
+                    info!("🔧 Log level restored to '{}'", restore);
+                }
+            }
+        });
+    }
+    Ok(previous)
+}
+
+/// Apply the `payloadLogging` configuration to payloads logged from now on
+pub fn set_payload_policy(config: &PayloadLoggingConfig) {
+    *PAYLOAD_POLICY.write().unwrap() = config.clone();
+}
+
+fn payload_policy() -> PayloadLoggingConfig {
+    PAYLOAD_POLICY.read().unwrap().clone()
+}
+
+/// Pretty JSON of a payload to log under the current policy: the payload
+/// itself in `full` mode, `summarize`'s summary of it in `summary` mode, or
+/// None when payloads aren't logged
+pub fn filter_payload<T: Serialize + ?Sized>(payload: &T, summarize: impl FnOnce(&T) -> Value) -> Option<String> {
+    let policy = payload_policy();
+    let mut value = match policy.mode {
+        PayloadLogMode::Off => return None,
+        PayloadLogMode::Summary => summarize(payload),
+        PayloadLogMode::Full => serde_json::to_value(payload).unwrap_or(serde_json::json!({"error": "serialize failed"})),
+    };
+    redact(&mut value, &policy);
+    serde_json::to_string_pretty(&value).ok()
+}
+
+/// Log line for a response payload; summaries cut long strings
+pub fn create_response_log<T: Serialize + ?Sized>(response: &T) -> Option<String> {
+    filter_payload(response, |response| {
+        let mut value = serde_json::to_value(response).unwrap_or_default();
+        truncate_strings(&mut value);
+        value
+    })
+}
+
+/// Log line for a raw body as received or sent; summaries keep the first
+/// `max_len` bytes
+pub fn create_raw_log(text: &str, max_len: usize) -> Option<String> {
+    let policy = payload_policy();
+    if policy.mode == PayloadLogMode::Off {
+        return None;
+    }
+    let mut text = match serde_json::from_str::<Value>(text) {
+        Ok(mut value) if policy.redact_content => {
+            redact(&mut value, &PayloadLoggingConfig { mask_secrets: false, ..policy.clone() });
+            value.to_string()
+        }
+        Ok(_) => text.to_string(),
+        Err(_) if policy.redact_content => format!("[{} chars]", text.chars().count()),
+        Err(_) => text.to_string(),
+    };
+    if policy.mask_secrets {
+        text = mask_secrets(&text).into_owned();
+    }
+    if policy.mode == PayloadLogMode::Summary {
+        text = truncate_content(&text, max_len);
+    }
+    Some(text)
+}
+
+/// Replace message content by its length and mask secrets, as the policy asks
+fn redact(value: &mut Value, policy: &PayloadLoggingConfig) {
+    match value {
+        Value::String(text) if policy.mask_secrets => {
+            if let std::borrow::Cow::Owned(masked) = mask_secrets(text) {
+                *text = masked;
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(|value| redact(value, policy)),
+        Value::Object(map) => {
+            for (key, field) in map.iter_mut() {
+                let length = match field {
+                    Value::String(text) => text.chars().count(),
+                    // Tool inputs are objects whose fields are all content
+                    Value::Object(_) => field.to_string().chars().count(),
+                    _ => 0,
+                };
+                if policy.redact_content && length > 0 && CONTENT_FIELDS.contains(&key.as_str()) {
+                    *field = Value::String(format!("[{} chars]", length));
+                } else {
+                    redact(field, policy);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Cut every string in a payload to the summary length
+fn truncate_strings(value: &mut Value) {
+    match value {
+        Value::String(text) if text.len() > SUMMARY_STRING_LEN => *text = truncate_content(text, SUMMARY_STRING_LEN),
+        Value::Array(values) => values.iter_mut().for_each(truncate_strings),
+        Value::Object(map) => map.values_mut().for_each(truncate_strings),
+        _ => {}
+    }
+}
 
 /// Truncate a string with a note about original length
 /// Handles UTF-8 properly by finding valid character boundaries
@@ -56,10 +218,8 @@ fn filter_openai_message(msg: &OpenAIMessage) -> serde_json::Value {
 
 /// Create a filtered summary of OpenAI request for logging
 /// Keeps original structure but truncates verbose content
-pub fn create_request_log_summary(request: &OpenAIRequest) -> serde_json::Value {
-    if VERBOSE_REQUEST_LOGGING {
-        serde_json::to_value(request).unwrap_or(serde_json::json!({"error": "serialize failed"}))
-    } else {
+pub fn create_request_log_summary(request: &OpenAIRequest) -> Option<String> {
+    filter_payload(request, |request| {
         let filtered_messages: Vec<serde_json::Value> = request.messages.iter()
             .map(filter_openai_message)
             .collect();
@@ -77,7 +237,7 @@ pub fn create_request_log_summary(request: &OpenAIRequest) -> serde_json::Value
             "messages": filtered_messages,
             "tools": tools,
         })
-    }
+    })
 }
 
 /// Create a filtered version of Claude message for logging  
@@ -113,7 +273,13 @@ fn filter_claude_message(msg: &crate::models::claude::ClaudeMessage) -> serde_js
                             obj
                         },
                         ClaudeContentBlock::ToolResult { tool_use_id, content, .. } => {
-                            serde_json::json!({"type": "tool_result", "tool_use_id": tool_use_id, "content": truncate_content(content, 50)})
+                            serde_json::json!({"type": "tool_result", "tool_use_id": tool_use_id, "content": truncate_content(&content.text(), 50), "images": content.images().len()})
+                        },
+                        ClaudeContentBlock::ServerToolUse { id, name, .. } => {
+                            serde_json::json!({"type": "server_tool_use", "id": id, "name": name, "input": "[truncated]"})
+                        },
+                        ClaudeContentBlock::WebSearchToolResult { tool_use_id, .. } => {
+                            serde_json::json!({"type": "web_search_tool_result", "tool_use_id": tool_use_id, "content": "[truncated]"})
                         },
                         ClaudeContentBlock::Unknown => {
                             serde_json::json!({"type": "unknown"})
@@ -140,10 +306,8 @@ fn filter_claude_message(msg: &crate::models::claude::ClaudeMessage) -> serde_js
 
 /// Create a filtered summary of Claude request for logging
 /// Keeps original structure but truncates verbose content
-pub fn create_claude_request_log_summary(request: &ClaudeRequest) -> serde_json::Value {
-    if VERBOSE_REQUEST_LOGGING {
-        serde_json::to_value(request).unwrap_or(serde_json::json!({"error": "serialize failed"}))
-    } else {
+pub fn create_claude_request_log_summary(request: &ClaudeRequest) -> Option<String> {
+    filter_payload(request, |request| {
         let filtered_messages: Vec<serde_json::Value> = request.messages.iter()
             .map(filter_claude_message)
             .collect();
@@ -166,9 +330,65 @@ pub fn create_claude_request_log_summary(request: &ClaudeRequest) -> serde_json:
             "max_tokens": request.max_tokens,
             "temperature": request.temperature,
             "stream": request.stream,
+            "metadata": request.metadata,
             "system": system,
             "messages": filtered_messages,
             "tools": tools,
         })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_redact() {
+        let payload = serde_json::json!({
+            "model": "gpt-4o",
+            "messages": [
+                {"role": "user", "content": "my key is sk-abcdefghijklmnopqrstuvwx"},
+                {"role": "assistant", "content": [{"type": "tool_use", "name": "bash", "input": {"command": "ls"}}]}
+            ]
+        });
+        
+        let mut masked = payload.clone();
+        redact(&mut masked, &PayloadLoggingConfig::default());
+        assert_eq!(masked["messages"][0]["content"], "my key is [REDACTED_SECRET]");
+        assert_eq!(masked["messages"][1]["content"][0]["input"]["command"], "ls");
+        
+        let mut redacted = payload;
+        redact(&mut redacted, &PayloadLoggingConfig { redact_content: true, ..Default::default() });
+        assert_eq!(redacted["model"], "gpt-4o");
+        assert_eq!(redacted["messages"][0]["role"], "user");
+        assert_eq!(redacted["messages"][0]["content"], "[37 chars]");
+        assert_eq!(redacted["messages"][1]["content"][0]["name"], "bash");
+        assert_eq!(redacted["messages"][1]["content"][0]["input"], "[16 chars]");
+    }
+    
+    #[tokio::test]
+    async fn test_set_log_level() {
+        let _filter = reloadable_filter("info");
+        assert_eq!(log_level().as_deref(), Some("info"));
+        assert!(set_log_level("aiapiproxy=loud", None).is_err());
+        
+        assert_eq!(set_log_level("debug", Some(Duration::from_millis(20))).unwrap(), "info");
+        assert_eq!(log_level().as_deref(), Some("debug"));
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(log_level().as_deref(), Some("info"));
+        
+        // A later change outlasts an earlier timed one
+        set_log_level("debug", Some(Duration::from_millis(20))).unwrap();
+        set_log_level("warn", None).unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(log_level().as_deref(), Some("warn"));
+    }
+    
+    #[test]
+    fn test_truncate_strings() {
+        let mut value = serde_json::json!({"content": [{"text": "é".repeat(300)}], "id": "msg_1"});
+        truncate_strings(&mut value);
+        assert!(value["content"][0]["text"].as_str().unwrap().ends_with("... (400 chars truncated)"));
+        assert_eq!(value["id"], "msg_1");
     }
 }
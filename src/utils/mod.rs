@@ -4,4 +4,5 @@
 
 pub mod error;
 pub mod logging;
+pub mod sse;
 pub mod thought_cache;
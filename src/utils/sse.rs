@@ -0,0 +1,165 @@
+//! Incremental Server-Sent Events decoding
+//!
+//! Upstream byte chunks don't line up with SSE lines: a `data:` line (or even a
+//! multi-byte UTF-8 character) can be split across TCP reads. [`SseDecoder`]
+//! carries the partial line between chunks and yields complete `data:` payloads.
+
+use crate::models::openai::OpenAIStreamResponse;
+use crate::services::error_reports;
+use anyhow::Result;
+use futures::{Stream, StreamExt};
+use tracing::{debug, warn};
+
+/// Incremental SSE decoder
+#[derive(Debug, Default)]
+pub struct SseDecoder {
+    buffer: Vec<u8>,
+}
+
+impl SseDecoder {
+    /// Create a new decoder
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Feed a chunk of bytes, returning the `data:` payloads of all complete lines
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.buffer.extend_from_slice(chunk);
+        
+        let mut payloads = Vec::new();
+        let mut start = 0;
+        while let Some(pos) = self.buffer[start..].iter().position(|&b| b == b'\n') {
+            let end = start + pos;
+            if let Some(data) = Self::parse_line(&self.buffer[start..end]) {
+                payloads.push(data);
+            }
+            start = end + 1;
+        }
+        self.buffer.drain(..start);
+        
+        payloads
+    }
+    
+    /// Flush the last line if the stream ended without a trailing newline
+    pub fn finish(&mut self) -> Option<String> {
+        let line = std::mem::take(&mut self.buffer);
+        Self::parse_line(&line)
+    }
+    
+    /// Extract the payload of a `data:` line; other fields and comments are skipped
+    fn parse_line(line: &[u8]) -> Option<String> {
+        let line = String::from_utf8_lossy(line);
+        let line = line.strip_suffix('\r').unwrap_or(&line);
+        let data = line.strip_prefix("data:")?;
+        Some(data.strip_prefix(' ').unwrap_or(data).to_string())
+    }
+}
+
+/// Decode a byte stream into SSE `data:` payloads
+pub fn data_stream<S, B, E>(stream: S) -> impl Stream<Item = Result<String>>
+where
+    S: Stream<Item = std::result::Result<B, E>>,
+    B: AsRef<[u8]>,
+    E: std::fmt::Display,
+{
+    let mut decoder = SseDecoder::new();
+    
+    stream
+        .map(Some)
+        .chain(futures::stream::once(async { None }))
+        .flat_map(move |item| {
+            let payloads: Vec<Result<String>> = match item {
+                Some(Ok(chunk)) => decoder.push(chunk.as_ref()).into_iter().map(Ok).collect(),
+                Some(Err(e)) => vec![Err(anyhow::anyhow!("Stream error: {}", e))],
+                None => decoder.finish().into_iter().map(Ok).collect(),
+            };
+            futures::stream::iter(payloads)
+        })
+}
+
+/// Decode an OpenAI chat completion SSE byte stream into chunks
+///
+/// The `[DONE]` marker is skipped, as are payloads that fail to parse (logged).
+pub fn openai_chunk_stream<S, B, E>(stream: S) -> impl Stream<Item = Result<OpenAIStreamResponse>>
+where
+    S: Stream<Item = std::result::Result<B, E>>,
+    B: AsRef<[u8]>,
+    E: std::fmt::Display,
+{
+    data_stream(stream).filter_map(|payload| async move {
+        match payload {
+            Ok(data) => parse_openai_chunk(&data).map(Ok),
+            Err(e) => Some(Err(e)),
+        }
+    })
+}
+
+/// Parse a single OpenAI chat completion chunk payload
+pub fn parse_openai_chunk(data: &str) -> Option<OpenAIStreamResponse> {
+    let data = data.trim();
+    if data.is_empty() {
+        return None;
+    }
+    if data == "[DONE]" {
+        debug!("Received streaming response end marker");
+        return None;
+    }
+    
+    match serde_json::from_str::<OpenAIStreamResponse>(data) {
+        Ok(stream_response) => Some(stream_response),
+        Err(e) => {
+            warn!("Failed to parse streaming response chunk: {} - data: {}", e, data);
+            error_reports::global().unparsable_response("OpenAI stream chunk", &e, data);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    const CHUNK: &str = r#"{"id":"test","object":"chat.completion.chunk","created":1234567890,"model":"gpt-4","choices":[{"index":0,"delta":{"content":"Hello"},"finish_reason":null}]}"#;
+    
+    #[test]
+    fn test_decoder_split_lines() {
+        let mut decoder = SseDecoder::new();
+        
+        assert!(decoder.push(b"event: message\r\ndata: {\"a\":").is_empty());
+        assert_eq!(decoder.push(b"1}\r\n\r\ndata:{\"b\":2}\n: keep-alive\ndata: [DO"), vec![r#"{"a":1}"#, r#"{"b":2}"#]);
+        assert!(decoder.push(b"NE]").is_empty());
+        assert_eq!(decoder.finish().as_deref(), Some("[DONE]"));
+        assert!(decoder.finish().is_none());
+    }
+    
+    #[test]
+    fn test_decoder_split_utf8() {
+        let mut decoder = SseDecoder::new();
+        let line = "data: 你好\n".as_bytes();
+        
+        // Split in the middle of a multi-byte character
+        assert!(decoder.push(&line[..8]).is_empty());
+        assert_eq!(decoder.push(&line[8..]), vec!["你好"]);
+    }
+    
+    #[test]
+    fn test_parse_openai_chunk() {
+        let chunk = parse_openai_chunk(CHUNK).unwrap();
+        assert_eq!(chunk.choices[0].delta.content.as_deref(), Some("Hello"));
+        
+        assert!(parse_openai_chunk("[DONE]").is_none());
+        assert!(parse_openai_chunk("not json").is_none());
+    }
+    
+    #[tokio::test]
+    async fn test_openai_chunk_stream() {
+        // One event split across two reads, followed by two events in one read
+        let sse = format!("data: {}\n\ndata: {}\n\ndata: {}\n\ndata: [DONE]\n\n", CHUNK, CHUNK, CHUNK);
+        let (first, rest) = sse.as_bytes().split_at(20);
+        let reads: Vec<std::result::Result<Vec<u8>, String>> = vec![Ok(first.to_vec()), Ok(rest.to_vec())];
+        
+        let chunks: Vec<_> = openai_chunk_stream(futures::stream::iter(reads)).collect().await;
+        assert_eq!(chunks.len(), 3);
+        assert!(chunks.iter().all(|c| c.is_ok()));
+    }
+}
@@ -2,46 +2,354 @@
 //!
 //! Caches thought_signatures from Gemini responses for use in subsequent requests.
 //! This is needed because Claude Code doesn't preserve custom fields like thought_signature.
+//!
+//! The cache holds at most `thoughtCache.capacity` signatures and evicts the
+//! least recently used one when full; signatures older than `ttlSecs` are
+//! dropped when looked up. With `persistPath`, the cache is loaded at startup
+//! and saved periodically, so sessions survive a proxy restart. With a shared
+//! [`StateBackend`], signatures are also stored there and looked up when
+//! missing locally, so replicas see each other's signatures.
 
+use crate::config::ThoughtCacheConfig;
+use crate::services::state_backend::{self, StateBackend};
+use anyhow::{Context, Result};
 use once_cell::sync::Lazy;
-use std::collections::HashMap;
-use std::sync::RwLock;
-use tracing::debug;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{debug, info, warn};
+
+static THOUGHT_SIGNATURE_CACHE: Lazy<ThoughtCache> = Lazy::new(ThoughtCache::default);
 
-// Global cache for thought_signatures
-// Maps tool_call_id -> thought_signature
-static THOUGHT_SIGNATURE_CACHE: Lazy<RwLock<HashMap<String, String>>> = 
-    Lazy::new(|| RwLock::new(HashMap::new()));
+/// The process-wide thought signature cache
+pub fn global() -> &'static ThoughtCache {
+    &THOUGHT_SIGNATURE_CACHE
+}
 
 /// Store a thought_signature for a tool call ID
 pub fn cache_thought_signature(tool_call_id: &str, signature: &str) {
-    if let Ok(mut cache) = THOUGHT_SIGNATURE_CACHE.write() {
-        debug!("📝 Caching thought_signature for tool_call_id: {}", tool_call_id);
-        cache.insert(tool_call_id.to_string(), signature.to_string());
-        // Simple cleanup: if cache gets too large, clear old entries
-        if cache.len() > 1000 {
-            cache.clear();
-        }
-    }
+    debug!("📝 Caching thought_signature for tool_call_id: {}", tool_call_id);
+    global().insert(tool_call_id, signature);
 }
 
 /// Get a cached thought_signature for a tool call ID
 pub fn get_cached_thought_signature(tool_call_id: &str) -> Option<String> {
-    if let Ok(cache) = THOUGHT_SIGNATURE_CACHE.read() {
-        let result = cache.get(tool_call_id).cloned();
-        if result.is_some() {
-            debug!("📖 Found cached thought_signature for tool_call_id: {}", tool_call_id);
+    let result = global().get(tool_call_id);
+    if result.is_some() {
+        debug!("📖 Found cached thought_signature for tool_call_id: {}", tool_call_id);
+    }
+    result
+}
+
+/// A cached signature
+#[derive(Debug, Clone)]
+struct Entry {
+    signature: String,
+    cached_at: SystemTime,
+    /// Position in the recency order
+    tick: u64,
+}
+
+/// Signature as saved to the persistence file
+#[derive(Debug, Serialize, Deserialize)]
+struct PersistedEntry {
+    #[serde(rename = "toolCallId")]
+    tool_call_id: String,
+    signature: String,
+    /// Unix timestamp in seconds
+    #[serde(rename = "cachedAt")]
+    cached_at: u64,
+}
+
+#[derive(Debug, Default)]
+struct CacheState {
+    entries: HashMap<String, Entry>,
+    /// Tool call IDs by last use, least recent first
+    recency: BTreeMap<u64, String>,
+    next_tick: u64,
+    hits: u64,
+    misses: u64,
+    /// Changed since the last save
+    dirty: bool,
+}
+
+impl CacheState {
+    fn touch(&mut self, tool_call_id: &str) {
+        let tick = self.next_tick;
+        self.next_tick += 1;
+        if let Some(entry) = self.entries.get_mut(tool_call_id) {
+            self.recency.remove(&entry.tick);
+            entry.tick = tick;
+            self.recency.insert(tick, tool_call_id.to_string());
         }
-        result
-    } else {
-        None
     }
+    
+    fn remove(&mut self, tool_call_id: &str) {
+        if let Some(entry) = self.entries.remove(tool_call_id) {
+            self.recency.remove(&entry.tick);
+            self.dirty = true;
+        }
+    }
+}
+
+/// Cache size and effectiveness, shown in health details
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThoughtCacheStats {
+    /// Cached signatures
+    pub entries: usize,
+    /// Maximum cached signatures
+    pub capacity: usize,
+    /// Lookups that found a signature
+    pub hits: u64,
+    /// Lookups that found none, or an expired one
+    pub misses: u64,
+    /// Share of lookups that found a signature
+    pub hit_rate: f64,
+}
+
+/// LRU cache of thought signatures by tool call ID
+#[derive(Debug)]
+pub struct ThoughtCache {
+    state: Mutex<CacheState>,
+    settings: Mutex<ThoughtCacheConfig>,
+    backend: RwLock<Option<Arc<dyn StateBackend>>>,
+}
+
+impl Default for ThoughtCache {
+    fn default() -> Self {
+        Self::new(ThoughtCacheConfig::default())
+    }
+}
+
+impl ThoughtCache {
+    /// Create an empty cache
+    pub fn new(config: ThoughtCacheConfig) -> Self {
+        Self {
+            state: Mutex::new(CacheState::default()),
+            settings: Mutex::new(config),
+            backend: RwLock::new(None),
+        }
+    }
+    
+    /// Share signatures through a backend, or stop sharing with `None`
+    pub fn set_backend(&self, backend: Option<Arc<dyn StateBackend>>) {
+        *self.backend.write().unwrap() = backend;
+    }
+    
+    /// Apply new settings, evicting entries beyond the new capacity, and load
+    /// the persisted signatures if a `persistPath` is set
+    pub fn configure(&self, config: &ThoughtCacheConfig) -> Result<()> {
+        *self.settings.lock().unwrap() = config.clone();
+        if let Some(path) = &config.persist_path {
+            if path.exists() {
+                let loaded = self.load(path)?;
+                info!("📖 Loaded {} thought signatures from {}", loaded, path.display());
+            }
+        }
+        let mut state = self.state.lock().unwrap();
+        self.evict(&mut state);
+        Ok(())
+    }
+    
+    /// Store a signature, evicting the least recently used one when full
+    pub fn insert(&self, tool_call_id: &str, signature: &str) {
+        self.insert_at(tool_call_id, signature, SystemTime::now());
+        if let Some(backend) = self.backend() {
+            let (key, signature, ttl) = (backend_key(tool_call_id), signature.to_string(), self.ttl());
+            state_backend::spawn_write(move || {
+                if let Err(e) = backend.set(&key, &signature, ttl) {
+                    warn!("Failed to store thought signature in {}: {:#}", backend.name(), e);
+                }
+            });
+        }
+    }
+    
+    fn insert_at(&self, tool_call_id: &str, signature: &str, cached_at: SystemTime) {
+        let mut state = self.state.lock().unwrap();
+        state.remove(tool_call_id);
+        let tick = state.next_tick;
+        state.next_tick += 1;
+        state.entries.insert(tool_call_id.to_string(), Entry {
+            signature: signature.to_string(),
+            cached_at,
+            tick,
+        });
+        state.recency.insert(tick, tool_call_id.to_string());
+        state.dirty = true;
+        self.evict(&mut state);
+    }
+    
+    /// Look up a signature that has not expired, locally or in the backend
+    pub fn get(&self, tool_call_id: &str) -> Option<String> {
+        let ttl = self.ttl();
+        {
+            let mut state = self.state.lock().unwrap();
+            match state.entries.get(tool_call_id) {
+                Some(entry) if is_expired(entry.cached_at, ttl) => state.remove(tool_call_id),
+                Some(entry) => {
+                    let signature = entry.signature.clone();
+                    state.hits += 1;
+                    state.touch(tool_call_id);
+                    return Some(signature);
+                }
+                None => {}
+            }
+        }
+        
+        // Cached by another replica; the backend expires it by itself
+        let signature = self.backend().and_then(|backend| {
+            backend.get(&backend_key(tool_call_id)).unwrap_or_else(|e| {
+                warn!("Failed to look up thought signature in {}: {:#}", backend.name(), e);
+                None
+            })
+        });
+        if let Some(signature) = &signature {
+            self.insert_at(tool_call_id, signature, SystemTime::now());
+        }
+        let mut state = self.state.lock().unwrap();
+        match signature {
+            Some(_) => state.hits += 1,
+            None => state.misses += 1,
+        }
+        signature
+    }
+    
+    /// Current size and hit rate
+    pub fn stats(&self) -> ThoughtCacheStats {
+        let capacity = self.settings.lock().unwrap().capacity;
+        let state = self.state.lock().unwrap();
+        let lookups = state.hits + state.misses;
+        ThoughtCacheStats {
+            entries: state.entries.len(),
+            capacity,
+            hits: state.hits,
+            misses: state.misses,
+            hit_rate: if lookups == 0 { 0.0 } else { state.hits as f64 / lookups as f64 },
+        }
+    }
+    
+    /// Number of cached signatures
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().entries.len()
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    
+    /// Save the signatures to the `persistPath`, if set and anything changed
+    ///
+    /// The file is replaced atomically, so a crash mid-save keeps the previous one.
+    pub fn persist(&self) -> Result<()> {
+        let Some(path) = self.settings.lock().unwrap().persist_path.clone() else {
+            return Ok(());
+        };
+        let entries: Vec<PersistedEntry> = {
+            let mut state = self.state.lock().unwrap();
+            if !state.dirty {
+                return Ok(());
+            }
+            state.dirty = false;
+            // Least recently used first, so loading restores the order
+            state.recency.values()
+                .filter_map(|id| state.entries.get(id).map(|entry| (id, entry)))
+                .map(|(id, entry)| PersistedEntry {
+                    tool_call_id: id.clone(),
+                    signature: entry.signature.clone(),
+                    cached_at: entry.cached_at.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+                })
+                .collect()
+        };
+        
+        let json = serde_json::to_vec(&entries).context("Failed to serialize thought signatures")?;
+        let tmp_path = path.with_extension("tmp");
+        std::fs::write(&tmp_path, json)
+            .with_context(|| format!("Failed to write {}", tmp_path.display()))?;
+        std::fs::rename(&tmp_path, &path)
+            .with_context(|| format!("Failed to replace {}", path.display()))?;
+        debug!("💾 Saved {} thought signatures to {}", entries.len(), path.display());
+        Ok(())
+    }
+    
+    /// Save the cache every `persistIntervalSecs` while the runtime is running
+    pub fn spawn_persistence(&'static self) {
+        let settings = self.settings.lock().unwrap().clone();
+        if settings.persist_path.is_none() {
+            return;
+        }
+        let interval = Duration::from_secs(settings.persist_interval_secs);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                if let Err(e) = self.persist() {
+                    warn!("Failed to save thought signatures: {:#}", e);
+                }
+            }
+        });
+    }
+    
+    /// Load unexpired signatures from a persistence file
+    fn load(&self, path: &Path) -> Result<usize> {
+        let json = std::fs::read(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let entries: Vec<PersistedEntry> = serde_json::from_slice(&json)
+            .with_context(|| format!("Invalid thought signature file {}", path.display()))?;
+        let ttl = self.ttl();
+        let mut loaded = 0;
+        for entry in entries {
+            let cached_at = UNIX_EPOCH + Duration::from_secs(entry.cached_at);
+            if !is_expired(cached_at, ttl) {
+                self.insert_at(&entry.tool_call_id, &entry.signature, cached_at);
+                loaded += 1;
+            }
+        }
+        self.state.lock().unwrap().dirty = false;
+        Ok(loaded)
+    }
+    
+    fn backend(&self) -> Option<Arc<dyn StateBackend>> {
+        self.backend.read().unwrap().clone()
+    }
+    
+    fn ttl(&self) -> Duration {
+        Duration::from_secs(self.settings.lock().unwrap().ttl_secs)
+    }
+    
+    /// Drop the least recently used entries beyond the capacity
+    fn evict(&self, state: &mut CacheState) {
+        let capacity = self.settings.lock().unwrap().capacity.max(1);
+        while state.entries.len() > capacity {
+            let Some((_, id)) = state.recency.pop_first() else {
+                break;
+            };
+            state.entries.remove(&id);
+            debug!("🗑️ Evicted thought_signature for tool_call_id: {}", id);
+        }
+    }
+}
+
+fn backend_key(tool_call_id: &str) -> String {
+    format!("thought:{}", tool_call_id)
+}
+
+fn is_expired(cached_at: SystemTime, ttl: Duration) -> bool {
+    cached_at.elapsed().is_ok_and(|age| age > ttl)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-
+    use crate::services::state_backend::MemoryBackend;
+    use tempfile::TempDir;
+    
+    fn cache(capacity: usize) -> ThoughtCache {
+        ThoughtCache::new(ThoughtCacheConfig { capacity, ..Default::default() })
+    }
+    
     #[test]
     fn test_cache_and_retrieve() {
         let id = "test_tool_call_123";
@@ -52,10 +360,84 @@ mod tests {
         let result = get_cached_thought_signature(id);
         assert_eq!(result, Some(sig.to_string()));
     }
-
+    
     #[test]
     fn test_missing_entry() {
         let result = get_cached_thought_signature("non_existent_id");
         assert_eq!(result, None);
     }
+    
+    #[test]
+    fn test_lru_eviction() {
+        let cache = cache(2);
+        cache.insert("call_1", "sig_1");
+        cache.insert("call_2", "sig_2");
+        // Using call_1 makes call_2 the least recently used
+        assert!(cache.get("call_1").is_some());
+        cache.insert("call_3", "sig_3");
+        
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get("call_1").as_deref(), Some("sig_1"));
+        assert_eq!(cache.get("call_2"), None);
+        assert_eq!(cache.get("call_3").as_deref(), Some("sig_3"));
+    }
+    
+    #[test]
+    fn test_ttl_and_stats() {
+        let cache = ThoughtCache::new(ThoughtCacheConfig { ttl_secs: 60, ..Default::default() });
+        cache.insert("fresh", "sig_1");
+        cache.insert_at("stale", "sig_2", SystemTime::now() - Duration::from_secs(120));
+        
+        assert!(cache.get("fresh").is_some());
+        assert!(cache.get("stale").is_none());
+        assert!(cache.get("unknown").is_none());
+        
+        let stats = cache.stats();
+        assert_eq!(stats.entries, 1);
+        assert_eq!(stats.hits, 1);
+        assert_eq!(stats.misses, 2);
+        assert!((stats.hit_rate - 1.0 / 3.0).abs() < 1e-9);
+    }
+    
+    #[test]
+    fn test_persistence() {
+        let dir = TempDir::new().unwrap();
+        let config = ThoughtCacheConfig {
+            capacity: 2,
+            persist_path: Some(dir.path().join("thoughts.json")),
+            ..Default::default()
+        };
+        
+        let cache = ThoughtCache::new(config.clone());
+        cache.insert("call_1", "sig_1");
+        cache.insert("call_2", "sig_2");
+        cache.get("call_1");
+        cache.persist().unwrap();
+        
+        // A restarted proxy picks the signatures up, in recency order
+        let restarted = ThoughtCache::new(ThoughtCacheConfig::default());
+        restarted.configure(&config).unwrap();
+        assert_eq!(restarted.len(), 2);
+        restarted.insert("call_3", "sig_3");
+        assert_eq!(restarted.get("call_1").as_deref(), Some("sig_1"));
+        assert_eq!(restarted.get("call_2"), None);
+    }
+    
+    #[test]
+    fn test_shared_backend() {
+        let backend: Arc<dyn StateBackend> = Arc::new(MemoryBackend::default());
+        let replica_a = cache(10);
+        let replica_b = cache(10);
+        replica_a.set_backend(Some(backend.clone()));
+        replica_b.set_backend(Some(backend));
+        
+        replica_a.insert("call_1", "sig_1");
+        assert!(replica_b.is_empty());
+        assert_eq!(replica_b.get("call_1").as_deref(), Some("sig_1"));
+        // Fetched signatures are kept locally
+        assert_eq!(replica_b.len(), 1);
+        assert_eq!(replica_b.stats().hits, 1);
+        assert_eq!(replica_b.get("call_2"), None);
+        assert_eq!(replica_b.stats().misses, 1);
+    }
 }
@@ -5,6 +5,7 @@ use std::collections::HashMap;
 use std::env;
 
 /// Setup test environment variables
+#[allow(dead_code)]
 fn setup_test_env() {
     cleanup_test_env(); // 确保环境干净
     env::set_var("OPENAI_API_KEY", "sk-test-key-12345678901234567890");
@@ -61,6 +62,7 @@ fn test_get_openai_model_mapping() {
         },
         request: RequestConfig {
             max_request_size: 1024,
+            max_image_request_size: 4096,
             max_concurrent_requests: 10,
             timeout: 30,
         },
@@ -122,7 +124,7 @@ fn test_default_values() {
     // Check default values
     // Both 0.0.0.0 and 127.0.0.1 are valid default hosts
     assert!(settings.server.host == "0.0.0.0" || settings.server.host == "127.0.0.1");
-    assert_eq!(settings.server.port, 8080); // 修改期望值以匹配实际行为
+    assert_eq!(settings.server.port, 8082);
     assert_eq!(settings.openai.base_url, "https://api.openai.com/v1");
     assert_eq!(settings.openai.timeout, 30);
     // 不检查具体的模型名称，因为它们可能会变化
@@ -130,6 +132,7 @@ fn test_default_values() {
     assert!(!settings.model_mapping.sonnet.is_empty());
     assert!(!settings.model_mapping.opus.is_empty());
     assert_eq!(settings.request.max_request_size, 10485760);
+    assert_eq!(settings.request.max_image_request_size, 33554432);
     assert_eq!(settings.request.max_concurrent_requests, 100);
     assert_eq!(settings.request.timeout, 30);
     assert_eq!(settings.security.allowed_origins, vec!["*".to_string()]);
@@ -1,6 +1,6 @@
 //! API converter unit tests
 
-use aiapiproxy::services::ApiConverter;
+use aiapiproxy::services::{ApiConverter, ResponseConverter};
 use aiapiproxy::models::claude::*;
 use aiapiproxy::models::openai::*;
 use aiapiproxy::config::settings::*;
@@ -28,6 +28,7 @@ fn create_test_settings() -> Settings {
         },
         request: RequestConfig {
             max_request_size: 1024,
+            max_image_request_size: 4096,
             max_concurrent_requests: 10,
             timeout: 30,
         },
@@ -104,6 +105,7 @@ fn test_convert_multimodal_request() {
                         source_type: "base64".to_string(),
                         media_type: "image/jpeg".to_string(),
                         data: "iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAYAAAAfFcSJAAAADUlEQVR42mP8/5+hHgAHggJ/PchI7wAAAABJRU5ErkJggg==".to_string(),
+                        url: None,
                     },
                 },
             ]),
@@ -120,7 +122,7 @@ fn test_convert_multimodal_request() {
         assert_eq!(parts.len(), 2);
         
         // Check text part
-        if let OpenAIContentPart::Text { text } = &parts[0] {
+        if let OpenAIContentPart::Text { text, .. } = &parts[0] {
             assert_eq!(text, "What's in this image?");
         } else {
             panic!("Expected text part");
@@ -138,6 +140,88 @@ fn test_convert_multimodal_request() {
     }
 }
 
+#[test]
+fn test_convert_image_url_source() {
+    let settings = create_test_settings();
+    let converter = ApiConverter::new(settings);
+    
+    // URL sources are deserialized without media_type/data
+    let claude_request: ClaudeRequest = serde_json::from_value(serde_json::json!({
+        "model": "claude-3-opus",
+        "max_tokens": 200,
+        "messages": [{
+            "role": "user",
+            "content": [
+                {"type": "text", "text": "Describe this"},
+                {"type": "image", "source": {"type": "url", "url": "https://example.com/cat.png"}}
+            ]
+        }]
+    })).unwrap();
+    
+    let openai_request = converter.convert_request(claude_request).unwrap();
+    
+    if let Some(OpenAIContent::Array(parts)) = &openai_request.messages[0].content {
+        assert_eq!(parts.len(), 2);
+        if let OpenAIContentPart::ImageUrl { image_url } = &parts[1] {
+            assert_eq!(image_url.url, "https://example.com/cat.png");
+        } else {
+            panic!("Expected image URL part");
+        }
+    } else {
+        panic!("Expected array content");
+    }
+}
+
+#[test]
+fn test_convert_system_blocks() {
+    let settings = create_test_settings();
+    let converter = ApiConverter::new(settings);
+    
+    let claude_request: ClaudeRequest = serde_json::from_value(serde_json::json!({
+        "model": "claude-3-sonnet",
+        "max_tokens": 100,
+        "system": [
+            {"type": "text", "text": "You are Claude Code.", "cache_control": {"type": "ephemeral"}},
+            {"type": "text", "text": "Today is Monday."}
+        ],
+        "messages": [{"role": "user", "content": "Hi"}]
+    })).unwrap();
+    
+    let openai_request = converter.convert_request(claude_request).unwrap();
+    
+    // Block boundaries and cache markers are preserved in order
+    let system = &openai_request.messages[0];
+    assert_eq!(system.role, "system");
+    let Some(OpenAIContent::Array(parts)) = &system.content else {
+        panic!("Expected array system content");
+    };
+    assert_eq!(parts.len(), 2);
+    let OpenAIContentPart::Text { text, cache_control } = &parts[0] else {
+        panic!("Expected text part");
+    };
+    assert_eq!(text, "You are Claude Code.");
+    assert_eq!(cache_control.as_ref().unwrap()["type"], "ephemeral");
+    let OpenAIContentPart::Text { text, cache_control } = &parts[1] else {
+        panic!("Expected text part");
+    };
+    assert_eq!(text, "Today is Monday.");
+    assert!(cache_control.is_none());
+    
+    // Without cache markers, blocks are merged into a single string
+    let claude_request: ClaudeRequest = serde_json::from_value(serde_json::json!({
+        "model": "claude-3-sonnet",
+        "max_tokens": 100,
+        "system": [{"type": "text", "text": "One"}, {"type": "text", "text": "Two"}],
+        "messages": [{"role": "user", "content": "Hi"}]
+    })).unwrap();
+    
+    let openai_request = converter.convert_request(claude_request).unwrap();
+    let Some(OpenAIContent::Text(text)) = &openai_request.messages[0].content else {
+        panic!("Expected text system content");
+    };
+    assert_eq!(text, "One\nTwo");
+}
+
 #[test]
 fn test_convert_response() {
     let settings = create_test_settings();
@@ -164,8 +248,11 @@ fn test_convert_response() {
             prompt_tokens: 15,
             completion_tokens: 10,
             total_tokens: 25,
+            prompt_tokens_details: None,
         }),
         system_fingerprint: None,
+        search_results: None,
+        search_queries: None,
     };
     
     let claude_response = converter.convert_response(openai_response, "claude-3-sonnet").unwrap();
@@ -196,6 +283,8 @@ fn test_convert_stream_chunk_start() {
         created: Utc::now().timestamp() as u64,
         model: "gpt-4o".to_string(),
         system_fingerprint: None,
+        search_results: None,
+        search_queries: None,
         choices: vec![OpenAIStreamChoice {
             index: 0,
             delta: OpenAIStreamDelta {
@@ -234,6 +323,34 @@ fn test_convert_stream_chunk_start() {
     }
 }
 
+#[test]
+fn test_response_converter_trait() {
+    let converter = ApiConverter::new(create_test_settings());
+    let response_converter: &dyn ResponseConverter = &converter;
+    
+    let openai_chunk = OpenAIStreamResponse {
+        id: "chatcmpl-test123".to_string(),
+        object: "chat.completion.chunk".to_string(),
+        created: 0,
+        model: "gpt-4o".to_string(),
+        system_fingerprint: None,
+        search_results: None,
+        search_queries: None,
+        choices: vec![OpenAIStreamChoice {
+            index: 0,
+            delta: OpenAIStreamDelta {
+                role: None,
+                content: Some("Hello".to_string()),
+                tool_calls: None,
+            },
+            logprobs: None,
+            finish_reason: None,
+        }],
+    };
+    let events = response_converter.convert_stream_chunk(openai_chunk, "claude-3-sonnet").unwrap();
+    assert_eq!(events.len(), 1);
+}
+
 #[test]
 fn test_convert_stream_chunk_delta() {
     let settings = create_test_settings();
@@ -245,6 +362,8 @@ fn test_convert_stream_chunk_delta() {
         created: Utc::now().timestamp() as u64,
         model: "gpt-4o".to_string(),
         system_fingerprint: None,
+        search_results: None,
+        search_queries: None,
         choices: vec![OpenAIStreamChoice {
             index: 0,
             delta: OpenAIStreamDelta {
@@ -264,7 +383,9 @@ fn test_convert_stream_chunk_delta() {
     // Check ContentBlockDelta event
     if let ClaudeStreamEvent::ContentBlockDelta { index, delta } = &claude_events[0] {
         assert_eq!(*index, 0);
-        let ClaudeContentDelta::TextDelta { text } = delta;
+        let ClaudeContentDelta::TextDelta { text } = delta else {
+            panic!("Expected TextDelta");
+        };
         assert_eq!(text, "Hello");
     } else {
         panic!("Expected ContentBlockDelta event");
@@ -282,6 +403,8 @@ fn test_convert_stream_chunk_end() {
         created: Utc::now().timestamp() as u64,
         model: "gpt-4o".to_string(),
         system_fingerprint: None,
+        search_results: None,
+        search_queries: None,
         choices: vec![OpenAIStreamChoice {
             index: 0,
             delta: OpenAIStreamDelta {
@@ -348,7 +471,7 @@ fn test_finish_reason_mapping() {
     let test_cases = vec![
         ("stop", "end_turn"),
         ("length", "max_tokens"),
-        ("content_filter", "stop_sequence"),
+        ("content_filter", "refusal"),
         ("tool_calls", "tool_use"),
         ("unknown", "end_turn"), // Unknown type should map to end_turn
     ];
@@ -375,8 +498,11 @@ fn test_finish_reason_mapping() {
                 prompt_tokens: 1,
                 completion_tokens: 1,
                 total_tokens: 2,
+                prompt_tokens_details: None,
             }),
             system_fingerprint: None,
+            search_results: None,
+            search_queries: None,
         };
         
         let claude_response = converter.convert_response(openai_response, "claude-3-sonnet").unwrap();
@@ -434,8 +560,11 @@ fn test_empty_response_handling() {
             prompt_tokens: 1,
             completion_tokens: 0,
             total_tokens: 1,
+            prompt_tokens_details: None,
         }),
         system_fingerprint: None,
+        search_results: None,
+        search_queries: None,
     };
     
     let result = converter.convert_response(openai_response, "claude-3-sonnet");
@@ -465,6 +594,7 @@ fn test_content_extraction() {
                 source_type: "base64".to_string(),
                 media_type: "image/jpeg".to_string(),
                 data: "test".to_string(),
+                url: None,
             },
         },
     ]);
@@ -2,8 +2,7 @@
 
 use aiapiproxy::utils::error::*;
 use aiapiproxy::utils::error::helpers::*;
-use axum::http::StatusCode;
-use serde_json;
+use http::StatusCode;
 
 #[test]
 fn test_app_error_status_codes() {
@@ -249,8 +248,7 @@ async fn test_error_from_conversions() {
 fn test_app_result_type() {
     // 测试AppResult类型别名
     let success: AppResult<String> = Ok("success".to_string());
-    assert!(success.is_ok());
-    assert_eq!(success.unwrap(), "success");
+    assert_eq!(success.ok(), Some("success".to_string()));
     
     let failure: AppResult<String> = Err(AppError::Validation("test".to_string()));
     assert!(failure.is_err());
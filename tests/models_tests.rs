@@ -2,7 +2,6 @@
 
 use aiapiproxy::models::claude::*;
 use aiapiproxy::models::openai::*;
-use serde_json;
 use std::collections::HashMap;
 
 #[test]
@@ -27,6 +26,9 @@ fn test_claude_request_serialization() {
             map.insert("user_id".to_string(), serde_json::Value::String("123".to_string()));
             map
         }),
+        n: None,
+        thinking: None,
+        betas: Vec::new(),
     };
     
     let json = serde_json::to_string(&request).unwrap();
@@ -71,6 +73,7 @@ fn test_claude_content_blocks() {
                 source_type: "base64".to_string(),
                 media_type: "image/jpeg".to_string(),
                 data: "iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAYAAAAfFcSJAAAADUlEQVR42mP8/5+hHgAHggJ/PchI7wAAAABJRU5ErkJggg==".to_string(),
+                url: None,
             },
         },
     ]);
@@ -116,7 +119,9 @@ fn test_claude_response_serialization() {
         usage: ClaudeUsage {
             input_tokens: 10,
             output_tokens: 15,
+            cache_read_input_tokens: None,
         },
+        metadata: None,
     };
     
     let json = serde_json::to_string(&response).unwrap();
@@ -146,7 +151,9 @@ fn test_claude_stream_events() {
             usage: ClaudeUsage {
                 input_tokens: 10,
                 output_tokens: 0,
+                cache_read_input_tokens: None,
             },
+            fallback: None,
         },
     };
     
@@ -228,11 +235,16 @@ fn test_openai_request_serialization() {
         user: Some("user123".to_string()),
         response_format: Some(OpenAIResponseFormat {
             format_type: "json_object".to_string(),
+            json_schema: None,
         }),
         seed: Some(42),
         tools: None,
         tool_choice: None,
         session_id: None,
+        request_id: None,
+        forwarded_headers: Vec::new(),
+        betas: Vec::new(),
+        extensions: HashMap::new(),
     };
     
     let json = serde_json::to_string(&request).unwrap();
@@ -254,7 +266,7 @@ fn test_openai_content_types() {
     // Test text content
     let text_content = OpenAIContent::Text("Hello world".to_string());
     let json = serde_json::to_string(&text_content).unwrap();
-    let deserialized: OpenAIContent = serde_json::from_str(&json).unwrap();
+    let _deserialized: OpenAIContent = serde_json::from_str(&json).unwrap();
     
     assert_eq!(text_content.extract_text(), "Hello world");
     assert!(!text_content.has_images());
@@ -263,6 +275,7 @@ fn test_openai_content_types() {
     let array_content = OpenAIContent::Array(vec![
         OpenAIContentPart::Text {
             text: "Look at this:".to_string(),
+            cache_control: None,
         },
         OpenAIContentPart::ImageUrl {
             image_url: OpenAIImageUrl {
@@ -281,7 +294,7 @@ fn test_openai_content_types() {
     if let OpenAIContent::Array(parts) = deserialized {
         assert_eq!(parts.len(), 2);
         
-        if let OpenAIContentPart::Text { text } = &parts[0] {
+        if let OpenAIContentPart::Text { text, .. } = &parts[0] {
             assert_eq!(text, "Look at this:");
         } else {
             panic!("Expected text part");
@@ -321,8 +334,11 @@ fn test_openai_response_serialization() {
             prompt_tokens: 9,
             completion_tokens: 12,
             total_tokens: 21,
+            prompt_tokens_details: None,
         }),
         system_fingerprint: Some("fp_123".to_string()),
+        search_results: None,
+        search_queries: None,
     };
     
     let json = serde_json::to_string(&response).unwrap();
@@ -338,6 +354,31 @@ fn test_openai_response_serialization() {
     assert_eq!(response.usage.as_ref().unwrap().total_tokens, deserialized.usage.as_ref().unwrap().total_tokens);
 }
 
+#[test]
+fn test_openai_response_lenient_usage() {
+    let base = r#"{"model": "local", "choices": [{"index": 0, "message": {"role": "assistant", "content": "Hi"}, "finish_reason": "stop"}]"#;
+    
+    // Missing and null usage
+    let response: OpenAIResponse = serde_json::from_str(&format!("{}}}", base)).unwrap();
+    assert!(response.usage.is_none());
+    let response: OpenAIResponse = serde_json::from_str(&format!(r#"{}, "usage": null}}"#, base)).unwrap();
+    assert!(response.usage.is_none());
+    
+    // Null counts
+    let response: OpenAIResponse = serde_json::from_str(&format!(
+        r#"{}, "usage": {{"prompt_tokens": 7, "completion_tokens": null, "total_tokens": null}}}}"#, base
+    )).unwrap();
+    let usage = response.usage.unwrap();
+    assert_eq!(usage.prompt_tokens, 7);
+    assert_eq!(usage.completion_tokens, 0);
+    assert!(!usage.is_empty());
+    
+    let response: OpenAIResponse = serde_json::from_str(&format!(
+        r#"{}, "usage": {{"prompt_tokens": null, "completion_tokens": null}}}}"#, base
+    )).unwrap();
+    assert!(response.usage.unwrap().is_empty());
+}
+
 #[test]
 fn test_openai_stream_response() {
     let stream_response = OpenAIStreamResponse {
@@ -346,6 +387,8 @@ fn test_openai_stream_response() {
         created: 1677652288,
         model: "gpt-4".to_string(),
         system_fingerprint: Some("fp_123".to_string()),
+        search_results: None,
+        search_queries: None,
         choices: vec![OpenAIStreamChoice {
             index: 0,
             delta: OpenAIStreamDelta {
@@ -422,6 +465,7 @@ fn test_tool_calling_structures() {
     assert_eq!(tool.function.description, deserialized.function.description);
     
     let tool_call = OpenAIToolCall {
+        index: None,
         id: Some("call_123".to_string()),
         tool_type: Some("function".to_string()),
         function: OpenAIFunctionCall {
@@ -517,6 +561,7 @@ fn test_content_extraction_edge_cases() {
                 source_type: "base64".to_string(),
                 media_type: "image/jpeg".to_string(),
                 data: "test".to_string(),
+                url: None,
             },
         },
     ]);
@@ -531,6 +576,7 @@ fn test_content_extraction_edge_cases() {
                 source_type: "base64".to_string(),
                 media_type: "image/jpeg".to_string(),
                 data: "test".to_string(),
+                url: None,
             },
         },
         ClaudeContentBlock::Text { text: "after".to_string() },
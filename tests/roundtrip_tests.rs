@@ -0,0 +1,83 @@
+//! Property-based round-trip tests of the API converter
+//!
+//! Run with `cargo test --features test-util`.
+
+use aiapiproxy::config::settings::*;
+use aiapiproxy::services::ApiConverter;
+use aiapiproxy::test_util::{self, check_no_content_loss, check_role_alternation, check_tool_ids, Contents};
+use proptest::prelude::*;
+use proptest::test_runner::TestCaseError;
+use std::collections::HashMap;
+
+fn create_test_settings() -> Settings {
+    Settings {
+        server: ServerConfig {
+            host: "localhost".to_string(),
+            port: 8080,
+        },
+        openai: OpenAIConfig {
+            api_key: "test_key".to_string(),
+            base_url: "https://api.openai.com/v1".to_string(),
+            timeout: 30,
+            stream_timeout: 300,
+        },
+        model_mapping: ModelMapping {
+            haiku: "gpt-4o-mini".to_string(),
+            sonnet: "gpt-4o".to_string(),
+            opus: "gpt-4".to_string(),
+            custom: HashMap::new(),
+        },
+        request: RequestConfig {
+            max_request_size: 1024,
+            max_image_request_size: 4096,
+            max_concurrent_requests: 10,
+            timeout: 30,
+        },
+        security: SecurityConfig {
+            allowed_origins: vec!["*".to_string()],
+            api_key_header: "Authorization".to_string(),
+            cors_enabled: true,
+        },
+        logging: LoggingConfig {
+            level: "info".to_string(),
+            format: "text".to_string(),
+        },
+    }
+}
+
+fn converter() -> ApiConverter {
+    ApiConverter::new(create_test_settings())
+}
+
+proptest! {
+    #[test]
+    fn test_claude_to_openai(request in test_util::claude_request()) {
+        let before = Contents::of_claude_request(&request);
+        let openai = converter().convert_request(request).unwrap();
+        let after = Contents::of_openai_request(&openai);
+        check_tool_ids(&before, &after).map_err(TestCaseError::fail)?;
+        check_no_content_loss(&before, &after).map_err(TestCaseError::fail)?;
+    }
+    
+    #[test]
+    fn test_openai_to_claude(request in test_util::openai_request()) {
+        let before = Contents::of_openai_request(&request);
+        let claude = converter().convert_openai_request_to_claude(request).unwrap();
+        check_role_alternation(&claude).map_err(TestCaseError::fail)?;
+        let after = Contents::of_claude_request(&claude);
+        check_tool_ids(&before, &after).map_err(TestCaseError::fail)?;
+        check_no_content_loss(&before, &after).map_err(TestCaseError::fail)?;
+    }
+    
+    #[test]
+    fn test_claude_round_trip(request in test_util::claude_request()) {
+        let converter = converter();
+        let before = Contents::of_claude_request(&request);
+        let openai = converter.convert_request(request).unwrap();
+        let claude = converter.convert_openai_request_to_claude(openai).unwrap();
+        check_role_alternation(&claude).map_err(TestCaseError::fail)?;
+        let after = Contents::of_claude_request(&claude);
+        check_tool_ids(&before, &after).map_err(TestCaseError::fail)?;
+        check_no_content_loss(&before, &after).map_err(TestCaseError::fail)?;
+    }
+}
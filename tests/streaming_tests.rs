@@ -14,8 +14,10 @@ fn create_test_config() -> AppConfig {
     let mut models = HashMap::new();
     models.insert("gpt-4o".to_string(), ModelConfig {
         name: "gpt-4o".to_string(),
+        enabled: true,
         alias: None,
         max_tokens: Some(8192),
+        context_window: None,
         temperature: None,
         options: Default::default(),
     });
@@ -23,16 +25,17 @@ fn create_test_config() -> AppConfig {
     let mut providers = HashMap::new();
     providers.insert("openai".to_string(), ProviderConfig {
         provider_type: "openai".to_string(),
+        enabled: true,
         base_url: "https://api.openai.com/v1".to_string(),
         api_key: "test_key".to_string(),
+        api_key_file: None,
         options: Default::default(),
         models,
     });
     
-    AppConfig { 
-        server: aiapiproxy::config::ServerConfig::default(),
+    AppConfig {
         providers,
-        model_mapping: HashMap::new(),
+        ..Default::default()
     }
 }
 
@@ -57,6 +60,7 @@ fn create_test_app_state() -> Arc<AppState> {
         },
         request: RequestConfig {
             max_request_size: 1024,
+            max_image_request_size: 4096,
             max_concurrent_requests: 10,
             timeout: 30,
         },
@@ -78,6 +82,17 @@ fn create_test_app_state() -> Arc<AppState> {
         settings,
         converter,
         router,
+        api_keys: None,
+        concurrency: None,
+        coalescer: None,
+        redactor: None,
+        guardrails: None,
+        request_log: None,
+        experiments: None,
+        shadow: None,
+        audit_log: None,
+        transcripts: None,
+        hooks: Vec::new(),
     })
 }
 
@@ -101,6 +116,9 @@ fn create_test_claude_stream_request() -> ClaudeRequest {
         tools: None,
         tool_choice: None,
         metadata: None,
+        n: None,
+        thinking: None,
+        betas: Vec::new(),
     }
 }
 
@@ -144,6 +162,8 @@ fn test_stream_chunk_conversion() {
         created: 1234567890,
         model: "gpt-4o".to_string(),
         system_fingerprint: None,
+        search_results: None,
+        search_queries: None,
         choices: vec![
             OpenAIStreamChoice {
                 index: 0,
@@ -165,15 +185,12 @@ fn test_stream_chunk_conversion() {
     assert!(!claude_events.is_empty());
     
     // Check first event
-    if let Some(first_event) = claude_events.first() {
-        match first_event {
-            ClaudeStreamEvent::ContentBlockDelta { delta, .. } => {
-                let ClaudeContentDelta::TextDelta { text } = delta;
-                assert_eq!(text, "Artificial intelligence");
-            }
-            _ => {}
-                // Accept any event type as the actual implementation may vary
-        }
+    // Accept any event type as the actual implementation may vary
+    if let Some(ClaudeStreamEvent::ContentBlockDelta { delta, .. }) = claude_events.first() {
+        let ClaudeContentDelta::TextDelta { text } = delta else {
+            panic!("Expected TextDelta");
+        };
+        assert_eq!(text, "Artificial intelligence");
     }
 }
 
@@ -188,6 +205,8 @@ fn test_stream_error_handling() {
         created: 1234567890,
         model: "gpt-4o".to_string(),
         system_fingerprint: None,
+        search_results: None,
+        search_queries: None,
         choices: vec![],
     };
     
@@ -196,7 +215,7 @@ fn test_stream_error_handling() {
     
     // Verify error handling
     match result {
-        Ok(events) => {
+        Ok(_events) => {
             // May return empty events or error events
             // Both behaviors are acceptable
         }
@@ -218,6 +237,8 @@ fn test_stream_completion_event() {
         created: 1234567890,
         model: "gpt-4o".to_string(),
         system_fingerprint: None,
+        search_results: None,
+        search_queries: None,
         choices: vec![
             OpenAIStreamChoice {
                 index: 0,
@@ -256,6 +277,8 @@ fn test_multiple_stream_chunks() {
             created: 1234567890,
             model: "gpt-4o".to_string(),
             system_fingerprint: None,
+            search_results: None,
+            search_queries: None,
             choices: vec![
                 OpenAIStreamChoice {
                     index: 0,
@@ -276,6 +299,8 @@ fn test_multiple_stream_chunks() {
             created: 1234567890,
             model: "gpt-4o".to_string(),
             system_fingerprint: None,
+            search_results: None,
+            search_queries: None,
             choices: vec![
                 OpenAIStreamChoice {
                     index: 0,
@@ -296,6 +321,8 @@ fn test_multiple_stream_chunks() {
             created: 1234567890,
             model: "gpt-4o".to_string(),
             system_fingerprint: None,
+            search_results: None,
+            search_queries: None,
             choices: vec![
                 OpenAIStreamChoice {
                     index: 0,
@@ -363,7 +390,9 @@ fn test_stream_event_serialization() {
     match deserialized {
         ClaudeStreamEvent::ContentBlockDelta { index, delta } => {
             assert_eq!(index, 0);
-            let ClaudeContentDelta::TextDelta { text } = delta;
+            let ClaudeContentDelta::TextDelta { text } = delta else {
+                panic!("Expected TextDelta");
+            };
             assert_eq!(text, "Test text");
         }
         _ => panic!("Deserialization failed"),